@@ -2,26 +2,53 @@
 //!
 //! `katatsuki` wraps [taglib2](https://taglib.org/) to allow safe access to 
 //! the metadata of various music files.
+//!
+//! Reading tags requires a backend: the default `taglib` feature, or the pure Rust
+//! `symphonia-tags` feature, which builds on targets TagLib can not be compiled for.
+//! Without either, only the `Track` and `TrackFileType` types are available.
 
 
 
+#[cfg(feature = "taglib")]
 use libkatatsuki_sys as sys;
 
-use std::ffi::{CStr, CString, NulError};
+use std::ffi::NulError;
+#[cfg(feature = "taglib")]
+use std::ffi::{CStr, CString};
+#[cfg(any(feature = "taglib", feature = "symphonia-tags"))]
 use std::io::{Error, ErrorKind, Result};
+#[cfg(feature = "taglib")]
 use std::os::raw::{c_char, c_void};
+#[cfg(any(feature = "taglib", feature = "symphonia-tags"))]
 use std::path::Path;
+#[cfg(feature = "taglib")]
 use std::slice::from_raw_parts;
 
-use chrono::Local;
+#[cfg(any(feature = "taglib", feature = "symphonia-tags"))]
 use imagesize::blob_size;
 pub use num_traits::{FromPrimitive, ToPrimitive};
 
+pub use quality::{Quality, HIRES_SAMPLE_RATE};
+pub use reader::MetadataReader;
+#[cfg(any(feature = "taglib", feature = "symphonia-tags"))]
+pub use reader::default_reader;
+#[cfg(feature = "symphonia-tags")]
+pub use symphonia_reader::SymphoniaReader;
+pub use track::TagUpdate;
 pub use track::Track;
+pub use track::TrackBuilder;
 pub use track::TrackFileType;
-
+pub use track::split_genres;
+
+#[cfg(feature = "taglib")]
+mod mapped;
+mod quality;
+mod reader;
+#[cfg(feature = "symphonia-tags")]
+mod symphonia_reader;
 mod track;
 
+#[cfg(feature = "taglib")]
 fn c_str_to_str(c_str: *const c_char) -> Option<String> {
     if c_str.is_null() {
         return None;
@@ -41,10 +68,12 @@ fn c_str_to_str(c_str: *const c_char) -> Option<String> {
     result
 }
 
+#[cfg(feature = "taglib")]
 struct TrackData {
     raw: *mut sys::track_data,
 }
 
+#[cfg(feature = "taglib")]
 /// Unsafe backing 
 impl TrackData {
     // Dangerous access here, path not existing is UB.
@@ -56,6 +85,14 @@ impl TrackData {
         }
     }
 
+    /// Reads the track from the contents of its file, which must outlive it.
+    /// Tracks read from memory can not be saved.
+    pub fn from_memory(path: &CString, contents: &[u8]) -> TrackData {
+        TrackData {
+            raw: unsafe { sys::create_track_data_from_memory(path.as_ptr(), contents.as_ptr(), contents.len()) },
+        }
+    }
+
     pub fn title(&self) -> String {
         c_str_to_str(unsafe { sys::get_title(self.raw) }).unwrap_or("".to_owned())
     }
@@ -76,6 +113,22 @@ impl TrackData {
         c_str_to_str(unsafe { sys::get_musicbrainz_track_id(self.raw) })
     }
 
+    pub fn comment(&self) -> Option<String> {
+        c_str_to_str(unsafe { sys::get_comment(self.raw) })
+    }
+
+    pub fn isrc(&self) -> Option<String> {
+        c_str_to_str(unsafe { sys::get_isrc(self.raw) })
+    }
+
+    pub fn encoder(&self) -> Option<String> {
+        c_str_to_str(unsafe { sys::get_encoder(self.raw) })
+    }
+
+    pub fn genres(&self) -> String {
+        c_str_to_str(unsafe { sys::get_genres(self.raw) }).unwrap_or("".to_owned())
+    }
+
     pub fn year(&self) -> u32 {
         unsafe { sys::get_year(self.raw) }
     }
@@ -114,18 +167,63 @@ impl TrackData {
             raw: sys::get_album_art_bytes(self.raw, size) as *const u8,
         }
     }
+
+    pub fn front_cover(&self) -> Option<Vec<u8>> {
+        let size = unsafe { sys::get_album_art_size(self.raw) };
+        if size == 0 {
+            return None;
+        }
+        let bytes = unsafe { self.cover_bytes(size) };
+        if bytes.raw.is_null() {
+            return None;
+        }
+        Some(unsafe { from_raw_parts(bytes.raw, size) }.to_vec())
+    }
+
+    pub fn set_front_cover(&self, cover: &[u8], mime_type: &CString) -> bool {
+        unsafe { sys::set_album_art(self.raw, cover.as_ptr(), cover.len(), mime_type.as_ptr()) }
+    }
+
+    pub fn set_tags(&self, update: &TagUpdate) -> bool {
+        let to_c_string = |tag: Option<String>| tag.and_then(|tag| CString::new(tag).ok());
+        let title = to_c_string(update.title.clone());
+        let artist = to_c_string(update.artist.clone());
+        let album = to_c_string(update.album.clone());
+        let album_artists = to_c_string(update.album_artists.as_ref().map(|artists| artists.join(";")));
+        let as_ptr = |tag: &Option<CString>| tag.as_ref().map_or(std::ptr::null(), |tag| tag.as_ptr());
+        unsafe {
+            sys::set_tags(
+                self.raw,
+                as_ptr(&title),
+                as_ptr(&artist),
+                as_ptr(&album),
+                as_ptr(&album_artists),
+                update.year.unwrap_or(0).max(0) as u32,
+                update.track_number.unwrap_or(0).max(0) as u32,
+            )
+        }
+    }
 }
 
+/// Gets the width and height of the encoded image, if it is an image.
+#[cfg(any(feature = "taglib", feature = "symphonia-tags"))]
+pub fn cover_dimensions(cover: &[u8]) -> Option<(i32, i32)> {
+    blob_size(cover).ok().map(|size| (size.width as i32, size.height as i32))
+}
+
+#[cfg(feature = "taglib")]
 struct CoverBytes {
     raw: *const u8,
 }
 
+#[cfg(feature = "taglib")]
 impl Drop for CoverBytes {
     fn drop(&mut self) {
         unsafe { sys::free_allocated_data(self.raw as *mut c_void) }
     }
 }
 
+#[cfg(feature = "taglib")]
 impl Drop for TrackData {
     fn drop(&mut self) {
         unsafe { sys::delete_track_data(self.raw) }
@@ -141,85 +239,217 @@ pub enum FileError {
     InvalidTagFile,
 }
 
-impl Track {
-    pub fn from_path(path: &Path, source: Option<&str>) -> Result<Track> {
-        if !path.exists() {
-            Err(Error::new(
-                ErrorKind::NotFound,
-                format!("File {:?} not found.", path),
-            ))
-        } else {
-            if let Ok(path_ptr) = path
-                .to_owned()
-                .to_str()
-                .ok_or(FileError::PathAsString)
-                .and_then(|path| CString::new(path).map_err(|err| FileError::NullPathString(err)))
-            {
-                let track: TrackData = TrackData::new(&path_ptr);
-                if let TrackFileType::Unknown = track.file_type() {
-                    Err(Error::new(
-                        ErrorKind::InvalidData,
-                        format!("File {:?} is unsupported", path),
-                    ))
-                } else {
-                    let mut fcw = 0;
-                    let mut fch = 0;
-                    if track.has_front_cover() {
-                        let bytes = unsafe { track.cover_bytes(384) };
-                        let slice = unsafe { from_raw_parts(bytes.raw, 384) };
-                        // match blob_size(slice) {
-                        //     Ok(size) => {
-                        //         fcw = size.width as i32;
-                        //         fch = size.height as i32;
-                        //     }
-                        //     Err(err) => println!("{:?}", err)
-                        // }
-                        if let Ok(size) = blob_size(slice) {
-                            fcw = size.width as i32;
-                            fch = size.height as i32;
-                            // println!("Width: {}, Height: {}", fcw, fch);
-                        }
-                        // } else {
-                        //     println!("Cover but unreadable");
-                        // }
-                        // } else {
-                        //     println!("No front cover");
-                        // }
-                    }
-
-                    let track = Ok(Track {
-                        file_path: path.to_owned(),
-                        file_type: track.file_type(),
-                        title: track.title(),
-                        artist: track.artist(),
-                        album: track.album(),
-                        album_artists: track
-                            .album_artists()
-                            .split(';')
-                            .map(|c| c.to_owned())
-                            .collect::<Vec<String>>(),
-                        year: track.year() as i32,
-                        track_number: track.track_number() as i32,
-                        musicbrainz_track_id: track.musicbrainz_track_id(),
-                        has_front_cover: track.has_front_cover(),
-                        front_cover_width: fcw,
-                        front_cover_height: fch,
-                        bitrate: track.bitrate(),
-                        sample_rate: track.sample_rate(),
-                        source: source.unwrap_or("None").to_owned(),
-                        disc_number: track.disc_number() as i32,
-                        duration: track.duration() as i32,
-                        updated: Local::now().format("%Y-%m-%d").to_string(),
-                    });
-                    drop(path_ptr);
-                    track
-                }
+/// Reads tracks through TagLib.
+#[cfg(feature = "taglib")]
+pub struct TagLibReader;
+
+#[cfg(feature = "taglib")]
+impl MetadataReader for TagLibReader {
+    fn name(&self) -> &'static str {
+        "taglib"
+    }
+
+    fn read_track(&self, path: &Path, source: Option<&str>) -> Result<Track> {
+        read_path(path, |path_ptr| from_track_data(path, source, TrackData::new(path_ptr)))
+    }
+
+    /// The file must not be truncated while it is read, since it may be mapped into memory.
+    /// If the file can not be put into memory, it is read as `read_track` reads it.
+    fn read_track_mapped(&self, path: &Path, source: Option<&str>) -> Result<Track> {
+        read_path(path, |path_ptr| {
+            let track = mapped::with_contents(path, |contents| {
+                from_track_data(path, source, TrackData::from_memory(path_ptr, contents))
+            });
+            match track {
+                Ok(Some(track)) => track,
+                _ => from_track_data(path, source, TrackData::new(path_ptr)),
+            }
+        })
+    }
+
+    fn read_front_cover(&self, path: &Path) -> Result<Option<Vec<u8>>> {
+        read_path(path, |path_ptr| Ok(TrackData::new(path_ptr).front_cover()))
+    }
+}
+
+#[cfg(feature = "taglib")]
+impl TagLibReader {
+    /// Embeds the encoded image as the front cover of the file, replacing its front cover.
+    /// Other pictures embedded in the file are kept.
+    pub fn write_front_cover(&self, path: &Path, cover: &[u8]) -> Result<()> {
+        let mime_type = if cover.starts_with(b"\x89PNG") { "image/png" } else { "image/jpeg" };
+        read_path(path, |path_ptr| {
+            let track = TrackData::new(path_ptr);
+            if track.set_front_cover(cover, &CString::new(mime_type).unwrap()) {
+                Ok(())
             } else {
                 Err(Error::new(
-                    ErrorKind::UnexpectedEof,
-                    format!("Path was invalid."),
+                    ErrorKind::Other,
+                    format!("File {:?} could not be saved.", path),
                 ))
             }
+        })
+    }
+
+    /// Writes the tags of the update to the file, leaving its other tags as they are.
+    pub fn write_tags(&self, path: &Path, update: &TagUpdate) -> Result<()> {
+        read_path(path, |path_ptr| {
+            let track = TrackData::new(path_ptr);
+            if let TrackFileType::Unknown = track.file_type() {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("File {:?} is unsupported", path),
+                ));
+            }
+            if track.set_tags(update) {
+                Ok(())
+            } else {
+                Err(Error::new(
+                    ErrorKind::Other,
+                    format!("File {:?} could not be saved.", path),
+                ))
+            }
+        })
+    }
+}
+
+#[cfg(feature = "taglib")]
+fn read_path<T, F>(path: &Path, read: F) -> Result<T>
+where
+    F: FnOnce(&CString) -> Result<T>,
+{
+    if !path.exists() {
+        Err(Error::new(
+            ErrorKind::NotFound,
+            format!("File {:?} not found.", path),
+        ))
+    } else {
+        if let Ok(path_ptr) = path
+            .to_owned()
+            .to_str()
+            .ok_or(FileError::PathAsString)
+            .and_then(|path| CString::new(path).map_err(|err| FileError::NullPathString(err)))
+        {
+            let track = read(&path_ptr);
+            drop(path_ptr);
+            track
+        } else {
+            Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                format!("Path was invalid."),
+            ))
+        }
+    }
+}
+
+#[cfg(feature = "taglib")]
+fn from_track_data(path: &Path, source: Option<&str>, track: TrackData) -> Result<Track> {
+    if let TrackFileType::Unknown = track.file_type() {
+        Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("File {:?} is unsupported", path),
+        ))
+    } else {
+        let mut fcw = 0;
+        let mut fch = 0;
+        if track.has_front_cover() {
+            let bytes = unsafe { track.cover_bytes(384) };
+            let slice = unsafe { from_raw_parts(bytes.raw, 384) };
+            // match blob_size(slice) {
+            //     Ok(size) => {
+            //         fcw = size.width as i32;
+            //         fch = size.height as i32;
+            //     }
+            //     Err(err) => println!("{:?}", err)
+            // }
+            if let Ok(size) = blob_size(slice) {
+                fcw = size.width as i32;
+                fch = size.height as i32;
+                // println!("Width: {}, Height: {}", fcw, fch);
+            }
+            // } else {
+            //     println!("Cover but unreadable");
+            // }
+            // } else {
+            //     println!("No front cover");
+            // }
+        }
+
+        Ok(Track::builder(path, track.file_type())
+            .title(track.title())
+            .artist(track.artist())
+            .album(track.album())
+            .album_artists(
+                track
+                    .album_artists()
+                    .split(';')
+                    .map(|c| c.to_owned())
+                    .collect::<Vec<String>>(),
+            )
+            .year(track.year() as i32)
+            .track_number(track.track_number() as i32)
+            .musicbrainz_track_id(track.musicbrainz_track_id())
+            .comment(track.comment())
+            .isrc(track.isrc())
+            .encoder(track.encoder())
+            .genres(split_genres(&track.genres()))
+            .front_cover(track.has_front_cover(), fcw, fch)
+            .bitrate(track.bitrate())
+            .sample_rate(track.sample_rate())
+            .source(source)
+            .disc_number(track.disc_number() as i32)
+            .duration(track.duration() as i32)
+            .build())
+    }
+}
+
+#[cfg(any(feature = "taglib", feature = "symphonia-tags"))]
+impl Track {
+    /// Reads the track of the file with the default backend.
+    pub fn from_path(path: &Path, source: Option<&str>) -> Result<Track> {
+        default_reader().read_track(path, source)
+    }
+
+    /// Reads the track as `from_path` does, but from the contents of the file in memory where
+    /// the backend can, which is faster when scanning many files at once.
+    ///
+    /// The file must not be truncated while it is read, since it may be mapped into memory.
+    pub fn from_path_mapped(path: &Path, source: Option<&str>) -> Result<Track> {
+        default_reader().read_track_mapped(path, source)
+    }
+
+    /// Reads the encoded image of the front cover of the track's file, if it has one.
+    pub fn read_front_cover(&self) -> Result<Option<Vec<u8>>> {
+        default_reader().read_front_cover(&self.file_path)
+    }
+
+    /// Embeds the encoded image as the front cover of the track's file, replacing its front cover.
+    /// Other pictures embedded in the file are kept. Covers can only be written through TagLib.
+    pub fn write_front_cover(&self, cover: &[u8]) -> Result<()> {
+        #[cfg(feature = "taglib")]
+        return TagLibReader.write_front_cover(&self.file_path, cover);
+        #[cfg(not(feature = "taglib"))]
+        {
+            let _ = cover;
+            Err(Error::new(
+                ErrorKind::Other,
+                format!("File {:?} could not be saved without TagLib.", self.file_path),
+            ))
+        }
+    }
+
+    /// Writes the tags of the update to the track's file, leaving its other tags as they are.
+    /// Tags can only be written through TagLib.
+    pub fn write_tags(&self, update: &TagUpdate) -> Result<()> {
+        #[cfg(feature = "taglib")]
+        return TagLibReader.write_tags(&self.file_path, update);
+        #[cfg(not(feature = "taglib"))]
+        {
+            let _ = update;
+            Err(Error::new(
+                ErrorKind::Other,
+                format!("File {:?} could not be saved without TagLib.", self.file_path),
+            ))
         }
     }
 }
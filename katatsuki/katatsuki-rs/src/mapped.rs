@@ -0,0 +1,99 @@
+//! Contents of files in memory, which tags are read from without TagLib seeking through the file
+//! with a syscall for every block it reads.
+//!
+//! Files at least `MAPPED_MIN_SIZE` large are mapped into memory, so only the pages TagLib
+//! touches are read from disk. Smaller files are read whole into a buffer kept by the thread,
+//! so a scan of many files does not allocate a buffer for every one of them.
+
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{Read, Result};
+#[cfg(unix)]
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+/// The smallest file that is mapped into memory rather than read into the buffer.
+/// Mapping a file has a fixed cost that reading a small file whole does not,
+/// and few tracks are smaller than this.
+const MAPPED_MIN_SIZE: u64 = 256 * 1024;
+
+thread_local! {
+    static BUFFER: RefCell<Vec<u8>> = RefCell::new(Vec::new());
+}
+
+/// A read-only mapping of a file into memory, unmapped when dropped.
+#[cfg(unix)]
+struct Mapping {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+#[cfg(unix)]
+impl Mapping {
+    fn new(file: &File, len: usize) -> Option<Mapping> {
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            None
+        } else {
+            Some(Mapping { ptr, len })
+        }
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr as *const u8, self.len) }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.ptr, self.len);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn with_mapping<T>(file: &File, len: u64, read: impl FnOnce(&[u8]) -> T) -> Option<T> {
+    let mapping = Mapping::new(file, len as usize)?;
+    Some(read(mapping.as_slice()))
+}
+
+// Large files are only read into memory where they can be mapped, and are otherwise left to TagLib.
+#[cfg(not(unix))]
+fn with_mapping<T>(_file: &File, _len: u64, _read: impl FnOnce(&[u8]) -> T) -> Option<T> {
+    None
+}
+
+/// Runs `read` on the contents of the file in memory. Returns `None` if the contents could not
+/// be put into memory, such as when the file can not be mapped, and should be read from disk.
+pub(crate) fn with_contents<T>(path: &Path, read: impl FnOnce(&[u8]) -> T) -> Result<Option<T>> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+    // Empty files can not be mapped, and have no tags anyway.
+    if len == 0 {
+        return Ok(None);
+    }
+    if len >= MAPPED_MIN_SIZE {
+        return Ok(with_mapping(&file, len, read));
+    }
+    BUFFER.with(|buffer| {
+        // The buffer is taken while it is read into, so a read on the same thread from within
+        // `read` gets a buffer of its own.
+        let mut bytes = buffer.take();
+        bytes.clear();
+        file.read_to_end(&mut bytes)?;
+        let result = read(&bytes);
+        buffer.replace(bytes);
+        Ok(Some(result))
+    })
+}
@@ -0,0 +1,154 @@
+use crate::track::{Track, TrackFileType};
+use std::str::FromStr;
+
+/// The sample rate in Hz above which lossless audio is high resolution.
+pub const HIRES_SAMPLE_RATE: i32 = 48000;
+
+/// The quality of a track, uniform across file types.
+///
+/// Qualities are ordered from worst to best, so the best of a set of
+/// duplicate tracks is the one with the greatest quality.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Quality {
+    /// Lossy audio below the mid bitrate of its format.
+    LossyLow,
+
+    /// Lossy audio at or above the mid bitrate of its format.
+    LossyMid,
+
+    /// Lossy audio at or above the high bitrate of its format.
+    LossyHigh,
+
+    /// Lossless audio at up to 16 bits per sample and 48kHz.
+    Lossless,
+
+    /// Lossless audio at more than 16 bits per sample, or more than 48kHz.
+    LosslessHiRes,
+}
+
+impl Quality {
+    /// Every quality, from worst to best.
+    pub const ALL: [Quality; 5] = [
+        Quality::LossyLow,
+        Quality::LossyMid,
+        Quality::LossyHigh,
+        Quality::Lossless,
+        Quality::LosslessHiRes,
+    ];
+
+    /// Classifies audio of the given file type, bitrate in kbps, and sample rate in Hz.
+    /// Returns `None` if the file type is unknown.
+    pub fn of(file_type: TrackFileType, bitrate: i32, sample_rate: i32) -> Option<Quality> {
+        if file_type.is_lossless() {
+            if file_type.bit_depth().map(|depth| depth > 16).unwrap_or(false)
+                || sample_rate > HIRES_SAMPLE_RATE
+            {
+                Some(Quality::LosslessHiRes)
+            } else {
+                Some(Quality::Lossless)
+            }
+        } else {
+            let (high, mid) = file_type.lossy_thresholds()?;
+            if bitrate >= high {
+                Some(Quality::LossyHigh)
+            } else if bitrate >= mid {
+                Some(Quality::LossyMid)
+            } else {
+                Some(Quality::LossyLow)
+            }
+        }
+    }
+
+    /// The name of the quality, as accepted by `Quality::from_str`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Quality::LossyLow => "lossy-low",
+            Quality::LossyMid => "lossy-mid",
+            Quality::LossyHigh => "lossy-high",
+            Quality::Lossless => "lossless",
+            Quality::LosslessHiRes => "lossless-hires",
+        }
+    }
+}
+
+/// Converts the name of a quality to its representation, ignoring case.
+/// The `lossy-` and `lossless-` prefixes may be left out, as in `high` or `hires`.
+impl FromStr for Quality {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s.to_lowercase().as_str() {
+            "lossy-low" | "low" => Ok(Quality::LossyLow),
+            "lossy-mid" | "mid" => Ok(Quality::LossyMid),
+            "lossy-high" | "high" => Ok(Quality::LossyHigh),
+            "lossless" => Ok(Quality::Lossless),
+            "lossless-hires" | "hires" => Ok(Quality::LosslessHiRes),
+            _ => Err(()),
+        }
+    }
+}
+
+impl TrackFileType {
+    /// Whether the file type is a lossless format.
+    pub fn is_lossless(&self) -> bool {
+        matches!(
+            self,
+            TrackFileType::FLAC4
+                | TrackFileType::FLAC8
+                | TrackFileType::FLAC16
+                | TrackFileType::FLAC24
+                | TrackFileType::FLAC32
+                | TrackFileType::FLAC
+                | TrackFileType::ALAC16
+                | TrackFileType::ALAC24
+                | TrackFileType::ALAC
+                | TrackFileType::AIFF4
+                | TrackFileType::AIFF8
+                | TrackFileType::AIFF16
+                | TrackFileType::AIFF24
+                | TrackFileType::AIFF32
+                | TrackFileType::AIFF
+                | TrackFileType::MonkeysAudio8
+                | TrackFileType::MonkeysAudio16
+                | TrackFileType::MonkeysAudio24
+                | TrackFileType::MonkeysAudio
+        )
+    }
+
+    /// The bits per sample of a lossless file type, if the file type specifies it.
+    pub fn bit_depth(&self) -> Option<u32> {
+        match self {
+            TrackFileType::FLAC4 | TrackFileType::AIFF4 => Some(4),
+            TrackFileType::FLAC8 | TrackFileType::AIFF8 | TrackFileType::MonkeysAudio8 => Some(8),
+            TrackFileType::FLAC16
+            | TrackFileType::ALAC16
+            | TrackFileType::AIFF16
+            | TrackFileType::MonkeysAudio16 => Some(16),
+            TrackFileType::FLAC24
+            | TrackFileType::ALAC24
+            | TrackFileType::AIFF24
+            | TrackFileType::MonkeysAudio24 => Some(24),
+            TrackFileType::FLAC32 | TrackFileType::AIFF32 => Some(32),
+            _ => None,
+        }
+    }
+
+    /// The minimum bitrates in kbps of `Quality::LossyHigh` and `Quality::LossyMid`
+    /// audio of a lossy file type, in that order.
+    ///
+    /// More efficient codecs reach the same quality at lower bitrates.
+    pub fn lossy_thresholds(&self) -> Option<(i32, i32)> {
+        match self {
+            TrackFileType::MP3CBR | TrackFileType::MP3VBR | TrackFileType::MP3 => Some((224, 160)),
+            TrackFileType::AAC | TrackFileType::Vorbis => Some((192, 128)),
+            TrackFileType::Opus => Some((128, 96)),
+            _ => None,
+        }
+    }
+}
+
+impl Track {
+    /// The quality of the track, or `None` if its file type is unknown.
+    pub fn quality(&self) -> Option<Quality> {
+        Quality::of(self.file_type, self.bitrate, self.sample_rate)
+    }
+}
@@ -0,0 +1,41 @@
+//! Backends that read the metadata of tracks from their files.
+//!
+//! Tags are read through TagLib with the default `taglib` feature, or in pure Rust through
+//! symphonia with the `symphonia-tags` feature, for platforms TagLib is painful to build on.
+//! When both are built, TagLib is used by default, since it reads more formats and can write
+//! front covers, which symphonia can not.
+
+use crate::track::Track;
+use std::io::Result;
+use std::path::Path;
+
+/// Reads tracks and their front covers from their files.
+pub trait MetadataReader: Send + Sync {
+    /// The name of the backend, such as `taglib`.
+    fn name(&self) -> &'static str;
+
+    /// Reads the track of the file, sourced from the given source if it is known.
+    /// Files that are not tracks of a supported type are `ErrorKind::InvalidData`.
+    fn read_track(&self, path: &Path, source: Option<&str>) -> Result<Track>;
+
+    /// Reads the track as `read_track` does, but from the contents of the file in memory where
+    /// the backend can, which is faster when scanning many files at once.
+    fn read_track_mapped(&self, path: &Path, source: Option<&str>) -> Result<Track> {
+        self.read_track(path, source)
+    }
+
+    /// Reads the encoded image of the front cover of the file, if it has one.
+    fn read_front_cover(&self, path: &Path) -> Result<Option<Vec<u8>>>;
+}
+
+/// Gets the backend tags are read with by default.
+#[cfg(feature = "taglib")]
+pub fn default_reader() -> &'static dyn MetadataReader {
+    &crate::TagLibReader
+}
+
+/// Gets the backend tags are read with by default.
+#[cfg(all(feature = "symphonia-tags", not(feature = "taglib")))]
+pub fn default_reader() -> &'static dyn MetadataReader {
+    &crate::symphonia_reader::SymphoniaReader
+}
@@ -0,0 +1,245 @@
+//! Reading tracks in pure Rust through symphonia, for platforms TagLib can not be built for.
+//!
+//! Only the container and its tags are read, and the audio is never decoded. Tags are read
+//! from the same keys TagLib reads them from, so a track reads the same through either backend,
+//! except that Monkey's Audio files are not supported, and the bitrate is the average bitrate
+//! of the whole file rather than of its audio.
+
+use crate::reader::MetadataReader;
+use crate::track::{split_genres, Track, TrackFileType};
+use imagesize::blob_size;
+use std::fs::File;
+use std::io::{Error, ErrorKind, Read, Result, Seek, SeekFrom};
+use std::path::Path;
+use symphonia::core::codecs::{
+    CodecParameters, CODEC_TYPE_AAC, CODEC_TYPE_ALAC, CODEC_TYPE_FLAC, CODEC_TYPE_MP3, CODEC_TYPE_OPUS,
+    CODEC_TYPE_VORBIS,
+};
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::{MetadataOptions, StandardTagKey, StandardVisualKey, Tag, Visual};
+use symphonia::core::probe::Hint;
+
+/// How far into the first frame of an MP3 file its Xing or VBRI header is looked for.
+const VBR_HEADER_SEARCH_SIZE: u64 = 2048;
+
+/// Reads tracks through symphonia.
+pub struct SymphoniaReader;
+
+/// The audio properties and tags of a file.
+struct Probed {
+    codec: CodecParameters,
+    tags: Vec<Tag>,
+    visuals: Vec<Visual>,
+}
+
+impl Probed {
+    /// Every value of the tag.
+    fn values(&self, key: StandardTagKey) -> Vec<String> {
+        self.tags
+            .iter()
+            .filter(|tag| tag.std_key == Some(key))
+            .map(|tag| tag.value.to_string().trim().to_owned())
+            .filter(|value| !value.is_empty())
+            .collect()
+    }
+
+    /// The first value of the first of the tags that has one.
+    fn first(&self, keys: &[StandardTagKey]) -> Option<String> {
+        keys.iter().find_map(|&key| self.values(key).into_iter().next())
+    }
+
+    /// The leading number of the first of the tags that has one, such as 3 of `3/12`, or 2001 of `2001-05-02`.
+    fn number(&self, keys: &[StandardTagKey]) -> Option<u32> {
+        keys.iter().find_map(|&key| {
+            self.values(key).iter().find_map(|value| {
+                let digits = value.chars().take_while(char::is_ascii_digit).collect::<String>();
+                digits.parse().ok()
+            })
+        })
+    }
+
+    /// The front cover, or a picture of another type if there is no front cover, as TagLib picks them.
+    /// Pictures of the other type have no usage.
+    fn front_cover(&self) -> Option<&Visual> {
+        self.visuals
+            .iter()
+            .find(|visual| visual.usage == Some(StandardVisualKey::FrontCover))
+            .or_else(|| self.visuals.iter().find(|visual| visual.usage.is_none()))
+    }
+
+    fn duration(&self) -> i32 {
+        match (self.codec.time_base, self.codec.n_frames) {
+            (Some(time_base), Some(frames)) => {
+                let time = time_base.calc_time(frames);
+                (time.seconds * 1000 + (time.frac * 1000.0) as u64) as i32
+            }
+            _ => 0,
+        }
+    }
+}
+
+fn unsupported(path: &Path) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("File {:?} is unsupported", path))
+}
+
+fn probe(path: &Path) -> Result<Probed> {
+    if !path.exists() {
+        return Err(Error::new(ErrorKind::NotFound, format!("File {:?} not found.", path)));
+    }
+    let stream = MediaSourceStream::new(Box::new(File::open(path)?), Default::default());
+    let mut hint = Hint::new();
+    if let Some(extension) = path.extension().and_then(|extension| extension.to_str()) {
+        hint.with_extension(extension);
+    }
+    let mut probed = symphonia::default::get_probe()
+        .format(&hint, stream, &FormatOptions::default(), &MetadataOptions::default())
+        .map_err(|_| unsupported(path))?;
+
+    let mut tags = Vec::new();
+    let mut visuals = Vec::new();
+    // Tags in front of the container, such as the ID3v2 tags of MP3 files, are read by the probe,
+    // and tags within the container by the format.
+    if let Some(mut metadata) = probed.metadata.get() {
+        if let Some(revision) = metadata.skip_to_latest() {
+            tags.extend_from_slice(revision.tags());
+            visuals.extend_from_slice(revision.visuals());
+        }
+    }
+    if let Some(revision) = probed.format.metadata().skip_to_latest() {
+        tags.extend_from_slice(revision.tags());
+        visuals.extend_from_slice(revision.visuals());
+    }
+    let codec = probed
+        .format
+        .default_track()
+        .map(|track| track.codec_params.clone())
+        .ok_or_else(|| unsupported(path))?;
+    Ok(Probed { codec, tags, visuals })
+}
+
+/// Whether the first frame of the MP3 file has a Xing, Info or VBRI header,
+/// which TagLib takes to mean the file is VBR.
+fn has_vbr_header(path: &Path) -> Result<bool> {
+    let mut file = File::open(path)?;
+    let mut header = [0u8; 10];
+    let mut offset = 0;
+    if file.read_exact(&mut header).is_ok() && &header[..3] == b"ID3" {
+        let size = header[6..10].iter().fold(0, |size, &byte| (size << 7) | u64::from(byte & 0x7f));
+        let footer = if header[5] & 0x10 != 0 { 10 } else { 0 };
+        offset = 10 + size + footer;
+    }
+    file.seek(SeekFrom::Start(offset))?;
+    let mut frame = Vec::new();
+    file.take(VBR_HEADER_SEARCH_SIZE).read_to_end(&mut frame)?;
+    Ok(frame
+        .windows(4)
+        .any(|window| window == b"Xing" || window == b"Info" || window == b"VBRI"))
+}
+
+fn is_aiff(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| ["aif", "aiff", "aifc"].contains(&extension.to_lowercase().as_str()))
+        .unwrap_or(false)
+}
+
+fn file_type(path: &Path, codec: &CodecParameters) -> TrackFileType {
+    let bit_depth = codec.bits_per_sample.unwrap_or(0);
+    match codec.codec {
+        CODEC_TYPE_FLAC => match bit_depth {
+            4 => TrackFileType::FLAC4,
+            8 => TrackFileType::FLAC8,
+            16 => TrackFileType::FLAC16,
+            24 => TrackFileType::FLAC24,
+            32 => TrackFileType::FLAC32,
+            _ => TrackFileType::FLAC,
+        },
+        CODEC_TYPE_MP3 => match has_vbr_header(path) {
+            Ok(true) => TrackFileType::MP3VBR,
+            _ => TrackFileType::MP3CBR,
+        },
+        CODEC_TYPE_AAC => TrackFileType::AAC,
+        CODEC_TYPE_ALAC => match bit_depth {
+            16 => TrackFileType::ALAC16,
+            24 => TrackFileType::ALAC24,
+            _ => TrackFileType::ALAC,
+        },
+        CODEC_TYPE_VORBIS => TrackFileType::Vorbis,
+        CODEC_TYPE_OPUS => TrackFileType::Opus,
+        // AIFF files are PCM, which WAV files also are, but WAV files are not supported.
+        _ if is_aiff(path) => match bit_depth {
+            4 => TrackFileType::AIFF4,
+            8 => TrackFileType::AIFF8,
+            16 => TrackFileType::AIFF16,
+            24 => TrackFileType::AIFF24,
+            32 => TrackFileType::AIFF32,
+            _ => TrackFileType::AIFF,
+        },
+        _ => TrackFileType::Unknown,
+    }
+}
+
+impl MetadataReader for SymphoniaReader {
+    fn name(&self) -> &'static str {
+        "symphonia"
+    }
+
+    fn read_track(&self, path: &Path, source: Option<&str>) -> Result<Track> {
+        let probed = probe(path)?;
+        let file_type = file_type(path, &probed.codec);
+        if let TrackFileType::Unknown = file_type {
+            return Err(unsupported(path));
+        }
+        let (fcw, fch) = probed
+            .front_cover()
+            .and_then(|cover| blob_size(&cover.data).ok())
+            .map(|size| (size.width as i32, size.height as i32))
+            .unwrap_or((0, 0));
+        let duration = probed.duration();
+        let bitrate = if duration > 0 {
+            (path.metadata()?.len() * 8 / duration as u64) as i32
+        } else {
+            0
+        };
+
+        Ok(Track::builder(path, file_type)
+            .title(probed.first(&[StandardTagKey::TrackTitle]).unwrap_or_default())
+            .artist(probed.first(&[StandardTagKey::Artist]).unwrap_or_default())
+            .album(probed.first(&[StandardTagKey::Album]).unwrap_or_default())
+            .album_artists(
+                probed
+                    .values(StandardTagKey::AlbumArtist)
+                    .join(";")
+                    .split(';')
+                    .map(|c| c.to_owned())
+                    .collect::<Vec<String>>(),
+            )
+            .year(probed.number(&[StandardTagKey::Date, StandardTagKey::ReleaseDate]).unwrap_or(0) as i32)
+            .track_number(probed.number(&[StandardTagKey::TrackNumber]).unwrap_or(0) as i32)
+            .musicbrainz_track_id(probed.first(&[
+                StandardTagKey::MusicBrainzTrackId,
+                StandardTagKey::MusicBrainzRecordingId,
+            ]))
+            .comment(probed.first(&[StandardTagKey::Comment]))
+            .isrc(probed.first(&[StandardTagKey::IdentIsrc]))
+            .encoder(probed.first(&[
+                StandardTagKey::EncoderSettings,
+                StandardTagKey::Encoder,
+                StandardTagKey::EncodedBy,
+            ]))
+            .genres(split_genres(&probed.values(StandardTagKey::Genre).join(";")))
+            .front_cover(probed.front_cover().is_some(), fcw, fch)
+            .bitrate(bitrate)
+            .sample_rate(probed.codec.sample_rate.unwrap_or(0) as i32)
+            .source(source)
+            // As with TagLib, tracks without a disc number are on the first disc.
+            .disc_number(probed.number(&[StandardTagKey::DiscNumber]).unwrap_or(1) as i32)
+            .duration(duration)
+            .build())
+    }
+
+    fn read_front_cover(&self, path: &Path) -> Result<Option<Vec<u8>>> {
+        Ok(probe(path)?.front_cover().map(|cover| cover.data.to_vec()))
+    }
+}
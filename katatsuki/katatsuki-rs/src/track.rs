@@ -1,8 +1,9 @@
+use chrono::Local;
 use std::path::PathBuf;
 use std::str::FromStr;
 use enum_primitive_derive::Primitive;
 
-#[derive(Debug, Primitive)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Primitive)]
 /// The File Type of the Track.
 /// TrackFileType discriminates on bitrates for lossless files, but
 /// does not for lossy files. 
@@ -84,7 +85,38 @@ pub enum TrackFileType {
     MP3 = 780,
 }
 
-#[derive(Debug)]
+impl TrackFileType {
+    /// Every file type katatsuki can return, excluding the generic `TrackFileType::MP3`.
+    pub const ALL: [TrackFileType; 25] = [
+        TrackFileType::Unknown,
+        TrackFileType::FLAC4,
+        TrackFileType::FLAC8,
+        TrackFileType::FLAC16,
+        TrackFileType::FLAC24,
+        TrackFileType::FLAC32,
+        TrackFileType::FLAC,
+        TrackFileType::MP3CBR,
+        TrackFileType::MP3VBR,
+        TrackFileType::AAC,
+        TrackFileType::Vorbis,
+        TrackFileType::Opus,
+        TrackFileType::ALAC16,
+        TrackFileType::ALAC24,
+        TrackFileType::ALAC,
+        TrackFileType::AIFF4,
+        TrackFileType::AIFF8,
+        TrackFileType::AIFF16,
+        TrackFileType::AIFF24,
+        TrackFileType::AIFF32,
+        TrackFileType::AIFF,
+        TrackFileType::MonkeysAudio8,
+        TrackFileType::MonkeysAudio16,
+        TrackFileType::MonkeysAudio24,
+        TrackFileType::MonkeysAudio,
+    ];
+}
+
+#[derive(Debug, Clone)]
 /// Represents a Track.
 pub struct Track {
     pub file_path: PathBuf,
@@ -96,6 +128,14 @@ pub struct Track {
     pub year: i32,
     pub track_number: i32,
     pub musicbrainz_track_id: Option<String>,
+    /// The comment tag of the track, such as where it was ripped from.
+    pub comment: Option<String>,
+    /// The International Standard Recording Code of the track, as tagged.
+    pub isrc: Option<String>,
+    /// The encoder and the settings the track was encoded with, such as `LAME 3.100 -V 0`.
+    pub encoder: Option<String>,
+    /// Every genre the track is tagged with, as split by `split_genres`.
+    pub genres: Vec<String>,
     pub has_front_cover: bool,
     pub front_cover_height: i32,
     pub front_cover_width: i32,
@@ -105,6 +145,233 @@ pub struct Track {
     pub disc_number: i32,
     pub duration: i32,
     pub updated: String,
+    /// The identifier of the track in a library, which stays the same when the file moves.
+    /// This is `None` for tracks read from a file that have not been added to a library.
+    pub uuid: Option<String>,
+}
+
+impl Track {
+    /// Starts building a track of the file at the given path. Every other field starts out unknown:
+    /// empty text and lists, zero numbers, no front cover, no optional tags, the source `None`,
+    /// and updated today.
+    pub fn builder<P: Into<PathBuf>>(file_path: P, file_type: TrackFileType) -> TrackBuilder {
+        TrackBuilder {
+            track: Track {
+                file_path: file_path.into(),
+                file_type,
+                title: String::new(),
+                artist: String::new(),
+                album_artists: Vec::new(),
+                album: String::new(),
+                year: 0,
+                track_number: 0,
+                musicbrainz_track_id: None,
+                comment: None,
+                isrc: None,
+                encoder: None,
+                genres: Vec::new(),
+                has_front_cover: false,
+                front_cover_height: 0,
+                front_cover_width: 0,
+                bitrate: 0,
+                sample_rate: 0,
+                source: NO_SOURCE.to_owned(),
+                disc_number: 0,
+                duration: 0,
+                updated: Local::now().format("%Y-%m-%d").to_string(),
+                uuid: None,
+            },
+        }
+    }
+
+    /// The year of the track, if it is tagged with one.
+    pub fn year(&self) -> Option<i32> {
+        Some(self.year).filter(|&year| year > 0)
+    }
+
+    /// The number of the track on its disc, if it is tagged with one.
+    pub fn track_number(&self) -> Option<i32> {
+        Some(self.track_number).filter(|&number| number > 0)
+    }
+
+    /// The number of the disc the track is on, if it is tagged with one.
+    pub fn disc_number(&self) -> Option<i32> {
+        Some(self.disc_number).filter(|&number| number > 0)
+    }
+
+    /// The bitrate of the track in kbps, if it is known.
+    pub fn bitrate(&self) -> Option<i32> {
+        Some(self.bitrate).filter(|&bitrate| bitrate > 0)
+    }
+
+    /// The sample rate of the track in Hz, if it is known.
+    pub fn sample_rate(&self) -> Option<i32> {
+        Some(self.sample_rate).filter(|&sample_rate| sample_rate > 0)
+    }
+
+    /// The width and height of the front cover, if the track has one and its size could be read.
+    pub fn front_cover_size(&self) -> Option<(i32, i32)> {
+        if self.has_front_cover && self.front_cover_width > 0 && self.front_cover_height > 0 {
+            Some((self.front_cover_width, self.front_cover_height))
+        } else {
+            None
+        }
+    }
+
+    /// Where the track was sourced from, if it is tagged with a source.
+    pub fn source(&self) -> Option<&str> {
+        Some(self.source.as_str()).filter(|&source| !source.is_empty() && source != NO_SOURCE)
+    }
+}
+
+/// The source of tracks that are not tagged with one.
+pub const NO_SOURCE: &str = "None";
+
+/// Builds a `Track` from only the metadata known about it, leaving the rest unknown,
+/// so metadata added to tracks does not have to be given everywhere tracks are made.
+#[derive(Debug, Clone)]
+pub struct TrackBuilder {
+    track: Track,
+}
+
+impl TrackBuilder {
+    pub fn title<S: Into<String>>(mut self, title: S) -> TrackBuilder {
+        self.track.title = title.into();
+        self
+    }
+
+    pub fn artist<S: Into<String>>(mut self, artist: S) -> TrackBuilder {
+        self.track.artist = artist.into();
+        self
+    }
+
+    pub fn album_artists(mut self, album_artists: Vec<String>) -> TrackBuilder {
+        self.track.album_artists = album_artists;
+        self
+    }
+
+    pub fn album<S: Into<String>>(mut self, album: S) -> TrackBuilder {
+        self.track.album = album.into();
+        self
+    }
+
+    pub fn year(mut self, year: i32) -> TrackBuilder {
+        self.track.year = year;
+        self
+    }
+
+    pub fn track_number(mut self, track_number: i32) -> TrackBuilder {
+        self.track.track_number = track_number;
+        self
+    }
+
+    pub fn disc_number(mut self, disc_number: i32) -> TrackBuilder {
+        self.track.disc_number = disc_number;
+        self
+    }
+
+    pub fn musicbrainz_track_id(mut self, musicbrainz_track_id: Option<String>) -> TrackBuilder {
+        self.track.musicbrainz_track_id = musicbrainz_track_id;
+        self
+    }
+
+    pub fn comment(mut self, comment: Option<String>) -> TrackBuilder {
+        self.track.comment = comment;
+        self
+    }
+
+    pub fn isrc(mut self, isrc: Option<String>) -> TrackBuilder {
+        self.track.isrc = isrc;
+        self
+    }
+
+    pub fn encoder(mut self, encoder: Option<String>) -> TrackBuilder {
+        self.track.encoder = encoder;
+        self
+    }
+
+    pub fn genres(mut self, genres: Vec<String>) -> TrackBuilder {
+        self.track.genres = genres;
+        self
+    }
+
+    /// Sets whether the track has a front cover, and its width and height, which are 0 if they could not be read.
+    pub fn front_cover(mut self, has_front_cover: bool, width: i32, height: i32) -> TrackBuilder {
+        self.track.has_front_cover = has_front_cover;
+        self.track.front_cover_width = width;
+        self.track.front_cover_height = height;
+        self
+    }
+
+    pub fn bitrate(mut self, bitrate: i32) -> TrackBuilder {
+        self.track.bitrate = bitrate;
+        self
+    }
+
+    pub fn sample_rate(mut self, sample_rate: i32) -> TrackBuilder {
+        self.track.sample_rate = sample_rate;
+        self
+    }
+
+    /// Sets where the track was sourced from, or `None` if it is not tagged with a source.
+    pub fn source(mut self, source: Option<&str>) -> TrackBuilder {
+        self.track.source = source.unwrap_or(NO_SOURCE).to_owned();
+        self
+    }
+
+    pub fn duration(mut self, duration: i32) -> TrackBuilder {
+        self.track.duration = duration;
+        self
+    }
+
+    /// Sets the day the track was last updated, such as `2018-04-01`.
+    pub fn updated<S: Into<String>>(mut self, updated: S) -> TrackBuilder {
+        self.track.updated = updated.into();
+        self
+    }
+
+    pub fn uuid(mut self, uuid: Option<String>) -> TrackBuilder {
+        self.track.uuid = uuid;
+        self
+    }
+
+    pub fn build(self) -> Track {
+        self.track
+    }
+}
+
+/// The tags written to the file of a track by `Track::write_tags`.
+/// Tags that are `None` are left as they are in the file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TagUpdate {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub album_artists: Option<Vec<String>>,
+    pub year: Option<i32>,
+    pub track_number: Option<i32>,
+}
+
+impl TagUpdate {
+    /// Whether the update leaves every tag as it is.
+    pub fn is_empty(&self) -> bool {
+        *self == TagUpdate::default()
+    }
+}
+
+/// The separators between multiple genres in a single genre tag.
+pub const GENRE_SEPARATORS: [char; 4] = [';', '/', ',', '|'];
+
+/// Splits a genre tag into every genre it lists, separated by any of `GENRE_SEPARATORS`.
+/// Genres are trimmed, and empty genres and genres repeated in a different case are dropped.
+pub fn split_genres(genres: &str) -> Vec<String> {
+    let mut split = Vec::<String>::new();
+    for genre in genres.split(&GENRE_SEPARATORS[..]).map(str::trim) {
+        if !genre.is_empty() && !split.iter().any(|g| g.eq_ignore_ascii_case(genre)) {
+            split.push(genre.to_owned());
+        }
+    }
+    split
 }
 
 /// Converts a lowercase string representation of a 
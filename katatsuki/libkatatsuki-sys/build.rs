@@ -50,6 +50,12 @@ fn main() {
       println!("cargo:rustc-link-lib=dylib=c++");
       println!("cargo:rustc-link-lib=dylib=z");
   }
+  else if target.contains("musl")
+  {
+      // Linked statically as well, so static builds such as seiri-server's need no shared libraries.
+      println!("cargo:rustc-link-lib=static=stdc++");
+      println!("cargo:rustc-link-lib=static=z");
+  }
   else if target.contains("linux")
   {
       println!("cargo:rustc-link-lib=dylib=stdc++");
@@ -0,0 +1,88 @@
+#pragma once
+
+#include <tiostream.h>
+#include <tbytevector.h>
+#include <tstring.h>
+
+#include <algorithm>
+#include <cstddef>
+#include <string>
+
+// A read-only stream over the contents of a file already in memory, such as a mapping of it,
+// so TagLib reads tags without a syscall for every block. The contents are borrowed, and must
+// outlive the stream.
+class MemoryStream : public TagLib::IOStream {
+private:
+    const char* data;
+    long long size;
+    long long position;
+#ifdef _WIN32
+    std::wstring path;
+#else
+    std::string path;
+#endif
+public:
+    MemoryStream(const TagLib::String& track_path, const char* data, size_t size)
+        : data(data), size(static_cast<long long>(size)), position(0) {
+#ifdef _WIN32
+        path = track_path.toWString();
+#else
+        path = track_path.to8Bit(true);
+#endif
+    }
+
+    // TagLib picks the type of the file by the extension of its name.
+    TagLib::FileName name() const override {
+        return TagLib::FileName(path.c_str());
+    }
+
+    TagLib::ByteVector readBlock(size_t length) override {
+        auto count = std::min(static_cast<long long>(length), size - position);
+        if (count <= 0) {
+            return TagLib::ByteVector();
+        }
+        TagLib::ByteVector block(data + position, static_cast<unsigned int>(count));
+        position += count;
+        return block;
+    }
+
+    // Tags are never written through the stream, only read.
+    void writeBlock(const TagLib::ByteVector&) override {}
+
+    void insert(const TagLib::ByteVector&, long long = 0, size_t = 0) override {}
+
+    void removeBlock(long long = 0, size_t = 0) override {}
+
+    bool readOnly() const override {
+        return true;
+    }
+
+    bool isOpen() const override {
+        return data != nullptr;
+    }
+
+    void seek(long long offset, Position p = Beginning) override {
+        switch (p) {
+        case Beginning:
+            position = offset;
+            break;
+        case Current:
+            position += offset;
+            break;
+        case End:
+            position = size + offset;
+            break;
+        }
+        position = std::clamp(position, 0LL, size);
+    }
+
+    long long tell() const override {
+        return position;
+    }
+
+    long long length() override {
+        return size;
+    }
+
+    void truncate(long long) override {}
+};
@@ -1,4 +1,5 @@
 #include "TrackData.h"
+#include "MemoryStream.h"
 #include "StringUtils.h"
 #include "track_file_type.h"
 
@@ -36,6 +37,12 @@ TrackData::TrackData(const char* track_path) {
     #endif
 }
 
+TrackData::TrackData(const char* track_path, const char* data, size_t size) {
+    TagLib::String path(track_path, TagLib::String::UTF8);
+    stream = make_unique<MemoryStream>(path, data, size);
+    f = make_shared<TagLib::FileRef>(stream.get(), true, TagLib::AudioProperties::Accurate);
+}
+
 const TagLib::String TrackData::GetTitle() {
     return f->tag()->title();
 }
@@ -97,6 +104,71 @@ unique_ptr<TagLib::ByteVector> TrackData::GetAlbumArtBytes() {
     return nullptr;
 }
 
+static TagLib::FLAC::Picture* NewFrontCover(const TagLib::ByteVector& data, const TagLib::String& mimeType) {
+    auto* picture = new TagLib::FLAC::Picture();
+    picture->setType(TagLib::FLAC::Picture::FrontCover);
+    picture->setMimeType(mimeType);
+    picture->setData(data);
+    return picture;
+}
+
+// Replaces the front cover, keeping any other pictures, and saves the file.
+const bool TrackData::SetAlbumArt(const TagLib::ByteVector& data, const TagLib::String& mimeType) {
+    if (auto flacFile = dynamic_cast<TagLib::FLAC::File *>(f->file())) {
+        for (TagLib::FLAC::Picture* picture : flacFile->pictureList()) {
+            if (picture->type() == TagLib::FLAC::Picture::FrontCover) {
+                flacFile->removePicture(picture, true);
+            }
+        }
+        flacFile->addPicture(NewFrontCover(data, mimeType));
+        return flacFile->save();
+    }
+
+    // As when reading, OGG files are written through the legacy xiphComment picture list.
+    if (auto xiphComment = dynamic_cast<TagLib::Ogg::XiphComment *>(f->file()->tag())) {
+        for (TagLib::FLAC::Picture* picture : xiphComment->pictureList()) {
+            if (picture->type() == TagLib::FLAC::Picture::FrontCover) {
+                xiphComment->removePicture(picture, true);
+            }
+        }
+        xiphComment->addPicture(NewFrontCover(data, mimeType));
+        return f->save();
+    }
+
+    auto pictureMap = f->tag()->pictures();
+    pictureMap.erase(TagLib::Picture::Type::FrontCover);
+    pictureMap.insert(TagLib::Picture(data, TagLib::Picture::Type::FrontCover, mimeType));
+    f->tag()->setPictures(pictureMap);
+    return f->save();
+}
+
+// Sets the tags that are not null or zero, leaving the rest as they are, and saves the file.
+// Album artists are separated by ';', as they are read.
+const bool TrackData::SetTags(const char* title, const char* artist, const char* album, const char* albumArtists, unsigned int year, unsigned int trackNumber) {
+    auto tag = f->tag();
+    if (albumArtists) {
+        auto properties = tag->properties();
+        properties.replace("ALBUMARTIST", TagLib::String(albumArtists, TagLib::String::UTF8).split(";"));
+        tag->setProperties(properties);
+    }
+    if (title) {
+        tag->setTitle(TagLib::String(title, TagLib::String::UTF8));
+    }
+    if (artist) {
+        tag->setArtist(TagLib::String(artist, TagLib::String::UTF8));
+    }
+    if (album) {
+        tag->setAlbum(TagLib::String(album, TagLib::String::UTF8));
+    }
+    if (year) {
+        tag->setYear(year);
+    }
+    if (trackNumber) {
+        tag->setTrack(trackNumber);
+    }
+    return f->save();
+}
+
 const unsigned int TrackData::GetTrackNumber() {
     return f->tag()->track();
 }
@@ -182,6 +254,39 @@ const TagLib::String TrackData::GetAlbumArtists() {
     return TagLib::String();
 }
 
+const TagLib::String TrackData::GetGenres() {
+    if (!f->tag()->properties()["GENRE"].isEmpty()) {
+        return join(f->tag()->properties()["GENRE"], ";");
+    }
+    return TagLib::String();
+}
+
+const TagLib::String TrackData::GetComment() {
+    if (!f->tag()->properties()["COMMENT"].isEmpty()) {
+        return f->tag()->properties()["COMMENT"].front();
+    }
+    return TagLib::String();
+}
+
+// TSRC in ID3v2 tags, and ISRC in Xiph comments and MP4 atoms.
+const TagLib::String TrackData::GetIsrc() {
+    if (!f->tag()->properties()["ISRC"].isEmpty()) {
+        return f->tag()->properties()["ISRC"].front();
+    }
+    return TagLib::String();
+}
+
+// The encoder settings are TSSE in ID3v2 tags, while FLAC and Vorbis encoders write ENCODER,
+// and MP4 files keep the encoding tool as ENCODEDBY.
+const TagLib::String TrackData::GetEncoder() {
+    for (const char* key : {"ENCODING", "ENCODER", "ENCODEDBY"}) {
+        if (!f->tag()->properties()[key].isEmpty()) {
+            return f->tag()->properties()[key].front();
+        }
+    }
+    return TagLib::String();
+}
+
 const TagLib::String TrackData::GetMusicBrainzTrackId() {
     if (!f->tag()->properties()["MUSICBRAINZ_TRACKID"].isEmpty()) {
         return f->tag()->properties()["MUSICBRAINZ_TRACKID"].front();
@@ -1,6 +1,7 @@
 #include "track_file_type.h"
 
 #include <tstring.h>
+#include <tiostream.h>
 #include <fileref.h>
 #include <array>
 #include <optional>
@@ -8,9 +9,13 @@
 
 class TrackData {
 private:
+	// Declared before the file, which reads from it until it is destroyed.
+	std::unique_ptr<TagLib::IOStream> stream;
 	std::shared_ptr<TagLib::FileRef> f;
 public:
 	TrackData(const char* track_path);
+	// Reads the track from its contents in memory, which must outlive it. Tracks read from memory can not be saved.
+	TrackData(const char* track_path, const char* data, size_t size);
 	virtual ~TrackData() {};
 	const enum track_file_type GetFileType();
 	const TagLib::String GetTitle();
@@ -18,6 +23,10 @@ public:
 	const TagLib::String GetAlbumArtists();
 	const TagLib::String GetAlbum();
 	const TagLib::String GetMusicBrainzTrackId();
+	const TagLib::String GetComment();
+	const TagLib::String GetIsrc();
+	const TagLib::String GetEncoder();
+	const TagLib::String GetGenres();
 	const unsigned int GetYear();
 	const unsigned int GetTrackNumber();
 	const bool HasAlbumArt();
@@ -26,4 +35,6 @@ public:
 	const unsigned int GetDiscNumber();
 	const long long GetDuration();
 	std::unique_ptr<TagLib::ByteVector> GetAlbumArtBytes();
+	const bool SetAlbumArt(const TagLib::ByteVector& data, const TagLib::String& mimeType);
+	const bool SetTags(const char* title, const char* artist, const char* album, const char* albumArtists, unsigned int year, unsigned int trackNumber);
 };
\ No newline at end of file
@@ -22,6 +22,11 @@ extern "C" track_data * create_track_data(const char* track_path) {
     return reinterpret_cast<track_data*>(trackData);
 }
 
+extern "C" track_data * create_track_data_from_memory(const char* track_path, const unsigned char* data, size_t size) {
+    auto trackData = new TrackData(track_path, reinterpret_cast<const char*>(data), size);
+    return reinterpret_cast<track_data*>(trackData);
+}
+
 extern "C" void delete_track_data(track_data* track_data) {
     delete reinterpret_cast<TrackData*>(track_data);
 }
@@ -51,6 +56,26 @@ extern "C" const char* get_musicbrainz_track_id(track_data* track_data) {
     return strdup(trackData->GetMusicBrainzTrackId().to8Bit(true).c_str());
 }
 
+extern "C" const char* get_comment(track_data* track_data) {
+    auto* trackData = reinterpret_cast<TrackData*>(track_data);
+    return strdup(trackData->GetComment().to8Bit(true).c_str());
+}
+
+extern "C" const char* get_isrc(track_data* track_data) {
+    auto* trackData = reinterpret_cast<TrackData*>(track_data);
+    return strdup(trackData->GetIsrc().to8Bit(true).c_str());
+}
+
+extern "C" const char* get_encoder(track_data* track_data) {
+    auto* trackData = reinterpret_cast<TrackData*>(track_data);
+    return strdup(trackData->GetEncoder().to8Bit(true).c_str());
+}
+
+extern "C" const char* get_genres(track_data* track_data) {
+    auto* trackData = reinterpret_cast<TrackData*>(track_data);
+    return strdup(trackData->GetGenres().to8Bit(true).c_str());
+}
+
 extern "C" const unsigned int get_year(track_data* track_data) {
     auto* trackData = reinterpret_cast<TrackData*>(track_data);
     return trackData->GetYear();
@@ -92,6 +117,12 @@ extern "C" const unsigned char* get_album_art_all_bytes(track_data* track_data)
     return nullptr;
 }
 
+extern "C" const size_t get_album_art_size(track_data* track_data) {
+    auto* trackData = reinterpret_cast<TrackData*>(track_data);
+    auto bytes = trackData->GetAlbumArtBytes();
+    return bytes ? bytes->size() : 0;
+}
+
 extern "C" const unsigned char* get_album_art_bytes(track_data* track_data, size_t size) {
     auto* trackData = reinterpret_cast<TrackData*>(track_data);
     auto bytes = trackData->GetAlbumArtBytes();
@@ -108,6 +139,17 @@ extern "C" const bool has_album_art(track_data* track_data) {
     return trackData->HasAlbumArt();
 }
 
+extern "C" const bool set_album_art(track_data* track_data, const unsigned char* bytes, size_t size, const char* mime_type) {
+    auto* trackData = reinterpret_cast<TrackData*>(track_data);
+    TagLib::ByteVector data(reinterpret_cast<const char*>(bytes), static_cast<unsigned int>(size));
+    return trackData->SetAlbumArt(data, TagLib::String(mime_type, TagLib::String::UTF8));
+}
+
+extern "C" const bool set_tags(track_data* track_data, const char* title, const char* artist, const char* album, const char* album_artists, unsigned int year, unsigned int track_number) {
+    auto* trackData = reinterpret_cast<TrackData*>(track_data);
+    return trackData->SetTags(title, artist, album, album_artists, year, track_number);
+}
+
 extern "C" void free_allocated_data(void* data) {
     std::free(data);
 }
\ No newline at end of file
@@ -11,6 +11,8 @@ extern "C" {
 
 track_data *create_track_data(const char *track_path);
 
+track_data *create_track_data_from_memory(const char *track_path, const unsigned char *data, size_t size);
+
 void delete_track_data(track_data *track_path);
 
 void free_allocated_data(void *data);
@@ -25,6 +27,14 @@ const char *get_album(track_data *track_data);
 
 const char *get_musicbrainz_track_id(track_data *track_data);
 
+const char *get_comment(track_data *track_data);
+
+const char *get_isrc(track_data *track_data);
+
+const char *get_encoder(track_data *track_data);
+
+const char *get_genres(track_data *track_data);
+
 const unsigned int get_year(track_data *track_data);
 
 const unsigned int get_track_number(track_data *track_data);
@@ -39,11 +49,17 @@ const int get_sample_rate(track_data *track_data);
 
 const unsigned char *get_album_art_all_bytes(track_data *track_data);
 
+const size_t get_album_art_size(track_data *track_data);
+
 const unsigned char *get_album_art_bytes(track_data* track_data, size_t size);
 
 const int get_file_type(track_data *track_data);
 
 const bool has_album_art(track_data *track_data);
+
+const bool set_album_art(track_data *track_data, const unsigned char *bytes, size_t size, const char *mime_type);
+
+const bool set_tags(track_data *track_data, const char *title, const char *artist, const char *album, const char *album_artists, unsigned int year, unsigned int track_number);
 #ifdef __cplusplus
 }
 #endif
\ No newline at end of file
@@ -12,6 +12,11 @@ extern "C" {
     pub fn create_track_data(track_path: *const ::std::os::raw::c_char)
      -> *mut track_data;
 }
+extern "C" {
+    pub fn create_track_data_from_memory(track_path: *const ::std::os::raw::c_char,
+                                         data: *const ::std::os::raw::c_uchar,
+                                         size: usize) -> *mut track_data;
+}
 extern "C" {
     pub fn delete_track_data(track_path: *mut track_data);
 }
@@ -38,6 +43,22 @@ extern "C" {
     pub fn get_musicbrainz_track_id(track_data: *mut track_data)
      -> *const ::std::os::raw::c_char;
 }
+extern "C" {
+    pub fn get_comment(track_data: *mut track_data)
+     -> *const ::std::os::raw::c_char;
+}
+extern "C" {
+    pub fn get_isrc(track_data: *mut track_data)
+     -> *const ::std::os::raw::c_char;
+}
+extern "C" {
+    pub fn get_encoder(track_data: *mut track_data)
+     -> *const ::std::os::raw::c_char;
+}
+extern "C" {
+    pub fn get_genres(track_data: *mut track_data)
+     -> *const ::std::os::raw::c_char;
+}
 extern "C" {
     pub fn get_year(track_data: *mut track_data) -> ::std::os::raw::c_uint;
 }
@@ -64,6 +85,9 @@ extern "C" {
     pub fn get_album_art_all_bytes(track_data: *mut track_data)
      -> *const ::std::os::raw::c_uchar;
 }
+extern "C" {
+    pub fn get_album_art_size(track_data: *mut track_data) -> usize;
+}
 extern "C" {
     pub fn get_album_art_bytes(track_data: *mut track_data, size: usize)
      -> *const ::std::os::raw::c_uchar;
@@ -75,3 +99,17 @@ extern "C" {
 extern "C" {
     pub fn has_album_art(track_data: *mut track_data) -> bool;
 }
+extern "C" {
+    pub fn set_album_art(track_data: *mut track_data,
+                         bytes: *const ::std::os::raw::c_uchar, size: usize,
+                         mime_type: *const ::std::os::raw::c_char) -> bool;
+}
+extern "C" {
+    pub fn set_tags(track_data: *mut track_data,
+                    title: *const ::std::os::raw::c_char,
+                    artist: *const ::std::os::raw::c_char,
+                    album: *const ::std::os::raw::c_char,
+                    album_artists: *const ::std::os::raw::c_char,
+                    year: ::std::os::raw::c_uint,
+                    track_number: ::std::os::raw::c_uint) -> bool;
+}
@@ -1,7 +1,14 @@
 import { Track } from "./types";
 
+interface QueryOptions {
+    /** Leaves out tracks linked as a version of another track in the results. */
+    collapse?: boolean;
+    /** Keeps only the preferred edition of each album in the results. */
+    editions?: boolean;
+}
+
 interface Seiri {
-    queryTracks: (bang: string) => { tracks: Track[] };
+    queryTracks: (bang: string, columns?: string[], options?: QueryOptions) => { tracks: Track[] };
     refreshTracks: (filePaths: string[]) => void;
     openTrackFolder: (track: Track) => void;
     hideWindow: () => void;
@@ -1,4 +1,4 @@
-const { app, BrowserWindow, Menu, Tray } = require("electron");
+const { app, BrowserWindow, Menu, Tray, dialog } = require("electron");
 const notifier = require("node-notifier");
 const path = require("path");
 const child = require("child_process");
@@ -48,21 +48,48 @@ if (!gotLock) {
 
 | Code                          | Description                                            |
 | ----------------------------- | ------------------------------------------------------ |
-| `TRACKADDED(Artist||Title)`   | A track has successfully been added to the library     |
+| `TRACKADDED(Artist||Title||UUID)` | A track has successfully been added to the library |
+| `BATCHIMPORTED(Imported||Total)` | A batch of files was processed by the watcher       |
+| `SIDECARADDED(Path)`          | A sidecar file was moved alongside its album          |
+| `ARCHIVEUNPACKED(Archive||Folder)` | A purchase archive was unpacked to be imported   |
+| `RIPIMPORTED(Rip||Folder)`    | A verified CD rip was moved to be imported             |
+| `IMPORTVETOED(File||Command)` | The import of the given file was vetoed by a hook      |
+| `FILEIGNORED(Path)`           | The given file was rejected before, so it was left alone |
+| `TRACKSREMOVED(Path||Count)` | Tracks were removed along with their files         |
+| `FILESRECONCILED(Files)`      | Files left in the watch folder were queued at startup |
+| `WAITINGFORDOWNLOAD(Path)`    | The given file is waiting to be downloaded         |
+| `LEASECHANGED(Holder||Previous)` | The write lease passed to another writer           |
+| `ELEASELAPSED(Holder)`        | The given writer never released the write lease        |
+| `ELEASELOST(Holder)`          | The given writer lost the write lease while writing    |
 | `!ETRACK`                      | Generic track error                                    |
 | !`ETRACKMOVE(Path)`            | The given track could not be moved to its library path |
+| `EPERMISSIONS(Path)`          | The configured permissions could not be given to the file |
+| !`EALBUMINCOMPLETE(Folder)`    | The album in the given folder was left in place        |
 | `!ECREATEDIRECTORY(Directory)` | The given directory could not be created               |
 | `!ENONTRACK(Path)`             | The given path is not a track                          |
 | !`EMISSINGTAG(Track||Tag)`     | The given track is missing the given tag               |
+| !`EHOOK(File||Command)`        | The given hook could not be run on the given file      |
 | !`EWATCHER`                    | Generic watcher error                                  |
 | !`EWATCHERDIED`                | The watcher died                                       |
+| `EWATCHERRESTARTING(Restarts||Delay)` | The watcher is restarted after the given delay |
+| !`EWATCHERDEGRADED(Restarts)`  | The watcher kept dying, so it is left stopped          |
 | !`EWATCHERNOACCESS(Path)`      | The watcher can not access the given folder            |
 | `ECONFIGINVALID`              | The configuration file is invalid                      |
 | `ECONFIGIO(Path)`             | The given configuration path can not be accessed       |
+| `EANALYSIS(Path)`             | The given track could not be analyzed                  |
+| `EWEBHOOK(Url)`               | An event could not be delivered to the given webhook   |
+| `ESYNC(Address)`              | Profiles could not be synced with the given instance   |
+| `EHTTP(Address)`              | A request to the HTTP API failed                       |
+| `EARCHIVE(Archive)`           | The given archive could not be unpacked                |
+| `ERIPREJECTED(Rip||Reason)`   | The given CD rip could not be verified by its rip log  |
+| `EREPORT(Folder)`             | The report of an import batch could not be written     |
+| `EBATCHSHED(Files)`           | A batch of files was shed since the import queue was full |
+| `EDBCONFLICT(Path)`           | A conflicting copy of the library was found            |
 */
 
-const expression = /^(TRACKADDED|E[A-Z]+)::(.*)$/;
+const expression = /^(TRACKADDED|BATCHIMPORTED|SIDECARADDED|ARCHIVEUNPACKED|RIPIMPORTED|IMPORTVETOED|FILEIGNORED|TRACKSREMOVED|FILESRECONCILED|LEASECHANGED|WAITINGFORDOWNLOAD|E[A-Z]+)::(.*)$/;
 const twoparamexpr = /^(.*)\|\|(.*)$/;
+const threeparamexpr = /^(.*)\|\|(.*)\|\|(.*)$/;
 
 const processWatcherMessage = message => {
   try {
@@ -85,13 +112,50 @@ const processWatcherMessage = message => {
     switch (messageType) {
       case "TRACKADDED":
         log.info("TRACKADDED recv with payload <" + messagePayload + ">");
-        let trackdata = twoparamexpr.exec(messagePayload);
-        if (trackdata && trackdata.length === 3) {
+        let trackdata = threeparamexpr.exec(messagePayload);
+        if (trackdata && trackdata.length === 4) {
           newTracksAdded.push(trackdata[1] + " - " + trackdata[2]);
         } else {
           log.warn("TRACKADDED bad recv <" + _message + ">");
         }
         break;
+      case "BATCHIMPORTED":
+        // Added tracks are already collected from their TRACKADDED messages.
+        log.info("BATCHIMPORTED recv with payload <" + messagePayload + ">");
+        break;
+      case "SIDECARADDED":
+        log.info("SIDECARADDED recv with payload <" + messagePayload + ">");
+        break;
+      case "ARCHIVEUNPACKED":
+        log.info("ARCHIVEUNPACKED recv with payload <" + messagePayload + ">");
+        break;
+      case "RIPIMPORTED":
+        log.info("RIPIMPORTED recv with payload <" + messagePayload + ">");
+        break;
+      case "IMPORTVETOED":
+        log.info("IMPORTVETOED recv with payload <" + messagePayload + ">");
+        break;
+      case "FILEIGNORED":
+        log.info("FILEIGNORED recv with payload <" + messagePayload + ">");
+        break;
+      case "TRACKSREMOVED":
+        log.info("TRACKSREMOVED recv with payload <" + messagePayload + ">");
+        break;
+      case "FILESRECONCILED":
+        log.info("FILESRECONCILED recv with payload <" + messagePayload + ">");
+        break;
+      case "WAITINGFORDOWNLOAD":
+        log.info("WAITINGFORDOWNLOAD recv with payload <" + messagePayload + ">");
+        break;
+      case "LEASECHANGED":
+        log.info("LEASECHANGED recv with payload <" + messagePayload + ">");
+        break;
+      case "ELEASELAPSED":
+        log.warn("ELEASELAPSED recv with payload <" + messagePayload + ">");
+        break;
+      case "ELEASELOST":
+        log.warn("ELEASELOST recv with payload <" + messagePayload + ">");
+        break;
       case "EMISSINGTAG":
         log.info("EMISSINGTAG recv with payload <" + messagePayload + ">");
         let tagdata = twoparamexpr.exec(messagePayload);
@@ -106,6 +170,19 @@ const processWatcherMessage = message => {
           log.info("EMISSINGTAG bad recv <" + _message + ">");
         }
         break;
+      case "EHOOK":
+        log.info("EHOOK recv");
+        let hookdata = twoparamexpr.exec(messagePayload);
+        if (hookdata && hookdata.length === 3) {
+          notifier.notify({
+            title: "Import hook failed.",
+            message: "The hook " + hookdata[2] + " could not be run on " + hookdata[1] + ".",
+            appID: appId
+          });
+        } else {
+          log.info("EHOOK bad recv <" + _message + ">");
+        }
+        break;
       case "ETRACKMOVE":
         log.info("ETRACKMOVE recv");
         notifier.notify({
@@ -114,6 +191,14 @@ const processWatcherMessage = message => {
           appID: appId
         });
         break;
+      case "EALBUMINCOMPLETE":
+        log.info("EALBUMINCOMPLETE recv");
+        notifier.notify({
+          title: "Album was not imported.",
+          message: "Some tracks in " + messagePayload + " could not be imported, so the album was left in place.",
+          appID: appId
+        });
+        break;
       case "ETRACK":
         log.info("ETRACK recv");
         notifier.notify({
@@ -154,6 +239,18 @@ const processWatcherMessage = message => {
           appID: appId
         });
         break;
+      case "EWATCHERRESTARTING":
+        log.warn("EWATCHERRESTARTING recv with payload <" + messagePayload + ">");
+        break;
+      case "EWATCHERDEGRADED":
+        log.info("EWATCHERDEGRADED recv");
+        notifier.notify({
+          title: "Track watcher stopped.",
+          message:
+            "The track watcher kept failing after " + messagePayload + " restarts, so it was stopped. Restart the track watcher once the problem is fixed.",
+          appID: appId
+        });
+        break;
       case "EWATCHERNOACCESS":
         log.info("EWATCHERNOACCESS recv");
         notifier.notify({
@@ -186,6 +283,32 @@ const processWatcherMessage = message => {
           runningWatcher.quit();
         }
         break;
+      case "EDBCONFLICT":
+        log.info("EDBCONFLICT recv");
+        // The watcher waits for every conflicting copy to be merged, then quits to be restarted.
+        dialog
+          .showMessageBox({
+            type: "warning",
+            title: "Library conflict",
+            message: "A conflicting copy of the library was found.",
+            detail:
+              messagePayload +
+              " was left by the service syncing the library. Merge it into the library to start the track watcher.",
+            buttons: ["Merge", "Quit"],
+            defaultId: 0,
+            cancelId: 1
+          })
+          .then(({ response }) => {
+            if (!runningWatcher) {
+              return;
+            }
+            if (response === 0) {
+              runningWatcher.command("resolveconflict " + messagePayload);
+            } else {
+              runningWatcher.quit();
+            }
+          });
+        break;
       case "ECONFIGIO":
         log.info("ECONFIGIO recv");
         notifier.notify({
@@ -198,6 +321,34 @@ const processWatcherMessage = message => {
           runningWatcher.quit();
         }
         break;
+      case "EANALYSIS":
+        // Analysis runs over the whole library, so failures are only logged.
+        log.warn("EANALYSIS recv with payload <" + messagePayload + ">");
+        break;
+      case "EWEBHOOK":
+        log.warn("EWEBHOOK recv with payload <" + messagePayload + ">");
+        break;
+      case "ESYNC":
+        log.warn("ESYNC recv with payload <" + messagePayload + ">");
+        break;
+      case "EHTTP":
+        log.warn("EHTTP recv with payload <" + messagePayload + ">");
+        break;
+      case "EARCHIVE":
+        log.warn("EARCHIVE recv with payload <" + messagePayload + ">");
+        break;
+      case "ERIPREJECTED":
+        log.warn("ERIPREJECTED recv with payload <" + messagePayload + ">");
+        break;
+      case "EREPORT":
+        log.warn("EREPORT recv with payload <" + messagePayload + ">");
+        break;
+      case "EPERMISSIONS":
+        log.warn("EPERMISSIONS recv with payload <" + messagePayload + ">");
+        break;
+      case "EBATCHSHED":
+        log.warn("EBATCHSHED recv with payload <" + messagePayload + ">");
+        break;
       default:
         log.warn("EUNKNOWN recv");
 
@@ -14,6 +14,7 @@ const launch_watcher = (onStdErr, onQuit) => {
   watcher.stderr.pipe(process.stdout);
   return {
     quit: () => watcher.stdin.write("exit\r\n"),
+    command: line => watcher.stdin.write(line + "\r\n"),
     disconnect: () => {
       if (watcher) {
         try {
@@ -0,0 +1,13 @@
+use std::env;
+use std::path::PathBuf;
+
+fn main() {
+    let crate_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+    let mut header_path = PathBuf::from(&crate_dir);
+    header_path.push("include");
+    header_path.push("seiri.h");
+
+    cbindgen::generate(&crate_dir)
+        .expect("Unable to generate C bindings")
+        .write_to_file(header_path);
+}
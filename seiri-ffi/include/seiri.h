@@ -0,0 +1,117 @@
+#ifndef SEIRI_H
+#define SEIRI_H
+
+/* This file is generated by cbindgen from seiri-ffi/src/lib.rs. Do not edit it manually. */
+
+#include <stdbool.h>
+#include <stdint.h>
+
+/**
+ * The result of a call into seiri.
+ */
+typedef enum SeiriStatus {
+  SEIRI_STATUS_OK = 0,
+  SEIRI_STATUS_INVALID_ARGUMENT = 1,
+  SEIRI_STATUS_CONFIG_ERROR = 2,
+  SEIRI_STATUS_QUERY_ERROR = 3,
+  SEIRI_STATUS_DATABASE_ERROR = 4,
+  SEIRI_STATUS_IMPORT_ERROR = 5,
+  SEIRI_STATUS_PANIC = 6,
+} SeiriStatus;
+
+/**
+ * A handle to an open library. Created with `seiri_open`, and freed with `seiri_close`.
+ */
+typedef struct SeiriLibrary SeiriLibrary;
+
+/**
+ * Called with the code and the `||`-separated arguments of every event
+ * emitted by the library. See `seiri::events::Event` for the list of codes.
+ */
+typedef void (*SeiriEventCallback)(const char *code, const char *payload, void *user_data);
+
+/**
+ * A track in the library.
+ * Album artists and genres are separated by `;`, and `musicbrainz_track_id`, `uuid`, `comment`, `isrc` and `encoder` may be null.
+ */
+typedef struct SeiriTrack {
+  const char *file_path;
+  const char *title;
+  const char *artist;
+  const char *album_artists;
+  const char *album;
+  int32_t year;
+  int32_t track_number;
+  const char *musicbrainz_track_id;
+  bool has_front_cover;
+  int32_t front_cover_width;
+  int32_t front_cover_height;
+  int32_t bitrate;
+  int32_t sample_rate;
+  const char *source;
+  int32_t disc_number;
+  int32_t duration;
+  int32_t file_type;
+  const char *updated;
+  const char *uuid;
+  const char *genres;
+  const char *comment;
+  const char *isrc;
+  const char *encoder;
+} SeiriTrack;
+
+/**
+ * Called once for every track matching a query.
+ */
+typedef void (*SeiriTrackCallback)(const struct SeiriTrack *track, void *user_data);
+
+/**
+ * Opens the library described by the seiri configuration file,
+ * and writes the handle to `library`.
+ *
+ * # Safety
+ * `library` must be a valid pointer to write the handle to.
+ */
+enum SeiriStatus seiri_open(struct SeiriLibrary **library);
+
+/**
+ * Closes a library opened with `seiri_open`.
+ *
+ * # Safety
+ * `library` must be a handle returned by `seiri_open` that has not already been closed.
+ */
+void seiri_close(struct SeiriLibrary *library);
+
+/**
+ * Sets the callback that receives events emitted by the library, replacing any previous callback.
+ * Passing a null callback stops events from being delivered.
+ *
+ * # Safety
+ * `library` must be a valid handle. `user_data` is passed to the callback as is.
+ */
+enum SeiriStatus seiri_set_event_callback(struct SeiriLibrary *library,
+                                          SeiriEventCallback callback,
+                                          void *user_data);
+
+/**
+ * Runs the bang query, calling `callback` once for every matching track.
+ *
+ * # Safety
+ * `library` must be a valid handle, and `query` a valid null-terminated string.
+ */
+enum SeiriStatus seiri_query(const struct SeiriLibrary *library,
+                             const char *query,
+                             SeiriTrackCallback callback,
+                             void *user_data);
+
+/**
+ * Imports the file at the given path into the library, as if it was
+ * added to the Automatically Add to Library folder.
+ * The result of the import is also delivered to the event callback.
+ *
+ * # Safety
+ * `library` must be a valid handle, and `path` a valid null-terminated string.
+ */
+enum SeiriStatus seiri_import(const struct SeiriLibrary *library, const char *path);
+
+#endif /* SEIRI_H */
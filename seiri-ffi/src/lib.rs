@@ -0,0 +1,291 @@
+//! C bindings for seiri.
+//!
+//! The generated header is written to `include/seiri.h` on every build.
+//! All strings passed to and from these functions are null-terminated UTF-8,
+//! and any pointer handed to a callback is only valid for the duration of that callback.
+
+use libc::{c_char, c_void};
+use num_traits::cast::ToPrimitive;
+use seiri::config::{get_config, Config};
+use seiri::database;
+use seiri::database::Connection;
+use seiri::events::Event;
+use seiri::import;
+use seiri::{Bang, Track};
+use std::ffi::{CStr, CString};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::Path;
+use std::ptr;
+
+/// The result of a call into seiri.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeiriStatus {
+    Ok = 0,
+    InvalidArgument = 1,
+    ConfigError = 2,
+    QueryError = 3,
+    DatabaseError = 4,
+    ImportError = 5,
+    Panic = 6,
+}
+
+/// Called with the code and the `||`-separated arguments of every event
+/// emitted by the library. See `seiri::events::Event` for the list of codes.
+pub type SeiriEventCallback =
+    Option<extern "C" fn(code: *const c_char, payload: *const c_char, user_data: *mut c_void)>;
+
+/// Called once for every track matching a query.
+pub type SeiriTrackCallback = extern "C" fn(track: *const SeiriTrack, user_data: *mut c_void);
+
+/// A handle to an open library. Created with `seiri_open`, and freed with `seiri_close`.
+pub struct SeiriLibrary {
+    config: Config,
+    conn: Connection,
+    event_callback: SeiriEventCallback,
+    event_user_data: *mut c_void,
+}
+
+/// A track in the library.
+/// Album artists and genres are separated by `;`, and `musicbrainz_track_id`, `uuid`, `comment`, `isrc` and `encoder` may be null.
+#[repr(C)]
+pub struct SeiriTrack {
+    pub file_path: *const c_char,
+    pub title: *const c_char,
+    pub artist: *const c_char,
+    pub album_artists: *const c_char,
+    pub album: *const c_char,
+    pub year: i32,
+    pub track_number: i32,
+    pub musicbrainz_track_id: *const c_char,
+    pub has_front_cover: bool,
+    pub front_cover_width: i32,
+    pub front_cover_height: i32,
+    pub bitrate: i32,
+    pub sample_rate: i32,
+    pub source: *const c_char,
+    pub disc_number: i32,
+    pub duration: i32,
+    pub file_type: i32,
+    pub updated: *const c_char,
+    pub uuid: *const c_char,
+    pub genres: *const c_char,
+    pub comment: *const c_char,
+    pub isrc: *const c_char,
+    pub encoder: *const c_char,
+}
+
+/// Owns the strings a `SeiriTrack` points to.
+struct TrackStrings {
+    file_path: CString,
+    title: CString,
+    artist: CString,
+    album_artists: CString,
+    album: CString,
+    musicbrainz_track_id: Option<CString>,
+    source: CString,
+    updated: CString,
+    uuid: Option<CString>,
+    genres: CString,
+    comment: Option<CString>,
+    isrc: Option<CString>,
+    encoder: Option<CString>,
+}
+
+fn to_c_string(string: &str) -> CString {
+    CString::new(string.replace('\0', "")).unwrap_or_default()
+}
+
+impl TrackStrings {
+    fn new(track: &Track) -> TrackStrings {
+        TrackStrings {
+            file_path: to_c_string(&track.file_path.to_string_lossy()),
+            title: to_c_string(&track.title),
+            artist: to_c_string(&track.artist),
+            album_artists: to_c_string(&track.album_artists.join(";")),
+            album: to_c_string(&track.album),
+            musicbrainz_track_id: track.musicbrainz_track_id.as_deref().map(to_c_string),
+            source: to_c_string(&track.source),
+            updated: to_c_string(&track.updated),
+            uuid: track.uuid.as_deref().map(to_c_string),
+            genres: to_c_string(&track.genres.join(";")),
+            comment: track.comment.as_deref().map(to_c_string),
+            isrc: track.isrc.as_deref().map(to_c_string),
+            encoder: track.encoder.as_deref().map(to_c_string),
+        }
+    }
+
+    fn as_track(&self, track: &Track) -> SeiriTrack {
+        SeiriTrack {
+            file_path: self.file_path.as_ptr(),
+            title: self.title.as_ptr(),
+            artist: self.artist.as_ptr(),
+            album_artists: self.album_artists.as_ptr(),
+            album: self.album.as_ptr(),
+            year: track.year,
+            track_number: track.track_number,
+            musicbrainz_track_id: self
+                .musicbrainz_track_id
+                .as_ref()
+                .map_or(ptr::null(), |id| id.as_ptr()),
+            has_front_cover: track.has_front_cover,
+            front_cover_width: track.front_cover_width,
+            front_cover_height: track.front_cover_height,
+            bitrate: track.bitrate,
+            sample_rate: track.sample_rate,
+            source: self.source.as_ptr(),
+            disc_number: track.disc_number,
+            duration: track.duration,
+            file_type: track.file_type.to_i32().unwrap_or(0),
+            updated: self.updated.as_ptr(),
+            uuid: self.uuid.as_ref().map_or(ptr::null(), |uuid| uuid.as_ptr()),
+            genres: self.genres.as_ptr(),
+            comment: self.comment.as_ref().map_or(ptr::null(), |comment| comment.as_ptr()),
+            isrc: self.isrc.as_ref().map_or(ptr::null(), |isrc| isrc.as_ptr()),
+            encoder: self.encoder.as_ref().map_or(ptr::null(), |encoder| encoder.as_ptr()),
+        }
+    }
+}
+
+impl SeiriLibrary {
+    fn emit(&self, event: &Event) {
+        if let Some(callback) = self.event_callback {
+            let code = to_c_string(event.code());
+            let payload = to_c_string(&event.args().join("||"));
+            callback(code.as_ptr(), payload.as_ptr(), self.event_user_data);
+        }
+    }
+}
+
+unsafe fn from_c_str<'a>(string: *const c_char) -> Option<&'a str> {
+    if string.is_null() {
+        None
+    } else {
+        CStr::from_ptr(string).to_str().ok()
+    }
+}
+
+fn guard<F>(f: F) -> SeiriStatus
+where
+    F: FnOnce() -> SeiriStatus,
+{
+    catch_unwind(AssertUnwindSafe(f)).unwrap_or(SeiriStatus::Panic)
+}
+
+/// Opens the library described by the seiri configuration file,
+/// and writes the handle to `library`.
+///
+/// # Safety
+/// `library` must be a valid pointer to write the handle to.
+#[no_mangle]
+pub unsafe extern "C" fn seiri_open(library: *mut *mut SeiriLibrary) -> SeiriStatus {
+    if library.is_null() {
+        return SeiriStatus::InvalidArgument;
+    }
+    guard(|| {
+        let config = match get_config() {
+            Ok(config) => config,
+            Err(_) => return SeiriStatus::ConfigError,
+        };
+        let handle = Box::new(SeiriLibrary {
+            config,
+            conn: database::get_database_connection(),
+            event_callback: None,
+            event_user_data: ptr::null_mut(),
+        });
+        *library = Box::into_raw(handle);
+        SeiriStatus::Ok
+    })
+}
+
+/// Closes a library opened with `seiri_open`.
+///
+/// # Safety
+/// `library` must be a handle returned by `seiri_open` that has not already been closed.
+#[no_mangle]
+pub unsafe extern "C" fn seiri_close(library: *mut SeiriLibrary) {
+    if !library.is_null() {
+        drop(Box::from_raw(library));
+    }
+}
+
+/// Sets the callback that receives events emitted by the library, replacing any previous callback.
+/// Passing a null callback stops events from being delivered.
+///
+/// # Safety
+/// `library` must be a valid handle. `user_data` is passed to the callback as is.
+#[no_mangle]
+pub unsafe extern "C" fn seiri_set_event_callback(
+    library: *mut SeiriLibrary,
+    callback: SeiriEventCallback,
+    user_data: *mut c_void,
+) -> SeiriStatus {
+    match library.as_mut() {
+        Some(library) => {
+            library.event_callback = callback;
+            library.event_user_data = user_data;
+            SeiriStatus::Ok
+        }
+        None => SeiriStatus::InvalidArgument,
+    }
+}
+
+/// Runs the bang query, calling `callback` once for every matching track.
+///
+/// # Safety
+/// `library` must be a valid handle, and `query` a valid null-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn seiri_query(
+    library: *const SeiriLibrary,
+    query: *const c_char,
+    callback: SeiriTrackCallback,
+    user_data: *mut c_void,
+) -> SeiriStatus {
+    let (library, query) = match (library.as_ref(), from_c_str(query)) {
+        (Some(library), Some(query)) => (library, query),
+        _ => return SeiriStatus::InvalidArgument,
+    };
+    guard(|| {
+        let bang = match Bang::new(query) {
+            Ok(bang) => bang,
+            Err(_) => return SeiriStatus::QueryError,
+        };
+        match database::query_tracks(bang, &library.conn, None, None) {
+            Ok(tracks) => {
+                for track in tracks {
+                    let strings = TrackStrings::new(&track);
+                    let c_track = strings.as_track(&track);
+                    callback(&c_track, user_data);
+                }
+                SeiriStatus::Ok
+            }
+            Err(_) => SeiriStatus::DatabaseError,
+        }
+    })
+}
+
+/// Imports the file at the given path into the library, as if it was
+/// added to the Automatically Add to Library folder.
+/// The result of the import is also delivered to the event callback.
+///
+/// # Safety
+/// `library` must be a valid handle, and `path` a valid null-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn seiri_import(
+    library: *const SeiriLibrary,
+    path: *const c_char,
+) -> SeiriStatus {
+    let (library, path) = match (library.as_ref(), from_c_str(path)) {
+        (Some(library), Some(path)) => (library, path),
+        _ => return SeiriStatus::InvalidArgument,
+    };
+    guard(|| {
+        let event = import::import_track(Path::new(path), &library.config, &library.conn, true);
+        library.emit(&event);
+        if event.is_error() {
+            SeiriStatus::ImportError
+        } else {
+            SeiriStatus::Ok
+        }
+    })
+}
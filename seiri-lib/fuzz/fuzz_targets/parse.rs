@@ -0,0 +1,22 @@
+//! Lexing and parsing any query must fail with an error rather than panic.
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use seiri::bangs::tokenize_with_spans;
+use seiri::Bang;
+
+fuzz_target!(|query: &str| {
+    if let Ok(bang) = Bang::new(query) {
+        // Anything that parses can be written back out as a query that parses the same way.
+        if let Some(written) = bang.to_query() {
+            assert_eq!(Bang::new(&written).ok(), Some(bang));
+        }
+    }
+
+    let mut end = 0;
+    for span in tokenize_with_spans(query) {
+        assert!(span.start >= end && span.start < span.end);
+        assert!(query.is_char_boundary(span.start) && query.is_char_boundary(span.end));
+        end = span.end;
+    }
+    assert!(end <= query.len());
+});
@@ -0,0 +1,12 @@
+//! Every bang survives being written as a query and parsed again.
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use seiri::Bang;
+
+fuzz_target!(|bang: Bang| {
+    let query = bang.to_query().expect("generated bangs can always be written as a query");
+    match Bang::new(&query) {
+        Ok(parsed) => assert_eq!(parsed, bang, "query was {:?}", query),
+        Err(err) => panic!("{:?} failed to parse: {}", query, err),
+    }
+});
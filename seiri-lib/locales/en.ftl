@@ -0,0 +1,51 @@
+### The messages of the events of seiri, by their code, in English.
+### The variables of every message are the arguments of its event, named as in `Event::arg_names`.
+
+TRACKADDED = Added { $title } by { $artist }.
+BATCHIMPORTED = Imported { $imported } of { $total } files.
+SIDECARADDED = Moved the sidecar file to { $path }.
+ARCHIVEUNPACKED = Unpacked { $archive } into { $folder } to be imported.
+RIPIMPORTED = The CD rip in { $rip } was verified by its rip log, and moved to { $folder } to be imported.
+IMPORTVETOED = The import of { $file } was vetoed by the hook { $command }.
+FILEIGNORED = { $path } was rejected before, so it was left alone.
+TRACKSREMOVED = Removed { $count } tracks along with { $path }.
+FILESRECONCILED = Queued { $files } files left in the watch folder while the watcher was not running.
+WAITINGFORDOWNLOAD = { $path } is waiting to be downloaded from cloud storage.
+LEASECHANGED = The write lease passed to { $holder }.
+ELEASELAPSED = { $holder } never released the write lease, so it lapsed.
+ELEASELOST = { $holder } lost the write lease while writing, so its writes were abandoned.
+EALBUMINCOMPLETE = The album in { $folder } was left in place, since some of its tracks could not be imported.
+ETRACKMOVE = { $file } could not be moved into the library.
+EPERMISSIONS = The configured permissions could not be given to { $file }, so it was left in the watch folder.
+ECREATEDIRECTORY = The folder { $directory } could not be created.
+ETRACK = { $file } could not be read as a track.
+ENONTRACK = { $file } is not a track, and was moved into the not added folder.
+EMISSINGTAG = { $file } is missing the { $tag } tag.
+EHOOK = The hook { $command } could not be run on { $file }, which was left in place.
+ELIBRARYNOTFOUND = The library folder could not be found, so { $path } was not imported.
+EWATCHER = The watcher failed: { $message }
+EWATCHERDIED = The watcher died: { $message }
+EWATCHERNOACCESS = The watcher can not access { $path }.
+EWATCHERRESTART = The watcher is restarting: { $message }
+EWATCHERRESTARTING = The watcher died, and is restarted in { $delay } seconds, for restart { $restarts } in a row.
+EWATCHERDEGRADED = The watcher kept dying after { $restarts } restarts in a row, so it was left stopped.
+ECONFIGINVALID = The configuration file is invalid: { $message }
+ECONFIGIO = The configuration file { $path } can not be accessed.
+EANALYSIS = { $path } could not be analyzed.
+EWEBHOOK = An event could not be delivered to the webhook { $url }.
+ESYNC = Profiles could not be synced with { $address }.
+EHTTP = The HTTP request from { $address } could not be answered.
+EARCHIVE = The archive { $archive } could not be unpacked, and was moved into the not added folder.
+ERIPREJECTED = The CD rip in { $rip } could not be verified by its rip log, and was moved into the not added folder: { $reason }
+EREPORT = The import report could not be written into { $folder }.
+EBATCHSHED = { $files } files were left in the watch folder, since the import queue was full.
+EDBCONFLICT = A conflicting copy of the library was found at { $path }.
+ETRACKNOTFOUND = The track { $track } is not in the library.
+ECASTTARGET = No cast target named { $target } was found.
+ENOTSUBSCRIBED = There is no subscription { $id }.
+EREPLICANOTFOUND = No replica named { $name } is known.
+ECOMMAND = The command { $command } could not be understood.
+ENOPROVENANCE = The track { $track } was not ripped through the rip intake.
+EFIELDVALUE = { $value } is not a valid value of the field { $field }.
+ELINK = The track { $track } could not be linked to { $related }.
+ENOTLINKED = The track { $track } is not linked to { $related }.
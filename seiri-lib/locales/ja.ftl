@@ -0,0 +1,51 @@
+### seiri のイベントのメッセージ（日本語）。イベントのコードごとに記述します。
+### メッセージの変数はイベントの引数で、`Event::arg_names` の名前で参照します。
+
+TRACKADDED = { $artist } の「{ $title }」を追加しました。
+BATCHIMPORTED = { $total } 件中 { $imported } 件のファイルを取り込みました。
+SIDECARADDED = 付属ファイルを { $path } に移動しました。
+ARCHIVEUNPACKED = 取り込むために { $archive } を { $folder } に展開しました。
+RIPIMPORTED = { $rip } の CD リッピングをログで検証し、取り込むために { $folder } に移動しました。
+IMPORTVETOED = フック { $command } により { $file } の取り込みが拒否されました。
+FILEIGNORED = { $path } は以前に拒否されたファイルのため、そのままにしました。
+TRACKSREMOVED = { $path } とともに { $count } 曲を削除しました。
+FILESRECONCILED = 監視が止まっている間に監視フォルダに残された { $files } 件のファイルを処理待ちにしました。
+WAITINGFORDOWNLOAD = { $path } はクラウドストレージからのダウンロード待ちです。
+LEASECHANGED = 書き込み権が { $holder } に移りました。
+ELEASELAPSED = { $holder } が書き込み権を解放しなかったため、書き込み権が失効しました。
+ELEASELOST = { $holder } が書き込み中に書き込み権を失ったため、書き込みを中止しました。
+EALBUMINCOMPLETE = 一部のトラックを取り込めなかったため、{ $folder } のアルバムをそのまま残しました。
+ETRACKMOVE = { $file } をライブラリに移動できませんでした。
+EPERMISSIONS = 設定されたパーミッションを { $file } に設定できなかったため、監視フォルダに残しました。
+ECREATEDIRECTORY = フォルダ { $directory } を作成できませんでした。
+ETRACK = { $file } をトラックとして読み込めませんでした。
+ENONTRACK = { $file } はトラックではないため、未追加フォルダに移動しました。
+EMISSINGTAG = { $file } に { $tag } タグがありません。
+EHOOK = フック { $command } を { $file } に対して実行できなかったため、ファイルをそのまま残しました。
+ELIBRARYNOTFOUND = ライブラリフォルダが見つからないため、{ $path } を取り込めませんでした。
+EWATCHER = 監視でエラーが発生しました: { $message }
+EWATCHERDIED = 監視が停止しました: { $message }
+EWATCHERNOACCESS = 監視から { $path } にアクセスできません。
+EWATCHERRESTART = 監視を再起動しています: { $message }
+EWATCHERRESTARTING = 監視が停止したため、{ $delay } 秒後に再起動します（連続 { $restarts } 回目）。
+EWATCHERDEGRADED = 連続 { $restarts } 回再起動しても監視が停止するため、監視を停止したままにしました。
+ECONFIGINVALID = 設定ファイルが正しくありません: { $message }
+ECONFIGIO = 設定ファイル { $path } にアクセスできません。
+EANALYSIS = { $path } を解析できませんでした。
+EWEBHOOK = Webhook { $url } にイベントを送信できませんでした。
+ESYNC = { $address } とプロファイルを同期できませんでした。
+EHTTP = { $address } からの HTTP リクエストに応答できませんでした。
+EARCHIVE = アーカイブ { $archive } を展開できなかったため、未追加フォルダに移動しました。
+ERIPREJECTED = { $rip } の CD リッピングをログで検証できなかったため、未追加フォルダに移動しました: { $reason }
+EREPORT = 取り込みレポートを { $folder } に書き込めませんでした。
+EBATCHSHED = 取り込みキューがいっぱいのため、{ $files } 件のファイルを監視フォルダに残しました。
+EDBCONFLICT = ライブラリの競合するコピーが { $path } に見つかりました。
+ETRACKNOTFOUND = トラック { $track } はライブラリにありません。
+ECASTTARGET = キャスト先 { $target } が見つかりませんでした。
+ENOTSUBSCRIBED = サブスクリプション { $id } はありません。
+EREPLICANOTFOUND = レプリカ { $name } は登録されていません。
+ECOMMAND = コマンド { $command } を解釈できませんでした。
+ENOPROVENANCE = トラック { $track } はリッピング取り込みを経ていません。
+EFIELDVALUE = { $value } はフィールド { $field } の値として正しくありません。
+ELINK = トラック { $track } を { $related } にリンクできませんでした。
+ENOTLINKED = トラック { $track } は { $related } にリンクされていません。
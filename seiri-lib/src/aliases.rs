@@ -0,0 +1,138 @@
+//! Alternative spellings of artist names.
+//!
+//! An artist may be tagged under several names, such as its Japanese and romanized
+//! names, or with and without a featured artist. Each alias maps one of those names to
+//! the canonical name of the artist, and the `!ar` and `!AR` bangs match tracks under
+//! any name of an artist whose canonical name or aliases match the search.
+//!
+//! Changing an alias changes which tracks an artist search matches, so it is recorded
+//! in the change log as an update of every track tagged with either name.
+
+use crate::database::{track_from_row, TRACK_COLUMNS};
+use crate::locks::LOCKED_CONDITION;
+use katatsuki::{TagUpdate, Track};
+use rusqlite::{Connection, OptionalExtension, Result, NO_PARAMS};
+use std::collections::BTreeMap;
+
+pub fn create_alias_table(conn: &Connection) {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS artist_aliases (
+        Alias TEXT PRIMARY KEY,
+        Artist TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS artist_aliases_artist ON artist_aliases(Artist);
+    CREATE TRIGGER IF NOT EXISTS artist_aliases_log_insert AFTER INSERT ON artist_aliases BEGIN
+        INSERT INTO changes(FilePath, Operation) SELECT FilePath, 1 FROM tracks
+            WHERE Artist LIKE '%' || NEW.Alias || '%' OR Artist LIKE '%' || NEW.Artist || '%';
+    END;
+    CREATE TRIGGER IF NOT EXISTS artist_aliases_log_update AFTER UPDATE ON artist_aliases BEGIN
+        INSERT INTO changes(FilePath, Operation) SELECT FilePath, 1 FROM tracks
+            WHERE Artist LIKE '%' || NEW.Alias || '%' OR Artist LIKE '%' || NEW.Artist || '%'
+            OR Artist LIKE '%' || OLD.Artist || '%';
+    END;
+    CREATE TRIGGER IF NOT EXISTS artist_aliases_log_delete AFTER DELETE ON artist_aliases BEGIN
+        INSERT INTO changes(FilePath, Operation) SELECT FilePath, 1 FROM tracks
+            WHERE Artist LIKE '%' || OLD.Alias || '%' OR Artist LIKE '%' || OLD.Artist || '%';
+    END;",
+    )
+    .unwrap();
+}
+
+/// Gets the canonical name of the artist with the given name, which is the name itself
+/// if it is not an alias.
+pub fn get_canonical_artist(name: &str, conn: &Connection) -> Result<String> {
+    let canonical = conn
+        .query_row(
+            "SELECT Artist FROM artist_aliases WHERE Alias = ?1",
+            &[name],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(canonical.unwrap_or_else(|| name.to_owned()))
+}
+
+/// Adds an alias for the artist, replacing any artist the alias was already mapped to.
+///
+/// If the artist is itself an alias, the alias is mapped to its canonical name instead,
+/// and if the alias was the canonical name of other aliases, they are mapped to the artist,
+/// so every alias maps directly to a canonical name. Adding an alias for itself does nothing.
+pub fn add_alias(alias: &str, artist: &str, conn: &Connection) -> Result<()> {
+    let artist = get_canonical_artist(artist, conn)?;
+    if alias == artist {
+        return Ok(());
+    }
+    conn.execute(
+        "UPDATE artist_aliases SET Artist = ?2 WHERE Artist = ?1",
+        &[alias, &artist],
+    )?;
+    conn.execute(
+        "INSERT INTO artist_aliases(Alias, Artist) VALUES (?1, ?2)
+        ON CONFLICT(Alias) DO UPDATE SET Artist = excluded.Artist",
+        &[alias, &artist],
+    )?;
+    Ok(())
+}
+
+/// Removes the alias. Returns false if there was no such alias.
+pub fn remove_alias(alias: &str, conn: &Connection) -> Result<bool> {
+    let removed = conn.execute("DELETE FROM artist_aliases WHERE Alias = ?1", &[alias])?;
+    Ok(removed > 0)
+}
+
+/// Gets every alias of the artist with the given name, which may itself be an alias.
+pub fn get_aliases(name: &str, conn: &Connection) -> Result<Vec<String>> {
+    let artist = get_canonical_artist(name, conn)?;
+    let mut statement =
+        conn.prepare("SELECT Alias FROM artist_aliases WHERE Artist = ?1 ORDER BY Alias")?;
+    let aliases = statement
+        .query_map(&[&artist], |row| row.get(0))?
+        .collect::<Result<Vec<String>>>()?;
+    Ok(aliases)
+}
+
+/// Gets every artist with aliases, as a map from each canonical name to its aliases.
+pub fn get_all_aliases(conn: &Connection) -> Result<BTreeMap<String, Vec<String>>> {
+    let mut statement =
+        conn.prepare("SELECT Artist, Alias FROM artist_aliases ORDER BY Artist, Alias")?;
+    let mut rows = statement.query(NO_PARAMS)?;
+    let mut artists = BTreeMap::<String, Vec<String>>::new();
+    while let Some(row) = rows.next()? {
+        artists.entry(row.get(0)?).or_default().push(row.get(1)?);
+    }
+    Ok(artists)
+}
+
+/// Rewrites the artist of the track to its canonical name.
+pub fn canonicalize_track(track: &mut Track, conn: &Connection) -> Result<()> {
+    track.artist = get_canonical_artist(&track.artist, conn)?;
+    Ok(())
+}
+
+/// Rewrites the artist of every track in the library tagged with an alias to its canonical name,
+/// returning the number of tracks rewritten.
+///
+/// The canonical name is also written to the tags of the file of each track, so it is kept when
+/// the track is next refreshed. A track whose file can not be written is only rewritten in the
+/// library. Locked tracks keep the artist they are tagged with.
+pub fn canonicalize_library(conn: &Connection) -> Result<usize> {
+    let mut statement = conn.prepare(&format!(
+        "SELECT {} FROM tracks WHERE Artist IN (SELECT Alias FROM artist_aliases) AND NOT {}",
+        TRACK_COLUMNS, LOCKED_CONDITION
+    ))?;
+    let tracks = statement
+        .query_map(NO_PARAMS, track_from_row)?
+        .collect::<Result<Vec<Track>>>()?;
+    for track in &tracks {
+        let artist = get_canonical_artist(&track.artist, conn)?;
+        let update = TagUpdate {
+            artist: Some(artist.clone()),
+            ..TagUpdate::default()
+        };
+        track.write_tags(&update).ok();
+        conn.execute(
+            "UPDATE tracks SET Artist = ?2 WHERE FilePath = ?1",
+            &[&track.file_path.to_string_lossy().into_owned(), &artist],
+        )?;
+    }
+    Ok(tracks.len())
+}
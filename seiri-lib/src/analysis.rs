@@ -0,0 +1,628 @@
+//! Analysis jobs, which decode the audio of tracks rather than only reading their tags.
+//!
+//! The waveform of a track is a downsampled series of its peaks, so frontends can draw it and
+//! scrub through it without decoding the file themselves. Waveforms are kept in the database
+//! by track ID, so they are removed along with their tracks, and are analyzed again whenever
+//! their track is updated. Preview clips are short excerpts of the track written as WAV files
+//! to the previews folder, and are only written if enabled in the configuration. The ReplayGain
+//! tags of tracks are read along with them, as described in `gains`.
+//!
+//! Spectral analysis, if enabled, looks for lossless tracks transcoded from lossy audio.
+//! Lossy encoders cut off frequencies above some point, usually between 16kHz and 20kHz
+//! depending on the bitrate, which leaves a sharp cliff in the spectrum that is kept
+//! by the transcode. Tracks with a cliff low enough are flagged as suspects,
+//! which the `!fake` bang matches.
+
+use crate::config::{AnalysisConfig, Backpressure, PoolConfig};
+use crate::database::{quality_condition, track_from_row, TRACK_COLUMNS};
+use crate::error::{Error, Result};
+use crate::events::Event;
+use crate::failures::{self, Stage};
+use crate::gains::{parse_gain_value, save_gain, Gain};
+use crate::paths::get_appdata_path;
+use crossbeam::channel::{bounded, unbounded, TrySendError};
+use katatsuki::{Quality, Track};
+use rusqlite::types::ToSql;
+use rusqlite::{Connection, OptionalExtension, NO_PARAMS};
+use std::f32::consts::PI;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use symphonia::core::audio::{SampleBuffer, SignalSpec};
+use symphonia::core::codecs::DecoderOptions;
+use symphonia::core::errors::Error as DecodeError;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::{MetadataOptions, StandardTagKey, Tag};
+use symphonia::core::probe::Hint;
+
+/// The number of peaks in a waveform.
+pub const WAVEFORM_RESOLUTION: usize = 1024;
+
+/// The length of a preview clip, in milliseconds.
+pub const PREVIEW_DURATION: u64 = 30_000;
+
+/// The number of frames peaks are first taken over, before being downsampled to the resolution.
+/// The length of a track is not known for certain until it is decoded, so peaks are kept
+/// at this granularity while decoding.
+const BLOCK_FRAMES: usize = 256;
+
+/// The number of samples in each window the spectrum is taken over.
+const SPECTRUM_WINDOW: usize = 4096;
+
+/// The number of windows taken every second of a track. Windows are spread out over the track,
+/// since averaging the spectrum over a few of them is enough to find its cutoff.
+const SPECTRUM_WINDOWS_PER_SECOND: u32 = 2;
+
+/// The number of frequency bins averaged together to find the level of each band.
+const BAND_BINS: usize = 16;
+
+/// Bands below this frequency are never taken to be the cutoff, since lossy encoders keep
+/// everything below it at any reasonable bitrate.
+const MIN_CUTOFF: u32 = 10_000;
+
+/// How far in decibels every band above a cutoff must fall below the band just under it.
+const CLIFF_DB: f32 = 30.0;
+
+/// Bands this far in decibels below the loudest band are silent, and never start a cliff.
+const SILENCE_DB: f32 = 90.0;
+
+/// Tracks with a cutoff below this frequency are suspects. Encoders at the highest bitrates
+/// cut off at around 20kHz, while the anti-aliasing filters of lossless recordings
+/// usually cut off just below the Nyquist frequency.
+pub const SUSPECT_CUTOFF: u32 = 19_500;
+
+/// The results of spectral analysis of a lossless track.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Spectrum {
+    /// The frequency in Hz of the sharp cutoff in the spectrum, if there is one.
+    pub cutoff: Option<u32>,
+    /// Whether the track is suspected to be transcoded from lossy audio.
+    pub suspect: bool,
+}
+
+/// A decoded excerpt of a track, as interleaved 16-bit samples.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Preview {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub samples: Vec<i16>,
+}
+
+/// The results of analyzing a track.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Analysis {
+    /// The peak amplitude of each of `WAVEFORM_RESOLUTION` equal parts of the track,
+    /// over every channel, from 0 for silence to 255 for full scale.
+    pub waveform: Vec<u8>,
+    pub preview: Option<Preview>,
+}
+
+/// Decodes the audio of the file, passing every decoded buffer of interleaved samples to `sink`.
+/// Decoding stops early if `sink` returns false.
+pub(crate) fn decode<F>(file_path: &Path, mut sink: F) -> Result<()>
+where
+    F: FnMut(&[f32], SignalSpec) -> bool,
+{
+    let decode_error = || Error::DecodeError(file_path.to_owned());
+    let file = File::open(file_path).map_err(|_| Error::FileIOError(file_path.to_owned()))?;
+    let stream = MediaSourceStream::new(Box::new(file), Default::default());
+    let mut hint = Hint::new();
+    if let Some(extension) = file_path.extension().and_then(|s| s.to_str()) {
+        hint.with_extension(extension);
+    }
+    let mut format = symphonia::default::get_probe()
+        .format(&hint, stream, &FormatOptions::default(), &MetadataOptions::default())
+        .map_err(|_| Error::UnsupportedFile(file_path.to_owned()))?
+        .format;
+    let track = format.default_track().ok_or_else(decode_error)?;
+    let track_id = track.id;
+    let mut decoder = symphonia::default::get_codecs()
+        .make(&track.codec_params, &DecoderOptions::default())
+        .map_err(|_| Error::UnsupportedFile(file_path.to_owned()))?;
+    let mut buffer: Option<SampleBuffer<f32>> = None;
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(DecodeError::IoError(ref err)) if err.kind() == ErrorKind::UnexpectedEof => return Ok(()),
+            Err(_) => return Err(decode_error()),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            // A corrupt packet is skipped over, as a player would.
+            Err(DecodeError::DecodeError(_)) => continue,
+            Err(_) => return Err(decode_error()),
+        };
+        let spec = *decoded.spec();
+        if buffer.as_ref().is_none_or(|buffer| buffer.capacity() < decoded.capacity()) {
+            buffer = Some(SampleBuffer::new(decoded.capacity() as u64, spec));
+        }
+        let buffer = buffer.as_mut().unwrap();
+        buffer.copy_interleaved_ref(decoded);
+        if !sink(buffer.samples(), spec) {
+            return Ok(());
+        }
+    }
+}
+
+/// Reads the ReplayGain tags of the track, without decoding it. Tags within the container are
+/// preferred over tags before it, such as ID3 tags prepended to a FLAC file.
+pub fn read_gain(track: &Track) -> Result<Gain> {
+    let file_path = &track.file_path;
+    let file = File::open(file_path).map_err(|_| Error::FileIOError(file_path.to_owned()))?;
+    let stream = MediaSourceStream::new(Box::new(file), Default::default());
+    let mut hint = Hint::new();
+    if let Some(extension) = file_path.extension().and_then(|s| s.to_str()) {
+        hint.with_extension(extension);
+    }
+    let mut probed = symphonia::default::get_probe()
+        .format(&hint, stream, &FormatOptions::default(), &MetadataOptions::default())
+        .map_err(|_| Error::UnsupportedFile(file_path.to_owned()))?;
+    let mut gain = Gain::default();
+    let mut read_tags = |tags: &[Tag]| {
+        for tag in tags {
+            let value = match parse_gain_value(&tag.value.to_string()) {
+                Some(value) => value,
+                None => continue,
+            };
+            // Freeform MP4 tags are keyed as `----:com.apple.iTunes:replaygain_track_gain`.
+            let key = tag.key.rsplit(':').next().unwrap_or_default().to_uppercase();
+            match (tag.std_key, key.as_str()) {
+                (Some(StandardTagKey::ReplayGainTrackGain), _) | (_, "REPLAYGAIN_TRACK_GAIN") => gain.track_gain = Some(value),
+                (Some(StandardTagKey::ReplayGainTrackPeak), _) | (_, "REPLAYGAIN_TRACK_PEAK") => gain.track_peak = Some(value),
+                (Some(StandardTagKey::ReplayGainAlbumGain), _) | (_, "REPLAYGAIN_ALBUM_GAIN") => gain.album_gain = Some(value),
+                (Some(StandardTagKey::ReplayGainAlbumPeak), _) | (_, "REPLAYGAIN_ALBUM_PEAK") => gain.album_peak = Some(value),
+                _ => {}
+            }
+        }
+    };
+    if let Some(revision) = probed.metadata.get().as_ref().and_then(|metadata| metadata.current()) {
+        read_tags(revision.tags());
+    }
+    if let Some(revision) = probed.format.metadata().current() {
+        read_tags(revision.tags());
+    }
+    Ok(gain)
+}
+
+/// Downsamples the peaks of every block to `WAVEFORM_RESOLUTION` peaks.
+fn downsample(blocks: &[f32]) -> Vec<u8> {
+    (0..WAVEFORM_RESOLUTION)
+        .map(|i| {
+            // Tracks shorter than the resolution repeat blocks across several peaks.
+            let start = i * blocks.len() / WAVEFORM_RESOLUTION;
+            let end = ((i + 1) * blocks.len() / WAVEFORM_RESOLUTION).max(start + 1);
+            let peak = blocks[start..end].iter().cloned().fold(0.0, f32::max);
+            (peak.min(1.0) * 255.0).round() as u8
+        })
+        .collect()
+}
+
+/// Decodes the track, taking its waveform and, if `preview` is true, its preview clip.
+///
+/// The preview clip starts a third of the way into the track, so it skips past any intro,
+/// unless the track is too short for the clip to fit after that.
+pub fn analyze_track(track: &Track, preview: bool) -> Result<Analysis> {
+    let duration = track.duration.max(0) as u64;
+    let preview_start = if duration > PREVIEW_DURATION {
+        (duration / 3).min(duration - PREVIEW_DURATION)
+    } else {
+        0
+    };
+    let mut blocks = Vec::new();
+    let (mut block_peak, mut block_frames) = (0f32, 0);
+    let mut clip: Option<Preview> = None;
+    let mut frame = 0u64;
+    decode(&track.file_path, |samples, spec| {
+        let channels = spec.channels.count().max(1);
+        let rate = u64::from(spec.rate);
+        let clip_frames = (preview_start * rate / 1000)..((preview_start + PREVIEW_DURATION) * rate / 1000);
+        for samples in samples.chunks(channels) {
+            block_peak = samples.iter().fold(block_peak, |peak, sample| peak.max(sample.abs()));
+            block_frames += 1;
+            if block_frames == BLOCK_FRAMES {
+                blocks.push(block_peak);
+                block_peak = 0.0;
+                block_frames = 0;
+            }
+            if preview && clip_frames.contains(&frame) {
+                let clip = clip.get_or_insert_with(|| Preview {
+                    sample_rate: spec.rate,
+                    channels: channels as u16,
+                    samples: Vec::new(),
+                });
+                clip.samples.extend(samples.iter().map(|sample| (sample.clamp(-1.0, 1.0) * 32767.0) as i16));
+            }
+            frame += 1;
+        }
+        true
+    })?;
+    if block_frames > 0 {
+        blocks.push(block_peak);
+    }
+    if blocks.is_empty() {
+        return Err(Error::DecodeError(track.file_path.clone()));
+    }
+    Ok(Analysis {
+        waveform: downsample(&blocks),
+        preview: clip,
+    })
+}
+
+/// Transforms the signal in place into its discrete Fourier transform.
+/// The length of the signal must be a power of two.
+fn fft(re: &mut [f32], im: &mut [f32]) {
+    let n = re.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+    let mut len = 2;
+    while len <= n {
+        let angle = -2.0 * PI / len as f32;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (sin, cos) = (angle * k as f32).sin_cos();
+                let (a, b) = (start + k, start + k + len / 2);
+                let (re_b, im_b) = (re[b] * cos - im[b] * sin, re[b] * sin + im[b] * cos);
+                re[b] = re[a] - re_b;
+                im[b] = im[a] - im_b;
+                re[a] += re_b;
+                im[a] += im_b;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+/// Finds the frequency in Hz of the lowest sharp cutoff in the spectrum, given the level
+/// in decibels of each band of `BAND_BINS` bins, where every band above the cutoff is at least
+/// `CLIFF_DB` below the band just under it.
+fn find_cutoff(levels: &[f32], sample_rate: u32) -> Option<u32> {
+    let band_width = sample_rate as f32 / SPECTRUM_WINDOW as f32 * BAND_BINS as f32;
+    let loudest = levels.iter().cloned().fold(f32::MIN, f32::max);
+    let first = ((MIN_CUTOFF as f32 / band_width) as usize).max(1);
+    (first..levels.len().saturating_sub(1))
+        .find(|&band| {
+            let below = levels[band - 1];
+            let above = levels[band + 1..].iter().cloned().fold(f32::MIN, f32::max);
+            below > loudest - SILENCE_DB && below - above > CLIFF_DB
+        })
+        .map(|band| (band as f32 * band_width) as u32)
+}
+
+/// Decodes the track and takes its spectrum, to find whether it was transcoded from lossy audio.
+pub fn analyze_spectrum(track: &Track) -> Result<Spectrum> {
+    let window: Vec<f32> = (0..SPECTRUM_WINDOW)
+        .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / SPECTRUM_WINDOW as f32).cos())
+        .collect();
+    let mut power = vec![0f64; SPECTRUM_WINDOW / 2];
+    let mut windows = 0;
+    let mut sample_rate = 0;
+    let (mut re, mut im) = (Vec::with_capacity(SPECTRUM_WINDOW), vec![0f32; SPECTRUM_WINDOW]);
+    let mut skip = 0;
+    decode(&track.file_path, |samples, spec| {
+        let channels = spec.channels.count().max(1);
+        sample_rate = spec.rate;
+        let stride = (spec.rate / SPECTRUM_WINDOWS_PER_SECOND) as usize;
+        for samples in samples.chunks(channels) {
+            if skip > 0 {
+                skip -= 1;
+                continue;
+            }
+            // Channels are mixed down, since a cutoff applies to every channel alike.
+            let sample = samples.iter().sum::<f32>() / channels as f32;
+            re.push(sample * window[re.len()]);
+            if re.len() == SPECTRUM_WINDOW {
+                im.iter_mut().for_each(|im| *im = 0.0);
+                fft(&mut re, &mut im);
+                for (bin, power) in power.iter_mut().enumerate() {
+                    *power += f64::from(re[bin] * re[bin] + im[bin] * im[bin]);
+                }
+                windows += 1;
+                re.clear();
+                skip = stride.saturating_sub(SPECTRUM_WINDOW);
+            }
+        }
+        true
+    })?;
+    if windows == 0 {
+        return Err(Error::DecodeError(track.file_path.clone()));
+    }
+    let levels = power
+        .chunks(BAND_BINS)
+        .map(|band| (10.0 * (band.iter().sum::<f64>() / (band.len() * windows) as f64 + 1e-30).log10()) as f32)
+        .collect::<Vec<f32>>();
+    let cutoff = find_cutoff(&levels, sample_rate);
+    Ok(Spectrum {
+        cutoff,
+        suspect: cutoff.is_some_and(|cutoff| cutoff < SUSPECT_CUTOFF),
+    })
+}
+
+/// Saves the waveform of the track with the given UUID.
+pub fn save_waveform(track_id: &str, waveform: &[u8], conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO waveforms(TrackId, Peaks, Updated)
+        VALUES (?1, ?2, (SELECT Updated FROM tracks WHERE TrackId = ?1))",
+        &[&track_id as &dyn ToSql, &waveform],
+    )?;
+    Ok(())
+}
+
+/// Gets the waveform of the track with the given UUID, if it was analyzed.
+pub fn get_waveform(track_id: &str, conn: &Connection) -> rusqlite::Result<Option<Vec<u8>>> {
+    conn.query_row(
+        "SELECT Peaks FROM waveforms WHERE TrackId = ?1",
+        &[track_id],
+        |row| row.get(0),
+    )
+    .optional()
+}
+
+/// Saves the spectral analysis of the track with the given UUID.
+pub fn save_spectrum(track_id: &str, spectrum: &Spectrum, conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO spectra(TrackId, Cutoff, Suspect, Updated)
+        VALUES (?1, ?2, ?3, (SELECT Updated FROM tracks WHERE TrackId = ?1))",
+        &[&track_id as &dyn ToSql, &spectrum.cutoff, &spectrum.suspect],
+    )?;
+    Ok(())
+}
+
+/// Gets the spectral analysis of the track with the given UUID, if it was analyzed.
+pub fn get_spectrum(track_id: &str, conn: &Connection) -> rusqlite::Result<Option<Spectrum>> {
+    conn.query_row(
+        "SELECT Cutoff, Suspect FROM spectra WHERE TrackId = ?1",
+        &[track_id],
+        |row| Ok(Spectrum { cutoff: row.get(0)?, suspect: row.get(1)? }),
+    )
+    .optional()
+}
+
+fn get_previews_path() -> PathBuf {
+    let mut previews_path = get_appdata_path();
+    previews_path.push("previews");
+    previews_path
+}
+
+/// Gets the path of the preview clip of the track with the given UUID, if one was written.
+pub fn get_preview_path(track_id: &str) -> Option<PathBuf> {
+    let mut preview_path = get_previews_path();
+    preview_path.push(format!("{}.wav", track_id));
+    if preview_path.is_file() {
+        Some(preview_path)
+    } else {
+        None
+    }
+}
+
+/// Writes the preview clip as a 16-bit PCM WAV file.
+pub fn write_preview(preview: &Preview, path: &Path) -> io::Result<()> {
+    let data_len = (preview.samples.len() * 2) as u32;
+    let block_align = preview.channels * 2;
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(b"RIFF")?;
+    file.write_all(&(36 + data_len).to_le_bytes())?;
+    file.write_all(b"WAVEfmt ")?;
+    file.write_all(&16u32.to_le_bytes())?;
+    file.write_all(&1u16.to_le_bytes())?;
+    file.write_all(&preview.channels.to_le_bytes())?;
+    file.write_all(&preview.sample_rate.to_le_bytes())?;
+    file.write_all(&(preview.sample_rate * u32::from(block_align)).to_le_bytes())?;
+    file.write_all(&block_align.to_le_bytes())?;
+    file.write_all(&16u16.to_le_bytes())?;
+    file.write_all(b"data")?;
+    file.write_all(&data_len.to_le_bytes())?;
+    for sample in &preview.samples {
+        file.write_all(&sample.to_le_bytes())?;
+    }
+    file.flush()
+}
+
+/// Analyzes the track and writes its preview clip if enabled, leaving its waveform to be saved.
+fn analyze_with_preview(track: &Track, config: &AnalysisConfig) -> Result<Vec<u8>> {
+    let track_id = track.uuid.as_deref().unwrap_or_default();
+    let analysis = analyze_track(track, config.preview_clips)?;
+    if let Some(preview) = analysis.preview {
+        let previews_path = get_previews_path();
+        let io_error = |_| Error::FileIOError(previews_path.clone());
+        fs::create_dir_all(&previews_path).map_err(io_error)?;
+        write_preview(&preview, &previews_path.join(format!("{}.wav", track_id))).map_err(io_error)?;
+    }
+    Ok(analysis.waveform)
+}
+
+/// Runs `work` on every track on the threads of the pool, for as long as `keep_going` returns
+/// true, passing every result to `done` on the calling thread. The tracks are queued for the
+/// threads as the pool is configured, and tracks shed from a full queue are left out.
+fn run_on_pool<T, W, C, D>(tracks: Vec<Track>, pool: &PoolConfig, keep_going: &C, work: W, mut done: D)
+where
+    T: Send,
+    W: Fn(&Track) -> Result<T> + Sync,
+    C: Fn() -> bool + Sync,
+    D: FnMut(&Track, Result<T>),
+{
+    let (queue_tx, queue_rx) = if pool.queue == 0 { unbounded() } else { bounded(pool.queue) };
+    let (done_tx, done_rx) = unbounded();
+    let work = &work;
+    thread::scope(|scope| {
+        for _ in 0..pool.threads() {
+            let queue_rx = queue_rx.clone();
+            let done_tx = done_tx.clone();
+            scope.spawn(move || {
+                for track in queue_rx {
+                    if !keep_going() {
+                        break;
+                    }
+                    let result = work(&track);
+                    if done_tx.send((track, result)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        // Once every thread stopped, queueing fails and the rest of the tracks are left out.
+        drop(queue_rx);
+        drop(done_tx);
+        scope.spawn(move || {
+            for track in tracks {
+                let queued = match pool.backpressure {
+                    Backpressure::Block => queue_tx.send(track).is_ok(),
+                    Backpressure::Shed => !matches!(queue_tx.try_send(track), Err(TrySendError::Disconnected(_))),
+                };
+                if !queued || !keep_going() {
+                    break;
+                }
+            }
+        });
+        for (track, result) in done_rx {
+            done(&track, result);
+        }
+    });
+}
+
+/// Gets every track matching the condition that has no results in the given table
+/// since it was last updated.
+fn get_unanalyzed_tracks(table: &str, condition: &str, conn: &Connection) -> rusqlite::Result<Vec<Track>> {
+    let mut statement = conn.prepare(&format!(
+        "SELECT {columns} FROM tracks LEFT JOIN {table} ON {table}.TrackId = tracks.TrackId
+        WHERE ({table}.TrackId IS NULL OR {table}.Updated IS NOT tracks.Updated) AND {condition}",
+        columns = TRACK_COLUMNS,
+        table = table,
+        condition = condition,
+    ))?;
+    let tracks = statement
+        .query_map(NO_PARAMS, track_from_row)?
+        .collect::<rusqlite::Result<Vec<Track>>>()?;
+    Ok(tracks)
+}
+
+/// Removes the preview clips of tracks no longer in the library.
+fn remove_stale_previews(conn: &Connection) -> rusqlite::Result<()> {
+    let entries = match fs::read_dir(get_previews_path()) {
+        Ok(entries) => entries,
+        Err(_) => return Ok(()),
+    };
+    let mut statement = conn.prepare("SELECT 1 FROM tracks WHERE TrackId = ?1")?;
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let path = entry.path();
+        let track_id = path.file_stem().and_then(|s| s.to_str()).unwrap_or_default();
+        if !statement.exists(&[track_id])? {
+            fs::remove_file(&path).ok();
+        }
+    }
+    Ok(())
+}
+
+/// Analyzes every track that was not analyzed since it was last updated, returning the number
+/// of analyses run. The ReplayGain tags of every track are read after its waveform is taken.
+/// Spectral analysis only runs on lossless tracks if it is enabled, and decodes them separately
+/// from the waveform. Tracks that could not be analyzed are reported to `report`
+/// and recorded in their error history, and are tried again the next time the job runs.
+///
+/// Analysis decodes every track it runs on, so it takes far longer than importing them.
+/// Tracks are decoded on the threads of the configured pool, and saved on the calling thread.
+/// Tracks shed from a full queue are analyzed the next time the job runs.
+pub fn analyze_library<R>(config: &AnalysisConfig, conn: &Connection, report: R) -> rusqlite::Result<usize>
+where
+    R: Fn(Event),
+{
+    analyze_library_while(config, conn, report, || true)
+}
+
+/// Analyzes the library as `analyze_library` does, stopping before the next track once
+/// `keep_going` returns false, such as when the window the job is scheduled in closes.
+/// Tracks that were not analyzed are analyzed the next time the job runs.
+pub fn analyze_library_while<R, C>(
+    config: &AnalysisConfig,
+    conn: &Connection,
+    report: R,
+    keep_going: C,
+) -> rusqlite::Result<usize>
+where
+    R: Fn(Event),
+    C: Fn() -> bool + Sync,
+{
+    let mut analyzed = 0;
+    let mut run = |track: &Track, result: Result<()>| match result {
+        Ok(()) => analyzed += 1,
+        Err(err) => {
+            let event = Event::AnalysisError(track.file_path.to_string_lossy().into_owned());
+            // A failure that can not be recorded is still reported.
+            failures::record_error(&track.file_path, Stage::Analysis, event.code(), &err.to_string(), conn).ok();
+            report(event)
+        }
+    };
+    let save_error = |track: &Track| Error::FileIOError(track.file_path.clone());
+    run_on_pool(
+        get_unanalyzed_tracks("waveforms", "1", conn)?,
+        &config.pool,
+        &keep_going,
+        |track| analyze_with_preview(track, config),
+        |track, waveform| {
+            let saved = waveform.and_then(|waveform| {
+                save_waveform(track.uuid.as_deref().unwrap_or_default(), &waveform, conn).map_err(|_| save_error(track))
+            });
+            run(track, saved)
+        },
+    );
+    if !keep_going() {
+        return Ok(analyzed);
+    }
+    run_on_pool(
+        get_unanalyzed_tracks("gains", "1", conn)?,
+        &config.pool,
+        &keep_going,
+        read_gain,
+        |track, gain| {
+            let saved = gain.and_then(|gain| {
+                save_gain(track.uuid.as_deref().unwrap_or_default(), &gain, conn).map_err(|_| save_error(track))
+            });
+            run(track, saved)
+        },
+    );
+    if !keep_going() {
+        return Ok(analyzed);
+    }
+    if config.spectral_analysis {
+        let lossless = format!(
+            "({} OR {})",
+            quality_condition(Quality::Lossless),
+            quality_condition(Quality::LosslessHiRes)
+        );
+        run_on_pool(
+            get_unanalyzed_tracks("spectra", &lossless, conn)?,
+            &config.pool,
+            &keep_going,
+            analyze_spectrum,
+            |track, spectrum| {
+                let saved = spectrum.and_then(|spectrum| {
+                    save_spectrum(track.uuid.as_deref().unwrap_or_default(), &spectrum, conn)
+                        .map_err(|_| save_error(track))
+                });
+                run(track, saved)
+            },
+        );
+        if !keep_going() {
+            return Ok(analyzed);
+        }
+    }
+    remove_stale_previews(conn)?;
+    Ok(analyzed)
+}
@@ -0,0 +1,386 @@
+//! Unpacking of purchase archives from stores such as Bandcamp and Qobuz.
+//!
+//! Stores deliver purchases as zip archives laid out in their own way. Bandcamp puts every
+//! track at the top of the archive as `Artist - Album - 01 Title.flac` along with `cover.jpg`,
+//! while Qobuz puts the album in a single folder, with a folder for every disc and a PDF booklet.
+//!
+//! An archive dropped into the watch folder is unpacked into the application directory,
+//! with every file of the album flattened into one folder so the album is imported as a unit.
+//! File names are normalized, the front cover is named `cover`, and albums that only have
+//! embedded art get it extracted as `cover` too, so art and booklets are imported as sidecars.
+//! The folder is then moved into the watch folder, named for the store so its tracks are
+//! imported with the store as their source.
+
+use crate::config::Config;
+use crate::database::Connection;
+use crate::downloads::{move_folder_contents, random_name};
+use crate::error::{Error, Result};
+use crate::events::Event;
+use crate::import;
+use crate::paths;
+use crate::rejections;
+use katatsuki::Track;
+use regex::Regex;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use zip::ZipArchive;
+
+/// The extensions of the audio files found in archives.
+const AUDIO_EXTENSIONS: &[&str] = &[
+    "flac", "mp3", "m4a", "aac", "alac", "ogg", "opus", "wav", "aif", "aiff", "wv", "ape",
+];
+
+/// The names of images that are the front cover of an album, without their extension.
+const COVER_NAMES: &[&str] = &["cover", "folder", "front", "albumart"];
+
+/// How the store that made an archive lays out its contents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StoreLayout {
+    /// Every file at the top of the archive, with tracks named `Artist - Album - 01 Title`.
+    Bandcamp,
+    /// The album in a single folder, possibly with a folder for every disc, and a PDF booklet.
+    Qobuz,
+    /// Any other archive, whose files are unpacked as they are named.
+    Generic,
+}
+
+impl StoreLayout {
+    /// The source tracks from archives of the store are imported with.
+    pub fn source(&self) -> &'static str {
+        match self {
+            StoreLayout::Bandcamp => "Bandcamp",
+            StoreLayout::Qobuz => "Qobuz",
+            StoreLayout::Generic => "None",
+        }
+    }
+}
+
+fn extension_of(path: &Path) -> String {
+    path.extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+}
+
+fn is_audio(path: &Path) -> bool {
+    AUDIO_EXTENSIONS.contains(&extension_of(path).as_str())
+}
+
+/// Whether the file is an archive that can be unpacked.
+pub fn is_archive(path: &Path) -> bool {
+    extension_of(path) == "zip"
+}
+
+fn bandcamp_name() -> Regex {
+    Regex::new(r"^(?P<album>.+ - .+) - (?P<track>\d+ .+)$").unwrap()
+}
+
+fn disc_folder() -> Regex {
+    Regex::new(r"(?i)^(disc|disk|cd)\s*(?P<disc>\d+)$").unwrap()
+}
+
+fn file_stem(path: &Path) -> String {
+    path.file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// Works out the layout of an archive from the paths of the files in it.
+pub fn detect_layout(files: &[PathBuf]) -> StoreLayout {
+    let bandcamp_name = bandcamp_name();
+    let mut audio = files.iter().filter(|path| is_audio(path)).peekable();
+    if audio.peek().is_none() {
+        return StoreLayout::Generic;
+    }
+    if files.iter().all(|path| path.components().count() == 1)
+        && audio.all(|path| bandcamp_name.is_match(&file_stem(path)))
+    {
+        return StoreLayout::Bandcamp;
+    }
+    let top_folders = files
+        .iter()
+        .map(|path| path.components().next())
+        .collect::<Vec<Option<Component>>>();
+    let in_one_folder = files.iter().all(|path| path.components().count() > 1)
+        && top_folders.windows(2).all(|folders| folders[0] == folders[1]);
+    let disc_folder = disc_folder();
+    let has_discs_or_booklet = files.iter().any(|path| {
+        extension_of(path) == "pdf"
+            || path
+                .parent()
+                .and_then(|folder| folder.file_name())
+                .is_some_and(|folder| disc_folder.is_match(&folder.to_string_lossy()))
+    });
+    if in_one_folder && has_discs_or_booklet {
+        return StoreLayout::Qobuz;
+    }
+    StoreLayout::Generic
+}
+
+/// The name of the album, for the name of the folder the archive is unpacked into.
+pub fn album_name(layout: StoreLayout, files: &[PathBuf], archive_path: &Path) -> String {
+    let name = match layout {
+        StoreLayout::Bandcamp => files
+            .iter()
+            .filter(|path| is_audio(path))
+            .find_map(|path| Some(bandcamp_name().captures(&file_stem(path))?["album"].to_owned())),
+        StoreLayout::Qobuz => files
+            .first()
+            .and_then(|path| path.components().next())
+            .map(|folder| folder.as_os_str().to_string_lossy().into_owned()),
+        StoreLayout::Generic => None,
+    };
+    // Anything after an underscore in the name of the folder would be taken as part of the source.
+    name.unwrap_or_else(|| file_stem(archive_path)).replace('_', " ")
+}
+
+/// The name the file is unpacked with, flattened out of the folders of the archive.
+pub fn normalized_name(layout: StoreLayout, path: &Path) -> String {
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let extension = extension_of(path);
+    let stem = file_stem(path);
+    if matches!(extension.as_str(), "jpg" | "jpeg" | "png")
+        && COVER_NAMES.iter().any(|cover| stem.eq_ignore_ascii_case(cover))
+    {
+        return format!("cover.{}", extension);
+    }
+    if !is_audio(path) {
+        return file_name;
+    }
+    match layout {
+        StoreLayout::Bandcamp => match bandcamp_name().captures(&stem) {
+            Some(captures) => format!("{}.{}", &captures["track"], extension),
+            None => file_name,
+        },
+        StoreLayout::Qobuz => {
+            // Tracks of every disc are numbered from one, so they are told apart by their disc.
+            let disc = path
+                .parent()
+                .and_then(|folder| folder.file_name())
+                .and_then(|folder| Some(disc_folder().captures(&folder.to_string_lossy())?["disc"].to_owned()));
+            match disc {
+                Some(disc) => format!("{}-{}", disc, file_name),
+                None => file_name,
+            }
+        }
+        StoreLayout::Generic => file_name,
+    }
+}
+
+/// Whether the path in the archive is metadata written by the archiver, such as `__MACOSX`.
+fn is_archiver_metadata(path: &Path) -> bool {
+    path.components().any(|component| {
+        let component = component.as_os_str().to_string_lossy();
+        component.starts_with('.') || component == "__MACOSX"
+    })
+}
+
+/// Unpacks the archive into the given folder, flattening its files into the folder
+/// with their normalized names. Returns the layout of the archive and the name of its album.
+///
+/// Paths in the archive that would escape the folder are skipped.
+pub fn unpack_archive(archive_path: &Path, folder: &Path) -> io::Result<(StoreLayout, String)> {
+    let mut archive = ZipArchive::new(File::open(archive_path)?)?;
+    let mut files = Vec::new();
+    for index in 0..archive.len() {
+        let file = archive.by_index(index)?;
+        match file.enclosed_name() {
+            Some(path) if file.is_file() && !is_archiver_metadata(path) => files.push((index, path.to_owned())),
+            _ => (),
+        }
+    }
+    let paths = files.iter().map(|(_, path)| path.clone()).collect::<Vec<PathBuf>>();
+    let layout = detect_layout(&paths);
+    fs::create_dir_all(folder)?;
+    for (index, path) in &files {
+        let name = normalized_name(layout, path);
+        let extension = extension_of(Path::new(&name));
+        let unpacked_path = paths::get_iterative_filename(&file_stem(Path::new(&name)), &extension, folder);
+        io::copy(&mut archive.by_index(*index)?, &mut File::create(unpacked_path)?)?;
+    }
+    Ok((layout, album_name(layout, &paths, archive_path)))
+}
+
+fn contains_audio(folder: &Path) -> bool {
+    fs::read_dir(folder)
+        .into_iter()
+        .flatten()
+        .any(|entry| entry.is_ok_and(|entry| is_audio(&entry.path())))
+}
+
+/// Writes the art embedded in the first track in the folder as its cover, unless it already has one.
+fn extract_embedded_cover(folder: &Path) -> io::Result<()> {
+    let mut files = fs::read_dir(folder)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .collect::<Vec<PathBuf>>();
+    if files.iter().any(|path| file_stem(path) == "cover") {
+        return Ok(());
+    }
+    files.sort();
+    let track = match files.iter().find(|path| is_audio(path)) {
+        Some(path) => Track::from_path(path, None)?,
+        None => return Ok(()),
+    };
+    if let Some(cover) = track.read_front_cover()? {
+        let extension = if cover.starts_with(b"\x89PNG") { "png" } else { "jpg" };
+        fs::write(folder.join(format!("cover.{}", extension)), cover)?;
+    }
+    Ok(())
+}
+
+/// Unpacks the archive found in the watch folder, and moves its files back into the watch folder
+/// to be imported as an album, removing the archive. Returns the folder the files were moved into.
+///
+/// An archive dropped directly into the watch folder is unpacked into a folder named for its store,
+/// otherwise it is unpacked alongside itself, keeping the source of the folder it was found in.
+pub fn import_archive(archive_path: &Path, auto_add_path: &Path) -> Result<PathBuf> {
+    let failed = || Error::FileIOError(archive_path.to_owned());
+    let mut unpack_path = paths::get_appdata_path();
+    unpack_path.push("archives");
+    unpack_path.push(random_name());
+    let imported = match unpack_archive(archive_path, &unpack_path) {
+        Ok((layout, album)) if contains_audio(&unpack_path) => {
+            // Art is only a nicety, so the album is imported without it if it can not be read.
+            extract_embedded_cover(&unpack_path).ok();
+            let parent = archive_path.parent().unwrap_or(auto_add_path);
+            let import_path = if parent == auto_add_path {
+                auto_add_path.join(format!("{}_{}", layout.source(), album))
+            } else {
+                parent.join(album)
+            };
+            move_folder_contents(&unpack_path, &import_path).map(|_| import_path)
+        }
+        _ => Err(failed()),
+    };
+    fs::remove_dir_all(&unpack_path).ok();
+    if imported.is_ok() {
+        fs::remove_file(archive_path).ok();
+    }
+    imported
+}
+
+/// Imports every archive among the paths, returning the event for each, along with the paths
+/// that are not archives. Archives that can not be unpacked are moved into the not added folder,
+/// and archives on the ignore list are left where they are.
+pub fn import_archives(paths: &[PathBuf], config: &Config, conn: &Connection) -> (Vec<Event>, Vec<PathBuf>) {
+    let (archives, rest): (Vec<&PathBuf>, Vec<&PathBuf>) = paths.iter().partition(|path| is_archive(path));
+    let events = archives
+        .into_iter()
+        .map(|archive| {
+            let auto_add_path = match paths::ensure_music_folder(&config.music_folder) {
+                Ok((_, auto_add_path)) => auto_add_path,
+                Err(_) => return Event::LibraryNotFound(archive.display().to_string()),
+            };
+            if rejections::is_ignored(archive, conn).unwrap_or(false) {
+                return Event::FileIgnored(archive.display().to_string());
+            }
+            match import_archive(archive, &auto_add_path) {
+                Ok(folder) => Event::ArchiveUnpacked(archive.display().to_string(), folder.display().to_string()),
+                Err(_) => {
+                    import::quarantine(archive, &auto_add_path, conn).ok();
+                    Event::ArchiveError(archive.display().to_string())
+                }
+            }
+        })
+        .collect();
+    (events, rest.into_iter().cloned().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::ScratchFolder;
+    use std::io::Write;
+    use zip::write::FileOptions;
+    use zip::ZipWriter;
+
+    fn paths(names: &[&str]) -> Vec<PathBuf> {
+        names.iter().map(PathBuf::from).collect()
+    }
+
+    /// Writes an archive of files named as given, each holding its own name.
+    fn write_archive(path: &Path, names: &[&str]) {
+        let mut archive = ZipWriter::new(File::create(path).unwrap());
+        for name in names {
+            archive.start_file(*name, FileOptions::default()).unwrap();
+            archive.write_all(name.as_bytes()).unwrap();
+        }
+        archive.finish().unwrap();
+    }
+
+    fn unpacked_names(folder: &Path) -> Vec<String> {
+        let mut names = fs::read_dir(folder)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect::<Vec<String>>();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn detects_the_layout_of_bandcamp_archives() {
+        let files = paths(&["Alpha - First - 01 One.flac", "Alpha - First - 02 Two.flac", "cover.jpg"]);
+        assert_eq!(detect_layout(&files), StoreLayout::Bandcamp);
+        assert_eq!(album_name(StoreLayout::Bandcamp, &files, Path::new("purchase.zip")), "Alpha - First");
+    }
+
+    #[test]
+    fn detects_the_layout_of_qobuz_archives() {
+        let discs = paths(&["First/Disc 1/01 One.flac", "First/Disc 2/01 One.flac"]);
+        let booklet = paths(&["First/01 One.flac", "First/booklet.pdf"]);
+        assert_eq!(detect_layout(&discs), StoreLayout::Qobuz);
+        assert_eq!(detect_layout(&booklet), StoreLayout::Qobuz);
+        assert_eq!(album_name(StoreLayout::Qobuz, &discs, Path::new("purchase.zip")), "First");
+    }
+
+    #[test]
+    fn detects_other_archives_as_generic() {
+        assert_eq!(detect_layout(&paths(&["01 One.flac", "02 Two.flac"])), StoreLayout::Generic);
+        assert_eq!(detect_layout(&paths(&["First/01 One.flac", "Second/01 One.flac"])), StoreLayout::Generic);
+        assert_eq!(detect_layout(&paths(&["Alpha - First - 01 One.txt"])), StoreLayout::Generic);
+        assert_eq!(
+            album_name(StoreLayout::Generic, &paths(&["01 One.flac"]), Path::new("First_Deluxe.zip")),
+            "First Deluxe"
+        );
+    }
+
+    #[test]
+    fn normalizes_the_names_of_unpacked_files() {
+        let bandcamp = StoreLayout::Bandcamp;
+        let qobuz = StoreLayout::Qobuz;
+        assert_eq!(normalized_name(bandcamp, Path::new("Alpha - First - 01 One.flac")), "01 One.flac");
+        assert_eq!(normalized_name(bandcamp, Path::new("Folder.JPG")), "cover.jpg");
+        assert_eq!(normalized_name(qobuz, Path::new("First/CD 2/01 One.flac")), "2-01 One.flac");
+        assert_eq!(normalized_name(qobuz, Path::new("First/booklet.pdf")), "booklet.pdf");
+        assert_eq!(normalized_name(StoreLayout::Generic, Path::new("First/01 One.flac")), "01 One.flac");
+    }
+
+    #[test]
+    fn unpacks_archives_flattened_into_one_folder() {
+        let scratch = ScratchFolder::new();
+        let archive_path = scratch.0.join("purchase.zip");
+        write_archive(
+            &archive_path,
+            &[
+                "First/Disc 1/01 One.flac",
+                "First/Disc 2/01 One.flac",
+                "First/booklet.pdf",
+                "First/front.png",
+                "__MACOSX/First/._01 One.flac",
+                "../Escaped.flac",
+            ],
+        );
+        let folder = scratch.0.join("Unpacked");
+
+        let (layout, album) = unpack_archive(&archive_path, &folder).unwrap();
+        assert_eq!(layout, StoreLayout::Qobuz);
+        assert_eq!(album, "First");
+        assert_eq!(unpacked_names(&folder), ["1-01 One.flac", "2-01 One.flac", "booklet.pdf", "cover.png"]);
+        assert_eq!(fs::read(folder.join("2-01 One.flac")).unwrap(), b"First/Disc 2/01 One.flac");
+        assert!(!scratch.0.join("Escaped.flac").exists());
+    }
+}
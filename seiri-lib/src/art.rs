@@ -0,0 +1,316 @@
+//! The album art quality report, and the job that upgrades low resolution art.
+//!
+//! Albums are reported by the largest front cover of any of their tracks, using the size of
+//! the art read when the tracks were imported, so the report never reads a file. Albums without
+//! art are reported with art of no size.
+//!
+//! The upgrade job looks for larger art for every reported album, first in the configured folders
+//! and then at the configured URLs, and embeds it as the front cover of every track of the album
+//! other than locked tracks. The tracks are then read again, so the library has the new size.
+//!
+//! Art extracted from tracks is cached in the application directory, keyed by the hash of its
+//! contents, so the hundreds of tracks of a box set that embed the same art share a single file.
+//! Every cached file counts the tracks referencing it, and is removed by `prune_art` once none do.
+
+use crate::config::{ArtConfig, Config};
+use crate::database::{add_track, create_table_with_foreign_keys, track_from_row, Connection, TRACK_COLUMNS};
+use crate::locks;
+use crate::paths::{get_appdata_path, sanitize_file_name};
+use crate::rejections::hash_bytes;
+use katatsuki::{cover_dimensions, Track};
+use rusqlite::types::ToSql;
+use rusqlite::{OptionalExtension, Result, NO_PARAMS};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The extensions art in the configured folders is looked for with.
+const ART_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png"];
+
+/// The largest art downloaded from a URL.
+#[cfg(feature = "net")]
+const MAX_DOWNLOAD_SIZE: u64 = 32 * 1024 * 1024;
+
+pub fn create_art_tables(conn: &Connection) {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS art_files (
+        Hash TEXT PRIMARY KEY,
+        Extension TEXT NOT NULL,
+        Size INTEGER NOT NULL,
+        RefCount INTEGER NOT NULL DEFAULT 0
+    );",
+    )
+    .unwrap();
+    // Updated is the update time of the track when its art was extracted.
+    create_table_with_foreign_keys(
+        "track_art",
+        "TrackId TEXT PRIMARY KEY REFERENCES tracks(TrackId) ON DELETE CASCADE,
+        Hash TEXT NOT NULL,
+        Updated DATE",
+        conn,
+    )
+    .unwrap();
+    conn.execute_batch(
+        "CREATE TRIGGER IF NOT EXISTS track_art_insert AFTER INSERT ON track_art BEGIN
+        UPDATE art_files SET RefCount = RefCount + 1 WHERE Hash = NEW.Hash;
+    END;
+    CREATE TRIGGER IF NOT EXISTS track_art_update AFTER UPDATE OF Hash ON track_art BEGIN
+        UPDATE art_files SET RefCount = RefCount - 1 WHERE Hash = OLD.Hash;
+        UPDATE art_files SET RefCount = RefCount + 1 WHERE Hash = NEW.Hash;
+    END;
+    CREATE TRIGGER IF NOT EXISTS track_art_delete AFTER DELETE ON track_art BEGIN
+        UPDATE art_files SET RefCount = RefCount - 1 WHERE Hash = OLD.Hash;
+    END;",
+    )
+    .unwrap();
+}
+
+/// An album, with the size of the largest front cover of any of its tracks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AlbumArt {
+    pub album: String,
+    pub album_artists: Vec<String>,
+    pub width: i32,
+    pub height: i32,
+    /// The number of tracks of the album.
+    pub tracks: usize,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ArtUpgradeReport {
+    /// The number of albums whose art was upgraded.
+    pub albums: usize,
+    /// The number of tracks the upgraded art was embedded into.
+    pub tracks: usize,
+    /// The tracks the art could not be embedded into.
+    pub failed: Vec<PathBuf>,
+}
+
+/// Gets every album whose art is smaller than `min_resolution` in width or height,
+/// including albums without art, in the order of their album artists.
+pub fn low_resolution_albums(min_resolution: i32, conn: &Connection) -> Result<Vec<AlbumArt>> {
+    // The width and height are of the track with the largest art, since they are bare columns.
+    let mut statement = conn.prepare(
+        "SELECT Album, AlbumArtists, FrontCoverWidth, FrontCoverHeight, COUNT(*),
+            MAX(CASE WHEN HasFrontCover THEN MIN(FrontCoverWidth, FrontCoverHeight) ELSE 0 END) AS Size
+        FROM tracks GROUP BY Album, AlbumArtists HAVING Size < ?1
+        ORDER BY AlbumArtists, Album",
+    )?;
+    let albums = statement.query_map(&[&min_resolution], |row| {
+        let size: i32 = row.get(5)?;
+        Ok(AlbumArt {
+            album: row.get(0)?,
+            album_artists: row.get::<_, String>(1)?.split(';').map(|artist| artist.to_owned()).collect(),
+            width: if size > 0 { row.get(2)? } else { 0 },
+            height: if size > 0 { row.get(3)? } else { 0 },
+            tracks: row.get::<_, i64>(4)? as usize,
+        })
+    })?;
+    albums.collect()
+}
+
+fn album_tracks(album: &AlbumArt, conn: &Connection) -> Result<Vec<Track>> {
+    let mut statement = conn.prepare(&format!(
+        "SELECT {} FROM tracks WHERE Album = ?1 AND AlbumArtists = ?2",
+        TRACK_COLUMNS
+    ))?;
+    let tracks = statement.query_map(
+        &[&album.album as &dyn ToSql, &album.album_artists.join(";")],
+        track_from_row,
+    )?;
+    tracks.collect()
+}
+
+/// Finds art for the album in the folder, named `Album Artists - Album`.
+fn find_in_folder(album: &AlbumArt, folder: &Path) -> Option<Vec<u8>> {
+    let name = sanitize_file_name(&format!("{} - {}", album.album_artists.join("; "), album.album));
+    ART_EXTENSIONS
+        .iter()
+        .map(|extension| folder.join(format!("{}.{}", name, extension)))
+        .find(|path| path.is_file())
+        .and_then(|path| fs::read(path).ok())
+}
+
+/// Percent-encodes the text for a component of a URL.
+#[cfg(feature = "net")]
+fn encode_url_component(text: &str) -> String {
+    text.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// Downloads art for the album from the URL template.
+#[cfg(feature = "net")]
+fn download(album: &AlbumArt, template: &str) -> Option<Vec<u8>> {
+    use std::io::Read;
+    let url = template
+        .replace("{artist}", &encode_url_component(&album.album_artists.join("; ")))
+        .replace("{album}", &encode_url_component(&album.album));
+    let response = ureq::get(&url).timeout(std::time::Duration::from_secs(30)).call().ok()?;
+    let mut art = Vec::new();
+    response.into_reader().take(MAX_DOWNLOAD_SIZE).read_to_end(&mut art).ok()?;
+    Some(art)
+}
+
+/// Finds art for the album that is larger than its art, in the configured folders and then at the
+/// configured URLs.
+pub fn find_larger_art(album: &AlbumArt, config: &ArtConfig) -> Option<Vec<u8>> {
+    let is_larger = |art: &Vec<u8>| {
+        cover_dimensions(art).is_some_and(|(width, height)| width.min(height) > album.width.min(album.height))
+    };
+    let found = config
+        .folders
+        .iter()
+        .filter_map(|folder| find_in_folder(album, Path::new(folder)))
+        .find(is_larger);
+    #[cfg(feature = "net")]
+    let found = found.or_else(|| config.urls.iter().filter_map(|url| download(album, url)).find(is_larger));
+    found
+}
+
+/// Embeds the art as the front cover of every track of the album other than locked tracks,
+/// and reads the tracks again. Returns the number of tracks the art was embedded into,
+/// adding the tracks it could not be embedded into to the failed tracks.
+fn embed_art(album: &AlbumArt, art: &[u8], failed: &mut Vec<PathBuf>, conn: &Connection) -> Result<usize> {
+    let mut embedded = 0;
+    for track in album_tracks(album, conn)? {
+        if locks::is_locked(&track, conn)? {
+            continue;
+        }
+        let read = track
+            .write_front_cover(art)
+            .and_then(|_| Track::from_path(&track.file_path, Some(&track.source)));
+        match read {
+            Ok(read) => {
+                add_track(&Track { uuid: track.uuid.clone(), ..read }, conn);
+                embedded += 1;
+            }
+            Err(_) => failed.push(track.file_path),
+        }
+    }
+    Ok(embedded)
+}
+
+/// Upgrades the art of every album reported as low resolution, with larger art found in the
+/// configured folders or at the configured URLs. Albums for which no larger art is found are
+/// left as they are.
+pub fn upgrade_art(config: &Config, conn: &Connection) -> Result<ArtUpgradeReport> {
+    let mut report = ArtUpgradeReport::default();
+    for album in low_resolution_albums(config.art.min_resolution, conn)? {
+        if let Some(art) = find_larger_art(&album, &config.art) {
+            let embedded = embed_art(&album, &art, &mut report.failed, conn)?;
+            if embedded > 0 {
+                report.albums += 1;
+                report.tracks += embedded;
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// The size of the art cache, and how much storing every image once saves.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ArtCacheStats {
+    /// The number of images in the cache.
+    pub files: usize,
+    /// The number of tracks whose art is in the cache.
+    pub tracks: usize,
+    /// The number of bytes of every image in the cache.
+    pub size: u64,
+    /// The number of bytes that would also be stored if every track had its own copy of its art.
+    pub saved: u64,
+}
+
+fn get_art_cache_path() -> PathBuf {
+    let mut art_path = get_appdata_path();
+    art_path.push("art");
+    art_path
+}
+
+fn cached_file_path(hash: &str, extension: &str) -> PathBuf {
+    let mut file_path = get_art_cache_path();
+    file_path.push(format!("{}.{}", hash, extension));
+    file_path
+}
+
+/// Gets the path of the cached front cover of the track, extracting it into the cache if it was not
+/// extracted since the track was last updated. Returns `None` if the track has no front cover.
+///
+/// Tracks whose art is the same share the same file.
+pub fn get_cached_art(track: &Track, conn: &Connection) -> Result<Option<PathBuf>> {
+    let track_id = match &track.uuid {
+        Some(track_id) => track_id,
+        None => return Ok(None),
+    };
+    let cached = conn
+        .query_row(
+            "SELECT art_files.Hash, Extension FROM track_art JOIN art_files ON art_files.Hash = track_art.Hash
+            WHERE TrackId = ?1 AND Updated IS ?2",
+            &[track_id, &track.updated],
+            |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)),
+        )
+        .optional()?
+        .map(|(hash, extension)| cached_file_path(&hash, &extension))
+        .filter(|file_path| file_path.is_file());
+    if cached.is_some() {
+        return Ok(cached);
+    }
+
+    let failed = || rusqlite::Error::InvalidPath(track.file_path.to_owned());
+    let art = match track.read_front_cover().map_err(|_| failed())? {
+        Some(art) => art,
+        None => {
+            conn.execute("DELETE FROM track_art WHERE TrackId = ?1", &[track_id])?;
+            return Ok(None);
+        }
+    };
+    let hash = hash_bytes(&art);
+    let extension = if art.starts_with(b"\x89PNG") { "png" } else { "jpg" };
+    let file_path = cached_file_path(&hash, extension);
+    if !file_path.is_file() {
+        fs::create_dir_all(get_art_cache_path()).map_err(|_| failed())?;
+        fs::write(&file_path, &art).map_err(|_| failed())?;
+    }
+    conn.execute(
+        "INSERT INTO art_files(Hash, Extension, Size) VALUES (?1, ?2, ?3) ON CONFLICT(Hash) DO NOTHING",
+        &[&hash as &dyn ToSql, &extension, &(art.len() as i64)],
+    )?;
+    conn.execute(
+        "INSERT INTO track_art(TrackId, Hash, Updated) VALUES (?1, ?2, ?3)
+        ON CONFLICT(TrackId) DO UPDATE SET Hash = excluded.Hash, Updated = excluded.Updated",
+        &[track_id, &hash, &track.updated],
+    )?;
+    Ok(Some(file_path))
+}
+
+/// Removes every cached image no track references anymore, returning how many were removed.
+pub fn prune_art(conn: &Connection) -> Result<usize> {
+    let mut statement = conn.prepare("SELECT Hash, Extension FROM art_files WHERE RefCount <= 0")?;
+    let unreferenced = statement
+        .query_map(NO_PARAMS, |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?
+        .collect::<Result<Vec<(String, String)>>>()?;
+    for (hash, extension) in &unreferenced {
+        fs::remove_file(cached_file_path(hash, extension)).ok();
+        conn.execute("DELETE FROM art_files WHERE Hash = ?1", &[hash])?;
+    }
+    Ok(unreferenced.len())
+}
+
+/// Gets the size of the art cache, and how much deduplicating its images saves.
+pub fn stats(conn: &Connection) -> Result<ArtCacheStats> {
+    conn.query_row(
+        "SELECT COUNT(*), IFNULL(SUM(RefCount), 0), IFNULL(SUM(Size), 0), IFNULL(SUM(Size * (RefCount - 1)), 0)
+        FROM art_files WHERE RefCount > 0",
+        NO_PARAMS,
+        |row| {
+            Ok(ArtCacheStats {
+                files: row.get::<_, i64>(0)? as usize,
+                tracks: row.get::<_, i64>(1)? as usize,
+                size: row.get::<_, i64>(2)? as u64,
+                saved: row.get::<_, i64>(3)? as u64,
+            })
+        },
+    )
+}
@@ -0,0 +1,102 @@
+use crate::config::NetworkConfig;
+use crate::error::{Error, Result};
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use std::net::IpAddr;
+
+const TOKEN_LENGTH: usize = 32;
+
+/// What a token allows a request to do. Every scope allows everything the scopes before it do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Scope {
+    /// Searching the library and reading tracks, such as to stream them.
+    ReadOnly,
+    /// Every request, including changes to the library and its configuration.
+    Admin,
+}
+
+/// Generates a new random authentication token suitable for `NetworkConfig::auth_tokens`.
+pub fn generate_token() -> String {
+    thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(TOKEN_LENGTH)
+        .collect::<String>()
+}
+
+fn is_loopback(bind_address: &str) -> bool {
+    match bind_address.parse::<IpAddr>() {
+        Ok(address) => address.is_loopback(),
+        Err(_) => bind_address.eq_ignore_ascii_case("localhost"),
+    }
+}
+
+/// Whether requests to the network APIs must carry an authentication token, which they must once
+/// any token is configured. The APIs are only served on localhost, so without tokens only
+/// programs on the machine can reach them.
+pub fn requires_auth(config: &NetworkConfig) -> bool {
+    !config.auth_tokens.is_empty() || !config.read_only_tokens.is_empty()
+}
+
+/// Ensures the network configuration is safe to start listening with, which it is only if the
+/// APIs are bound to localhost. The APIs are served without TLS, so their tokens and everything
+/// they serve would otherwise be sent across the network in the clear, and they are reached from
+/// other machines through a tunnel instead, such as SSH port forwarding.
+pub fn validate_network_config(config: &NetworkConfig) -> Result<()> {
+    if !is_loopback(&config.bind_address) {
+        return Err(Error::InsecureNetworkConfig(config.bind_address.to_owned()));
+    }
+    Ok(())
+}
+
+/// Compares two byte strings in time independent of where they first differ.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b.iter()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Extracts the token from the value of an `Authorization: Bearer <token>` header.
+pub fn parse_bearer_token(header: &str) -> Option<&str> {
+    let mut parts = header.trim().splitn(2, ' ');
+    match (parts.next(), parts.next()) {
+        (Some(scheme), Some(token)) if scheme.eq_ignore_ascii_case("bearer") => Some(token.trim()),
+        _ => None,
+    }
+}
+
+fn has_token(tokens: &[String], token: &str) -> bool {
+    !token.is_empty() && tokens.iter().any(|t| constant_time_eq(t.as_bytes(), token.as_bytes()))
+}
+
+/// Checks the token presented by a request against the configured tokens,
+/// returning the scope the token allows. Requests that need no token are allowed every scope.
+pub fn authenticate(config: &NetworkConfig, token: Option<&str>) -> Result<Scope> {
+    if !requires_auth(config) {
+        return Ok(Scope::Admin);
+    }
+    match token {
+        Some(token) if has_token(&config.auth_tokens, token) => Ok(Scope::Admin),
+        Some(token) if has_token(&config.read_only_tokens, token) => Ok(Scope::ReadOnly),
+        _ => Err(Error::Unauthorized),
+    }
+}
+
+/// Checks the token presented on connecting to the control socket of the watcher. Unlike the
+/// other network APIs, an admin token is needed even on localhost, since any program on the
+/// machine can connect to it, and its commands change the library. Every connection is refused
+/// if no admin token is configured.
+pub fn authenticate_control(config: &NetworkConfig, token: Option<&str>) -> Result<()> {
+    match token {
+        Some(token) if has_token(&config.auth_tokens, token) => Ok(()),
+        _ => Err(Error::Unauthorized),
+    }
+}
+
+/// Checks that the token presented by a request allows a request that needs the given scope.
+pub fn authorize(config: &NetworkConfig, token: Option<&str>, scope: Scope) -> Result<()> {
+    if authenticate(config, token)? < scope {
+        return Err(Error::Forbidden);
+    }
+    Ok(())
+}
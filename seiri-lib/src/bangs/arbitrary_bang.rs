@@ -0,0 +1,163 @@
+use super::bangs::Bang;
+use super::duplicates::{DuplicateCriteria, DuplicateCriterion};
+use super::field::{FieldComparison, FieldMatch};
+use super::relation::{RelatedTo, Relation};
+use arbitrary::{Arbitrary, Error, Result, Unstructured};
+use chrono::NaiveDate;
+use katatsuki::{Quality, TrackFileType};
+
+const MAX_DEPTH: usize = 4;
+
+const FIELDS: &[&str] = &["mood", "energy", "color"];
+
+const FIELD_VALUES: &[&str] = &["chill", "Upbeat", "dark red"];
+
+const FILE_TYPES: &[TrackFileType] = &[
+    TrackFileType::Unknown,
+    TrackFileType::FLAC4,
+    TrackFileType::FLAC8,
+    TrackFileType::FLAC16,
+    TrackFileType::FLAC24,
+    TrackFileType::FLAC32,
+    TrackFileType::FLAC,
+    TrackFileType::MP3CBR,
+    TrackFileType::MP3VBR,
+    TrackFileType::AAC,
+    TrackFileType::Vorbis,
+    TrackFileType::Opus,
+    TrackFileType::ALAC16,
+    TrackFileType::ALAC24,
+    TrackFileType::ALAC,
+    TrackFileType::AIFF4,
+    TrackFileType::AIFF8,
+    TrackFileType::AIFF16,
+    TrackFileType::AIFF24,
+    TrackFileType::AIFF32,
+    TrackFileType::AIFF,
+    TrackFileType::MonkeysAudio8,
+    TrackFileType::MonkeysAudio16,
+    TrackFileType::MonkeysAudio24,
+    TrackFileType::MonkeysAudio,
+    TrackFileType::MP3,
+];
+
+fn arbitrary_ticks(u: &mut Unstructured) -> Result<i64> {
+    u.int_in_range(0..=i64::from(u32::MAX) * 10_000)
+}
+
+fn arbitrary_date(u: &mut Unstructured) -> Result<String> {
+    let date = NaiveDate::from_ymd_opt(
+        u.int_in_range(1000..=9999)?,
+        u.int_in_range(1..=12)?,
+        u.int_in_range(1..=28)?,
+    )
+    .ok_or(Error::IncorrectFormat)?;
+    Ok(date.format("%Y-%m-%d").to_string())
+}
+
+fn arbitrary_field(u: &mut Unstructured) -> Result<FieldMatch> {
+    let comparison = *u.choose(&FieldComparison::ALL)?;
+    // Values are generated, since the whitespace around other strings is not kept,
+    // and only integers can be compared.
+    let value = if comparison == FieldComparison::Equal && bool::arbitrary(u)? {
+        u.choose(FIELD_VALUES)?.to_string()
+    } else {
+        i64::arbitrary(u)?.to_string()
+    };
+    Ok(FieldMatch {
+        field: u.choose(FIELDS)?.to_string(),
+        comparison,
+        value,
+    })
+}
+
+fn arbitrary_duplicate_criteria(u: &mut Unstructured) -> Result<DuplicateCriteria> {
+    // Criteria are never repeated, since repeats are left out when parsed.
+    let mut criteria = vec![*u.choose(&DuplicateCriterion::ALL)?];
+    while criteria.len() < DuplicateCriterion::ALL.len() && bool::arbitrary(u)? {
+        let criterion = *u.choose(&DuplicateCriterion::ALL)?;
+        if !criteria.contains(&criterion) {
+            criteria.push(criterion);
+        }
+    }
+    Ok(DuplicateCriteria(criteria))
+}
+
+fn arbitrary_leaf(u: &mut Unstructured) -> Result<Bang> {
+    Ok(match u.int_in_range(0..=34)? {
+        0 => Bang::TitleSearch(String::arbitrary(u)?),
+        1 => Bang::TitleSearchExact(String::arbitrary(u)?),
+        2 => Bang::FullTextSearch(String::arbitrary(u)?),
+        3 => Bang::FullTextSearchExact(String::arbitrary(u)?),
+        4 => Bang::AlbumTitle(String::arbitrary(u)?),
+        5 => Bang::AlbumTitleExact(String::arbitrary(u)?),
+        6 => Bang::AlbumArtists(String::arbitrary(u)?),
+        7 => Bang::AlbumArtistsExact(String::arbitrary(u)?),
+        8 => Bang::Artist(String::arbitrary(u)?),
+        9 => Bang::ArtistExact(String::arbitrary(u)?),
+        10 => Bang::Source(String::arbitrary(u)?),
+        11 => Bang::Format(*u.choose(FILE_TYPES)?),
+        12 => Bang::BitrateLessThan(i32::arbitrary(u)?),
+        13 => Bang::BitrateGreaterThan(i32::arbitrary(u)?),
+        14 => Bang::CoverArtWidthLessThan(i32::arbitrary(u)?),
+        15 => Bang::CoverArtWidthGreaterThan(i32::arbitrary(u)?),
+        16 => Bang::CoverArtHeightLessThan(i32::arbitrary(u)?),
+        17 => Bang::CoverArtHeightGreaterThan(i32::arbitrary(u)?),
+        18 => Bang::DurationLessThan(arbitrary_ticks(u)?),
+        19 => Bang::DurationGreaterThan(arbitrary_ticks(u)?),
+        20 => Bang::HasCoverArt(bool::arbitrary(u)?),
+        21 => Bang::HasMusicbrainzId(bool::arbitrary(u)?),
+        22 => Bang::HasDuplicates(bool::arbitrary(u)?),
+        23 => Bang::UpdatedBefore(arbitrary_date(u)?),
+        24 => Bang::Quality(*u.choose(&Quality::ALL)?),
+        25 => Bang::Genre(String::arbitrary(u)?),
+        26 => Bang::FakeLossless(bool::arbitrary(u)?),
+        27 => Bang::Note(String::arbitrary(u)?),
+        28 => Bang::Isrc(String::arbitrary(u)?),
+        29 => Bang::Encoder(String::arbitrary(u)?),
+        30 => Bang::Related(RelatedTo {
+            relation: *u.choose(&Relation::ALL)?,
+            // UUIDs are generated, since the whitespace around other strings is not kept.
+            track_id: if bool::arbitrary(u)? { Some(format!("{:032x}", u128::arbitrary(u)?)) } else { None },
+        }),
+        31 => Bang::CustomField(arbitrary_field(u)?),
+        32 => Bang::Failure(String::arbitrary(u)?),
+        33 => Bang::DuplicatesBy(arbitrary_duplicate_criteria(u)?),
+        _ => Bang::UpdatedAfter(arbitrary_date(u)?),
+    })
+}
+
+/// Generates the left hand side of a logical operator, which is never itself a logical operator.
+fn arbitrary_operand(u: &mut Unstructured, depth: usize) -> Result<Bang> {
+    if depth > 0 && u.ratio(1, 4)? {
+        Ok(Bang::Grouping(Box::new(arbitrary_expression(u, depth - 1)?)))
+    } else {
+        arbitrary_leaf(u)
+    }
+}
+
+fn arbitrary_expression(u: &mut Unstructured, depth: usize) -> Result<Bang> {
+    let lhs = arbitrary_operand(u, depth)?;
+    if depth == 0 {
+        return Ok(lhs);
+    }
+    Ok(match u.int_in_range(0..=2)? {
+        0 => Bang::LogicalAnd(Box::new(lhs), Box::new(arbitrary_expression(u, depth - 1)?)),
+        1 => Bang::LogicalOr(Box::new(lhs), Box::new(arbitrary_expression(u, depth - 1)?)),
+        _ => lhs,
+    })
+}
+
+/// Generates bangs in the same shape that `Bang::new` produces, so that
+/// parsing `Bang::to_query` always results in an equal bang.
+///
+/// `Bang::FilePath` has no query syntax, and is never generated.
+impl<'a> Arbitrary<'a> for Bang {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        if u.ratio(1, 32)? {
+            Ok(Bang::All)
+        } else {
+            arbitrary_expression(u, MAX_DEPTH)
+        }
+    }
+}
@@ -1,12 +1,18 @@
 extern crate itertools;
 
-use katatsuki::TrackFileType;
+use katatsuki::{Quality, TrackFileType};
 use crate::error::{Result};
+use super::duplicates::DuplicateCriteria;
+use super::field::FieldMatch;
 use super::lexer::{lex_query};
 use super::parser::{parse_token_stream};
+use super::relation::RelatedTo;
+use super::spans::token_offsets;
+use super::time::{NS_PER_TICK, TICKS_PER_SEC};
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Bang {
     All,
     TitleSearch(String),
@@ -20,7 +26,9 @@ pub enum Bang {
     Artist(String),
     ArtistExact(String),
     Source(String),
+    Genre(String),
     Format(TrackFileType),
+    Quality(Quality),
     BitrateLessThan(i32), 
     BitrateGreaterThan(i32),
     CoverArtWidthLessThan(i32),
@@ -31,7 +39,24 @@ pub enum Bang {
     DurationGreaterThan(i64),
     HasCoverArt(bool),
     HasMusicbrainzId(bool),
+    /// Matches tracks with duplicates by title and album artists.
     HasDuplicates(bool),
+    /// Matches tracks with duplicates by every one of the columns, such as `title,artist`.
+    DuplicatesBy(DuplicateCriteria),
+    FakeLossless(bool),
+    /// Matches the comment tag or the private note of the track partially.
+    Note(String),
+    /// Matches the ISRC of the track, with or without its hyphens.
+    Isrc(String),
+    /// Matches the encoder settings of the track partially.
+    Encoder(String),
+    /// Matches tracks linked as an alternate version of another track, such as remixes.
+    Related(RelatedTo),
+    /// Matches the value of a custom field of the track, such as `mood=chill`.
+    CustomField(FieldMatch),
+    /// Matches tracks whose file failed to be imported or analyzed, by the code, stage or message
+    /// of the failure partially.
+    Failure(String),
     LogicalAnd(Box<Bang>, Box<Bang>),
     LogicalOr(Box<Bang>, Box<Bang>),
     Grouping(Box<Bang>),
@@ -43,8 +68,122 @@ pub enum Bang {
 impl Bang {
     pub fn new(query: &str) -> Result<Bang> {
         let token_stream = lex_query(query)?;
+        let offsets = token_offsets(query, &token_stream);
+        let token_stream = offsets.into_iter().zip(token_stream).collect::<Vec<_>>();
         parse_token_stream(&mut token_stream.iter())
     }
+
+    /// Writes the bang back out as a query string, such that `Bang::new`
+    /// parses the query back into an equivalent bang.
+    ///
+    /// Logical operators are right-associative, so a logical operator on the
+    /// left hand side of another is wrapped in a grouping.
+    /// Returns `None` for `Bang::FilePath`, which can not be written as a query.
+    pub fn to_query(&self) -> Option<String> {
+        let query = match self {
+            Bang::All => String::new(),
+            Bang::TitleSearch(search) => bang_query("t", search),
+            Bang::TitleSearchExact(search) => bang_query("T", search),
+            Bang::FullTextSearch(search) => bang_query("q", search),
+            Bang::FullTextSearchExact(search) => bang_query("Q", search),
+            Bang::AlbumTitle(search) => bang_query("al", search),
+            Bang::AlbumTitleExact(search) => bang_query("AL", search),
+            Bang::AlbumArtists(search) => bang_query("alar", search),
+            Bang::AlbumArtistsExact(search) => bang_query("ALAR", search),
+            Bang::Artist(search) => bang_query("ar", search),
+            Bang::ArtistExact(search) => bang_query("AR", search),
+            Bang::Source(search) => bang_query("s", search),
+            Bang::Genre(genre) => bang_query("g", genre),
+            Bang::Format(format) => bang_query("f", file_type_name(*format)),
+            Bang::Quality(quality) => bang_query("qual", quality.name()),
+            Bang::BitrateLessThan(bitrate) => bang_query("brlt", &bitrate.to_string()),
+            Bang::BitrateGreaterThan(bitrate) => bang_query("brgt", &bitrate.to_string()),
+            Bang::CoverArtWidthLessThan(cw) => bang_query("cwlt", &cw.to_string()),
+            Bang::CoverArtWidthGreaterThan(cw) => bang_query("cwgt", &cw.to_string()),
+            Bang::CoverArtHeightLessThan(ch) => bang_query("chlt", &ch.to_string()),
+            Bang::CoverArtHeightGreaterThan(ch) => bang_query("chgt", &ch.to_string()),
+            Bang::DurationLessThan(ticks) => bang_query("dlt", &ticks_to_duration(*ticks)),
+            Bang::DurationGreaterThan(ticks) => bang_query("dgt", &ticks_to_duration(*ticks)),
+            Bang::HasCoverArt(c) => bang_query("c", &c.to_string()),
+            Bang::HasMusicbrainzId(mb) => bang_query("mb", &mb.to_string()),
+            Bang::HasDuplicates(dup) => bang_query("dup", &dup.to_string()),
+            Bang::DuplicatesBy(criteria) => bang_query("dup", &criteria.to_string()),
+            Bang::FakeLossless(fake) => bang_query("fake", &fake.to_string()),
+            Bang::Note(search) => bang_query("note", search),
+            Bang::Isrc(isrc) => bang_query("isrc", isrc),
+            Bang::Encoder(search) => bang_query("enc", search),
+            Bang::Related(related) => bang_query("rel", &related.to_string()),
+            Bang::CustomField(field) => bang_query("x", &field.to_string()),
+            Bang::Failure(search) => bang_query("err", search),
+            Bang::UpdatedBefore(date) => bang_query("ubf", date),
+            Bang::UpdatedAfter(date) => bang_query("uaf", date),
+            Bang::LogicalAnd(lhs, rhs) => format!("{} & {}", lhs.to_operand()?, rhs.to_query()?),
+            Bang::LogicalOr(lhs, rhs) => format!("{} | {}", lhs.to_operand()?, rhs.to_query()?),
+            Bang::Grouping(inner) => format!("!!{{{}}}", inner.to_query()?),
+            Bang::FilePath(_) => return None,
+        };
+        Some(query)
+    }
+
+    fn to_operand(&self) -> Option<String> {
+        match self {
+            Bang::LogicalAnd(_, _) | Bang::LogicalOr(_, _) => {
+                Some(format!("!!{{{}}}", self.to_query()?))
+            }
+            _ => self.to_query(),
+        }
+    }
+}
+
+fn bang_query(identifier: &str, argument: &str) -> String {
+    let mut query = format!("!{}{{", identifier);
+    for c in argument.chars() {
+        if c == '}' || c == '\\' {
+            query.push('\\');
+        }
+        query.push(c);
+    }
+    query.push('}');
+    query
+}
+
+fn ticks_to_duration(ticks: i64) -> String {
+    let ticks = ticks.max(0);
+    let duration = Duration::from_secs((ticks / TICKS_PER_SEC) as u64)
+        + Duration::from_nanos((ticks % TICKS_PER_SEC) as u64 * NS_PER_TICK as u64);
+    humantime::format_duration(duration).to_string()
+}
+
+/// The name of the file type as accepted by the `!f` bang.
+fn file_type_name(file_type: TrackFileType) -> &'static str {
+    match file_type {
+        TrackFileType::Unknown => "unknown",
+        TrackFileType::FLAC4 => "flac4",
+        TrackFileType::FLAC8 => "flac8",
+        TrackFileType::FLAC16 => "flac16",
+        TrackFileType::FLAC24 => "flac24",
+        TrackFileType::FLAC32 => "flac32",
+        TrackFileType::FLAC => "flac",
+        TrackFileType::MP3CBR => "cbr",
+        TrackFileType::MP3VBR => "vbr",
+        TrackFileType::AAC => "aac",
+        TrackFileType::Vorbis => "vorbis",
+        TrackFileType::Opus => "opus",
+        TrackFileType::ALAC16 => "alac16",
+        TrackFileType::ALAC24 => "alac24",
+        TrackFileType::ALAC => "alac",
+        TrackFileType::AIFF4 => "aiff4",
+        TrackFileType::AIFF8 => "aiff8",
+        TrackFileType::AIFF16 => "aiff16",
+        TrackFileType::AIFF24 => "aiff24",
+        TrackFileType::AIFF32 => "aiff32",
+        TrackFileType::AIFF => "aiff",
+        TrackFileType::MonkeysAudio8 => "ape8",
+        TrackFileType::MonkeysAudio16 => "ape16",
+        TrackFileType::MonkeysAudio24 => "ape24",
+        TrackFileType::MonkeysAudio => "ape",
+        TrackFileType::MP3 => "mp3",
+    }
 }
 
 impl From<PathBuf> for Bang {
@@ -0,0 +1,95 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// A column tracks are compared by to find duplicates, as named in the argument of the `!dup` bang.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DuplicateCriterion {
+    Title,
+    Artist,
+    AlbumArtists,
+    Album,
+    TrackNumber,
+    DiscNumber,
+    Year,
+    /// The duration of the track, so tracks within a second of each other are duplicates by it.
+    Duration,
+    /// The ISRC of the track, without its hyphens.
+    Isrc,
+    MusicbrainzId,
+    /// The waveform of the track taken by the analysis job, so only tracks of the same recording,
+    /// such as copies of it encoded or tagged differently, are duplicates by it.
+    Fingerprint,
+}
+
+impl DuplicateCriterion {
+    pub const ALL: [DuplicateCriterion; 11] = [
+        DuplicateCriterion::Title,
+        DuplicateCriterion::Artist,
+        DuplicateCriterion::AlbumArtists,
+        DuplicateCriterion::Album,
+        DuplicateCriterion::TrackNumber,
+        DuplicateCriterion::DiscNumber,
+        DuplicateCriterion::Year,
+        DuplicateCriterion::Duration,
+        DuplicateCriterion::Isrc,
+        DuplicateCriterion::MusicbrainzId,
+        DuplicateCriterion::Fingerprint,
+    ];
+
+    /// The name of the criterion as accepted by the `!dup` bang, such as `tracknumber`.
+    pub fn name(self) -> &'static str {
+        match self {
+            DuplicateCriterion::Title => "title",
+            DuplicateCriterion::Artist => "artist",
+            DuplicateCriterion::AlbumArtists => "albumartists",
+            DuplicateCriterion::Album => "album",
+            DuplicateCriterion::TrackNumber => "tracknumber",
+            DuplicateCriterion::DiscNumber => "discnumber",
+            DuplicateCriterion::Year => "year",
+            DuplicateCriterion::Duration => "duration",
+            DuplicateCriterion::Isrc => "isrc",
+            DuplicateCriterion::MusicbrainzId => "mbid",
+            DuplicateCriterion::Fingerprint => "fingerprint",
+        }
+    }
+}
+
+impl FromStr for DuplicateCriterion {
+    type Err = ();
+
+    fn from_str(name: &str) -> Result<DuplicateCriterion, ()> {
+        let name = name.trim().to_lowercase();
+        DuplicateCriterion::ALL
+            .iter()
+            .copied()
+            .find(|criterion| criterion.name() == name)
+            .ok_or(())
+    }
+}
+
+/// The argument of the `!dup` bang that names the columns tracks are compared by, such as
+/// `title,artist`. Tracks are duplicates if every one of the columns is the same.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DuplicateCriteria(pub Vec<DuplicateCriterion>);
+
+impl FromStr for DuplicateCriteria {
+    type Err = ();
+
+    fn from_str(argument: &str) -> Result<DuplicateCriteria, ()> {
+        let mut criteria = Vec::new();
+        for name in argument.split(',') {
+            let criterion = name.parse()?;
+            if !criteria.contains(&criterion) {
+                criteria.push(criterion);
+            }
+        }
+        Ok(DuplicateCriteria(criteria))
+    }
+}
+
+impl fmt::Display for DuplicateCriteria {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let names = self.0.iter().map(|criterion| criterion.name()).collect::<Vec<&str>>();
+        write!(f, "{}", names.join(","))
+    }
+}
@@ -0,0 +1,75 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// How the value of a custom field is compared to the value in a `!x` bang.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FieldComparison {
+    /// Matches values equal to the value, ignoring case.
+    Equal,
+    /// Matches integer values less than the value.
+    LessThan,
+    /// Matches integer values greater than the value.
+    GreaterThan,
+}
+
+impl FieldComparison {
+    pub const ALL: [FieldComparison; 3] = [
+        FieldComparison::Equal,
+        FieldComparison::LessThan,
+        FieldComparison::GreaterThan,
+    ];
+
+    /// The operator of the comparison in a `!x` bang, such as `=`.
+    pub fn operator(self) -> char {
+        match self {
+            FieldComparison::Equal => '=',
+            FieldComparison::LessThan => '<',
+            FieldComparison::GreaterThan => '>',
+        }
+    }
+}
+
+/// The argument of the `!x` bang, matching tracks by the value of a custom field,
+/// such as `mood=chill` or `energy>5`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldMatch {
+    /// The name of the field, in lowercase.
+    pub field: String,
+    pub comparison: FieldComparison,
+    pub value: String,
+}
+
+impl FromStr for FieldMatch {
+    type Err = ();
+
+    fn from_str(argument: &str) -> Result<FieldMatch, ()> {
+        let (index, comparison) = argument
+            .char_indices()
+            .find_map(|(index, c)| {
+                FieldComparison::ALL
+                    .iter()
+                    .find(|comparison| comparison.operator() == c)
+                    .map(|comparison| (index, *comparison))
+            })
+            .ok_or(())?;
+        let field = argument[..index].trim().to_lowercase();
+        let value = argument[index + 1..].trim();
+        if field.is_empty() || value.is_empty() {
+            return Err(());
+        }
+        if comparison != FieldComparison::Equal && value.parse::<i64>().is_err() {
+            return Err(());
+        }
+        Ok(FieldMatch {
+            field,
+            comparison,
+            value: value.to_owned(),
+        })
+    }
+}
+
+impl fmt::Display for FieldMatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}{}", self.field, self.comparison.operator(), self.value)
+    }
+}
@@ -134,7 +134,7 @@ fn match_argument_edge(
                     Token::Argument("true".to_owned()),
                     Token::ArgumentEnd,
                 ]),
-                LexerMode::Argument,
+                LexerMode::ArgumentEdge,
             )),
             _ => return Err(Error::LexerUnexpectedCharacter(*c, LexerMode::ArgumentEdge)),
         };
@@ -297,7 +297,6 @@ fn ensure_arguments_balanced(tokens: &Vec<Token>) -> bool {
 /// and the true tick sugar ` -> {true}
 pub fn lex_query(query: &str) -> Result<Vec<Token>> {
     let mut tokens = Vec::<Token>::new();
-    let mut mode = LexerMode::Bang;
 
     // The empty query matches all tracks
     if query.chars().count() == 0 {
@@ -306,18 +305,34 @@ pub fn lex_query(query: &str) -> Result<Vec<Token>> {
         return Ok(tokens);
     };
 
-    let query = query.to_owned();
-    let mut characters = multipeek(query.chars());
+    if let Some(title) = lex_title(query) {
+        tokens.extend(title);
+        return Ok(tokens);
+    }
 
-    match match_title(&query, &mut characters) {
-        Some(Token::PreprocessTokenExpand(title)) => {
-            tokens.extend(title.into_iter());
-            return Ok(tokens);
-        }
-        _ => (),
+    let (tokens, result) = lex_bangs(query);
+    result.map(|_| tokens)
+}
+
+/// Lexes a bang-less title search into the equivalent `!q` token stream,
+/// or returns `None` if the query starts with a bang.
+pub(super) fn lex_title(query: &str) -> Option<Vec<Token>> {
+    let mut characters = multipeek(query.chars());
+    match match_title(query, &mut characters) {
+        Some(Token::PreprocessTokenExpand(title)) => Some(title),
+        _ => None,
     }
+}
+
+/// Lexes a query that starts with a bang.
+///
+/// Returns every token lexed before the first error along with the error, so
+/// that the valid prefix of a query can still be inspected.
+pub(super) fn lex_bangs(query: &str) -> (Vec<Token>, Result<()>) {
+    let mut tokens = Vec::<Token>::new();
+    let mut mode = LexerMode::Bang;
+    let mut characters = multipeek(query.chars());
 
-    characters.reset_peek();
     while let Some(c) = characters.peek().cloned() {
         let result = match mode {
             LexerMode::Bang => match_bang(&c, &mut characters),
@@ -338,16 +353,15 @@ pub fn lex_query(query: &str) -> Result<Vec<Token>> {
                 }
                 None => (),
             },
-            Err(err) => return Err(err),
+            Err(err) => return (tokens, Err(err)),
         }
         characters.reset_peek();
     }
 
     if ensure_arguments_balanced(&tokens) {
         tokens.push(Token::InputEnd);
-        Ok(tokens)
+        (tokens, Ok(()))
     } else {
-        Err(Error::LexerUnexpectedEndOfInput)
+        (tokens, Err(Error::LexerUnexpectedEndOfInput))
     }
-    //Ok(tokens)
 }
@@ -1,11 +1,21 @@
 mod lexer;
+#[cfg(feature = "arbitrary")]
+mod arbitrary_bang;
 mod bangs;
+mod duplicates;
+mod field;
 mod parser;
+mod relation;
+mod spans;
 mod time;
 //pub use self::lexer::lex_query;
 pub use self::bangs::Bang;
+pub use self::duplicates::{DuplicateCriteria, DuplicateCriterion};
+pub use self::field::{FieldComparison, FieldMatch};
+pub use self::relation::{RelatedTo, Relation};
 pub use self::lexer::LexerMode;
 pub use self::lexer::Token;
+pub use self::spans::{tokenize_with_spans, TokenCategory, TokenSpan};
 pub use self::time::ticks_to_ms;
 pub use self::time::ms_to_ticks;
 //pub use self::parser::parse_token_stream;
\ No newline at end of file
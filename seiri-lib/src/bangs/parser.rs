@@ -2,7 +2,10 @@ use std::slice::Iter;
 use std::str::FromStr;
 use super::lexer::Token;
 use super::bangs::Bang;
-use katatsuki::TrackFileType;
+use super::duplicates::DuplicateCriteria;
+use super::field::FieldMatch;
+use super::relation::RelatedTo;
+use katatsuki::{Quality, TrackFileType};
 use crate::error::{Error, Result};
 use humantime::Duration;
 use chrono::NaiveDate;
@@ -26,7 +29,9 @@ impl BangIdentifier for str {
             "ar" => BangType::Artist,
             "AR" => BangType::ArtistExact,
             "s" => BangType::Source,
+            "g" => BangType::Genre,
             "f" => BangType::Format,
+            "qual" => BangType::Quality,
             "dlt" => BangType::DurationLessThan,
             "dgt" => BangType::DurationGreaterThan,
             "brlt" => BangType::BitrateLessThan,
@@ -38,6 +43,13 @@ impl BangIdentifier for str {
             "c" => BangType::HasCoverArt,
             "mb" => BangType::HasMusicbrainzId,
             "dup" => BangType::HasDuplicates,
+            "fake" => BangType::FakeLossless,
+            "note" => BangType::Note,
+            "isrc" => BangType::Isrc,
+            "enc" => BangType::Encoder,
+            "rel" => BangType::Related,
+            "x" => BangType::CustomField,
+            "err" => BangType::Failure,
             "ubf" => BangType::UpdatedBefore,
             "uaf" => BangType::UpdatedAfter,
             "!" => BangType::Grouping,
@@ -58,7 +70,9 @@ enum BangType {
     Artist,
     ArtistExact,
     Source,
+    Genre,
     Format,
+    Quality,
     BitrateLessThan,
     BitrateGreaterThan,
     DurationLessThan,
@@ -70,88 +84,115 @@ enum BangType {
     HasCoverArt,
     HasMusicbrainzId,
     HasDuplicates,
+    FakeLossless,
+    Note,
+    Isrc,
+    Encoder,
+    Related,
+    CustomField,
+    Failure,
     UpdatedBefore,
     UpdatedAfter,
     Grouping,
     Unknown(String),
 }
 
-/// Takes 3 tokens from the iterator,
-/// and returns the middle one.
-/// Intended to extract the argument token, will panic if
-/// mismatches.
-fn extract_argument(tokens: &mut Iter<Token>) -> Token {
-    let mut tokens = tokens.take(3);
-    let argument = tokens.nth(1).cloned().unwrap();
-    tokens.next(); // Advance past the ArgumentEnd.
-    argument
+/// A token, along with the byte offset in the query it was lexed from.
+pub type PositionedToken = (usize, Token);
+
+fn unexpected_token(token: &PositionedToken) -> Error {
+    Error::ParserUnexpectedToken(token.1.clone(), token.0)
+}
+
+/// Takes the next token from the iterator, and ensures it matches the expected token.
+fn expect_token(expected: &Token, tokens: &mut Iter<PositionedToken>) -> Result<()> {
+    match tokens.next() {
+        Some((_, token)) if token == expected => Ok(()),
+        Some(token) => Err(unexpected_token(token)),
+        None => Err(Error::LexerUnexpectedEndOfInput),
+    }
 }
 
-fn parse_bang<F, T>(producer: F, argument: Token) -> Result<Bang>
+/// Takes the sequence [ArgumentBegin, Argument, ArgumentEnd] from the iterator,
+/// and returns the argument.
+fn extract_argument(tokens: &mut Iter<PositionedToken>) -> Result<String> {
+    expect_token(&Token::ArgumentBegin, tokens)?;
+    let argument = match tokens.next() {
+        Some((_, Token::Argument(argument))) => argument.clone(),
+        Some(token) => return Err(unexpected_token(token)),
+        None => return Err(Error::LexerUnexpectedEndOfInput),
+    };
+    expect_token(&Token::ArgumentEnd, tokens)?;
+    Ok(argument)
+}
+
+fn parse_bang<F, T>(producer: F, argument: Result<String>) -> Result<Bang>
 where
     T: FromStr,
     F: Fn(T) -> Bang,
 {
-    if let Token::Argument(argument) = argument {
-        let parsed = argument.parse::<T>();
-        if let Ok(parsed) = parsed {
-            Ok(producer(parsed))
-        } else {
-            Err(Error::ParserInvalidInput(argument))
-        }
+    let argument = argument?;
+    let parsed = argument.parse::<T>();
+    if let Ok(parsed) = parsed {
+        Ok(producer(parsed))
     } else {
-        Err(Error::LexerUnexpectedEndOfInput)
+        Err(Error::ParserInvalidInput(argument))
     }
 }
 
-pub fn take_until_braces_balanced<'a, 'b>(tokens: &'a mut Iter<Token>) -> Result<Vec<Token>> {
-    let mut group = Vec::<Token>::new();
-    // Assume that we have an argument begin here.
-    if let Some(&Token::ArgumentBegin) = tokens.next() {
-        let mut counter = 1;
-        while let Some(token) = tokens.next().cloned() {
-            match token {
-                Token::ArgumentBegin => counter += 1,
-                Token::ArgumentEnd => counter -= 1,
-                _ => (),
-            };
-            if counter != 0 {
-                group.push(token);
-            };
-            if counter == 0 {
-                // We need to pad the grouping with the
-                // InputEnd token, since parse_token_stream
-                // expects an InputEnd at the end.
-                group.push(Token::InputEnd);
-                return Ok(group);
-            }
+pub fn take_until_braces_balanced(
+    tokens: &mut Iter<PositionedToken>,
+) -> Result<Vec<PositionedToken>> {
+    let mut group = Vec::<PositionedToken>::new();
+    // A grouping always begins with an argument begin.
+    expect_token(&Token::ArgumentBegin, tokens)?;
+    let mut counter = 1;
+    while let Some((position, token)) = tokens.next().cloned() {
+        match token {
+            Token::ArgumentBegin => counter += 1,
+            Token::ArgumentEnd => counter -= 1,
+            _ => (),
+        };
+        if counter != 0 {
+            group.push((position, token));
+        };
+        if counter == 0 {
+            // We need to pad the grouping with the
+            // InputEnd token, since parse_token_stream
+            // expects an InputEnd at the end.
+            // The grouping ends where its closing brace is.
+            group.push((position, Token::InputEnd));
+            return Ok(group);
         }
-        Err(Error::LexerUnexpectedEndOfInput)
-    } else {
-        panic!("Sent the wrong token!");
     }
+    Err(Error::LexerUnexpectedEndOfInput)
 }
 
-pub fn parse_token_stream(tokens: &mut Iter<Token>) -> Result<Bang> {
+/// Parses a token stream into a bang.
+///
+/// Every malformed sequence of tokens results in an error,
+/// with `Error::ParserUnexpectedToken` reporting where in the query
+/// the offending token was found.
+pub fn parse_token_stream(tokens: &mut Iter<PositionedToken>) -> Result<Bang> {
     // We're assuming that the slice begins at the
     // start of a token stream.
     // valid tokens at the beginning are either a bang prefix (!),
     // or the match all bang.
 
-    let opening_token = tokens.next().cloned();
+    let opening_token = tokens.next();
     match opening_token {
-        Some(Token::BangPrefix(_)) => (),
-        Some(Token::MatchAll) => return Ok(Bang::All),
-        Some(token) => return Err(Error::ParserUnexpectedToken(token)),
+        Some((_, Token::BangPrefix(_))) => (),
+        Some((_, Token::MatchAll)) => return Ok(Bang::All),
+        Some(token) => return Err(unexpected_token(token)),
         None => return Err(Error::LexerUnexpectedEndOfInput),
     }
 
     // At this point the opening_token is a bang prefix,
     // so the 2nd token must be a bang identifier.
 
-    let bang_ident = tokens.next().cloned();
+    let identifier_token = tokens.next();
 
-    let lhs = if let Some(Token::BangIdentifier(bang_ident)) = bang_ident {
+    let lhs = if let Some((_, Token::BangIdentifier(bang_ident))) = identifier_token {
         match bang_ident.as_bang_type() {
             // For all bangs that aren't groupings, we can just
             // assume that it follows the sequence
@@ -200,10 +241,18 @@ pub fn parse_token_stream(tokens: &mut Iter<Token>) -> Result<Bang> {
                 |search: String| Bang::Source(search),
                 extract_argument(tokens),
             ),
+            BangType::Genre => parse_bang(
+                |genre: String| Bang::Genre(genre),
+                extract_argument(tokens),
+            ),
             BangType::Format => parse_bang(
                 |format: TrackFileType| Bang::Format(format),
                 extract_argument(tokens),
             ),
+            BangType::Quality => parse_bang(
+                |quality: Quality| Bang::Quality(quality),
+                extract_argument(tokens),
+            ),
             BangType::DurationLessThan => parse_bang(
                 |duration: Duration| Bang::DurationLessThan(duration.to_ticks()),
                 extract_argument(tokens),
@@ -243,8 +292,43 @@ pub fn parse_token_stream(tokens: &mut Iter<Token>) -> Result<Bang> {
                 |mb: bool| Bang::HasMusicbrainzId(mb),
                 extract_argument(tokens),
             ),
-            BangType::HasDuplicates => parse_bang(
-                |dup: bool| Bang::HasDuplicates(dup),
+            BangType::HasDuplicates => {
+                // Anything but `true` or `false` names the columns tracks are compared by.
+                let argument = extract_argument(tokens)?;
+                match argument.parse::<bool>() {
+                    Ok(dup) => Ok(Bang::HasDuplicates(dup)),
+                    Err(_) => parse_bang(
+                        |criteria: DuplicateCriteria| Bang::DuplicatesBy(criteria),
+                        Ok(argument),
+                    ),
+                }
+            }
+            BangType::FakeLossless => parse_bang(
+                |fake: bool| Bang::FakeLossless(fake),
+                extract_argument(tokens),
+            ),
+            BangType::Note => parse_bang(
+                |search: String| Bang::Note(search),
+                extract_argument(tokens),
+            ),
+            BangType::Isrc => parse_bang(
+                |isrc: String| Bang::Isrc(isrc),
+                extract_argument(tokens),
+            ),
+            BangType::Encoder => parse_bang(
+                |search: String| Bang::Encoder(search),
+                extract_argument(tokens),
+            ),
+            BangType::Related => parse_bang(
+                |related: RelatedTo| Bang::Related(related),
+                extract_argument(tokens),
+            ),
+            BangType::CustomField => parse_bang(
+                |field: FieldMatch| Bang::CustomField(field),
+                extract_argument(tokens),
+            ),
+            BangType::Failure => parse_bang(
+                |search: String| Bang::Failure(search),
                 extract_argument(tokens),
             ),
             BangType::UpdatedBefore => parse_bang(
@@ -264,14 +348,16 @@ pub fn parse_token_stream(tokens: &mut Iter<Token>) -> Result<Bang> {
 
             BangType::Unknown(unknown) => return Err(Error::ParserUnknownBang(unknown)),
         }
+    } else if let Some(token) = identifier_token {
+        return Err(unexpected_token(token));
     } else {
         return Err(Error::LexerUnexpectedEndOfInput);
     };
 
     // At this point, three tokens minimum should have been consumed.
-    match tokens.next().cloned() {
-        Some(Token::InputEnd) => lhs,
-        Some(Token::LogicalOperator(operator)) => match operator {
+    match tokens.next() {
+        Some((_, Token::InputEnd)) => lhs,
+        Some((_, Token::LogicalOperator(operator))) => match operator {
             '|' => Ok(Bang::LogicalOr(
                 Box::new(lhs?),
                 Box::new(parse_token_stream(tokens)?),
@@ -282,7 +368,138 @@ pub fn parse_token_stream(tokens: &mut Iter<Token>) -> Result<Bang> {
             )),
             c => Err(Error::ParserUnknownBang(c.to_string())),
         },
-        Some(t) => Err(Error::ParserUnexpectedToken(t)),
+        Some(token) => Err(unexpected_token(token)),
         None => Err(Error::LexerUnexpectedEndOfInput),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Bang;
+    use crate::bangs::{DuplicateCriteria, DuplicateCriterion, FieldComparison, FieldMatch, RelatedTo, Relation};
+    use katatsuki::{Quality, TrackFileType};
+
+    fn parse(query: &str) -> Bang {
+        Bang::new(query).unwrap()
+    }
+
+    #[test]
+    fn parses_exact_searches() {
+        assert_eq!(parse("!T{Hello}"), Bang::TitleSearchExact("Hello".to_owned()));
+        assert_eq!(parse("!Q{Hello}"), Bang::FullTextSearchExact("Hello".to_owned()));
+    }
+
+    #[test]
+    fn parses_album_titles() {
+        assert_eq!(parse("!al{first}"), Bang::AlbumTitle("first".to_owned()));
+        assert_eq!(parse("!AL{First}"), Bang::AlbumTitleExact("First".to_owned()));
+    }
+
+    #[test]
+    fn parses_album_artists() {
+        assert_eq!(parse("!alar{delta}"), Bang::AlbumArtists("delta".to_owned()));
+        assert_eq!(parse("!ALAR{Delta}"), Bang::AlbumArtistsExact("Delta".to_owned()));
+    }
+
+    #[test]
+    fn parses_artists() {
+        assert_eq!(parse("!ar{alpha}"), Bang::Artist("alpha".to_owned()));
+        assert_eq!(parse("!AR{Alpha}"), Bang::ArtistExact("Alpha".to_owned()));
+    }
+
+    #[test]
+    fn parses_bitrates() {
+        assert_eq!(parse("!brlt{500}"), Bang::BitrateLessThan(500));
+        assert_eq!(parse("!brgt{950}"), Bang::BitrateGreaterThan(950));
+        assert!(Bang::new("!brlt{fast}").is_err());
+    }
+
+    #[test]
+    fn parses_cover_art_sizes() {
+        assert_eq!(parse("!cwlt{600}"), Bang::CoverArtWidthLessThan(600));
+        assert_eq!(parse("!cwgt{1000}"), Bang::CoverArtWidthGreaterThan(1000));
+        assert_eq!(parse("!chlt{300}"), Bang::CoverArtHeightLessThan(300));
+        assert_eq!(parse("!chgt{300}"), Bang::CoverArtHeightGreaterThan(300));
+        assert!(Bang::new("!cwgt{wide}").is_err());
+    }
+
+    #[test]
+    fn parses_has_cover_art() {
+        assert_eq!(parse("!c{true}"), Bang::HasCoverArt(true));
+        assert_eq!(parse("!c{false}"), Bang::HasCoverArt(false));
+        assert_eq!(parse("!c`"), Bang::HasCoverArt(true));
+        assert!(Bang::new("!c{maybe}").is_err());
+    }
+
+    #[test]
+    fn parses_has_musicbrainz_id() {
+        assert_eq!(parse("!mb{true}"), Bang::HasMusicbrainzId(true));
+        assert_eq!(parse("!mb{false}"), Bang::HasMusicbrainzId(false));
+        assert_eq!(parse("!mb`"), Bang::HasMusicbrainzId(true));
+    }
+
+    #[test]
+    fn parses_duplicates() {
+        assert_eq!(parse("!dup{true}"), Bang::HasDuplicates(true));
+        assert_eq!(parse("!dup{false}"), Bang::HasDuplicates(false));
+        assert_eq!(parse("!dup`"), Bang::HasDuplicates(true));
+    }
+
+    #[test]
+    fn parses_true_tick_before_operators() {
+        assert_eq!(
+            parse("!c` & !mb{false}"),
+            Bang::LogicalAnd(Box::new(Bang::HasCoverArt(true)), Box::new(Bang::HasMusicbrainzId(false)))
+        );
+    }
+
+    #[test]
+    fn parses_the_queries_bangs_are_written_as() {
+        let and = |lhs: Bang, rhs: Bang| Bang::LogicalAnd(Box::new(lhs), Box::new(rhs));
+        let or = |lhs: Bang, rhs: Bang| Bang::LogicalOr(Box::new(lhs), Box::new(rhs));
+        let bangs = vec![
+            Bang::TitleSearch("Hello".to_owned()),
+            Bang::TitleSearchExact("Hello {World}".to_owned()),
+            Bang::FullTextSearch("back\\slash".to_owned()),
+            Bang::ArtistExact("}{".to_owned()),
+            Bang::AlbumArtists("しんさんと".to_owned()),
+            Bang::Genre("electronic/house/*".to_owned()),
+            Bang::Format(TrackFileType::FLAC24),
+            Bang::Format(TrackFileType::MonkeysAudio),
+            Bang::Quality(Quality::ALL[0]),
+            Bang::BitrateLessThan(-320),
+            Bang::CoverArtHeightGreaterThan(1200),
+            Bang::DurationLessThan(0),
+            // Three and a half minutes, with a tick left over.
+            Bang::DurationGreaterThan(2_100_000_001),
+            Bang::HasCoverArt(false),
+            Bang::HasDuplicates(true),
+            Bang::DuplicatesBy(DuplicateCriteria(vec![DuplicateCriterion::Fingerprint, DuplicateCriterion::Title])),
+            Bang::FakeLossless(true),
+            Bang::Isrc("US-RC1-76-07839".to_owned()),
+            Bang::Related(RelatedTo {
+                relation: Relation::CoverOf,
+                track_id: Some("6f8ab2b0-5c7e-4d5b-9a0e-2f1d0b8c7a11".to_owned()),
+            }),
+            Bang::Related(RelatedTo { relation: Relation::RemixOf, track_id: None }),
+            Bang::CustomField(FieldMatch {
+                field: "energy".to_owned(),
+                comparison: FieldComparison::GreaterThan,
+                value: "5".to_owned(),
+            }),
+            Bang::UpdatedBefore("2020-02-29".to_owned()),
+            and(Bang::HasMusicbrainzId(true), Bang::Note("live".to_owned())),
+            // The left hand side of an operator is only ever a group, as operators are parsed.
+            or(
+                Bang::Grouping(Box::new(and(Bang::Artist("a".to_owned()), Bang::AlbumTitle("b".to_owned())))),
+                Bang::Source("c".to_owned()),
+            ),
+            and(Bang::Encoder("LAME".to_owned()), or(Bang::Failure("".to_owned()), Bang::UpdatedAfter("2001-01-01".to_owned()))),
+            Bang::Grouping(Box::new(or(Bang::AlbumTitleExact("x".to_owned()), Bang::AlbumArtistsExact("y".to_owned())))),
+        ];
+        for bang in bangs {
+            let query = bang.to_query().unwrap();
+            assert_eq!(Bang::new(&query).ok(), Some(bang), "query was {:?}", query);
+        }
+    }
+}
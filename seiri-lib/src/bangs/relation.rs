@@ -0,0 +1,81 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// How a track is related to another, as an alternate version of it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Relation {
+    RemixOf,
+    LiveVersionOf,
+    CoverOf,
+}
+
+impl Relation {
+    pub const ALL: [Relation; 3] = [Relation::RemixOf, Relation::LiveVersionOf, Relation::CoverOf];
+
+    /// The name of the relation as accepted by the `!rel` bang, such as `remixof`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Relation::RemixOf => "remixof",
+            Relation::LiveVersionOf => "liveof",
+            Relation::CoverOf => "coverof",
+        }
+    }
+
+    /// The code the relation is stored as in the library.
+    pub fn to_i32(self) -> i32 {
+        match self {
+            Relation::RemixOf => 0,
+            Relation::LiveVersionOf => 1,
+            Relation::CoverOf => 2,
+        }
+    }
+
+    pub fn from_i32(code: i32) -> Option<Relation> {
+        Relation::ALL.iter().copied().find(|relation| relation.to_i32() == code)
+    }
+}
+
+impl FromStr for Relation {
+    type Err = ();
+
+    fn from_str(name: &str) -> Result<Relation, ()> {
+        let name = name.trim().to_lowercase();
+        Relation::ALL
+            .iter()
+            .copied()
+            .find(|relation| relation.name() == name)
+            .ok_or(())
+    }
+}
+
+/// The argument of the `!rel` bang, matching tracks that are related to the track with the
+/// given UUID, such as `remixof:<UUID>`, or to any track if there is no UUID, such as `remixof`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelatedTo {
+    pub relation: Relation,
+    pub track_id: Option<String>,
+}
+
+impl FromStr for RelatedTo {
+    type Err = ();
+
+    fn from_str(argument: &str) -> Result<RelatedTo, ()> {
+        let (relation, track_id) = match argument.split_once(':') {
+            Some((relation, track_id)) => (relation, Some(track_id.trim())),
+            None => (argument, None),
+        };
+        Ok(RelatedTo {
+            relation: relation.parse()?,
+            track_id: track_id.filter(|track_id| !track_id.is_empty()).map(str::to_owned),
+        })
+    }
+}
+
+impl fmt::Display for RelatedTo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.track_id {
+            Some(track_id) => write!(f, "{}:{}", self.relation.name(), track_id),
+            None => write!(f, "{}", self.relation.name()),
+        }
+    }
+}
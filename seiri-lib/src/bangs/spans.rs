@@ -0,0 +1,193 @@
+use super::lexer::{lex_bangs, lex_title, Token};
+
+/// The category of a span of a query, for syntax highlighting.
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum TokenCategory {
+    /// The `!` prefix and the identifier of a bang, such as `!al`.
+    BangName,
+
+    /// The braces surrounding a bang argument, or the true tick.
+    Delimiter,
+
+    /// The content of a bang argument, or a bang-less title search.
+    Argument,
+
+    /// A logical `&` or `|` between two bangs.
+    Operator,
+
+    /// The part of the query that failed to lex.
+    Error,
+}
+
+/// A token of a query along with its location.
+///
+/// `start` and `end` are byte offsets into the query, so the text of the
+/// token is always `&query[start..end]`.
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct TokenSpan {
+    pub start: usize,
+    pub end: usize,
+    pub category: TokenCategory,
+}
+
+struct SpanBuilder<'a> {
+    query: &'a str,
+    position: usize,
+    spans: Vec<TokenSpan>,
+    /// The offset of each token aligned so far.
+    offsets: Vec<usize>,
+}
+
+impl<'a> SpanBuilder<'a> {
+    fn new(query: &'a str) -> SpanBuilder<'a> {
+        SpanBuilder {
+            query,
+            position: 0,
+            spans: Vec::new(),
+            offsets: Vec::new(),
+        }
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.query[self.position..]
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.position += rest.len() - rest.trim_start().len();
+    }
+
+    fn push(&mut self, length: usize, category: TokenCategory) {
+        if length == 0 {
+            return;
+        }
+        let start = self.position;
+        self.position += length;
+        match self.spans.last_mut() {
+            // Merge the prefix and identifier into a single bang name.
+            Some(last)
+                if category == TokenCategory::BangName
+                    && last.category == category
+                    && last.end == start =>
+            {
+                last.end = self.position
+            }
+            _ => self.spans.push(TokenSpan {
+                start,
+                end: self.position,
+                category,
+            }),
+        }
+    }
+
+    /// Consumes the given characters, returning false if the query does not continue with them.
+    fn expect(&mut self, expected: &str, category: TokenCategory) -> bool {
+        self.skip_whitespace();
+        if self.rest().starts_with(expected) {
+            self.push(expected.len(), category);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Consumes the raw text of an argument, which may contain escapes.
+    fn expect_argument(&mut self, argument: &str) -> bool {
+        let mut length = 0;
+        let mut raw = self.rest().chars();
+        for expected in argument.chars() {
+            let mut c = raw.next();
+            if c == Some('\\') {
+                length += 1;
+                c = raw.next();
+            }
+            match c {
+                Some(c) if c == expected => length += c.len_utf8(),
+                _ => return false,
+            }
+        }
+        self.push(length, TokenCategory::Argument);
+        true
+    }
+
+    /// Finds the location of each token in the query, stopping at the first token
+    /// that can not be found.
+    fn align(&mut self, tokens: &[Token]) -> bool {
+        let mut tokens = tokens.iter();
+        while let Some(token) = tokens.next() {
+            // Whitespace is only significant within arguments.
+            if !matches!(token, Token::Argument(_)) {
+                self.skip_whitespace();
+            }
+            self.offsets.push(self.position);
+            let found = match token {
+                Token::BangPrefix(c) => self.expect(&c.to_string(), TokenCategory::BangName),
+                Token::BangIdentifier(ident) => self.expect(ident, TokenCategory::BangName),
+                Token::ArgumentBegin => {
+                    if self.expect("`", TokenCategory::Delimiter) {
+                        // The true tick expands to [ArgumentBegin, Argument, ArgumentEnd].
+                        tokens.next();
+                        tokens.next();
+                        self.offsets.push(self.position - 1);
+                        self.offsets.push(self.position - 1);
+                        true
+                    } else {
+                        self.expect("{", TokenCategory::Delimiter)
+                    }
+                }
+                Token::Argument(argument) => self.expect_argument(argument),
+                Token::ArgumentEnd => self.expect("}", TokenCategory::Delimiter),
+                Token::LogicalOperator(c) => self.expect(&c.to_string(), TokenCategory::Operator),
+                Token::MatchAll | Token::InputEnd | Token::PreprocessTokenExpand(_) => true,
+            };
+            if !found {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Lexes the query, and returns the location and category of each of its tokens.
+///
+/// Unlike `lex_query`, this does not fail on invalid queries. Instead, the part
+/// of the query after the last valid token is returned as a single error span.
+/// Whitespace between tokens is not part of any span.
+pub fn tokenize_with_spans(query: &str) -> Vec<TokenSpan> {
+    let mut builder = SpanBuilder::new(query);
+
+    if query.is_empty() {
+        return builder.spans;
+    }
+
+    if lex_title(query).is_some() {
+        builder.push(query.len(), TokenCategory::Argument);
+        return builder.spans;
+    }
+
+    let (tokens, result) = lex_bangs(query);
+    if !builder.align(&tokens) || result.is_err() {
+        builder.skip_whitespace();
+        if !builder.rest().is_empty() {
+            builder.push(builder.rest().len(), TokenCategory::Error);
+        } else if let Some(last) = builder.spans.last_mut() {
+            // The query ended early, so the last token is where it went wrong.
+            last.category = TokenCategory::Error;
+        }
+    }
+    builder.spans
+}
+
+/// Finds the byte offset in the query that each token of a successfully lexed query starts at.
+///
+/// Tokens produced by desugaring a title search all start at the beginning of the query.
+pub(super) fn token_offsets(query: &str, tokens: &[Token]) -> Vec<usize> {
+    let mut builder = SpanBuilder::new(query);
+    if lex_title(query).is_none() {
+        builder.align(tokens);
+    }
+    builder.offsets.truncate(tokens.len());
+    let position = builder.position;
+    builder.offsets.resize(tokens.len(), position);
+    builder.offsets
+}
@@ -4,8 +4,9 @@
 
 //https://msdn.microsoft.com/en-us/library/system.timespan.ticks(v=vs.110).aspx
 const TICKS_PER_MS: i64 = 10000;
-const NS_PER_TICK: i64 = 100;
+pub const NS_PER_TICK: i64 = 100;
 const SEC_PER_MS: i64 = 1000;
+pub const TICKS_PER_SEC: i64 = TICKS_PER_MS * SEC_PER_MS;
 use humantime::Duration;
 
 pub fn ticks_to_ms(ticks: i64) -> i32 {
@@ -0,0 +1,58 @@
+//! Lists of the artists and albums in the library, for browsing it rather than searching it.
+//!
+//! Names are ordered by `database::BROWSE_COLLATION`, by the configured locale with the
+//! `collation` feature, with `Various Artists` last as tracks are ordered.
+
+use crate::database::{album_artists_exact_pattern, BROWSE_COLLATION};
+use rusqlite::types::ToSql;
+use rusqlite::{Connection, Result};
+
+/// An album in the library.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BrowseAlbum {
+    pub title: String,
+    pub album_artists: Vec<String>,
+    /// The earliest year of the tracks of the album, or 0 if none of them has one.
+    pub year: i32,
+    pub tracks: usize,
+}
+
+fn split_album_artists(album_artists: String) -> Vec<String> {
+    album_artists.split(';').map(|artist| artist.to_owned()).collect()
+}
+
+/// Gets the album artists of every album in the library, as they are credited together.
+pub fn get_album_artists(conn: &Connection) -> Result<Vec<Vec<String>>> {
+    let mut statement = conn.prepare(&format!(
+        "SELECT DISTINCT AlbumArtists FROM tracks
+        ORDER BY CASE WHEN AlbumArtists = 'Various Artists' THEN 1 END, AlbumArtists COLLATE {}",
+        BROWSE_COLLATION
+    ))?;
+    let rows = statement.query_map(rusqlite::NO_PARAMS, |row| row.get(0).map(split_album_artists))?;
+    rows.collect()
+}
+
+/// Gets every album in the library, or only those the given artist is an album artist of,
+/// ordered by their album artists and then their titles.
+pub fn get_albums(album_artist: Option<&str>, conn: &Connection) -> Result<Vec<BrowseAlbum>> {
+    let pattern = album_artist.map(album_artists_exact_pattern);
+    let mut statement = conn.prepare(&format!(
+        "SELECT Album, AlbumArtists, IFNULL(MIN(NULLIF(Year, 0)), 0), COUNT(*) FROM tracks
+        {}
+        GROUP BY AlbumArtists, Album
+        ORDER BY CASE WHEN AlbumArtists = 'Various Artists' THEN 1 END, AlbumArtists COLLATE {collation},
+            Album COLLATE {collation}",
+        if pattern.is_some() { "WHERE AlbumArtists REGEXP ?1" } else { "" },
+        collation = BROWSE_COLLATION
+    ))?;
+    let params = pattern.iter().map(|pattern| pattern as &dyn ToSql).collect::<Vec<&dyn ToSql>>();
+    let rows = statement.query_map(&params, |row| {
+        Ok(BrowseAlbum {
+            title: row.get(0)?,
+            album_artists: row.get(1).map(split_album_artists)?,
+            year: row.get(2)?,
+            tracks: row.get::<_, i64>(3)? as usize,
+        })
+    })?;
+    rows.collect()
+}
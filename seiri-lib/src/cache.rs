@@ -0,0 +1,100 @@
+//! An in-memory cache of query results.
+//!
+//! Queries are keyed by their canonical query string, so `hello`, `!q{hello}`
+//! and `!q{hello} ` all share a single entry. The whole cache is dropped as soon
+//! as the change log shows the library has been modified since the results were read.
+
+use crate::bangs::Bang;
+use crate::database;
+use crate::database::Connection;
+use katatsuki::Track;
+use rusqlite::Result;
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+/// The number of queries a cache created with `QueryCache::default` holds.
+pub const DEFAULT_CAPACITY: usize = 32;
+
+struct CacheEntry {
+    query: String,
+    limit: Option<i32>,
+    offset: Option<i32>,
+    tracks: Arc<Vec<Track>>,
+}
+
+/// A least recently used cache of query results.
+pub struct QueryCache {
+    capacity: usize,
+    /// The change version of the library the cached results were read at.
+    version: Option<i64>,
+    /// Cached results, with the most recently used first.
+    entries: VecDeque<CacheEntry>,
+}
+
+impl QueryCache {
+    pub const fn new(capacity: usize) -> QueryCache {
+        QueryCache {
+            capacity,
+            version: None,
+            entries: VecDeque::new(),
+        }
+    }
+
+    /// Drops every cached result.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.version = None;
+    }
+
+    /// Drops every cached result if the library has changed since they were read.
+    fn invalidate(&mut self, conn: &Connection) -> Result<()> {
+        let version = database::get_change_version(conn)?;
+        if self.version != Some(version) {
+            self.entries.clear();
+            self.version = Some(version);
+        }
+        Ok(())
+    }
+
+    /// Runs the query, returning the cached results if the same query was run
+    /// since the library last changed.
+    pub fn query(
+        &mut self,
+        bang: Bang,
+        conn: &Connection,
+        limit: Option<i32>,
+        offset: Option<i32>,
+    ) -> Result<Arc<Vec<Track>>> {
+        self.invalidate(conn)?;
+        let query = match bang.to_query() {
+            Some(query) if self.capacity > 0 => query,
+            _ => return database::query_tracks(bang, conn, limit, offset).map(Arc::new),
+        };
+
+        let cached = self
+            .entries
+            .iter()
+            .position(|e| e.query == query && e.limit == limit && e.offset == offset);
+        if let Some(entry) = cached.and_then(|index| self.entries.remove(index)) {
+            let tracks = Arc::clone(&entry.tracks);
+            self.entries.push_front(entry);
+            return Ok(tracks);
+        }
+
+        let tracks = Arc::new(database::query_tracks(bang, conn, limit, offset)?);
+        self.entries.push_front(CacheEntry {
+            query,
+            limit,
+            offset,
+            tracks: Arc::clone(&tracks),
+        });
+        self.entries.truncate(self.capacity);
+        Ok(tracks)
+    }
+}
+
+impl Default for QueryCache {
+    fn default() -> QueryCache {
+        QueryCache::new(DEFAULT_CAPACITY)
+    }
+}
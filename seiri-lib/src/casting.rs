@@ -0,0 +1,368 @@
+//! Casting of tracks to Chromecast and AirPlay targets on the local network.
+//!
+//! Targets are discovered by their mDNS announcements. Tracks are not sent to a target, but
+//! streamed from a small HTTP server the target is pointed at, which serves only the tracks
+//! being cast, each under a random token, since targets can not be given a bearer token.
+//! Nothing else in the library is exposed by it. The server listens on every interface on `CastingConfig::stream_port`, and is advertised to
+//! targets at the address of the interface that reaches them, unless `stream_address` is set.
+//!
+//! Chromecast targets are sent the whole of a queue, and play it through in order. AirPlay
+//! targets are only sent the first track of a queue, since AirPlay has no queue of its own, and
+//! only those that accept media by URL, such as Apple TVs, can be cast to.
+
+use crate::auth::generate_token;
+use crate::config::CastingConfig;
+use crate::error::{Error, Result};
+use crate::http::{content_type, read_request, send_file, send_status};
+use katatsuki::Track;
+use mdns_sd::{ServiceDaemon, ServiceEvent};
+use rust_cast::channels::media::{
+    Media, MediaQueue, Metadata, MusicTrackMediaMetadata, QueueItem, QueueType, StreamType,
+};
+use rust_cast::channels::receiver::CastDeviceApp;
+use rust_cast::CastDevice;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io;
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const CHROMECAST_SERVICE: &str = "_googlecast._tcp.local.";
+const AIRPLAY_SERVICE: &str = "_airplay._tcp.local.";
+/// The Cast receiver every Chromecast target can play media URLs with.
+const DEFAULT_MEDIA_RECEIVER_ID: &str = "CC1AD845";
+const RECEIVER_ID: &str = "receiver-0";
+/// How long to wait for a target, or for a target to read from the stream server.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The protocol a target is cast to with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CastKind {
+    Chromecast,
+    AirPlay,
+}
+
+impl CastKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CastKind::Chromecast => "chromecast",
+            CastKind::AirPlay => "airplay",
+        }
+    }
+}
+
+/// A device on the local network tracks can be cast to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CastTarget {
+    /// The name the device announces itself by, such as `Living Room TV`.
+    pub name: String,
+    pub kind: CastKind,
+    pub address: IpAddr,
+    pub port: u16,
+}
+
+/// Gets the part of the full name of an mDNS service instance before its service type.
+fn instance_name(fullname: &str, service: &str) -> String {
+    fullname
+        .strip_suffix(service)
+        .unwrap_or(fullname)
+        .trim_end_matches('.')
+        .to_owned()
+}
+
+/// Discovers the targets on the local network that announce themselves within the timeout,
+/// ordered by name.
+pub fn discover(timeout: Duration) -> Result<Vec<CastTarget>> {
+    let failed = || Error::CastFailed("mDNS".to_owned());
+    let daemon = ServiceDaemon::new().map_err(|_| failed())?;
+    let browsers = [
+        (CastKind::Chromecast, CHROMECAST_SERVICE, daemon.browse(CHROMECAST_SERVICE).map_err(|_| failed())?),
+        (CastKind::AirPlay, AIRPLAY_SERVICE, daemon.browse(AIRPLAY_SERVICE).map_err(|_| failed())?),
+    ];
+    let deadline = Instant::now() + timeout;
+    let mut targets = HashMap::new();
+    while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+        // Both browsers are polled in turn, so neither waits out the whole timeout alone.
+        let slice = remaining.min(Duration::from_millis(100));
+        for (kind, service, browser) in &browsers {
+            while let Ok(event) = browser.recv_timeout(slice) {
+                let info = match event {
+                    ServiceEvent::ServiceResolved(info) => info,
+                    _ => continue,
+                };
+                let address = match info.get_addresses().iter().next() {
+                    Some(address) => *address,
+                    None => continue,
+                };
+                // Chromecasts announce a random instance name, and their friendly name separately.
+                let name = info
+                    .get_property_val_str("fn")
+                    .filter(|_| *kind == CastKind::Chromecast)
+                    .map(str::to_owned)
+                    .unwrap_or_else(|| instance_name(info.get_fullname(), service));
+                targets.insert(
+                    info.get_fullname().to_owned(),
+                    CastTarget {
+                        name,
+                        kind: *kind,
+                        address,
+                        port: info.get_port(),
+                    },
+                );
+            }
+        }
+    }
+    daemon.shutdown().ok();
+    let mut targets = targets.into_values().collect::<Vec<CastTarget>>();
+    targets.sort_by_key(|target| target.name.to_lowercase());
+    Ok(targets)
+}
+
+/// Gets the target of the name, ignoring case.
+pub fn find_target<'a>(targets: &'a [CastTarget], name: &str) -> Option<&'a CastTarget> {
+    targets.iter().find(|target| target.name.eq_ignore_ascii_case(name.trim()))
+}
+
+/// The files being streamed, by the token each is served under.
+type Streams = Arc<Mutex<HashMap<String, PathBuf>>>;
+
+/// Serves a single request for a stream, of the form `GET /stream/<token>`.
+fn handle_stream_request(mut stream: TcpStream, streams: &Streams) -> io::Result<()> {
+    let request = read_request(&stream)?;
+    let path = request
+        .target
+        .strip_prefix("/stream/")
+        .and_then(|token| streams.lock().unwrap().get(token).cloned());
+    match (path, request.method.as_str()) {
+        (Some(path), "GET" | "HEAD") => send_file(&mut stream, &request, &path),
+        _ => send_status(&mut stream, "404 Not Found"),
+    }
+}
+
+/// Casts tracks to targets, keeping the stream server the targets play from.
+pub struct Caster {
+    config: CastingConfig,
+    streams: Streams,
+    listening: bool,
+    /// The target last cast to, which playback is controlled on.
+    target: Option<CastTarget>,
+}
+
+/// A change to the playback of the target last cast to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CastControl {
+    Play,
+    Pause,
+    Stop,
+}
+
+impl Caster {
+    pub fn new(config: &CastingConfig) -> Caster {
+        Caster {
+            config: config.clone(),
+            streams: Arc::new(Mutex::new(HashMap::new())),
+            listening: false,
+            target: None,
+        }
+    }
+
+    /// The target last cast to, unless it was stopped since.
+    pub fn target(&self) -> Option<&CastTarget> {
+        self.target.as_ref()
+    }
+
+    /// Starts the stream server, if it was not started already.
+    fn listen(&mut self) -> Result<()> {
+        if self.listening {
+            return Ok(());
+        }
+        let address = SocketAddr::from(([0, 0, 0, 0], self.config.stream_port));
+        let listener = TcpListener::bind(address).map_err(|_| Error::CastFailed(address.to_string()))?;
+        let streams = Arc::clone(&self.streams);
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let streams = Arc::clone(&streams);
+                // Targets read ahead and seek with requests of their own, so each is served apart.
+                thread::spawn(move || handle_stream_request(stream, &streams).ok());
+            }
+        });
+        self.listening = true;
+        Ok(())
+    }
+
+    /// Gets the address the target can reach the stream server at.
+    fn stream_address(&self, target: &CastTarget) -> Option<String> {
+        if let Some(address) = &self.config.stream_address {
+            return Some(address.to_owned());
+        }
+        // Connecting a UDP socket sends nothing, but picks the interface that routes to the target.
+        let unspecified: IpAddr = if target.address.is_ipv4() {
+            [0, 0, 0, 0].into()
+        } else {
+            [0u16; 8].into()
+        };
+        let socket = UdpSocket::bind((unspecified, 0)).ok()?;
+        socket.connect((target.address, target.port)).ok()?;
+        match socket.local_addr().ok()?.ip() {
+            IpAddr::V6(address) => Some(format!("[{}]", address)),
+            address => Some(address.to_string()),
+        }
+    }
+
+    /// Serves the tracks under new tokens in place of every track cast before, returning the
+    /// URL each is served at.
+    fn serve_tracks(&mut self, target: &CastTarget, tracks: &[Track]) -> Result<Vec<String>> {
+        self.listen()?;
+        let address = self
+            .stream_address(target)
+            .ok_or_else(|| Error::CastFailed(target.name.to_owned()))?;
+        let mut streams = self.streams.lock().unwrap();
+        streams.clear();
+        Ok(tracks
+            .iter()
+            .map(|track| {
+                let token = generate_token();
+                streams.insert(token.clone(), track.file_path.to_owned());
+                format!("http://{}:{}/stream/{}", address, self.config.stream_port, token)
+            })
+            .collect())
+    }
+
+    /// Casts the tracks to the target in order, replacing whatever the target was playing.
+    ///
+    /// Fails if there are no tracks, the stream server could not be started, or the target
+    /// could not be reached or refused the tracks.
+    pub fn cast(&mut self, target: &CastTarget, tracks: &[Track]) -> Result<()> {
+        let failed = || Error::CastFailed(target.name.to_owned());
+        if tracks.is_empty() {
+            return Err(failed());
+        }
+        let urls = self.serve_tracks(target, tracks)?;
+        let cast = match target.kind {
+            CastKind::Chromecast => cast_to_chromecast(target, tracks, &urls),
+            CastKind::AirPlay => cast_to_airplay(target, &urls[0]),
+        };
+        if cast.is_none() {
+            self.streams.lock().unwrap().clear();
+            return Err(failed());
+        }
+        self.target = Some(target.clone());
+        Ok(())
+    }
+
+    /// Changes the playback of the target last cast to. Stopping also stops serving its tracks.
+    ///
+    /// Fails if nothing was cast, or the target could not be reached.
+    pub fn control(&mut self, control: CastControl) -> Result<()> {
+        let target = self.target.clone().ok_or_else(|| Error::CastFailed(String::new()))?;
+        let controlled = match target.kind {
+            CastKind::Chromecast => control_chromecast(&target, control),
+            CastKind::AirPlay => control_airplay(&target, control),
+        };
+        if control == CastControl::Stop {
+            self.streams.lock().unwrap().clear();
+            self.target = None;
+        }
+        controlled.ok_or(Error::CastFailed(target.name))
+    }
+}
+
+fn chromecast_media(track: &Track, url: &str) -> Media {
+    Media {
+        content_id: url.to_owned(),
+        stream_type: StreamType::Buffered,
+        content_type: content_type(&track.file_path).to_owned(),
+        metadata: Some(Metadata::MusicTrack(MusicTrackMediaMetadata {
+            title: Some(track.title.to_owned()),
+            artist: Some(track.artist.to_owned()),
+            album_name: Some(track.album.to_owned()),
+            album_artist: track.album_artists.first().cloned(),
+            track_number: u32::try_from(track.track_number).ok().filter(|number| *number > 0),
+            disc_number: u32::try_from(track.disc_number).ok().filter(|number| *number > 0),
+            ..MusicTrackMediaMetadata::default()
+        })),
+        duration: Some(track.duration as f32 / 1000.0),
+    }
+}
+
+fn connect_chromecast<'a>(target: &CastTarget) -> Option<CastDevice<'a>> {
+    let device = CastDevice::connect_without_host_verification(target.address.to_string(), target.port).ok()?;
+    device.connection.connect(RECEIVER_ID).ok()?;
+    Some(device)
+}
+
+fn cast_to_chromecast(target: &CastTarget, tracks: &[Track], urls: &[String]) -> Option<()> {
+    let device = connect_chromecast(target)?;
+    let app = device.receiver.launch_app(&CastDeviceApp::DefaultMediaReceiver).ok()?;
+    device.connection.connect(app.transport_id.as_str()).ok()?;
+    let mut media = tracks.iter().zip(urls).map(|(track, url)| chromecast_media(track, url));
+    if tracks.len() == 1 {
+        device
+            .media
+            .load(app.transport_id.as_str(), app.session_id.as_str(), &media.next()?)
+            .ok()?;
+    } else {
+        let queue = MediaQueue {
+            items: media.map(|media| QueueItem { media }).collect(),
+            start_index: 0,
+            queue_type: QueueType::Playlist,
+        };
+        device
+            .media
+            .load_queue(app.transport_id.as_str(), app.session_id.as_str(), &queue)
+            .ok()?;
+    }
+    Some(())
+}
+
+fn control_chromecast(target: &CastTarget, control: CastControl) -> Option<()> {
+    let device = connect_chromecast(target)?;
+    let status = device.receiver.get_status().ok()?;
+    let app = status
+        .applications
+        .iter()
+        .find(|app| app.app_id == DEFAULT_MEDIA_RECEIVER_ID)?;
+    if control == CastControl::Stop {
+        return device.receiver.stop_app(app.session_id.as_str()).ok();
+    }
+    device.connection.connect(app.transport_id.as_str()).ok()?;
+    let status = device.media.get_status(app.transport_id.as_str(), None).ok()?;
+    for entry in status.entries {
+        let controlled = match control {
+            CastControl::Play => device.media.play(app.transport_id.as_str(), entry.media_session_id),
+            _ => device.media.pause(app.transport_id.as_str(), entry.media_session_id),
+        };
+        controlled.ok()?;
+    }
+    Some(())
+}
+
+fn post_to_airplay(target: &CastTarget, path: &str, content_type: &str, body: &str) -> Option<()> {
+    let address = match target.address {
+        IpAddr::V6(address) => format!("[{}]", address),
+        address => address.to_string(),
+    };
+    ureq::post(&format!("http://{}:{}{}", address, target.port, path))
+        .timeout(TIMEOUT)
+        .set("Content-Type", content_type)
+        .send_string(body)
+        .ok()
+        .map(|_| ())
+}
+
+fn cast_to_airplay(target: &CastTarget, url: &str) -> Option<()> {
+    let body = format!("Content-Location: {}\nStart-Position: 0\n", url);
+    post_to_airplay(target, "/play", "text/parameters", &body)
+}
+
+fn control_airplay(target: &CastTarget, control: CastControl) -> Option<()> {
+    let path = match control {
+        CastControl::Play => "/rate?value=1.000000",
+        CastControl::Pause => "/rate?value=0.000000",
+        CastControl::Stop => "/stop",
+    };
+    post_to_airplay(target, path, "text/parameters", "")
+}
@@ -0,0 +1,223 @@
+//! A static catalog of the library, for sharing a listing of the collection
+//! without exposing seiri itself.
+//!
+//! The catalog lists every album artist, their albums, and the tracks of each album.
+//! It leaves out the paths of files, so nothing about where the library is kept is shared.
+//! A catalog is exported either as a single JSON file, or as a static site made of an
+//! `index.html` page, the same `catalog.json`, and an `art` folder of album art thumbnails.
+
+use crate::database::{track_from_row, Connection, BROWSE_COLLATION, TRACK_COLUMNS};
+use chrono::Local;
+use image::imageops::FilterType;
+use image::ImageFormat;
+use katatsuki::Track;
+use rusqlite::NO_PARAMS;
+use serde_derive::Serialize;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The width and height album art thumbnails are scaled to fit within.
+pub const THUMBNAIL_SIZE: u32 = 256;
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Catalog {
+    /// The date the catalog was generated.
+    pub generated: String,
+    pub artists: Vec<CatalogArtist>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct CatalogArtist {
+    pub name: String,
+    pub albums: Vec<CatalogAlbum>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct CatalogAlbum {
+    pub title: String,
+    pub year: i32,
+    /// The path of the art thumbnail of the album, relative to the catalog.
+    /// This is only set in catalogs exported as a site.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub art: Option<String>,
+    pub tracks: Vec<CatalogTrack>,
+    /// A track of the album with a front cover, which art thumbnails are made from.
+    #[serde(skip)]
+    cover_track: Option<Track>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct CatalogTrack {
+    pub disc_number: i32,
+    pub track_number: i32,
+    pub title: String,
+    pub artist: String,
+    /// The duration of the track, in milliseconds.
+    pub duration: i32,
+    /// The name of the quality of the track, such as `lossless`.
+    pub quality: Option<&'static str>,
+}
+
+impl CatalogTrack {
+    fn from_track(track: &Track) -> CatalogTrack {
+        CatalogTrack {
+            disc_number: track.disc_number,
+            track_number: track.track_number,
+            title: track.title.to_owned(),
+            artist: track.artist.to_owned(),
+            duration: track.duration,
+            quality: track.quality().map(|quality| quality.name()),
+        }
+    }
+}
+
+/// Builds the catalog of every track in the library, with artists in alphabetical order,
+/// their albums in order of release, and tracks in the order they appear on the album.
+pub fn build_catalog(conn: &Connection) -> rusqlite::Result<Catalog> {
+    let mut statement = conn.prepare(&format!(
+        "SELECT {} FROM tracks
+        ORDER BY CASE WHEN AlbumArtists = 'Various Artists' THEN 1 END, AlbumArtists COLLATE {collation},
+            Year, Album COLLATE {collation}, DiscNumber, TrackNumber",
+        TRACK_COLUMNS,
+        collation = BROWSE_COLLATION
+    ))?;
+    let mut rows = statement.query(NO_PARAMS)?;
+    let mut artists = Vec::<CatalogArtist>::new();
+    while let Some(row) = rows.next()? {
+        let track = track_from_row(row)?;
+        let name = track.album_artists.join("; ");
+        if artists.last().is_none_or(|artist| artist.name != name) {
+            artists.push(CatalogArtist {
+                name,
+                albums: Vec::new(),
+            });
+        }
+        let albums = &mut artists.last_mut().unwrap().albums;
+        if albums.last().is_none_or(|album| album.title != track.album) {
+            albums.push(CatalogAlbum {
+                title: track.album.to_owned(),
+                year: track.year,
+                art: None,
+                tracks: Vec::new(),
+                cover_track: None,
+            });
+        }
+        let album = albums.last_mut().unwrap();
+        album.tracks.push(CatalogTrack::from_track(&track));
+        if album.cover_track.is_none() && track.has_front_cover {
+            album.cover_track = Some(track);
+        }
+    }
+    Ok(Catalog {
+        generated: Local::now().format("%Y-%m-%d").to_string(),
+        artists,
+    })
+}
+
+/// Writes the catalog to a single JSON file at the given path, overwriting any file there.
+pub fn export_json(catalog: &Catalog, json_path: &Path) -> io::Result<()> {
+    let json = serde_json::to_string_pretty(catalog).map_err(io::Error::other)?;
+    fs::write(json_path, json)
+}
+
+/// Writes a thumbnail of the front cover of the track, scaled to fit `THUMBNAIL_SIZE`, as a JPEG.
+fn write_thumbnail(track: &Track, thumbnail_path: &Path) -> io::Result<()> {
+    let cover = track
+        .read_front_cover()?
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "The track has no front cover."))?;
+    let thumbnail = image::load_from_memory(&cover)
+        .map_err(io::Error::other)?
+        .resize(THUMBNAIL_SIZE, THUMBNAIL_SIZE, FilterType::Triangle)
+        .to_rgb8();
+    thumbnail
+        .save_with_format(thumbnail_path, ImageFormat::Jpeg)
+        .map_err(io::Error::other)
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+fn format_duration(duration: i32) -> String {
+    let seconds = duration.max(0) / 1000;
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+fn catalog_html(catalog: &Catalog) -> String {
+    let mut html = String::from(
+        "<!DOCTYPE html>
+<html>
+<head>
+<meta charset=\"utf-8\">
+<title>Music Library</title>
+<style>
+body { font-family: sans-serif; margin: 2em auto; max-width: 60em; }
+.album { display: flex; gap: 1em; margin-bottom: 2em; }
+.album img, .album .noart { width: 128px; height: 128px; object-fit: cover; background: #ddd; flex: none; }
+.album table { border-collapse: collapse; width: 100%; }
+.album td { padding: 0.1em 0.5em; }
+.number, .duration { color: #777; text-align: right; }
+</style>
+</head>
+<body>
+",
+    );
+    html.push_str(&format!("<p>Generated {}</p>\n", escape_html(&catalog.generated)));
+    for artist in &catalog.artists {
+        html.push_str(&format!("<h2>{}</h2>\n", escape_html(&artist.name)));
+        for album in &artist.albums {
+            html.push_str("<div class=\"album\">\n");
+            match &album.art {
+                Some(art) => html.push_str(&format!("<img src=\"{}\" alt=\"\">\n", escape_html(art))),
+                None => html.push_str("<div class=\"noart\"></div>\n"),
+            }
+            html.push_str(&format!(
+                "<div>\n<h3>{} ({})</h3>\n<table>\n",
+                escape_html(&album.title),
+                album.year
+            ));
+            for track in &album.tracks {
+                html.push_str(&format!(
+                    "<tr><td class=\"number\">{}.{}</td><td>{}</td><td>{}</td><td class=\"duration\">{}</td></tr>\n",
+                    track.disc_number,
+                    track.track_number,
+                    escape_html(&track.title),
+                    escape_html(&track.artist),
+                    format_duration(track.duration)
+                ));
+            }
+            html.push_str("</table>\n</div>\n</div>\n");
+        }
+    }
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+/// Writes the catalog as a static site into the given folder, creating it if it does not exist.
+///
+/// An art thumbnail is made for every album with a front cover, and set as its `art`.
+/// Albums whose art can not be read are listed without art.
+pub fn export_site(catalog: &mut Catalog, site_path: &Path) -> io::Result<()> {
+    let mut art_path = PathBuf::from(site_path);
+    art_path.push("art");
+    fs::create_dir_all(&art_path)?;
+    let albums = catalog
+        .artists
+        .iter_mut()
+        .flat_map(|artist| artist.albums.iter_mut());
+    for (index, album) in albums.enumerate() {
+        let file_name = format!("{}.jpg", index);
+        album.art = album
+            .cover_track
+            .as_ref()
+            .and_then(|track| write_thumbnail(track, &art_path.join(&file_name)).ok())
+            .map(|_| format!("art/{}", file_name));
+    }
+    export_json(catalog, &site_path.join("catalog.json"))?;
+    fs::write(site_path.join("index.html"), catalog_html(catalog))
+}
@@ -0,0 +1,31 @@
+//! Ordering of names by the collation of a locale, so accented and CJK names of artists and albums
+//! sort as readers of the locale expect, rather than by their bytes.
+//!
+//! Every connection to the library has the `LOCALE` collation, by the locale configured in
+//! `DatabaseConfig`. Lists of artists and albums are ordered by `database::BROWSE_COLLATION`,
+//! which is this collation when this feature is built, and `NOCASE` otherwise.
+
+use icu_collator::options::CollatorOptions;
+use icu_collator::{Collator, CollatorBorrowed};
+use icu_locale_core::Locale;
+use rusqlite::{Connection, Result};
+
+/// The name of the collation in SQL.
+pub const LOCALE_COLLATION: &str = "LOCALE";
+
+/// Gets the collator of the locale, such as `sv-SE`, or of the root locale if no locale is given
+/// or it is not a locale.
+pub fn collator(locale: Option<&str>) -> CollatorBorrowed<'static> {
+    let locale = locale
+        .and_then(|locale| Locale::try_from_str(locale.trim()).ok())
+        .unwrap_or(Locale::UNKNOWN);
+    Collator::try_new((&locale).into(), CollatorOptions::default())
+        .or_else(|_| Collator::try_new(Default::default(), CollatorOptions::default()))
+        .unwrap()
+}
+
+/// Adds the `LOCALE` collation of the locale to the connection.
+pub fn add_locale_collation(conn: &Connection, locale: Option<&str>) -> Result<()> {
+    let collator = collator(locale);
+    conn.create_collation(LOCALE_COLLATION, move |a, b| collator.compare(a, b))
+}
@@ -0,0 +1,160 @@
+//! The columns of tracks a query returns, so frontends that only need a few of them, such as a
+//! list of paths or a lightweight search dropdown, are not sent every column of every track.
+//!
+//! Columns are requested by name, such as `title`, `artist` and `path`. Every column is returned
+//! if none are named.
+
+use crate::error::{Error, Result};
+use katatsuki::{ToPrimitive, Track};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// A column of the tracks returned by a query.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TrackColumn {
+    Path,
+    Title,
+    Artist,
+    AlbumArtists,
+    Album,
+    Year,
+    TrackNumber,
+    DiscNumber,
+    Genres,
+    MusicbrainzTrackId,
+    Comment,
+    Isrc,
+    Encoder,
+    /// Whether the track has a front cover, and its size.
+    FrontCover,
+    Bitrate,
+    SampleRate,
+    Source,
+    Duration,
+    FileType,
+    Updated,
+    Uuid,
+    /// The gain in decibels to play the track at as the playback configuration prefers, from
+    /// `gains::get_playback_gains`, if it has ReplayGain tags.
+    Gain,
+    /// The hash of every column of the track itself, from `track_hash`.
+    Hash,
+}
+
+impl TrackColumn {
+    /// Every column, in the order tracks are returned with them.
+    pub const ALL: &'static [TrackColumn] = &[
+        TrackColumn::Path,
+        TrackColumn::Title,
+        TrackColumn::Artist,
+        TrackColumn::AlbumArtists,
+        TrackColumn::Genres,
+        TrackColumn::Album,
+        TrackColumn::Year,
+        TrackColumn::TrackNumber,
+        TrackColumn::MusicbrainzTrackId,
+        TrackColumn::Comment,
+        TrackColumn::Isrc,
+        TrackColumn::Encoder,
+        TrackColumn::FrontCover,
+        TrackColumn::Bitrate,
+        TrackColumn::SampleRate,
+        TrackColumn::Source,
+        TrackColumn::DiscNumber,
+        TrackColumn::Duration,
+        TrackColumn::FileType,
+        TrackColumn::Updated,
+        TrackColumn::Uuid,
+        TrackColumn::Gain,
+        TrackColumn::Hash,
+    ];
+
+    /// The name the column is requested by.
+    pub fn name(self) -> &'static str {
+        match self {
+            TrackColumn::Path => "path",
+            TrackColumn::Title => "title",
+            TrackColumn::Artist => "artist",
+            TrackColumn::AlbumArtists => "album_artists",
+            TrackColumn::Album => "album",
+            TrackColumn::Year => "year",
+            TrackColumn::TrackNumber => "track_number",
+            TrackColumn::DiscNumber => "disc_number",
+            TrackColumn::Genres => "genres",
+            TrackColumn::MusicbrainzTrackId => "musicbrainz_track_id",
+            TrackColumn::Comment => "comment",
+            TrackColumn::Isrc => "isrc",
+            TrackColumn::Encoder => "encoder",
+            TrackColumn::FrontCover => "front_cover",
+            TrackColumn::Bitrate => "bitrate",
+            TrackColumn::SampleRate => "sample_rate",
+            TrackColumn::Source => "source",
+            TrackColumn::Duration => "duration",
+            TrackColumn::FileType => "file_type",
+            TrackColumn::Updated => "updated",
+            TrackColumn::Uuid => "uuid",
+            TrackColumn::Gain => "gain",
+            TrackColumn::Hash => "hash",
+        }
+    }
+
+    /// Gets the column with the given name, ignoring case.
+    pub fn from_name(name: &str) -> Option<TrackColumn> {
+        let name = name.trim();
+        TrackColumn::ALL
+            .iter()
+            .copied()
+            .find(|column| column.name().eq_ignore_ascii_case(name))
+    }
+}
+
+/// Parses the named columns, in the order they are named and without repeats. No names name
+/// every column.
+pub fn parse_column_names<'a, I>(names: I) -> Result<Vec<TrackColumn>>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let mut columns = Vec::new();
+    for name in names {
+        let column = TrackColumn::from_name(name).ok_or_else(|| Error::UnknownColumn(name.trim().to_owned()))?;
+        if !columns.contains(&column) {
+            columns.push(column);
+        }
+    }
+    if columns.is_empty() {
+        columns.extend_from_slice(TrackColumn::ALL);
+    }
+    Ok(columns)
+}
+
+/// Hashes every column of the track, so a client that keeps the results of a query can tell which
+/// tracks changed when it runs the query again, and only render those again. The hash changes
+/// whenever any column of the track does, but is only comparable with the hashes of the same
+/// build of seiri, so it is never kept across restarts.
+pub fn track_hash(track: &Track) -> String {
+    let mut hasher = DefaultHasher::new();
+    track.file_path.hash(&mut hasher);
+    track.file_type.to_i32().hash(&mut hasher);
+    track.title.hash(&mut hasher);
+    track.artist.hash(&mut hasher);
+    track.album_artists.hash(&mut hasher);
+    track.album.hash(&mut hasher);
+    track.year.hash(&mut hasher);
+    track.track_number.hash(&mut hasher);
+    track.musicbrainz_track_id.hash(&mut hasher);
+    track.comment.hash(&mut hasher);
+    track.isrc.hash(&mut hasher);
+    track.encoder.hash(&mut hasher);
+    track.genres.hash(&mut hasher);
+    track.has_front_cover.hash(&mut hasher);
+    track.front_cover_height.hash(&mut hasher);
+    track.front_cover_width.hash(&mut hasher);
+    track.bitrate.hash(&mut hasher);
+    track.sample_rate.hash(&mut hasher);
+    track.source.hash(&mut hasher);
+    track.disc_number.hash(&mut hasher);
+    track.duration.hash(&mut hasher);
+    track.updated.hash(&mut hasher);
+    track.uuid.hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
@@ -0,0 +1,246 @@
+//! Imports of folders of tracks the library mostly has already, such as a mixtape a friend put
+//! together, as a playlist of the tracks of the library rather than as duplicates of them.
+//!
+//! Every track of the folder is matched against the tracks of the library by its fingerprint,
+//! which is its waveform scaled to its loudest peak, so the same recording matches across
+//! encodings and the levels it was mastered at. Only tracks of about the same duration are
+//! compared, and the waveforms of tracks of the library that were not analyzed yet are taken and
+//! saved as they are compared. Tracks that match nothing are imported as any other track is,
+//! and the tracks that matched are left where they are.
+
+use crate::analysis::{analyze_track, get_waveform, save_waveform};
+use crate::bangs::ms_to_ticks;
+use crate::config::Config;
+use crate::database::{get_track_by_uuid, track_from_row, TRACK_COLUMNS};
+use crate::events::Event;
+use crate::import;
+use crate::paths;
+use crate::profiles::{add_to_playlist, create_playlist};
+use katatsuki::Track;
+use rusqlite::functions::FunctionFlags;
+use rusqlite::{Connection, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// How much longer or shorter in milliseconds a track of the library may be than a track of the
+/// folder to be compared with it, such as for a copy with its trailing silence cut off.
+pub(crate) const DURATION_TOLERANCE: i32 = 2000;
+/// How many parts of their waveforms fingerprints are shifted against each other by at most when
+/// compared, for tracks whose audio starts a little earlier or later.
+const MAX_SHIFT: usize = 8;
+/// The mean difference between the parts of two fingerprints below which they are taken to be of
+/// the same recording.
+const MATCH_THRESHOLD: f32 = 0.05;
+
+/// What came of importing a folder as a compilation.
+#[derive(Debug)]
+pub struct CompilationImport {
+    pub playlist_id: i64,
+    /// Every track of the folder that matched a track of the library, along with the path of the
+    /// track of the library it matched.
+    pub matched: Vec<(PathBuf, PathBuf)>,
+    /// The event of the import of every track that did not match.
+    pub imported: Vec<Event>,
+}
+
+/// Scales the waveform to its loudest peak.
+pub(crate) fn fingerprint(waveform: &[u8]) -> Vec<f32> {
+    let peak = f32::from(waveform.iter().cloned().max().unwrap_or(0).max(1));
+    waveform.iter().map(|part| f32::from(*part) / peak).collect()
+}
+
+fn mean_difference(a: &[f32], b: &[f32]) -> f32 {
+    let parts = a.len().min(b.len()).max(1);
+    a.iter().zip(b).map(|(a, b)| (a - b).abs()).sum::<f32>() / parts as f32
+}
+
+/// The smallest mean difference between the fingerprints, shifted against each other either way.
+fn fingerprint_distance(a: &[f32], b: &[f32]) -> f32 {
+    (0..=MAX_SHIFT)
+        .flat_map(|shift| {
+            vec![
+                mean_difference(&a[shift.min(a.len())..], b),
+                mean_difference(a, &b[shift.min(b.len())..]),
+            ]
+        })
+        .fold(f32::INFINITY, f32::min)
+}
+
+/// Adds the `fingerprints_match(a, b)` function to the connection, which is true if the waveforms
+/// are of the same recording, and false if either is NULL.
+pub fn add_fingerprint_function(conn: &Connection) -> Result<()> {
+    conn.create_scalar_function("fingerprints_match", 2, FunctionFlags::SQLITE_DETERMINISTIC, |ctx| {
+        match (ctx.get::<Option<Vec<u8>>>(0)?, ctx.get::<Option<Vec<u8>>>(1)?) {
+            (Some(a), Some(b)) => Ok(fingerprint_distance(&fingerprint(&a), &fingerprint(&b)) < MATCH_THRESHOLD),
+            _ => Ok(false),
+        }
+    })
+}
+
+/// Gets the fingerprint of a track of the library, taking and saving its waveform if it has none.
+fn library_fingerprint(track: &Track, conn: &Connection) -> Result<Option<Vec<f32>>> {
+    let track_id = match &track.uuid {
+        Some(track_id) => track_id,
+        None => return Ok(None),
+    };
+    if let Some(waveform) = get_waveform(track_id, conn)? {
+        return Ok(Some(fingerprint(&waveform)));
+    }
+    // A track of the library that can not be decoded is never matched.
+    match analyze_track(track, false) {
+        Ok(analysis) => {
+            save_waveform(track_id, &analysis.waveform, conn)?;
+            Ok(Some(fingerprint(&analysis.waveform)))
+        }
+        Err(_) => Ok(None),
+    }
+}
+
+/// Finds the track of the library that is the same recording as the track, if there is one,
+/// preferring the closest match.
+pub fn find_match(track: &Track, conn: &Connection) -> Result<Option<Track>> {
+    // A track that can not be decoded matches nothing, and is imported as it is.
+    match analyze_track(track, false) {
+        Ok(analysis) => find_fingerprint_match(&fingerprint(&analysis.waveform), track.duration, conn),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Finds the track of the library of about the duration in milliseconds whose fingerprint is the
+/// closest to the fingerprint, if any is close enough to be the same recording.
+pub(crate) fn find_fingerprint_match(track_fingerprint: &[f32], duration: i32, conn: &Connection) -> Result<Option<Track>> {
+    let mut statement = conn.prepare(&format!(
+        "SELECT {} FROM tracks WHERE ABS(Duration - ?1) <= ?2",
+        TRACK_COLUMNS
+    ))?;
+    // Durations are stored in ticks.
+    let candidates = statement
+        .query_map([ms_to_ticks(duration), ms_to_ticks(DURATION_TOLERANCE)], track_from_row)?
+        .collect::<Result<Vec<Track>>>()?;
+    let mut closest = None;
+    for candidate in candidates {
+        if let Some(candidate_fingerprint) = library_fingerprint(&candidate, conn)? {
+            let distance = fingerprint_distance(track_fingerprint, &candidate_fingerprint);
+            if distance < MATCH_THRESHOLD && closest.as_ref().is_none_or(|(closest, _)| distance < *closest) {
+                closest = Some((distance, candidate));
+            }
+        }
+    }
+    Ok(closest.map(|(_, track)| track))
+}
+
+/// Imports the tracks of the folder as a playlist of the profile with the given name, in the
+/// order of the names of their files. Tracks the library already has are listed in the playlist
+/// in place of importing them again, and only the rest are imported, moving them out of the folder.
+///
+/// Files that are not tracks are left in the folder, as are tracks that could not be imported,
+/// which are left out of the playlist.
+pub fn import_compilation(
+    folder: &Path,
+    profile_id: &str,
+    name: &str,
+    config: &Config,
+    conn: &Connection,
+) -> Result<CompilationImport> {
+    let mut files = fs::read_dir(folder)
+        .map_err(|_| rusqlite::Error::InvalidPath(folder.to_owned()))?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| !entry.file_name().to_string_lossy().starts_with('.'))
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file())
+        .collect::<Vec<PathBuf>>();
+    files.sort();
+    let playlist_id = create_playlist(profile_id, name, conn)?;
+    let mut compilation = CompilationImport {
+        playlist_id,
+        matched: Vec::new(),
+        imported: Vec::new(),
+    };
+    for file in files {
+        let track = match paths::read_track(&file, None) {
+            Ok(track) => track,
+            Err(_) => continue,
+        };
+        match find_match(&track, conn)? {
+            Some(existing) => {
+                add_to_playlist(playlist_id, &existing.file_path, conn)?;
+                compilation.matched.push((file, existing.file_path));
+            }
+            None => {
+                let event = import::import_track(&file, config, conn, true);
+                if let Event::TrackAdded { uuid, .. } = &event {
+                    if let Some(added) = get_track_by_uuid(uuid, conn)? {
+                        add_to_playlist(playlist_id, &added.file_path, conn)?;
+                    }
+                }
+                compilation.imported.push(event);
+            }
+        }
+    }
+    Ok(compilation)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::save_waveform;
+    use crate::database::{add_track, create_database, enable_foreign_keys};
+    use katatsuki::TrackFileType;
+
+    /// A waveform that does not repeat, so it only matches itself when lined up with itself.
+    fn waveform() -> Vec<u8> {
+        (0..64u32).map(|part| (part * 37 % 64 * 3) as u8).collect()
+    }
+
+    /// Adds a track of the duration in milliseconds with the waveform to the library.
+    fn add_analyzed_track(file_name: &str, duration: i32, waveform: Option<&[u8]>, conn: &Connection) {
+        let track = Track::builder(format!("/music/{}", file_name), TrackFileType::FLAC16)
+            .title(file_name.to_owned())
+            .duration(duration)
+            .build();
+        let track_id = add_track(&track, conn);
+        if let Some(waveform) = waveform {
+            save_waveform(&track_id, waveform, conn).unwrap();
+        }
+    }
+
+    fn matched_name(track_fingerprint: &[f32], duration: i32, conn: &Connection) -> Option<String> {
+        find_fingerprint_match(track_fingerprint, duration, conn)
+            .unwrap()
+            .map(|track| track.file_path.file_name().unwrap().to_string_lossy().into_owned())
+    }
+
+    #[test]
+    fn matches_fingerprints_across_levels_and_offsets() {
+        let waveform = waveform();
+        let quieter = waveform.iter().map(|part| part / 2).collect::<Vec<u8>>();
+        let later = [&[0, 0, 0][..], &waveform].concat();
+        let reversed = waveform.iter().rev().cloned().collect::<Vec<u8>>();
+        assert!(fingerprint_distance(&fingerprint(&waveform), &fingerprint(&quieter)) < MATCH_THRESHOLD);
+        assert!(fingerprint_distance(&fingerprint(&waveform), &fingerprint(&later)) < MATCH_THRESHOLD);
+        assert!(fingerprint_distance(&fingerprint(&waveform), &fingerprint(&reversed)) >= MATCH_THRESHOLD);
+        assert_eq!(fingerprint(&[0, 0]), [0.0, 0.0]);
+    }
+
+    #[test]
+    fn finds_the_closest_track_of_about_the_same_duration() {
+        let conn = Connection::open_in_memory().unwrap();
+        enable_foreign_keys(&conn).unwrap();
+        create_database(&conn);
+        let waveform = waveform();
+        // Nearly the same recording, but for its second part.
+        let mut nearly = waveform.clone();
+        nearly[1] /= 2;
+        add_analyzed_track("a.flac", 180_000, Some(&nearly), &conn);
+        add_analyzed_track("b.flac", 181_000, Some(&waveform), &conn);
+        // The same recording, but far too long to be compared.
+        add_analyzed_track("c.flac", 240_000, Some(&waveform), &conn);
+        // A track that was not analyzed and can not be decoded is never matched.
+        add_analyzed_track("d.flac", 180_000, None, &conn);
+
+        assert_eq!(matched_name(&fingerprint(&waveform), 180_000, &conn).as_deref(), Some("b.flac"));
+        assert_eq!(matched_name(&fingerprint(&waveform), 236_000, &conn), None);
+        let reversed = waveform.iter().rev().cloned().collect::<Vec<u8>>();
+        assert_eq!(matched_name(&fingerprint(&reversed), 180_000, &conn), None);
+    }
+}
@@ -1,5 +1,12 @@
 use dirs::home_dir;
 use crate::error::{ConfigErrorType, Error, Result};
+use crate::fields::CustomField;
+use crate::hooks::Hook;
+use crate::inference::Confidence;
+use crate::layouts::MediaType;
+use crate::normalization::NormalizationRule;
+use crate::schedule::Schedule;
+use crate::variants::Transcoder;
 use crate::paths::*;
 use serde_derive::{Serialize, Deserialize};
 use std::default::Default;
@@ -10,6 +17,671 @@ use toml;
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Config {
     pub music_folder: String,
+    // Plain values must come before sections, or the configuration can not be written as TOML.
+    /// Whether the music folder is indexed as it is laid out, rather than managed by seiri.
+    /// Files are never moved: the music folder itself is watched instead of the watch folder,
+    /// and tracks are added, changed and removed where they are.
+    #[serde(default)]
+    pub adopt_layout: bool,
+    /// How artist and album folders that differ from an existing folder only in case are named.
+    #[serde(default)]
+    pub folder_casing: FolderCasing,
+    /// Whether the artist of every imported track is rewritten to its canonical name,
+    /// if it is tagged with an alias.
+    #[serde(default)]
+    pub canonical_artists: bool,
+    /// The program `import_url` downloads with, followed by its arguments. The URL is added as the last argument.
+    #[serde(default = "default_downloader")]
+    pub downloader: Vec<String>,
+    /// The language frontends show the messages of events in, as a language tag such as `ja`.
+    /// Messages are shown in English in languages seiri is not translated into.
+    #[serde(default = "default_locale")]
+    pub locale: String,
+    /// How long a hook may run for, in seconds, before it is stopped and the import is abandoned.
+    #[serde(default = "default_hook_timeout")]
+    pub hook_timeout: u64,
+    #[serde(default)]
+    pub network: NetworkConfig,
+    /// Which tags a track must have to be imported, and how missing tags are filled in otherwise.
+    #[serde(default)]
+    pub required_tags: RequiredTagsConfig,
+    #[serde(default)]
+    pub inference: InferenceConfig,
+    #[serde(default)]
+    pub sidecars: SidecarConfig,
+    #[serde(default)]
+    pub analysis: AnalysisConfig,
+    #[serde(default)]
+    pub cleanup: CleanupConfig,
+    #[serde(default)]
+    pub permissions: PermissionsConfig,
+    #[serde(default)]
+    pub art: ArtConfig,
+    #[serde(default)]
+    pub reports: ReportConfig,
+    #[serde(default)]
+    pub listens: ListenConfig,
+    /// The folders tracks are organized into within the music folder.
+    #[serde(default)]
+    pub layouts: LayoutConfig,
+    /// How the editions of an album are chosen between, when only one edition of each album is listed.
+    #[serde(default)]
+    pub editions: EditionConfig,
+    /// The threads imports are processed on.
+    #[serde(default)]
+    pub import: PoolConfig,
+    #[serde(default)]
+    pub database: DatabaseConfig,
+    #[serde(default)]
+    pub casting: CastingConfig,
+    #[serde(default)]
+    pub playback: PlaybackConfig,
+    #[serde(default)]
+    pub rips: RipConfig,
+    #[serde(default)]
+    pub needledrops: NeedledropConfig,
+    /// How the watcher is restarted when it dies.
+    #[serde(default)]
+    pub watchdog: WatchdogConfig,
+    /// Programs run at stages of every import, in order.
+    // An empty list would be written as a plain value after the sections before it.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hooks: Vec<Hook>,
+    /// Rules that normalize the tags of every imported track, in order.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub normalization: Vec<NormalizationRule>,
+    /// HTTP endpoints every event is posted to.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub webhooks: Vec<WebhookConfig>,
+    /// The hours of the day background jobs are allowed to run in. Jobs without a schedule
+    /// run whenever they are asked to.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub schedules: Vec<Schedule>,
+    /// Fields tracks can be given values of beyond their tags, matched by the `!x` bang.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub custom_fields: Vec<CustomField>,
+    /// Programs that transcode tracks into other formats, which tracks can be exported in.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub transcoders: Vec<Transcoder>,
+}
+
+/// Configuration for the analysis jobs, which decode the audio of tracks.
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct AnalysisConfig {
+    /// Whether a 30 second preview clip of every track is written along with its waveform.
+    pub preview_clips: bool,
+    /// Whether lossless tracks are checked for being transcoded from lossy audio,
+    /// which the `!fake` bang matches.
+    pub spectral_analysis: bool,
+    /// The threads tracks are decoded on.
+    pub pool: PoolConfig,
+}
+
+/// What a job does with more work once its queue is full.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Backpressure {
+    /// Holds the work back until the queue has room for it.
+    Block,
+    /// Sheds the work, which is left for the next time the job runs.
+    Shed,
+}
+
+/// Configuration for the threads a job runs on, and how much work is queued for them.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(default)]
+pub struct PoolConfig {
+    /// The number of threads, which is at least 1.
+    pub threads: usize,
+    /// The most work queued for the threads at once, or 0 for no bound.
+    pub queue: usize,
+    pub backpressure: Backpressure,
+}
+
+impl PoolConfig {
+    /// The number of threads, at least 1 even if configured as 0.
+    pub fn threads(&self) -> usize {
+        self.threads.max(1)
+    }
+
+    /// Whether the queue is full, with the given amount of work queued.
+    pub fn is_full(&self, queued: usize) -> bool {
+        self.queue != 0 && queued >= self.queue
+    }
+}
+
+/// Configuration for network-exposed APIs.
+///
+/// APIs are only ever bound to localhost, and are reached from other machines through a tunnel,
+/// such as SSH port forwarding. Binding to any other address fails.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct NetworkConfig {
+    pub bind_address: String,
+    pub port: u16,
+    /// Tokens accepted as `Authorization: Bearer <token>`, allowing every request.
+    pub auth_tokens: Vec<String>,
+    /// Tokens that only allow reading the library, such as for a frontend shared by a household.
+    pub read_only_tokens: Vec<String>,
+    /// Whether other instances can sync their profiles with this one on the port.
+    pub sync: bool,
+    /// How often profiles are synced with every peer, in seconds.
+    pub sync_interval: u64,
+    /// Whether the HTTP API, which searches the library and streams its tracks, is served.
+    pub http: bool,
+    /// The port the HTTP API is served on, at the same address as sync.
+    pub http_port: u16,
+    /// Other instances that profiles are synced with.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub sync_peers: Vec<SyncPeer>,
+}
+
+/// Another instance of seiri that ratings, play counts and playlists are synced with.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SyncPeer {
+    /// The address and port the peer serves sync on, such as `localhost:9236` for a peer reached
+    /// through a tunnel, since sync is only served on localhost.
+    pub address: String,
+    /// One of the authentication tokens of the peer. A read-only token only allows pulling files.
+    pub token: String,
+    /// Whether tracks the peer has that are not in this library are downloaded and imported.
+    #[serde(default)]
+    pub pull_files: bool,
+}
+
+/// An HTTP endpoint that events are posted to as JSON.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WebhookConfig {
+    pub url: String,
+    /// The codes of the events posted to the webhook, such as `TRACKADDED`.
+    /// Every event is posted if this is empty.
+    #[serde(default)]
+    pub events: Vec<String>,
+    /// How many times delivery of an event is retried if the webhook can not be reached.
+    #[serde(default = "default_webhook_retries")]
+    pub retries: u32,
+}
+
+fn default_locale() -> String {
+    "en".to_owned()
+}
+
+fn default_hook_timeout() -> u64 {
+    60
+}
+
+fn default_downloader() -> Vec<String> {
+    ["yt-dlp", "--extract-audio", "--embed-metadata", "--embed-thumbnail"]
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect()
+}
+
+fn default_webhook_retries() -> u32 {
+    3
+}
+
+impl WebhookConfig {
+    /// Whether events of the given code are posted to the webhook.
+    pub fn accepts(&self, code: &str) -> bool {
+        self.events.is_empty() || self.events.iter().any(|event| event.eq_ignore_ascii_case(code))
+    }
+}
+
+/// Configuration for non-audio files dropped alongside an album, such as booklets and logs.
+///
+/// Sidecar files are moved into the album folder of the tracks they were found with,
+/// instead of the not added folder.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct SidecarConfig {
+    pub enabled: bool,
+    /// The extensions of sidecar files, without the leading dot.
+    pub extensions: Vec<String>,
+}
+
+impl SidecarConfig {
+    /// Whether the file at the given path is a sidecar file.
+    pub fn is_sidecar(&self, path: &Path) -> bool {
+        self.enabled
+            && path
+                .extension()
+                .and_then(|s| s.to_str())
+                .map(|ext| self.extensions.iter().any(|allowed| allowed.eq_ignore_ascii_case(ext)))
+                .unwrap_or(false)
+    }
+}
+
+/// What is done with a track that is missing a tag.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TagFallback {
+    /// The track is not imported, and is moved into the not added folder.
+    Require,
+    /// The tag is inferred from the other tags of the track or its file name, such as the album
+    /// artist from the artist. The track is not imported if it can not be inferred.
+    Infer,
+    /// The track is imported without the tag. Tags the track is moved into folders by are given
+    /// a placeholder, such as `Unknown Artist`.
+    Allow,
+}
+
+/// Which tags a track must have to be imported, and how each is filled in when it is missing.
+///
+/// A missing title is inferred from the file name, the artist from the album artists and the
+/// album artists from the artist, the album from the title as a single, and the track number
+/// from the number the file name starts with. Tags are filled in after the post-tag-read hooks
+/// and normalization rules, and only in the library, never in the file.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(default)]
+pub struct RequiredTagsConfig {
+    pub title: TagFallback,
+    pub artist: TagFallback,
+    pub album: TagFallback,
+    pub album_artists: TagFallback,
+    pub track_number: TagFallback,
+}
+
+impl Default for RequiredTagsConfig {
+    fn default() -> RequiredTagsConfig {
+        RequiredTagsConfig {
+            title: TagFallback::Require,
+            artist: TagFallback::Require,
+            album: TagFallback::Require,
+            album_artists: TagFallback::Require,
+            track_number: TagFallback::Allow,
+        }
+    }
+}
+
+/// How tags missing from imported tracks are inferred from the names of their files and folders,
+/// before they are filled in or rejected as configured in `RequiredTagsConfig`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct InferenceConfig {
+    /// Patterns of file names without their extension that tags are inferred from, tried in
+    /// order, such as `{artist} - {title}`. See the `inference` module.
+    pub file_name_patterns: Vec<String>,
+    /// Whether tags are inferred from the names of the folders tracks were dropped in.
+    pub folder_names: bool,
+    /// How confident a tag inferred from the name of a folder must be to be used.
+    pub folder_confidence: Confidence,
+    /// Whether inferred tags are written to the file, rather than only kept in the library.
+    pub write_back: bool,
+}
+
+impl Default for InferenceConfig {
+    fn default() -> InferenceConfig {
+        InferenceConfig {
+            file_name_patterns: [
+                "{track_number} - {artist} - {title}",
+                "{track_number} - {title}",
+                "{track_number}. {title}",
+                "{artist} - {title}",
+            ]
+            .iter()
+            .map(|pattern| pattern.to_string())
+            .collect(),
+            folder_names: true,
+            folder_confidence: Confidence::Medium,
+            write_back: true,
+        }
+    }
+}
+
+/// How the watch folder is cleaned up after imports.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct CleanupConfig {
+    /// Whether folders left empty by an import are removed. Folders directly in the watch folder
+    /// named for a source, such as `Bandcamp`, are kept to be dropped into again.
+    pub remove_empty_folders: bool,
+    /// The number of days files are kept in the not added folder before they are removed.
+    /// Files are kept forever if this is not set.
+    pub purge_not_added_after: Option<u32>,
+}
+
+impl Default for CleanupConfig {
+    fn default() -> CleanupConfig {
+        CleanupConfig {
+            remove_empty_folders: true,
+            purge_not_added_after: None,
+        }
+    }
+}
+
+/// The permissions and ownership imported files are given once they are moved into the library,
+/// so they can be read by another user, such as that of a media server. Files otherwise keep those
+/// they were dropped into the watch folder with. Only applied on Unix. A file that can not be given
+/// them is left in the watch folder, and reported with `Event::PermissionsError`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct PermissionsConfig {
+    /// The mode of imported files, best written in octal, such as `0o644`.
+    pub file_mode: Option<u32>,
+    /// The mode of the folders in the library files are moved into, such as `0o755`.
+    pub folder_mode: Option<u32>,
+    /// The user that owns imported files and their folders, by name or ID, such as `plex`.
+    /// Only root can give files to another user.
+    pub owner: Option<String>,
+    /// The group that owns imported files and their folders, by name or ID, such as `media`.
+    pub group: Option<String>,
+}
+
+/// Configuration for the album art quality report, and the job that upgrades low resolution art.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct ArtConfig {
+    /// The smallest width and height of album art that is not reported as low resolution.
+    pub min_resolution: i32,
+    /// Folders of higher resolution art to upgrade albums with, searched in order. Art is named
+    /// for the album artists and title of its album, as `Album Artists - Album.jpg`.
+    pub folders: Vec<String>,
+    /// URLs of higher resolution art, tried in order after the folders, where `{artist}` and
+    /// `{album}` are replaced with the album artists and title of the album.
+    /// URLs are only tried when seiri is built with network support.
+    pub urls: Vec<String>,
+}
+
+/// The layout of folders tracks of each media type are organized into, as described in `layouts`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct LayoutConfig {
+    pub music: String,
+    pub audiobook: String,
+}
+
+impl LayoutConfig {
+    /// The layout tracks of the media type are organized into.
+    pub fn layout(&self, media_type: MediaType) -> &str {
+        match media_type {
+            MediaType::Music => &self.music,
+            MediaType::Audiobook => &self.audiobook,
+        }
+    }
+}
+
+impl Default for LayoutConfig {
+    fn default() -> LayoutConfig {
+        LayoutConfig {
+            music: "{album_artist}/{album}".to_owned(),
+            audiobook: "{album_artist}/{series}/{part} - {book}".to_owned(),
+        }
+    }
+}
+
+/// Which edition of an album is preferred, when only one edition of each album is listed.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum EditionPreference {
+    /// The edition whose worst track is of the best quality.
+    #[default]
+    Quality,
+    /// The edition with the most tracks, such as a deluxe edition.
+    Tracks,
+    /// The edition first released, such as the original release of a remastered album.
+    Earliest,
+    /// The edition last released, such as the latest remaster.
+    Latest,
+}
+
+/// Configuration for grouping the editions of albums, as described in `editions`.
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct EditionConfig {
+    /// Which edition of an album is preferred. Editions that are as preferred as each other are
+    /// chosen between by quality, then by the number of tracks.
+    pub prefer: EditionPreference,
+}
+
+/// Which ReplayGain a track is played at.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum GainMode {
+    /// The gain of the track alone, so every track is played about as loud as every other.
+    #[default]
+    Track,
+    /// The gain of the album of the track, so the tracks of an album keep their levels relative
+    /// to each other. Tracks without an album gain are played at their track gain.
+    Album,
+}
+
+/// Configuration for how tracks are played, which query results tell playback clients.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(default)]
+pub struct PlaybackConfig {
+    pub gain_mode: GainMode,
+    /// Whether the gain is lowered so the peak of the track does not clip, if its peak is tagged.
+    pub prevent_clipping: bool,
+}
+
+impl Default for PlaybackConfig {
+    fn default() -> PlaybackConfig {
+        PlaybackConfig {
+            gain_mode: GainMode::Track,
+            prevent_clipping: true,
+        }
+    }
+}
+
+/// Configuration for the reports written after every import batch.
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct ReportConfig {
+    /// Whether a report of every import batch is written into the reports folder,
+    /// as a JSON file and a plain text summary.
+    pub enabled: bool,
+    /// The folder reports are written into, which is the reports folder of the application
+    /// directory if this is not set.
+    pub folder: Option<String>,
+}
+
+/// Configuration for play tracking.
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct ListenConfig {
+    /// Whether every play is kept in the listening history of its profile, along with when it was
+    /// played, so it can be exported to be scrobbled later. Play counts are kept either way.
+    pub history: bool,
+}
+
+/// Where a pool of connections keeps the library.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum DatabaseStorage {
+    /// The library in the application directory.
+    #[default]
+    Library,
+    /// A scratch library kept in memory, which is empty when the pool is created and gone once
+    /// the pool is dropped. The pool only has one connection, so readers and writers take turns.
+    Memory,
+    /// A scratch library in a temporary file, which is empty when the pool is created and removed
+    /// once the pool is dropped.
+    Temporary,
+}
+
+/// Configuration for the pool of connections to the library shared by the threads of the watcher.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct DatabaseConfig {
+    /// The fewest connections kept open, even while idle.
+    pub min_connections: u32,
+    /// The most connections open at once. Threads wait for a connection once every one is in use.
+    pub max_connections: u32,
+    /// How long a thread waits for a connection before giving up, in seconds.
+    pub connection_timeout: u64,
+    /// Whether connections are checked to still read the library before they are handed out.
+    pub health_checks: bool,
+    /// Where the library is kept. Scratch libraries are for experiments and tests, which leave
+    /// the library in the application directory as it is.
+    pub storage: DatabaseStorage,
+    /// How long a search of the library may run before it is interrupted, in milliseconds,
+    /// or 0 to let searches run for as long as they take.
+    pub query_timeout: u64,
+    /// Searches that run for at least this long are written to the slow query log in the
+    /// application directory, in milliseconds, or 0 to only log searches that time out.
+    pub slow_query_threshold: u64,
+    /// The locale artists and albums are ordered by when they are listed, such as `sv-SE`,
+    /// which needs the `collation` feature. Names are ordered by the root locale if it is not set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub locale: Option<String>,
+    /// The key the library is encrypted with, which needs the `sqlcipher` feature.
+    /// Prefer `key_command` for libraries on shared storage, so the key is not kept beside them.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    /// A command that prints the key the library is encrypted with, such as
+    /// `["secret-tool", "lookup", "service", "seiri"]` to read it from the keyring.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub key_command: Vec<String>,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> DatabaseConfig {
+        DatabaseConfig {
+            min_connections: 1,
+            max_connections: 8,
+            connection_timeout: 30,
+            health_checks: true,
+            storage: DatabaseStorage::Library,
+            query_timeout: 10_000,
+            slow_query_threshold: 1_000,
+            locale: None,
+            key: None,
+            key_command: Vec::new(),
+        }
+    }
+}
+
+impl Default for PoolConfig {
+    fn default() -> PoolConfig {
+        PoolConfig {
+            threads: 1,
+            queue: 0,
+            backpressure: Backpressure::Block,
+        }
+    }
+}
+
+impl Default for ArtConfig {
+    fn default() -> ArtConfig {
+        ArtConfig {
+            min_resolution: 500,
+            folders: Vec::new(),
+            urls: Vec::new(),
+        }
+    }
+}
+
+impl Default for SidecarConfig {
+    fn default() -> SidecarConfig {
+        SidecarConfig {
+            enabled: true,
+            extensions: ["pdf", "log", "cue", "txt", "nfo", "jpg", "jpeg", "png"]
+                .iter()
+                .map(|ext| ext.to_string())
+                .collect(),
+        }
+    }
+}
+
+/// Configuration for the intake of CDs ripped with Exact Audio Copy or whipper.
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct RipConfig {
+    /// The folder the ripper writes to, with every rip in a folder of its own along with its log.
+    /// Rips are only taken in if this is set.
+    pub folder: Option<String>,
+    /// Whether rips are rejected if AccurateRip found any of their tracks to be inaccurate.
+    /// Tracks that are not in the AccurateRip database are still taken in.
+    pub require_accurate: bool,
+}
+
+/// Configuration for suggesting where needledrop recordings are split into tracks.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct NeedledropConfig {
+    /// The level in dBFS below which the recording is silent, which must be above the surface
+    /// noise of the record.
+    pub silence_threshold: f32,
+    /// How long in milliseconds a silence must last to be a gap between tracks.
+    pub min_silence: u64,
+    /// The shortest track in milliseconds that is suggested. Silences within this of a cut or
+    /// either end of the recording are passed over.
+    pub min_track: u64,
+}
+
+impl Default for NeedledropConfig {
+    fn default() -> Self {
+        NeedledropConfig {
+            silence_threshold: -45.0,
+            min_silence: 1500,
+            min_track: 30000,
+        }
+    }
+}
+
+/// Configuration for restarting the watcher when it dies.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct WatchdogConfig {
+    /// The most times in a row the watcher is restarted before it is left stopped.
+    pub max_restarts: u32,
+    /// How long in seconds the watchdog waits before the first restart in a row, which doubles
+    /// with every restart after it.
+    pub backoff: u64,
+    /// The longest in seconds the watchdog waits before a restart.
+    pub max_backoff: u64,
+    /// How long in seconds the watcher must run after a restart for the restarts before it to no
+    /// longer count as in a row.
+    pub reset_after: u64,
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        WatchdogConfig {
+            max_restarts: 5,
+            backoff: 1,
+            max_backoff: 300,
+            reset_after: 600,
+        }
+    }
+}
+
+/// Configuration for casting tracks to devices on the local network.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct CastingConfig {
+    /// The port tracks are streamed to cast targets on, on every interface.
+    pub stream_port: u16,
+    /// The address cast targets are told to stream from, such as when they reach this machine
+    /// through a forwarded port. By default, the address of the interface that routes to the target.
+    pub stream_address: Option<String>,
+}
+
+impl Default for CastingConfig {
+    fn default() -> CastingConfig {
+        CastingConfig {
+            stream_port: 9237,
+            stream_address: None,
+        }
+    }
+}
+
+impl Default for NetworkConfig {
+    fn default() -> NetworkConfig {
+        NetworkConfig {
+            bind_address: "127.0.0.1".to_owned(),
+            port: 9236,
+            auth_tokens: Vec::new(),
+            read_only_tokens: Vec::new(),
+            sync: false,
+            sync_interval: 300,
+            http: false,
+            http_port: 9238,
+            sync_peers: Vec::new(),
+        }
+    }
 }
 
 impl Default for Config {
@@ -19,6 +691,37 @@ impl Default for Config {
         home_dir.push("seiri");
         Config {
             music_folder: home_dir.to_str().unwrap().to_owned(),
+            adopt_layout: false,
+            network: NetworkConfig::default(),
+            required_tags: RequiredTagsConfig::default(),
+            inference: InferenceConfig::default(),
+            sidecars: SidecarConfig::default(),
+            folder_casing: FolderCasing::default(),
+            canonical_artists: false,
+            downloader: default_downloader(),
+            locale: default_locale(),
+            hook_timeout: default_hook_timeout(),
+            analysis: AnalysisConfig::default(),
+            cleanup: CleanupConfig::default(),
+            permissions: PermissionsConfig::default(),
+            art: ArtConfig::default(),
+            reports: ReportConfig::default(),
+            listens: ListenConfig::default(),
+            layouts: LayoutConfig::default(),
+            editions: EditionConfig::default(),
+            import: PoolConfig::default(),
+            database: DatabaseConfig::default(),
+            casting: CastingConfig::default(),
+            playback: PlaybackConfig::default(),
+            rips: RipConfig::default(),
+            needledrops: NeedledropConfig::default(),
+            watchdog: WatchdogConfig::default(),
+            hooks: Vec::new(),
+            normalization: Vec::new(),
+            webhooks: Vec::new(),
+            schedules: Vec::new(),
+            custom_fields: Vec::new(),
+            transcoders: Vec::new(),
         }
     }
 }
@@ -46,3 +749,13 @@ pub fn get_config() -> Result<Config> {
         Err(Error::ConfigError(ConfigErrorType::Invalid))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_http_port_is_not_the_stream_port() {
+        assert_ne!(NetworkConfig::default().http_port, CastingConfig::default().stream_port);
+    }
+}
@@ -0,0 +1,82 @@
+//! Conflicting copies of the library database, left next to it by the service syncing the folder
+//! it is in, such as Dropbox or Syncthing, when the library was written on two machines at once.
+//!
+//! A conflicting copy is a fork of the library, so the watcher refuses to run while there is one,
+//! rather than carrying on with one side of the fork and silently losing the other. A conflicting
+//! copy is resolved by merging it into the library, after which it is set aside so it is not
+//! found again. Copies are only recognized by the names Dropbox, Nextcloud and Syncthing give
+//! them, since other services name copies in ways that can not be told apart from other files.
+
+use crate::database::Connection;
+use crate::library::{merge, MergeReport, MergeStrategy, PlayCountStrategy};
+use rusqlite::Result;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The extension given to conflicting copies once they are merged.
+const MERGED_EXTENSION: &str = "merged";
+
+/// Whether the file is a conflicting copy of the database with the given file name, such as
+/// `tracks (Ronny's conflicted copy 2020-01-01).db` of Dropbox and Nextcloud for `tracks.db`,
+/// or `tracks.sync-conflict-20200101-120000-ABCDEFG.db` of Syncthing.
+pub fn is_conflict_copy(file_name: &str, database_name: &str) -> bool {
+    let (stem, extension) = match database_name.rfind('.') {
+        Some(index) => database_name.split_at(index),
+        None => (database_name, ""),
+    };
+    if file_name == database_name
+        || file_name.len() < database_name.len()
+        || !file_name.starts_with(stem)
+        || !file_name.ends_with(extension)
+    {
+        return false;
+    }
+    let marker = file_name[stem.len()..file_name.len() - extension.len()].to_lowercase();
+    marker.contains("conflicted copy") || marker.starts_with(".sync-conflict-")
+}
+
+/// Finds every conflicting copy of the database next to it, in the order of their names.
+pub fn find_conflicts(database_path: &Path) -> Vec<PathBuf> {
+    let (folder, database_name) = match (database_path.parent(), database_path.file_name()) {
+        (Some(folder), Some(name)) => (folder, name.to_string_lossy()),
+        _ => return Vec::new(),
+    };
+    let mut conflicts = fs::read_dir(folder)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| path.is_file())
+                .filter(|path| {
+                    path.file_name()
+                        .is_some_and(|name| is_conflict_copy(&name.to_string_lossy(), &database_name))
+                })
+                .collect::<Vec<PathBuf>>()
+        })
+        .unwrap_or_default();
+    conflicts.sort();
+    conflicts
+}
+
+/// Merges the conflicting copy into the library. Tracks only in the copy are added, and the
+/// greater play count of tracks in both is kept, since both sides of the fork share the plays
+/// from before it. The copy is left in place, to be set aside with `set_aside`.
+pub fn merge_conflict(conflict_path: &Path, conn: &Connection) -> Result<MergeReport> {
+    let strategy = MergeStrategy {
+        add_missing_tracks: true,
+        play_counts: PlayCountStrategy::Max,
+        rebase: None,
+    };
+    merge(conflict_path, &strategy, conn)
+}
+
+/// Sets the merged conflicting copy aside, so it is not found again. Returns where it was moved.
+pub fn set_aside(conflict_path: &Path) -> io::Result<PathBuf> {
+    let mut merged_path = conflict_path.as_os_str().to_owned();
+    merged_path.push(".");
+    merged_path.push(MERGED_EXTENSION);
+    let merged_path = PathBuf::from(merged_path);
+    fs::rename(conflict_path, &merged_path)?;
+    Ok(merged_path)
+}
@@ -1,54 +1,368 @@
 extern crate rusqlite;
 
-use crate::bangs::{ms_to_ticks, ticks_to_ms, Bang};
-use r2d2::{CustomizeConnection, Pool};
+use crate::bangs::{ms_to_ticks, ticks_to_ms, Bang, DuplicateCriterion, FieldComparison, FieldMatch, RelatedTo};
+use crate::config::{get_config, DatabaseConfig, DatabaseStorage};
+use crate::encryption::{apply_key, apply_library_key, encrypt_if_unencrypted, resolve_key};
+use r2d2::event::{AcquireEvent, CheckoutEvent, ReleaseEvent, TimeoutEvent};
+use r2d2::{CustomizeConnection, HandleError, HandleEvent, ManageConnection, Pool, PooledConnection};
 use r2d2_sqlite::SqliteConnectionManager;
-use rusqlite::{Error, Result, NO_PARAMS, functions::FunctionFlags};
+use rusqlite::{Error, ErrorCode, OpenFlags, OptionalExtension, Result, Row, Transaction, TransactionBehavior, NO_PARAMS, functions::FunctionFlags};
 use rand::{thread_rng, Rng};
 use rand::distributions::Alphanumeric;
 use regex::Regex;
-use rusqlite::types::ToSql;
+use rusqlite::types::{FromSql, ToSql};
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf, MAIN_SEPARATOR};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
 use katatsuki::Track;
 use katatsuki::TrackFileType;
+use katatsuki::{Quality, HIRES_SAMPLE_RATE};
 use katatsuki::{ToPrimitive, FromPrimitive};
 use crate::paths::get_appdata_path;
+use crate::aliases::create_alias_table;
+use crate::art::{create_art_tables, prune_art};
+use crate::genres::{create_genre_tables, parse_genre_path, set_track_genres, subgenres_query};
+use crate::lease::create_lease_table;
+use crate::locks::create_lock_tables;
+use crate::notes::create_note_table;
+use crate::relationships::create_relation_table;
+use crate::editions::create_release_group_table;
+use crate::library::{create_file_operation_tables, create_tag_replacement_tables};
+use crate::failures::create_error_table;
+use crate::inference::create_inference_table;
+use crate::fields::create_field_table;
+use crate::provenance::create_provenance_tables;
+use crate::variants::{create_variant_table, remove_variants_under};
+#[cfg(feature = "collation")]
+use crate::collation::add_locale_collation;
+#[cfg(feature = "analysis")]
+use crate::compilations::{add_fingerprint_function, DURATION_TOLERANCE};
+use crate::profiles::create_profile_tables;
+use crate::queue::create_queue_tables;
+use crate::rejections::create_rejection_tables;
+use crate::scans::create_scan_table;
+use crate::suggestions::create_suggestion_tables;
 
 pub use rusqlite::Connection;
 
-pub type ConnectionPool = Pool<SqliteConnectionManager>;
+/// The result of a query, or of anything else done on a connection to the library.
+pub type QueryResult<T> = Result<T>;
+
+/// Opens connections to the library for a pool, checking them by reading the schema of the library,
+/// which fails if the database can no longer be read, such as when its drive was unmounted.
+#[derive(Debug)]
+pub struct SeiriConnectionManager(SqliteConnectionManager);
+
+impl ManageConnection for SeiriConnectionManager {
+    type Connection = Connection;
+    type Error = Error;
+
+    fn connect(&self) -> Result<Connection> {
+        self.0.connect()
+    }
+
+    fn is_valid(&self, conn: &mut Connection) -> Result<()> {
+        conn.query_row("SELECT COUNT(*) FROM sqlite_master", NO_PARAMS, |row| row.get::<_, i64>(0))
+            .map(|_| ())
+    }
+
+    fn has_broken(&self, conn: &mut Connection) -> bool {
+        self.0.has_broken(conn)
+    }
+}
+
+/// Counts of how the connections of a pool were used since it was created.
+#[derive(Debug, Default)]
+struct PoolMetrics {
+    opened: AtomicU64,
+    closed: AtomicU64,
+    checkouts: AtomicU64,
+    /// The total time threads waited for a connection, in microseconds.
+    waited: AtomicU64,
+    timeouts: AtomicU64,
+    errors: AtomicU64,
+}
+
+#[derive(Debug)]
+struct MetricsHandler(Arc<PoolMetrics>);
+
+impl HandleEvent for MetricsHandler {
+    fn handle_acquire(&self, _: AcquireEvent) {
+        self.0.opened.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn handle_release(&self, _: ReleaseEvent) {
+        self.0.closed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn handle_checkout(&self, event: CheckoutEvent) {
+        self.0.checkouts.fetch_add(1, Ordering::Relaxed);
+        self.0.waited.fetch_add(event.duration().as_micros() as u64, Ordering::Relaxed);
+    }
+
+    fn handle_timeout(&self, _: TimeoutEvent) {
+        self.0.timeouts.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl HandleError<Error> for MetricsHandler {
+    fn handle_error(&self, _: Error) {
+        self.0.errors.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// The state of a pool, and how its connections were used since it was created.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolStats {
+    /// The most connections the pool opens at once.
+    pub max_connections: u32,
+    /// The connections open now, in use or idle.
+    pub connections: u32,
+    pub idle_connections: u32,
+    /// The number of connections opened, including those since closed.
+    pub opened: u64,
+    pub closed: u64,
+    /// The number of times a connection was handed out.
+    pub checkouts: u64,
+    /// The average time threads waited for a connection, in microseconds.
+    pub average_wait: u64,
+    /// The number of times a thread gave up waiting for a connection.
+    pub timeouts: u64,
+    /// The number of connections that could not be opened, or failed their health check.
+    pub errors: u64,
+}
+
+/// A pool of connections to the library, shared by the threads of a process.
+///
+/// Every thread that reads or writes the library, other than for a single command, takes its
+/// connection from the pool rather than opening its own, so the number of open connections is
+/// bounded as configured. A connection is returned to the pool once it is dropped.
+#[derive(Clone)]
+pub struct ConnectionPool {
+    pool: Pool<SeiriConnectionManager>,
+    metrics: Arc<PoolMetrics>,
+    // Dropped after the pool, once its connections are closed.
+    _scratch: Option<Arc<ScratchDatabase>>,
+}
+
+/// A scratch library, which only lasts as long as the pool of connections to it.
+struct ScratchDatabase {
+    /// A connection held open for as long as the pool, since a library in memory is gone once
+    /// no connection to it is open.
+    _keep_alive: Option<Mutex<Connection>>,
+    /// The temporary file of the library, which is removed along with its journals.
+    path: Option<PathBuf>,
+}
+
+impl Drop for ScratchDatabase {
+    fn drop(&mut self) {
+        if let Some(path) = &self.path {
+            for suffix in ["", "-wal", "-shm"] {
+                let mut file = path.clone().into_os_string();
+                file.push(suffix);
+                fs::remove_file(file).ok();
+            }
+        }
+    }
+}
+
+impl ConnectionPool {
+    /// Takes a connection from the pool, waiting for one to be returned if every connection is
+    /// in use, for at most the configured timeout.
+    pub fn get(&self) -> std::result::Result<PooledConnection<SeiriConnectionManager>, r2d2::Error> {
+        self.pool.get()
+    }
+
+    /// Takes a connection from the pool if one is idle or can be opened, without waiting.
+    pub fn try_get(&self) -> Option<PooledConnection<SeiriConnectionManager>> {
+        self.pool.try_get()
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        let state = self.pool.state();
+        let checkouts = self.metrics.checkouts.load(Ordering::Relaxed);
+        PoolStats {
+            max_connections: self.pool.max_size(),
+            connections: state.connections,
+            idle_connections: state.idle_connections,
+            opened: self.metrics.opened.load(Ordering::Relaxed),
+            closed: self.metrics.closed.load(Ordering::Relaxed),
+            checkouts,
+            average_wait: self.metrics.waited.load(Ordering::Relaxed) / checkouts.max(1),
+            timeouts: self.metrics.timeouts.load(Ordering::Relaxed),
+            errors: self.metrics.errors.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct SeiriConnectionCustomizer {
+    /// The key the library is encrypted with, if it is encrypted.
+    key: Option<String>,
+    /// The locale names are collated by.
+    #[cfg_attr(not(feature = "collation"), allow(dead_code))]
+    locale: Option<String>,
+}
 
-#[derive(Copy, Clone, Debug)]
-struct SeiriConnectionCustomizer;
 impl CustomizeConnection<Connection, Error> for SeiriConnectionCustomizer {
     fn on_acquire(&self, conn: &mut Connection) -> Result<()> {
+        if let Some(key) = &self.key {
+            apply_key(conn, key)?;
+        }
         enable_wal_mode(conn).unwrap();
+        enable_foreign_keys(conn).unwrap();
         add_regexp_function(conn).unwrap();
+        #[cfg(feature = "analysis")]
+        add_fingerprint_function(conn).unwrap();
+        #[cfg(feature = "collation")]
+        add_locale_collation(conn, self.locale.as_deref()).unwrap();
         create_database(conn);
         Ok(())
     }
 }
 
-pub fn get_database_connection() -> Connection {
+/// The collation names of artists and albums are ordered by when they are listed, by locale with
+/// the `collation` feature, and ignoring case otherwise.
+#[cfg(feature = "collation")]
+pub const BROWSE_COLLATION: &str = crate::collation::LOCALE_COLLATION;
+#[cfg(not(feature = "collation"))]
+pub const BROWSE_COLLATION: &str = "NOCASE";
+
+/// Gets the path of the library database.
+pub fn get_database_path() -> PathBuf {
     let mut database_path = get_appdata_path();
     database_path.push("tracks.db");
-    let conn = Connection::open(database_path.as_path()).unwrap();
+    database_path
+}
+
+pub fn get_database_connection() -> Connection {
+    let conn = Connection::open(get_database_path()).unwrap();
+    apply_library_key(&conn).unwrap();
     enable_wal_mode(&conn).unwrap();
+    enable_foreign_keys(&conn).unwrap();
     add_regexp_function(&conn).unwrap();
+    #[cfg(feature = "analysis")]
+    add_fingerprint_function(&conn).unwrap();
+    #[cfg(feature = "collation")]
+    add_locale_collation(&conn, get_config().ok().and_then(|config| config.database.locale).as_deref()).unwrap();
     create_database(&conn);
     conn
 }
 
 pub fn get_connection_pool() -> ConnectionPool {
-    let mut database_path = get_appdata_path();
-    database_path.push("tracks.db");
-    let manager = SqliteConnectionManager::file(&database_path);
+    get_configured_connection_pool(&DatabaseConfig::default())
+}
+
+/// Gets a name for a scratch library that no other scratch library has.
+pub(crate) fn scratch_name() -> String {
+    let suffix = thread_rng().sample_iter(&Alphanumeric).take(16).collect::<String>();
+    format!("seiri-scratch-{}-{}", std::process::id(), suffix)
+}
+
+/// A path of its own in the temporary folder with the extension, removed once the test is done
+/// with it.
+#[cfg(test)]
+pub(crate) struct ScratchFile(pub(crate) PathBuf);
+
+#[cfg(test)]
+impl ScratchFile {
+    pub(crate) fn new(extension: &str) -> ScratchFile {
+        ScratchFile(std::env::temp_dir().join(format!("{}.{}", scratch_name(), extension)))
+    }
+}
+
+#[cfg(test)]
+impl Drop for ScratchFile {
+    fn drop(&mut self) {
+        fs::remove_file(&self.0).ok();
+    }
+}
+
+/// An empty folder of its own in the temporary folder, removed once the test is done with it.
+#[cfg(test)]
+pub(crate) struct ScratchFolder(pub(crate) PathBuf);
+
+#[cfg(test)]
+impl ScratchFolder {
+    pub(crate) fn new() -> ScratchFolder {
+        let path = std::env::temp_dir().join(scratch_name());
+        fs::create_dir_all(&path).unwrap();
+        ScratchFolder(path)
+    }
+}
+
+#[cfg(test)]
+impl Drop for ScratchFolder {
+    fn drop(&mut self) {
+        fs::remove_dir_all(&self.0).ok();
+    }
+}
+
+/// Gets a pool of connections to the library, sized and checked as configured.
+///
+/// A scratch library is created with the whole schema of the library, see `DatabaseStorage`.
+///
+/// Panics if the library is encrypted and its key can not be resolved, or is the wrong key.
+pub fn get_configured_connection_pool(config: &DatabaseConfig) -> ConnectionPool {
+    set_query_limits(config);
+    let key = resolve_key(config).unwrap();
+    let mut max_connections = config.max_connections.max(1);
+    let (manager, scratch) = match config.storage {
+        DatabaseStorage::Library => {
+            let database_path = get_database_path();
+            if let Some(key) = &key {
+                encrypt_if_unencrypted(&database_path, key).unwrap();
+            }
+            (SqliteConnectionManager::file(&database_path), None)
+        }
+        DatabaseStorage::Memory => {
+            // Connections to a library in memory lock each other out rather than wait, so
+            // there is only one.
+            max_connections = 1;
+            let uri = format!("file:{}?mode=memory&cache=shared", scratch_name());
+            let flags = OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_CREATE | OpenFlags::SQLITE_OPEN_URI;
+            let keep_alive = Connection::open_with_flags(&uri, flags).unwrap();
+            let scratch = ScratchDatabase {
+                _keep_alive: Some(Mutex::new(keep_alive)),
+                path: None,
+            };
+            (SqliteConnectionManager::file(&uri).with_flags(flags), Some(scratch))
+        }
+        DatabaseStorage::Temporary => {
+            let database_path = std::env::temp_dir().join(format!("{}.db", scratch_name()));
+            let scratch = ScratchDatabase {
+                _keep_alive: None,
+                path: Some(database_path.clone()),
+            };
+            (SqliteConnectionManager::file(&database_path), Some(scratch))
+        }
+    };
+    let metrics = Arc::new(PoolMetrics::default());
     let pool = Pool::builder()
-        .connection_customizer(Box::new(SeiriConnectionCustomizer))
-        .build(manager)
+        .max_size(max_connections)
+        .min_idle(Some(config.min_connections.min(max_connections)))
+        .connection_timeout(Duration::from_secs(config.connection_timeout.max(1)))
+        .test_on_check_out(config.health_checks)
+        .event_handler(Box::new(MetricsHandler(Arc::clone(&metrics))))
+        .error_handler(Box::new(MetricsHandler(Arc::clone(&metrics))))
+        .connection_customizer(Box::new(SeiriConnectionCustomizer {
+            key,
+            locale: config.locale.clone(),
+        }))
+        .build(SeiriConnectionManager(manager))
         .unwrap();
-    pool
+    ConnectionPool {
+        pool,
+        metrics,
+        _scratch: scratch.map(Arc::new),
+    }
 }
 
 
@@ -65,6 +379,62 @@ fn escape_regex_search(string: &str) -> String {
           .replace('^', r"\^")
 }
 
+/// The pattern `AlbumArtists` is matched against to partially match an album artist.
+pub(crate) fn album_artists_pattern(artist: &str) -> String {
+    format!("(?:^|;)(?:.*?)((?i){})(?:.*?)(?:;|$)", escape_regex_search(artist))
+}
+
+/// The pattern `AlbumArtists` is matched against to exactly match an album artist.
+pub(crate) fn album_artists_exact_pattern(artist: &str) -> String {
+    format!("(?:^|;)({})(?:;|$)", escape_regex_search(artist))
+}
+
+/// Normalizes an ISRC as written with or without its hyphens, such as `US-RC1-76-07839`,
+/// to the form `Isrc` is compared in.
+pub(crate) fn normalize_isrc(isrc: &str) -> String {
+    isrc.chars()
+        .filter(|c| *c != '-' && *c != ' ')
+        .collect::<String>()
+        .to_ascii_uppercase()
+}
+
+/// The condition matching tracks of the given quality, classified the same way as `Quality::of`.
+pub(crate) fn quality_condition(quality: Quality) -> String {
+    let conditions = TrackFileType::ALL
+        .iter()
+        .filter_map(|file_type| {
+            let id = file_type.to_i32().unwrap();
+            if file_type.is_lossless() {
+                let hires_depth = file_type.bit_depth().map(|depth| depth > 16).unwrap_or(false);
+                match (quality, hires_depth) {
+                    (Quality::LosslessHiRes, true) => Some(format!("FileType = {}", id)),
+                    (Quality::LosslessHiRes, false) => Some(format!(
+                        "(FileType = {} AND SampleRate > {})",
+                        id, HIRES_SAMPLE_RATE
+                    )),
+                    (Quality::Lossless, false) => Some(format!(
+                        "(FileType = {} AND SampleRate <= {})",
+                        id, HIRES_SAMPLE_RATE
+                    )),
+                    _ => None,
+                }
+            } else {
+                let (high, mid) = file_type.lossy_thresholds()?;
+                match quality {
+                    Quality::LossyHigh => Some(format!("(FileType = {} AND Bitrate >= {})", id, high)),
+                    Quality::LossyMid => Some(format!(
+                        "(FileType = {} AND Bitrate >= {} AND Bitrate < {})",
+                        id, mid, high
+                    )),
+                    Quality::LossyLow => Some(format!("(FileType = {} AND Bitrate < {})", id, mid)),
+                    _ => None,
+                }
+            }
+        })
+        .collect::<Vec<String>>();
+    format!("({})", conditions.join(" OR "))
+}
+
 #[allow(dead_code)]
 pub fn add_regexp_function(db: &Connection) -> Result<()> {
     let mut cached_regexes = HashMap::new();
@@ -96,6 +466,9 @@ pub fn add_regexp_function(db: &Connection) -> Result<()> {
 
 #[allow(dead_code)]
 pub fn create_database(conn: &Connection) {
+    // This only takes effect before the first table is created, after which `maintain`
+    // enables it for libraries created before it was set.
+    conn.execute_batch("PRAGMA auto_vacuum = INCREMENTAL").unwrap();
     conn.execute(
         "CREATE TABLE IF NOT EXISTS tracks ( 
         FilePath TEXT PRIMARY KEY,
@@ -115,10 +488,259 @@ pub fn create_database(conn: &Connection) {
         DiscNumber INTEGER,
         Duration INTEGER,
         FileType INTEGER,
-        Updated DATE
+        Updated DATE,
+        TrackId TEXT,
+        Comment TEXT,
+        Isrc TEXT,
+        Encoder TEXT
     )",
         NO_PARAMS,
     ).unwrap();
+    create_track_ids(conn);
+    create_tag_columns(conn);
+    create_change_log(conn);
+    create_profile_tables(conn);
+    create_lease_table(conn);
+    create_alias_table(conn);
+    create_genre_tables(conn);
+    create_queue_tables(conn);
+    create_rejection_tables(conn);
+    create_analysis_tables(conn);
+    create_lock_tables(conn);
+    create_art_tables(conn);
+    create_note_table(conn);
+    create_relation_table(conn);
+    create_release_group_table(conn);
+    create_scan_table(conn);
+    create_file_operation_tables(conn);
+    create_tag_replacement_tables(conn);
+    create_inference_table(conn);
+    create_field_table(conn);
+    create_variant_table(conn);
+    create_provenance_tables(conn);
+    create_error_table(conn);
+    create_suggestion_tables(conn);
+}
+
+/// Creates the tables holding the results of the analysis jobs. These are created even without
+/// the analysis feature, so libraries analyzed elsewhere can still be searched by the results.
+fn create_analysis_tables(conn: &Connection) {
+    // Updated is the update time of the track when it was analyzed.
+    create_table_with_foreign_keys(
+        "waveforms",
+        "TrackId TEXT PRIMARY KEY REFERENCES tracks(TrackId) ON DELETE CASCADE,
+        Peaks BLOB NOT NULL,
+        Updated DATE",
+        conn,
+    )
+    .unwrap();
+    create_table_with_foreign_keys(
+        "spectra",
+        "TrackId TEXT PRIMARY KEY REFERENCES tracks(TrackId) ON DELETE CASCADE,
+        Cutoff INTEGER,
+        Suspect INTEGER NOT NULL,
+        Updated DATE",
+        conn,
+    )
+    .unwrap();
+    create_table_with_foreign_keys(
+        "gains",
+        "TrackId TEXT PRIMARY KEY REFERENCES tracks(TrackId) ON DELETE CASCADE,
+        TrackGain REAL,
+        TrackPeak REAL,
+        AlbumGain REAL,
+        AlbumPeak REAL,
+        Updated DATE",
+        conn,
+    )
+    .unwrap();
+}
+
+/// The SQL condition under which the `other` track is a duplicate of the track by the criterion.
+/// It is never true of tracks missing the column, such as tracks without an ISRC.
+fn duplicate_condition(criterion: DuplicateCriterion) -> String {
+    let equal = |column: &str| format!("other.{column} = tracks.{column}");
+    match criterion {
+        DuplicateCriterion::Title => equal("Title"),
+        DuplicateCriterion::Artist => equal("Artist"),
+        DuplicateCriterion::AlbumArtists => equal("AlbumArtists"),
+        DuplicateCriterion::Album => equal("Album"),
+        DuplicateCriterion::TrackNumber => equal("TrackNumber"),
+        DuplicateCriterion::DiscNumber => equal("DiscNumber"),
+        DuplicateCriterion::Year => equal("Year"),
+        // Durations are stored in ticks.
+        DuplicateCriterion::Duration => format!("ABS(other.Duration - tracks.Duration) <= {}", ms_to_ticks(1000)),
+        DuplicateCriterion::Isrc => "REPLACE(REPLACE(UPPER(other.Isrc), '-', ''), ' ', '') \
+            = REPLACE(REPLACE(UPPER(tracks.Isrc), '-', ''), ' ', '')".to_owned(),
+        DuplicateCriterion::MusicbrainzId => equal("MusicBrainzTrackId"),
+        // Waveforms are compared like the tracks of a compilation are matched to the library,
+        // so copies of a recording encoded or trimmed differently are still duplicates.
+        #[cfg(feature = "analysis")]
+        DuplicateCriterion::Fingerprint => format!(
+            "ABS(other.Duration - tracks.Duration) <= {} AND fingerprints_match(\
+                (SELECT Peaks FROM waveforms WHERE waveforms.TrackId = other.TrackId), \
+                (SELECT Peaks FROM waveforms WHERE waveforms.TrackId = tracks.TrackId))",
+            ms_to_ticks(DURATION_TOLERANCE)
+        ),
+        // Without the analysis feature no waveforms are taken, so no track is a duplicate by them.
+        #[cfg(not(feature = "analysis"))]
+        DuplicateCriterion::Fingerprint => "0".to_owned(),
+    }
+}
+
+/// An SQL expression generating a random version 4 UUID.
+const NEW_UUID: &str = "(lower(hex(randomblob(4))) || '-' || lower(hex(randomblob(2))) || '-4'
+    || substr(lower(hex(randomblob(2))), 2) || '-'
+    || substr('89ab', 1 + (abs(random()) % 4), 1) || substr(lower(hex(randomblob(2))), 2) || '-'
+    || lower(hex(randomblob(6))))";
+
+/// Ensures every track has a UUID, adding the TrackId column to libraries created before it existed.
+fn create_track_ids(conn: &Connection) {
+    // Every connection in a pool runs this as it is opened, so the column is checked for
+    // and added within a single write transaction.
+    let transaction = Transaction::new_unchecked(conn, TransactionBehavior::Immediate).unwrap();
+    let has_track_ids = transaction
+        .prepare("SELECT 1 FROM pragma_table_info('tracks') WHERE name = 'TrackId'")
+        .and_then(|mut statement| statement.exists(NO_PARAMS))
+        .unwrap();
+    if !has_track_ids {
+        transaction.execute_batch("ALTER TABLE tracks ADD COLUMN TrackId TEXT").unwrap();
+    }
+    transaction.execute_batch(&format!(
+        "UPDATE tracks SET TrackId = {} WHERE TrackId IS NULL;
+        CREATE UNIQUE INDEX IF NOT EXISTS tracks_track_id ON tracks(TrackId);",
+        NEW_UUID
+    ))
+    .unwrap();
+    transaction.commit().unwrap();
+}
+
+/// The columns of tags added to the tracks table after it was first created, in the order they were added.
+const TAG_COLUMNS: &[&str] = &["Comment", "Isrc", "Encoder"];
+
+/// Adds the columns of `TAG_COLUMNS` to libraries created before they existed. The tags of tracks
+/// already in the library are read when the tracks are next read.
+fn create_tag_columns(conn: &Connection) {
+    let transaction = Transaction::new_unchecked(conn, TransactionBehavior::Immediate).unwrap();
+    for column in TAG_COLUMNS {
+        let has_column = transaction
+            .prepare("SELECT 1 FROM pragma_table_info('tracks') WHERE name = ?1")
+            .and_then(|mut statement| statement.exists(&[column]))
+            .unwrap();
+        if !has_column {
+            transaction
+                .execute_batch(&format!("ALTER TABLE tracks ADD COLUMN {} TEXT", column))
+                .unwrap();
+        }
+    }
+    transaction.commit().unwrap();
+}
+
+/// Creates a table with foreign keys to other tables, if it does not exist.
+///
+/// Foreign keys can not be added to an existing table, so a table created by an older
+/// version of seiri without them is copied into a new table that has them,
+/// dropping every row that references a row that no longer exists.
+pub(crate) fn create_table_with_foreign_keys(name: &str, columns: &str, conn: &Connection) -> Result<()> {
+    conn.execute_batch(&format!("CREATE TABLE IF NOT EXISTS {} ({})", name, columns))?;
+    let has_foreign_keys = |conn: &Connection| {
+        conn.prepare("SELECT 1 FROM pragma_foreign_key_list(?1)")?
+            .exists(&[name])
+    };
+    if has_foreign_keys(conn)? {
+        return Ok(());
+    }
+
+    // Foreign keys can only be turned off outside of a transaction, and must be off
+    // while the old table is dropped so its rows are not cascaded.
+    conn.execute_batch("PRAGMA foreign_keys = OFF")?;
+    let result = Transaction::new_unchecked(conn, TransactionBehavior::Immediate).and_then(|transaction| {
+        // Another connection may have rebuilt the table in the meantime.
+        if !has_foreign_keys(&transaction)? {
+            transaction.execute_batch(&format!(
+                "CREATE TABLE {name}_rebuilt ({columns});
+                INSERT INTO {name}_rebuilt SELECT * FROM {name};
+                DELETE FROM {name}_rebuilt WHERE rowid IN
+                    (SELECT rowid FROM pragma_foreign_key_check('{name}_rebuilt'));
+                DROP TABLE {name};
+                ALTER TABLE {name}_rebuilt RENAME TO {name};",
+                name = name,
+                columns = columns
+            ))?;
+        }
+        transaction.commit()
+    });
+    conn.execute_batch("PRAGMA foreign_keys = ON")?;
+    result
+}
+
+/// The trigger logging updates to the tracks table, as it is stored in `sqlite_master`.
+///
+/// A moved track is logged as removed from its old path, so replicas do not keep it there.
+const TRACKS_LOG_UPDATE: &str = "CREATE TRIGGER tracks_log_update AFTER UPDATE ON tracks BEGIN
+        INSERT INTO changes(FilePath, Operation) SELECT OLD.FilePath, 2 WHERE OLD.FilePath IS NOT NEW.FilePath;
+        INSERT INTO changes(FilePath, Operation) VALUES (NEW.FilePath, 1);
+    END";
+
+/// Whether the library logs updates with a trigger other than `TRACKS_LOG_UPDATE`, such as
+/// libraries made before moves were logged as removals, which logged them with a trigger of its own.
+fn has_outdated_update_log(conn: &Connection) -> Result<bool> {
+    let has_move_log = conn
+        .prepare("SELECT 1 FROM sqlite_master WHERE type = 'trigger' AND name = 'tracks_log_move'")?
+        .exists(NO_PARAMS)?;
+    let update_log = conn
+        .query_row(
+            "SELECT sql FROM sqlite_master WHERE type = 'trigger' AND name = 'tracks_log_update'",
+            NO_PARAMS,
+            |row| row.get::<_, String>(0),
+        )
+        .optional()?;
+    Ok(has_move_log || update_log.as_deref() != Some(TRACKS_LOG_UPDATE))
+}
+
+/// The change log records every write to the tracks table, so that
+/// read-only replicas can catch up from a snapshot without a full export.
+fn create_change_log(conn: &Connection) {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS changes (
+        Version INTEGER PRIMARY KEY AUTOINCREMENT,
+        FilePath TEXT NOT NULL,
+        Operation INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS replicas (
+        Name TEXT PRIMARY KEY,
+        ChangeVersion INTEGER NOT NULL
+    );
+    CREATE TRIGGER IF NOT EXISTS tracks_log_insert AFTER INSERT ON tracks BEGIN
+        INSERT INTO changes(FilePath, Operation) VALUES (NEW.FilePath, 0);
+    END;
+    CREATE TRIGGER IF NOT EXISTS tracks_log_delete AFTER DELETE ON tracks BEGIN
+        INSERT INTO changes(FilePath, Operation) VALUES (OLD.FilePath, 2);
+    END;",
+    ).unwrap();
+    if !has_outdated_update_log(conn).unwrap() {
+        return;
+    }
+
+    // Every connection in a pool runs this as it is opened, so the trigger is replaced within a
+    // single write transaction, and no update lands while there is none.
+    let transaction = Transaction::new_unchecked(conn, TransactionBehavior::Immediate).unwrap();
+    if has_outdated_update_log(&transaction).unwrap() {
+        transaction
+            .execute_batch(&format!(
+                "DROP TRIGGER IF EXISTS tracks_log_move;
+                DROP TRIGGER IF EXISTS tracks_log_update;
+                {};",
+                TRACKS_LOG_UPDATE
+            ))
+            .unwrap();
+    }
+    transaction.commit().unwrap();
+}
+
+/// Enforces foreign keys, so that removing a track or a profile removes everything referencing it.
+pub fn enable_foreign_keys(conn: &Connection) -> Result<()> {
+    conn.execute_batch("PRAGMA foreign_keys = ON")
 }
 
 #[allow(dead_code)]
@@ -128,6 +750,118 @@ pub fn enable_wal_mode(conn: &Connection) -> Result<()> {
     Ok(())
 }
 
+/// How long searches of the library may run, as configured in `DatabaseConfig`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct QueryLimits {
+    /// How long a search runs before it is interrupted.
+    pub timeout: Option<Duration>,
+    /// How long a search runs before it is written to the slow query log.
+    pub slow_query_threshold: Option<Duration>,
+}
+
+impl QueryLimits {
+    pub fn from_config(config: &DatabaseConfig) -> QueryLimits {
+        let millis = |millis: u64| Some(Duration::from_millis(millis)).filter(|_| millis > 0);
+        QueryLimits {
+            timeout: millis(config.query_timeout),
+            slow_query_threshold: millis(config.slow_query_threshold),
+        }
+    }
+}
+
+static QUERY_LIMITS: OnceLock<QueryLimits> = OnceLock::new();
+
+/// Limits every search of the process as configured, unless searches were already limited.
+/// Returns whether the limits were set.
+pub fn set_query_limits(config: &DatabaseConfig) -> bool {
+    QUERY_LIMITS.set(QueryLimits::from_config(config)).is_ok()
+}
+
+/// Gets the limits of every search of the process. Unless they were set with `set_query_limits`,
+/// they are read from the configuration once, or are the defaults if it can not be read.
+pub fn query_limits() -> QueryLimits {
+    *QUERY_LIMITS.get_or_init(|| {
+        QueryLimits::from_config(&get_config().map(|config| config.database).unwrap_or_default())
+    })
+}
+
+/// Gets the path of the slow query log, where every search that ran for longer than the slow query
+/// threshold or timed out is written as a line of the time it ran at, how long it ran for in
+/// milliseconds, whether it timed out, its bang query and its SQL, separated by tabs.
+pub fn get_slow_query_log_path() -> PathBuf {
+    let mut log_path = get_appdata_path();
+    log_path.push("slow_queries.log");
+    log_path
+}
+
+fn log_slow_query(query: &str, sql: &str, duration: Duration, timed_out: bool) {
+    let line = format!(
+        "{}\t{}\t{}\t{}\t{}\n",
+        chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
+        duration.as_millis(),
+        if timed_out { "timeout" } else { "slow" },
+        query.replace(['\t', '\n'], " "),
+        sql.split_whitespace().collect::<Vec<&str>>().join(" ")
+    );
+    // A search is never failed because it could not be logged.
+    if let Ok(mut log) = OpenOptions::new().create(true).append(true).open(get_slow_query_log_path()) {
+        log.write_all(line.as_bytes()).ok();
+    }
+}
+
+/// Runs the statements on the connection, interrupting them once they run for longer than the timeout.
+fn interrupt_after<T, F>(timeout: Option<Duration>, conn: &Connection, run: F) -> Result<T>
+where
+    F: FnOnce() -> Result<T>,
+{
+    let timer = timeout.map(|timeout| {
+        let handle = conn.get_interrupt_handle();
+        let (finished, until_finished) = mpsc::channel::<()>();
+        let timer = thread::spawn(move || {
+            if let Err(RecvTimeoutError::Timeout) = until_finished.recv_timeout(timeout) {
+                handle.interrupt();
+            }
+        });
+        (finished, timer)
+    });
+    let result = run();
+    if let Some((finished, timer)) = timer {
+        // The timer is waited for, so it can not interrupt whatever runs on the connection next.
+        drop(finished);
+        timer.join().ok();
+    }
+    result
+}
+
+/// Runs the search of the tracks matching the bang query with the SQL, interrupting it once it
+/// runs for longer than the timeout, and logging it if it is slow or times out.
+fn run_limited<T, F>(query: &str, sql: &str, conn: &Connection, run: F) -> Result<T>
+where
+    F: FnOnce() -> Result<T>,
+{
+    let limits = query_limits();
+    let started = Instant::now();
+    let result = interrupt_after(limits.timeout, conn, run);
+    let duration = started.elapsed();
+    let timed_out = limits.timeout.is_some()
+        && matches!(&result, Err(Error::SqliteFailure(error, _)) if error.code == ErrorCode::OperationInterrupted);
+    if timed_out || limits.slow_query_threshold.is_some_and(|threshold| duration >= threshold) {
+        log_slow_query(query, sql, duration, timed_out);
+    }
+    match result {
+        Err(Error::SqliteFailure(error, _)) if timed_out => Err(Error::SqliteFailure(
+            error,
+            Some(format!("The search timed out after {} ms", duration.as_millis())),
+        )),
+        result => result,
+    }
+}
+
+/// The bang query of the bang as written to the slow query log.
+fn logged_query(bang: &Bang) -> String {
+    bang.to_query().unwrap_or_else(|| format!("{:?}", bang))
+}
+
 #[allow(dead_code)]
 pub fn query_tracks(
     bang: Bang,
@@ -135,66 +869,120 @@ pub fn query_tracks(
     limit: Option<i32>,
     offset: Option<i32>,
 ) -> Result<Vec<Track>> {
+    let logged = logged_query(&bang);
     let mut params = Vec::<(String, String)>::new();
-    let mut query = if let Bang::All = bang {
-        "SELECT * FROM tracks".to_string()
-    } else {
-        format!(
-            "SELECT * FROM tracks WHERE ({})",
-            to_query_string(bang, &mut params)
-        )
-    };
+    let mut query = select_query(TRACK_COLUMNS, bang, &mut params);
+
+    query.push_str(" ORDER BY CASE WHEN AlbumArtists = 'Various Artists' THEN 1 END, AlbumArtists,Album,TrackNumber");
 
-    if let Some(limit) = limit {
-        query.push_str(&format!(" LIMIT {}", limit));
+    // SQLite only allows an offset after a limit, where a negative limit means no limit.
+    if limit.is_some() || offset.is_some() {
+        query.push_str(&format!(" LIMIT {}", limit.unwrap_or(-1)));
     }
 
     if let Some(offset) = offset {
         query.push_str(&format!(" OFFSET {}", offset));
     }
 
-    query.push_str(" ORDER BY CASE WHEN AlbumArtists = 'Various Artists' THEN 1 END, AlbumArtists,Album,TrackNumber");
-
-    let mut tracks = Vec::<Track>::new();
-    println!("Executing query: {:?}", query);
     let mut statement = conn.prepare(&query)?;
-    println!("Preparing parameters: {:?}", params);
 
     let params = params
         .iter()
         .map(|c| (c.0.as_ref(), &c.1 as &dyn ToSql))
         .collect::<Vec<(&str, &dyn ToSql)>>();
 
-    let mut rows = statement.query_named(params.as_slice())?;
-    while let Ok(Some(row)) = rows.next() {
-        let track = Track {
-            file_path: PathBuf::from(&row.get::<_, String>(0)?),
-            title: row.get(1)?,
-            artist: row.get(2)?,
-            album_artists: row.get::<_, String>(3)?
+    run_limited(&logged, &query, conn, || {
+        let mut tracks = Vec::<Track>::new();
+        let mut rows = statement.query_named(params.as_slice())?;
+        while let Some(row) = rows.next()? {
+            tracks.push(track_from_row(row)?)
+        }
+        Ok(tracks)
+    })
+}
+
+/// Builds the query selecting the columns of the tracks matching the bang.
+fn select_query(columns: &str, bang: Bang, params: &mut Vec<(String, String)>) -> String {
+    if let Bang::All = bang {
+        format!("SELECT {} FROM tracks", columns)
+    } else {
+        format!("SELECT {} FROM tracks WHERE ({})", columns, to_query_string(bang, params))
+    }
+}
+
+/// Runs the query of a single value, with the named parameters.
+fn query_value<T: FromSql>(query: &str, params: &[(String, String)], conn: &Connection) -> Result<T> {
+    let params = params
+        .iter()
+        .map(|c| (c.0.as_ref(), &c.1 as &dyn ToSql))
+        .collect::<Vec<(&str, &dyn ToSql)>>();
+    conn.query_row_named(query, params.as_slice(), |row| row.get(0))
+}
+
+/// Counts the tracks matching the bang, without reading any of them.
+pub fn count(bang: Bang, conn: &Connection) -> Result<i64> {
+    let logged = logged_query(&bang);
+    let mut params = Vec::<(String, String)>::new();
+    let query = select_query("COUNT(*)", bang, &mut params);
+    run_limited(&logged, &query, conn, || query_value(&query, &params, conn))
+}
+
+/// Whether any track matches the bang, which stops at the first match.
+pub fn exists(bang: Bang, conn: &Connection) -> Result<bool> {
+    let logged = logged_query(&bang);
+    let mut params = Vec::<(String, String)>::new();
+    let query = format!("SELECT EXISTS ({} LIMIT 1)", select_query("1", bang, &mut params));
+    run_limited(&logged, &query, conn, || query_value(&query, &params, conn))
+}
+
+/// The columns read by `track_from_row`, which are every column of the tracks table
+/// followed by the genres of the track.
+pub(crate) const TRACK_COLUMNS: &str = "tracks.*, (SELECT group_concat(Genre, ';') FROM track_genres
+    WHERE track_genres.FilePath = tracks.FilePath) AS Genres";
+
+/// Reads a track from a row of `TRACK_COLUMNS`.
+pub(crate) fn track_from_row(row: &Row) -> Result<Track> {
+    let file_type = TrackFileType::from_i32(row.get::<_, i32>(16)?).unwrap_or(TrackFileType::Unknown);
+    Ok(Track::builder(row.get::<_, String>(0)?, file_type)
+        .title(row.get::<_, String>(1)?)
+        .artist(row.get::<_, String>(2)?)
+        .album_artists(
+            row.get::<_, String>(3)?
                 .split(';')
                 .map(|c| c.to_owned())
                 .collect::<Vec<String>>(),
-            album: row.get(4)?,
-            year: row.get(5)?,
-            track_number: row.get(6)?,
-            musicbrainz_track_id: row.get(7).ok(),
-            has_front_cover: row.get(8)?,
-            front_cover_width: row.get(9).ok().unwrap_or(0),
-            front_cover_height: row.get(10).ok().unwrap_or(0),
-            bitrate: row.get(11)?,
-            sample_rate: row.get(12)?,
-            source: row.get(13).ok().unwrap_or("None".to_owned()),
-            disc_number: row.get(14)?,
-            duration: ticks_to_ms(row.get(15)?),
-            file_type: TrackFileType::from_i32(row.get::<_, i32>(16)?)
-                .unwrap_or(TrackFileType::Unknown),
-            updated: row.get::<_, String>(17)?
-        };
-        tracks.push(track)
-    }
+        )
+        .album(row.get::<_, String>(4)?)
+        .year(row.get(5)?)
+        .track_number(row.get(6)?)
+        .musicbrainz_track_id(row.get(7).ok())
+        .comment(row.get(19)?)
+        .isrc(row.get(20)?)
+        .encoder(row.get(21)?)
+        .genres(
+            row.get::<_, Option<String>>(22)?
+                .map(|genres| genres.split(';').map(|genre| genre.to_owned()).collect())
+                .unwrap_or_default(),
+        )
+        .front_cover(row.get(8)?, row.get(9).ok().unwrap_or(0), row.get(10).ok().unwrap_or(0))
+        .bitrate(row.get(11)?)
+        .sample_rate(row.get(12)?)
+        .source(row.get::<_, String>(13).ok().as_deref())
+        .disc_number(row.get(14)?)
+        .duration(ticks_to_ms(row.get(15)?))
+        .updated(row.get::<_, String>(17)?)
+        .uuid(row.get(18).ok())
+        .build())
+}
 
-    Ok(tracks)
+/// Gets the track with the given UUID, wherever its file is.
+pub fn get_track_by_uuid(uuid: &str, conn: &Connection) -> Result<Option<Track>> {
+    conn.query_row(
+        &format!("SELECT {} FROM tracks WHERE TrackId = ?1", TRACK_COLUMNS),
+        &[uuid],
+        track_from_row,
+    )
+    .optional()
 }
 
 #[allow(dead_code)]
@@ -235,15 +1023,30 @@ fn to_query_string(bang: Bang, params: &mut Vec<(String, String)>) -> String {
             params.push((param_name, title));
             format
         }
+        // Artists also match under every name of an artist with aliases matching the search,
+        // but only tracks by exactly one of those names, so a short name does not match every
+        // artist whose name contains it.
         Bang::Artist(artist) => {
             let param_name = get_rand_param();
-            let format = format!("(Artist LIKE {})", param_name);
+            let format = format!(
+                "(Artist LIKE {param} OR EXISTS (SELECT 1 FROM artist_aliases AS matched
+                    JOIN artist_aliases AS spelling ON spelling.Artist = matched.Artist
+                    WHERE (matched.Alias LIKE {param} OR matched.Artist LIKE {param})
+                    AND (tracks.Artist = spelling.Alias OR tracks.Artist = spelling.Artist)))",
+                param = param_name
+            );
             params.push((param_name, format!("%{}%", artist)));
             format
         }
         Bang::ArtistExact(artist) => {
             let param_name = get_rand_param();
-            let format = format!("(Artist = {})", param_name);
+            let format = format!(
+                "(Artist = {param} OR EXISTS (SELECT 1 FROM artist_aliases AS matched
+                    JOIN artist_aliases AS spelling ON spelling.Artist = matched.Artist
+                    WHERE (matched.Alias = {param} OR matched.Artist = {param})
+                    AND (tracks.Artist = spelling.Alias OR tracks.Artist = spelling.Artist)))",
+                param = param_name
+            );
             params.push((param_name, format!("{}", artist)));
             format
         }
@@ -251,16 +1054,13 @@ fn to_query_string(bang: Bang, params: &mut Vec<(String, String)>) -> String {
         Bang::AlbumArtists(artist) => {
             let param_name = get_rand_param();
             let format = format!("(AlbumArtists REGEXP {})", param_name);
-            params.push((
-                param_name,
-                format!("(?:^|;)(?:.*?)((?i){})(?:.*?)(?:;|$)", escape_regex_search(&artist)),
-            ));
+            params.push((param_name, album_artists_pattern(&artist)));
             format
         }
         Bang::AlbumArtistsExact(artist) => {
             let param_name = get_rand_param();
             let format = format!("(AlbumArtists REGEXP {})", param_name);
-            params.push((param_name, format!("(?:^|;)({})(?:;|$)", escape_regex_search(&artist))));
+            params.push((param_name, album_artists_exact_pattern(&artist)));
             format
         }
         Bang::Source(source) => {
@@ -269,6 +1069,21 @@ fn to_query_string(bang: Bang, params: &mut Vec<(String, String)>) -> String {
             params.push((param_name, format!("{}", source)));
             format
         }
+        // Genres are matched by the genre tree, and tracks are of every genre they are tagged with.
+        Bang::Genre(path) => {
+            let param_name = get_rand_param();
+            let (genre, subgenres) = parse_genre_path(&path);
+            let format = if subgenres {
+                format!(
+                    "(FilePath IN (SELECT FilePath FROM track_genres WHERE Genre IN ({})))",
+                    subgenres_query(&param_name)
+                )
+            } else {
+                format!("(FilePath IN (SELECT FilePath FROM track_genres WHERE Genre = {}))", param_name)
+            };
+            params.push((param_name, genre.to_owned()));
+            format
+        }
         Bang::Format(filetype) => {
 
             match filetype {
@@ -320,6 +1135,7 @@ fn to_query_string(bang: Bang, params: &mut Vec<(String, String)>) -> String {
                 }
             }
         }
+        Bang::Quality(quality) => quality_condition(quality),
         Bang::BitrateLessThan(bitrate) => {
             let param_name = get_rand_param();
             let format = format!("(Bitrate < {})", param_name);
@@ -383,7 +1199,8 @@ fn to_query_string(bang: Bang, params: &mut Vec<(String, String)>) -> String {
         Bang::HasCoverArt(has) => {
             let param_name = get_rand_param();
             let format = format!("(HasFrontCover = {})", param_name);
-            params.push((param_name, format!("{}", has)));
+            // Booleans are stored as integers, and would never equal the string "true".
+            params.push((param_name, format!("{}", has as i32)));
             format
         }
         Bang::HasMusicbrainzId(has) => (if has {
@@ -396,16 +1213,90 @@ fn to_query_string(bang: Bang, params: &mut Vec<(String, String)>) -> String {
         } else {
             "(Title, AlbumArtists) not in (select Title, AlbumArtists from tracks group by Title, AlbumArtists having count(*) > 1)"
         }).to_owned(),
+        Bang::DuplicatesBy(criteria) => {
+            let conditions = criteria.0.iter().map(|criterion| duplicate_condition(*criterion)).collect::<Vec<String>>();
+            format!(
+                "(EXISTS (SELECT 1 FROM tracks AS other WHERE other.TrackId != tracks.TrackId AND {}))",
+                conditions.join(" AND "),
+            )
+        }
+        Bang::FakeLossless(fake) => (if fake {
+            "(TrackId IN (SELECT TrackId FROM spectra WHERE Suspect = 1))"
+        } else {
+            "(TrackId NOT IN (SELECT TrackId FROM spectra WHERE Suspect = 1))"
+        }).to_owned(),
+        Bang::Isrc(isrc) => {
+            let param_name = get_rand_param();
+            let format = format!(
+                "(REPLACE(REPLACE(UPPER(Isrc), '-', ''), ' ', '') = {})",
+                param_name
+            );
+            params.push((param_name, normalize_isrc(&isrc)));
+            format
+        }
+        Bang::Encoder(search) => {
+            let param_name = get_rand_param();
+            let format = format!("(Encoder LIKE {})", param_name);
+            params.push((param_name, format!("%{}%", search)));
+            format
+        }
+        Bang::Related(RelatedTo { relation, track_id: Some(track_id) }) => {
+            let param_name = get_rand_param();
+            let format = format!(
+                "(TrackId IN (SELECT TrackId FROM track_relations WHERE Relation = {} AND RelatedId = {}))",
+                relation.to_i32(),
+                param_name
+            );
+            params.push((param_name, track_id));
+            format
+        }
+        Bang::Related(RelatedTo { relation, track_id: None }) => format!(
+            "(TrackId IN (SELECT TrackId FROM track_relations WHERE Relation = {}))",
+            relation.to_i32()
+        ),
+        Bang::CustomField(FieldMatch { field, comparison, value }) => {
+            let field_param = get_rand_param();
+            let value_param = get_rand_param();
+            let condition = match comparison {
+                FieldComparison::Equal => format!("Value = {} COLLATE NOCASE", value_param),
+                // Only integers are compared, never text that happens to sort before or after them.
+                FieldComparison::LessThan => format!("typeof(Value) = 'integer' AND Value < {}", value_param),
+                FieldComparison::GreaterThan => format!("typeof(Value) = 'integer' AND Value > {}", value_param),
+            };
+            let format = format!(
+                "(TrackId IN (SELECT TrackId FROM custom_fields WHERE Field = {} AND {}))",
+                field_param, condition
+            );
+            params.push((field_param, field));
+            params.push((value_param, value));
+            format
+        }
+        Bang::Note(search) => {
+            let param_name = get_rand_param();
+            let format = format!(
+                "(Comment LIKE {} OR TrackId IN (SELECT TrackId FROM track_notes WHERE Note LIKE {}))",
+                param_name, param_name
+            );
+            params.push((param_name, format!("%{}%", search)));
+            format
+        }
+        Bang::Failure(search) => {
+            let param_name = get_rand_param();
+            let failed = format!("Code LIKE {} OR Stage LIKE {} OR Message LIKE {}", param_name, param_name, param_name);
+            let format = format!(
+                "(TrackId IN (SELECT TrackId FROM errors WHERE {}) OR FilePath IN (SELECT FilePath FROM errors WHERE {}))",
+                failed, failed
+            );
+            params.push((param_name, format!("%{}%", search)));
+            format
+        }
         Bang::FullTextSearch(search) => {
             let param_name = get_rand_param();
             let album_artists_param = get_rand_param();
             let format = format!("(Title LIKE {} OR Album LIKE {} OR Artist LIKE {} OR AlbumArtists REGEXP {} COLLATE NOCASE)", 
                 param_name, param_name, param_name, album_artists_param);
             params.push((param_name, format!("%{}%", search)));
-            params.push((
-                album_artists_param,
-                format!("(?:^|;)(?:.*?)((?i){})(?:.*?)(?:;|$)", escape_regex_search(&search)),
-            ));
+            params.push((album_artists_param, album_artists_pattern(&search)));
 
             format
         }
@@ -418,7 +1309,7 @@ fn to_query_string(bang: Bang, params: &mut Vec<(String, String)>) -> String {
                 param_name, param_name, param_name, album_artists_param
             );
             params.push((param_name, format!("{}", search)));
-            params.push((album_artists_param, format!("(?:^|;)({})(?:;|$)", escape_regex_search(&search))));
+            params.push((album_artists_param, album_artists_exact_pattern(&search)));
             format
         }
         Bang::LogicalAnd(lhs, rhs) => {
@@ -443,16 +1334,35 @@ fn to_query_string(bang: Bang, params: &mut Vec<(String, String)>) -> String {
 
 #[allow(dead_code)]
 pub fn remove_track(track: &Track, conn: &Connection) {
+    remove_variants_under(&track.file_path, conn).unwrap();
     conn.execute(
         "DELETE FROM tracks WHERE FilePath = ?1",
         &[&track.file_path.to_string_lossy().into_owned()],
     ).unwrap();
 }
 
-#[allow(dead_code)]
-pub fn add_track(track: &Track, conn: &Connection) {
+/// Removes the track at the given path, or every track in the folder at the given path and its
+/// subfolders, returning how many tracks were removed.
+pub fn remove_tracks_under(path: &Path, conn: &Connection) -> Result<usize> {
+    let file_path = path.to_string_lossy().into_owned();
+    let folder_path = format!("{}{}", file_path.trim_end_matches(MAIN_SEPARATOR), MAIN_SEPARATOR);
+    remove_variants_under(path, conn)?;
     conn.execute(
-        "INSERT OR REPLACE INTO tracks(
+        "DELETE FROM tracks WHERE FilePath = ?1 OR substr(FilePath, 1, length(?2)) = ?2",
+        &[&file_path, &folder_path],
+    )
+}
+
+/// Adds the track to the library, replacing any track at the same path, and returns its UUID.
+///
+/// A track that already has a UUID keeps it, and a track replacing another at the same path
+/// takes the UUID of the track it replaces. Otherwise, a new UUID is generated.
+/// Replacing a track updates it in place, so anything referencing it is kept.
+#[allow(dead_code)]
+pub fn add_track(track: &Track, conn: &Connection) -> String {
+    let file_path = track.file_path.as_os_str().to_string_lossy().into_owned();
+    conn.execute(&format!(
+        "INSERT INTO tracks(
                 FilePath, 
                 Title,
                 Artist,
@@ -470,11 +1380,39 @@ pub fn add_track(track: &Track, conn: &Connection) {
                 DiscNumber,
                 Duration,
                 FileType,
-                Updated) 
+                Updated,
+                TrackId,
+                Comment,
+                Isrc,
+                Encoder) 
                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7,
-                        ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18)",
+                        ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18,
+                        COALESCE(?19, (SELECT TrackId FROM tracks WHERE FilePath = ?1), {}), ?20, ?21, ?22)
+                ON CONFLICT(FilePath) DO UPDATE SET
+                    Title = excluded.Title,
+                    Artist = excluded.Artist,
+                    AlbumArtists = excluded.AlbumArtists,
+                    Album = excluded.Album,
+                    Year = excluded.Year,
+                    TrackNumber = excluded.TrackNumber,
+                    MusicBrainzTrackId = excluded.MusicBrainzTrackId,
+                    HasFrontCover = excluded.HasFrontCover,
+                    FrontCoverWidth = excluded.FrontCoverWidth,
+                    FrontCoverHeight = excluded.FrontCoverHeight,
+                    Bitrate = excluded.Bitrate,
+                    SampleRate = excluded.SampleRate,
+                    Source = excluded.Source,
+                    DiscNumber = excluded.DiscNumber,
+                    Duration = excluded.Duration,
+                    FileType = excluded.FileType,
+                    Updated = excluded.Updated,
+                    TrackId = excluded.TrackId,
+                    Comment = excluded.Comment,
+                    Isrc = excluded.Isrc,
+                    Encoder = excluded.Encoder",
+                NEW_UUID),
         &[
-            &track.file_path.as_os_str().to_string_lossy().into_owned() as &dyn ToSql,
+            &file_path as &dyn ToSql,
             &track.title,
             &track.artist,
             &track.album_artists.join(";"),
@@ -492,6 +1430,621 @@ pub fn add_track(track: &Track, conn: &Connection) {
             &ms_to_ticks(track.duration),
             &track.file_type.to_i32().unwrap(),
             &track.updated,
+            &track.uuid,
+            &track.comment,
+            &track.isrc,
+            &track.encoder,
         ],
     ).unwrap();
+    set_track_genres(&file_path, &track.genres, conn).unwrap();
+    conn.query_row(
+        "SELECT TrackId FROM tracks WHERE FilePath = ?1",
+        &[&file_path],
+        |row| row.get(0),
+    ).unwrap()
+}
+
+/// Replaces a track with the same track read again after its file was moved or retagged.
+///
+/// The track is updated in place, so the statistics and playlist entries of every profile
+/// follow it to its new path, and it keeps its UUID.
+pub fn replace_track(old_track: &Track, new_track: &Track, conn: &Connection) -> Result<String> {
+    conn.execute(
+        "UPDATE tracks SET FilePath = ?2 WHERE FilePath = ?1",
+        &[
+            &old_track.file_path.to_string_lossy().into_owned(),
+            &new_track.file_path.to_string_lossy().into_owned(),
+        ],
+    )?;
+    Ok(add_track(new_track, conn))
+}
+
+/// The format version of exported snapshots and deltas.
+/// Bump this whenever the layout of the tracks table changes.
+pub const SNAPSHOT_FORMAT_VERSION: i32 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeOperation {
+    Insert,
+    Update,
+    Delete,
+}
+
+#[derive(Debug)]
+pub struct Change {
+    pub version: i64,
+    pub file_path: PathBuf,
+    pub operation: ChangeOperation,
+}
+
+/// Gets the version of the latest change to the library,
+/// or 0 if the library has never been written to.
+pub fn get_change_version(conn: &Connection) -> Result<i64> {
+    conn.query_row("SELECT IFNULL(MAX(Version), 0) FROM changes", NO_PARAMS, |row| {
+        row.get(0)
+    })
+}
+
+/// Gets all changes to the library made after the given version, in order.
+pub fn get_changes_since(version: i64, conn: &Connection) -> Result<Vec<Change>> {
+    let mut statement = conn.prepare(
+        "SELECT Version, FilePath, Operation FROM changes WHERE Version > ?1 ORDER BY Version",
+    )?;
+    let mut changes = Vec::<Change>::new();
+    let mut rows = statement.query(&[&version])?;
+    while let Some(row) = rows.next()? {
+        changes.push(Change {
+            version: row.get(0)?,
+            file_path: PathBuf::from(&row.get::<_, String>(1)?),
+            operation: match row.get::<_, i32>(2)? {
+                0 => ChangeOperation::Insert,
+                1 => ChangeOperation::Update,
+                _ => ChangeOperation::Delete,
+            },
+        })
+    }
+    Ok(changes)
+}
+
+fn write_snapshot_info(conn: &Connection, schema: &str, version: i64) -> Result<()> {
+    conn.execute_batch(&format!(
+        "CREATE TABLE {schema}.snapshot_info (
+            FormatVersion INTEGER,
+            ChangeVersion INTEGER,
+            Created DATE
+        );
+        INSERT INTO {schema}.snapshot_info VALUES ({format}, {version}, datetime('now'));",
+        schema = schema,
+        format = SNAPSHOT_FORMAT_VERSION,
+        version = version
+    ))
+}
+
+/// The tables replicas are given, in full by `export_snapshot` and as they change by `export_delta`.
+/// The rest of the library, such as notes, profiles and listens, is never exported.
+const REPLICATED_TABLES: [&str; 2] = ["tracks", "track_genres"];
+
+/// Exports a compact, read-only copy of the library to the given path,
+/// overwriting any file that already exists there.
+///
+/// The snapshot contains the `REPLICATED_TABLES` and a snapshot_info table recording
+/// the change version at the time of export, which can be passed to
+/// `export_delta` to bring the snapshot up to date later.
+///
+/// Returns the change version of the snapshot.
+pub fn export_snapshot(snapshot_path: &Path, conn: &Connection) -> Result<i64> {
+    let version = get_change_version(conn)?;
+    fs::remove_file(snapshot_path).unwrap_or(());
+
+    // The tables are made with their keys and indexes, but not the triggers of the change log,
+    // which write to a table the snapshot does not have.
+    let schema = conn
+        .prepare(&format!(
+            "SELECT sql FROM sqlite_master WHERE type IN ('table', 'index') AND sql IS NOT NULL
+                AND tbl_name IN ('{}') ORDER BY type = 'index'",
+            REPLICATED_TABLES.join("', '")
+        ))?
+        .query_map(NO_PARAMS, |row| row.get(0))?
+        .collect::<Result<Vec<String>>>()?;
+    let snapshot = Connection::open(snapshot_path)?;
+    apply_library_key(&snapshot)?;
+    snapshot.execute_batch(&schema.join(";\n"))?;
+    drop(snapshot);
+
+    conn.execute(
+        "ATTACH DATABASE ?1 AS snapshot",
+        &[&snapshot_path.to_string_lossy().into_owned()],
+    )?;
+    let result = REPLICATED_TABLES
+        .iter()
+        .try_for_each(|table| conn.execute_batch(&format!("INSERT INTO snapshot.{table} SELECT * FROM main.{table}")))
+        .and_then(|_| write_snapshot_info(conn, "snapshot", version));
+
+    conn.execute_batch("DETACH DATABASE snapshot")?;
+    result.map(|_| version)
+}
+
+/// Exports the changes made after the given version to a delta file at the given path,
+/// overwriting any file that already exists there.
+///
+/// The delta contains a tracks table with the current rows of every added or updated track,
+/// a track_genres table with the genres of those tracks,
+/// a removed table listing the file paths of every removed track, and a snapshot_info table.
+///
+/// Returns the change version the delta brings a replica up to.
+///
+/// A delta can not be exported since a version the change log was pruned past by `prune_changes`,
+/// and the replica needs a new snapshot instead.
+pub fn export_delta(since: i64, delta_path: &Path, conn: &Connection) -> Result<i64> {
+    let version = get_change_version(conn)?;
+    // Versions are never reused, so the change log is missing changes if it starts after the next one.
+    let oldest: Option<i64> = conn.query_row("SELECT MIN(Version) FROM changes", NO_PARAMS, |row| row.get(0))?;
+    if oldest.is_some_and(|oldest| since + 1 < oldest) {
+        return Err(Error::SqliteFailure(
+            rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_RANGE),
+            Some(format!("The changes since version {} were pruned, so the replica needs a new snapshot", since)),
+        ));
+    }
+    fs::remove_file(delta_path).unwrap_or(());
+    conn.execute(
+        "ATTACH DATABASE ?1 AS delta",
+        &[&delta_path.to_string_lossy().into_owned()],
+    )?;
+
+    let result = conn
+        .execute_batch(&format!(
+            "CREATE TABLE delta.tracks AS SELECT * FROM tracks WHERE FilePath IN
+                (SELECT FilePath FROM changes WHERE Version > {since} AND Version <= {version});
+            CREATE TABLE delta.track_genres AS SELECT * FROM track_genres WHERE FilePath IN
+                (SELECT FilePath FROM delta.tracks);
+            CREATE TABLE delta.removed AS SELECT DISTINCT FilePath FROM changes
+                WHERE Version > {since} AND Version <= {version} AND Operation = 2
+                AND FilePath NOT IN (SELECT FilePath FROM tracks);",
+            since = since,
+            version = version
+        ))
+        .and_then(|_| write_snapshot_info(conn, "delta", version));
+
+    conn.execute_batch("DETACH DATABASE delta")?;
+    result.map(|_| version)
+}
+
+/// Records that the replica with the given name is up to date with the change version, such as
+/// once it applied a delta, and prunes the changes no replica needs anymore.
+///
+/// Returns the number of changes pruned.
+pub fn acknowledge_replica(name: &str, version: i64, conn: &Connection) -> Result<usize> {
+    conn.execute(
+        "INSERT INTO replicas(Name, ChangeVersion) VALUES (?1, ?2)
+            ON CONFLICT(Name) DO UPDATE SET ChangeVersion = excluded.ChangeVersion",
+        &[&name as &dyn ToSql, &version],
+    )?;
+    prune_changes(conn)
+}
+
+/// Stops keeping changes for the replica with the given name, returning whether it was known.
+pub fn forget_replica(name: &str, conn: &Connection) -> Result<bool> {
+    Ok(conn.execute("DELETE FROM replicas WHERE Name = ?1", &[name])? > 0)
+}
+
+/// Removes the changes every replica acknowledged by `acknowledge_replica` is up to date with
+/// from the change log, or every change if there are none, returning the number removed.
+///
+/// The latest change is always kept, since it is the change version of the library.
+pub fn prune_changes(conn: &Connection) -> Result<usize> {
+    conn.execute(
+        "DELETE FROM changes WHERE Version < (SELECT MAX(Version) FROM changes)
+            AND Version <= IFNULL((SELECT MIN(ChangeVersion) FROM replicas), (SELECT MAX(Version) FROM changes))",
+        NO_PARAMS,
+    )
+}
+
+/// The size of the database file before and after `maintain`, in pages,
+/// and the number of cached images it removed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MaintenanceReport {
+    pub pages_before: i64,
+    pub pages_after: i64,
+    pub art_pruned: usize,
+}
+
+fn get_page_count(conn: &Connection) -> Result<i64> {
+    conn.query_row("PRAGMA page_count", NO_PARAMS, |row| row.get(0))
+}
+
+/// Compacts the database and refreshes the statistics the query planner uses,
+/// which long-lived libraries need as tracks are added and removed over time.
+///
+/// Cached images no track references anymore and changes no replica needs are removed first,
+/// then pages freed by removed rows are returned to the file system, and the write-ahead log is
+/// truncated. A library created before incremental vacuuming was enabled is vacuumed
+/// in full the first time, which rewrites the whole file and may take a while.
+///
+/// This can not be run within a transaction, and other writers should be kept out
+/// by holding the write lease.
+pub fn maintain(conn: &Connection) -> Result<MaintenanceReport> {
+    let art_pruned = prune_art(conn)?;
+    prune_changes(conn)?;
+    let pages_before = get_page_count(conn)?;
+    let auto_vacuum: i32 = conn.query_row("PRAGMA auto_vacuum", NO_PARAMS, |row| row.get(0))?;
+    // 2 is INCREMENTAL.
+    if auto_vacuum == 2 {
+        // Each step frees a single page, so the pragma is stepped until it finishes.
+        let mut statement = conn.prepare("PRAGMA incremental_vacuum")?;
+        let mut rows = statement.query(NO_PARAMS)?;
+        while rows.next()?.is_some() {}
+    } else {
+        conn.execute_batch("PRAGMA auto_vacuum = INCREMENTAL; VACUUM")?;
+    }
+    conn.execute_batch("ANALYZE; PRAGMA wal_checkpoint(TRUNCATE)")?;
+    Ok(MaintenanceReport {
+        pages_before,
+        pages_after: get_page_count(conn)?,
+        art_pruned,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use katatsuki::TrackFileType;
+
+    /// A library in memory without tracks.
+    fn empty_library() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        enable_foreign_keys(&conn).unwrap();
+        add_regexp_function(&conn).unwrap();
+        #[cfg(feature = "analysis")]
+        add_fingerprint_function(&conn).unwrap();
+        create_database(&conn);
+        conn
+    }
+
+    /// Adds a track of the duration in milliseconds to the library, returning its UUID.
+    fn add_timed_track(file_name: &str, duration: i32, conn: &Connection) -> String {
+        let track = Track::builder(format!("/music/{}", file_name), TrackFileType::FLAC16)
+            .title(file_name.to_owned())
+            .duration(duration)
+            .build();
+        add_track(&track, conn)
+    }
+
+    /// The operations of the changes since the version, with the names of the files changed.
+    fn changes_since(version: i64, conn: &Connection) -> Vec<(String, ChangeOperation)> {
+        get_changes_since(version, conn)
+            .unwrap()
+            .into_iter()
+            .map(|change| (change.file_path.file_name().unwrap().to_string_lossy().into_owned(), change.operation))
+            .collect()
+    }
+
+    /// The rows of the column of the table of the database at the path, in order.
+    fn column_of(path: &Path, sql: &str) -> Vec<String> {
+        let conn = Connection::open(path).unwrap();
+        let mut statement = conn.prepare(sql).unwrap();
+        let rows = statement.query_map(NO_PARAMS, |row| row.get(0)).unwrap();
+        rows.collect::<Result<Vec<String>>>().unwrap()
+    }
+
+    /// A library in memory with three tracks, where the first two are duplicates by title and
+    /// album artists.
+    fn library() -> Connection {
+        let conn = empty_library();
+        let tracks = vec![
+            Track::builder("/music/a.flac", TrackFileType::FLAC16)
+                .title("Hello".to_owned())
+                .artist("Alpha feat. Beta".to_owned())
+                .album_artists(vec!["Alpha".to_owned()])
+                .album("First Album".to_owned())
+                .bitrate(900)
+                .front_cover(true, 500, 400)
+                .musicbrainz_track_id(Some("6f8ab2b0-5c7e-4d5b-9a0e-2f1d0b8c7a11".to_owned()))
+                .build(),
+            Track::builder("/music/b.mp3", TrackFileType::MP3CBR)
+                .title("Hello".to_owned())
+                .artist("Gamma".to_owned())
+                .album_artists(vec!["Alpha".to_owned()])
+                .album("Second".to_owned())
+                .bitrate(320)
+                .front_cover(false, 0, 0)
+                .build(),
+            Track::builder("/music/c.flac", TrackFileType::FLAC24)
+                .title("World".to_owned())
+                .artist("Alpha".to_owned())
+                .album_artists(vec!["Alpha".to_owned(), "Delta".to_owned()])
+                .album("First".to_owned())
+                .bitrate(1000)
+                .front_cover(true, 1200, 1200)
+                .build(),
+        ];
+        for track in &tracks {
+            add_track(track, &conn);
+        }
+        conn
+    }
+
+    /// The names of the files of the tracks matching the query, in order.
+    fn matching(query: &str, conn: &Connection) -> Vec<String> {
+        let mut names = query_tracks(Bang::new(query).unwrap(), conn, None, None)
+            .unwrap()
+            .into_iter()
+            .map(|track| track.file_path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect::<Vec<String>>();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn queries_exact_searches() {
+        let conn = library();
+        assert_eq!(matching("!T{Hello}", &conn), ["a.flac", "b.mp3"]);
+        assert_eq!(matching("!T{Hell}", &conn), Vec::<String>::new());
+        assert_eq!(matching("!Q{Gamma}", &conn), ["b.mp3"]);
+        assert_eq!(matching("!Q{Delta}", &conn), ["c.flac"]);
+    }
+
+    #[test]
+    fn queries_album_titles() {
+        let conn = library();
+        assert_eq!(matching("!al{first}", &conn), ["a.flac", "c.flac"]);
+        assert_eq!(matching("!AL{First}", &conn), ["c.flac"]);
+    }
+
+    #[test]
+    fn queries_album_artists() {
+        let conn = library();
+        assert_eq!(matching("!alar{delt}", &conn), ["c.flac"]);
+        assert_eq!(matching("!ALAR{Alpha}", &conn), ["a.flac", "b.mp3", "c.flac"]);
+        assert_eq!(matching("!ALAR{Delt}", &conn), Vec::<String>::new());
+    }
+
+    #[test]
+    fn queries_artists() {
+        let conn = library();
+        assert_eq!(matching("!ar{alpha}", &conn), ["a.flac", "c.flac"]);
+        assert_eq!(matching("!AR{Alpha}", &conn), ["c.flac"]);
+    }
+
+    #[test]
+    fn queries_artists_by_their_aliases() {
+        let conn = empty_library();
+        for (file_name, artist) in [("a.flac", "Ado"), ("b.flac", "Adore"), ("c.flac", "あど")] {
+            let track = Track::builder(format!("/music/{}", file_name), TrackFileType::FLAC16)
+                .artist(artist.to_owned())
+                .build();
+            add_track(&track, &conn);
+        }
+        crate::aliases::add_alias("あど", "Ado", &conn).unwrap();
+        assert_eq!(matching("!ar{あど}", &conn), ["a.flac", "c.flac"]);
+        assert_eq!(matching("!AR{あど}", &conn), ["a.flac", "c.flac"]);
+    }
+
+    #[test]
+    fn queries_bitrates() {
+        let conn = library();
+        assert_eq!(matching("!brlt{500}", &conn), ["b.mp3"]);
+        assert_eq!(matching("!brgt{950}", &conn), ["c.flac"]);
+        assert_eq!(matching("!brgt{1000}", &conn), Vec::<String>::new());
+    }
+
+    #[test]
+    fn queries_cover_art_sizes() {
+        let conn = library();
+        assert_eq!(matching("!cwlt{600}", &conn), ["a.flac", "b.mp3"]);
+        assert_eq!(matching("!cwgt{1000}", &conn), ["c.flac"]);
+        assert_eq!(matching("!chlt{300}", &conn), ["b.mp3"]);
+        assert_eq!(matching("!chgt{300}", &conn), ["a.flac", "c.flac"]);
+    }
+
+    #[test]
+    fn queries_has_cover_art() {
+        let conn = library();
+        assert_eq!(matching("!c{true}", &conn), ["a.flac", "c.flac"]);
+        assert_eq!(matching("!c`", &conn), ["a.flac", "c.flac"]);
+        assert_eq!(matching("!c{false}", &conn), ["b.mp3"]);
+    }
+
+    #[test]
+    fn queries_has_musicbrainz_id() {
+        let conn = library();
+        assert_eq!(matching("!mb{true}", &conn), ["a.flac"]);
+        assert_eq!(matching("!mb{false}", &conn), ["b.mp3", "c.flac"]);
+    }
+
+    #[test]
+    fn queries_duplicates() {
+        let conn = library();
+        assert_eq!(matching("!dup{true}", &conn), ["a.flac", "b.mp3"]);
+        assert_eq!(matching("!dup{false}", &conn), ["c.flac"]);
+    }
+
+    #[test]
+    fn queries_duplicates_by_duration_within_a_second() {
+        let conn = empty_library();
+        add_timed_track("a.flac", 180_000, &conn);
+        add_timed_track("b.flac", 180_900, &conn);
+        add_timed_track("c.flac", 182_000, &conn);
+        add_timed_track("d.flac", 200_000, &conn);
+        assert_eq!(matching("!dup{duration}", &conn), ["a.flac", "b.flac"]);
+        assert_eq!(matching("!dup{duration,title}", &conn), Vec::<String>::new());
+    }
+
+    #[cfg(feature = "analysis")]
+    #[test]
+    fn queries_duplicates_by_fingerprint() {
+        use crate::analysis::save_waveform;
+
+        let conn = empty_library();
+        let waveform = (0..64).map(|part| part * 2).collect::<Vec<u8>>();
+        // A quieter copy of the same recording, trimmed by a second.
+        let quieter = waveform.iter().map(|part| part / 2).collect::<Vec<u8>>();
+        let other = waveform.iter().rev().cloned().collect::<Vec<u8>>();
+        save_waveform(&add_timed_track("a.flac", 180_000, &conn), &waveform, &conn).unwrap();
+        save_waveform(&add_timed_track("b.flac", 179_000, &conn), &quieter, &conn).unwrap();
+        save_waveform(&add_timed_track("c.flac", 180_000, &conn), &other, &conn).unwrap();
+        // The same waveform, but far too long to be the same recording.
+        save_waveform(&add_timed_track("d.flac", 240_000, &conn), &waveform, &conn).unwrap();
+        // A track without a waveform is never a duplicate by it.
+        add_timed_track("e.flac", 180_000, &conn);
+        assert_eq!(matching("!dup{fingerprint}", &conn), ["a.flac", "b.flac"]);
+    }
+
+    #[test]
+    fn replaces_the_update_log_of_older_libraries_once() {
+        let conn = empty_library();
+        assert!(!has_outdated_update_log(&conn).unwrap());
+        conn.execute_batch(
+            "DROP TRIGGER tracks_log_update;
+            CREATE TRIGGER tracks_log_move AFTER UPDATE OF FilePath ON tracks BEGIN
+                INSERT INTO changes(FilePath, Operation) VALUES (OLD.FilePath, 2);
+            END;",
+        )
+        .unwrap();
+        assert!(has_outdated_update_log(&conn).unwrap());
+
+        create_change_log(&conn);
+        assert!(!has_outdated_update_log(&conn).unwrap());
+        add_timed_track("a.flac", 1000, &conn);
+        let version = get_change_version(&conn).unwrap();
+        conn.execute("UPDATE tracks SET FilePath = '/music/b.flac' WHERE FilePath = '/music/a.flac'", NO_PARAMS)
+            .unwrap();
+        assert_eq!(
+            changes_since(version, &conn),
+            [("a.flac".to_owned(), ChangeOperation::Delete), ("b.flac".to_owned(), ChangeOperation::Update)]
+        );
+    }
+
+    #[test]
+    fn logs_every_write_to_the_tracks() {
+        let conn = empty_library();
+        add_timed_track("a.flac", 1000, &conn);
+        add_timed_track("b.flac", 1000, &conn);
+        let version = get_change_version(&conn).unwrap();
+        add_timed_track("a.flac", 2000, &conn);
+        conn.execute("UPDATE tracks SET FilePath = '/music/c.flac' WHERE FilePath = '/music/b.flac'", NO_PARAMS)
+            .unwrap();
+        remove_track(&Track::builder("/music/a.flac", TrackFileType::FLAC16).build(), &conn);
+        assert_eq!(
+            changes_since(version, &conn),
+            [
+                ("a.flac".to_owned(), ChangeOperation::Update),
+                ("b.flac".to_owned(), ChangeOperation::Delete),
+                ("c.flac".to_owned(), ChangeOperation::Update),
+                ("a.flac".to_owned(), ChangeOperation::Delete),
+            ]
+        );
+        assert_eq!(get_change_version(&conn).unwrap(), version + 4);
+    }
+
+    #[test]
+    fn exports_deltas_of_the_changes_since_a_version() {
+        let conn = empty_library();
+        add_timed_track("a.flac", 1000, &conn);
+        add_timed_track("b.flac", 1000, &conn);
+        let since = get_change_version(&conn).unwrap();
+        add_track(
+            &Track::builder("/music/c.flac", TrackFileType::FLAC16).genres(vec!["House".to_owned()]).build(),
+            &conn,
+        );
+        remove_track(&Track::builder("/music/b.flac", TrackFileType::FLAC16).build(), &conn);
+
+        let delta = ScratchFile::new("db");
+        let version = export_delta(since, &delta.0, &conn).unwrap();
+        assert_eq!(version, get_change_version(&conn).unwrap());
+        assert_eq!(column_of(&delta.0, "SELECT FilePath FROM tracks"), ["/music/c.flac"]);
+        assert_eq!(column_of(&delta.0, "SELECT Genre FROM track_genres"), ["House"]);
+        assert_eq!(column_of(&delta.0, "SELECT FilePath FROM removed"), ["/music/b.flac"]);
+        assert_eq!(
+            column_of(&delta.0, "SELECT CAST(ChangeVersion AS TEXT) FROM snapshot_info"),
+            [version.to_string()]
+        );
+    }
+
+    #[test]
+    fn exports_snapshots_of_the_replicated_tables_only() {
+        let conn = empty_library();
+        let track_id = add_timed_track("a.flac", 1000, &conn);
+        add_timed_track("b.flac", 1000, &conn);
+        crate::notes::set_note(&track_id, "Private", &conn).unwrap();
+
+        let snapshot = ScratchFile::new("db");
+        let version = export_snapshot(&snapshot.0, &conn).unwrap();
+        assert_eq!(version, get_change_version(&conn).unwrap());
+        assert_eq!(
+            column_of(&snapshot.0, "SELECT name FROM sqlite_master WHERE type = 'table' ORDER BY name"),
+            ["snapshot_info", "track_genres", "tracks"]
+        );
+        assert_eq!(
+            column_of(&snapshot.0, "SELECT name FROM sqlite_master WHERE type IN ('index', 'trigger') AND sql IS NOT NULL ORDER BY name"),
+            ["track_genres_genre", "tracks_track_id"]
+        );
+        assert_eq!(
+            column_of(&snapshot.0, "SELECT FilePath FROM tracks ORDER BY FilePath"),
+            ["/music/a.flac", "/music/b.flac"]
+        );
+    }
+
+    #[test]
+    fn prunes_changes_every_replica_is_up_to_date_with() {
+        let conn = empty_library();
+        for file_name in ["a.flac", "b.flac", "c.flac", "d.flac", "e.flac"] {
+            add_timed_track(file_name, 1000, &conn);
+        }
+        let delta = ScratchFile::new("db");
+        assert_eq!(acknowledge_replica("laptop", 2, &conn).unwrap(), 2);
+        assert_eq!(acknowledge_replica("phone", 4, &conn).unwrap(), 0);
+        assert!(export_delta(1, &delta.0, &conn).is_err());
+        assert_eq!(export_delta(2, &delta.0, &conn).unwrap(), 5);
+        assert_eq!(column_of(&delta.0, "SELECT FilePath FROM tracks ORDER BY FilePath").len(), 3);
+
+        assert!(forget_replica("laptop", &conn).unwrap());
+        assert!(!forget_replica("laptop", &conn).unwrap());
+        assert_eq!(prune_changes(&conn).unwrap(), 2);
+        // Without any replica, only the latest change is kept, so the version of the library stays.
+        forget_replica("phone", &conn).unwrap();
+        assert_eq!(prune_changes(&conn).unwrap(), 0);
+        assert_eq!(get_change_version(&conn).unwrap(), 5);
+        assert_eq!(changes_since(0, &conn), [("e.flac".to_owned(), ChangeOperation::Insert)]);
+    }
+
+    #[test]
+    fn queries_combined_bangs() {
+        let conn = library();
+        assert_eq!(matching("!c` & !mb{false}", &conn), ["c.flac"]);
+        assert_eq!(matching("!brlt{500} | !AL{First}", &conn), ["b.mp3", "c.flac"]);
+    }
+
+    #[test]
+    fn limits_searches_as_configured() {
+        let config = DatabaseConfig {
+            query_timeout: 250,
+            slow_query_threshold: 0,
+            ..DatabaseConfig::default()
+        };
+        assert_eq!(
+            QueryLimits::from_config(&config),
+            QueryLimits {
+                timeout: Some(Duration::from_millis(250)),
+                slow_query_threshold: None,
+            }
+        );
+    }
+
+    #[test]
+    fn interrupts_searches_that_run_past_the_timeout() {
+        let conn = empty_library();
+        let endless = "WITH RECURSIVE n(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM n) SELECT COUNT(*) FROM n";
+        let result = interrupt_after(Some(Duration::from_millis(50)), &conn, || {
+            conn.query_row(endless, NO_PARAMS, |row| row.get::<_, i64>(0))
+        });
+        assert!(matches!(result, Err(Error::SqliteFailure(error, _)) if error.code == ErrorCode::OperationInterrupted));
+
+        let quick = interrupt_after(Some(Duration::from_millis(50)), &conn, || {
+            conn.query_row("SELECT COUNT(*) FROM tracks", NO_PARAMS, |row| row.get::<_, i64>(0))
+        });
+        assert_eq!(quick.unwrap(), 0);
+        // The timer of a search that finished in time does not interrupt what runs after it.
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(count(Bang::All, &conn).unwrap(), 0);
+    }
 }
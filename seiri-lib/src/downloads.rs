@@ -0,0 +1,122 @@
+//! Imports from URLs, by handing them off to a downloader such as yt-dlp.
+//!
+//! Only `http` and `https` URLs are imported. The downloader is run in an empty folder of the
+//! application directory with the URL as its last argument, after `--`, and everything it leaves
+//! in the folder is moved into a new folder of the watch folder, to be imported like anything
+//! else dropped there. The new folder is named for the site the URL is from, so the tracks are
+//! imported with the site as their source.
+
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::paths;
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use walkdir::WalkDir;
+
+/// The source of tracks downloaded from the URL, from the name of its site,
+/// such as `Bandcamp` for `https://artist.bandcamp.com/album/title`.
+pub fn url_source(url: &str) -> String {
+    let host = url
+        .split_once("://")
+        .map_or(url, |(_, rest)| rest)
+        .split(['/', '?', '#'])
+        .next()
+        .unwrap_or("");
+    let host = host.rsplit('@').next().unwrap_or(host);
+    let host = host.split(':').next().unwrap_or(host);
+    let labels = host.split('.').filter(|label| !label.is_empty()).collect::<Vec<&str>>();
+    let site = match labels.as_slice() {
+        [.., site, _] | [site] => site,
+        [] => return "Download".to_owned(),
+    };
+    // Anything after an underscore in the name of the folder is not part of the source.
+    let site = site.replace('_', "-");
+    let mut characters = site.chars();
+    match characters.next() {
+        Some(first) => first.to_uppercase().chain(characters).collect(),
+        None => "Download".to_owned(),
+    }
+}
+
+/// Whether the URL is an `http` or `https` URL, so the downloader is never handed anything else,
+/// such as an option of its own.
+fn is_web_url(url: &str) -> bool {
+    url.split_once("://").is_some_and(|(scheme, _)| {
+        scheme.eq_ignore_ascii_case("http") || scheme.eq_ignore_ascii_case("https")
+    })
+}
+
+/// A random name for a new folder.
+pub(crate) fn random_name() -> String {
+    thread_rng().sample_iter(&Alphanumeric).take(8).collect::<String>()
+}
+
+/// A path for a new folder of the given name in the destination, numbered if the name is taken.
+pub(crate) fn get_iterative_folder(name: &str, destination: &Path) -> PathBuf {
+    let mut folder = destination.join(name);
+    let mut counter = 0;
+    while folder.exists() {
+        counter += 1;
+        folder = destination.join(format!("{} ({})", name, counter));
+    }
+    folder
+}
+
+/// Moves every file in the folder other than hidden files into the other folder, keeping their names.
+pub(crate) fn move_folder_contents(folder: &Path, destination: &Path) -> Result<usize> {
+    let files = WalkDir::new(folder)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file())
+        .filter(|entry| !entry.file_name().to_string_lossy().starts_with('.'))
+        .map(|entry| entry.into_path())
+        .collect::<Vec<PathBuf>>();
+    for file in &files {
+        paths::move_into_folder(file, destination)?;
+    }
+    Ok(files.len())
+}
+
+/// Downloads the URL with the configured downloader, and moves what it downloaded into the
+/// watch folder to be imported. Returns the folder of the watch folder the files were moved into.
+///
+/// Fails if the URL is not an `http` or `https` URL, or if the downloader could not be run,
+/// exited with a failing status, or downloaded nothing.
+pub fn import_url(url: &str, config: &Config) -> Result<PathBuf> {
+    let failed = || Error::DownloadFailed(url.to_owned());
+    if !is_web_url(url) {
+        return Err(failed());
+    }
+    let (_, auto_add_path) = paths::ensure_music_folder(&config.music_folder).map_err(|_| failed())?;
+    let (program, args) = config.downloader.split_first().ok_or_else(failed)?;
+
+    let mut download_path = paths::get_appdata_path();
+    download_path.push("downloads");
+    download_path.push(random_name());
+    fs::create_dir_all(&download_path)
+        .map_err(|_| Error::UnableToCreateDirectory(download_path.to_string_lossy().into_owned()))?;
+
+    // The downloader writes nothing to stderr, which seiri-watcher reports events on.
+    let status = Command::new(program)
+        .args(args)
+        .arg("--")
+        .arg(url)
+        .current_dir(&download_path)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+    let import_path = auto_add_path.join(format!("{}_{}", url_source(url), random_name()));
+    let moved = match status {
+        Ok(status) if status.success() => move_folder_contents(&download_path, &import_path),
+        _ => Err(failed()),
+    };
+    fs::remove_dir_all(&download_path).ok();
+    match moved {
+        Ok(0) | Err(_) => Err(failed()),
+        Ok(_) => Ok(import_path),
+    }
+}
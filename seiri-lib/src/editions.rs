@@ -0,0 +1,165 @@
+//! Groups the editions of an album, such as its remasters, deluxe editions and regional releases,
+//! so only the preferred edition of each album needs to be listed.
+//!
+//! Tracks set to a MusicBrainz release group are grouped with the other tracks of that release
+//! group. Every other track is grouped by its album artists and the title of its album without
+//! any edition it names, so `Abbey Road (2019 Remaster)` and `Abbey Road [Japan Edition]` are
+//! grouped with `Abbey Road`. A release group is kept by the UUID of its track, so it follows the
+//! track when it is moved, and is removed along with the track.
+
+use crate::config::EditionPreference;
+use crate::database::create_table_with_foreign_keys;
+use katatsuki::{Quality, Track};
+use regex::Regex;
+use rusqlite::{Connection, Result, NO_PARAMS};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::sync::OnceLock;
+
+pub fn create_release_group_table(conn: &Connection) {
+    create_table_with_foreign_keys(
+        "track_release_groups",
+        "TrackId TEXT PRIMARY KEY REFERENCES tracks(TrackId) ON DELETE CASCADE,
+        ReleaseGroupId TEXT NOT NULL",
+        conn,
+    )
+    .unwrap();
+}
+
+/// Sets the MusicBrainz release group of the track with the given UUID, replacing any it had.
+/// An empty release group removes it. Returns whether there is such a track.
+pub fn set_release_group(uuid: &str, release_group_id: &str, conn: &Connection) -> Result<bool> {
+    let release_group_id = release_group_id.trim();
+    if release_group_id.is_empty() {
+        conn.execute("DELETE FROM track_release_groups WHERE TrackId = ?1", &[uuid])?;
+        return conn.query_row("SELECT EXISTS (SELECT 1 FROM tracks WHERE TrackId = ?1)", &[uuid], |row| {
+            row.get(0)
+        });
+    }
+    let set = conn.execute(
+        "INSERT INTO track_release_groups(TrackId, ReleaseGroupId) SELECT TrackId, ?2 FROM tracks WHERE TrackId = ?1
+        ON CONFLICT(TrackId) DO UPDATE SET ReleaseGroupId = excluded.ReleaseGroupId",
+        &[uuid, release_group_id],
+    )?;
+    Ok(set > 0)
+}
+
+/// Gets the release group of every track set to one, by the UUIDs of the tracks.
+pub fn get_release_groups(conn: &Connection) -> Result<HashMap<String, String>> {
+    let mut statement = conn.prepare("SELECT TrackId, ReleaseGroupId FROM track_release_groups")?;
+    let rows = statement.query_map(NO_PARAMS, |row| Ok((row.get(0)?, row.get(1)?)))?;
+    rows.collect()
+}
+
+fn edition_pattern() -> &'static Regex {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    PATTERN.get_or_init(|| {
+        // Abbey Road (2019 Remaster), Abbey Road [Super Deluxe Edition], or Abbey Road - Remastered 2009
+        Regex::new(
+            r"(?i)\s*(?:[(\[][^()\[\]]*\b(?:remaster(?:ed)?|deluxe|edition|expanded|anniversary|bonus tracks?|reissue|legacy|mono|stereo)\b[^()\[\]]*[)\]]|\s[-–]\s[^-–]*\b(?:remaster(?:ed)?|deluxe|edition|expanded|anniversary|reissue)\b[^-–]*)$",
+        )
+        .unwrap()
+    })
+}
+
+/// Gets the title of the album without any edition it names, such as `Abbey Road` for
+/// `Abbey Road (2019 Remaster) [Deluxe Edition]`, in lowercase.
+pub fn base_title(album: &str) -> String {
+    let mut title = album.trim();
+    while let Some(edition) = edition_pattern().find(title) {
+        if edition.start() == 0 {
+            break;
+        }
+        title = title[..edition.start()].trim_end();
+    }
+    title.to_lowercase()
+}
+
+/// What the editions of an album are grouped by.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum AlbumGroup {
+    /// The MusicBrainz release group of the album.
+    ReleaseGroup(String),
+    /// The album artists and base title of the album, in lowercase.
+    Album(String, String),
+}
+
+/// Gets the group of editions the track is in, from the release groups of tracks by their UUIDs.
+pub fn album_group(track: &Track, release_groups: &HashMap<String, String>) -> AlbumGroup {
+    match track.uuid.as_ref().and_then(|uuid| release_groups.get(uuid)) {
+        Some(release_group_id) => AlbumGroup::ReleaseGroup(release_group_id.clone()),
+        None => AlbumGroup::Album(track.album_artists.join(", ").to_lowercase(), base_title(&track.album)),
+    }
+}
+
+/// An edition of an album, with the tracks of it that are being grouped.
+struct Edition {
+    album_artists: Vec<String>,
+    album: String,
+    tracks: usize,
+    /// The quality of the worst track of the edition.
+    quality: Option<Quality>,
+    /// The earliest year a track of the edition is tagged with.
+    year: Option<i32>,
+}
+
+/// Orders editions from least to most preferred.
+fn compare_editions(a: &Edition, b: &Edition, preference: EditionPreference) -> Ordering {
+    let quality = a.quality.cmp(&b.quality);
+    let tracks = a.tracks.cmp(&b.tracks);
+    // Editions without a year are never the earliest or the latest.
+    let earliest = b.year.unwrap_or(i32::MAX).cmp(&a.year.unwrap_or(i32::MAX));
+    let latest = a.year.unwrap_or(i32::MIN).cmp(&b.year.unwrap_or(i32::MIN));
+    let preferred = match preference {
+        EditionPreference::Quality => quality.then(tracks),
+        EditionPreference::Tracks => tracks.then(quality),
+        EditionPreference::Earliest => earliest.then(quality).then(tracks),
+        EditionPreference::Latest => latest.then(quality).then(tracks),
+    };
+    // The edition named plainer is preferred, such as the one without an edition in its title.
+    preferred.then_with(|| b.album.len().cmp(&a.album.len())).then_with(|| b.album.cmp(&a.album))
+}
+
+/// Leaves out every track that is not in the preferred edition of its album among the tracks,
+/// so each album is only listed once. Tracks without an album are kept.
+pub fn preferred_editions(tracks: Vec<Track>, preference: EditionPreference, conn: &Connection) -> Result<Vec<Track>> {
+    let release_groups = get_release_groups(conn)?;
+    let mut groups: HashMap<AlbumGroup, Vec<Edition>> = HashMap::new();
+    for track in tracks.iter().filter(|track| !track.album.trim().is_empty()) {
+        let editions = groups.entry(album_group(track, &release_groups)).or_default();
+        let index = match editions
+            .iter()
+            .position(|edition| edition.album_artists == track.album_artists && edition.album == track.album)
+        {
+            Some(index) => index,
+            None => {
+                editions.push(Edition {
+                    album_artists: track.album_artists.clone(),
+                    album: track.album.clone(),
+                    tracks: 0,
+                    quality: track.quality(),
+                    year: None,
+                });
+                editions.len() - 1
+            }
+        };
+        let edition = &mut editions[index];
+        edition.tracks += 1;
+        edition.quality = edition.quality.min(track.quality());
+        edition.year = match (edition.year, track.year()) {
+            (Some(year), Some(track_year)) => Some(year.min(track_year)),
+            (year, track_year) => year.or(track_year),
+        };
+    }
+    let preferred = groups
+        .values()
+        .filter_map(|editions| editions.iter().max_by(|a, b| compare_editions(a, b, preference)))
+        .map(|edition| (&edition.album_artists, &edition.album))
+        .collect::<HashSet<_>>();
+    Ok(tracks
+        .into_iter()
+        .filter(|track| {
+            track.album.trim().is_empty() || preferred.contains(&(&track.album_artists, &track.album))
+        })
+        .collect())
+}
@@ -0,0 +1,126 @@
+//! Encryption of the library database with SQLCipher, for libraries kept on shared or
+//! cloud-synced storage.
+//!
+//! The library is encrypted once a key is configured, either in the configuration itself or
+//! printed by a command, such as one that reads it from the keyring of the system. Encryption
+//! needs the `sqlcipher` feature, which links against the SQLCipher of the system in place of
+//! the bundled SQLite. A library that is not encrypted yet is encrypted with the key the first
+//! time it is opened with one, and snapshots exported from it are encrypted with the same key.
+
+use crate::config::{get_config, DatabaseConfig};
+use crate::database::get_database_path;
+use crate::error::{Error, Result};
+use rusqlite::types::ToSql;
+use rusqlite::{Connection, NO_PARAMS};
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+
+static LIBRARY_KEY: OnceLock<Option<String>> = OnceLock::new();
+
+/// Gets the key configured for the library, running the key command if it is not configured
+/// itself. Keys can only be configured when built with the `sqlcipher` feature, so a library
+/// meant to be encrypted is never written unencrypted.
+pub fn resolve_key(config: &DatabaseConfig) -> Result<Option<String>> {
+    let key = match (&config.key, config.key_command.split_first()) {
+        (Some(key), _) => Some(key.to_owned()),
+        (None, Some((program, args))) => {
+            let failed = || Error::DatabaseKeyUnavailable(config.key_command.join(" "));
+            let output = Command::new(program)
+                .args(args)
+                .stdin(Stdio::null())
+                .stderr(Stdio::null())
+                .output()
+                .map_err(|_| failed())?;
+            if !output.status.success() {
+                return Err(failed());
+            }
+            let key = String::from_utf8(output.stdout).map_err(|_| failed())?;
+            let key = key.trim_end_matches(&['\r', '\n'][..]);
+            if key.is_empty() {
+                return Err(failed());
+            }
+            Some(key.to_owned())
+        }
+        (None, None) => None,
+    };
+    if key.is_some() && !cfg!(feature = "sqlcipher") {
+        return Err(Error::EncryptionUnsupported);
+    }
+    Ok(key)
+}
+
+/// Gets the key of the library as configured, which is resolved and used to encrypt the library
+/// if it is not encrypted yet only once for the whole process. If the configuration can not be
+/// read, the library is taken to be unencrypted.
+///
+/// Panics if the key is configured but can not be resolved.
+pub fn library_key() -> Option<&'static str> {
+    LIBRARY_KEY
+        .get_or_init(|| {
+            let config = get_config().map(|config| config.database).unwrap_or_default();
+            let key = resolve_key(&config).unwrap();
+            if let Some(key) = &key {
+                encrypt_if_unencrypted(&get_database_path(), key).unwrap();
+            }
+            key
+        })
+        .as_deref()
+}
+
+/// Unlocks the connection with the key, which must be done before anything is read or written
+/// through it. Fails if the database is encrypted with another key.
+pub fn apply_key(conn: &Connection, key: &str) -> rusqlite::Result<()> {
+    conn.pragma_update(None, "key", &key)?;
+    conn.query_row("SELECT COUNT(*) FROM sqlite_master", NO_PARAMS, |row| row.get::<_, i64>(0))?;
+    Ok(())
+}
+
+/// Unlocks the connection with the key of the library, if it is encrypted.
+pub fn apply_library_key(conn: &Connection) -> rusqlite::Result<()> {
+    match library_key() {
+        Some(key) => apply_key(conn, key),
+        None => Ok(()),
+    }
+}
+
+/// Encrypts the database at the path with the key, if it exists and is not encrypted yet.
+/// The database is exported into an encrypted copy, which then replaces it, so it must not
+/// be open elsewhere while it is encrypted. Returns whether the database was encrypted.
+pub fn encrypt_if_unencrypted(path: &Path, key: &str) -> Result<bool> {
+    if !path.exists() {
+        return Ok(false);
+    }
+    let failed = || Error::FileIOError(path.to_owned());
+    let encrypted_path = path.with_extension("db.encrypted");
+    {
+        let conn = Connection::open(path).map_err(|_| failed())?;
+        // Only a database that is not encrypted can be read without a key.
+        let readable = conn
+            .query_row("SELECT COUNT(*) FROM sqlite_master", NO_PARAMS, |row| row.get::<_, i64>(0))
+            .is_ok();
+        if !readable {
+            return Ok(false);
+        }
+        fs::remove_file(&encrypted_path).unwrap_or(());
+        conn.execute_batch("PRAGMA wal_checkpoint(TRUNCATE);")
+            .and_then(|_| {
+                conn.execute(
+                    "ATTACH DATABASE ?1 AS encrypted KEY ?2",
+                    &[&encrypted_path.to_string_lossy().into_owned() as &dyn ToSql, &key],
+                )
+            })
+            .and_then(|_| conn.query_row("SELECT sqlcipher_export('encrypted')", NO_PARAMS, |_| Ok(())))
+            .and_then(|_| conn.execute_batch("DETACH DATABASE encrypted;"))
+            .map_err(|_| failed())?;
+    }
+    // The write-ahead log was checkpointed into the database, so it has nothing the copy does not.
+    for suffix in &["-wal", "-shm"] {
+        let mut log_path = path.as_os_str().to_owned();
+        log_path.push(suffix);
+        fs::remove_file(log_path).unwrap_or(());
+    }
+    fs::rename(&encrypted_path, path).map_err(|_| failed())?;
+    Ok(true)
+}
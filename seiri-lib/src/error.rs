@@ -24,12 +24,42 @@ quick_error! {
         UnableToMove(file_name: String) {
             display(r#"The file {} could not be moved."#, file_name)
         }
+        UnableToSetPermissions(file_name: String) {
+            display(r#"The permissions of the file {} could not be set."#, file_name)
+        }
         FileIOError(file_name:  PathBuf) {
             display(r#"The file {:?} could not be processed."#, file_name)
         }
+        DecodeError(file_name: PathBuf) {
+            display(r#"The audio of the file {:?} could not be decoded."#, file_name)
+        }
         UnableToCreateDirectory(directory_name: String) {
             display(r#"The directory {} could not be created."#, directory_name)
         }
+        HookFailed(file_name: String, command: String) {
+            display(r#"The hook "{}" could not be run on the file "{}""#, command, file_name)
+        }
+        DownloadFailed(url: String) {
+            display(r#"Nothing could be downloaded from "{}""#, url)
+        }
+        CastFailed(target: String) {
+            display(r#"Could not cast to "{}""#, target)
+        }
+        InvalidSplits(file_name: String) {
+            display(r#"The recording "{}" can not be split at the given cuts"#, file_name)
+        }
+        TranscodeFailed(file_name: String, format: String) {
+            display(r#"The file "{}" could not be transcoded to {}"#, file_name, format)
+        }
+        WebhookFailed(url: String) {
+            display(r#"The event could not be delivered to the webhook "{}""#, url)
+        }
+        SyncFailed(peer: String) {
+            display(r#"Could not sync with "{}""#, peer)
+        }
+        HttpFailed(address: String) {
+            display(r#"Could not answer the HTTP request of "{}""#, address)
+        }
         UnsupportedOS {
             display("The operating system is unsupported.")
         }
@@ -45,8 +75,8 @@ quick_error! {
         LexerUnexpectedEndOfInput {
             display(r#"Input ended before argument was fully parsed."#)
         }
-        ParserUnexpectedToken(t: Token) {
-            display(r#"Unexpected "{:?}" when parsing query"#, t)
+        ParserUnexpectedToken(t: Token, position: usize) {
+            display(r#"Unexpected "{:?}" at position {} when parsing query"#, t, position)
         }
         ParserUnknownBang(b: String) {
             display(r#"Unknown bang !"{:?}" when parsing query"#, b)
@@ -54,8 +84,26 @@ quick_error! {
         ParserInvalidInput(input: String) {
             display(r#"Invalid input "{}" when parsing bang"#, input)
         }
+        UnknownColumn(column: String) {
+            display(r#"Unknown column "{}" when parsing columns"#, column)
+        }
         ConfigError(error: ConfigErrorType) {
             display(r#"Error "{:?}" when parsing configuration"#, error)
         }
+        DatabaseKeyUnavailable(command: String) {
+            display(r#"The key of the library could not be read with "{}""#, command)
+        }
+        EncryptionUnsupported {
+            display("The library can not be encrypted without the sqlcipher feature.")
+        }
+        InsecureNetworkConfig(bind_address: String) {
+            display(r#"Refusing to expose APIs on {}, since they are only served on localhost"#, bind_address)
+        }
+        Unauthorized {
+            display("The request did not provide a valid authentication token.")
+        }
+        Forbidden {
+            display("The authentication token of the request does not allow it.")
+        }
     }
 }
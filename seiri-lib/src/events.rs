@@ -0,0 +1,279 @@
+use std::borrow::Cow;
+use std::fmt;
+
+/// An event emitted by the library, usually in response to importing tracks.
+///
+/// Events are written to stderr by seiri-watcher in the form `CODE::ARG1||ARG2`,
+/// which is the format the `Display` implementation produces.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    TrackAdded { artist: String, title: String, uuid: String },
+    /// A batch of files was processed by the watcher, of which `imported` were added to the library.
+    BatchImported { imported: usize, total: usize },
+    /// None of the tracks of the album in the given folder were imported, since some could not be.
+    AlbumIncomplete(String),
+    /// A sidecar file was moved alongside its album, to the given path.
+    SidecarAdded(String),
+    /// The purchase archive at the first path was unpacked into the folder at the second, to be imported.
+    ArchiveUnpacked(String, String),
+    /// The CD rip in the folder at the first path was verified by its rip log, and moved into the
+    /// folder of the watch folder at the second, to be imported as an album.
+    RipImported(String, String),
+    /// The import of the given file was vetoed by the hook with the given command,
+    /// and the file was moved into the not added folder.
+    ImportVetoed(String, String),
+    /// The given file is on the ignore list, so it was left where it is.
+    FileIgnored(String),
+    /// The file or folder at `path` was removed from the music folder, and with it `count` tracks.
+    TracksRemoved { path: String, count: usize },
+    /// `files` files were found in the watch folder when the watcher started, left there while
+    /// it was not running, and were queued to be processed.
+    FilesReconciled { files: usize },
+    /// The write lease passed to `holder` from `previous`, which is empty if nobody held it before.
+    LeaseChanged { holder: String, previous: String },
+    /// The given writer never released its write lease, and it lapsed.
+    LeaseLapsed(String),
+    /// The given writer lost its write lease to another writer while it was writing.
+    LeaseLost(String),
+    TrackMoveError(String),
+    /// The configured permissions could not be given to the given file, which was moved back to
+    /// where it was found.
+    PermissionsError(String),
+    CreateDirectoryError(String),
+    TrackError(String),
+    NonTrack(String),
+    MissingTag(String, &'static str),
+    /// The hook with the given command could not be run on the given file, which was left in place.
+    HookError(String, String),
+    LibraryNotFound(String),
+    WatcherError(String),
+    WatcherDied(String),
+    WatcherNoAccess(String),
+    WatcherRestart(String),
+    /// The watcher died, and is restarted for the `restarts`th time in a row in `delay` seconds.
+    WatcherRestarting { restarts: u32, delay: u64 },
+    /// The watcher died again after being restarted `restarts` times in a row, so it is left
+    /// stopped until seiri-watcher is restarted.
+    WatcherDegraded { restarts: u32 },
+    ConfigInvalid(String),
+    ConfigIOError(String),
+    /// The given track could not be analyzed, since its audio could not be decoded or stored.
+    AnalysisError(String),
+    /// An event could not be delivered to the webhook with the given URL, even after retrying.
+    WebhookError(String),
+    /// Profiles could not be synced with the instance at the given address.
+    SyncError(String),
+    /// A request to the HTTP API from the given address failed, or the API could not be served
+    /// on the given address.
+    HttpError(String),
+    /// The given archive could not be unpacked, and was moved into the not added folder.
+    ArchiveError(String),
+    /// The CD rip in the folder at the first path failed to be verified by its rip log for the
+    /// reason at the second, and was moved into the not added folder.
+    RipRejected(String, String),
+    /// The report of an import batch could not be written into the given folder.
+    ReportError(String),
+    /// A batch of `files` files was shed, since the import queue was full. The files are left
+    /// in the watch folder, and are imported the next time the watcher starts.
+    BatchShed { files: usize },
+    /// A conflicting copy of the library was found at the given path, left by the service syncing
+    /// its folder. The watcher does not run until the copy is merged with `resolveconflict`.
+    DatabaseConflict(String),
+    /// The given file is a placeholder of a file kept in cloud storage, so its folder is only
+    /// processed once it is downloaded.
+    WaitingForDownload(String),
+    /// The track with the given path or UUID, named by a command, is not in the library.
+    TrackNotFound(String),
+    /// No cast target with the given name was found on the local network.
+    CastTargetNotFound(String),
+    /// There is no subscription with the given identifier on the connection it was named on.
+    NotSubscribed(String),
+    /// No replica with the given name is known.
+    ReplicaNotFound(String),
+    /// The given command could not be understood, since its arguments are missing or malformed.
+    InvalidCommand(String),
+    /// The track with the given UUID was not ripped through the rip intake, so it has no provenance.
+    NoProvenance(String),
+    /// The value given for the field of a track could not be parsed as the type of the field.
+    InvalidFieldValue(String, String),
+    /// The track with the first UUID could not be linked to the track with the second, since
+    /// either is not in the library or they are the same track.
+    LinkError(String, String),
+    /// The track with the first UUID was not linked to the track with the second.
+    NotLinked(String, String),
+}
+
+impl Event {
+    /// The machine-readable code identifying the kind of event.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Event::TrackAdded { .. } => "TRACKADDED",
+            Event::BatchImported { .. } => "BATCHIMPORTED",
+            Event::SidecarAdded(_) => "SIDECARADDED",
+            Event::ArchiveUnpacked(_, _) => "ARCHIVEUNPACKED",
+            Event::RipImported(_, _) => "RIPIMPORTED",
+            Event::ImportVetoed(_, _) => "IMPORTVETOED",
+            Event::FileIgnored(_) => "FILEIGNORED",
+            Event::TracksRemoved { .. } => "TRACKSREMOVED",
+            Event::FilesReconciled { .. } => "FILESRECONCILED",
+            Event::WaitingForDownload(_) => "WAITINGFORDOWNLOAD",
+            Event::LeaseChanged { .. } => "LEASECHANGED",
+            Event::LeaseLapsed(_) => "ELEASELAPSED",
+            Event::LeaseLost(_) => "ELEASELOST",
+            Event::AlbumIncomplete(_) => "EALBUMINCOMPLETE",
+            Event::TrackMoveError(_) => "ETRACKMOVE",
+            Event::PermissionsError(_) => "EPERMISSIONS",
+            Event::CreateDirectoryError(_) => "ECREATEDIRECTORY",
+            Event::TrackError(_) => "ETRACK",
+            Event::NonTrack(_) => "ENONTRACK",
+            Event::MissingTag(_, _) => "EMISSINGTAG",
+            Event::HookError(_, _) => "EHOOK",
+            Event::LibraryNotFound(_) => "ELIBRARYNOTFOUND",
+            Event::WatcherError(_) => "EWATCHER",
+            Event::WatcherDied(_) => "EWATCHERDIED",
+            Event::WatcherNoAccess(_) => "EWATCHERNOACCESS",
+            Event::WatcherRestart(_) => "EWATCHERRESTART",
+            Event::WatcherRestarting { .. } => "EWATCHERRESTARTING",
+            Event::WatcherDegraded { .. } => "EWATCHERDEGRADED",
+            Event::ConfigInvalid(_) => "ECONFIGINVALID",
+            Event::ConfigIOError(_) => "ECONFIGIO",
+            Event::AnalysisError(_) => "EANALYSIS",
+            Event::WebhookError(_) => "EWEBHOOK",
+            Event::SyncError(_) => "ESYNC",
+            Event::HttpError(_) => "EHTTP",
+            Event::ArchiveError(_) => "EARCHIVE",
+            Event::RipRejected(_, _) => "ERIPREJECTED",
+            Event::ReportError(_) => "EREPORT",
+            Event::BatchShed { .. } => "EBATCHSHED",
+            Event::DatabaseConflict(_) => "EDBCONFLICT",
+            Event::TrackNotFound(_) => "ETRACKNOTFOUND",
+            Event::CastTargetNotFound(_) => "ECASTTARGET",
+            Event::NotSubscribed(_) => "ENOTSUBSCRIBED",
+            Event::ReplicaNotFound(_) => "EREPLICANOTFOUND",
+            Event::InvalidCommand(_) => "ECOMMAND",
+            Event::NoProvenance(_) => "ENOPROVENANCE",
+            Event::InvalidFieldValue(_, _) => "EFIELDVALUE",
+            Event::LinkError(_, _) => "ELINK",
+            Event::NotLinked(_, _) => "ENOTLINKED",
+        }
+    }
+
+    /// The arguments of the event, in order.
+    pub fn args(&self) -> Vec<Cow<'_, str>> {
+        match self {
+            Event::TrackAdded { artist, title, uuid } => {
+                vec![artist.into(), title.into(), uuid.into()]
+            }
+            Event::BatchImported { imported, total } => {
+                vec![imported.to_string().into(), total.to_string().into()]
+            }
+            Event::TracksRemoved { path, count } => vec![path.into(), count.to_string().into()],
+            Event::BatchShed { files } | Event::FilesReconciled { files } => vec![files.to_string().into()],
+            Event::WatcherRestarting { restarts, delay } => {
+                vec![restarts.to_string().into(), delay.to_string().into()]
+            }
+            Event::WatcherDegraded { restarts } => vec![restarts.to_string().into()],
+            Event::LeaseChanged { holder, previous } => vec![holder.into(), previous.into()],
+            Event::MissingTag(file_name, tag) => vec![file_name.into(), (*tag).into()],
+            Event::ImportVetoed(file_name, command) | Event::HookError(file_name, command) => {
+                vec![file_name.into(), command.into()]
+            }
+            Event::ArchiveUnpacked(archive, folder) => vec![archive.into(), folder.into()],
+            Event::RipImported(rip, folder) => vec![rip.into(), folder.into()],
+            Event::RipRejected(rip, reason) => vec![rip.into(), reason.into()],
+            Event::InvalidFieldValue(field, value) => vec![field.into(), value.into()],
+            Event::LinkError(track, related) | Event::NotLinked(track, related) => {
+                vec![track.into(), related.into()]
+            }
+            Event::SidecarAdded(arg)
+            | Event::FileIgnored(arg)
+            | Event::WaitingForDownload(arg)
+            | Event::LeaseLapsed(arg)
+            | Event::LeaseLost(arg)
+            | Event::AlbumIncomplete(arg)
+            | Event::TrackMoveError(arg)
+            | Event::PermissionsError(arg)
+            | Event::CreateDirectoryError(arg)
+            | Event::TrackError(arg)
+            | Event::NonTrack(arg)
+            | Event::LibraryNotFound(arg)
+            | Event::WatcherError(arg)
+            | Event::WatcherDied(arg)
+            | Event::WatcherNoAccess(arg)
+            | Event::WatcherRestart(arg)
+            | Event::ConfigInvalid(arg)
+            | Event::ConfigIOError(arg)
+            | Event::AnalysisError(arg)
+            | Event::WebhookError(arg)
+            | Event::SyncError(arg)
+            | Event::HttpError(arg)
+            | Event::ArchiveError(arg)
+            | Event::ReportError(arg)
+            | Event::DatabaseConflict(arg)
+            | Event::TrackNotFound(arg)
+            | Event::CastTargetNotFound(arg)
+            | Event::NotSubscribed(arg)
+            | Event::ReplicaNotFound(arg)
+            | Event::InvalidCommand(arg)
+            | Event::NoProvenance(arg) => vec![arg.into()],
+        }
+    }
+
+    /// The names of the arguments of the event, in the order of `args`, which are the variables
+    /// of its message in the message catalogs.
+    pub fn arg_names(&self) -> &'static [&'static str] {
+        match self {
+            Event::TrackAdded { .. } => &["artist", "title", "uuid"],
+            Event::BatchImported { .. } => &["imported", "total"],
+            Event::TracksRemoved { .. } => &["path", "count"],
+            Event::BatchShed { .. } | Event::FilesReconciled { .. } => &["files"],
+            Event::WatcherRestarting { .. } => &["restarts", "delay"],
+            Event::WatcherDegraded { .. } => &["restarts"],
+            Event::LeaseChanged { .. } => &["holder", "previous"],
+            Event::MissingTag(_, _) => &["file", "tag"],
+            Event::ImportVetoed(_, _) | Event::HookError(_, _) => &["file", "command"],
+            Event::ArchiveUnpacked(_, _) => &["archive", "folder"],
+            Event::RipImported(_, _) => &["rip", "folder"],
+            Event::RipRejected(_, _) => &["rip", "reason"],
+            Event::InvalidFieldValue(_, _) => &["field", "value"],
+            Event::LinkError(_, _) | Event::NotLinked(_, _) => &["track", "related"],
+            Event::LeaseLapsed(_) | Event::LeaseLost(_) => &["holder"],
+            Event::AlbumIncomplete(_) | Event::ReportError(_) => &["folder"],
+            Event::CreateDirectoryError(_) => &["directory"],
+            Event::TrackMoveError(_) | Event::PermissionsError(_) | Event::TrackError(_) | Event::NonTrack(_) => {
+                &["file"]
+            }
+            Event::WatcherError(_) | Event::WatcherDied(_) | Event::WatcherRestart(_) | Event::ConfigInvalid(_) => {
+                &["message"]
+            }
+            Event::WebhookError(_) => &["url"],
+            Event::SyncError(_) | Event::HttpError(_) => &["address"],
+            Event::ArchiveError(_) => &["archive"],
+            Event::SidecarAdded(_)
+            | Event::FileIgnored(_)
+            | Event::WaitingForDownload(_)
+            | Event::LibraryNotFound(_)
+            | Event::WatcherNoAccess(_)
+            | Event::ConfigIOError(_)
+            | Event::AnalysisError(_)
+            | Event::DatabaseConflict(_) => &["path"],
+            Event::TrackNotFound(_) => &["track"],
+            Event::CastTargetNotFound(_) => &["target"],
+            Event::NotSubscribed(_) => &["id"],
+            Event::ReplicaNotFound(_) => &["name"],
+            Event::InvalidCommand(_) => &["command"],
+            Event::NoProvenance(_) => &["track"],
+        }
+    }
+
+    /// Whether this event reports a failure.
+    pub fn is_error(&self) -> bool {
+        self.code().starts_with('E')
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}::{}", self.code(), self.args().join("||"))
+    }
+}
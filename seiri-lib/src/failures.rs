@@ -0,0 +1,166 @@
+//! The history of the failures of files to be imported or analyzed, so files that fail again and
+//! again can be found and dealt with at once, instead of being found again on every scan.
+//!
+//! Every failure is kept against the path of the file, along with the UUID of its track if the
+//! file is in the library, and is matched by the `!err` bang. Failures are kept until they are
+//! resolved, even once the file is imported.
+
+use crate::database;
+use crate::events::Event;
+use crate::Bang;
+use chrono::Local;
+use rusqlite::types::ToSql;
+use rusqlite::{Connection, Result, Row};
+use std::path::{Path, PathBuf};
+
+pub fn create_error_table(conn: &Connection) {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS errors (
+        ErrorId INTEGER PRIMARY KEY,
+        FilePath TEXT NOT NULL,
+        TrackId TEXT,
+        Occurred DATE NOT NULL,
+        Stage TEXT NOT NULL,
+        Code TEXT NOT NULL,
+        Message TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS errors_file_path ON errors(FilePath);
+    CREATE INDEX IF NOT EXISTS errors_track_id ON errors(TrackId);",
+    )
+    .unwrap();
+}
+
+/// What a file failed at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    Import,
+    Analysis,
+}
+
+impl Stage {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Stage::Import => "import",
+            Stage::Analysis => "analysis",
+        }
+    }
+
+    fn from_str(stage: &str) -> Stage {
+        match stage {
+            "analysis" => Stage::Analysis,
+            _ => Stage::Import,
+        }
+    }
+}
+
+/// A failure of a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileError {
+    pub error_id: i64,
+    pub file_path: PathBuf,
+    /// The UUID of the track of the file, if it was in the library when it failed.
+    pub track_id: Option<String>,
+    /// The local time the file failed at.
+    pub occurred: String,
+    pub stage: Stage,
+    /// The code of the event the failure was reported with, such as `EMISSINGTAG`.
+    pub code: String,
+    pub message: String,
+}
+
+/// A file that failed, along with how often it failed since its failures were last resolved.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProblemFile {
+    pub file_path: PathBuf,
+    pub failures: i64,
+    /// The local time the file last failed at.
+    pub last_occurred: String,
+    /// The code of the last failure of the file.
+    pub last_code: String,
+}
+
+fn error_from_row(row: &Row) -> Result<FileError> {
+    Ok(FileError {
+        error_id: row.get(0)?,
+        file_path: PathBuf::from(row.get::<_, String>(1)?),
+        track_id: row.get(2)?,
+        occurred: row.get(3)?,
+        stage: Stage::from_str(&row.get::<_, String>(4)?),
+        code: row.get(5)?,
+        message: row.get(6)?,
+    })
+}
+
+/// Records a failure of the file at the path.
+pub fn record_error(path: &Path, stage: Stage, code: &str, message: &str, conn: &Connection) -> Result<()> {
+    let file_path = path.to_string_lossy().into_owned();
+    conn.execute(
+        "INSERT INTO errors(FilePath, TrackId, Occurred, Stage, Code, Message)
+        VALUES (?1, (SELECT TrackId FROM tracks WHERE FilePath = ?1), ?2, ?3, ?4, ?5)",
+        &[
+            &file_path as &dyn ToSql,
+            &Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+            &stage.as_str(),
+            &code,
+            &message,
+        ],
+    )?;
+    Ok(())
+}
+
+/// Records the event as a failure of the file at the path, if it reports one.
+pub fn record_event(path: &Path, stage: Stage, event: &Event, conn: &Connection) -> Result<()> {
+    if !event.is_error() {
+        return Ok(());
+    }
+    record_error(path, stage, event.code(), &event.args().join(": "), conn)
+}
+
+/// Gets every failure of the file at the path, or of the track at the path, most recent first.
+pub fn get_errors(path: &Path, conn: &Connection) -> Result<Vec<FileError>> {
+    let mut statement = conn.prepare(
+        "SELECT ErrorId, FilePath, TrackId, Occurred, Stage, Code, Message FROM errors
+        WHERE FilePath = ?1 OR TrackId = (SELECT TrackId FROM tracks WHERE FilePath = ?1)
+        ORDER BY ErrorId DESC",
+    )?;
+    let errors = statement.query_map(&[&path.to_string_lossy().into_owned()], error_from_row)?;
+    errors.collect()
+}
+
+/// Gets every file that failed at least `min_failures` times, the files that failed most often first.
+pub fn get_problem_files(min_failures: i64, conn: &Connection) -> Result<Vec<ProblemFile>> {
+    let mut statement = conn.prepare(
+        "SELECT FilePath, COUNT(*), MAX(Occurred),
+            (SELECT Code FROM errors AS last WHERE last.FilePath = errors.FilePath ORDER BY ErrorId DESC LIMIT 1)
+        FROM errors GROUP BY FilePath HAVING COUNT(*) >= ?1
+        ORDER BY COUNT(*) DESC, MAX(Occurred) DESC",
+    )?;
+    let files = statement.query_map(&[&min_failures], |row| {
+        Ok(ProblemFile {
+            file_path: PathBuf::from(row.get::<_, String>(0)?),
+            failures: row.get(1)?,
+            last_occurred: row.get(2)?,
+            last_code: row.get(3)?,
+        })
+    })?;
+    files.collect()
+}
+
+/// Resolves every failure of the file at the path, or of the track at the path, returning how
+/// many were resolved.
+pub fn resolve_errors(path: &Path, conn: &Connection) -> Result<usize> {
+    conn.execute(
+        "DELETE FROM errors WHERE FilePath = ?1 OR TrackId = (SELECT TrackId FROM tracks WHERE FilePath = ?1)",
+        &[&path.to_string_lossy().into_owned()],
+    )
+}
+
+/// Resolves every failure of every track matching the bang, such as `!err{EANALYSIS}` once the
+/// cause is fixed, returning how many were resolved.
+pub fn resolve_matching(bang: Bang, conn: &Connection) -> Result<usize> {
+    let mut resolved = 0;
+    for track in database::query_tracks(bang, conn, None, None)? {
+        resolved += resolve_errors(&track.file_path, conn)?;
+    }
+    Ok(resolved)
+}
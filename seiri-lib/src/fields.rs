@@ -0,0 +1,136 @@
+//! Custom fields of tracks, such as their mood or energy, so the library can be extended with
+//! data that is not in the tags of tracks.
+//!
+//! Fields are configured as `[[custom_fields]]` tables, each naming its `type`, which is either
+//! `text`, `integer`, or `enum` along with the `values` it allows:
+//!
+//! ```toml
+//! [[custom_fields]]
+//! name = "mood"
+//! type = "enum"
+//! values = ["chill", "upbeat", "melancholic"]
+//!
+//! [[custom_fields]]
+//! name = "energy"
+//! type = "integer"
+//! ```
+//!
+//! Like notes, the values of fields are only kept in the library, by the UUID of their track, and
+//! are removed along with the track. Tracks are matched by the value of a field with the `!x`
+//! bang, such as `!x{mood=chill}`, or `!x{energy>5}` for integer fields.
+
+use crate::database::create_table_with_foreign_keys;
+use rusqlite::types::{ToSql, ToSqlOutput};
+use rusqlite::{Connection, Result};
+use serde_derive::{Deserialize, Serialize};
+use std::fmt;
+
+/// The type of the values of a custom field.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldType {
+    Text,
+    Integer,
+    /// One of the values the field allows.
+    Enum,
+}
+
+/// A custom field tracks can be given a value of.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CustomField {
+    /// The name of the field, which is matched ignoring case.
+    pub name: String,
+    #[serde(rename = "type")]
+    pub field_type: FieldType,
+    /// The values an `enum` field allows.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub values: Vec<String>,
+}
+
+/// The value of a custom field of a track.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FieldValue {
+    Text(String),
+    Integer(i64),
+}
+
+impl ToSql for FieldValue {
+    fn to_sql(&self) -> Result<ToSqlOutput<'_>> {
+        match self {
+            FieldValue::Text(text) => text.to_sql(),
+            FieldValue::Integer(integer) => integer.to_sql(),
+        }
+    }
+}
+
+impl fmt::Display for FieldValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FieldValue::Text(text) => write!(f, "{}", text),
+            FieldValue::Integer(integer) => write!(f, "{}", integer),
+        }
+    }
+}
+
+impl CustomField {
+    /// Parses the value of the field, or returns `None` if the field does not allow it.
+    /// The values of `enum` fields are spelled as they are configured.
+    pub fn parse_value(&self, value: &str) -> Option<FieldValue> {
+        let value = value.trim();
+        match self.field_type {
+            FieldType::Text => Some(FieldValue::Text(value.to_owned())).filter(|_| !value.is_empty()),
+            FieldType::Integer => value.parse().ok().map(FieldValue::Integer),
+            FieldType::Enum => self
+                .values
+                .iter()
+                .find(|allowed| allowed.eq_ignore_ascii_case(value))
+                .map(|allowed| FieldValue::Text(allowed.to_owned())),
+        }
+    }
+}
+
+/// Gets the configured field with the name, ignoring case.
+pub fn find_field<'a>(fields: &'a [CustomField], name: &str) -> Option<&'a CustomField> {
+    fields.iter().find(|field| field.name.eq_ignore_ascii_case(name.trim()))
+}
+
+pub fn create_field_table(conn: &Connection) {
+    // Values have numeric affinity, so integers compare as integers
+    // against the values of `!x` bangs, which are always given as text.
+    create_table_with_foreign_keys(
+        "custom_fields",
+        "TrackId TEXT NOT NULL REFERENCES tracks(TrackId) ON DELETE CASCADE,
+        Field TEXT NOT NULL,
+        Value NUMERIC NOT NULL,
+        PRIMARY KEY (TrackId, Field)",
+        conn,
+    )
+    .unwrap();
+}
+
+/// Sets the field of the track with the given UUID to the value, replacing any value it had.
+/// Returns whether there is such a track.
+pub fn set_field(uuid: &str, field: &CustomField, value: &FieldValue, conn: &Connection) -> Result<bool> {
+    let set = conn.execute(
+        "INSERT INTO custom_fields(TrackId, Field, Value) SELECT TrackId, ?2, ?3 FROM tracks WHERE TrackId = ?1
+        ON CONFLICT(TrackId, Field) DO UPDATE SET Value = excluded.Value",
+        &[&uuid as &dyn ToSql, &field.name.to_lowercase(), value],
+    )?;
+    Ok(set > 0)
+}
+
+pub fn remove_field(uuid: &str, name: &str, conn: &Connection) -> Result<()> {
+    conn.execute(
+        "DELETE FROM custom_fields WHERE TrackId = ?1 AND Field = ?2",
+        &[uuid, &name.trim().to_lowercase()],
+    )?;
+    Ok(())
+}
+
+/// Gets the fields the track with the given UUID has a value of, by their name in lowercase.
+pub fn get_fields(uuid: &str, conn: &Connection) -> Result<Vec<(String, String)>> {
+    let mut statement =
+        conn.prepare("SELECT Field, CAST(Value AS TEXT) FROM custom_fields WHERE TrackId = ?1 ORDER BY Field")?;
+    let rows = statement.query_map(&[uuid], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    rows.collect()
+}
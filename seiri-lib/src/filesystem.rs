@@ -0,0 +1,319 @@
+//! The file system the watcher reads the watch folder from.
+//!
+//! The watcher only ever looks at the watch folder through `WatchFileSystem`, so the pipeline of
+//! `watcher::list_in`, `watcher::WatchQueue` and `watcher::process_batch_in` can be driven over a
+//! file system other than the disk, such as a `MemoryFileSystem` in tests or a remote folder
+//! when the pipeline is embedded elsewhere. The function that processes the files is handed the
+//! file system too, and reads and moves the tracks through it, so the files moved by an abandoned
+//! batch are moved back within the same file system.
+
+use crate::config::Config;
+use crate::database::Connection;
+use crate::error::{Error, Result};
+use crate::paths;
+use crate::placeholders;
+use crate::rejections;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use katatsuki::Track;
+use walkdir::{DirEntry, WalkDir};
+
+/// The files directly inside a folder along with their sizes, used to tell when the folder has settled.
+pub type FolderSnapshot = Vec<(PathBuf, u64)>;
+
+/// Whether the file or folder at the path is hidden, by its name.
+pub(crate) fn is_hidden_path(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|s| s.to_str())
+        .map(|s| s.starts_with('.'))
+        .unwrap_or(false)
+}
+
+/// A file system the watch folder is read from.
+pub trait WatchFileSystem: Send + Sync {
+    /// Gets the file at the path, or every file in the folder at the path and its subfolders,
+    /// other than hidden files and the files of hidden folders.
+    fn files_under(&self, path: &Path) -> Vec<PathBuf>;
+
+    /// Gets the files directly inside the folder other than hidden files, along with their
+    /// sizes, sorted by path.
+    fn snapshot_folder(&self, folder: &Path) -> FolderSnapshot;
+
+    fn is_file(&self, path: &Path) -> bool;
+
+    /// Whether the file is done being written to, so it can be processed.
+    fn is_idle(&self, path: &Path) -> bool;
+
+    /// Whether the file is a placeholder of a file kept in cloud storage, which is downloaded
+    /// before it is processed.
+    fn is_placeholder(&self, path: &Path) -> bool;
+
+    /// Starts downloading the placeholder, without waiting for it to be downloaded.
+    fn hydrate(&self, path: &Path);
+
+    /// Reads the tags of the track at the path.
+    fn read_track(&self, path: &Path) -> Result<Track>;
+
+    /// Moves the file, recording the move in the `paths::MoveJournal` of the thread if one is kept.
+    fn move_file(&self, from: &Path, to: &Path) -> io::Result<()>;
+
+    /// Moves the files back to where they were, in the reverse order they were moved in.
+    /// Returns the files that could not be moved back.
+    fn undo_moves(&self, moves: &[(PathBuf, PathBuf)]) -> Vec<PathBuf>;
+
+    /// Cleans up the watch folder as configured, once the groups of files were processed.
+    fn clean_up(&self, groups: &[Vec<PathBuf>], config: &Config, conn: &Connection);
+}
+
+/// The disk, which the watcher reads the watch folder from unless it is given another file system.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DiskFileSystem;
+
+impl WatchFileSystem for DiskFileSystem {
+    fn files_under(&self, path: &Path) -> Vec<PathBuf> {
+        let is_hidden = |entry: &DirEntry| is_hidden_path(entry.path());
+        WalkDir::new(path)
+            .into_iter()
+            .filter_entry(|entry| !is_hidden(entry))
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_file())
+            .map(|entry| entry.into_path())
+            .collect()
+    }
+
+    fn snapshot_folder(&self, folder: &Path) -> FolderSnapshot {
+        let mut snapshot = fs::read_dir(folder)
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file() && !is_hidden_path(path))
+            .map(|path| {
+                let len = path.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+                (path, len)
+            })
+            .collect::<FolderSnapshot>();
+        snapshot.sort();
+        snapshot
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        path.is_file()
+    }
+
+    fn is_idle(&self, path: &Path) -> bool {
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(false)
+            .truncate(false)
+            .open(path)
+            .is_ok()
+    }
+
+    fn is_placeholder(&self, path: &Path) -> bool {
+        placeholders::is_placeholder(path)
+    }
+
+    fn hydrate(&self, path: &Path) {
+        placeholders::hydrate(path)
+    }
+
+    fn read_track(&self, path: &Path) -> Result<Track> {
+        paths::read_track(path, None)
+    }
+
+    fn move_file(&self, from: &Path, to: &Path) -> io::Result<()> {
+        paths::move_file(from, to)
+    }
+
+    fn undo_moves(&self, moves: &[(PathBuf, PathBuf)]) -> Vec<PathBuf> {
+        paths::undo_moves(moves)
+    }
+
+    fn clean_up(&self, groups: &[Vec<PathBuf>], config: &Config, conn: &Connection) {
+        let auto_add_path = match paths::ensure_music_folder(&config.music_folder) {
+            Ok((_, auto_add_path)) => auto_add_path,
+            Err(_) => return,
+        };
+        if config.cleanup.remove_empty_folders {
+            let folders = groups
+                .iter()
+                .flatten()
+                .filter_map(|path| path.parent())
+                .collect::<BTreeSet<&Path>>();
+            for folder in folders {
+                paths::remove_empty_folders(folder, &auto_add_path);
+            }
+        }
+        if let Some(days) = config.cleanup.purge_not_added_after {
+            for folder in paths::purge_not_added(&auto_add_path, days).unwrap_or_default() {
+                rejections::forget_quarantined_in(&folder, conn).ok();
+            }
+        }
+    }
+}
+
+/// A file kept by a `MemoryFileSystem`.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryFile {
+    pub size: u64,
+    /// Whether the file is still being written to.
+    pub busy: bool,
+    /// Whether the file is a placeholder that is not downloaded yet.
+    pub placeholder: bool,
+    /// The tags of the file if it is a track, whose path is set to wherever the file is read from.
+    pub track: Option<Track>,
+}
+
+/// A file system kept in memory, for driving the watcher without touching the disk.
+///
+/// Folders exist as long as there are files in them. Files are changed by the methods of the file
+/// system, and the watcher is told about the change with `watcher::WatchQueue::handle`. Nothing is
+/// ever cleaned up, and placeholders are only downloaded by `download`.
+#[derive(Debug, Default)]
+pub struct MemoryFileSystem {
+    files: Mutex<BTreeMap<PathBuf, MemoryFile>>,
+    hydrated: Mutex<Vec<PathBuf>>,
+}
+
+impl MemoryFileSystem {
+    pub fn new() -> MemoryFileSystem {
+        MemoryFileSystem::default()
+    }
+
+    fn files(&self) -> std::sync::MutexGuard<'_, BTreeMap<PathBuf, MemoryFile>> {
+        self.files.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Adds the file, replacing any file at its path.
+    pub fn add_file<P: Into<PathBuf>>(&self, path: P, file: MemoryFile) {
+        self.files().insert(path.into(), file);
+    }
+
+    /// Changes the size of the file, as if it was written to.
+    pub fn resize(&self, path: &Path, size: u64) {
+        if let Some(file) = self.files().get_mut(path) {
+            file.size = size;
+        }
+    }
+
+    /// Marks the file as done being written to.
+    pub fn finish_writing(&self, path: &Path) {
+        if let Some(file) = self.files().get_mut(path) {
+            file.busy = false;
+        }
+    }
+
+    /// Downloads the placeholder, so it is an ordinary file.
+    pub fn download(&self, path: &Path) {
+        if let Some(file) = self.files().get_mut(path) {
+            file.placeholder = false;
+        }
+    }
+
+    /// Removes the file, or every file in the folder at the path.
+    pub fn remove(&self, path: &Path) {
+        self.files().retain(|file_path, _| !file_path.starts_with(path));
+    }
+
+    /// Moves the file, or every file in the folder at the path.
+    pub fn rename(&self, from: &Path, to: &Path) {
+        let mut files = self.files();
+        let moved = files
+            .keys()
+            .filter(|file_path| file_path.starts_with(from))
+            .cloned()
+            .collect::<Vec<PathBuf>>();
+        for file_path in moved {
+            if let (Some(file), Ok(rest)) = (files.remove(&file_path), file_path.strip_prefix(from)) {
+                let new_path = if rest.as_os_str().is_empty() { to.to_owned() } else { to.join(rest) };
+                files.insert(new_path, file);
+            }
+        }
+    }
+
+    /// Gets the placeholders the watcher started downloading, in the order it did.
+    pub fn hydrated(&self) -> Vec<PathBuf> {
+        self.hydrated.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+    }
+}
+
+impl WatchFileSystem for MemoryFileSystem {
+    fn files_under(&self, path: &Path) -> Vec<PathBuf> {
+        self.files()
+            .keys()
+            .filter(|file_path| {
+                file_path.strip_prefix(path).is_ok_and(|rest| {
+                    !is_hidden_path(path)
+                        && !rest.components().any(|component| component.as_os_str().to_string_lossy().starts_with('.'))
+                })
+            })
+            .cloned()
+            .collect()
+    }
+
+    fn snapshot_folder(&self, folder: &Path) -> FolderSnapshot {
+        self.files()
+            .iter()
+            .filter(|(file_path, _)| file_path.parent() == Some(folder) && !is_hidden_path(file_path))
+            .map(|(file_path, file)| (file_path.clone(), file.size))
+            .collect()
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        self.files().contains_key(path)
+    }
+
+    fn is_idle(&self, path: &Path) -> bool {
+        self.files().get(path).is_some_and(|file| !file.busy)
+    }
+
+    fn is_placeholder(&self, path: &Path) -> bool {
+        self.files().get(path).is_some_and(|file| file.placeholder)
+    }
+
+    fn hydrate(&self, path: &Path) {
+        self.hydrated
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .push(path.to_owned());
+    }
+
+    fn read_track(&self, path: &Path) -> Result<Track> {
+        match self.files().get(path) {
+            Some(MemoryFile { track: Some(track), .. }) => Ok(Track {
+                file_path: path.to_owned(),
+                ..track.clone()
+            }),
+            Some(_) => Err(Error::UnsupportedFile(path.to_owned())),
+            None => Err(Error::FileIOError(path.to_owned())),
+        }
+    }
+
+    fn move_file(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let mut files = self.files();
+        if files.contains_key(to) {
+            return Err(io::Error::from(ErrorKind::AlreadyExists));
+        }
+        let file = files.remove(from).ok_or_else(|| io::Error::from(ErrorKind::NotFound))?;
+        files.insert(to.to_owned(), file);
+        paths::journal_move(from, to);
+        Ok(())
+    }
+
+    fn undo_moves(&self, moves: &[(PathBuf, PathBuf)]) -> Vec<PathBuf> {
+        moves
+            .iter()
+            .rev()
+            .filter(|(from, to)| self.move_file(to, from).is_err())
+            .map(|(_, to)| to.clone())
+            .collect()
+    }
+
+    fn clean_up(&self, _groups: &[Vec<PathBuf>], _config: &Config, _conn: &Connection) {}
+}
@@ -0,0 +1,96 @@
+//! The ReplayGain of tracks, and the gain each track is played at, so playback clients are given
+//! the gain to play a track at with the track itself, instead of reading its tags and choosing
+//! between its track and album gain themselves.
+//!
+//! Gains are read from the ReplayGain tags of tracks by the analysis job, and are kept in the
+//! database by track ID like its other results, so they are removed along with their tracks and
+//! read again whenever their track is updated. Tracks without ReplayGain tags are kept without
+//! gains, so their tags are not read again every time the job runs.
+
+use crate::config::{GainMode, PlaybackConfig};
+use katatsuki::Track;
+use rusqlite::types::ToSql;
+use rusqlite::{Connection, OptionalExtension, Result, Row};
+use std::collections::HashMap;
+
+/// The ReplayGain of a track, as it is tagged. Gains are in decibels, and peaks are amplitudes
+/// where 1 is full scale.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Gain {
+    pub track_gain: Option<f64>,
+    pub track_peak: Option<f64>,
+    pub album_gain: Option<f64>,
+    pub album_peak: Option<f64>,
+}
+
+impl Gain {
+    /// The gain in decibels the track is played at as configured, if it has a gain to play it at.
+    ///
+    /// If clipping is prevented, the gain is lowered just so the peak of the track is played at
+    /// full scale, if it would otherwise be played over it.
+    pub fn playback_gain(&self, config: &PlaybackConfig) -> Option<f64> {
+        let (gain, peak) = match (config.gain_mode, self.album_gain) {
+            (GainMode::Album, Some(album_gain)) => (album_gain, self.album_peak),
+            _ => (self.track_gain?, self.track_peak),
+        };
+        match peak {
+            Some(peak) if config.prevent_clipping && peak > 0.0 => Some(gain.min(-20.0 * peak.log10())),
+            _ => Some(gain),
+        }
+    }
+}
+
+/// Parses a tagged gain, such as `-6.50 dB`, or a tagged peak, such as `0.988525`.
+pub fn parse_gain_value(value: &str) -> Option<f64> {
+    let number = value.trim().trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    number.trim().parse::<f64>().ok().filter(|number| number.is_finite())
+}
+
+fn gain_from_row(row: &Row) -> Result<Gain> {
+    Ok(Gain {
+        track_gain: row.get(0)?,
+        track_peak: row.get(1)?,
+        album_gain: row.get(2)?,
+        album_peak: row.get(3)?,
+    })
+}
+
+/// Saves the gain of the track with the given UUID.
+pub fn save_gain(track_id: &str, gain: &Gain, conn: &Connection) -> Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO gains(TrackId, TrackGain, TrackPeak, AlbumGain, AlbumPeak, Updated)
+        VALUES (?1, ?2, ?3, ?4, ?5, (SELECT Updated FROM tracks WHERE TrackId = ?1))",
+        &[
+            &track_id as &dyn ToSql,
+            &gain.track_gain,
+            &gain.track_peak,
+            &gain.album_gain,
+            &gain.album_peak,
+        ],
+    )?;
+    Ok(())
+}
+
+/// Gets the gain of the track with the given UUID, if its tags were read.
+pub fn get_gain(track_id: &str, conn: &Connection) -> Result<Option<Gain>> {
+    conn.query_row(
+        "SELECT TrackGain, TrackPeak, AlbumGain, AlbumPeak FROM gains WHERE TrackId = ?1",
+        &[track_id],
+        gain_from_row,
+    )
+    .optional()
+}
+
+/// Gets the gain every one of the tracks is played at as configured, by the UUIDs of the tracks.
+/// Tracks without a gain to play them at are left out.
+pub fn get_playback_gains(tracks: &[Track], config: &PlaybackConfig, conn: &Connection) -> Result<HashMap<String, f64>> {
+    let mut statement = conn.prepare("SELECT TrackGain, TrackPeak, AlbumGain, AlbumPeak FROM gains WHERE TrackId = ?1")?;
+    let mut gains = HashMap::new();
+    for track_id in tracks.iter().filter_map(|track| track.uuid.as_deref()) {
+        let gain = statement.query_row(&[track_id], gain_from_row).optional()?;
+        if let Some(gain) = gain.and_then(|gain| gain.playback_gain(config)) {
+            gains.insert(track_id.to_owned(), gain);
+        }
+    }
+    Ok(gains)
+}
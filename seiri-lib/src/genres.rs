@@ -0,0 +1,159 @@
+//! Genres of tracks, and the genre tree they are searched by.
+//!
+//! Every genre a track is tagged with is kept in the track_genres table, so tracks can
+//! have many genres. The genre tree is edited by the user, by giving a genre a parent,
+//! and `!g{electronic/*}` matches tracks of electronic and every genre under it.
+//! Genre names are unique within the tree and ignore case, so only the last genre of a
+//! path names the genre, and `!g{electronic/house}` is the same as `!g{house}`.
+
+use crate::database::create_table_with_foreign_keys;
+use rusqlite::{Connection, OptionalExtension, Result, NO_PARAMS};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+pub fn create_genre_tables(conn: &Connection) {
+    create_table_with_foreign_keys(
+        "track_genres",
+        "FilePath TEXT NOT NULL REFERENCES tracks(FilePath) ON DELETE CASCADE ON UPDATE CASCADE,
+        Genre TEXT NOT NULL COLLATE NOCASE,
+        PRIMARY KEY (FilePath, Genre)",
+        conn,
+    )
+    .unwrap();
+    conn.execute_batch(
+        "CREATE INDEX IF NOT EXISTS track_genres_genre ON track_genres(Genre);
+    CREATE TABLE IF NOT EXISTS genre_tree (
+        Genre TEXT PRIMARY KEY COLLATE NOCASE,
+        Parent TEXT NOT NULL COLLATE NOCASE
+    );",
+    )
+    .unwrap();
+}
+
+/// Splits the argument of a `!g` bang into the genre it names, and whether
+/// it also matches every genre under it.
+pub(crate) fn parse_genre_path(path: &str) -> (&str, bool) {
+    let (path, subgenres) = match path.trim().strip_suffix("/*") {
+        Some(path) => (path, true),
+        None => (path.trim(), false),
+    };
+    (path.rsplit('/').next().unwrap_or("").trim(), subgenres)
+}
+
+/// An SQL query selecting the genre bound to the given parameter and every genre under it.
+pub(crate) fn subgenres_query(param: &str) -> String {
+    format!(
+        "WITH RECURSIVE subgenres(Genre) AS (
+            SELECT {} UNION
+            SELECT genre_tree.Genre FROM genre_tree JOIN subgenres ON genre_tree.Parent = subgenres.Genre
+        ) SELECT Genre FROM subgenres",
+        param
+    )
+}
+
+/// Replaces the genres of the track at the given path.
+pub(crate) fn set_track_genres(file_path: &str, genres: &[String], conn: &Connection) -> Result<()> {
+    conn.execute("DELETE FROM track_genres WHERE FilePath = ?1", &[file_path])?;
+    let mut statement =
+        conn.prepare("INSERT OR IGNORE INTO track_genres(FilePath, Genre) VALUES (?1, ?2)")?;
+    for genre in genres {
+        statement.execute(&[file_path, genre])?;
+    }
+    Ok(())
+}
+
+/// Records an update of every track of the genre or any genre under it, since changing
+/// the tree changes which tracks `!g` matches.
+///
+/// Triggers can not run recursive queries, so this is done by every function changing the tree.
+fn log_genre_change(genre: &str, conn: &Connection) -> Result<()> {
+    conn.execute(
+        &format!(
+            "INSERT INTO changes(FilePath, Operation)
+            SELECT DISTINCT FilePath, 1 FROM track_genres WHERE Genre IN ({})",
+            subgenres_query("?1")
+        ),
+        &[genre],
+    )?;
+    Ok(())
+}
+
+/// Gets the parent of the genre in the genre tree, if it has one.
+pub fn get_genre_parent(genre: &str, conn: &Connection) -> Result<Option<String>> {
+    conn.query_row(
+        "SELECT Parent FROM genre_tree WHERE Genre = ?1",
+        &[genre],
+        |row| row.get(0),
+    )
+    .optional()
+}
+
+/// Places the genre under the parent in the genre tree, replacing its previous parent.
+///
+/// Returns false without changing the tree if the parent is the genre itself,
+/// or is already under the genre.
+pub fn set_genre_parent(genre: &str, parent: &str, conn: &Connection) -> Result<bool> {
+    let is_subgenre = conn
+        .prepare(&format!(
+            "SELECT 1 FROM ({}) WHERE Genre = ?2 COLLATE NOCASE",
+            subgenres_query("?1")
+        ))?
+        .exists(&[genre, parent])?;
+    if is_subgenre {
+        return Ok(false);
+    }
+    conn.execute(
+        "INSERT INTO genre_tree(Genre, Parent) VALUES (?1, ?2)
+        ON CONFLICT(Genre) DO UPDATE SET Parent = excluded.Parent",
+        &[genre, parent],
+    )?;
+    log_genre_change(genre, conn)?;
+    Ok(true)
+}
+
+/// Removes the genre from under its parent, leaving it at the top of the genre tree.
+/// Returns false if the genre had no parent.
+pub fn remove_genre_parent(genre: &str, conn: &Connection) -> Result<bool> {
+    log_genre_change(genre, conn)?;
+    let removed = conn.execute("DELETE FROM genre_tree WHERE Genre = ?1", &[genre])?;
+    Ok(removed > 0)
+}
+
+/// Gets the genre tree, as a map from every genre with genres under it to those genres.
+pub fn get_genre_tree(conn: &Connection) -> Result<BTreeMap<String, Vec<String>>> {
+    let mut statement =
+        conn.prepare("SELECT Parent, Genre FROM genre_tree ORDER BY Parent, Genre")?;
+    let mut rows = statement.query(NO_PARAMS)?;
+    let mut tree = BTreeMap::<String, Vec<String>>::new();
+    let mut last_parent: Option<String> = None;
+    while let Some(row) = rows.next()? {
+        // Parents ignore case, so a parent given in different cases is kept under the first.
+        let parent = match (last_parent, row.get::<_, String>(0)?) {
+            (Some(last), parent) if last.eq_ignore_ascii_case(&parent) => last,
+            (_, parent) => parent,
+        };
+        tree.entry(parent.clone()).or_default().push(row.get(1)?);
+        last_parent = Some(parent);
+    }
+    Ok(tree)
+}
+
+/// Gets every genre of every track in the library, along with the number of tracks of each.
+pub fn get_genres(conn: &Connection) -> Result<Vec<(String, i64)>> {
+    let mut statement = conn.prepare(
+        "SELECT Genre, COUNT(*) FROM track_genres GROUP BY Genre ORDER BY Genre",
+    )?;
+    let genres = statement
+        .query_map(NO_PARAMS, |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<Vec<(String, i64)>>>()?;
+    Ok(genres)
+}
+
+/// Gets the genres of the track at the given path.
+pub fn get_track_genres(file_path: &Path, conn: &Connection) -> Result<Vec<String>> {
+    let mut statement = conn.prepare("SELECT Genre FROM track_genres WHERE FilePath = ?1")?;
+    let genres = statement
+        .query_map(&[&file_path.to_string_lossy().into_owned()], |row| row.get(0))?
+        .collect::<Result<Vec<String>>>()?;
+    Ok(genres)
+}
@@ -0,0 +1,213 @@
+//! Hooks, which run user-provided programs at stages of an import.
+//!
+//! A hook is a command run with the name of its stage and the path of the file being imported
+//! as its last two arguments, and the tags of the track in `SEIRI_*` environment variables,
+//! such as `SEIRI_TITLE` and `SEIRI_ALBUM_ARTISTS`. Other programs, such as a WebAssembly
+//! runtime running a plugin, can be run by giving them as the command.
+//!
+//! A hook changes the tags of the track by printing lines such as `title=New Title`
+//! to stdout, and vetoes the import by exiting with a failing status. Changes are only made
+//! to the library, and are not written back to the tags of the file.
+//! Hooks run in the order they are configured, each seeing the changes of the hooks before it.
+//! A hook that runs past its timeout is stopped, and the import does not go ahead.
+
+use crate::error::{Error, Result};
+use katatsuki::{split_genres, Track};
+use serde_derive::{Deserialize, Serialize};
+use std::io::{self, Read};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often a running hook is checked on, to stop it once it runs past its timeout.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The stages of an import hooks run at.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum HookStage {
+    /// Before the tags of the file are read, for every file other than sidecar files.
+    /// Hooks at this stage only get the path of the file, and can only veto the import.
+    PreImport,
+    /// After the tags of the track are read, before it is moved into the library.
+    PostTagRead,
+    /// After the track is moved into the library, before it is added to the database.
+    /// The track is already in the library folder, so hooks at this stage can not veto it.
+    PostMove,
+}
+
+impl HookStage {
+    /// The name of the stage, as passed to hooks.
+    pub fn name(&self) -> &'static str {
+        match self {
+            HookStage::PreImport => "pre-import",
+            HookStage::PostTagRead => "post-tag-read",
+            HookStage::PostMove => "post-move",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Hook {
+    pub stage: HookStage,
+    /// The program to run, followed by its arguments.
+    pub command: Vec<String>,
+}
+
+/// A change to a tag of a track, as the name of the tag and its new value.
+pub type Change = (String, String);
+
+/// The outcome of running the hooks of a stage.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    /// Every hook let the import go ahead, making the given changes.
+    Accepted(Vec<Change>),
+    /// The hook with the given command vetoed the import.
+    Vetoed(String),
+}
+
+/// Applies the change to the track, ignoring changes to unknown tags and invalid numbers.
+fn apply_change(track: &mut Track, (tag, value): &Change) {
+    let number = || value.trim().parse::<i32>().ok();
+    match tag.as_str() {
+        "title" => track.title = value.to_owned(),
+        "artist" => track.artist = value.to_owned(),
+        "album" => track.album = value.to_owned(),
+        "album_artists" => track.album_artists = value.split(';').map(|artist| artist.to_owned()).collect(),
+        "genres" => track.genres = split_genres(value),
+        "year" => track.year = number().unwrap_or(track.year),
+        "track_number" => track.track_number = number().unwrap_or(track.track_number),
+        "disc_number" => track.disc_number = number().unwrap_or(track.disc_number),
+        _ => (),
+    }
+}
+
+/// Applies the changes made by hooks to the track, in order.
+pub fn apply_changes(track: &mut Track, changes: &[Change]) {
+    changes.iter().for_each(|change| apply_change(track, change));
+}
+
+fn track_env(track: &Track) -> Vec<(&'static str, String)> {
+    vec![
+        ("SEIRI_TITLE", track.title.to_owned()),
+        ("SEIRI_ARTIST", track.artist.to_owned()),
+        ("SEIRI_ALBUM", track.album.to_owned()),
+        ("SEIRI_ALBUM_ARTISTS", track.album_artists.join(";")),
+        ("SEIRI_GENRES", track.genres.join(";")),
+        ("SEIRI_YEAR", track.year.to_string()),
+        ("SEIRI_TRACK_NUMBER", track.track_number.to_string()),
+        ("SEIRI_DISC_NUMBER", track.disc_number.to_string()),
+        ("SEIRI_SOURCE", track.source.to_owned()),
+    ]
+}
+
+/// Runs the command, returning whether it succeeded and what it printed to stdout,
+/// or `None` if it ran past the timeout, in which case it is killed.
+fn run_with_timeout(command: &mut Command, timeout: Duration) -> io::Result<Option<(bool, Vec<u8>)>> {
+    let mut child = command.stdout(Stdio::piped()).spawn()?;
+    // Stdout is read alongside, so a hook printing more than the pipe holds does not block on it.
+    let mut stdout = child.stdout.take().expect("stdout is piped");
+    let reader = thread::spawn(move || {
+        let mut output = Vec::new();
+        stdout.read_to_end(&mut output).map(|_| output)
+    });
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            child.kill().ok();
+            child.wait()?;
+            return Ok(None);
+        }
+        thread::sleep(POLL_INTERVAL);
+    };
+    let output = reader.join().unwrap_or_else(|_| Ok(Vec::new()))?;
+    Ok(Some((status.success(), output)))
+}
+
+/// Runs every hook of the stage on the file at the given path, whose tags are given at
+/// every stage after `HookStage::PreImport`. The changes of each hook are applied to the track.
+///
+/// Returns an error if a hook could not be run or ran past the timeout, in which case the import
+/// should not go ahead.
+pub fn run_hooks(
+    stage: HookStage,
+    file_path: &Path,
+    mut track: Option<&mut Track>,
+    hooks: &[Hook],
+    timeout: Duration,
+) -> Result<Verdict> {
+    let mut changes = Vec::new();
+    for hook in hooks.iter().filter(|hook| hook.stage == stage) {
+        let command = hook.command.join(" ");
+        let failed = || Error::HookFailed(file_path.to_string_lossy().into_owned(), command.clone());
+        let (program, args) = hook.command.split_first().ok_or_else(failed)?;
+        // Hooks write nothing to stderr, which seiri-watcher reports events on.
+        let output = run_with_timeout(
+            Command::new(program)
+                .args(args)
+                .arg(stage.name())
+                .arg(file_path)
+                .envs(track.as_deref().map(track_env).unwrap_or_default())
+                .stdin(Stdio::null())
+                .stderr(Stdio::null()),
+            timeout,
+        );
+        let (success, stdout) = match output {
+            Ok(Some(output)) => output,
+            Ok(None) | Err(_) => return Err(failed()),
+        };
+        if !success {
+            return Ok(Verdict::Vetoed(command));
+        }
+        let hook_changes = String::from_utf8_lossy(&stdout)
+            .lines()
+            .filter_map(|line| line.split_once('='))
+            .map(|(tag, value)| (tag.trim().to_owned(), value.to_owned()))
+            .collect::<Vec<Change>>();
+        if let Some(track) = track.as_deref_mut() {
+            apply_changes(track, &hook_changes);
+        }
+        changes.extend(hook_changes);
+    }
+    Ok(Verdict::Accepted(changes))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn hook(script: &str) -> Hook {
+        Hook {
+            stage: HookStage::PreImport,
+            command: vec!["sh".to_owned(), "-c".to_owned(), script.to_owned()],
+        }
+    }
+
+    #[test]
+    fn runs_hooks_until_one_vetoes() {
+        let path = Path::new("/watch/a.flac");
+        let timeout = Duration::from_secs(10);
+        let accepted = run_hooks(HookStage::PreImport, path, None, &[hook("echo title=Hello")], timeout);
+        assert_eq!(accepted.unwrap(), Verdict::Accepted(vec![("title".to_owned(), "Hello".to_owned())]));
+        let vetoed = run_hooks(HookStage::PreImport, path, None, &[hook("echo title=Hello"), hook("exit 1")], timeout);
+        assert_eq!(vetoed.unwrap(), Verdict::Vetoed("sh -c exit 1".to_owned()));
+    }
+
+    #[test]
+    fn stops_hooks_that_run_past_the_timeout() {
+        let started = Instant::now();
+        let verdict = run_hooks(
+            HookStage::PreImport,
+            Path::new("/watch/a.flac"),
+            None,
+            &[hook("sleep 30")],
+            Duration::from_millis(100),
+        );
+        assert!(matches!(verdict, Err(Error::HookFailed(file_name, _)) if file_name == "/watch/a.flac"));
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+}
@@ -0,0 +1,440 @@
+//! A small HTTP API for frontends on other machines, such as a phone on the same network,
+//! to search the library and stream its tracks.
+//!
+//! The API is served on `NetworkConfig::http_port` once `NetworkConfig::http` is set, and only
+//! reads the library, so read-only tokens allow every request. Tokens are given as
+//! `Authorization: Bearer <token>`. There are two requests:
+//!
+//! * `GET /tracks?q=<query>` answers every track matching the bang query as a JSON array.
+//! * `GET /stream/<uuid>` streams the file of the track with the UUID, seeking with `Range`.
+//!
+//! Like sync, the API does not support TLS, so it refuses to be served on any address but
+//! localhost, and is only reached from other machines through a tunnel.
+
+use crate::auth::{authorize, parse_bearer_token, validate_network_config, Scope};
+use crate::bangs::Bang;
+use crate::config::NetworkConfig;
+use crate::database::{get_track_by_uuid, query_tracks, ConnectionPool};
+use crate::error::{Error, Result};
+use crate::events::Event;
+use katatsuki::Track;
+use serde_json::json;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// How long to wait for a client to send its request, or to read what it is sent.
+const TIMEOUT: Duration = Duration::from_secs(30);
+/// The longest request line or header read, so a client can not send an endless one.
+const MAX_LINE: u64 = 8192;
+/// The most headers read of a request.
+const MAX_HEADERS: usize = 64;
+/// How many connections to the API may be open at once. Connections made past it are closed
+/// right away, so connections that never send a request can not take up threads.
+const MAX_CONNECTIONS: usize = 16;
+
+/// The request line and headers of a request.
+pub(crate) struct Request {
+    pub method: String,
+    pub target: String,
+    headers: Vec<(String, String)>,
+}
+
+impl Request {
+    /// Gets the value of the header of the name, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// The error a request whose request line or headers are longer than they may be is read as.
+fn too_large() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "request header fields too large")
+}
+
+/// Reads a line of a request of at most `MAX_LINE` bytes, failing with `too_large` if it is longer.
+fn read_line(reader: &mut BufReader<TcpStream>, line: &mut String) -> io::Result<usize> {
+    let read = reader.by_ref().take(MAX_LINE).read_line(line)?;
+    if read as u64 == MAX_LINE && !line.ends_with('\n') {
+        return Err(too_large());
+    }
+    Ok(read)
+}
+
+/// Reads the request line and headers of a request, leaving any body unread.
+///
+/// Fails with `io::ErrorKind::InvalidData` if a line is longer than `MAX_LINE`, or there are
+/// more than `MAX_HEADERS` headers.
+pub(crate) fn read_request(stream: &TcpStream) -> io::Result<Request> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    read_line(&mut reader, &mut request_line)?;
+    let mut headers = Vec::new();
+    for read in 0.. {
+        let mut header = String::new();
+        if read_line(&mut reader, &mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if read == MAX_HEADERS {
+            return Err(too_large());
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            headers.push((name.trim().to_owned(), value.trim().to_owned()));
+        }
+    }
+    let mut parts = request_line.split_whitespace();
+    Ok(Request {
+        method: parts.next().unwrap_or_default().to_owned(),
+        target: parts.next().unwrap_or_default().to_owned(),
+        headers,
+    })
+}
+
+/// Writes a response of the status without a body, such as `404 Not Found`.
+pub(crate) fn send_status(stream: &mut TcpStream, status: &str) -> io::Result<()> {
+    stream.write_all(format!("HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status).as_bytes())
+}
+
+/// Gets the MIME type of the file at the path, by its extension.
+pub(crate) fn content_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "flac" => "audio/flac",
+        "mp3" => "audio/mpeg",
+        "m4a" | "m4b" | "mp4" | "aac" => "audio/mp4",
+        "ogg" | "oga" | "opus" => "audio/ogg",
+        "wav" => "audio/wav",
+        "aif" | "aiff" => "audio/aiff",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Parses the first byte of a `Range: bytes=<start>-` header, ignoring ranges of other forms.
+fn parse_range_start(header: &str) -> Option<u64> {
+    let range = header.trim().strip_prefix("bytes=")?;
+    range.split('-').next()?.trim().parse().ok()
+}
+
+/// Answers a `GET` or `HEAD` request for the file at the path, from the start of the range
+/// of the request if it has one.
+pub(crate) fn send_file(stream: &mut TcpStream, request: &Request, path: &Path) -> io::Result<()> {
+    let range_start = request.header("range").and_then(parse_range_start);
+    let mut file = File::open(path)?;
+    let length = file.metadata()?.len();
+    let start = range_start.unwrap_or(0);
+    if range_start.is_some() && start >= length {
+        return stream.write_all(
+            format!(
+                "HTTP/1.1 416 Range Not Satisfiable\r\nContent-Range: bytes */{}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                length
+            )
+            .as_bytes(),
+        );
+    }
+    let status = match range_start {
+        Some(_) => format!(
+            "206 Partial Content\r\nContent-Range: bytes {}-{}/{}",
+            start,
+            length.saturating_sub(1),
+            length
+        ),
+        None => "200 OK".to_owned(),
+    };
+    stream.write_all(
+        format!(
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nAccept-Ranges: bytes\r\nConnection: close\r\n\r\n",
+            status,
+            content_type(path),
+            length - start
+        )
+        .as_bytes(),
+    )?;
+    if request.method == "GET" {
+        file.seek(SeekFrom::Start(start))?;
+        io::copy(&mut file.take(length - start), stream)?;
+    }
+    Ok(())
+}
+
+/// Decodes a component of a query string, in which spaces may be given as `+`.
+fn decode_component(component: &str) -> String {
+    let bytes = component.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' if i + 2 < bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok();
+                match hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                    Some(byte) => {
+                        decoded.push(byte);
+                        i += 2;
+                    }
+                    None => decoded.push(b'%'),
+                }
+            }
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Gets the value of the parameter of the name in the query string of the target, if it has one.
+fn query_parameter(target: &str, name: &str) -> Option<String> {
+    let (_, query) = target.split_once('?')?;
+    query
+        .split('&')
+        .filter_map(|parameter| parameter.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| decode_component(value))
+}
+
+/// The JSON object a track is answered as.
+fn track_json(track: &Track) -> serde_json::Value {
+    json!({
+        "uuid": track.uuid,
+        "title": track.title,
+        "artist": track.artist,
+        "album": track.album,
+        "album_artists": track.album_artists,
+        "genres": track.genres,
+        "year": track.year,
+        "disc_number": track.disc_number,
+        "track_number": track.track_number,
+        "duration": track.duration,
+    })
+}
+
+/// Answers a single request to the API.
+fn handle_request(mut stream: TcpStream, peer: &str, config: &NetworkConfig, pool: &ConnectionPool) -> Result<()> {
+    let failed = || Error::HttpFailed(peer.to_owned());
+    let request = match read_request(&stream) {
+        Ok(request) => request,
+        Err(err) => {
+            if err.kind() == io::ErrorKind::InvalidData {
+                send_status(&mut stream, "431 Request Header Fields Too Large").ok();
+            }
+            return Err(failed());
+        }
+    };
+    let token = request.header("authorization").and_then(parse_bearer_token);
+    if let Err(err) = authorize(config, token, Scope::ReadOnly) {
+        send_status(&mut stream, "401 Unauthorized").map_err(|_| failed())?;
+        return Err(err);
+    }
+    if request.method != "GET" && request.method != "HEAD" {
+        return send_status(&mut stream, "405 Method Not Allowed").map_err(|_| failed());
+    }
+    let conn = pool.get().map_err(|_| failed())?;
+    let path = request.target.split('?').next().unwrap_or_default();
+    if path == "/tracks" {
+        let query = query_parameter(&request.target, "q").unwrap_or_default();
+        let bang = match Bang::new(&query) {
+            Ok(bang) => bang,
+            Err(_) => return send_status(&mut stream, "400 Bad Request").map_err(|_| failed()),
+        };
+        let tracks = query_tracks(bang, &conn, None, None).map_err(|_| failed())?;
+        let body = serde_json::Value::Array(tracks.iter().map(track_json).collect()).to_string();
+        return stream
+            .write_all(
+                format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                )
+                .as_bytes(),
+            )
+            .map_err(|_| failed());
+    }
+    let track = match path.strip_prefix("/stream/") {
+        Some(uuid) => get_track_by_uuid(uuid, &conn).map_err(|_| failed())?,
+        None => None,
+    };
+    // The connection is only needed to find the track, so it is not held while streaming.
+    drop(conn);
+    match track {
+        Some(track) => send_file(&mut stream, &request, &track.file_path).map_err(|_| failed()),
+        None => send_status(&mut stream, "404 Not Found").map_err(|_| failed()),
+    }
+}
+
+/// Serves the API on the configured address and HTTP port, answering every request on a thread
+/// of its own with at most `MAX_CONNECTIONS` open at once, and reporting requests that failed
+/// to `report`. This blocks for as long as the listener works.
+///
+/// Fails if the address is not localhost, see `validate_network_config`.
+pub fn serve<R>(config: &'static NetworkConfig, pool: Arc<ConnectionPool>, report: R) -> Result<()>
+where
+    R: Fn(Event) + Copy + Send + 'static,
+{
+    validate_network_config(config)?;
+    let address = format!("{}:{}", config.bind_address, config.http_port);
+    let listener = TcpListener::bind(&address).map_err(|_| Error::HttpFailed(address.clone()))?;
+    let connections = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming().flatten() {
+        if connections.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+            connections.fetch_sub(1, Ordering::SeqCst);
+            continue;
+        }
+        let pool = Arc::clone(&pool);
+        let connections = Arc::clone(&connections);
+        thread::spawn(move || {
+            let peer = stream.peer_addr().map(|address| address.to_string()).unwrap_or_default();
+            let handled = stream
+                .set_write_timeout(Some(TIMEOUT))
+                .map_err(|_| Error::HttpFailed(peer.clone()))
+                .and_then(|()| handle_request(stream, &peer, config, &pool));
+            connections.fetch_sub(1, Ordering::SeqCst);
+            if handled.is_err() {
+                report(Event::HttpError(peer));
+            }
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{DatabaseConfig, DatabaseStorage};
+    use crate::database::{get_configured_connection_pool, ScratchFile};
+    use std::fs;
+
+    /// Sends the request for the file to a listener on localhost, returning what it answered.
+    fn answer(request: &'static str, path: &Path) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(address).unwrap();
+            stream.write_all(request.as_bytes()).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        });
+        let (mut stream, _) = listener.accept().unwrap();
+        let request = read_request(&stream).unwrap();
+        send_file(&mut stream, &request, path).unwrap();
+        drop(stream);
+        client.join().unwrap()
+    }
+
+    /// Sends the request to a listener on localhost, returning the request it read.
+    fn read_sent(request: String) -> io::Result<Request> {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(address).unwrap();
+            // The listener stops reading a request that is too large, and may close on the rest.
+            stream.write_all(request.as_bytes()).ok();
+        });
+        let (stream, _) = listener.accept().unwrap();
+        let request = read_request(&stream);
+        client.join().unwrap();
+        request
+    }
+
+    #[test]
+    fn refuses_requests_with_too_large_headers() {
+        let read = read_sent("GET /tracks HTTP/1.1\r\nRange: bytes=0-\r\n\r\n".to_owned()).unwrap();
+        assert_eq!(read.header("range"), Some("bytes=0-"));
+
+        let long_line = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(MAX_LINE as usize));
+        let read = read_sent(long_line);
+        assert_eq!(read.err().map(|err| err.kind()), Some(io::ErrorKind::InvalidData));
+
+        let many_headers = format!("GET /tracks HTTP/1.1\r\n{}\r\n", "X-Header: a\r\n".repeat(MAX_HEADERS + 1));
+        let read = read_sent(many_headers);
+        assert_eq!(read.err().map(|err| err.kind()), Some(io::ErrorKind::InvalidData));
+    }
+
+    #[test]
+    fn parses_the_start_of_ranges() {
+        assert_eq!(parse_range_start("bytes=100-"), Some(100));
+        assert_eq!(parse_range_start(" bytes=0-499"), Some(0));
+        assert_eq!(parse_range_start("bytes=-500"), None);
+        assert_eq!(parse_range_start("items=100-"), None);
+    }
+
+    #[test]
+    fn reads_the_parameters_of_queries() {
+        let target = "/tracks?limit=5&q=%21al%7BFirst+Album%7D&sort";
+        assert_eq!(query_parameter(target, "q").as_deref(), Some("!al{First Album}"));
+        assert_eq!(query_parameter(target, "limit").as_deref(), Some("5"));
+        assert_eq!(query_parameter(target, "sort"), None);
+        assert_eq!(query_parameter("/tracks", "q"), None);
+        // Escapes that are not hexadecimal are left as they are.
+        assert_eq!(decode_component("100%zz%2"), "100%zz%2");
+    }
+
+    #[test]
+    fn types_files_by_their_extension() {
+        assert_eq!(content_type(Path::new("/music/a.FLAC")), "audio/flac");
+        assert_eq!(content_type(Path::new("/music/a.m4a")), "audio/mp4");
+        assert_eq!(content_type(Path::new("/music/cover.jpg")), "application/octet-stream");
+    }
+
+    #[test]
+    fn sends_files_from_the_start_of_the_range() {
+        let file = ScratchFile::new("flac");
+        fs::write(&file.0, b"fLaC!").unwrap();
+
+        let whole = answer("GET /stream/a HTTP/1.1\r\n\r\n", &file.0);
+        assert!(whole.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(whole.contains("Content-Type: audio/flac\r\n"));
+        assert!(whole.ends_with("\r\n\r\nfLaC!"));
+
+        let ranged = answer("GET /stream/a HTTP/1.1\r\nRange: bytes=2-\r\n\r\n", &file.0);
+        assert!(ranged.starts_with("HTTP/1.1 206 Partial Content\r\nContent-Range: bytes 2-4/5\r\n"));
+        assert!(ranged.contains("Content-Length: 3\r\n"));
+        assert!(ranged.ends_with("\r\n\r\naC!"));
+
+        let head = answer("HEAD /stream/a HTTP/1.1\r\n\r\n", &file.0);
+        assert!(head.contains("Content-Length: 5\r\n"));
+        assert!(head.ends_with("\r\n\r\n"));
+
+        let past = answer("GET /stream/a HTTP/1.1\r\nRange: bytes=5-\r\n\r\n", &file.0);
+        assert!(past.starts_with("HTTP/1.1 416 Range Not Satisfiable\r\nContent-Range: bytes */5\r\n"));
+    }
+
+    #[test]
+    fn refuses_ranges_of_empty_files() {
+        let file = ScratchFile::new("flac");
+        fs::write(&file.0, b"").unwrap();
+
+        let whole = answer("GET /stream/a HTTP/1.1\r\n\r\n", &file.0);
+        assert!(whole.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(whole.contains("Content-Length: 0\r\n"));
+
+        let ranged = answer("GET /stream/a HTTP/1.1\r\nRange: bytes=0-\r\n\r\n", &file.0);
+        assert!(ranged.starts_with("HTTP/1.1 416 Range Not Satisfiable\r\nContent-Range: bytes */0\r\n"));
+    }
+
+    #[test]
+    fn refuses_to_serve_on_addresses_but_localhost() {
+        let config: &'static NetworkConfig = Box::leak(Box::new(NetworkConfig {
+            bind_address: "0.0.0.0".to_owned(),
+            http: true,
+            ..NetworkConfig::default()
+        }));
+        let pool = get_configured_connection_pool(&DatabaseConfig {
+            storage: DatabaseStorage::Memory,
+            ..DatabaseConfig::default()
+        });
+        let served = serve(config, Arc::new(pool), |_| ());
+        assert!(matches!(served, Err(Error::InsecureNetworkConfig(address)) if address == "0.0.0.0"));
+    }
+}
@@ -0,0 +1,502 @@
+use crate::aliases;
+#[cfg(feature = "archives")]
+use crate::archives;
+use crate::config::{Config, LayoutConfig, PermissionsConfig};
+use crate::database;
+use crate::database::Connection;
+use crate::error::Error;
+use crate::events::Event;
+use crate::failures::{self, Stage};
+use crate::hooks;
+use crate::hooks::{Change, HookStage, Verdict};
+use crate::inference;
+use crate::inference::InferredTag;
+use crate::normalization;
+use crate::paths;
+use crate::paths::FolderCasing;
+use crate::provenance;
+use crate::rejections;
+use katatsuki::Track;
+use std::borrow::Cow;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Runs the configured hooks of the stage, giving each the configured time to run.
+fn run_hooks(stage: HookStage, file_path: &Path, track: Option<&mut Track>, config: &Config) -> Result<Verdict, Error> {
+    hooks::run_hooks(stage, file_path, track, &config.hooks, Duration::from_secs(config.hook_timeout))
+}
+
+fn osstr_to_string(osstr: Option<&OsStr>) -> Cow<'_, str> {
+    osstr
+        .map(|s| s.to_string_lossy())
+        .unwrap_or(Cow::Borrowed(""))
+}
+
+/// The event for a track that could not be moved into the library.
+fn move_error_event(err: Error, track: &Track) -> Event {
+    match err {
+        Error::UnableToMove(_) => Event::TrackMoveError(track.file_path.display().to_string()),
+        Error::UnableToSetPermissions(_) => Event::PermissionsError(track.file_path.display().to_string()),
+        Error::UnableToCreateDirectory(new_directory) => Event::CreateDirectoryError(new_directory),
+        _ => Event::TrackError(track.file_path.display().to_string()),
+    }
+}
+
+/// The event for a file that could not be read as a track, other than unsupported files.
+fn read_error_event(err: Error) -> Event {
+    match err {
+        Error::FileIOError(file_name) => {
+            Event::TrackError(osstr_to_string(file_name.file_name()).into_owned())
+        }
+        Error::MissingRequiredTag(file_name, tag) => Event::MissingTag(
+            osstr_to_string(Path::new(&file_name).file_name()).into_owned(),
+            tag,
+        ),
+        Error::HookFailed(file_name, command) => Event::HookError(
+            osstr_to_string(Path::new(&file_name).file_name()).into_owned(),
+            command,
+        ),
+        _ => Event::TrackError("Unknown Error".to_owned()),
+    }
+}
+
+/// The file the failure to read a track is of, if it is known.
+fn error_path(err: &Error) -> Option<PathBuf> {
+    match err {
+        Error::FileIOError(file_name) => Some(file_name.clone()),
+        Error::MissingRequiredTag(file_name, _) | Error::HookFailed(file_name, _) => Some(PathBuf::from(file_name)),
+        _ => None,
+    }
+}
+
+/// Records the event in the error history of the file at the path if it reports a failure,
+/// and returns it.
+fn record(path: &Path, event: Event, conn: &Connection) -> Event {
+    // A failure that can not be recorded is still reported.
+    failures::record_event(path, Stage::Import, &event, conn).ok();
+    event
+}
+
+/// The event for a file that could not be read as a track, recorded against the file.
+fn record_read_error(err: Error, conn: &Connection) -> Event {
+    match error_path(&err) {
+        Some(path) => record(&path, read_error_event(err), conn),
+        None => read_error_event(err),
+    }
+}
+
+/// Moves a file that could not be imported into the not added folder, remembering it so that
+/// once it is deleted from there, the same file is ignored if it is dropped again.
+pub(crate) fn quarantine(path: &Path, auto_add_path: &Path, conn: &Connection) -> Result<(), Error> {
+    let quarantined_path = paths::move_non_track(path, auto_add_path)?;
+    // A file that can not be remembered is still quarantined, it is only imported again if dropped again.
+    rejections::record_quarantined(&quarantined_path, conn).ok();
+    Ok(())
+}
+
+/// Moves a file whose import was vetoed by the given hook into the not added folder.
+fn veto_import(path: &Path, command: String, auto_add_path: &Path, conn: &Connection) -> Event {
+    let file_name = osstr_to_string(path.file_name()).into_owned();
+    match quarantine(path, auto_add_path, conn) {
+        Ok(()) => Event::ImportVetoed(file_name, command),
+        Err(_) => Event::TrackMoveError(file_name),
+    }
+}
+
+/// Infers the tags missing from the track from the name of its file, then from the folders it is in
+/// below the root, writing them to the file if configured.
+fn infer_tags(track: &mut Track, root: &Path, config: &Config) -> Vec<InferredTag> {
+    let mut inferred = inference::infer_from_file_name(track, &config.inference.file_name_patterns);
+    if config.inference.folder_names {
+        inferred.extend(inference::infer_from_folders(track, root, config.inference.folder_confidence));
+    }
+    if config.inference.write_back {
+        // A file that can not be written still has the inferred tags in the library.
+        inference::write_back(track, &inferred).ok();
+    }
+    inferred
+}
+
+/// Adds a track that was moved into the library to the database, after running its post-move
+/// hooks, rewriting its artist to its canonical name if the config asks for it.
+fn add_track(mut track: Track, original_path: &Path, inferred: &[InferredTag], config: &Config, conn: &Connection) -> Event {
+    // The track is already in the library folder, so it is added even if a hook fails.
+    let file_path = track.file_path.clone();
+    run_hooks(HookStage::PostMove, &file_path, Some(&mut track), config).ok();
+    if config.canonical_artists {
+        // A track whose alias can not be looked up keeps the artist it is tagged with.
+        aliases::canonicalize_track(&mut track, conn).unwrap_or(());
+    }
+    let uuid = database::add_track(&track, conn);
+    inference::flag_inferred(&uuid, inferred, conn).ok();
+    provenance::claim_pending(original_path, &uuid, conn).ok();
+    Event::TrackAdded {
+        artist: track.artist.trim().to_owned(),
+        title: track.title.trim().to_owned(),
+        uuid,
+    }
+}
+
+/// Moves a sidecar file into the album folder of the tracks it was dropped with,
+/// or into the not added folder if there are none.
+fn import_sidecar(
+    path: &Path,
+    library_path: &Path,
+    auto_add_path: &Path,
+    casing: FolderCasing,
+    layouts: &LayoutConfig,
+    permissions: &PermissionsConfig,
+) -> Event {
+    match paths::move_sidecar(path, library_path, casing, layouts, permissions) {
+        Ok(Some(new_path)) => Event::SidecarAdded(new_path.display().to_string()),
+        Ok(None) => match paths::move_non_track(path, auto_add_path) {
+            Ok(_) => Event::NonTrack(osstr_to_string(path.file_name()).into_owned()),
+            Err(_) => Event::TrackMoveError(osstr_to_string(path.file_name()).into_owned()),
+        },
+        Err(Error::UnableToCreateDirectory(new_directory)) => {
+            Event::CreateDirectoryError(new_directory)
+        }
+        Err(Error::UnableToSetPermissions(_)) => Event::PermissionsError(path.display().to_string()),
+        Err(_) => Event::TrackMoveError(osstr_to_string(path.file_name()).into_owned()),
+    }
+}
+
+/// Imports the file at the given path into the library.
+///
+/// The file is moved into its proper place in the library folder, and added to the database.
+/// If the file is not a track, it is moved into the not added folder instead,
+/// unless it is a sidecar file that can be moved alongside the tracks it was found with.
+/// Files whose import is vetoed by a hook are also moved into the not added folder.
+/// Files on the ignore list are left where they are.
+/// If `retry` is set, the import is attempted once more on failure.
+///
+/// Returns the event describing the result of the import, which is recorded in the error history
+/// of the file if it reports a failure.
+pub fn import_track(path: &Path, config: &Config, conn: &Connection, retry: bool) -> Event {
+    record(path, import_dropped_file(path, config, conn, retry), conn)
+}
+
+fn import_dropped_file(path: &Path, config: &Config, conn: &Connection, retry: bool) -> Event {
+    if config.sidecars.is_sidecar(path) {
+        return import_file(path, config, conn, retry);
+    }
+    if rejections::is_ignored(path, conn).unwrap_or(false) {
+        return Event::FileIgnored(path.display().to_string());
+    }
+    match run_hooks(HookStage::PreImport, path, None, config) {
+        Ok(Verdict::Accepted(_)) => import_file(path, config, conn, retry),
+        Ok(Verdict::Vetoed(command)) => match paths::ensure_music_folder(&config.music_folder) {
+            Ok(library_path) => veto_import(path, command, &library_path.1, conn),
+            Err(_) => Event::LibraryNotFound(path.display().to_string()),
+        },
+        Err(err) => read_error_event(err),
+    }
+}
+
+/// Imports the file at the given path, after its pre-import hooks have run.
+fn import_file(path: &Path, config: &Config, conn: &Connection, retry: bool) -> Event {
+    // Missing tags are filled in or rejected once the hooks and normalization rules ran.
+    let track = paths::read_track(path, None);
+    match paths::ensure_music_folder(&config.music_folder) {
+        Ok(library_path) => match track {
+            Ok(mut track) => {
+                let file_path = track.file_path.clone();
+                match run_hooks(HookStage::PostTagRead, &file_path, Some(&mut track), config) {
+                    Ok(Verdict::Accepted(mut changes)) => {
+                        changes.extend(normalization::normalize(&mut track, &config.normalization));
+                        import_read_track(track, &changes, &library_path, config, conn, retry)
+                    }
+                    Ok(Verdict::Vetoed(command)) => veto_import(&file_path, command, &library_path.1, conn),
+                    Err(err) => read_error_event(err),
+                }
+            }
+            Err(_) if retry => import_file(path, config, conn, false),
+            Err(err) => match err {
+                Error::UnsupportedFile(ref file_name) if config.sidecars.is_sidecar(file_name) => {
+                    import_sidecar(
+                        file_name,
+                        &library_path.0,
+                        &library_path.1,
+                        config.folder_casing,
+                        &config.layouts,
+                        &config.permissions,
+                    )
+                }
+                Error::UnsupportedFile(file_name) => {
+                    match quarantine(&file_name, &library_path.1, conn) {
+                        Ok(()) => Event::NonTrack(osstr_to_string(file_name.file_name()).into_owned()),
+                        Err(_) => {
+                            Event::TrackMoveError(osstr_to_string(file_name.file_name()).into_owned())
+                        }
+                    }
+                }
+                err => read_error_event(err),
+            },
+        },
+        Err(_) => Event::LibraryNotFound(path.display().to_string()),
+    }
+}
+
+/// Moves a track whose tags were read and changed by its post-tag-read hooks and normalization
+/// rules into the library, and adds it to the database, once its missing tags are inferred
+/// or filled in.
+fn import_read_track(
+    mut track: Track,
+    changes: &[Change],
+    library_path: &(PathBuf, PathBuf),
+    config: &Config,
+    conn: &Connection,
+    retry: bool,
+) -> Event {
+    let inferred = infer_tags(&mut track, &library_path.1, config);
+    let filled = match paths::fill_required_tags(&mut track, &config.required_tags) {
+        Ok(filled) => filled,
+        Err(err) => return read_error_event(err),
+    };
+    match paths::move_new_track(&track, &library_path.0, &library_path.1, config.folder_casing, &config.layouts, &config.permissions) {
+        Ok(mut moved) => {
+            // The moved track is read again from its file, so the changes are made again.
+            hooks::apply_changes(&mut moved, changes);
+            hooks::apply_changes(&mut moved, &inferred.iter().map(InferredTag::change).collect::<Vec<Change>>());
+            hooks::apply_changes(&mut moved, &filled);
+            add_track(moved, &track.file_path, &inferred, config, conn)
+        }
+        Err(_) if retry => import_file(&track.file_path, config, conn, false),
+        Err(err) => move_error_event(err, &track),
+    }
+}
+
+/// The album metadata shared by every track of an album.
+struct SharedAlbum {
+    album: String,
+    album_artists: Option<Vec<String>>,
+}
+
+/// Picks the most common of the values, preferring the earliest on ties.
+fn most_common<T: PartialEq>(values: Vec<T>) -> Option<T> {
+    let counts = values
+        .iter()
+        .map(|value| values.iter().filter(|other| *other == value).count())
+        .collect::<Vec<usize>>();
+    let max = counts.iter().copied().max()?;
+    let index = counts.iter().position(|count| *count == max)?;
+    values.into_iter().nth(index)
+}
+
+/// Finds the album metadata shared by the tracks, if they are all from the same album.
+///
+/// Tracks without an album title are assumed to be from the same album as the rest.
+fn shared_album(tracks: &[Track]) -> Option<SharedAlbum> {
+    if tracks.len() < 2 {
+        return None;
+    }
+    let albums = tracks
+        .iter()
+        .map(|track| track.album.trim())
+        .filter(|album| !album.is_empty())
+        .collect::<Vec<&str>>();
+    let first = albums.first()?.to_lowercase();
+    if albums.iter().any(|album| album.to_lowercase() != first) {
+        return None;
+    }
+    let album_artists = tracks
+        .iter()
+        .filter(|track| paths::has_album_artists(track))
+        .map(|track| track.album_artists.clone())
+        .collect::<Vec<Vec<String>>>();
+    Some(SharedAlbum {
+        album: most_common(albums)?.to_owned(),
+        album_artists: most_common(album_artists),
+    })
+}
+
+/// Imports the files found together in a single folder.
+///
+/// If the tracks in the folder are all from the same album, they are imported as a unit.
+/// Every track is given the same album title and album artists, so that the album is
+/// not scattered across folders, and nothing is moved unless every track can be imported.
+/// Otherwise, an `Event::AlbumIncomplete` is returned along with the errors, and all of the
+/// files are left where they are. Sidecar files are moved into the folder of the album.
+///
+/// Files that are not from a single album are each imported with `import_track`.
+/// Purchase archives among the files are unpacked back into the watch folder, to be imported from there.
+pub fn import_album(paths: &[PathBuf], config: &Config, conn: &Connection) -> Vec<Event> {
+    #[cfg(feature = "archives")]
+    if paths.iter().any(|path| archives::is_archive(path)) {
+        let (mut events, rest) = archives::import_archives(paths, config, conn);
+        if !rest.is_empty() {
+            events.extend(import_album(&rest, config, conn));
+        }
+        return events;
+    }
+
+    if let [path] = paths {
+        return vec![import_track(path, config, conn, true)];
+    }
+
+    let library_path = match paths::ensure_music_folder(&config.music_folder) {
+        Ok(library_path) => library_path,
+        Err(_) => {
+            return paths
+                .iter()
+                .map(|path| Event::LibraryNotFound(path.display().to_string()))
+                .collect()
+        }
+    };
+
+    let (sidecars, files): (Vec<&PathBuf>, Vec<&PathBuf>) =
+        paths.iter().partition(|path| config.sidecars.is_sidecar(path));
+    let mut tracks = Vec::new();
+    let mut changes = Vec::new();
+    let mut non_tracks = Vec::new();
+    let mut errors = Vec::new();
+    // Vetoed files leave the album, so they are moved out along with any error.
+    let mut vetoed = Vec::new();
+    for file in files {
+        if rejections::is_ignored(file, conn).unwrap_or(false) {
+            vetoed.push(Event::FileIgnored(file.display().to_string()));
+            continue;
+        }
+        match run_hooks(HookStage::PreImport, file, None, config) {
+            Ok(Verdict::Accepted(_)) => (),
+            Ok(Verdict::Vetoed(command)) => {
+                vetoed.push(veto_import(file, command, &library_path.1, conn));
+                continue;
+            }
+            Err(err) => {
+                errors.push(err);
+                continue;
+            }
+        }
+        match paths::read_track(file, None) {
+            Ok(mut track) => {
+                let file_path = track.file_path.clone();
+                match run_hooks(HookStage::PostTagRead, &file_path, Some(&mut track), config) {
+                    Ok(Verdict::Accepted(mut track_changes)) => {
+                        track_changes.extend(normalization::normalize(&mut track, &config.normalization));
+                        tracks.push(track);
+                        changes.push(track_changes);
+                    }
+                    Ok(Verdict::Vetoed(command)) => vetoed.push(veto_import(&file_path, command, &library_path.1, conn)),
+                    Err(err) => errors.push(err),
+                }
+            }
+            Err(Error::UnsupportedFile(file_name)) => non_tracks.push(file_name),
+            Err(err) => errors.push(err),
+        }
+    }
+
+    let shared = match shared_album(&tracks) {
+        Some(shared) => shared,
+        None => {
+            // Sidecars go first, while the tracks they were dropped with are still alongside them.
+            // Hooks already ran on every other file, so they are not run again.
+            let mut events = vetoed;
+            events.extend(sidecars.into_iter().map(|path| import_track(path, config, conn, true)));
+            events.extend(tracks.into_iter().zip(changes).map(|(track, changes)| {
+                let path = track.file_path.clone();
+                record(&path, import_read_track(track, &changes, &library_path, config, conn, true), conn)
+            }));
+            events.extend(non_tracks.iter().map(|path| record(path, import_file(path, config, conn, true), conn)));
+            events.extend(errors.into_iter().map(|err| match err {
+                Error::FileIOError(file_name) => record(&file_name, import_file(&file_name, config, conn, true), conn),
+                err => record_read_error(err, conn),
+            }));
+            return events;
+        }
+    };
+
+    let mut inferred = Vec::new();
+    for track in tracks.iter_mut() {
+        track.album = shared.album.clone();
+        if let Some(album_artists) = &shared.album_artists {
+            track.album_artists = album_artists.clone();
+        }
+        inferred.push(infer_tags(track, &library_path.1, config));
+        if let Err(err) = paths::fill_required_tags(track, &config.required_tags) {
+            errors.push(err);
+        }
+    }
+
+    if !errors.is_empty() {
+        let folder = tracks[0].file_path.parent().unwrap_or_else(|| Path::new(""));
+        let mut events = vetoed;
+        events.extend(errors.into_iter().map(|err| record_read_error(err, conn)));
+        events.push(Event::AlbumIncomplete(folder.display().to_string()));
+        return events;
+    }
+
+    let album_folder = paths::get_track_directory(&tracks[0], &library_path.0, config.folder_casing, &config.layouts);
+    let mut events = vetoed;
+    for (track, inferred) in tracks.into_iter().zip(inferred) {
+        events.push(
+            match paths::move_album_track(
+                &track,
+                &library_path.0,
+                &library_path.1,
+                config.folder_casing,
+                &config.layouts,
+                &config.permissions,
+            ) {
+                Ok(moved) => add_track(moved, &track.file_path, &inferred, config, conn),
+                Err(err) => record(&track.file_path, move_error_event(err, &track), conn),
+            },
+        );
+    }
+    for sidecar in sidecars {
+        let moved = paths::move_into_folder(sidecar, &album_folder).and_then(|new_path| {
+            paths::apply_permissions_or_undo(sidecar, &new_path, &library_path.0, &config.permissions)?;
+            Ok(new_path)
+        });
+        events.push(match moved {
+            Ok(new_path) => Event::SidecarAdded(new_path.display().to_string()),
+            Err(Error::UnableToCreateDirectory(new_directory)) => {
+                Event::CreateDirectoryError(new_directory)
+            }
+            Err(Error::UnableToSetPermissions(_)) => Event::PermissionsError(sidecar.display().to_string()),
+            Err(_) => Event::TrackMoveError(osstr_to_string(sidecar.file_name()).into_owned()),
+        });
+    }
+    for non_track in non_tracks {
+        events.push(record(&non_track, import_file(&non_track, config, conn, true), conn));
+    }
+    events
+}
+
+/// Adds the file at the given path to the library where it is, for a music folder whose layout
+/// is adopted rather than managed, replacing the track if it is already in the library.
+///
+/// Nothing is ever moved, so hooks are not run and files that are not tracks are left alone,
+/// returning `None`. The tags of the track are still normalized.
+pub fn index_track(path: &Path, config: &Config, conn: &Connection) -> Option<Event> {
+    let file_name = || osstr_to_string(path.file_name()).into_owned();
+    let mut track = match Track::from_path(path, None) {
+        Ok(track) => track,
+        Err(err) if err.kind() == std::io::ErrorKind::InvalidData => return None,
+        Err(_) => return Some(Event::TrackError(file_name())),
+    };
+    normalization::normalize(&mut track, &config.normalization);
+    let inferred = infer_tags(&mut track, Path::new(&config.music_folder), config);
+    if let Err(err) = paths::fill_required_tags(&mut track, &config.required_tags) {
+        return Some(read_error_event(err));
+    }
+    if config.canonical_artists {
+        aliases::canonicalize_track(&mut track, conn).unwrap_or(());
+    }
+    let uuid = database::add_track(&track, conn);
+    inference::flag_inferred(&uuid, &inferred, conn).ok();
+    Some(Event::TrackAdded {
+        artist: track.artist.trim().to_owned(),
+        title: track.title.trim().to_owned(),
+        uuid,
+    })
+}
+
+/// Adds every track among the files to the library where it is, with `index_track`.
+pub fn index_album(paths: &[PathBuf], config: &Config, conn: &Connection) -> Vec<Event> {
+    paths
+        .iter()
+        .filter_map(|path| index_track(path, config, conn).map(|event| record(path, event, conn)))
+        .collect()
+}
@@ -0,0 +1,314 @@
+//! Inference of the tags missing from imported tracks, from the names of their files and of the
+//! folders they were dropped into the watch folder in.
+//!
+//! File names are matched against the patterns configured in `InferenceConfig`, in order,
+//! without their extension. Patterns name the tags they are made of in braces:
+//!
+//! ```toml
+//! [inference]
+//! file_name_patterns = ["{track_number} - {artist} - {title}", "{track_number} - {title}", "{artist} - {title}"]
+//! ```
+//!
+//! The tags are `title`, `artist`, `album`, `album_artists`, `year` and `track_number`. Only the
+//! tags missing from the track are taken from the first pattern that matches, so embedded tags
+//! always win.
+//!
+//! Tags still missing are then inferred from the folders the track was dropped in, laid out as
+//! `Artist/Album (Year)/track.flac`. The folder of the track names the album, along with its year
+//! in parentheses or brackets, and its artist if named as `Artist - Album`. The folder above it
+//! names the artist and album artist. Folder names are less certain than file names, so each tag
+//! inferred from them has a `Confidence`, and is only used if it is as confident as configured.
+//!
+//! Inferred tags are kept track of in the library along with their confidence, so they can be told
+//! apart from embedded tags, and are written back to the file if configured.
+
+use crate::database::create_table_with_foreign_keys;
+use crate::hooks::{apply_changes, Change};
+use crate::paths::has_album_artists;
+use katatsuki::{TagUpdate, Track};
+use regex::Regex;
+use rusqlite::{Connection, Result, Transaction, TransactionBehavior};
+use serde_derive::{Deserialize, Serialize};
+use std::io;
+use std::path::Path;
+use std::sync::OnceLock;
+
+/// The source of tags inferred from the name of the file of a track.
+pub const FILE_NAME_SOURCE: &str = "filename";
+
+/// The source of tags inferred from the names of the folders a track was dropped in.
+pub const FOLDER_SOURCE: &str = "folder";
+
+/// How certain an inferred tag is to be right.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Confidence {
+    /// Taken from a folder name that may not be named for the tag at all, such as a source folder.
+    Low,
+    /// Taken from a folder name in the place the tag is expected, such as the artist folder.
+    Medium,
+    /// Taken from a part of a name that is clearly the tag, such as the year in parentheses.
+    High,
+}
+
+impl Confidence {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Confidence::Low => "low",
+            Confidence::Medium => "medium",
+            Confidence::High => "high",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Confidence> {
+        match name {
+            "low" => Some(Confidence::Low),
+            "medium" => Some(Confidence::Medium),
+            "high" => Some(Confidence::High),
+            _ => None,
+        }
+    }
+}
+
+/// The tags patterns can be made of, and whether each is a number.
+const PATTERN_TAGS: [(&str, bool); 6] = [
+    ("title", false),
+    ("artist", false),
+    ("album", false),
+    ("album_artists", false),
+    ("year", true),
+    ("track_number", true),
+];
+
+/// A tag inferred for a track, rather than embedded in its file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InferredTag {
+    /// The name of the tag, as hooks change it, such as `title`.
+    pub tag: String,
+    pub value: String,
+    /// Where the tag was inferred from, such as `filename`.
+    pub source: String,
+    pub confidence: Confidence,
+}
+
+impl InferredTag {
+    /// The change that gives the track the inferred tag.
+    pub fn change(&self) -> Change {
+        (self.tag.clone(), self.value.clone())
+    }
+}
+
+pub fn create_inference_table(conn: &Connection) {
+    create_table_with_foreign_keys(
+        "inferred_tags",
+        "TrackId TEXT NOT NULL REFERENCES tracks(TrackId) ON DELETE CASCADE,
+        Tag TEXT NOT NULL,
+        Source TEXT NOT NULL,
+        PRIMARY KEY (TrackId, Tag)",
+        conn,
+    )
+    .unwrap();
+    // Tags flagged before they had a confidence were only ever inferred from file names.
+    let transaction = Transaction::new_unchecked(conn, TransactionBehavior::Immediate).unwrap();
+    let has_confidence = transaction
+        .prepare("SELECT 1 FROM pragma_table_info('inferred_tags') WHERE name = 'Confidence'")
+        .and_then(|mut statement| statement.exists(rusqlite::NO_PARAMS))
+        .unwrap();
+    if !has_confidence {
+        transaction
+            .execute_batch("ALTER TABLE inferred_tags ADD COLUMN Confidence TEXT NOT NULL DEFAULT 'high'")
+            .unwrap();
+    }
+    transaction.commit().unwrap();
+}
+
+/// Compiles the file name pattern into a regular expression, or `None` if it names an unknown tag.
+fn pattern_regex(pattern: &str) -> Option<Regex> {
+    let mut regex = String::from("^");
+    let mut rest = pattern;
+    while let Some(start) = rest.find('{') {
+        let end = start + rest[start..].find('}')?;
+        let name = &rest[start + 1..end];
+        let (_, numeric) = PATTERN_TAGS.iter().find(|(tag, _)| *tag == name)?;
+        regex.push_str(&regex::escape(&rest[..start]));
+        if *numeric {
+            regex.push_str(&format!(r"(?P<{}>\d+)", name));
+        } else {
+            regex.push_str(&format!(r"(?P<{}>.+?)", name));
+        }
+        rest = &rest[end + 1..];
+    }
+    regex.push_str(&regex::escape(rest));
+    regex.push('$');
+    Regex::new(&regex).ok()
+}
+
+/// Whether the tag of the track is missing.
+fn is_missing(track: &Track, tag: &str) -> bool {
+    match tag {
+        "title" => track.title.trim().is_empty(),
+        "artist" => track.artist.trim().is_empty(),
+        "album" => track.album.trim().is_empty(),
+        "album_artists" => !has_album_artists(track),
+        "year" => track.year <= 0,
+        "track_number" => track.track_number <= 0,
+        _ => false,
+    }
+}
+
+/// Infers the tags missing from the track from the name of its file, by the first of the patterns
+/// it matches, giving them to the track. Returns the tags that were inferred.
+pub fn infer_from_file_name(track: &mut Track, patterns: &[String]) -> Vec<InferredTag> {
+    let stem = match track.file_path.file_stem() {
+        Some(stem) => stem.to_string_lossy().into_owned(),
+        None => return Vec::new(),
+    };
+    let captures = patterns
+        .iter()
+        .filter_map(|pattern| pattern_regex(pattern))
+        .find_map(|regex| regex.captures(stem.trim()).map(|captures| (regex.clone(), captures)));
+    let (regex, captures) = match captures {
+        Some(found) => found,
+        None => return Vec::new(),
+    };
+    let inferred = regex
+        .capture_names()
+        .flatten()
+        .filter(|tag| is_missing(track, tag))
+        .filter_map(|tag| {
+            let value = captures.name(tag)?.as_str().trim();
+            let numeric = PATTERN_TAGS.iter().any(|(name, numeric)| *name == tag && *numeric);
+            if value.is_empty() || (numeric && value.parse::<i32>().map_or(true, |number| number <= 0)) {
+                return None;
+            }
+            Some(InferredTag {
+                tag: tag.to_owned(),
+                value: value.to_owned(),
+                source: FILE_NAME_SOURCE.to_owned(),
+                confidence: Confidence::High,
+            })
+        })
+        .collect::<Vec<InferredTag>>();
+    apply_changes(track, &inferred.iter().map(InferredTag::change).collect::<Vec<Change>>());
+    inferred
+}
+
+fn album_folder_pattern() -> &'static Regex {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    PATTERN.get_or_init(|| {
+        Regex::new(
+            r"^(?:(?P<artist>.+?) - )?(?:[(\[](?P<leading_year>\d{4})[)\]]\s*(?:- )?)?(?P<album>.+?)(?:\s*[(\[](?P<year>\d{4})[)\]])?$",
+        )
+        .unwrap()
+    })
+}
+
+/// Infers the tags missing from the track from the names of the folders it is in below the root,
+/// giving it those at least as confident as the given confidence. Returns the tags that were inferred.
+pub fn infer_from_folders(track: &mut Track, root: &Path, min_confidence: Confidence) -> Vec<InferredTag> {
+    let folders = match track.file_path.parent().and_then(|parent| parent.strip_prefix(root).ok()) {
+        Some(folders) => folders
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy().trim().to_owned())
+            .collect::<Vec<String>>(),
+        None => return Vec::new(),
+    };
+    let (album_folder, artist_folder) = match folders.as_slice() {
+        [] => return Vec::new(),
+        [album_folder] => (album_folder, None),
+        [.., artist_folder, album_folder] => (album_folder, Some(artist_folder)),
+    };
+    let captures = match album_folder_pattern().captures(album_folder) {
+        Some(captures) => captures,
+        None => return Vec::new(),
+    };
+    let capture = |name: &str| captures.name(name).map(|value| value.as_str().trim()).filter(|value| !value.is_empty());
+    let year = capture("year").or_else(|| capture("leading_year"));
+    let named_artist = capture("artist");
+    // A folder that only has a name could be named for anything, unless it is in an artist folder.
+    let album_confidence = match (year.is_some() || named_artist.is_some(), artist_folder.is_some()) {
+        (true, _) => Confidence::High,
+        (false, true) => Confidence::Medium,
+        (false, false) => Confidence::Low,
+    };
+    // The folder above an album folder is only clearly an artist folder if the album folder is
+    // clearly an album folder.
+    let artist_confidence = if year.is_some() { Confidence::Medium } else { Confidence::Low };
+
+    let mut candidates = Vec::new();
+    if let Some(album) = capture("album") {
+        candidates.push(("album", album.to_owned(), album_confidence));
+    }
+    if let Some(year) = year {
+        candidates.push(("year", year.to_owned(), Confidence::High));
+    }
+    match (named_artist, artist_folder) {
+        (Some(artist), _) => {
+            candidates.push(("artist", artist.to_owned(), Confidence::High));
+            candidates.push(("album_artists", artist.to_owned(), Confidence::High));
+        }
+        (None, Some(artist)) if !artist.is_empty() => {
+            candidates.push(("artist", artist.clone(), artist_confidence));
+            candidates.push(("album_artists", artist.clone(), artist_confidence));
+        }
+        _ => (),
+    }
+    let inferred = candidates
+        .into_iter()
+        .filter(|(tag, _, confidence)| *confidence >= min_confidence && is_missing(track, tag))
+        .map(|(tag, value, confidence)| InferredTag {
+            tag: tag.to_owned(),
+            value,
+            source: FOLDER_SOURCE.to_owned(),
+            confidence,
+        })
+        .collect::<Vec<InferredTag>>();
+    apply_changes(track, &inferred.iter().map(InferredTag::change).collect::<Vec<Change>>());
+    inferred
+}
+
+/// Writes the inferred tags of the track to its file, leaving its other tags as they are.
+pub fn write_back(track: &Track, inferred: &[InferredTag]) -> io::Result<()> {
+    let mut update = TagUpdate::default();
+    for inferred in inferred {
+        let value = inferred.value.clone();
+        match inferred.tag.as_str() {
+            "title" => update.title = Some(value),
+            "artist" => update.artist = Some(value),
+            "album" => update.album = Some(value),
+            "album_artists" => update.album_artists = Some(value.split(';').map(|artist| artist.to_owned()).collect()),
+            "year" => update.year = value.parse().ok(),
+            "track_number" => update.track_number = value.parse().ok(),
+            _ => (),
+        }
+    }
+    if update.is_empty() {
+        return Ok(());
+    }
+    track.write_tags(&update)
+}
+
+/// Flags the tags of the track with the given UUID as inferred, replacing the source and
+/// confidence of any tag already flagged.
+pub fn flag_inferred(uuid: &str, inferred: &[InferredTag], conn: &Connection) -> Result<()> {
+    let mut statement = conn.prepare(
+        "INSERT INTO inferred_tags(TrackId, Tag, Source, Confidence) SELECT TrackId, ?2, ?3, ?4 FROM tracks WHERE TrackId = ?1
+        ON CONFLICT(TrackId, Tag) DO UPDATE SET Source = excluded.Source, Confidence = excluded.Confidence",
+    )?;
+    for inferred in inferred {
+        statement.execute(&[uuid, &inferred.tag, &inferred.source, inferred.confidence.name()])?;
+    }
+    Ok(())
+}
+
+/// Gets the tags of the track with the given UUID that were inferred, along with where each was
+/// inferred from and how confidently, by the name of the tag.
+pub fn get_inferred_tags(uuid: &str, conn: &Connection) -> Result<Vec<(String, String, Confidence)>> {
+    let mut statement =
+        conn.prepare("SELECT Tag, Source, Confidence FROM inferred_tags WHERE TrackId = ?1 ORDER BY Tag")?;
+    let rows = statement.query_map(&[uuid], |row| {
+        let confidence = row.get::<_, String>(2)?;
+        Ok((row.get(0)?, row.get(1)?, Confidence::from_name(&confidence).unwrap_or(Confidence::High)))
+    })?;
+    rows.collect()
+}
@@ -0,0 +1,280 @@
+//! The folders tracks are organized into within the music folder, from the layout configured
+//! for the media type of the track.
+//!
+//! A layout is a template of folders separated by `/`, such as `{album_artist}/{album}`, where
+//! each variable is replaced with a tag of the track:
+//!
+//! | Variable         | Value                                                          |
+//! | ---------------- | -------------------------------------------------------------- |
+//! | `{album_artist}` | The album artists, or the authors of an audiobook              |
+//! | `{artist}`       | The artist                                                     |
+//! | `{album}`        | The album                                                      |
+//! | `{year}`         | The year                                                       |
+//! | `{series}`       | The series of an audiobook, such as `Discworld`                |
+//! | `{part}`         | The part of the series an audiobook is, such as `3`            |
+//! | `{book}`         | The title of an audiobook, without its series and part         |
+//! | `{narrator}`     | The narrator of an audiobook                                   |
+//!
+//! Audiobooks are rarely tagged with their series, so the series and part are read from the
+//! album as audiobooks are commonly titled, such as `Discworld, Book 3: Equal Rites`, or
+//! `Equal Rites (Discworld #3)`. The narrator is read from a comment such as `Narrated by Nigel
+//! Planer`, or is the artist when it is not one of the authors. Variables that are not known
+//! are empty, and folders left empty by them are left out, with any dashes around them.
+
+use crate::config::LayoutConfig;
+use katatsuki::Track;
+use regex::Regex;
+use std::path::Path;
+use std::sync::OnceLock;
+
+/// Genres audiobooks are tagged with, in lowercase.
+const AUDIOBOOK_GENRES: &[&str] = &["audiobook", "audiobooks", "audio book", "spoken word", "hörbuch"];
+
+/// The kind of media a track is, which decides the layout it is organized with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MediaType {
+    Music,
+    Audiobook,
+}
+
+/// Gets the kind of media the track is. Tracks are audiobooks if they are tagged with an
+/// audiobook genre, or are M4B files.
+pub fn media_type(track: &Track) -> MediaType {
+    let is_m4b = Path::new(&track.file_path)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.eq_ignore_ascii_case("m4b"))
+        .unwrap_or(false);
+    let has_audiobook_genre = track
+        .genres
+        .iter()
+        .any(|genre| AUDIOBOOK_GENRES.contains(&genre.trim().to_lowercase().as_str()));
+    if is_m4b || has_audiobook_genre {
+        MediaType::Audiobook
+    } else {
+        MediaType::Music
+    }
+}
+
+/// The series, part and title an audiobook is, as read from its album.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct SeriesPart {
+    pub series: String,
+    pub part: String,
+    pub book: String,
+}
+
+fn series_patterns() -> &'static [Regex] {
+    static PATTERNS: OnceLock<Vec<Regex>> = OnceLock::new();
+    PATTERNS.get_or_init(|| {
+        [
+            // Equal Rites (Discworld #3), or Equal Rites [Discworld, Book 3]
+            r"^(?P<book>.+?)\s*[(\[](?P<series>.+?),?\s*(?:#|(?i:book|vol\.?|volume|part)\s*)(?P<part>\d+(?:\.\d+)?)[)\]]$",
+            // Discworld, Book 3: Equal Rites, or Discworld Book 3 - Equal Rites, or Discworld, Vol. 3
+            r"^(?P<series>.+?),?\s+(?i:book|vol\.?|volume|part)\s*(?P<part>\d+(?:\.\d+)?)(?:\s*[:\-–]\s*(?P<book>.+))?$",
+        ]
+        .iter()
+        .map(|pattern| Regex::new(pattern).unwrap())
+        .collect()
+    })
+}
+
+fn narrator_pattern() -> &'static Regex {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    PATTERN.get_or_init(|| Regex::new(r"(?i)\b(?:narrated|read) by:?\s+(?P<narrator>[^.;\r\n]+)").unwrap())
+}
+
+/// Reads the series and part of the audiobook from its album. The book is the whole album if
+/// it names no series.
+pub fn series_part(album: &str) -> SeriesPart {
+    let album = album.trim();
+    for pattern in series_patterns() {
+        if let Some(captures) = pattern.captures(album) {
+            let capture = |name: &str| captures.name(name).map_or("", |capture| capture.as_str().trim());
+            return SeriesPart {
+                series: capture("series").to_owned(),
+                part: capture("part").to_owned(),
+                book: match capture("book") {
+                    "" => album.to_owned(),
+                    book => book.to_owned(),
+                },
+            };
+        }
+    }
+    SeriesPart {
+        book: album.to_owned(),
+        ..SeriesPart::default()
+    }
+}
+
+/// Reads the narrator of the audiobook, from its comment or otherwise its artist.
+pub fn narrator(track: &Track) -> String {
+    if let Some(captures) = track.comment.as_deref().and_then(|comment| narrator_pattern().captures(comment)) {
+        return captures["narrator"].trim().to_owned();
+    }
+    let artist = track.artist.trim();
+    if track.album_artists.iter().any(|author| author.trim() == artist) || track.album_artists.join(", ") == artist {
+        String::new()
+    } else {
+        artist.to_owned()
+    }
+}
+
+/// Gets the value of the variable for the track, or `None` if there is no such variable.
+fn variable(name: &str, track: &Track, media_type: MediaType) -> Option<String> {
+    let audiobook = media_type == MediaType::Audiobook;
+    let value = match name {
+        "album_artist" => track.album_artists.join(", "),
+        "artist" => track.artist.clone(),
+        "album" => track.album.clone(),
+        "year" => track.year().map(|year| year.to_string()).unwrap_or_default(),
+        "series" if audiobook => series_part(&track.album).series,
+        "part" if audiobook => series_part(&track.album).part,
+        "book" if audiobook => series_part(&track.album).book,
+        "narrator" if audiobook => narrator(track),
+        "series" | "part" | "narrator" => String::new(),
+        "book" => track.album.clone(),
+        _ => return None,
+    };
+    Some(value.trim().to_owned())
+}
+
+/// Replaces every variable of the folder template with its value for the track. Braces that
+/// are not around a variable are kept as they are.
+fn render_folder(template: &str, track: &Track, media_type: MediaType) -> String {
+    let mut folder = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        folder.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        match after.find('}').and_then(|end| variable(&after[..end], track, media_type).map(|value| (end, value))) {
+            Some((end, value)) => {
+                folder.push_str(&value);
+                rest = &after[end + 1..];
+            }
+            None => {
+                folder.push('{');
+                rest = after;
+            }
+        }
+    }
+    folder.push_str(rest);
+    folder
+}
+
+/// Gets the names of the variables of the layout for the media type of the track that are empty
+/// for it, such as `year` for a track without a year, in the order the layout uses them.
+pub fn empty_variables(track: &Track, layouts: &LayoutConfig) -> Vec<String> {
+    let media_type = media_type(track);
+    let layout = layouts.layout(media_type);
+    let mut empty = Vec::new();
+    for (start, _) in layout.match_indices('{') {
+        let name = match layout[start + 1..].split_once('}') {
+            Some((name, _)) => name,
+            None => break,
+        };
+        if variable(name, track, media_type).is_some_and(|value| value.is_empty()) && !empty.iter().any(|n| n == name) {
+            empty.push(name.to_owned());
+        }
+    }
+    empty
+}
+
+/// Gets the folders the track is organized into, from the layout for its media type.
+/// Folders are not sanitized, and folders left empty are left out.
+pub fn track_folders(track: &Track, layouts: &LayoutConfig) -> Vec<String> {
+    let media_type = media_type(track);
+    layouts
+        .layout(media_type)
+        .split('/')
+        .map(|template| render_folder(template, track, media_type))
+        .map(|folder| folder.trim_matches(|c: char| c.is_whitespace() || c == '-' || c == '–').to_owned())
+        .filter(|folder| !folder.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use katatsuki::TrackFileType;
+
+    fn audiobook(album: &str) -> Track {
+        Track::builder("/watch/book.m4b", TrackFileType::AAC)
+            .title("Chapter 1".to_owned())
+            .artist("Nigel Planer".to_owned())
+            .album_artists(vec!["Terry Pratchett".to_owned()])
+            .album(album.to_owned())
+            .build()
+    }
+
+    #[test]
+    fn tells_audiobooks_by_extension_or_genre() {
+        let song = Track::builder("/watch/a.flac", TrackFileType::FLAC16).build();
+        assert_eq!(media_type(&song), MediaType::Music);
+        assert_eq!(media_type(&audiobook("Mort")), MediaType::Audiobook);
+        let spoken = Track::builder("/watch/a.mp3", TrackFileType::MP3CBR)
+            .genres(vec![" Spoken Word ".to_owned()])
+            .build();
+        assert_eq!(media_type(&spoken), MediaType::Audiobook);
+    }
+
+    #[test]
+    fn reads_series_and_part_from_the_album() {
+        let part = |series: &str, part: &str, book: &str| SeriesPart {
+            series: series.to_owned(),
+            part: part.to_owned(),
+            book: book.to_owned(),
+        };
+        assert_eq!(series_part("Equal Rites (Discworld #3)"), part("Discworld", "3", "Equal Rites"));
+        assert_eq!(series_part("Equal Rites [Discworld, Book 3]"), part("Discworld", "3", "Equal Rites"));
+        assert_eq!(series_part("Discworld, Book 3: Equal Rites"), part("Discworld", "3", "Equal Rites"));
+        assert_eq!(series_part("Discworld Vol. 2.5 - Mort"), part("Discworld", "2.5", "Mort"));
+        assert_eq!(series_part("Discworld, Volume 4"), part("Discworld", "4", "Discworld, Volume 4"));
+        assert_eq!(series_part("Mort"), part("", "", "Mort"));
+    }
+
+    #[test]
+    fn reads_the_narrator_from_the_comment_or_artist() {
+        let commented = Track {
+            comment: Some("Unabridged. Read by: Stephen Briggs; 2005".to_owned()),
+            ..audiobook("Mort")
+        };
+        assert_eq!(narrator(&commented), "Stephen Briggs");
+        assert_eq!(narrator(&audiobook("Mort")), "Nigel Planer");
+        let by_author = Track {
+            artist: "Terry Pratchett".to_owned(),
+            ..audiobook("Mort")
+        };
+        assert_eq!(narrator(&by_author), "");
+    }
+
+    #[test]
+    fn renders_the_folders_of_the_layout_for_the_media_type() {
+        let layouts = LayoutConfig::default();
+        let song = Track::builder("/watch/a.flac", TrackFileType::FLAC16)
+            .album_artists(vec!["Alpha".to_owned(), "Beta".to_owned()])
+            .album("First".to_owned())
+            .build();
+        assert_eq!(track_folders(&song, &layouts), ["Alpha, Beta", "First"]);
+        assert_eq!(
+            track_folders(&audiobook("Equal Rites (Discworld #3)"), &layouts),
+            ["Terry Pratchett", "Discworld", "3 - Equal Rites"]
+        );
+        // Folders left empty are left out, along with the dashes around empty variables.
+        assert_eq!(track_folders(&audiobook("Mort"), &layouts), ["Terry Pratchett", "Mort"]);
+    }
+
+    #[test]
+    fn keeps_braces_that_are_not_around_a_variable() {
+        let layouts = LayoutConfig {
+            music: "{album_artist}/{unknown} {year}/{narrator}".to_owned(),
+            ..LayoutConfig::default()
+        };
+        let song = Track::builder("/watch/a.flac", TrackFileType::FLAC16)
+            .album_artists(vec!["Alpha".to_owned()])
+            .year(1999)
+            .build();
+        assert_eq!(track_folders(&song, &layouts), ["Alpha", "{unknown} 1999"]);
+        assert_eq!(empty_variables(&song, &layouts), ["narrator"]);
+    }
+}
@@ -0,0 +1,230 @@
+//! An advisory lease on writing to the library.
+//!
+//! The watcher is not the only process with write access to the database, and
+//! writes to the tracks table interleaved with writes to the tables derived from it,
+//! such as playlists, can leave the two out of step. Every writer holds the lease
+//! while it writes, by running its writes through `with_lease`.
+//!
+//! The lease is kept in the database itself, so it is shared by every process that
+//! opens the library. A lease that is not released lapses after `LEASE_DURATION`,
+//! so a writer that dies while holding it only blocks other writers for a while.
+
+use crate::events::Event;
+use rusqlite::types::ToSql;
+use rusqlite::{Connection, Error, ErrorCode, OptionalExtension, Result, Transaction, TransactionBehavior, NO_PARAMS};
+use std::thread;
+use std::time::Duration;
+
+/// How long a lease lasts before it lapses, if it is not released.
+pub const LEASE_DURATION: Duration = Duration::from_secs(30);
+
+/// How long `with_lease` waits between attempts to take a lease held by another writer.
+const RETRY_INTERVAL: Duration = Duration::from_millis(250);
+
+/// The result of an attempt to take the lease.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Lease {
+    /// The lease was taken from its previous holder, if there was one.
+    /// `lapsed` is true if the previous holder never released it.
+    Acquired {
+        previous: Option<String>,
+        lapsed: bool,
+    },
+    /// The lease was already held by, or last released by, the same holder.
+    Renewed,
+    /// The lease is held by the given writer.
+    Held(String),
+}
+
+impl Lease {
+    /// The events reporting that the lease changed hands to the given holder, if it did.
+    pub fn events(&self, holder: &str) -> Vec<Event> {
+        match self {
+            Lease::Acquired { previous, lapsed } => {
+                let previous = previous.clone().unwrap_or_default();
+                let mut events = Vec::new();
+                if *lapsed {
+                    events.push(Event::LeaseLapsed(previous.clone()));
+                }
+                events.push(Event::LeaseChanged {
+                    holder: holder.to_owned(),
+                    previous,
+                });
+                events
+            }
+            Lease::Renewed | Lease::Held(_) => Vec::new(),
+        }
+    }
+}
+
+pub fn create_lease_table(conn: &Connection) {
+    // The table holds at most one row. A released lease is kept with an expiry of 0,
+    // to remember who held it last.
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS write_lease (
+        Id INTEGER PRIMARY KEY CHECK (Id = 0),
+        Holder TEXT NOT NULL,
+        Expires INTEGER NOT NULL
+    );",
+    )
+    .unwrap();
+}
+
+/// Tries to take the lease for the given holder, without waiting.
+///
+/// Holders are identified by name, so every writer must use a name no other writer uses.
+/// Taking a lease the holder already holds extends it by `LEASE_DURATION`.
+pub fn acquire_lease(holder: &str, conn: &Connection) -> Result<Lease> {
+    // An immediate transaction keeps another writer from taking the lease
+    // between reading and writing it.
+    let transaction = Transaction::new_unchecked(conn, TransactionBehavior::Immediate)?;
+    let current = transaction
+        .query_row(
+            "SELECT Holder, Expires, Expires > CAST(strftime('%s', 'now') AS INTEGER)
+            FROM write_lease WHERE Id = 0",
+            NO_PARAMS,
+            |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?, row.get::<_, bool>(2)?)),
+        )
+        .optional()?;
+    let lease = match current {
+        Some((current, _, _)) if current == holder => Lease::Renewed,
+        Some((current, _, true)) => return Ok(Lease::Held(current)),
+        Some((current, expires, false)) => Lease::Acquired {
+            previous: Some(current),
+            lapsed: expires != 0,
+        },
+        None => Lease::Acquired {
+            previous: None,
+            lapsed: false,
+        },
+    };
+    transaction.execute(
+        "INSERT OR REPLACE INTO write_lease(Id, Holder, Expires)
+        VALUES (0, ?1, CAST(strftime('%s', 'now') AS INTEGER) + ?2)",
+        &[&holder as &dyn ToSql, &(LEASE_DURATION.as_secs() as i64)],
+    )?;
+    transaction.commit()?;
+    Ok(lease)
+}
+
+/// Extends the lease held by the given holder by another `LEASE_DURATION`.
+/// Returns false if the lease is no longer held by the holder, since it lapsed and was taken
+/// by another writer, in which case the writes made since it was taken should be abandoned.
+pub fn renew_lease(holder: &str, conn: &Connection) -> Result<bool> {
+    let renewed = conn.execute(
+        "UPDATE write_lease SET Expires = CAST(strftime('%s', 'now') AS INTEGER) + ?2
+        WHERE Id = 0 AND Holder = ?1 AND Expires != 0",
+        &[&holder as &dyn ToSql, &(LEASE_DURATION.as_secs() as i64)],
+    )?;
+    Ok(renewed > 0)
+}
+
+/// Whether the lease is still held by the given holder, or lapsed without being taken by another
+/// writer, so that `renew_lease` would renew it.
+pub fn holds_lease(holder: &str, conn: &Connection) -> Result<bool> {
+    conn.prepare("SELECT 1 FROM write_lease WHERE Id = 0 AND Holder = ?1 AND Expires != 0")?
+        .exists(&[holder])
+}
+
+/// Releases the lease, if it is held by the given holder.
+/// Returns false if the lease was not held by the holder.
+pub fn release_lease(holder: &str, conn: &Connection) -> Result<bool> {
+    let released = conn.execute(
+        "UPDATE write_lease SET Expires = 0 WHERE Id = 0 AND Holder = ?1 AND Expires != 0",
+        &[holder],
+    )?;
+    Ok(released > 0)
+}
+
+/// Gets the writer currently holding the lease, if it is held.
+pub fn get_lease_holder(conn: &Connection) -> Result<Option<String>> {
+    conn.query_row(
+        "SELECT Holder FROM write_lease
+        WHERE Id = 0 AND Expires > CAST(strftime('%s', 'now') AS INTEGER)",
+        NO_PARAMS,
+        |row| row.get(0),
+    )
+    .optional()
+}
+
+/// Runs the writes while holding the lease, waiting for any other writer to release it first,
+/// or to finish the transaction it is writing in.
+/// The lease changing hands is reported to `report`, as is the lease being lost while writing.
+///
+/// The writes should finish well within `LEASE_DURATION`, after which another writer may
+/// take the lease regardless. Writes that may take longer renew the lease between batches
+/// with `renew_lease`, and abandon the batch once it is lost.
+pub fn with_lease<T, F, R>(holder: &str, conn: &Connection, report: R, write: F) -> Result<T>
+where
+    F: FnOnce() -> T,
+    R: Fn(Event),
+{
+    loop {
+        match acquire_lease(holder, conn) {
+            Ok(Lease::Held(_)) => thread::sleep(RETRY_INTERVAL),
+            // Another writer is in the middle of its writes, and holds the lease until they are done.
+            Err(Error::SqliteFailure(error, _))
+                if matches!(error.code, ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked) =>
+            {
+                thread::sleep(RETRY_INTERVAL)
+            }
+            Ok(lease) => {
+                lease.events(holder).into_iter().for_each(&report);
+                break;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    let value = write();
+    if !release_lease(holder, conn)? {
+        report(Event::LeaseLost(holder.to_owned()));
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::ScratchFile;
+    use std::sync::mpsc;
+
+    #[test]
+    fn waits_for_the_lease_from_another_connection() {
+        let file = ScratchFile::new("db");
+        let (first, second) = (Connection::open(&file.0).unwrap(), Connection::open(&file.0).unwrap());
+        create_lease_table(&first);
+        // The second connection fails at once while the first is writing, rather than waiting.
+        second.busy_timeout(Duration::from_millis(0)).unwrap();
+
+        let (taken, was_taken) = mpsc::channel();
+        let (release, released) = mpsc::channel::<()>();
+        let writer = thread::spawn(move || {
+            with_lease("first", &first, |_| (), || {
+                // A write transaction is left open on the first connection until it is released.
+                let transaction = Transaction::new_unchecked(&first, TransactionBehavior::Immediate).unwrap();
+                taken.send(()).unwrap();
+                released.recv().unwrap();
+                transaction.commit().unwrap();
+            })
+            .unwrap();
+        });
+        was_taken.recv().unwrap();
+
+        let events = std::cell::RefCell::new(Vec::new());
+        let waiting = thread::spawn(move || {
+            thread::sleep(RETRY_INTERVAL * 2);
+            release.send(()).unwrap();
+        });
+        let holder = with_lease("second", &second, |event| events.borrow_mut().push(event), || {
+            get_lease_holder(&second).unwrap()
+        });
+        writer.join().unwrap();
+        waiting.join().unwrap();
+
+        assert_eq!(holder.unwrap().as_deref(), Some("second"));
+        assert_eq!(
+            events.into_inner(),
+            [Event::LeaseChanged { holder: "second".to_owned(), previous: "first".to_owned() }]
+        );
+    }
+}
@@ -2,28 +2,168 @@ extern crate quick_error;
 extern crate chrono;
 extern crate humantime;
 extern crate itertools;
+#[cfg(feature = "library")]
 extern crate r2d2;
+#[cfg(feature = "library")]
 extern crate r2d2_sqlite;
+#[cfg(feature = "library")]
 extern crate rand;
+#[cfg(feature = "library")]
 extern crate regex;
+#[cfg(feature = "library")]
 extern crate rusqlite;
+#[cfg(feature = "library")]
 extern crate app_dirs;
+#[cfg(feature = "library")]
 extern crate toml;
 extern crate katatsuki;
+#[cfg(feature = "library")]
 extern crate dirs;
+#[cfg(all(feature = "library", unix))]
+extern crate libc;
+#[cfg(feature = "watcher")]
+extern crate notify;
+#[cfg(feature = "watcher")]
+extern crate threadpool;
+#[cfg(feature = "watcher")]
+extern crate walkdir;
+#[cfg(feature = "analysis")]
+extern crate symphonia;
+#[cfg(feature = "net")]
+extern crate ureq;
+#[cfg(any(feature = "net", feature = "catalog", feature = "scrobbles"))]
+extern crate serde_json;
+#[cfg(feature = "catalog")]
+extern crate image;
+#[cfg(feature = "archives")]
+extern crate zip;
+#[cfg(feature = "casting")]
+extern crate mdns_sd;
+#[cfg(feature = "casting")]
+extern crate rust_cast;
 
-mod bangs;
+#[cfg(all(feature = "library", not(any(feature = "taglib", feature = "symphonia-tags"))))]
+compile_error!("the library feature needs a tag backend, either the taglib or the symphonia-tags feature");
+
+pub mod bangs;
 mod error;
 
 
 pub use katatsuki::TrackFileType;
 pub use katatsuki::Track;
+pub use katatsuki::TrackBuilder;
+pub use katatsuki::Quality;
+pub use katatsuki::MetadataReader;
 pub use self::error::{Error, Result, ConfigErrorType};
 pub use self::bangs::Bang;
 
+#[cfg(feature = "library")]
+pub mod aliases;
+#[cfg(feature = "archives")]
+pub mod archives;
+#[cfg(feature = "library")]
+pub mod art;
+#[cfg(feature = "analysis")]
+pub mod analysis;
+#[cfg(feature = "net")]
+pub mod auth;
+#[cfg(feature = "library")]
+pub mod browse;
+#[cfg(feature = "library")]
+pub mod cache;
+#[cfg(feature = "casting")]
+pub mod casting;
+#[cfg(feature = "catalog")]
+pub mod catalog;
+#[cfg(feature = "collation")]
+pub mod collation;
+pub mod columns;
+#[cfg(feature = "analysis")]
+pub mod compilations;
+#[cfg(feature = "library")]
 pub mod config;
+#[cfg(feature = "library")]
+pub mod conflicts;
+#[cfg(feature = "library")]
 pub mod database;
+#[cfg(feature = "watcher")]
+pub mod downloads;
+#[cfg(feature = "library")]
+pub mod editions;
+#[cfg(feature = "library")]
+pub mod encryption;
+pub mod events;
+#[cfg(feature = "library")]
+pub mod failures;
+#[cfg(feature = "library")]
+pub mod fields;
+#[cfg(feature = "watcher")]
+pub mod filesystem;
+#[cfg(feature = "library")]
+pub mod gains;
+#[cfg(feature = "library")]
+pub mod genres;
+#[cfg(feature = "library")]
+pub mod hooks;
+#[cfg(feature = "net")]
+pub mod http;
+#[cfg(feature = "library")]
+pub mod import;
+#[cfg(feature = "library")]
+pub mod inference;
+#[cfg(feature = "library")]
+pub mod layouts;
+#[cfg(feature = "library")]
+pub mod lease;
+#[cfg(feature = "library")]
+pub mod library;
+#[cfg(feature = "library")]
+pub mod locks;
+pub mod messages;
+#[cfg(feature = "needledrops")]
+pub mod needledrops;
+#[cfg(feature = "library")]
+pub mod normalization;
+#[cfg(feature = "library")]
+pub mod notes;
+#[cfg(feature = "library")]
 pub mod paths;
+#[cfg(feature = "watcher")]
+pub mod placeholders;
+#[cfg(feature = "library")]
+pub mod profiles;
+#[cfg(feature = "library")]
+pub mod provenance;
+#[cfg(feature = "library")]
+pub mod queue;
+#[cfg(feature = "library")]
+pub mod rejections;
+#[cfg(feature = "library")]
+pub mod relationships;
+#[cfg(feature = "net")]
+pub mod replication;
+#[cfg(feature = "watcher")]
+pub mod reports;
+#[cfg(feature = "watcher")]
+pub mod rips;
+#[cfg(feature = "library")]
+pub mod scans;
+#[cfg(feature = "library")]
+pub mod schedule;
+#[cfg(feature = "scrobbles")]
+pub mod scrobbles;
+#[cfg(feature = "library")]
+pub mod search;
+#[cfg(feature = "library")]
+pub mod subscriptions;
+#[cfg(feature = "library")]
+pub mod suggestions;
+#[cfg(feature = "library")]
+pub mod variants;
+#[cfg(feature = "watcher")]
+pub mod watcher;
+#[cfg(feature = "net")]
+pub mod webhooks;
 
 pub mod ticks {
     pub use crate::bangs::ms_to_ticks;
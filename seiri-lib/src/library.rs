@@ -0,0 +1,1389 @@
+//! Queries over the library as a whole, built on the tables of every other module.
+
+#[cfg(feature = "analysis")]
+use crate::analysis::{analyze_track, get_waveform};
+#[cfg(feature = "analysis")]
+use crate::compilations::{find_fingerprint_match, fingerprint};
+use crate::config::{LayoutConfig, RequiredTagsConfig};
+use crate::database::{
+    add_track, create_database, create_table_with_foreign_keys, get_track_by_uuid, query_tracks, remove_track,
+    scratch_name, track_from_row, Connection, TRACK_COLUMNS,
+};
+use crate::encryption::{apply_library_key, library_key};
+use crate::locks;
+#[cfg(feature = "imaging")]
+use crate::rejections::hash_bytes;
+use crate::scans::{self, ScanResult};
+use crate::layouts;
+use crate::Bang;
+use crate::profiles::{add_to_playlist, create_playlist, get_playlist_tracks, get_playlists, get_profiles};
+use crate::paths::{self, FolderCasing};
+use crate::variants::{self, Transcoder};
+use katatsuki::{TagUpdate, Track};
+use regex::Regex;
+use rusqlite::types::ToSql;
+use rusqlite::{OpenFlags, OptionalExtension, Result, Transaction, TransactionBehavior, NO_PARAMS};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+/// How much a track having the same artist as the seed track counts towards its similarity,
+/// where artists with aliases are the same under any of their names.
+const SAME_ARTIST_WEIGHT: i64 = 3;
+
+/// How much a track being on the same album as the seed track counts towards its similarity.
+const SAME_ALBUM_WEIGHT: i64 = 2;
+
+/// How much every genre a track shares with the seed track counts towards its similarity.
+const SHARED_GENRE_WEIGHT: i64 = 2;
+
+/// How much every profile that played both a track and the seed track, and every playlist
+/// containing both, counts towards the similarity of the track.
+const CO_LISTEN_WEIGHT: i64 = 1;
+
+/// The longest path in characters that Windows opens without long path support.
+const MAX_PATH_LENGTH: usize = 260;
+
+/// The longest file or folder name in bytes on most file systems.
+const MAX_NAME_LENGTH: usize = 255;
+
+/// Builds a queue of up to `n` tracks related to the track with the given UUID,
+/// for continuous playback after it.
+///
+/// Tracks are ranked by how many signals they share with the seed track: the same artist,
+/// the same album, shared genres, and being played by the same profiles or kept in
+/// the same playlists. Tracks sharing no signals are left out, and tracks ranked the same
+/// are shuffled, so the queue differs every time. The seed track itself is never included.
+///
+/// Returns no tracks if there is no track with the UUID.
+pub fn similar(track_id: &str, n: usize, conn: &Connection) -> Result<Vec<Track>> {
+    let canonical_artist = |artist: &str| {
+        format!(
+            "COALESCE((SELECT Artist FROM artist_aliases WHERE Alias = {artist}), {artist})",
+            artist = artist
+        )
+    };
+    let query = format!(
+        "WITH seed AS (SELECT FilePath, Artist, Album, AlbumArtists FROM tracks WHERE TrackId = ?1),
+        scores AS (SELECT tracks.FilePath AS FilePath,
+            {same_artist} * ({track_artist} = {seed_artist})
+            + {same_album} * (tracks.Album = seed.Album AND tracks.AlbumArtists = seed.AlbumArtists)
+            + {shared_genre} * (SELECT COUNT(*) FROM track_genres AS genres
+                JOIN track_genres AS seed_genres ON seed_genres.Genre = genres.Genre
+                WHERE genres.FilePath = tracks.FilePath AND seed_genres.FilePath = seed.FilePath)
+            + {co_listen} * (SELECT COUNT(*) FROM profile_tracks AS plays
+                JOIN profile_tracks AS seed_plays ON seed_plays.ProfileId = plays.ProfileId
+                WHERE plays.FilePath = tracks.FilePath AND seed_plays.FilePath = seed.FilePath
+                AND plays.PlayCount > 0 AND seed_plays.PlayCount > 0)
+            + {co_listen} * (SELECT COUNT(DISTINCT entries.PlaylistId) FROM playlist_tracks AS entries
+                JOIN playlist_tracks AS seed_entries ON seed_entries.PlaylistId = entries.PlaylistId
+                WHERE entries.FilePath = tracks.FilePath AND seed_entries.FilePath = seed.FilePath)
+            AS Score
+            FROM tracks, seed WHERE tracks.FilePath != seed.FilePath)
+        SELECT {columns} FROM tracks JOIN scores ON scores.FilePath = tracks.FilePath
+        WHERE Score > 0 ORDER BY Score DESC, random() LIMIT ?2",
+        same_artist = SAME_ARTIST_WEIGHT,
+        same_album = SAME_ALBUM_WEIGHT,
+        shared_genre = SHARED_GENRE_WEIGHT,
+        co_listen = CO_LISTEN_WEIGHT,
+        track_artist = canonical_artist("tracks.Artist"),
+        seed_artist = canonical_artist("seed.Artist"),
+        columns = TRACK_COLUMNS,
+    );
+    let mut statement = conn.prepare(&query)?;
+    let tracks = statement
+        .query_map(&[&track_id as &dyn ToSql, &(n as i64)], track_from_row)?
+        .collect::<Result<Vec<Track>>>()?;
+    Ok(tracks)
+}
+
+/// A track that differs between two versions of the library.
+#[derive(Debug, Clone)]
+pub struct TrackChange {
+    pub before: Track,
+    pub after: Track,
+    /// The names of the fields of the track that differ, such as `title` and `file_path`.
+    pub fields: Vec<&'static str>,
+}
+
+/// The tracks added, removed and changed between two versions of the library.
+#[derive(Debug, Clone, Default)]
+pub struct LibraryDiff {
+    pub added: Vec<Track>,
+    pub removed: Vec<Track>,
+    pub changed: Vec<TrackChange>,
+}
+
+/// Gets the names of the fields that differ between the two tracks,
+/// ignoring when they were last updated.
+fn changed_fields(before: &Track, after: &Track) -> Vec<&'static str> {
+    let sorted = |genres: &[String]| {
+        let mut genres = genres.iter().map(|genre| genre.to_lowercase()).collect::<Vec<String>>();
+        genres.sort();
+        genres
+    };
+    let fields = [
+        ("file_path", before.file_path != after.file_path),
+        ("file_type", before.file_type != after.file_type),
+        ("title", before.title != after.title),
+        ("artist", before.artist != after.artist),
+        ("album_artists", before.album_artists != after.album_artists),
+        ("album", before.album != after.album),
+        ("year", before.year != after.year),
+        ("track_number", before.track_number != after.track_number),
+        ("disc_number", before.disc_number != after.disc_number),
+        ("musicbrainz_track_id", before.musicbrainz_track_id != after.musicbrainz_track_id),
+        ("comment", before.comment != after.comment),
+        ("isrc", before.isrc != after.isrc),
+        ("encoder", before.encoder != after.encoder),
+        // Genres are kept in no particular order, and ignore case.
+        ("genres", sorted(&before.genres) != sorted(&after.genres)),
+        ("has_front_cover", before.has_front_cover != after.has_front_cover),
+        (
+            "front_cover",
+            (before.front_cover_width, before.front_cover_height)
+                != (after.front_cover_width, after.front_cover_height),
+        ),
+        ("bitrate", before.bitrate != after.bitrate),
+        ("sample_rate", before.sample_rate != after.sample_rate),
+        ("source", before.source != after.source),
+        ("duration", before.duration != after.duration),
+    ];
+    fields
+        .iter()
+        .filter(|(_, changed)| *changed)
+        .map(|(field, _)| *field)
+        .collect()
+}
+
+/// Gets every track of the library, keyed by its UUID, or its file path if it has none.
+fn get_tracks_by_id(conn: &Connection) -> Result<HashMap<String, Track>> {
+    let mut statement = conn.prepare(&format!("SELECT {} FROM tracks", TRACK_COLUMNS))?;
+    let tracks = statement
+        .query_map(NO_PARAMS, track_from_row)?
+        .map(|track| {
+            track.map(|track| {
+                let id = track
+                    .uuid
+                    .clone()
+                    .unwrap_or_else(|| track.file_path.to_string_lossy().into_owned());
+                (id, track)
+            })
+        })
+        .collect::<Result<HashMap<String, Track>>>()?;
+    Ok(tracks)
+}
+
+fn diff_tracks(mut before: HashMap<String, Track>, after: HashMap<String, Track>) -> LibraryDiff {
+    let mut diff = LibraryDiff::default();
+    for (id, after) in after {
+        match before.remove(&id) {
+            None => diff.added.push(after),
+            Some(before) => {
+                let fields = changed_fields(&before, &after);
+                if !fields.is_empty() {
+                    diff.changed.push(TrackChange { before, after, fields });
+                }
+            }
+        }
+    }
+    diff.removed.extend(before.into_values());
+    let by_path = |a: &Track, b: &Track| a.file_path.cmp(&b.file_path);
+    diff.added.sort_by(by_path);
+    diff.removed.sort_by(by_path);
+    diff.changed.sort_by(|a, b| by_path(&a.after, &b.after));
+    diff
+}
+
+fn open_snapshot(snapshot_path: &Path) -> Result<Connection> {
+    let snapshot = Connection::open_with_flags(snapshot_path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    apply_library_key(&snapshot)?;
+    Ok(snapshot)
+}
+
+/// Opens another library, unlocking it with the key of this library if it can not be read
+/// without one, as with conflicting copies of an encrypted library.
+fn open_other_library(other_db_path: &Path, flags: OpenFlags) -> Result<Connection> {
+    let other = Connection::open_with_flags(other_db_path, flags)?;
+    let readable = other
+        .query_row("SELECT COUNT(*) FROM sqlite_master", NO_PARAMS, |row| row.get::<_, i64>(0))
+        .is_ok();
+    if readable || library_key().is_none() {
+        return Ok(other);
+    }
+    // The key has to be given before anything is read, so the library is opened again.
+    drop(other);
+    let other = Connection::open_with_flags(other_db_path, flags)?;
+    apply_library_key(&other)?;
+    Ok(other)
+}
+
+/// Copies another library to the path, and brings the copy up to the schema of this library,
+/// since libraries made by older versions of seiri lack the tables and columns read from them.
+/// The other library is only read.
+fn open_migrated_copy(other_db_path: &Path, copy_path: &Path) -> Result<Connection> {
+    let other = open_other_library(other_db_path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    other.execute("VACUUM INTO ?1", &[&copy_path.to_string_lossy().into_owned()])?;
+    drop(other);
+    let copy = open_other_library(copy_path, OpenFlags::SQLITE_OPEN_READ_WRITE)?;
+    create_database(&copy);
+    Ok(copy)
+}
+
+/// Compares two snapshots exported by `export_snapshot`, reporting the tracks added, removed
+/// and changed from `snapshot_a` to `snapshot_b`, in the order of their file paths.
+///
+/// Tracks are matched by their UUID, so a track whose file moved is changed rather than
+/// removed and added again. Both snapshots must be of the same `SNAPSHOT_FORMAT_VERSION`.
+pub fn diff(snapshot_a: &Path, snapshot_b: &Path) -> Result<LibraryDiff> {
+    let before = get_tracks_by_id(&open_snapshot(snapshot_a)?)?;
+    let after = get_tracks_by_id(&open_snapshot(snapshot_b)?)?;
+    Ok(diff_tracks(before, after))
+}
+
+/// Compares a snapshot or backup of the library to the library as it is now,
+/// such as to audit what an import or removing duplicates did since the snapshot was taken.
+pub fn diff_with_library(snapshot_path: &Path, conn: &Connection) -> Result<LibraryDiff> {
+    let before = get_tracks_by_id(&open_snapshot(snapshot_path)?)?;
+    Ok(diff_tracks(before, get_tracks_by_id(conn)?))
+}
+
+/// How play counts of the same track in both libraries are combined by `merge`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlayCountStrategy {
+    /// Plays in both libraries are counted, for libraries listened to separately.
+    /// Merging the same library again counts its plays again.
+    Sum,
+    /// The greater play count is kept, for libraries that were copied from each other.
+    Max,
+}
+
+/// How another library is merged into this one.
+#[derive(Debug, Clone)]
+pub struct MergeStrategy {
+    /// Whether tracks only in the other library are added to this one.
+    /// Otherwise only the statistics and playlists of tracks in both libraries are merged.
+    pub add_missing_tracks: bool,
+    pub play_counts: PlayCountStrategy,
+    /// The music folder of the other library and the music folder of this one.
+    /// Added tracks under the first are added under the second instead,
+    /// for when the files of the other library were copied into this one.
+    pub rebase: Option<(PathBuf, PathBuf)>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MergeReport {
+    /// The number of tracks of the other library that were already in this one.
+    pub matched: usize,
+    /// The number of tracks of the other library added to this one.
+    pub added: usize,
+    /// The number of tracks of the other library that were not added, since they were
+    /// not to be, or another track is already at their path.
+    pub skipped: usize,
+    /// The number of playlists of the other library created in this one.
+    /// Playlists with the same name as one of this library are merged into it.
+    pub playlists: usize,
+}
+
+/// What identifies a track across libraries, where its file may be at a different path.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TrackKey {
+    pub uuid: Option<String>,
+    pub musicbrainz_track_id: Option<String>,
+    pub title: String,
+    pub artist: String,
+    pub album: String,
+    pub album_artists: Vec<String>,
+    pub disc_number: i32,
+    pub track_number: i32,
+}
+
+impl TrackKey {
+    pub fn of(track: &Track) -> TrackKey {
+        TrackKey {
+            uuid: track.uuid.clone(),
+            musicbrainz_track_id: track.musicbrainz_track_id.clone(),
+            title: track.title.to_owned(),
+            artist: track.artist.to_owned(),
+            album: track.album.to_owned(),
+            album_artists: track.album_artists.clone(),
+            disc_number: track.disc_number,
+            track_number: track.track_number,
+        }
+    }
+}
+
+/// Finds the path of the track of the library that is the same as a track of another library,
+/// preferring the same UUID, then the same MusicBrainz ID, then the same tags.
+///
+/// Tracks whose tags differ between the libraries are found by `find_same_recording` instead.
+pub fn find_same_track(key: &TrackKey, conn: &Connection) -> Result<Option<String>> {
+    conn.query_row(
+        "SELECT FilePath FROM tracks WHERE TrackId = ?1
+            OR (?2 IS NOT NULL AND MusicBrainzTrackId = ?2)
+            OR (Title = ?3 COLLATE NOCASE AND Artist = ?4 COLLATE NOCASE
+                AND Album = ?5 COLLATE NOCASE AND AlbumArtists = ?6 COLLATE NOCASE
+                AND DiscNumber = ?7 AND TrackNumber = ?8)
+        ORDER BY TrackId IS ?1 DESC, MusicBrainzTrackId IS ?2 DESC LIMIT 1",
+        &[
+            &key.uuid as &dyn ToSql,
+            &key.musicbrainz_track_id,
+            &key.title,
+            &key.artist,
+            &key.album,
+            &key.album_artists.join(";"),
+            &key.disc_number,
+            &key.track_number,
+        ],
+        |row| row.get(0),
+    )
+    .optional()
+}
+
+/// Finds the path of the track of the library that is the same recording as a track of another
+/// library, by their fingerprints, for tracks whose tags differ between the libraries. The track
+/// of the other library is fingerprinted by the waveform the other library saved for it, or by
+/// decoding its file if it can be reached and was not analyzed.
+#[cfg(feature = "analysis")]
+fn find_same_recording(track: &Track, other: &Connection, conn: &Connection) -> Result<Option<String>> {
+    let waveform = match &track.uuid {
+        Some(track_id) => get_waveform(track_id, other)?,
+        None => None,
+    };
+    let waveform = match waveform {
+        Some(waveform) => waveform,
+        None => match analyze_track(track, false) {
+            Ok(analysis) => analysis.waveform,
+            Err(_) => return Ok(None),
+        },
+    };
+    Ok(find_fingerprint_match(&fingerprint(&waveform), track.duration, conn)?
+        .map(|track| track.file_path.to_string_lossy().into_owned()))
+}
+
+/// Merges the statistics of every profile of the other library into this one,
+/// for the tracks of the other library at the paths of the map.
+fn merge_stats(
+    other: &Connection,
+    paths: &HashMap<String, String>,
+    play_counts: PlayCountStrategy,
+    conn: &Connection,
+) -> Result<()> {
+    let play_count = match play_counts {
+        PlayCountStrategy::Sum => "PlayCount + excluded.PlayCount",
+        PlayCountStrategy::Max => "MAX(PlayCount, excluded.PlayCount)",
+    };
+    let mut insert = conn.prepare(&format!(
+        "INSERT INTO profile_tracks(ProfileId, FilePath, PlayCount, Rating, Favorite, LastPlayed)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+        ON CONFLICT(ProfileId, FilePath) DO UPDATE SET
+            PlayCount = {},
+            Rating = IFNULL(Rating, excluded.Rating),
+            Favorite = MAX(Favorite, excluded.Favorite),
+            LastPlayed = CASE WHEN LastPlayed IS NULL OR excluded.LastPlayed > LastPlayed
+                THEN excluded.LastPlayed ELSE LastPlayed END",
+        play_count
+    ))?;
+    let mut statement = other.prepare(
+        "SELECT ProfileId, FilePath, PlayCount, Rating, Favorite, LastPlayed FROM profile_tracks",
+    )?;
+    let mut rows = statement.query(NO_PARAMS)?;
+    while let Some(row) = rows.next()? {
+        if let Some(file_path) = paths.get(&row.get::<_, String>(1)?) {
+            insert.execute(&[
+                &row.get::<_, String>(0)? as &dyn ToSql,
+                file_path,
+                &row.get::<_, i32>(2)?,
+                &row.get::<_, Option<i32>>(3)?,
+                &row.get::<_, bool>(4)?,
+                &row.get::<_, Option<String>>(5)?,
+            ])?;
+        }
+    }
+    Ok(())
+}
+
+/// Merges every playlist of the other library into the playlist of the same profile and name,
+/// appending the tracks it does not already have. Returns the number of playlists created.
+fn merge_playlists(other: &Connection, paths: &HashMap<String, String>, conn: &Connection) -> Result<usize> {
+    let mut created = 0;
+    for profile in get_profiles(other)? {
+        for playlist in get_playlists(&profile.profile_id, other)? {
+            let existing = get_playlists(&profile.profile_id, conn)?
+                .iter()
+                .any(|existing| existing.name == playlist.name);
+            let playlist_id = create_playlist(&profile.profile_id, &playlist.name, conn)?;
+            if !existing {
+                created += 1;
+            }
+            let mut tracks = get_playlist_tracks(playlist_id, conn)?
+                .into_iter()
+                .collect::<HashSet<PathBuf>>();
+            for track in get_playlist_tracks(playlist.playlist_id, other)? {
+                if let Some(file_path) = paths.get(track.to_string_lossy().as_ref()) {
+                    let file_path = PathBuf::from(file_path);
+                    if !tracks.contains(&file_path) {
+                        add_to_playlist(playlist_id, &file_path, conn)?;
+                        tracks.insert(file_path);
+                    }
+                }
+            }
+        }
+    }
+    Ok(created)
+}
+
+/// Merges the library in the database at the given path, such as the library of another
+/// computer, into this one.
+///
+/// Tracks in both libraries are found by their UUID, their MusicBrainz ID, or their tags, and with
+/// the `analysis` feature by their fingerprints where their tags differ, and are never added twice. The play counts, ratings and favourites of every profile are
+/// reconciled, keeping the rating of this library where both have rated a track, and playlists
+/// are merged by name. Files are not copied, so added tracks are only playable if the files
+/// of the other library can be reached at their paths, or have been copied to where
+/// `MergeStrategy::rebase` puts them.
+///
+/// The merge is made in a single transaction, so it is either made in full or not at all.
+///
+/// The other library is read from a copy brought up to the schema of this library, so libraries
+/// made by older versions of seiri are merged as well, and the copy is removed afterwards.
+pub fn merge(other_db_path: &Path, strategy: &MergeStrategy, conn: &Connection) -> Result<MergeReport> {
+    let copy_path = std::env::temp_dir().join(format!("{}.db", scratch_name()));
+    let report = open_migrated_copy(other_db_path, &copy_path).and_then(|other| merge_library(&other, strategy, conn));
+    fs::remove_file(&copy_path).unwrap_or(());
+    report
+}
+
+fn merge_library(other: &Connection, strategy: &MergeStrategy, conn: &Connection) -> Result<MergeReport> {
+    let transaction = Transaction::new_unchecked(conn, TransactionBehavior::Immediate)?;
+    let mut report = MergeReport::default();
+    // The paths of the tracks of the other library, mapped to the tracks of this library.
+    let mut paths = HashMap::<String, String>::new();
+    for mut track in get_tracks_by_id(other)?.into_values() {
+        let other_path = track.file_path.to_string_lossy().into_owned();
+        let same_track = find_same_track(&TrackKey::of(&track), &transaction)?;
+        #[cfg(feature = "analysis")]
+        let same_track = match same_track {
+            Some(file_path) => Some(file_path),
+            None => find_same_recording(&track, other, &transaction)?,
+        };
+        if let Some(file_path) = same_track {
+            report.matched += 1;
+            paths.insert(other_path, file_path);
+            continue;
+        }
+        if let Some((from, to)) = &strategy.rebase {
+            if let Ok(relative_path) = track.file_path.strip_prefix(from) {
+                track.file_path = to.join(relative_path);
+            }
+        }
+        let file_path = track.file_path.to_string_lossy().into_owned();
+        let taken = transaction
+            .prepare("SELECT 1 FROM tracks WHERE FilePath = ?1")?
+            .exists(&[&file_path])?;
+        if !strategy.add_missing_tracks || taken {
+            report.skipped += 1;
+            continue;
+        }
+        add_track(&track, &transaction);
+        report.added += 1;
+        paths.insert(other_path, file_path);
+    }
+    for profile in get_profiles(other)? {
+        // Profiles only in the other library are added, but existing profiles keep their names.
+        transaction.execute(
+            "INSERT OR IGNORE INTO profiles(ProfileId, Name) VALUES (?1, ?2)",
+            &[&profile.profile_id, &profile.name],
+        )?;
+    }
+    merge_stats(other, &paths, strategy.play_counts, &transaction)?;
+    report.playlists = merge_playlists(other, &paths, &transaction)?;
+    transaction.commit()?;
+    Ok(report)
+}
+
+/// How an existing collection is imported by `bootstrap`.
+#[derive(Debug, Clone)]
+pub struct BootstrapOptions {
+    /// The source the tracks are imported with, since they were not dropped into a source folder.
+    pub source: String,
+    /// Whether tracks already in the library are left as they are, rather than read again.
+    pub skip_existing: bool,
+    /// Whether files that did not change since they were last scanned are left as they were,
+    /// rather than read again.
+    pub skip_unchanged: bool,
+    /// How missing tags are filled in, or whether the tracks missing them are left out.
+    pub required_tags: RequiredTagsConfig,
+}
+
+impl Default for BootstrapOptions {
+    fn default() -> BootstrapOptions {
+        BootstrapOptions {
+            source: "None".to_owned(),
+            skip_existing: true,
+            skip_unchanged: true,
+            required_tags: RequiredTagsConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BootstrapReport {
+    /// The number of tracks added to the library.
+    pub imported: usize,
+    /// The number of tracks that were already in the library.
+    pub skipped: usize,
+    /// The number of files that are not tracks, such as art and booklets.
+    pub unsupported: usize,
+    /// The tracks that could not be read, or are missing tags required to be imported.
+    pub failed: Vec<PathBuf>,
+}
+
+/// Gets every file in the folder and its subfolders, other than hidden files and folders.
+fn files_in(folder: &Path, files: &mut Vec<PathBuf>) {
+    let entries = match fs::read_dir(folder) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        match entry.file_type() {
+            Ok(file_type) if file_type.is_dir() => files_in(&entry.path(), files),
+            Ok(file_type) if file_type.is_file() => files.push(entry.path()),
+            _ => (),
+        }
+    }
+}
+
+/// Imports a collection that is already organized, such as on first run, adding every track
+/// in the folder and its subfolders to the library where it is, instead of dropping the whole
+/// collection into the watch folder. The tables of the library are created if they do not exist.
+///
+/// Files are never moved, renamed or changed, so files that are not tracks and tracks that can
+/// not be imported are left where they are and counted in the report. The collection is imported
+/// in a single transaction, so it is either imported in full or not at all.
+///
+/// What came of every file is remembered, so files that did not change since are not read again
+/// when the collection is scanned again, unless `skip_unchanged` is false.
+pub fn bootstrap(existing_folder: &Path, options: &BootstrapOptions, conn: &Connection) -> Result<BootstrapReport> {
+    if !existing_folder.is_dir() {
+        return Err(rusqlite::Error::InvalidPath(existing_folder.to_owned()));
+    }
+    create_database(conn);
+    let mut files = Vec::new();
+    files_in(existing_folder, &mut files);
+    files.sort();
+    let transaction = Transaction::new_unchecked(conn, TransactionBehavior::Immediate)?;
+    let mut report = BootstrapReport::default();
+    let mut exists = transaction.prepare("SELECT 1 FROM tracks WHERE FilePath = ?1")?;
+    for file in files {
+        if options.skip_existing && exists.exists(&[&file.to_string_lossy().into_owned()])? {
+            report.skipped += 1;
+            continue;
+        }
+        let identity = scans::file_identity(&file);
+        let cached = match identity {
+            Some(identity) if options.skip_unchanged => scans::get_cached_scan(&file, &identity, &transaction)?,
+            _ => None,
+        };
+        let result = match cached {
+            // Unchanged tracks are still in the library as they were read.
+            Some(ScanResult::Track) => {
+                report.skipped += 1;
+                continue;
+            }
+            Some(result) => result,
+            None => {
+                // Files in the library are left alone, so they can be read from memory, which is faster for large scans.
+                let read = Track::from_path_mapped(&file, Some(&options.source)).map(|mut track| {
+                    let filled = paths::fill_required_tags(&mut track, &options.required_tags).is_ok();
+                    (track, filled)
+                });
+                let result = match read {
+                    Ok((track, true)) => {
+                        add_track(&track, &transaction);
+                        report.imported += 1;
+                        ScanResult::Track
+                    }
+                    Err(err) if err.kind() == ErrorKind::InvalidData => ScanResult::Unsupported,
+                    _ => ScanResult::Failed,
+                };
+                if let Some(identity) = identity {
+                    scans::cache_scan(&file, &identity, result, &transaction)?;
+                }
+                result
+            }
+        };
+        match result {
+            ScanResult::Track => (),
+            ScanResult::Unsupported => report.unsupported += 1,
+            ScanResult::Failed => report.failed.push(file),
+        }
+    }
+    drop(exists);
+    transaction.commit()?;
+    Ok(report)
+}
+
+/// Removes every track in the folder and its subfolders whose file is gone,
+/// such as since an adopted music folder was last watched, and forgets every other file that is
+/// gone from the cache of scanned files. Returns the paths of the removed tracks.
+pub fn prune_missing(folder: &Path, conn: &Connection) -> Result<Vec<PathBuf>> {
+    let mut statement = conn.prepare("SELECT FilePath FROM tracks")?;
+    let missing = statement
+        .query_map(NO_PARAMS, |row| row.get::<_, String>(0))?
+        .collect::<Result<Vec<String>>>()?
+        .into_iter()
+        .map(PathBuf::from)
+        .filter(|path| path.starts_with(folder) && !path.exists())
+        .collect::<Vec<PathBuf>>();
+    for path in &missing {
+        conn.execute("DELETE FROM tracks WHERE FilePath = ?1", &[&path.to_string_lossy().into_owned()])?;
+    }
+    scans::forget_missing(folder, conn)?;
+    Ok(missing)
+}
+
+/// The format `normalize_art` re-encodes art into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArtFormat {
+    Jpeg,
+    Png,
+}
+
+impl ArtFormat {
+    /// Gets the format with the given name, such as `jpeg` or `png`.
+    pub fn from_name(name: &str) -> Option<ArtFormat> {
+        match name.to_lowercase().as_str() {
+            "jpg" | "jpeg" => Some(ArtFormat::Jpeg),
+            "png" => Some(ArtFormat::Png),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NormalizeArtReport {
+    /// The number of tracks whose art was re-encoded.
+    pub tracks: usize,
+    /// The number of bytes of art removed from the re-encoded tracks.
+    pub saved: u64,
+    /// The tracks whose art could not be re-encoded.
+    pub failed: Vec<PathBuf>,
+}
+
+/// The quality art is re-encoded as a JPEG with.
+#[cfg(feature = "imaging")]
+const JPEG_QUALITY: u8 = 90;
+
+/// Scales the art to fit within `max_dimension` and encodes it in the format.
+#[cfg(feature = "imaging")]
+fn reencode_art(art: &[u8], max_dimension: u32, format: ArtFormat) -> image::ImageResult<Vec<u8>> {
+    use image::codecs::jpeg::JpegEncoder;
+    use image::imageops::FilterType;
+    use image::ImageFormat;
+    let mut image = image::load_from_memory(art)?;
+    if image.width() > max_dimension || image.height() > max_dimension {
+        image = image.resize(max_dimension, max_dimension, FilterType::Lanczos3);
+    }
+    let mut encoded = Vec::new();
+    match format {
+        // JPEG has no alpha channel, so transparent art is flattened.
+        ArtFormat::Jpeg => JpegEncoder::new_with_quality(&mut encoded, JPEG_QUALITY).encode_image(&image.to_rgb8())?,
+        ArtFormat::Png => image.write_to(&mut std::io::Cursor::new(&mut encoded), ImageFormat::Png)?,
+    }
+    Ok(encoded)
+}
+
+/// Re-encodes the front cover of every track matching the query that is larger than `max_dimension`
+/// in width or height, scaling it to fit and encoding it in the format, then reads the tracks again.
+/// Locked tracks are left as they are.
+///
+/// Only the size of the art recorded in the library is used to find oversized art, so tracks whose
+/// art is small enough are never read. Identical art shared by the tracks of an album is only
+/// re-encoded once.
+#[cfg(feature = "imaging")]
+pub fn normalize_art(
+    bang: Bang,
+    max_dimension: u32,
+    format: ArtFormat,
+    conn: &Connection,
+) -> Result<NormalizeArtReport> {
+    let max_dimension = max_dimension.max(1);
+    let mut report = NormalizeArtReport::default();
+    let mut reencoded: HashMap<String, Vec<u8>> = HashMap::new();
+    let oversized = query_tracks(bang, conn, None, None)?.into_iter().filter(|track| {
+        track.has_front_cover
+            && (track.front_cover_width.max(track.front_cover_height).max(0) as u32) > max_dimension
+    });
+    for track in oversized {
+        if locks::is_locked(&track, conn)? {
+            continue;
+        }
+        let art = match track.read_front_cover() {
+            Ok(Some(art)) => art,
+            Ok(None) => continue,
+            Err(_) => {
+                report.failed.push(track.file_path);
+                continue;
+            }
+        };
+        let hash = hash_bytes(&art);
+        if !reencoded.contains_key(&hash) {
+            match reencode_art(&art, max_dimension, format) {
+                Ok(normalized) => reencoded.insert(hash.clone(), normalized),
+                Err(_) => {
+                    report.failed.push(track.file_path);
+                    continue;
+                }
+            };
+        }
+        let normalized = &reencoded[&hash];
+        let read = track
+            .write_front_cover(normalized)
+            .and_then(|_| Track::from_path(&track.file_path, Some(&track.source)));
+        match read {
+            Ok(read) => {
+                add_track(&Track { uuid: track.uuid.clone(), ..read }, conn);
+                report.tracks += 1;
+                report.saved += (art.len() as u64).saturating_sub(normalized.len() as u64);
+            }
+            Err(_) => report.failed.push(track.file_path),
+        }
+    }
+    Ok(report)
+}
+
+/// A track that would be organized into the same file as another by a layout.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathCollision {
+    /// The file the tracks would be organized into.
+    pub path: PathBuf,
+    /// The paths the tracks are at now.
+    pub tracks: Vec<PathBuf>,
+}
+
+/// What would go wrong organizing every track of the library by a layout, from `audit_template`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TemplateAudit {
+    /// The number of tracks audited.
+    pub tracks: usize,
+    /// Files more than one track would be organized into. Every track after the first would be
+    /// numbered, such as `1-01 Title (1).flac`, rather than kept together.
+    pub collisions: Vec<PathCollision>,
+    /// Tracks whose path would be too long, with the path they would be organized into.
+    pub too_long: Vec<(PathBuf, PathBuf)>,
+    /// Tracks that are missing a tag the layout organizes them by, with the names of the
+    /// variables that would be empty, such as `year`.
+    pub missing_fields: Vec<(PathBuf, Vec<String>)>,
+}
+
+impl TemplateAudit {
+    /// Whether every track can be organized by the layout without any problems.
+    pub fn is_clean(&self) -> bool {
+        self.collisions.is_empty() && self.too_long.is_empty() && self.missing_fields.is_empty()
+    }
+}
+
+/// Whether the path is longer than Windows allows, or has a file or folder name longer than
+/// most file systems allow. Both limits are checked on every platform, so the library can be
+/// moved between them.
+fn exceeds_path_limits(path: &Path) -> bool {
+    path.to_string_lossy().chars().count() > MAX_PATH_LENGTH
+        || path
+            .components()
+            .any(|component| component.as_os_str().to_string_lossy().len() > MAX_NAME_LENGTH)
+}
+
+/// Simulates organizing every track of the library in the library folder by the layout,
+/// without moving any of them, reporting the tracks that would be organized into the same
+/// file, the tracks whose paths would be too long, and the tracks missing tags the layout
+/// organizes them by. Run this before reorganizing the library with a new layout.
+///
+/// Files that differ only in case are the same file on case-insensitive file systems, so they
+/// are reported as collisions.
+pub fn audit_template(
+    template: &LayoutConfig,
+    library_path: &Path,
+    casing: FolderCasing,
+    conn: &Connection,
+) -> Result<TemplateAudit> {
+    let tracks = query_tracks(Bang::new("").unwrap(), conn, None, None)?;
+    let mut audit = TemplateAudit {
+        tracks: tracks.len(),
+        ..TemplateAudit::default()
+    };
+    let mut planned: HashMap<String, PathCollision> = HashMap::new();
+    for track in tracks {
+        let path = paths::get_track_path(&track, library_path, casing, template);
+        if exceeds_path_limits(&path) {
+            audit.too_long.push((track.file_path.clone(), path.clone()));
+        }
+        let empty = layouts::empty_variables(&track, template);
+        if !empty.is_empty() {
+            audit.missing_fields.push((track.file_path.clone(), empty));
+        }
+        planned
+            .entry(path.to_string_lossy().to_lowercase())
+            .or_insert_with(|| PathCollision { path, tracks: Vec::new() })
+            .tracks
+            .push(track.file_path);
+    }
+    audit.collisions = planned.into_values().filter(|collision| collision.tracks.len() > 1).collect();
+    audit.collisions.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(audit)
+}
+
+/// Journals every file operation made by `apply`, so it can be undone.
+pub fn create_file_operation_tables(conn: &Connection) {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS file_operations (
+        OperationId INTEGER PRIMARY KEY AUTOINCREMENT,
+        Operation INTEGER NOT NULL,
+        Query TEXT NOT NULL,
+        Applied TEXT NOT NULL,
+        Undone INTEGER NOT NULL DEFAULT 0
+    );",
+    )
+    .unwrap();
+    create_table_with_foreign_keys(
+        "file_operation_files",
+        "OperationId INTEGER NOT NULL REFERENCES file_operations(OperationId) ON DELETE CASCADE,
+        FromPath TEXT NOT NULL,
+        ToPath TEXT NOT NULL,
+        TrackId TEXT,
+        Source TEXT",
+        conn,
+    )
+    .unwrap();
+}
+
+/// An operation on the files of the tracks matching a query, made by `apply`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileOp {
+    /// Copies the files into the folder, laid out as they are in the library, such as onto a USB
+    /// stick. The tracks are left as they are.
+    CopyTo(PathBuf),
+    /// Moves the files into the folder, laid out as they are in the library. The tracks stay in the
+    /// library at their new paths, along with their statistics.
+    MoveTo(PathBuf),
+    /// Deletes the files, and removes their tracks from the library. The files are kept in the trash
+    /// folder of the application directory until the operation is forgotten, so it can be undone.
+    Delete,
+    /// Copies the files into the folder like `CopyTo`, transcoded into the format of the transcoder.
+    /// Each track is transcoded once, and later exports copy the variant it was transcoded into.
+    /// Files already in the format are copied as they are.
+    ExportAs(PathBuf, Transcoder),
+}
+
+impl FileOp {
+    fn to_i32(&self) -> i32 {
+        match self {
+            FileOp::CopyTo(_) => 0,
+            FileOp::MoveTo(_) => 1,
+            FileOp::Delete => 2,
+            FileOp::ExportAs(_, _) => 3,
+        }
+    }
+}
+
+/// What came of a file operation, or what would come of it for a dry run.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FileOpReport {
+    /// The journaled operation, which `undo` undoes, or `None` for a dry run, or if no file was
+    /// changed.
+    pub operation_id: Option<i64>,
+    /// Every file that was copied, moved or deleted, with where it was copied or moved to, or
+    /// kept in the trash.
+    pub files: Vec<(PathBuf, PathBuf)>,
+    /// The files that were left as they are, since their tracks are locked, or a file is already
+    /// where they would be copied or moved to.
+    pub skipped: Vec<PathBuf>,
+    pub failed: Vec<PathBuf>,
+}
+
+/// Gets the folder files deleted by the operation are kept in.
+fn trash_folder(operation_id: i64) -> PathBuf {
+    let mut trash_path = paths::get_appdata_path();
+    trash_path.push("trash");
+    trash_path.push(operation_id.to_string());
+    trash_path
+}
+
+/// Moves the file, creating the folder it is moved into.
+fn move_into_place(from: &Path, to: &Path) -> std::io::Result<()> {
+    if let Some(folder) = to.parent() {
+        fs::create_dir_all(folder)?;
+    }
+    paths::move_file(from, to)
+}
+
+fn set_track_path(from: &Path, to: &Path, conn: &Connection) -> Result<usize> {
+    conn.execute(
+        "UPDATE tracks SET FilePath = ?2 WHERE FilePath = ?1",
+        &[&from.to_string_lossy().into_owned(), &to.to_string_lossy().into_owned()],
+    )
+}
+
+/// Copies, moves, deletes or exports the files of every track matching the query, or only reports
+/// what would be done if `dry_run` is set. Files are copied and moved to their paths relative to the library,
+/// or into the folder itself if they are outside of the library, and never replace a file.
+/// Locked tracks are never moved or deleted.
+///
+/// Every changed file is journaled as it is changed, so the operation can be undone with `undo`
+/// even if it stopped partway.
+pub fn apply(bang: Bang, op: &FileOp, library_path: &Path, dry_run: bool, conn: &Connection) -> Result<FileOpReport> {
+    let query = bang.to_query().unwrap_or_default();
+    let tracks = query_tracks(bang, conn, None, None)?;
+    let mut report = FileOpReport::default();
+    let operation_id = if dry_run {
+        None
+    } else {
+        conn.execute(
+            "INSERT INTO file_operations(Operation, Query, Applied) VALUES (?1, ?2, datetime('now'))",
+            &[&op.to_i32() as &dyn ToSql, &query],
+        )?;
+        Some(conn.last_insert_rowid())
+    };
+
+    for (index, track) in tracks.iter().enumerate() {
+        let from = &track.file_path;
+        if !matches!(op, FileOp::CopyTo(_) | FileOp::ExportAs(_, _)) && locks::is_locked(track, conn)? {
+            report.skipped.push(from.clone());
+            continue;
+        }
+        let to = match op {
+            FileOp::CopyTo(folder) | FileOp::MoveTo(folder) | FileOp::ExportAs(folder, _) => {
+                let relative = from.strip_prefix(library_path).ok().or_else(|| from.file_name().map(Path::new));
+                match (relative, op) {
+                    (Some(relative), FileOp::ExportAs(_, transcoder)) if !transcoder.is_format_of(track) => {
+                        folder.join(relative).with_extension(&transcoder.format)
+                    }
+                    (Some(relative), _) => folder.join(relative),
+                    (None, _) => {
+                        report.failed.push(from.clone());
+                        continue;
+                    }
+                }
+            }
+            FileOp::Delete => {
+                let file_name = from.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+                trash_folder(operation_id.unwrap_or(0)).join(format!("{}-{}", index, file_name))
+            }
+        };
+        if to.exists() {
+            report.skipped.push(from.clone());
+            continue;
+        }
+        let operation_id = match operation_id {
+            Some(operation_id) => operation_id,
+            None => {
+                report.files.push((from.clone(), to));
+                continue;
+            }
+        };
+
+        let done = match op {
+            FileOp::CopyTo(_) => to
+                .parent()
+                .map_or(Ok(()), fs::create_dir_all)
+                .and_then(|_| fs::copy(from, &to).map(|_| ())),
+            FileOp::MoveTo(_) | FileOp::Delete => move_into_place(from, &to),
+            FileOp::ExportAs(_, transcoder) => {
+                let variant = if transcoder.is_format_of(track) {
+                    Ok(from.clone())
+                } else {
+                    variants::transcode(track, transcoder, conn)
+                };
+                match variant {
+                    Ok(variant) => to
+                        .parent()
+                        .map_or(Ok(()), fs::create_dir_all)
+                        .and_then(|_| fs::copy(variant, &to).map(|_| ())),
+                    Err(_) => {
+                        report.failed.push(from.clone());
+                        continue;
+                    }
+                }
+            }
+        };
+        if done.is_err() {
+            report.failed.push(from.clone());
+            continue;
+        }
+        match op {
+            FileOp::CopyTo(_) | FileOp::ExportAs(_, _) => {}
+            FileOp::MoveTo(_) => {
+                set_track_path(from, &to, conn)?;
+            }
+            FileOp::Delete => remove_track(track, conn),
+        }
+        conn.execute(
+            "INSERT INTO file_operation_files(OperationId, FromPath, ToPath, TrackId, Source) VALUES (?1, ?2, ?3, ?4, ?5)",
+            &[
+                &operation_id as &dyn ToSql,
+                &from.to_string_lossy().into_owned(),
+                &to.to_string_lossy().into_owned(),
+                &track.uuid,
+                &track.source,
+            ],
+        )?;
+        report.files.push((from.clone(), to));
+    }
+
+    match operation_id {
+        Some(operation_id) if report.files.is_empty() => {
+            conn.execute("DELETE FROM file_operations WHERE OperationId = ?1", &[&operation_id])?;
+        }
+        operation_id => report.operation_id = operation_id,
+    }
+    Ok(report)
+}
+
+/// Undoes the operation made by `apply`, removing the copies it made, moving the files it moved
+/// back, or restoring the files it deleted from the trash and adding their tracks again with their
+/// UUIDs. The statistics of deleted tracks are not restored. Files are never moved back over
+/// another file. An operation is only undone once.
+///
+/// The report lists every file put back, with where it was put back from.
+pub fn undo(operation_id: i64, conn: &Connection) -> Result<FileOpReport> {
+    let mut report = FileOpReport::default();
+    let operation = conn
+        .query_row(
+            "SELECT Operation FROM file_operations WHERE OperationId = ?1 AND Undone = 0",
+            &[&operation_id],
+            |row| row.get::<_, i32>(0),
+        )
+        .optional()?;
+    let operation = match operation {
+        Some(operation) => operation,
+        None => return Ok(report),
+    };
+    let mut statement = conn.prepare(
+        "SELECT FromPath, ToPath, TrackId, Source FROM file_operation_files WHERE OperationId = ?1 ORDER BY rowid DESC",
+    )?;
+    let files = statement
+        .query_map(&[&operation_id], |row| {
+            Ok((
+                PathBuf::from(row.get::<_, String>(0)?),
+                PathBuf::from(row.get::<_, String>(1)?),
+                row.get::<_, Option<String>>(2)?,
+                row.get::<_, String>(3)?,
+            ))
+        })?
+        .collect::<Result<Vec<_>>>()?;
+
+    for (from, to, track_id, source) in files {
+        // Copies and exports are removed, while moved and deleted files are put back.
+        if operation == 0 || operation == 3 {
+            match fs::remove_file(&to) {
+                Ok(()) => report.files.push((to, from)),
+                Err(err) if err.kind() == ErrorKind::NotFound => report.skipped.push(to),
+                Err(_) => report.failed.push(to),
+            }
+            continue;
+        }
+        if from.exists() || !to.exists() {
+            report.skipped.push(to);
+            continue;
+        }
+        if move_into_place(&to, &from).is_err() {
+            report.failed.push(to);
+            continue;
+        }
+        if operation == 1 {
+            set_track_path(&to, &from, conn)?;
+        } else {
+            match Track::from_path(&from, Some(&source)) {
+                Ok(track) => {
+                    add_track(&Track { uuid: track_id, ..track }, conn);
+                }
+                Err(_) => {
+                    report.failed.push(to);
+                    continue;
+                }
+            }
+        }
+        report.files.push((to, from));
+    }
+    fs::remove_dir(trash_folder(operation_id)).ok();
+    conn.execute("UPDATE file_operations SET Undone = 1 WHERE OperationId = ?1", &[&operation_id])?;
+    report.operation_id = Some(operation_id);
+    Ok(report)
+}
+
+/// Forgets the operation made by `apply`, so it can no longer be undone, emptying the files it
+/// deleted from the trash.
+pub fn forget_operation(operation_id: i64, conn: &Connection) -> Result<()> {
+    fs::remove_dir_all(trash_folder(operation_id)).ok();
+    conn.execute("DELETE FROM file_operations WHERE OperationId = ?1", &[&operation_id])?;
+    Ok(())
+}
+
+/// Journals every find-and-replace made by `replace`, so it can be undone.
+pub fn create_tag_replacement_tables(conn: &Connection) {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS tag_replacements (
+        ReplacementId INTEGER PRIMARY KEY AUTOINCREMENT,
+        Tag TEXT NOT NULL,
+        Query TEXT NOT NULL,
+        Applied TEXT NOT NULL,
+        Undone INTEGER NOT NULL DEFAULT 0
+    );",
+    )
+    .unwrap();
+    create_table_with_foreign_keys(
+        "tag_replacement_tracks",
+        "ReplacementId INTEGER NOT NULL REFERENCES tag_replacements(ReplacementId) ON DELETE CASCADE,
+        TrackId TEXT,
+        Before TEXT NOT NULL,
+        After TEXT NOT NULL",
+        conn,
+    )
+    .unwrap();
+}
+
+/// A tag `replace` finds and replaces text in, which is written back to the files of tracks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplaceField {
+    Title,
+    Artist,
+    Album,
+    /// Every album artist of a track, each of which is replaced in on its own.
+    AlbumArtists,
+}
+
+impl ReplaceField {
+    /// Gets the tag with the given name, as hooks name it, such as `title` or `album_artists`.
+    pub fn from_name(name: &str) -> Option<ReplaceField> {
+        match name.to_lowercase().as_str() {
+            "title" => Some(ReplaceField::Title),
+            "artist" => Some(ReplaceField::Artist),
+            "album" => Some(ReplaceField::Album),
+            "album_artists" | "albumartists" => Some(ReplaceField::AlbumArtists),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ReplaceField::Title => "title",
+            ReplaceField::Artist => "artist",
+            ReplaceField::Album => "album",
+            ReplaceField::AlbumArtists => "album_artists",
+        }
+    }
+
+    fn values(self, track: &Track) -> Vec<String> {
+        match self {
+            ReplaceField::Title => vec![track.title.clone()],
+            ReplaceField::Artist => vec![track.artist.clone()],
+            ReplaceField::Album => vec![track.album.clone()],
+            ReplaceField::AlbumArtists => track.album_artists.clone(),
+        }
+    }
+
+    /// Splits the values of the tag as they are journaled, separated by `;` like album artists are.
+    fn split(self, value: &str) -> Vec<String> {
+        match self {
+            ReplaceField::AlbumArtists => value.split(';').map(|artist| artist.to_owned()).collect(),
+            _ => vec![value.to_owned()],
+        }
+    }
+
+    fn update(self, mut values: Vec<String>) -> TagUpdate {
+        let mut update = TagUpdate::default();
+        match self {
+            ReplaceField::Title => update.title = values.pop(),
+            ReplaceField::Artist => update.artist = values.pop(),
+            ReplaceField::Album => update.album = values.pop(),
+            ReplaceField::AlbumArtists => update.album_artists = Some(values),
+        }
+        update
+    }
+}
+
+/// What `replace` finds in tags.
+#[derive(Debug, Clone)]
+pub enum ReplacePattern {
+    /// Text found as it is written, including its case.
+    Literal(String),
+    /// A regular expression, whose groups can be named in the replacement, such as `$1`.
+    Regex(Regex),
+}
+
+impl ReplacePattern {
+    /// Compiles the regular expression into a pattern.
+    pub fn regex(pattern: &str) -> std::result::Result<ReplacePattern, regex::Error> {
+        Regex::new(pattern).map(ReplacePattern::Regex)
+    }
+
+    fn replace(&self, value: &str, replacement: &str) -> String {
+        match self {
+            ReplacePattern::Literal(literal) if literal.is_empty() => value.to_owned(),
+            ReplacePattern::Literal(literal) => value.replace(literal.as_str(), replacement),
+            ReplacePattern::Regex(regex) => regex.replace_all(value, replacement).into_owned(),
+        }
+    }
+}
+
+/// A tag of a track changed by `replace`, with its values separated by `;` if it has many.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TagReplacement {
+    pub file_path: PathBuf,
+    pub before: String,
+    pub after: String,
+}
+
+/// What came of a find-and-replace, or what would come of it for a dry run.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReplaceReport {
+    /// The journaled replacement, which `undo_replace` undoes, or `None` for a dry run, or if no
+    /// tag was changed.
+    pub replacement_id: Option<i64>,
+    pub changes: Vec<TagReplacement>,
+    /// The tracks whose tags were left as they are, since they are locked, or their tag was
+    /// changed again since it was replaced.
+    pub skipped: Vec<PathBuf>,
+    pub failed: Vec<PathBuf>,
+}
+
+/// Writes the values of the tag to the file of the track, and updates the track as it is read
+/// again, keeping its UUID.
+fn write_tag(track: &Track, field: ReplaceField, values: Vec<String>, conn: &Connection) -> bool {
+    let written = track
+        .write_tags(&field.update(values))
+        .and_then(|_| Track::from_path(&track.file_path, Some(&track.source)));
+    match written {
+        Ok(written) => {
+            add_track(&Track { uuid: track.uuid.clone(), ..written }, conn);
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+/// Replaces the pattern in the tag of every track matching the query with the replacement, such
+/// as a mis-spelled artist, or only reports what would be changed if `dry_run` is set. Changed
+/// tags are written back to the files of the tracks. Locked tracks are never changed.
+///
+/// Every changed tag is journaled as it is changed, so the replacement can be undone with
+/// `undo_replace` even if it stopped partway.
+pub fn replace(
+    bang: Bang,
+    field: ReplaceField,
+    pattern: &ReplacePattern,
+    replacement: &str,
+    dry_run: bool,
+    conn: &Connection,
+) -> Result<ReplaceReport> {
+    let query = bang.to_query().unwrap_or_default();
+    let tracks = query_tracks(bang, conn, None, None)?;
+    let mut report = ReplaceReport::default();
+    let replacement_id = if dry_run {
+        None
+    } else {
+        conn.execute(
+            "INSERT INTO tag_replacements(Tag, Query, Applied) VALUES (?1, ?2, datetime('now'))",
+            &[&field.name(), &query.as_str()],
+        )?;
+        Some(conn.last_insert_rowid())
+    };
+
+    for track in tracks {
+        let before = field.values(&track);
+        let after = before
+            .iter()
+            .map(|value| pattern.replace(value, replacement))
+            .collect::<Vec<String>>();
+        if after == before {
+            continue;
+        }
+        if locks::is_locked(&track, conn)? {
+            report.skipped.push(track.file_path);
+            continue;
+        }
+        let change = TagReplacement {
+            file_path: track.file_path.clone(),
+            before: before.join(";"),
+            after: after.join(";"),
+        };
+        if let Some(replacement_id) = replacement_id {
+            if !write_tag(&track, field, after, conn) {
+                report.failed.push(track.file_path);
+                continue;
+            }
+            conn.execute(
+                "INSERT INTO tag_replacement_tracks(ReplacementId, TrackId, Before, After) VALUES (?1, ?2, ?3, ?4)",
+                &[&replacement_id as &dyn ToSql, &track.uuid, &change.before, &change.after],
+            )?;
+        }
+        report.changes.push(change);
+    }
+
+    match replacement_id {
+        Some(replacement_id) if report.changes.is_empty() => {
+            conn.execute("DELETE FROM tag_replacements WHERE ReplacementId = ?1", &[&replacement_id])?;
+        }
+        replacement_id => report.replacement_id = replacement_id,
+    }
+    Ok(report)
+}
+
+/// Undoes the replacement made by `replace`, writing back the tags as they were before it. Tags
+/// changed again since, and tracks locked since, are left as they are. A replacement is only
+/// undone once.
+pub fn undo_replace(replacement_id: i64, conn: &Connection) -> Result<ReplaceReport> {
+    let mut report = ReplaceReport::default();
+    let field = conn
+        .query_row(
+            "SELECT Tag FROM tag_replacements WHERE ReplacementId = ?1 AND Undone = 0",
+            &[&replacement_id],
+            |row| row.get::<_, String>(0),
+        )
+        .optional()?;
+    let field = match field.as_deref().and_then(ReplaceField::from_name) {
+        Some(field) => field,
+        None => return Ok(report),
+    };
+    let mut statement = conn.prepare(
+        "SELECT TrackId, Before, After FROM tag_replacement_tracks WHERE ReplacementId = ?1 ORDER BY rowid DESC",
+    )?;
+    let changes = statement
+        .query_map(&[&replacement_id], |row| {
+            Ok((row.get::<_, Option<String>>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?))
+        })?
+        .collect::<Result<Vec<_>>>()?;
+
+    for (track_id, before, after) in changes {
+        let track = match track_id {
+            Some(track_id) => get_track_by_uuid(&track_id, conn)?,
+            None => None,
+        };
+        // Tracks removed from the library since have no file to write back to.
+        let track = match track {
+            Some(track) => track,
+            None => continue,
+        };
+        if field.values(&track).join(";") != after || locks::is_locked(&track, conn)? {
+            report.skipped.push(track.file_path);
+            continue;
+        }
+        if !write_tag(&track, field, field.split(&before), conn) {
+            report.failed.push(track.file_path);
+            continue;
+        }
+        report.changes.push(TagReplacement {
+            file_path: track.file_path,
+            before: after,
+            after: before,
+        });
+    }
+    conn.execute("UPDATE tag_replacements SET Undone = 1 WHERE ReplacementId = ?1", &[&replacement_id])?;
+    report.replacement_id = Some(replacement_id);
+    Ok(report)
+}
@@ -0,0 +1,93 @@
+//! Locks on tracks and albums whose paths and tags the user maintains by hand.
+//!
+//! Automation leaves locked tracks alone: reconsidering a track neither moves it nor reads
+//! its tags again, and the library is not canonicalized over it. A track is locked by its UUID,
+//! so the lock follows the track if it is moved by hand, and an album is locked by its title
+//! and album artists, so tracks added to the album later are locked too.
+
+use crate::database::create_table_with_foreign_keys;
+use katatsuki::Track;
+use rusqlite::types::ToSql;
+use rusqlite::{Connection, Result, NO_PARAMS};
+
+pub fn create_lock_tables(conn: &Connection) {
+    create_table_with_foreign_keys(
+        "track_locks",
+        "TrackId TEXT PRIMARY KEY REFERENCES tracks(TrackId) ON DELETE CASCADE",
+        conn,
+    )
+    .unwrap();
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS album_locks (
+        Album TEXT NOT NULL COLLATE NOCASE,
+        AlbumArtists TEXT NOT NULL COLLATE NOCASE,
+        PRIMARY KEY (Album, AlbumArtists)
+    );",
+    )
+    .unwrap();
+}
+
+/// An SQL condition that holds for the tracks of the tracks table that are locked.
+pub(crate) const LOCKED_CONDITION: &str = "(TrackId IN (SELECT TrackId FROM track_locks)
+    OR EXISTS (SELECT 1 FROM album_locks WHERE album_locks.Album = tracks.Album
+        AND album_locks.AlbumArtists = tracks.AlbumArtists))";
+
+/// Locks the track with the given UUID. Returns whether there is such a track.
+pub fn lock_track(uuid: &str, conn: &Connection) -> Result<bool> {
+    let locked = conn.execute(
+        "INSERT OR IGNORE INTO track_locks(TrackId) SELECT TrackId FROM tracks WHERE TrackId = ?1",
+        &[uuid],
+    )?;
+    Ok(locked > 0 || is_track_locked(uuid, conn)?)
+}
+
+pub fn unlock_track(uuid: &str, conn: &Connection) -> Result<()> {
+    conn.execute("DELETE FROM track_locks WHERE TrackId = ?1", &[uuid])?;
+    Ok(())
+}
+
+/// Locks every track of the album, including tracks added to it later.
+pub fn lock_album(album: &str, album_artists: &[String], conn: &Connection) -> Result<()> {
+    conn.execute(
+        "INSERT OR IGNORE INTO album_locks(Album, AlbumArtists) VALUES (?1, ?2)",
+        &[&album as &dyn ToSql, &album_artists.join(";")],
+    )?;
+    Ok(())
+}
+
+/// Unlocks the album. Tracks of the album that were locked by themselves stay locked.
+pub fn unlock_album(album: &str, album_artists: &[String], conn: &Connection) -> Result<()> {
+    conn.execute(
+        "DELETE FROM album_locks WHERE Album = ?1 AND AlbumArtists = ?2",
+        &[&album as &dyn ToSql, &album_artists.join(";")],
+    )?;
+    Ok(())
+}
+
+fn is_track_locked(uuid: &str, conn: &Connection) -> Result<bool> {
+    conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM track_locks WHERE TrackId = ?1)",
+        &[uuid],
+        |row| row.get(0),
+    )
+}
+
+/// Whether the track is locked, by itself or by its album.
+pub fn is_locked(track: &Track, conn: &Connection) -> Result<bool> {
+    conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM track_locks WHERE TrackId = ?1)
+            OR EXISTS (SELECT 1 FROM album_locks WHERE Album = ?2 AND AlbumArtists = ?3)",
+        &[&track.uuid as &dyn ToSql, &track.album, &track.album_artists.join(";")],
+        |row| row.get(0),
+    )
+}
+
+/// Gets the UUIDs of every locked track, whether locked by itself or by its album.
+pub fn get_locked_tracks(conn: &Connection) -> Result<Vec<String>> {
+    let mut statement = conn.prepare(&format!(
+        "SELECT TrackId FROM tracks WHERE {} ORDER BY FilePath",
+        LOCKED_CONDITION
+    ))?;
+    let tracks = statement.query_map(NO_PARAMS, |row| row.get(0))?;
+    tracks.collect()
+}
@@ -0,0 +1,268 @@
+//! The messages of events for people to read, kept apart from their codes for machines, in
+//! catalogs of every language seiri is translated into.
+//!
+//! The catalogs are the files in *seiri-lib/locales*, written in the syntax of
+//! [Fluent](https://projectfluent.org/) so frontends can read them with any Fluent library.
+//! Only messages and their variables are used, and every message is named by the code of its
+//! event, with the arguments of the event as its variables.
+
+use crate::events::Event;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+/// A language seiri is translated into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Locale {
+    #[default]
+    English,
+    Japanese,
+}
+
+impl Locale {
+    pub const ALL: [Locale; 2] = [Locale::English, Locale::Japanese];
+
+    /// The locale of the language tag, such as `ja` or `en-CA`, if seiri is translated into
+    /// its language.
+    pub fn from_tag(tag: &str) -> Option<Locale> {
+        let language = tag.split(['-', '_']).next().unwrap_or_default().to_lowercase();
+        match language.as_str() {
+            "en" => Some(Locale::English),
+            "ja" => Some(Locale::Japanese),
+            _ => None,
+        }
+    }
+
+    pub fn tag(self) -> &'static str {
+        match self {
+            Locale::English => "en",
+            Locale::Japanese => "ja",
+        }
+    }
+
+    fn source(self) -> &'static str {
+        match self {
+            Locale::English => include_str!("../locales/en.ftl"),
+            Locale::Japanese => include_str!("../locales/ja.ftl"),
+        }
+    }
+}
+
+/// The messages of a language, by their names.
+#[derive(Debug, Clone, Default)]
+pub struct Catalog {
+    messages: HashMap<String, String>,
+}
+
+impl Catalog {
+    /// Reads the messages of a Fluent resource. Indented lines continue the message before them,
+    /// and comments, terms and attributes are passed over.
+    pub fn parse(source: &str) -> Catalog {
+        let mut messages = HashMap::new();
+        let mut current: Option<(String, String)> = None;
+        for line in source.lines() {
+            let continued = line.starts_with([' ', '\t']) && !line.trim().is_empty();
+            if continued && !line.trim_start().starts_with('.') {
+                if let Some((_, value)) = current.as_mut() {
+                    if !value.is_empty() {
+                        value.push('\n');
+                    }
+                    value.push_str(line.trim());
+                }
+                continue;
+            }
+            if continued {
+                continue;
+            }
+            if let Some((name, value)) = current.take() {
+                messages.insert(name, value);
+            }
+            if line.starts_with(['#', '-']) {
+                continue;
+            }
+            if let Some((name, value)) = line.split_once('=') {
+                current = Some((name.trim().to_owned(), value.trim().to_owned()));
+            }
+        }
+        if let Some((name, value)) = current {
+            messages.insert(name, value);
+        }
+        Catalog { messages }
+    }
+
+    /// Gets the catalog of the language, which is read once.
+    pub fn of(locale: Locale) -> &'static Catalog {
+        static CATALOGS: [OnceLock<Catalog>; 2] = [OnceLock::new(), OnceLock::new()];
+        let index = Locale::ALL.iter().position(|other| *other == locale).unwrap_or(0);
+        CATALOGS[index].get_or_init(|| Catalog::parse(locale.source()))
+    }
+
+    /// Formats the message with the given name, filling in its variables with the given values.
+    /// A variable without a value is left as it is written.
+    pub fn format(&self, name: &str, variables: &[(&str, &str)]) -> Option<String> {
+        let message = self.messages.get(name)?;
+        let mut formatted = String::with_capacity(message.len());
+        let mut rest = message.as_str();
+        while let Some(start) = rest.find('{') {
+            formatted.push_str(&rest[..start]);
+            let end = match rest[start..].find('}') {
+                Some(end) => start + end,
+                None => break,
+            };
+            let placeable = rest[start + 1..end].trim();
+            let value = match placeable.strip_prefix('$') {
+                Some(variable) => variables.iter().find(|(name, _)| *name == variable).map(|(_, value)| *value),
+                // Braces are written as string literals, such as `{ "{" }`.
+                None => placeable.strip_prefix('"').and_then(|literal| literal.strip_suffix('"')),
+            };
+            formatted.push_str(value.unwrap_or(&rest[start..=end]));
+            rest = &rest[end + 1..];
+        }
+        formatted.push_str(rest);
+        Some(formatted)
+    }
+}
+
+/// The message of the event in the language, falling back to English if it is not translated,
+/// and to the event as it is written to stderr if it has no message.
+pub fn describe(event: &Event, locale: Locale) -> String {
+    let args = event.args();
+    let variables = event
+        .arg_names()
+        .iter()
+        .zip(args.iter())
+        .map(|(name, value)| (*name, value.as_ref()))
+        .collect::<Vec<(&str, &str)>>();
+    Catalog::of(locale)
+        .format(event.code(), &variables)
+        .or_else(|| Catalog::of(Locale::English).format(event.code(), &variables))
+        .unwrap_or_else(|| event.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An event of every variant, in the order of their declaration.
+    fn every_event() -> Vec<Event> {
+        let s = || String::from("x");
+        vec![
+            Event::TrackAdded { artist: s(), title: s(), uuid: s() },
+            Event::BatchImported { imported: 1, total: 1 },
+            Event::AlbumIncomplete(s()),
+            Event::SidecarAdded(s()),
+            Event::ArchiveUnpacked(s(), s()),
+            Event::RipImported(s(), s()),
+            Event::ImportVetoed(s(), s()),
+            Event::FileIgnored(s()),
+            Event::TracksRemoved { path: s(), count: 1 },
+            Event::FilesReconciled { files: 1 },
+            Event::LeaseChanged { holder: s(), previous: s() },
+            Event::LeaseLapsed(s()),
+            Event::LeaseLost(s()),
+            Event::TrackMoveError(s()),
+            Event::PermissionsError(s()),
+            Event::CreateDirectoryError(s()),
+            Event::TrackError(s()),
+            Event::NonTrack(s()),
+            Event::MissingTag(s(), "x"),
+            Event::HookError(s(), s()),
+            Event::LibraryNotFound(s()),
+            Event::WatcherError(s()),
+            Event::WatcherDied(s()),
+            Event::WatcherNoAccess(s()),
+            Event::WatcherRestart(s()),
+            Event::WatcherRestarting { restarts: 1, delay: 1 },
+            Event::WatcherDegraded { restarts: 1 },
+            Event::ConfigInvalid(s()),
+            Event::ConfigIOError(s()),
+            Event::AnalysisError(s()),
+            Event::WebhookError(s()),
+            Event::SyncError(s()),
+            Event::HttpError(s()),
+            Event::ArchiveError(s()),
+            Event::RipRejected(s(), s()),
+            Event::ReportError(s()),
+            Event::BatchShed { files: 1 },
+            Event::DatabaseConflict(s()),
+            Event::WaitingForDownload(s()),
+            Event::TrackNotFound(s()),
+            Event::CastTargetNotFound(s()),
+            Event::NotSubscribed(s()),
+            Event::ReplicaNotFound(s()),
+            Event::InvalidCommand(s()),
+            Event::NoProvenance(s()),
+            Event::InvalidFieldValue(s(), s()),
+            Event::LinkError(s(), s()),
+            Event::NotLinked(s(), s()),
+        ]
+    }
+
+    /// The position of the variant of the event in `every_event`. A variant added to `Event`
+    /// fails to compile here until it is given the next position, and added there too.
+    fn position(event: &Event) -> usize {
+        match event {
+            Event::TrackAdded { .. } => 0,
+            Event::BatchImported { .. } => 1,
+            Event::AlbumIncomplete(_) => 2,
+            Event::SidecarAdded(_) => 3,
+            Event::ArchiveUnpacked(_, _) => 4,
+            Event::RipImported(_, _) => 5,
+            Event::ImportVetoed(_, _) => 6,
+            Event::FileIgnored(_) => 7,
+            Event::TracksRemoved { .. } => 8,
+            Event::FilesReconciled { .. } => 9,
+            Event::LeaseChanged { .. } => 10,
+            Event::LeaseLapsed(_) => 11,
+            Event::LeaseLost(_) => 12,
+            Event::TrackMoveError(_) => 13,
+            Event::PermissionsError(_) => 14,
+            Event::CreateDirectoryError(_) => 15,
+            Event::TrackError(_) => 16,
+            Event::NonTrack(_) => 17,
+            Event::MissingTag(_, _) => 18,
+            Event::HookError(_, _) => 19,
+            Event::LibraryNotFound(_) => 20,
+            Event::WatcherError(_) => 21,
+            Event::WatcherDied(_) => 22,
+            Event::WatcherNoAccess(_) => 23,
+            Event::WatcherRestart(_) => 24,
+            Event::WatcherRestarting { .. } => 25,
+            Event::WatcherDegraded { .. } => 26,
+            Event::ConfigInvalid(_) => 27,
+            Event::ConfigIOError(_) => 28,
+            Event::AnalysisError(_) => 29,
+            Event::WebhookError(_) => 30,
+            Event::SyncError(_) => 31,
+            Event::HttpError(_) => 32,
+            Event::ArchiveError(_) => 33,
+            Event::RipRejected(_, _) => 34,
+            Event::ReportError(_) => 35,
+            Event::BatchShed { .. } => 36,
+            Event::DatabaseConflict(_) => 37,
+            Event::WaitingForDownload(_) => 38,
+            Event::TrackNotFound(_) => 39,
+            Event::CastTargetNotFound(_) => 40,
+            Event::NotSubscribed(_) => 41,
+            Event::ReplicaNotFound(_) => 42,
+            Event::InvalidCommand(_) => 43,
+            Event::NoProvenance(_) => 44,
+            Event::InvalidFieldValue(_, _) => 45,
+            Event::LinkError(_, _) => 46,
+            Event::NotLinked(_, _) => 47,
+        }
+    }
+
+    #[test]
+    fn every_event_has_a_message_in_every_catalog() {
+        let events = every_event();
+        assert!(events.iter().enumerate().all(|(index, event)| position(event) == index));
+        for locale in Locale::ALL {
+            for event in &events {
+                let message = Catalog::of(locale).format(event.code(), &[]);
+                assert!(message.is_some(), "{} has no message in {}", event.code(), locale.tag());
+                // Every variable of the message is an argument of the event.
+                assert!(!describe(event, locale).contains("{ $"), "{} in {}", event.code(), locale.tag());
+            }
+        }
+    }
+}
@@ -0,0 +1,330 @@
+//! Splitting of needledrops, recordings of a whole side of a record in a single file, into a file
+//! for each track at the silences between them.
+//!
+//! Splitting happens in two steps. Cuts are first suggested from the silences of the recording.
+//! They are then confirmed or adjusted and given titles, such as by the user in the frontend, and
+//! passed back to split the recording there. The tracks are written as 24-bit AIFF files, tagged
+//! for the album, and moved into a new folder of the watch folder to be imported like anything
+//! else dropped there, with `Vinyl` as their source. The recording itself is left where it is.
+
+use crate::analysis::decode;
+use crate::config::{Config, NeedledropConfig};
+use crate::downloads::{get_iterative_folder, move_folder_contents, random_name};
+use crate::error::{Error, Result};
+use crate::paths;
+use katatsuki::TagUpdate;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use symphonia::core::audio::SignalSpec;
+
+/// The length in milliseconds of the windows the level of a recording is measured over.
+const WINDOW_MS: u64 = 50;
+
+/// A span of a recording, in milliseconds from its start.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Segment {
+    pub start: u64,
+    pub end: u64,
+}
+
+/// A track to split off a recording.
+#[derive(Debug, Clone)]
+pub struct SplitTrack {
+    pub segment: Segment,
+    pub title: String,
+}
+
+/// The album the tracks split off a recording are tagged with.
+#[derive(Debug, Clone)]
+pub struct SplitAlbum {
+    pub artist: String,
+    pub album: String,
+    pub year: Option<i32>,
+    /// The track number of the first track, such as 5 for the second side of a record with four
+    /// tracks on its first.
+    pub first_track: i32,
+}
+
+/// The level in dBFS of samples with the given sum of squares.
+fn decibels(sum: f64, samples: usize) -> f32 {
+    let rms = (sum / samples.max(1) as f64).sqrt();
+    (20.0 * rms.max(1e-10).log10()) as f32
+}
+
+/// Measures the level of every window of the recording in dBFS.
+fn measure_levels(file_path: &Path) -> Result<Vec<f32>> {
+    let mut levels = Vec::new();
+    let (mut sum, mut samples_in_window, mut frames_in_window) = (0f64, 0usize, 0u64);
+    decode(file_path, |samples, spec| {
+        let channels = spec.channels.count().max(1);
+        let window_frames = (u64::from(spec.rate) * WINDOW_MS / 1000).max(1);
+        for frame in samples.chunks(channels) {
+            sum += frame.iter().map(|sample| f64::from(*sample).powi(2)).sum::<f64>();
+            samples_in_window += frame.len();
+            frames_in_window += 1;
+            if frames_in_window == window_frames {
+                levels.push(decibels(sum, samples_in_window));
+                sum = 0.0;
+                samples_in_window = 0;
+                frames_in_window = 0;
+            }
+        }
+        true
+    })?;
+    if frames_in_window > 0 {
+        levels.push(decibels(sum, samples_in_window));
+    }
+    Ok(levels)
+}
+
+/// Suggests segments from the levels of the windows of a recording, cut at the middle of every
+/// silence long enough to be a gap between tracks, with the silence at either end trimmed.
+fn suggest_from_levels(levels: &[f32], config: &NeedledropConfig) -> Vec<Segment> {
+    // Every run of silent windows, by the window it starts at and the window after it ends.
+    let mut silences = Vec::new();
+    let mut silence_start = None;
+    for (i, level) in levels.iter().enumerate() {
+        match (*level < config.silence_threshold, silence_start) {
+            (true, None) => silence_start = Some(i),
+            (false, Some(start)) => {
+                silences.push((start, i));
+                silence_start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(start) = silence_start {
+        silences.push((start, levels.len()));
+    }
+
+    let start = match silences.first() {
+        Some(&(0, lead_in)) => lead_in,
+        _ => 0,
+    };
+    let end = match silences.last() {
+        Some(&(lead_out, end)) if end == levels.len() => lead_out,
+        _ => levels.len(),
+    };
+    if start >= end {
+        return Vec::new();
+    }
+    let (start, end) = (start as u64 * WINDOW_MS, end as u64 * WINDOW_MS);
+    let mut segments = Vec::new();
+    let mut segment_start = start;
+    for &(silence_start, silence_end) in &silences {
+        let (silence_start, silence_end) = (silence_start as u64 * WINDOW_MS, silence_end as u64 * WINDOW_MS);
+        if silence_start <= start || silence_end >= end || silence_end - silence_start < config.min_silence {
+            continue;
+        }
+        // Cuts that would leave a track too short are passed over, such as for a quiet passage.
+        let cut = (silence_start + silence_end) / 2;
+        if cut - segment_start >= config.min_track && end - cut >= config.min_track {
+            segments.push(Segment { start: segment_start, end: cut });
+            segment_start = cut;
+        }
+    }
+    segments.push(Segment { start: segment_start, end });
+    segments
+}
+
+/// Suggests where to split the recording into tracks, from the silences between them. The silence
+/// before the first track and after the last is left out of the segments.
+pub fn suggest_segments(file_path: &Path, config: &NeedledropConfig) -> Result<Vec<Segment>> {
+    Ok(suggest_from_levels(&measure_levels(file_path)?, config))
+}
+
+/// The sample rate as an 80-bit extended precision number, as AIFF files store it.
+fn extended(sample_rate: u32) -> [u8; 10] {
+    let mut bytes = [0; 10];
+    if sample_rate == 0 {
+        return bytes;
+    }
+    let shift = u64::from(sample_rate).leading_zeros();
+    let exponent = 16383 + 63 - shift as u16;
+    bytes[..2].copy_from_slice(&exponent.to_be_bytes());
+    bytes[2..].copy_from_slice(&(u64::from(sample_rate) << shift).to_be_bytes());
+    bytes
+}
+
+/// Writes a 24-bit PCM AIFF file, filling in the sizes of its header once every frame is written.
+struct AiffWriter {
+    file: BufWriter<File>,
+    channels: u16,
+    frames: u32,
+}
+
+impl AiffWriter {
+    fn create(path: &Path, channels: u16, sample_rate: u32) -> io::Result<AiffWriter> {
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(b"FORM")?;
+        file.write_all(&0u32.to_be_bytes())?;
+        file.write_all(b"AIFFCOMM")?;
+        file.write_all(&18u32.to_be_bytes())?;
+        file.write_all(&channels.to_be_bytes())?;
+        file.write_all(&0u32.to_be_bytes())?;
+        file.write_all(&24u16.to_be_bytes())?;
+        file.write_all(&extended(sample_rate))?;
+        file.write_all(b"SSND")?;
+        file.write_all(&0u32.to_be_bytes())?;
+        // The offset and block size of the sound data, which are not used.
+        file.write_all(&[0; 8])?;
+        Ok(AiffWriter { file, channels, frames: 0 })
+    }
+
+    fn write_frame(&mut self, frame: &[f32]) -> io::Result<()> {
+        for sample in frame {
+            let sample = (sample.clamp(-1.0, 1.0) * 8_388_607.0).round() as i32;
+            self.file.write_all(&sample.to_be_bytes()[1..])?;
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        let data_len = self.frames * u32::from(self.channels) * 3;
+        // Chunks are padded to an even length, which is left out of their own size.
+        let padding = data_len % 2;
+        if padding == 1 {
+            self.file.write_all(&[0])?;
+        }
+        self.file.seek(SeekFrom::Start(4))?;
+        self.file.write_all(&(46 + data_len + padding).to_be_bytes())?;
+        self.file.seek(SeekFrom::Start(22))?;
+        self.file.write_all(&self.frames.to_be_bytes())?;
+        self.file.seek(SeekFrom::Start(42))?;
+        self.file.write_all(&(8 + data_len).to_be_bytes())?;
+        self.file.flush()
+    }
+}
+
+/// Writes the frames of a recording into the file of the track each is in.
+struct Splitter<'a> {
+    tracks: &'a [SplitTrack],
+    files: &'a [PathBuf],
+    /// The track the next frame is in or before.
+    next: usize,
+    writer: Option<AiffWriter>,
+    frame: u64,
+    /// How many of the files were written.
+    written: usize,
+}
+
+impl Splitter<'_> {
+    /// Writes the frames of the buffer, returning whether any frames after them are still needed.
+    fn write(&mut self, samples: &[f32], spec: SignalSpec) -> io::Result<bool> {
+        let channels = spec.channels.count().max(1);
+        let rate = u64::from(spec.rate);
+        for frame in samples.chunks(channels) {
+            // Positions are compared in thousandths of frames, to be exact to the millisecond.
+            let position = self.frame * 1000;
+            while self.next < self.tracks.len() && position >= self.tracks[self.next].segment.end * rate {
+                self.next_track()?;
+            }
+            if self.next == self.tracks.len() {
+                return Ok(false);
+            }
+            if position >= self.tracks[self.next].segment.start * rate {
+                if self.writer.is_none() {
+                    self.writer = Some(AiffWriter::create(&self.files[self.next], channels as u16, spec.rate)?);
+                }
+                if let Some(writer) = &mut self.writer {
+                    writer.write_frame(frame)?;
+                }
+            }
+            self.frame += 1;
+        }
+        Ok(true)
+    }
+
+    /// Finishes the file of the track the frames were in, moving on to the next track.
+    fn next_track(&mut self) -> io::Result<()> {
+        if let Some(writer) = self.writer.take() {
+            writer.finish()?;
+            self.written += 1;
+        }
+        self.next += 1;
+        Ok(())
+    }
+}
+
+/// Writes every track of the recording into its file in a single pass.
+fn write_tracks(file_path: &Path, tracks: &[SplitTrack], files: &[PathBuf]) -> Result<()> {
+    let io_error = || Error::FileIOError(file_path.to_owned());
+    let mut splitter = Splitter {
+        tracks,
+        files,
+        next: 0,
+        writer: None,
+        frame: 0,
+        written: 0,
+    };
+    let mut failed = false;
+    decode(file_path, |samples, spec| {
+        splitter.write(samples, spec).unwrap_or_else(|_| {
+            failed = true;
+            false
+        })
+    })?;
+    if failed {
+        return Err(io_error());
+    }
+    while splitter.next < tracks.len() {
+        splitter.next_track().map_err(|_| io_error())?;
+    }
+    // A track that starts after the end of the recording has nothing to be split into.
+    if splitter.written < tracks.len() {
+        return Err(Error::InvalidSplits(file_path.display().to_string()));
+    }
+    Ok(())
+}
+
+/// Splits the recording into a file for each of the tracks, and moves them into a new folder of
+/// the watch folder to be imported. Returns the folder they were moved into.
+///
+/// The tracks must be in order and must not overlap, though they may leave out parts of the
+/// recording, such as the silences between them. Tags can only be written through TagLib, so
+/// without it the tracks are named for their number and title but are otherwise untagged.
+pub fn split_recording(file_path: &Path, tracks: &[SplitTrack], album: &SplitAlbum, config: &Config) -> Result<PathBuf> {
+    let in_order = tracks.windows(2).all(|pair| pair[0].segment.end <= pair[1].segment.start);
+    if tracks.is_empty() || !in_order || tracks.iter().any(|track| track.segment.start >= track.segment.end) {
+        return Err(Error::InvalidSplits(file_path.display().to_string()));
+    }
+    let (_, auto_add_path) = paths::ensure_music_folder(&config.music_folder)
+        .map_err(|_| Error::UnableToCreateDirectory(config.music_folder.clone()))?;
+
+    let mut split_path = paths::get_appdata_path();
+    split_path.push("needledrops");
+    split_path.push(random_name());
+    fs::create_dir_all(&split_path)
+        .map_err(|_| Error::UnableToCreateDirectory(split_path.to_string_lossy().into_owned()))?;
+    let files = tracks
+        .iter()
+        .enumerate()
+        .map(|(i, track)| {
+            let file_name = format!("{:02} {}", album.first_track + i as i32, track.title);
+            split_path.join(format!("{}.aiff", paths::sanitize_file_name(&file_name)))
+        })
+        .collect::<Vec<PathBuf>>();
+
+    let split = write_tracks(file_path, tracks, &files).and_then(|_| {
+        for (i, (file, track)) in files.iter().zip(tracks).enumerate() {
+            let update = TagUpdate {
+                title: Some(track.title.clone()),
+                artist: Some(album.artist.clone()),
+                album: Some(album.album.clone()),
+                album_artists: Some(vec![album.artist.clone()]),
+                year: album.year,
+                track_number: Some(album.first_track + i as i32),
+            };
+            if let Ok(split_track) = paths::read_track(file, None) {
+                split_track.write_tags(&update).ok();
+            }
+        }
+        let name = paths::sanitize_file_name(&album.album);
+        let import_path = get_iterative_folder(&format!("Vinyl_{}", name), &auto_add_path);
+        move_folder_contents(&split_path, &import_path).map(|_| import_path)
+    });
+    fs::remove_dir_all(&split_path).ok();
+    split
+}
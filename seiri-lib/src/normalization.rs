@@ -0,0 +1,210 @@
+//! Rules that normalize the tags of every track as it is imported, before it is added to the
+//! library, such as trimming whitespace or mapping synonyms of a genre to the genre.
+//!
+//! Rules are configured as `[[normalization]]` tables, each naming its `rule`, and run in the
+//! order they are configured, each seeing the changes of the rules before it:
+//!
+//! ```toml
+//! [[normalization]]
+//! rule = "trim"
+//!
+//! [[normalization]]
+//! rule = "feat"
+//!
+//! [[normalization]]
+//! rule = "genre"
+//! from = ["Hip Hop", "Rap"]
+//! to = "Hip-Hop"
+//!
+//! [[normalization]]
+//! rule = "strip-suffix"
+//! suffix = "[Explicit]"
+//! ```
+//!
+//! Rules run after the post-tag-read hooks, so tracks are moved into the library by their
+//! normalized tags. Like the changes of hooks, they only change the library and never the file.
+//! `preview` shows what each rule would change on tracks without changing them.
+
+use crate::hooks::{apply_changes, Change};
+use katatsuki::Track;
+use regex::Regex;
+use serde_derive::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::slice;
+use std::sync::OnceLock;
+
+fn text_tags() -> Vec<String> {
+    ["title", "artist", "album", "album_artists"].iter().map(|tag| tag.to_string()).collect()
+}
+
+fn credit_tags() -> Vec<String> {
+    ["title", "artist"].iter().map(|tag| tag.to_string()).collect()
+}
+
+fn title_tags() -> Vec<String> {
+    ["title", "album"].iter().map(|tag| tag.to_string()).collect()
+}
+
+/// A rule that normalizes tags of tracks. Tags are named as hooks change them, such as `title`
+/// and `album_artists`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "rule", rename_all = "kebab-case")]
+pub enum NormalizationRule {
+    /// Trims whitespace around the tags, and collapses whitespace within them to single spaces.
+    Trim {
+        #[serde(default = "text_tags")]
+        tags: Vec<String>,
+    },
+    /// Credits featured artists in the tags as `feat.`, rather than `Feat.`, `ft.` or `featuring`.
+    Feat {
+        #[serde(default = "credit_tags")]
+        tags: Vec<String>,
+    },
+    /// Replaces any of the genres with the genre, ignoring case.
+    Genre { from: Vec<String>, to: String },
+    /// Removes the suffix from the end of the tags, ignoring case, such as `[Explicit]`.
+    StripSuffix {
+        suffix: String,
+        #[serde(default = "title_tags")]
+        tags: Vec<String>,
+    },
+}
+
+fn feat_pattern() -> &'static Regex {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    PATTERN.get_or_init(|| Regex::new(r"(?i)(?P<before>[(\[]|\s)(?:feat\.?|ft\.?|featuring)\s+").unwrap())
+}
+
+/// Gets the values of the tag of the track, or `None` if it is not a text tag.
+fn tag_values(track: &Track, tag: &str) -> Option<Vec<String>> {
+    match tag {
+        "title" => Some(vec![track.title.clone()]),
+        "artist" => Some(vec![track.artist.clone()]),
+        "album" => Some(vec![track.album.clone()]),
+        "album_artists" => Some(track.album_artists.clone()),
+        "genres" => Some(track.genres.clone()),
+        _ => None,
+    }
+}
+
+/// Replaces every value of each of the tags of the track with its normalized value, returning
+/// the changes made.
+fn normalize_tags<F>(track: &mut Track, tags: &[String], normalize: F) -> Vec<Change>
+where
+    F: Fn(&str) -> String,
+{
+    let mut changes = Vec::new();
+    for tag in tags {
+        let values = match tag_values(track, tag) {
+            Some(values) => values,
+            None => continue,
+        };
+        let normalized = values.iter().map(|value| normalize(value)).collect::<Vec<String>>();
+        if normalized != values {
+            let change = (tag.to_owned(), normalized.join(";"));
+            apply_changes(track, slice::from_ref(&change));
+            changes.push(change);
+        }
+    }
+    changes
+}
+
+impl NormalizationRule {
+    /// The name of the rule, as configured.
+    pub fn name(&self) -> &'static str {
+        match self {
+            NormalizationRule::Trim { .. } => "trim",
+            NormalizationRule::Feat { .. } => "feat",
+            NormalizationRule::Genre { .. } => "genre",
+            NormalizationRule::StripSuffix { .. } => "strip-suffix",
+        }
+    }
+
+    /// Normalizes the tags of the track by the rule, returning the changes made.
+    pub fn apply(&self, track: &mut Track) -> Vec<Change> {
+        match self {
+            NormalizationRule::Trim { tags } => normalize_tags(track, tags, |value| {
+                value.split_whitespace().collect::<Vec<&str>>().join(" ")
+            }),
+            NormalizationRule::Feat { tags } => normalize_tags(track, tags, |value| {
+                feat_pattern().replace_all(value, "${before}feat. ").into_owned()
+            }),
+            NormalizationRule::Genre { from, to } => {
+                let genres = track
+                    .genres
+                    .iter()
+                    .map(|genre| {
+                        if from.iter().any(|synonym| synonym.trim().eq_ignore_ascii_case(genre.trim())) {
+                            to.trim().to_owned()
+                        } else {
+                            genre.to_owned()
+                        }
+                    })
+                    .fold(Vec::<String>::new(), |mut genres, genre| {
+                        if !genres.iter().any(|other| other.eq_ignore_ascii_case(&genre)) {
+                            genres.push(genre);
+                        }
+                        genres
+                    });
+                if genres == track.genres {
+                    return Vec::new();
+                }
+                let change = ("genres".to_owned(), genres.join(";"));
+                apply_changes(track, slice::from_ref(&change));
+                vec![change]
+            }
+            NormalizationRule::StripSuffix { suffix, tags } => {
+                let suffix = suffix.trim().to_lowercase();
+                let length = suffix.chars().count();
+                normalize_tags(track, tags, |value| {
+                    let trimmed = value.trim_end();
+                    let start = trimmed.char_indices().rev().nth(length.saturating_sub(1)).map(|(start, _)| start);
+                    match start {
+                        Some(start) if length > 0 && trimmed[start..].to_lowercase() == suffix => {
+                            trimmed[..start].trim_end().to_owned()
+                        }
+                        _ => value.to_owned(),
+                    }
+                })
+            }
+        }
+    }
+}
+
+/// Normalizes the tags of the track by every rule in order, returning the changes made.
+pub fn normalize(track: &mut Track, rules: &[NormalizationRule]) -> Vec<Change> {
+    rules.iter().flat_map(|rule| rule.apply(track)).collect()
+}
+
+/// A change a rule would make to a tag of a track.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NormalizationChange {
+    /// The index of the rule among the configured rules.
+    pub rule: usize,
+    pub file_path: PathBuf,
+    pub tag: String,
+    /// The value of the tag before the rule, with multiple values separated by `;`.
+    pub before: String,
+    pub after: String,
+}
+
+/// Gets every change the rules would make to the tracks, without changing them.
+pub fn preview(tracks: &[Track], rules: &[NormalizationRule]) -> Vec<NormalizationChange> {
+    let mut previewed = Vec::new();
+    for track in tracks {
+        let mut track = track.clone();
+        for (index, rule) in rules.iter().enumerate() {
+            let before = track.clone();
+            for (tag, after) in rule.apply(&mut track) {
+                previewed.push(NormalizationChange {
+                    rule: index,
+                    file_path: track.file_path.clone(),
+                    before: tag_values(&before, &tag).unwrap_or_default().join(";"),
+                    tag,
+                    after,
+                });
+            }
+        }
+    }
+    previewed
+}
@@ -0,0 +1,46 @@
+//! Private notes on tracks, such as where a track was ripped from.
+//!
+//! Unlike the comment tag, a note is only kept in the library and never written to the file,
+//! so it survives the track being retagged. A note is kept by the UUID of its track, so it
+//! follows the track when it is moved, and is removed along with the track.
+
+use crate::database::create_table_with_foreign_keys;
+use rusqlite::{Connection, OptionalExtension, Result};
+
+pub fn create_note_table(conn: &Connection) {
+    create_table_with_foreign_keys(
+        "track_notes",
+        "TrackId TEXT PRIMARY KEY REFERENCES tracks(TrackId) ON DELETE CASCADE,
+        Note TEXT NOT NULL",
+        conn,
+    )
+    .unwrap();
+}
+
+/// Sets the note of the track with the given UUID, replacing any note it had.
+/// An empty note removes the note. Returns whether there is such a track.
+pub fn set_note(uuid: &str, note: &str, conn: &Connection) -> Result<bool> {
+    if note.trim().is_empty() {
+        remove_note(uuid, conn)?;
+        return conn.query_row("SELECT EXISTS (SELECT 1 FROM tracks WHERE TrackId = ?1)", &[uuid], |row| {
+            row.get(0)
+        });
+    }
+    let set = conn.execute(
+        "INSERT INTO track_notes(TrackId, Note) SELECT TrackId, ?2 FROM tracks WHERE TrackId = ?1
+        ON CONFLICT(TrackId) DO UPDATE SET Note = excluded.Note",
+        &[uuid, note],
+    )?;
+    Ok(set > 0)
+}
+
+/// Gets the note of the track with the given UUID, if it has one.
+pub fn get_note(uuid: &str, conn: &Connection) -> Result<Option<String>> {
+    conn.query_row("SELECT Note FROM track_notes WHERE TrackId = ?1", &[uuid], |row| row.get(0))
+        .optional()
+}
+
+pub fn remove_note(uuid: &str, conn: &Connection) -> Result<()> {
+    conn.execute("DELETE FROM track_notes WHERE TrackId = ?1", &[uuid])?;
+    Ok(())
+}
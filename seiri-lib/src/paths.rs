@@ -1,11 +1,17 @@
 use app_dirs::*;
 use chrono::prelude::*;
+use crate::config::{LayoutConfig, PermissionsConfig, RequiredTagsConfig, TagFallback};
 use crate::error::{Error, Result};
+use crate::hooks::{apply_changes, Change};
+use crate::layouts;
 use katatsuki::Track;
 // use tree_magic;
+use std::cell::RefCell;
 use std::fs;
+use std::fs::{File, FileTimes, OpenOptions};
 use std::io;
-use std::io::ErrorKind;
+use std::io::{ErrorKind, Read, Seek, SeekFrom};
+use serde_derive::{Deserialize, Serialize};
 use std::path::{Path, PathBuf};
 
 trait InvalidChar {
@@ -21,45 +27,219 @@ impl InvalidChar for char {
     }
 }
 
-pub fn new_track_checked(track_path: &Path, source: Option<&str>) -> Result<Track> {
+/// Reads up to `buf.len()` bytes, stopping early only at the end of the file.
+fn read_up_to(file: &mut File, buf: &mut [u8]) -> io::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match file.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(ref err) if err.kind() == ErrorKind::Interrupted => (),
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(read)
+}
+
+/// Guesses the extension of an audio file from its magic bytes,
+/// returning every extension TagLib reads the format from, preferred extension first.
+fn sniff_extensions(track_path: &Path) -> Option<&'static [&'static str]> {
+    let mut file = File::open(track_path).ok()?;
+    let mut header = [0u8; 64];
+    let mut len = read_up_to(&mut file, &mut header).ok()?;
+
+    // FLAC and MP3 files may begin with an ID3v2 tag, which is skipped past.
+    let has_id3 = len >= 10 && &header[0..3] == b"ID3";
+    if has_id3 {
+        let size = header[6..10]
+            .iter()
+            .fold(0u64, |size, &b| (size << 7) | u64::from(b & 0x7f));
+        let footer = if header[5] & 0x10 != 0 { 10 } else { 0 };
+        file.seek(SeekFrom::Start(10 + size + footer)).ok()?;
+        len = read_up_to(&mut file, &mut header).ok()?;
+    }
+    let header = &header[..len];
 
-    // let mimetype = tree_magic::from_filepath(track_path);
-    // if !mimetype.starts_with("audio") {
-    //     return Err(Error::UnsupportedFile(track_path.to_owned()));
-    // } 
+    if header.starts_with(b"fLaC") {
+        Some(&["flac"])
+    } else if header.starts_with(b"OggS") {
+        // The first packet of the first page identifies the codec,
+        // and begins after the page header and its segment table.
+        let page = header.get(27 + usize::from(*header.get(26)?)..)?;
+        if page.starts_with(b"OpusHead") {
+            Some(&["opus"])
+        } else if page.starts_with(b"\x01vorbis") {
+            Some(&["ogg"])
+        } else if page.starts_with(b"\x7fFLAC") {
+            Some(&["oga"])
+        } else {
+            None
+        }
+    } else if header.len() >= 12 && &header[4..8] == b"ftyp" {
+        Some(&["m4a", "mp4", "m4b"])
+    } else if header.len() >= 12 && &header[0..4] == b"FORM" && matches!(&header[8..12], b"AIFF" | b"AIFC") {
+        Some(&["aiff", "aif"])
+    } else if header.starts_with(b"MAC ") {
+        Some(&["ape"])
+    } else if header.len() >= 2 && header[0] == 0xff && header[1] & 0xe0 == 0xe0 && header[1] & 0x06 != 0 {
+        // An MPEG audio frame sync with a layer set, which rules out ADTS AAC.
+        Some(&["mp3"])
+    } else if has_id3 {
+        // ID3v2 tags are otherwise almost always followed by MP3 frames,
+        // possibly after some padding.
+        Some(&["mp3"])
+    } else {
+        None
+    }
+}
+
+/// Renames a file whose extension does not match its contents to the extension it should have.
+/// Returns the new path of the file, or `None` if it was not renamed.
+fn correct_extension(track_path: &Path) -> Option<PathBuf> {
+    let extensions = sniff_extensions(track_path)?;
+    let current = track_path
+        .extension()
+        .and_then(|s| s.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+    if extensions.contains(&current.as_str()) {
+        return None;
+    }
+    let file_name = track_path.file_stem().and_then(|s| s.to_str())?;
+    let corrected = get_iterative_filename(file_name, extensions[0], track_path.parent()?);
+    fs::rename(track_path, &corrected).ok()?;
+    Some(corrected)
+}
+
+/// Reads the track at the given path, without checking its tags.
+///
+/// If the file is not recognized as a track, its contents are sniffed in case it is a
+/// track with the wrong extension, in which case it is renamed to the correct extension.
+pub fn read_track(track_path: &Path, source: Option<&str>) -> Result<Track> {
     match Track::from_path(track_path, source) {
-        Ok(track) => {
-            if track.title.is_empty() {
-                return Err(Error::MissingRequiredTag(
-                    track_path.to_str().unwrap().to_owned(),
-                    "Title",
-                ));
-            }
-            if track.artist.is_empty() {
-                return Err(Error::MissingRequiredTag(
-                    track_path.to_str().unwrap().to_owned(),
-                    "Artist",
-                ));
+        Ok(track) => Ok(track),
+        Err(ioerror) => match ioerror.kind() {
+            ErrorKind::InvalidData => match correct_extension(track_path) {
+                Some(corrected) => read_track(&corrected, source),
+                None => Err(Error::UnsupportedFile(PathBuf::from(track_path))),
+            },
+            _ => Err(Error::FileIOError(PathBuf::from(track_path))),
+        },
+    }
+}
+
+/// Whether the track has at least one non-empty album artist.
+pub fn has_album_artists(track: &Track) -> bool {
+    track.album_artists.iter().any(|artist| !artist.trim().is_empty())
+}
+
+/// Ensures the track has all of the tags required to be imported.
+pub fn check_required_tags(track: &Track) -> Result<()> {
+    let missing = if track.title.is_empty() {
+        "Title"
+    } else if track.artist.is_empty() {
+        "Artist"
+    } else if track.album.is_empty() {
+        "Album"
+    } else if track.album_artists.is_empty() {
+        "AlbumArtists"
+    } else {
+        return Ok(());
+    };
+    Err(Error::MissingRequiredTag(
+        track.file_path.to_string_lossy().into_owned(),
+        missing,
+    ))
+}
+
+/// Reads the track at the given path, ensuring it has all of the tags required to be imported.
+///
+/// See `read_track` for how files with the wrong extension are handled.
+pub fn new_track_checked(track_path: &Path, source: Option<&str>) -> Result<Track> {
+    let track = read_track(track_path, source)?;
+    check_required_tags(&track)?;
+    Ok(track)
+}
+
+/// Splits the stem of the file name of the track into the number it starts with, if any, and
+/// the rest, such as `3` and `Title` for `03 - Title.flac`.
+fn split_file_stem(track: &Track) -> (Option<i32>, String) {
+    let stem = track.file_path.file_stem().map(|stem| stem.to_string_lossy()).unwrap_or_default();
+    let digits = stem.chars().take_while(|c| c.is_ascii_digit()).count();
+    let number = stem[..digits].parse::<i32>().ok().filter(|number| *number > 0);
+    let rest = match number {
+        Some(_) => stem[digits..].trim_start_matches(|c: char| c.is_whitespace() || "-_.".contains(c)),
+        None => &stem,
+    };
+    // A file name that is only a number is a title rather than a track number.
+    match rest.trim() {
+        "" => (None, stem.trim().to_owned()),
+        rest => (number, rest.to_owned()),
+    }
+}
+
+/// Fills in the tags of the track that are missing and not required, as configured, then ensures
+/// it has every tag required to be imported.
+///
+/// Returns the changes made to the tags, to be made again once the track is read from its file.
+pub fn fill_required_tags(track: &mut Track, required: &RequiredTagsConfig) -> Result<Vec<Change>> {
+    let mut changes = Vec::new();
+    let mut fill = |track: &mut Track, tag: &str, value: String| {
+        let change = (tag.to_owned(), value);
+        apply_changes(track, std::slice::from_ref(&change));
+        changes.push(change);
+    };
+    let (file_number, file_title) = split_file_stem(track);
+    if track.title.trim().is_empty() {
+        match required.title {
+            TagFallback::Infer if !file_title.is_empty() => fill(track, "title", file_title),
+            TagFallback::Allow => fill(track, "title", "Untitled".to_owned()),
+            _ => (),
+        }
+    }
+    if track.artist.trim().is_empty() {
+        match required.artist {
+            TagFallback::Infer if has_album_artists(track) => {
+                let artist = track.album_artists.join(", ");
+                fill(track, "artist", artist)
             }
-            if track.album.is_empty() {
-                return Err(Error::MissingRequiredTag(
-                    track_path.to_str().unwrap().to_owned(),
-                    "Album",
-                ));
+            TagFallback::Allow => fill(track, "artist", "Unknown Artist".to_owned()),
+            _ => (),
+        }
+    }
+    if !has_album_artists(track) {
+        match required.album_artists {
+            TagFallback::Infer if !track.artist.trim().is_empty() => {
+                let artist = track.artist.clone();
+                fill(track, "album_artists", artist)
             }
-            if track.album_artists.len() == 0 {
-                return Err(Error::MissingRequiredTag(
-                    track_path.to_str().unwrap().to_owned(),
-                    "AlbumArtists",
-                ));
+            TagFallback::Allow => fill(track, "album_artists", "Unknown Artist".to_owned()),
+            _ => (),
+        }
+    }
+    if track.album.trim().is_empty() {
+        match required.album {
+            TagFallback::Infer if !track.title.trim().is_empty() => {
+                let album = track.title.clone();
+                fill(track, "album", album)
             }
-            Ok(track)
+            TagFallback::Allow => fill(track, "album", "Unknown Album".to_owned()),
+            _ => (),
         }
-        Err(ioerror) => match ioerror.kind() {
-            ErrorKind::InvalidData => Err(Error::UnsupportedFile(PathBuf::from(track_path))),
-            _ => Err(Error::FileIOError(PathBuf::from(track_path))),
-        },
     }
+    if track.track_number <= 0 {
+        if let (TagFallback::Infer, Some(number)) = (required.track_number, file_number) {
+            fill(track, "track_number", number.to_string());
+        }
+    }
+    check_required_tags(track)?;
+    if track.track_number <= 0 && required.track_number != TagFallback::Allow {
+        return Err(Error::MissingRequiredTag(
+            track.file_path.to_string_lossy().into_owned(),
+            "TrackNumber",
+        ));
+    }
+    Ok(changes)
 }
 
 /// Gets the application data path.
@@ -92,24 +272,52 @@ pub fn ensure_music_folder(folder_path: &str) -> io::Result<(PathBuf, PathBuf)>
     Ok((music_folder, auto_add_folder))
 }
 
-fn sanitize_file_name(path: &str) -> String {
+pub(crate) fn sanitize_file_name(path: &str) -> String {
     path.replace(|c: char| c.is_invalid_for_path(), "_").trim_end_matches('.').to_string()
 }
 
-pub fn get_track_directory(track: &Track, library_path: &Path) -> PathBuf {
-    let mut track_path = PathBuf::from(library_path);
+/// How the artist and album folders of tracks are cased, when a folder
+/// whose name differs only in case already exists.
+///
+/// On case-insensitive file systems such folders are the same folder,
+/// so its name would otherwise not match the paths of the tracks in it.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum FolderCasing {
+    /// Use the casing of the existing folder, so tracks tagged "Abba" go into "ABBA".
+    #[default]
+    Existing,
+    /// Always case folders as the tags are.
+    Canonical,
+}
 
-    let artist_folder = if track.album_artists.len() > 0 {
-        track.album_artists.join(", ")
-    } else {
-        (&track.album_artists[0]).to_owned()
-    };
+/// Finds the name of the folder within `parent` that matches `name` ignoring case,
+/// preferring an exact match.
+fn find_existing_case(parent: &Path, name: &str) -> Option<String> {
+    let folders = fs::read_dir(parent)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .collect::<Vec<String>>();
+    if folders.iter().any(|folder| folder == name) {
+        return Some(name.to_owned());
+    }
+    let name = name.to_lowercase();
+    folders.into_iter().find(|folder| folder.to_lowercase() == name)
+}
 
-    let artist_folder = artist_folder.trim();
-    let album_folder = &track.album.to_owned();
-    let album_folder = album_folder.trim();
-    track_path.push(sanitize_file_name(&artist_folder));
-    track_path.push(sanitize_file_name(&album_folder));
+/// Gets the folder of the track in the library, from the layout for its media type.
+pub fn get_track_directory(track: &Track, library_path: &Path, casing: FolderCasing, layouts: &LayoutConfig) -> PathBuf {
+    let mut track_path = PathBuf::from(library_path);
+    for folder in layouts::track_folders(track, layouts) {
+        let folder = sanitize_file_name(&folder);
+        let folder = match casing {
+            FolderCasing::Existing => find_existing_case(&track_path, &folder).unwrap_or(folder),
+            FolderCasing::Canonical => folder,
+        };
+        track_path.push(folder);
+    }
     track_path
 }
 
@@ -121,12 +329,18 @@ fn get_track_filename(track: &Track) -> String {
     sanitize_file_name(file_name)
 }
 
-fn get_iterative_filename(filename: &str, extension: &str, destination: &Path) -> PathBuf {
+pub(crate) fn get_iterative_filename(filename: &str, extension: &str, destination: &Path) -> PathBuf {
+    get_iterative_filename_except(filename, extension, destination, Path::new(""))
+}
+
+/// Gets a name for the file in the destination like `get_iterative_filename`, taking the file at
+/// `except` to be free, which is the file being named itself.
+fn get_iterative_filename_except(filename: &str, extension: &str, destination: &Path, except: &Path) -> PathBuf {
     let mut new_path = PathBuf::from(destination);
     let mut counter = 0;
     new_path.push(format!("{}.{}", filename, extension));
 
-    while new_path.exists() {
+    while new_path.exists() && new_path != except {
         counter += 1;
         new_path.pop();
         new_path.push(format!("{} ({}).{}", filename, counter, extension))
@@ -155,7 +369,7 @@ fn get_source(track_file_path: &Path, relative_to: &Path) -> String {
     }
 }
 
-fn ensure_not_added(auto_add_path: &Path) -> io::Result<PathBuf> {
+pub(crate) fn ensure_not_added(auto_add_path: &Path) -> io::Result<PathBuf> {
     let mut not_added = PathBuf::from(auto_add_path);
     let local: DateTime<Local> = Local::now();
     not_added.push(".notadded");
@@ -166,60 +380,404 @@ fn ensure_not_added(auto_add_path: &Path) -> io::Result<PathBuf> {
     }
 }
 
-pub fn move_non_track(path: &Path, auto_add_path: &Path) -> Result<()> {
+/// Moves the file into the not added folder of today, returning its new path.
+pub fn move_non_track(path: &Path, auto_add_path: &Path) -> Result<PathBuf> {
     if let Ok(notadded) = ensure_not_added(auto_add_path) {
         let ext = path.extension().and_then(|s| s.to_str()).unwrap_or("");
         let filename = path.file_stem()
             .and_then(|s| s.to_str())
             .unwrap_or("unnamed file");
         let new_file_name = get_iterative_filename(filename, ext, &notadded);
-        if let Err(_) = fs::rename(path, &new_file_name) {
+        if move_file(path, &new_file_name).is_err() {
             return Err(Error::UnableToMove(
                 new_file_name.to_string_lossy().into_owned(),
             ));
         } else {
-            return Ok(());
+            return Ok(new_file_name);
         }
     }
     Err(Error::UnableToMove("not added folder".to_owned()))
 }
 
-fn track_warrants_move(track_as_saved: &Track, track_as_read: &Track) -> bool {
-    !(track_as_saved.title == track_as_read.title && track_as_saved.album == track_as_read.album
-        && track_as_saved.artist == track_as_read.artist
-        && track_as_saved.track_number == track_as_read.track_number
-        && track_as_saved.album_artists == track_as_read.album_artists)
+/// Whether the folder holds nothing but hidden files, such as `.DS_Store`.
+fn is_effectively_empty(folder: &Path) -> bool {
+    match fs::read_dir(folder) {
+        Ok(entries) => entries.filter_map(|entry| entry.ok()).all(|entry| {
+            entry.file_type().map(|file_type| file_type.is_file()).unwrap_or(false)
+                && is_hidden_file(&entry.path())
+        }),
+        Err(_) => false,
+    }
+}
+
+/// Removes the folder if it holds nothing but hidden files, then each of its parents left empty,
+/// up to but never including the watch folder. Returns how many folders were removed.
+///
+/// Folders directly in the watch folder named for a source, such as `Bandcamp`, are kept
+/// to be dropped into again, while folders such as `Bandcamp_Album` are removed.
+pub fn remove_empty_folders(folder: &Path, auto_add_path: &Path) -> usize {
+    let mut removed = 0;
+    let mut folder = folder;
+    while folder != auto_add_path
+        && folder.starts_with(auto_add_path)
+        && !is_in_hidden_path(folder, auto_add_path)
+    {
+        let is_source_folder = folder.parent() == Some(auto_add_path)
+            && !folder.file_name().is_some_and(|name| name.to_string_lossy().contains('_'));
+        if is_source_folder || !is_effectively_empty(folder) || fs::remove_dir_all(folder).is_err() {
+            break;
+        }
+        removed += 1;
+        folder = match folder.parent() {
+            Some(parent) => parent,
+            None => break,
+        };
+    }
+    removed
+}
+
+/// Removes the folders of the not added folder for days more than the given number of days ago,
+/// returning the folders that were removed.
+pub fn purge_not_added(auto_add_path: &Path, days: u32) -> io::Result<Vec<PathBuf>> {
+    let not_added = auto_add_path.join(".notadded");
+    if !not_added.exists() {
+        return Ok(Vec::new());
+    }
+    let oldest_kept = Local::now().naive_local().date() - chrono::Duration::days(i64::from(days));
+    let mut purged = Vec::new();
+    for entry in fs::read_dir(&not_added)? {
+        let path = entry?.path();
+        let date = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| NaiveDate::parse_from_str(name, "%Y-%m-%d").ok());
+        match date {
+            Some(date) if date < oldest_kept && path.is_dir() => {
+                fs::remove_dir_all(&path)?;
+                purged.push(path);
+            }
+            _ => (),
+        }
+    }
+    Ok(purged)
+}
+
+/// Moves a sidecar file into the album folder of a track found in the same folder as it.
+///
+/// Returns the new path of the sidecar, or `Ok(None)` if there is no track
+/// alongside it to determine the album from.
+pub fn move_sidecar(
+    path: &Path,
+    library_path: &Path,
+    casing: FolderCasing,
+    layouts: &LayoutConfig,
+    permissions: &PermissionsConfig,
+) -> Result<Option<PathBuf>> {
+    // Sibling tracks are only read, since they may be waiting to be imported themselves.
+    let sibling_track = path
+        .parent()
+        .and_then(|parent| fs::read_dir(parent).ok())
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|sibling| sibling != path && sibling.is_file() && !is_hidden_file(sibling))
+        .filter_map(|sibling| Track::from_path(&sibling, None).ok())
+        .find(|track| !track.album.is_empty() && !track.album_artists.is_empty());
+
+    let track = match sibling_track {
+        Some(track) => track,
+        None => return Ok(None),
+    };
+
+    let new_path = move_into_folder(path, &get_track_directory(&track, library_path, casing, layouts))?;
+    apply_permissions_or_undo(path, &new_path, library_path, permissions)?;
+    Ok(Some(new_path))
+}
+
+/// Sets the times of a copied file to those of the original,
+/// including its creation time on platforms that can set it.
+fn copy_file_times(from: &Path, to: &Path) -> io::Result<()> {
+    let metadata = fs::metadata(from)?;
+    #[allow(unused_mut)]
+    let mut times = FileTimes::new()
+        .set_accessed(metadata.accessed()?)
+        .set_modified(metadata.modified()?);
+    #[cfg(any(target_os = "macos", windows))]
+    {
+        #[cfg(target_os = "macos")]
+        use std::os::darwin::fs::FileTimesExt;
+        #[cfg(windows)]
+        use std::os::windows::fs::FileTimesExt;
+        if let Ok(created) = metadata.created() {
+            times = times.set_created(created);
+        }
+    }
+    OpenOptions::new().write(true).open(to)?.set_times(times)
+}
+
+/// Copies a file along with its permissions, times, and extended attributes such as Finder tags.
+#[cfg(target_os = "macos")]
+fn copy_file_with_metadata(from: &Path, to: &Path) -> io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+    let from_c = CString::new(from.as_os_str().as_bytes())?;
+    let to_c = CString::new(to.as_os_str().as_bytes())?;
+    let flags = libc::COPYFILE_DATA | libc::COPYFILE_METADATA;
+    if unsafe { libc::copyfile(from_c.as_ptr(), to_c.as_ptr(), std::ptr::null_mut(), flags) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // copyfile does not keep the creation time.
+    copy_file_times(from, to)
+}
+
+/// Gets the ID of the user, given by name or ID.
+#[cfg(unix)]
+fn user_id(user: &str) -> io::Result<u32> {
+    use std::ffi::CString;
+    if let Ok(id) = user.parse() {
+        return Ok(id);
+    }
+    let name = CString::new(user)?;
+    let mut passwd = unsafe { std::mem::zeroed::<libc::passwd>() };
+    let mut buffer = vec![0 as libc::c_char; 16384];
+    let mut found = std::ptr::null_mut();
+    let code = unsafe { libc::getpwnam_r(name.as_ptr(), &mut passwd, buffer.as_mut_ptr(), buffer.len(), &mut found) };
+    if code != 0 {
+        return Err(io::Error::from_raw_os_error(code));
+    }
+    if found.is_null() {
+        return Err(io::Error::new(ErrorKind::NotFound, format!("There is no user named {}", user)));
+    }
+    Ok(passwd.pw_uid)
+}
+
+/// Gets the ID of the group, given by name or ID.
+#[cfg(unix)]
+fn group_id(group: &str) -> io::Result<u32> {
+    use std::ffi::CString;
+    if let Ok(id) = group.parse() {
+        return Ok(id);
+    }
+    let name = CString::new(group)?;
+    let mut entry = unsafe { std::mem::zeroed::<libc::group>() };
+    let mut buffer = vec![0 as libc::c_char; 16384];
+    let mut found = std::ptr::null_mut();
+    let code = unsafe { libc::getgrnam_r(name.as_ptr(), &mut entry, buffer.as_mut_ptr(), buffer.len(), &mut found) };
+    if code != 0 {
+        return Err(io::Error::from_raw_os_error(code));
+    }
+    if found.is_null() {
+        return Err(io::Error::new(ErrorKind::NotFound, format!("There is no group named {}", group)));
+    }
+    Ok(entry.gr_gid)
+}
+
+/// Gives a file moved into the library, and the folders between it and the library, the
+/// configured permissions and ownership.
+#[cfg(unix)]
+pub fn apply_permissions(path: &Path, library_path: &Path, permissions: &PermissionsConfig) -> io::Result<()> {
+    use std::os::unix::fs::{chown, PermissionsExt};
+    let owner = permissions.owner.as_deref().map(user_id).transpose()?;
+    let group = permissions.group.as_deref().map(group_id).transpose()?;
+    let folders = path
+        .ancestors()
+        .skip(1)
+        .take_while(|folder| *folder != library_path && folder.starts_with(library_path));
+    // Ownership is changed first, since changing it may clear the setuid and setgid bits of the mode.
+    for folder in folders {
+        if owner.is_some() || group.is_some() {
+            chown(folder, owner, group)?;
+        }
+        if let Some(mode) = permissions.folder_mode {
+            fs::set_permissions(folder, fs::Permissions::from_mode(mode))?;
+        }
+    }
+    if owner.is_some() || group.is_some() {
+        chown(path, owner, group)?;
+    }
+    if let Some(mode) = permissions.file_mode {
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    }
+    Ok(())
+}
+
+/// Files keep their permissions on platforms other than Unix.
+#[cfg(not(unix))]
+pub fn apply_permissions(_path: &Path, _library_path: &Path, _permissions: &PermissionsConfig) -> io::Result<()> {
+    Ok(())
+}
+
+/// Applies the configured permissions to a file moved into the library from `from`. If they
+/// can not be applied, the file is moved back, so it is imported once the permissions can be.
+pub(crate) fn apply_permissions_or_undo(from: &Path, to: &Path, library_path: &Path, permissions: &PermissionsConfig) -> Result<()> {
+    if apply_permissions(to, library_path, permissions).is_ok() {
+        return Ok(());
+    }
+    if move_file(to, from).is_ok() {
+        if let Some(folder) = to.parent() {
+            // Only an empty folder can be removed.
+            fs::remove_dir(folder).ok();
+        }
+    }
+    Err(Error::UnableToSetPermissions(to.to_string_lossy().into_owned()))
+}
+
+/// Copies a file along with its permissions and times.
+#[cfg(not(target_os = "macos"))]
+fn copy_file_with_metadata(from: &Path, to: &Path) -> io::Result<()> {
+    fs::copy(from, to)?;
+    copy_file_times(from, to)
+}
+
+/// Moves a file, copying it if the destination is on another volume.
+///
+/// A copied file keeps what a renamed file would, that is its creation and modification times,
+/// and on macOS, its extended attributes, so files sorted by date added in the file manager
+/// stay in order.
+pub fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    let result = match fs::rename(from, to) {
+        Err(err) if err.kind() == ErrorKind::CrossesDevices => {
+            if let Err(err) = copy_file_with_metadata(from, to) {
+                fs::remove_file(to).unwrap_or(());
+                return Err(err);
+            }
+            fs::remove_file(from)
+        }
+        result => result,
+    };
+    if result.is_ok() {
+        journal_move(from, to);
+    }
+    result
+}
+
+/// Records the move in the `MoveJournal` of this thread, if one is kept, for files moved
+/// other than by `move_file`.
+pub(crate) fn journal_move(from: &Path, to: &Path) {
+    MOVES.with(|moves| {
+        if let Some(moves) = moves.borrow_mut().as_mut() {
+            moves.push((from.to_owned(), to.to_owned()));
+        }
+    });
+}
+
+thread_local! {
+    /// The files moved on this thread while a `MoveJournal` is kept, from and to where.
+    static MOVES: RefCell<Option<Vec<(PathBuf, PathBuf)>>> = const { RefCell::new(None) };
+}
+
+/// Keeps track of the files moved on this thread by `move_file`, so they can be moved back
+/// if the changes to the library made alongside them are rolled back.
+pub struct MoveJournal(());
+
+impl MoveJournal {
+    pub fn start() -> MoveJournal {
+        MOVES.with(|moves| *moves.borrow_mut() = Some(Vec::new()));
+        MoveJournal(())
+    }
+
+    /// Stops keeping track of moves, returning the moves made since the journal was started.
+    pub fn finish(self) -> Vec<(PathBuf, PathBuf)> {
+        MOVES.with(|moves| moves.borrow_mut().take()).unwrap_or_default()
+    }
+}
+
+impl Drop for MoveJournal {
+    fn drop(&mut self) {
+        MOVES.with(|moves| *moves.borrow_mut() = None);
+    }
+}
+
+/// Moves the files back to where they were, in the reverse order they were moved in,
+/// removing the folders they were moved into if they are left empty.
+/// Returns the files that could not be moved back.
+pub fn undo_moves(moves: &[(PathBuf, PathBuf)]) -> Vec<PathBuf> {
+    let mut failed = Vec::new();
+    for (from, to) in moves.iter().rev() {
+        if move_file(to, from).is_err() {
+            failed.push(to.clone());
+            continue;
+        }
+        if let Some(folder) = to.parent() {
+            // Only an empty folder can be removed.
+            fs::remove_dir(folder).ok();
+        }
+    }
+    failed
+}
+
+/// Moves a file into the given folder, keeping its name unless a file with that name already exists.
+pub fn move_into_folder(path: &Path, folder: &Path) -> Result<PathBuf> {
+    if fs::create_dir_all(folder).is_err() {
+        return Err(Error::UnableToCreateDirectory(
+            folder.to_string_lossy().into_owned(),
+        ));
+    }
+
+    let ext = path.extension().and_then(|s| s.to_str()).unwrap_or("");
+    let filename = path.file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("unnamed file");
+    let new_file_name = get_iterative_filename(filename, ext, folder);
+    if move_file(path, &new_file_name).is_err() {
+        return Err(Error::UnableToMove(
+            new_file_name.to_string_lossy().into_owned(),
+        ));
+    }
+    Ok(new_file_name)
+}
+
+fn is_hidden_file(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|s| s.to_str())
+        .map(|s| s.starts_with('.'))
+        .unwrap_or(false)
 }
 
 /// Reconsider the location of a track.
 /// If the file is gone or deleted, returns Ok(None).
 /// Otherwise, returns a new Track that has a new
 /// or same location, depending if its properties have changed.
-pub fn reconsider_track(track: &Track, library_path: &Path) -> Result<Option<Track>> {
+/// The new Track keeps the UUID of the track, and its missing tags are filled in as they were
+/// when it was imported.
+pub fn reconsider_track(
+    track: &Track,
+    library_path: &Path,
+    casing: FolderCasing,
+    layouts: &LayoutConfig,
+    required: &RequiredTagsConfig,
+) -> Result<Option<Track>> {
     let track_file_path = Path::new(&track.file_path);
     if !track_file_path.exists() {
         return Ok(None);
     }
 
-    match new_track_checked(track_file_path, Some(&track.source)) {
-        Ok(track_as_read) => {
-            if !track_warrants_move(track, &track_as_read) {
-                return Ok(Some(track_as_read));
-            }
+    let filled = read_track(track_file_path, Some(&track.source)).and_then(|mut track_as_read| {
+        let changes = fill_required_tags(&mut track_as_read, required)?;
+        Ok((track_as_read, changes))
+    });
+    match filled {
+        Ok((track_as_read, changes)) => {
             let track_as_read = Track {
+                uuid: track.uuid.clone(),
                 file_path: track.file_path.to_owned(),
                 ..track_as_read
             };
-            println!("{:?}", track_as_read);
-            match move_track(&track_as_read, library_path, &track_as_read.source) {
-                Ok(track) => {
+            // The track is left where it is if its tags still place it there, whichever tags
+            // the layout of the track is made of.
+            if get_track_destination(&track_as_read, library_path, casing, layouts) == track_as_read.file_path {
+                return Ok(Some(track_as_read));
+            }
+            match move_track(&track_as_read, library_path, &track_as_read.source, casing, layouts) {
+                Ok(mut track) => {
+                    apply_changes(&mut track, &changes);
                     //  Cleanup
-                    if let Some(old_dir) = &track_file_path.parent() {
-                        // If the directory is empty, simply remove it.
-                        fs::remove_dir(old_dir).unwrap_or(());
-                        if let Some(old_dir) = old_dir.parent() {
-                            // Cleanup after the artist as well.
-                            fs::remove_dir(old_dir).unwrap_or(());
+                    // Remove the folders of the track left empty, up to the music folder,
+                    // since layouts can nest tracks any number of folders deep.
+                    for old_dir in track_file_path.ancestors().skip(1) {
+                        if old_dir == library_path || !old_dir.starts_with(library_path) || fs::remove_dir(old_dir).is_err() {
+                            break;
                         }
                     }
                     Ok(Some(track))
@@ -233,7 +791,14 @@ pub fn reconsider_track(track: &Track, library_path: &Path) -> Result<Option<Tra
 
 /// Moves the given track to its proper destination in the library, relative
 /// to the Automatically Add to Library path.
-pub fn move_new_track(track: &Track, library_path: &Path, auto_add_path: &Path) -> Result<Track> {
+pub fn move_new_track(
+    track: &Track,
+    library_path: &Path,
+    auto_add_path: &Path,
+    casing: FolderCasing,
+    layouts: &LayoutConfig,
+    permissions: &PermissionsConfig,
+) -> Result<Track> {
     // The original path where the track was found.
     let original_path = Path::new(&track.file_path);
 
@@ -241,61 +806,293 @@ pub fn move_new_track(track: &Track, library_path: &Path, auto_add_path: &Path)
     // and marks it as the source.
     let source = get_source(original_path, auto_add_path);
 
-    move_track(track, library_path, &source)
+    let moved = move_track(track, library_path, &source, casing, layouts)?;
+    apply_permissions_or_undo(original_path, &moved.file_path, library_path, permissions)?;
+    Ok(moved)
+}
+
+/// Moves a track of an album to its proper destination in the library, relative
+/// to the Automatically Add to Library path.
+///
+/// Unlike `move_new_track`, the tags of the moved file are not read again,
+/// so the album metadata shared by the rest of the album is kept.
+pub fn move_album_track(
+    track: &Track,
+    library_path: &Path,
+    auto_add_path: &Path,
+    casing: FolderCasing,
+    layouts: &LayoutConfig,
+    permissions: &PermissionsConfig,
+) -> Result<Track> {
+    let source = get_source(&track.file_path, auto_add_path);
+    let new_file_name = move_track_file(track, library_path, casing, layouts)?;
+    apply_permissions_or_undo(&track.file_path, &new_file_name, library_path, permissions)?;
+    Ok(Track {
+        file_path: new_file_name,
+        source,
+        ..track.clone()
+    })
 }
 
 /// Moves a track to its proper position in the library, with the given source.
-pub fn move_track(track: &Track, library_path: &Path, source: &str) -> Result<Track> {
-    let track_file_path = Path::new(&track.file_path);
+/// The moved track keeps the UUID of the track, and is read again from the moved file,
+/// so its tags must be checked before it is moved.
+pub fn move_track(
+    track: &Track,
+    library_path: &Path,
+    source: &str,
+    casing: FolderCasing,
+    layouts: &LayoutConfig,
+) -> Result<Track> {
+    let new_file_name = move_track_file(track, library_path, casing, layouts)?;
+    Ok(Track {
+        uuid: track.uuid.clone(),
+        ..read_track(&new_file_name, Some(source))?
+    })
+}
 
-    // get the track file extension
-    let track_ext = {
-        if !track_file_path
+/// Gets the extension of the file of a track.
+fn track_extension(track_file_path: &Path) -> String {
+    if !track_file_path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or(".")
+        .starts_with(".")
+    {
+        track_file_path
+            .extension()
+            .and_then(|s| s.to_str())
+            .unwrap_or("")
+            .to_owned()
+    } else {
+        // Handle dotfiles.
+        track_file_path
             .file_stem()
             .and_then(|s| s.to_str())
-            .unwrap_or(".")
-            .starts_with(".")
-        {
-            Path::new(&track.file_path)
-                .extension()
-                .and_then(|s| s.to_str())
-                .unwrap_or("")
-                .to_owned()
-        } else {
-            // Handle dotfiles.
-            track_file_path
-                .file_stem()
-                .and_then(|s| s.to_str())
-                .unwrap()
-                .trim_start_matches('.')
-                .to_owned()
-        }
-    };
+            .unwrap()
+            .trim_start_matches('.')
+            .to_owned()
+    }
+}
+
+/// Gets the path the track is moved to in the library, if no other file is already there.
+pub fn get_track_path(track: &Track, library_path: &Path, casing: FolderCasing, layouts: &LayoutConfig) -> PathBuf {
+    let mut track_path = get_track_directory(track, library_path, casing, layouts);
+    track_path.push(format!("{}.{}", get_track_filename(track), track_extension(&track.file_path)));
+    track_path
+}
+
+/// Gets the path the file of the track is moved to in the library, without overwriting any
+/// other file. This is the path of the file itself if it is already where it belongs.
+fn get_track_destination(track: &Track, library_path: &Path, casing: FolderCasing, layouts: &LayoutConfig) -> PathBuf {
+    let track_file_path = Path::new(&track.file_path);
+
+    // get the track file extension
+    let track_ext = track_extension(track_file_path);
 
     // The new filename of the track, from the track metadata.
-    let track_file_name = get_track_filename(&track);
+    let track_file_name = get_track_filename(track);
 
     // The new directory of the track in the library, from track metadata
-    let track_folder = get_track_directory(&track, &library_path);
+    let track_folder = get_track_directory(track, library_path, casing, layouts);
+
+    // Make sure not to overwrite any files.
+    get_iterative_filename_except(&track_file_name, &track_ext, &track_folder, track_file_path)
+}
+
+/// Moves the file of a track to its proper position in the library, returning its new path.
+fn move_track_file(track: &Track, library_path: &Path, casing: FolderCasing, layouts: &LayoutConfig) -> Result<PathBuf> {
+    let track_file_path = Path::new(&track.file_path);
+    let new_file_name = get_track_destination(track, library_path, casing, layouts);
+    if new_file_name == track_file_path {
+        return Ok(new_file_name);
+    }
 
     // Ensure the new directory
-    if let Err(_) = fs::create_dir_all(&track_folder) {
+    let track_folder = new_file_name.parent().unwrap_or(library_path);
+    if fs::create_dir_all(track_folder).is_err() {
         return Err(Error::UnableToCreateDirectory(
             track_folder.to_string_lossy().into_owned(),
         ));
     }
 
-    // Make sure not to overwrite any files.
-    let new_file_name = get_iterative_filename(&track_file_name, &track_ext, &track_folder);
-
     // Do the move.
-    if let Err(err) = fs::rename(track_file_path, &new_file_name) {
-        println!("{}", err);
-        println!("{:?}", track_file_path);
+    if move_file(track_file_path, &new_file_name).is_err() {
         Err(Error::UnableToMove(
             new_file_name.to_string_lossy().into_owned(),
         ))
     } else {
-        new_track_checked(&new_file_name, Some(&source))
+        Ok(new_file_name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::ScratchFolder;
+    use katatsuki::TrackFileType;
+
+    fn track(title: &str) -> Track {
+        Track::builder("", TrackFileType::FLAC16)
+            .title(title.to_owned())
+            .artist("Alpha".to_owned())
+            .album_artists(vec!["Alpha".to_owned()])
+            .album("First".to_owned())
+            .disc_number(1)
+            .track_number(1)
+            .comment(Some("Ripped in 2019".to_owned()))
+            .build()
+    }
+
+    /// Writes the file of the track where the default layout places it in the library.
+    fn place(track: Track, library_path: &Path) -> Track {
+        let named = Track {
+            file_path: "a.flac".into(),
+            ..track.clone()
+        };
+        let file_path = get_track_path(&named, library_path, FolderCasing::default(), &LayoutConfig::default());
+        fs::create_dir_all(file_path.parent().unwrap()).unwrap();
+        fs::write(&file_path, b"track").unwrap();
+        Track { file_path, ..track }
+    }
+
+    #[test]
+    fn leaves_tracks_whose_layout_did_not_change_in_place() {
+        let scratch = ScratchFolder::new();
+        let placed = place(track("Hello"), &scratch.0);
+        // The comment is not part of the layout, so the track stays where it is.
+        let commented = Track {
+            comment: Some("Remastered".to_owned()),
+            ..placed.clone()
+        };
+
+        let moved = move_track_file(&commented, &scratch.0, FolderCasing::default(), &LayoutConfig::default()).unwrap();
+
+        assert_eq!(moved, placed.file_path);
+        assert!(placed.file_path.is_file());
+        assert_eq!(fs::read_dir(placed.file_path.parent().unwrap()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn leaves_tracks_named_around_another_file_in_place() {
+        let scratch = ScratchFolder::new();
+        let placed = place(track("Hello"), &scratch.0);
+        let (stem, folder) = (placed.file_path.file_stem().unwrap(), placed.file_path.parent().unwrap());
+        // Another file took the name of the track, so it was named around it when it was moved.
+        let renamed = folder.join(format!("{} (1).flac", stem.to_string_lossy()));
+        fs::rename(&placed.file_path, &renamed).unwrap();
+        fs::write(&placed.file_path, b"other").unwrap();
+        let track = Track {
+            file_path: renamed.clone(),
+            ..placed
+        };
+
+        let moved = move_track_file(&track, &scratch.0, FolderCasing::default(), &LayoutConfig::default()).unwrap();
+
+        assert_eq!(moved, renamed);
+        assert!(renamed.is_file());
+    }
+
+    #[test]
+    fn moves_tracks_whose_layout_changed() {
+        let scratch = ScratchFolder::new();
+        let placed = place(track("Hello"), &scratch.0);
+        let retitled = Track {
+            album: "Second".to_owned(),
+            ..placed.clone()
+        };
+
+        let moved = move_track_file(&retitled, &scratch.0, FolderCasing::default(), &LayoutConfig::default()).unwrap();
+
+        assert_eq!(moved, scratch.0.join("Alpha").join("Second").join(placed.file_path.file_name().unwrap()));
+        assert!(moved.is_file());
+        assert!(!placed.file_path.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn moves_back_files_that_can_not_be_given_their_permissions() {
+        let scratch = ScratchFolder::new();
+        let library_path = scratch.0.join("library");
+        let from = scratch.0.join("a.flac");
+        let to = library_path.join("Artist").join("a.flac");
+        fs::write(&from, b"track").unwrap();
+        fs::create_dir_all(to.parent().unwrap()).unwrap();
+        move_file(&from, &to).unwrap();
+        let permissions = PermissionsConfig {
+            owner: Some("seiri-no-such-user".to_owned()),
+            ..PermissionsConfig::default()
+        };
+
+        let result = apply_permissions_or_undo(&from, &to, &library_path, &permissions);
+
+        assert!(matches!(result, Err(Error::UnableToSetPermissions(_))));
+        assert!(from.is_file());
+        assert!(!to.parent().unwrap().exists());
+    }
+
+    #[test]
+    fn moves_back_the_files_moved_while_a_journal_was_kept() {
+        let scratch = ScratchFolder::new();
+        let (first, second) = (scratch.0.join("1.flac"), scratch.0.join("2.flac"));
+        let album = scratch.0.join("library").join("Album");
+        fs::create_dir_all(&album).unwrap();
+        fs::write(&first, b"one").unwrap();
+        fs::write(&second, b"two").unwrap();
+
+        let journal = MoveJournal::start();
+        move_file(&first, &album.join("1.flac")).unwrap();
+        move_file(&second, &album.join("2.flac")).unwrap();
+        let moves = journal.finish();
+        // Moves made once the journal is finished are not kept.
+        fs::write(scratch.0.join("3.flac"), b"three").unwrap();
+        move_file(&scratch.0.join("3.flac"), &scratch.0.join("4.flac")).unwrap();
+
+        assert_eq!(moves, [(first.clone(), album.join("1.flac")), (second.clone(), album.join("2.flac"))]);
+        assert!(undo_moves(&moves).is_empty());
+        assert_eq!(fs::read(&first).unwrap(), b"one");
+        assert_eq!(fs::read(&second).unwrap(), b"two");
+        assert!(!album.exists());
+    }
+
+    #[test]
+    fn reports_the_files_that_could_not_be_moved_back() {
+        let scratch = ScratchFolder::new();
+        let (first, second) = (scratch.0.join("1.flac"), scratch.0.join("2.flac"));
+        let album = scratch.0.join("Album");
+        fs::create_dir_all(&album).unwrap();
+        fs::write(&first, b"one").unwrap();
+        fs::write(&second, b"two").unwrap();
+        let journal = MoveJournal::start();
+        move_file(&first, &album.join("1.flac")).unwrap();
+        move_file(&second, &album.join("2.flac")).unwrap();
+        let moves = journal.finish();
+        fs::remove_file(album.join("2.flac")).unwrap();
+        fs::write(album.join("cover.jpg"), b"cover").unwrap();
+
+        assert_eq!(undo_moves(&moves), [album.join("2.flac")]);
+        assert!(first.is_file());
+        assert!(!second.exists());
+        // Folders are only removed once nothing is left in them.
+        assert!(album.join("cover.jpg").is_file());
+    }
+
+    #[test]
+    fn keeps_the_times_of_copied_files() {
+        let scratch = ScratchFolder::new();
+        let (from, to) = (scratch.0.join("1.flac"), scratch.0.join("2.flac"));
+        fs::write(&from, b"one").unwrap();
+        let added = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_546_300_800);
+        OpenOptions::new()
+            .write(true)
+            .open(&from)
+            .unwrap()
+            .set_times(FileTimes::new().set_accessed(added).set_modified(added))
+            .unwrap();
+
+        copy_file_with_metadata(&from, &to).unwrap();
+        assert_eq!(fs::read(&to).unwrap(), b"one");
+        assert_eq!(fs::metadata(&to).unwrap().modified().unwrap(), added);
+        assert!(from.is_file());
     }
 }
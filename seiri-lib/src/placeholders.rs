@@ -0,0 +1,64 @@
+//! Placeholders of files kept in cloud storage, such as OneDrive files on demand or iCloud Drive
+//! files that were evicted, which are listed in the folder but not downloaded yet.
+//!
+//! The tags of a placeholder can not be read until it is downloaded, so the watcher downloads
+//! placeholders before processing them, rather than quarantining them as tracks that could not
+//! be read. Reading a placeholder is what downloads it on both platforms, so it is only read on
+//! a thread of its own. Placeholders are only recognized on Windows and macOS, since the cloud
+//! clients of other platforms download files before they are listed.
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::thread;
+
+/// Files whose data is only in cloud storage.
+#[cfg(windows)]
+const FILE_ATTRIBUTE_OFFLINE: u32 = 0x1000;
+/// Files that are downloaded when opened.
+#[cfg(windows)]
+const FILE_ATTRIBUTE_RECALL_ON_OPEN: u32 = 0x0004_0000;
+/// Files that are downloaded when read, such as OneDrive files on demand.
+#[cfg(windows)]
+const FILE_ATTRIBUTE_RECALL_ON_DATA_ACCESS: u32 = 0x0040_0000;
+
+/// Files whose data is only in cloud storage, such as evicted iCloud Drive files.
+#[cfg(target_os = "macos")]
+const SF_DATALESS: u32 = 0x4000_0000;
+
+/// Whether the file is a placeholder, whose data is not downloaded yet.
+#[cfg(windows)]
+pub fn is_placeholder(path: &Path) -> bool {
+    use std::os::windows::fs::MetadataExt;
+    path.symlink_metadata()
+        .map(|metadata| {
+            metadata.file_attributes()
+                & (FILE_ATTRIBUTE_OFFLINE | FILE_ATTRIBUTE_RECALL_ON_OPEN | FILE_ATTRIBUTE_RECALL_ON_DATA_ACCESS)
+                != 0
+        })
+        .unwrap_or(false)
+}
+
+/// Whether the file is a placeholder, whose data is not downloaded yet.
+#[cfg(target_os = "macos")]
+pub fn is_placeholder(path: &Path) -> bool {
+    use std::os::macos::fs::MetadataExt;
+    path.symlink_metadata()
+        .map(|metadata| metadata.st_flags() & SF_DATALESS != 0)
+        .unwrap_or(false)
+}
+
+/// Whether the file is a placeholder, whose data is not downloaded yet.
+#[cfg(not(any(windows, target_os = "macos")))]
+pub fn is_placeholder(_path: &Path) -> bool {
+    false
+}
+
+/// Starts downloading the placeholder, without waiting for it to be downloaded.
+/// The placeholder is left as it is if its cloud client can not download it right now.
+pub fn hydrate(path: &Path) {
+    let path = path.to_owned();
+    thread::spawn(move || {
+        File::open(path).and_then(|mut file| file.read(&mut [0; 1])).ok();
+    });
+}
@@ -0,0 +1,419 @@
+//! Per-user data for libraries shared between multiple people.
+//!
+//! Profiles are identified by an opaque id chosen by the frontend. Play counts,
+//! ratings, favourites and playlists are all kept separate per profile, while the
+//! tracks themselves are shared. Only tracks in the library can have statistics
+//! or be added to playlists.
+
+use crate::database::create_table_with_foreign_keys;
+use rusqlite::{Connection, OptionalExtension, Result, Transaction, TransactionBehavior, NO_PARAMS};
+use rusqlite::types::ToSql;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub struct Profile {
+    pub profile_id: String,
+    pub name: String,
+}
+
+#[derive(Debug, Default)]
+pub struct TrackStats {
+    pub play_count: i32,
+    /// The rating of the track, from 1 to 5, if the track has been rated.
+    pub rating: Option<i32>,
+    pub favorite: bool,
+    pub last_played: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct Playlist {
+    pub playlist_id: i64,
+    pub profile_id: String,
+    pub name: String,
+}
+
+/// Every statistic and playlist is removed along with its profile, and every statistic
+/// and playlist entry for a track is removed along with the track, or follows it when it moves.
+pub fn create_profile_tables(conn: &Connection) {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS profiles (
+        ProfileId TEXT PRIMARY KEY,
+        Name TEXT
+    );",
+    )
+    .unwrap();
+    create_table_with_foreign_keys(
+        "profile_tracks",
+        "ProfileId TEXT NOT NULL REFERENCES profiles(ProfileId) ON DELETE CASCADE,
+        FilePath TEXT NOT NULL REFERENCES tracks(FilePath) ON DELETE CASCADE ON UPDATE CASCADE,
+        PlayCount INTEGER NOT NULL DEFAULT 0,
+        Rating INTEGER,
+        Favorite INTEGER NOT NULL DEFAULT 0,
+        LastPlayed DATE,
+        PRIMARY KEY (ProfileId, FilePath)",
+        conn,
+    )
+    .unwrap();
+    create_table_with_foreign_keys(
+        "playlists",
+        "PlaylistId INTEGER PRIMARY KEY AUTOINCREMENT,
+        ProfileId TEXT NOT NULL REFERENCES profiles(ProfileId) ON DELETE CASCADE,
+        Name TEXT NOT NULL,
+        UNIQUE (ProfileId, Name)",
+        conn,
+    )
+    .unwrap();
+    create_table_with_foreign_keys(
+        "playlist_tracks",
+        "PlaylistId INTEGER NOT NULL REFERENCES playlists(PlaylistId) ON DELETE CASCADE,
+        Position INTEGER NOT NULL,
+        FilePath TEXT NOT NULL REFERENCES tracks(FilePath) ON DELETE CASCADE ON UPDATE CASCADE,
+        PRIMARY KEY (PlaylistId, Position)",
+        conn,
+    )
+    .unwrap();
+    // Entries after a removed entry move up by one, including entries removed along with their
+    // track. They move through negative positions, so no two entries ever share a position.
+    conn.execute_batch(
+        "CREATE TRIGGER IF NOT EXISTS playlist_tracks_close_gap AFTER DELETE ON playlist_tracks BEGIN
+        UPDATE playlist_tracks SET Position = -Position WHERE PlaylistId = OLD.PlaylistId AND Position > OLD.Position;
+        UPDATE playlist_tracks SET Position = -Position - 1 WHERE PlaylistId = OLD.PlaylistId AND Position < 0;
+    END;",
+    )
+    .unwrap();
+    create_table_with_foreign_keys(
+        "profile_listens",
+        "ProfileId TEXT NOT NULL REFERENCES profiles(ProfileId) ON DELETE CASCADE,
+        FilePath TEXT NOT NULL REFERENCES tracks(FilePath) ON DELETE CASCADE ON UPDATE CASCADE,
+        ListenedAt INTEGER NOT NULL",
+        conn,
+    )
+    .unwrap();
+    conn.execute_batch(
+        "CREATE INDEX IF NOT EXISTS profile_listens_listened_at ON profile_listens(ProfileId, ListenedAt)",
+    )
+    .unwrap();
+    create_modification_times(conn).unwrap();
+}
+
+/// The current time, as an SQL expression of the format modification times are kept in.
+pub(crate) const MODIFIED_NOW: &str = "strftime('%Y-%m-%dT%H:%M:%fZ', 'now')";
+
+/// Records when the statistics of every track and every playlist were last modified,
+/// and which playlists were removed when, so that replicas can tell which changes are newer.
+/// The sync_peers table records how far changes have been exchanged with every replica,
+/// and the synced_changes table which changes were last applied from each replica as they were
+/// made there, so they are not sent back to it.
+///
+/// Modification times are kept by triggers, so every writer keeps them, and a writer
+/// can set the modification time itself by writing it along with its change.
+fn create_modification_times(conn: &Connection) -> Result<()> {
+    let transaction = Transaction::new_unchecked(conn, TransactionBehavior::Immediate)?;
+    for table in &["profile_tracks", "playlists"] {
+        let has_modified = transaction
+            .prepare("SELECT 1 FROM pragma_table_info(?1) WHERE name = 'Modified'")?
+            .exists(&[table])?;
+        if !has_modified {
+            transaction.execute_batch(&format!("ALTER TABLE {} ADD COLUMN Modified TEXT", table))?;
+        }
+    }
+    transaction.execute_batch(&format!(
+        "CREATE TABLE IF NOT EXISTS removed_playlists (
+            ProfileId TEXT NOT NULL,
+            Name TEXT NOT NULL,
+            Modified TEXT NOT NULL,
+            PRIMARY KEY (ProfileId, Name)
+        );
+        CREATE TABLE IF NOT EXISTS sync_peers (
+            Peer TEXT PRIMARY KEY,
+            Pulled TEXT,
+            Pushed TEXT
+        );
+        CREATE TABLE IF NOT EXISTS synced_changes (
+            Peer TEXT NOT NULL,
+            Kind TEXT NOT NULL,
+            ProfileId TEXT NOT NULL,
+            Item TEXT NOT NULL,
+            Modified TEXT NOT NULL,
+            PRIMARY KEY (Peer, Kind, ProfileId, Item)
+        ) WITHOUT ROWID;
+        CREATE TABLE IF NOT EXISTS sync_instance (
+            Id INTEGER PRIMARY KEY CHECK (Id = 0),
+            InstanceId TEXT NOT NULL
+        );
+        CREATE TRIGGER IF NOT EXISTS profile_tracks_modified_insert AFTER INSERT ON profile_tracks
+            WHEN NEW.Modified IS NULL BEGIN
+            UPDATE profile_tracks SET Modified = {now} WHERE rowid = NEW.rowid;
+        END;
+        CREATE TRIGGER IF NOT EXISTS profile_tracks_modified_update AFTER UPDATE ON profile_tracks
+            WHEN NEW.Modified IS OLD.Modified BEGIN
+            UPDATE profile_tracks SET Modified = {now} WHERE rowid = NEW.rowid;
+        END;
+        CREATE TRIGGER IF NOT EXISTS playlists_modified_insert AFTER INSERT ON playlists BEGIN
+            UPDATE playlists SET Modified = IFNULL(NEW.Modified, {now}) WHERE PlaylistId = NEW.PlaylistId;
+            DELETE FROM removed_playlists WHERE ProfileId = NEW.ProfileId AND Name = NEW.Name;
+        END;
+        CREATE TRIGGER IF NOT EXISTS playlists_modified_update AFTER UPDATE ON playlists
+            WHEN NEW.Modified IS OLD.Modified BEGIN
+            UPDATE playlists SET Modified = {now} WHERE PlaylistId = NEW.PlaylistId;
+        END;
+        CREATE TRIGGER IF NOT EXISTS playlists_log_delete AFTER DELETE ON playlists BEGIN
+            INSERT OR REPLACE INTO removed_playlists(ProfileId, Name, Modified)
+                VALUES (OLD.ProfileId, OLD.Name, {now});
+        END;
+        CREATE TRIGGER IF NOT EXISTS playlist_tracks_modified_insert AFTER INSERT ON playlist_tracks BEGIN
+            UPDATE playlists SET Modified = {now} WHERE PlaylistId = NEW.PlaylistId;
+        END;
+        CREATE TRIGGER IF NOT EXISTS playlist_tracks_modified_update AFTER UPDATE ON playlist_tracks BEGIN
+            UPDATE playlists SET Modified = {now} WHERE PlaylistId IN (OLD.PlaylistId, NEW.PlaylistId);
+        END;
+        CREATE TRIGGER IF NOT EXISTS playlist_tracks_modified_delete AFTER DELETE ON playlist_tracks BEGIN
+            UPDATE playlists SET Modified = {now} WHERE PlaylistId = OLD.PlaylistId;
+        END;",
+        now = MODIFIED_NOW
+    ))?;
+    transaction.commit()
+}
+
+fn path_string(file_path: &Path) -> String {
+    file_path.to_string_lossy().into_owned()
+}
+
+/// Adds a profile, or renames it if it already exists.
+pub fn add_profile(profile_id: &str, name: &str, conn: &Connection) -> Result<()> {
+    conn.execute(
+        "INSERT INTO profiles(ProfileId, Name) VALUES (?1, ?2)
+            ON CONFLICT(ProfileId) DO UPDATE SET Name = excluded.Name",
+        &[profile_id, name],
+    )?;
+    Ok(())
+}
+
+/// Removes a profile along with all its statistics and playlists.
+pub fn remove_profile(profile_id: &str, conn: &Connection) -> Result<()> {
+    conn.execute("DELETE FROM profiles WHERE ProfileId = ?1", &[profile_id])?;
+    Ok(())
+}
+
+pub fn get_profiles(conn: &Connection) -> Result<Vec<Profile>> {
+    let mut statement = conn.prepare("SELECT ProfileId, Name FROM profiles ORDER BY Name")?;
+    let mut profiles = Vec::<Profile>::new();
+    let mut rows = statement.query(NO_PARAMS)?;
+    while let Some(row) = rows.next()? {
+        profiles.push(Profile {
+            profile_id: row.get(0)?,
+            name: row.get(1)?,
+        })
+    }
+    Ok(profiles)
+}
+
+fn ensure_track_stats(profile_id: &str, file_path: &str, conn: &Connection) -> Result<()> {
+    conn.execute(
+        "INSERT OR IGNORE INTO profile_tracks(ProfileId, FilePath) VALUES (?1, ?2)",
+        &[profile_id, file_path],
+    )?;
+    Ok(())
+}
+
+/// Records a single play of the track for the given profile.
+pub fn record_play(profile_id: &str, file_path: &Path, conn: &Connection) -> Result<()> {
+    let file_path = path_string(file_path);
+    ensure_track_stats(profile_id, &file_path, conn)?;
+    conn.execute(
+        "UPDATE profile_tracks SET PlayCount = PlayCount + 1, LastPlayed = datetime('now')
+            WHERE ProfileId = ?1 AND FilePath = ?2",
+        &[profile_id, &file_path],
+    )?;
+    Ok(())
+}
+
+/// Adds a play of the track to the listening history of the given profile, at the given time in
+/// seconds since the Unix epoch, or now. Unlike `record_play`, the play count is left as it is.
+pub fn record_listen(profile_id: &str, file_path: &Path, listened_at: Option<i64>, conn: &Connection) -> Result<()> {
+    conn.execute(
+        "INSERT INTO profile_listens(ProfileId, FilePath, ListenedAt)
+            SELECT ?1, FilePath, IFNULL(?3, CAST(strftime('%s', 'now') AS INTEGER)) FROM tracks WHERE FilePath = ?2",
+        &[&profile_id as &dyn ToSql, &path_string(file_path), &listened_at],
+    )?;
+    Ok(())
+}
+
+/// Counts the plays in the listening history of the given profile by the day of the week and the
+/// hour of the day they were in, in local time, with Sunday as the first day.
+pub fn get_listening_heatmap(profile_id: &str, conn: &Connection) -> Result<[[u32; 24]; 7]> {
+    let mut statement = conn.prepare(
+        "SELECT CAST(strftime('%w', ListenedAt, 'unixepoch', 'localtime') AS INTEGER),
+            CAST(strftime('%H', ListenedAt, 'unixepoch', 'localtime') AS INTEGER), COUNT(*)
+            FROM profile_listens WHERE ProfileId = ?1 GROUP BY 1, 2",
+    )?;
+    let mut heatmap = [[0; 24]; 7];
+    let mut rows = statement.query(&[profile_id])?;
+    while let Some(row) = rows.next()? {
+        let (day, hour) = (row.get::<_, i64>(0)?, row.get::<_, i64>(1)?);
+        if (0..7).contains(&day) && (0..24).contains(&hour) {
+            heatmap[day as usize][hour as usize] = row.get(2)?;
+        }
+    }
+    Ok(heatmap)
+}
+
+/// Sets the rating of the track for the given profile.
+/// Ratings are clamped between 1 and 5, and `None` clears the rating.
+pub fn set_rating(
+    profile_id: &str,
+    file_path: &Path,
+    rating: Option<i32>,
+    conn: &Connection,
+) -> Result<()> {
+    let file_path = path_string(file_path);
+    let rating = rating.map(|r| r.clamp(1, 5));
+    ensure_track_stats(profile_id, &file_path, conn)?;
+    conn.execute(
+        "UPDATE profile_tracks SET Rating = ?1 WHERE ProfileId = ?2 AND FilePath = ?3",
+        &[&rating as &dyn ToSql, &profile_id, &file_path],
+    )?;
+    Ok(())
+}
+
+pub fn set_favorite(
+    profile_id: &str,
+    file_path: &Path,
+    favorite: bool,
+    conn: &Connection,
+) -> Result<()> {
+    let file_path = path_string(file_path);
+    ensure_track_stats(profile_id, &file_path, conn)?;
+    conn.execute(
+        "UPDATE profile_tracks SET Favorite = ?1 WHERE ProfileId = ?2 AND FilePath = ?3",
+        &[&favorite as &dyn ToSql, &profile_id, &file_path],
+    )?;
+    Ok(())
+}
+
+/// Gets the statistics of the track for the given profile.
+/// Tracks that the profile has never interacted with have default statistics.
+pub fn get_track_stats(profile_id: &str, file_path: &Path, conn: &Connection) -> Result<TrackStats> {
+    let stats = conn
+        .query_row(
+            "SELECT PlayCount, Rating, Favorite, LastPlayed FROM profile_tracks
+                WHERE ProfileId = ?1 AND FilePath = ?2",
+            &[profile_id, &path_string(file_path)],
+            |row| {
+                Ok(TrackStats {
+                    play_count: row.get(0)?,
+                    rating: row.get(1)?,
+                    favorite: row.get(2)?,
+                    last_played: row.get(3)?,
+                })
+            },
+        )
+        .optional()?;
+    Ok(stats.unwrap_or_default())
+}
+
+/// Gets the file paths of the favourite tracks of the given profile.
+pub fn get_favorites(profile_id: &str, conn: &Connection) -> Result<Vec<PathBuf>> {
+    let mut statement = conn.prepare(
+        "SELECT FilePath FROM profile_tracks WHERE ProfileId = ?1 AND Favorite = 1",
+    )?;
+    let mut favorites = Vec::<PathBuf>::new();
+    let mut rows = statement.query(&[profile_id])?;
+    while let Some(row) = rows.next()? {
+        favorites.push(PathBuf::from(&row.get::<_, String>(0)?))
+    }
+    Ok(favorites)
+}
+
+/// Creates a playlist for the given profile, and returns its id.
+/// If the profile already has a playlist with the same name, returns the existing id.
+pub fn create_playlist(profile_id: &str, name: &str, conn: &Connection) -> Result<i64> {
+    conn.execute(
+        "INSERT OR IGNORE INTO playlists(ProfileId, Name) VALUES (?1, ?2)",
+        &[profile_id, name],
+    )?;
+    conn.query_row(
+        "SELECT PlaylistId FROM playlists WHERE ProfileId = ?1 AND Name = ?2",
+        &[profile_id, name],
+        |row| row.get(0),
+    )
+}
+
+pub fn remove_playlist(playlist_id: i64, conn: &Connection) -> Result<()> {
+    conn.execute("DELETE FROM playlists WHERE PlaylistId = ?1", &[&playlist_id])?;
+    Ok(())
+}
+
+pub fn get_playlists(profile_id: &str, conn: &Connection) -> Result<Vec<Playlist>> {
+    let mut statement = conn.prepare(
+        "SELECT PlaylistId, ProfileId, Name FROM playlists WHERE ProfileId = ?1 ORDER BY Name",
+    )?;
+    let mut playlists = Vec::<Playlist>::new();
+    let mut rows = statement.query(&[profile_id])?;
+    while let Some(row) = rows.next()? {
+        playlists.push(Playlist {
+            playlist_id: row.get(0)?,
+            profile_id: row.get(1)?,
+            name: row.get(2)?,
+        })
+    }
+    Ok(playlists)
+}
+
+/// Appends a track to the end of the playlist.
+pub fn add_to_playlist(playlist_id: i64, file_path: &Path, conn: &Connection) -> Result<()> {
+    conn.execute(
+        "INSERT INTO playlist_tracks(PlaylistId, Position, FilePath)
+            SELECT ?1, IFNULL(MAX(Position) + 1, 0), ?2 FROM playlist_tracks WHERE PlaylistId = ?1",
+        &[&playlist_id as &dyn ToSql, &path_string(file_path)],
+    )?;
+    Ok(())
+}
+
+/// Removes the track at the given position from the playlist,
+/// moving every track after it up by one.
+///
+/// The tracks after it are moved by a trigger in the same statement, which also closes the gap
+/// left by a track removed from the library.
+pub fn remove_from_playlist(playlist_id: i64, position: i64, conn: &Connection) -> Result<()> {
+    conn.execute(
+        "DELETE FROM playlist_tracks WHERE PlaylistId = ?1 AND Position = ?2",
+        &[&playlist_id, &position],
+    )?;
+    Ok(())
+}
+
+/// Gets the file paths of the tracks in the playlist, in order.
+pub fn get_playlist_tracks(playlist_id: i64, conn: &Connection) -> Result<Vec<PathBuf>> {
+    let mut statement = conn.prepare(
+        "SELECT FilePath FROM playlist_tracks WHERE PlaylistId = ?1 ORDER BY Position",
+    )?;
+    let mut tracks = Vec::<PathBuf>::new();
+    let mut rows = statement.query(&[&playlist_id])?;
+    while let Some(row) = rows.next()? {
+        tracks.push(PathBuf::from(&row.get::<_, String>(0)?))
+    }
+    Ok(tracks)
+}
+
+/// Points every statistic and playlist entry for a track to its new location,
+/// after the track has been moved within the library.
+///
+/// This is only needed if the track was not moved with `database::replace_track`,
+/// which moves its references along with it.
+pub fn move_track_references(old_path: &Path, new_path: &Path, conn: &Connection) -> Result<()> {
+    let old_path = path_string(old_path);
+    let new_path = path_string(new_path);
+    if old_path == new_path {
+        return Ok(());
+    }
+    conn.execute(
+        "UPDATE OR REPLACE profile_tracks SET FilePath = ?2 WHERE FilePath = ?1",
+        &[&old_path, &new_path],
+    )?;
+    conn.execute(
+        "UPDATE playlist_tracks SET FilePath = ?2 WHERE FilePath = ?1",
+        &[&old_path, &new_path],
+    )?;
+    Ok(())
+}
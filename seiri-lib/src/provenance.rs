@@ -0,0 +1,139 @@
+//! Where tracks ripped from CDs came from, as their rip logs recorded it, such as whether
+//! AccurateRip found them to be accurate rips.
+//!
+//! Provenance is recorded by the path a file of a rip is moved into the watch folder at,
+//! before it is imported, and passes to its track once the track is added to the library.
+
+use crate::database::create_table_with_foreign_keys;
+use rusqlite::{Connection, OptionalExtension, Result, Row};
+use std::path::Path;
+
+/// What AccurateRip found of a ripped track.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccurateRip {
+    /// The rip matched the rips of `confidence` others.
+    Accurate { confidence: u32 },
+    /// The track is in the AccurateRip database, but the rip did not match the rips of any of
+    /// the `confidence` others.
+    Inaccurate { confidence: u32 },
+    /// The track is not in the AccurateRip database, so the rip could not be verified.
+    NotPresent,
+    /// The rip log recorded nothing of AccurateRip, such as when it was not asked.
+    Unknown,
+}
+
+impl AccurateRip {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AccurateRip::Accurate { .. } => "accurate",
+            AccurateRip::Inaccurate { .. } => "inaccurate",
+            AccurateRip::NotPresent => "notpresent",
+            AccurateRip::Unknown => "unknown",
+        }
+    }
+
+    pub fn confidence(self) -> Option<u32> {
+        match self {
+            AccurateRip::Accurate { confidence } | AccurateRip::Inaccurate { confidence } => Some(confidence),
+            _ => None,
+        }
+    }
+
+    fn from_parts(status: &str, confidence: Option<u32>) -> AccurateRip {
+        match (status, confidence) {
+            ("accurate", Some(confidence)) => AccurateRip::Accurate { confidence },
+            ("inaccurate", Some(confidence)) => AccurateRip::Inaccurate { confidence },
+            ("notpresent", _) => AccurateRip::NotPresent,
+            _ => AccurateRip::Unknown,
+        }
+    }
+}
+
+/// How a track was ripped from a CD.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RipProvenance {
+    /// The program the track was ripped with, along with its version, such as `whipper 0.9.0`.
+    pub ripper: String,
+    pub accurate_rip: AccurateRip,
+    /// The CRC of the copied audio, as the ripper computed it.
+    pub copy_crc: Option<String>,
+    /// Whether the rip log was signed with the checksum of its ripper. The checksum is not checked.
+    pub log_checksum: bool,
+}
+
+const PROVENANCE_COLUMNS: &str = "Ripper TEXT NOT NULL,
+        AccurateRip TEXT NOT NULL,
+        Confidence INTEGER,
+        CopyCrc TEXT,
+        LogChecksum INTEGER NOT NULL";
+
+pub fn create_provenance_tables(conn: &Connection) {
+    create_table_with_foreign_keys(
+        "rip_provenance",
+        &format!(
+            "TrackId TEXT PRIMARY KEY REFERENCES tracks(TrackId) ON DELETE CASCADE,
+        {}",
+            PROVENANCE_COLUMNS
+        ),
+        conn,
+    )
+    .unwrap();
+    conn.execute_batch(&format!(
+        "CREATE TABLE IF NOT EXISTS pending_provenance (
+        FilePath TEXT PRIMARY KEY,
+        {}
+    )",
+        PROVENANCE_COLUMNS
+    ))
+    .unwrap();
+}
+
+fn provenance_from_row(row: &Row) -> Result<RipProvenance> {
+    let status = row.get::<_, String>(1)?;
+    Ok(RipProvenance {
+        ripper: row.get(0)?,
+        accurate_rip: AccurateRip::from_parts(&status, row.get(2)?),
+        copy_crc: row.get(3)?,
+        log_checksum: row.get(4)?,
+    })
+}
+
+/// Records the provenance of the file of a rip at the path, for its track once it is imported.
+pub fn record_pending(file_path: &Path, provenance: &RipProvenance, conn: &Connection) -> Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO pending_provenance(FilePath, Ripper, AccurateRip, Confidence, CopyCrc, LogChecksum)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        rusqlite::params![
+            file_path.to_string_lossy(),
+            provenance.ripper,
+            provenance.accurate_rip.as_str(),
+            provenance.accurate_rip.confidence(),
+            provenance.copy_crc,
+            provenance.log_checksum,
+        ],
+    )?;
+    Ok(())
+}
+
+/// Gives the track with the given UUID the provenance recorded for the file it was imported from,
+/// if any was. Returns whether there was.
+pub fn claim_pending(file_path: &Path, track_id: &str, conn: &Connection) -> Result<bool> {
+    let file_path = file_path.to_string_lossy();
+    let claimed = conn.execute(
+        "INSERT OR REPLACE INTO rip_provenance(TrackId, Ripper, AccurateRip, Confidence, CopyCrc, LogChecksum)
+        SELECT ?1, Ripper, AccurateRip, Confidence, CopyCrc, LogChecksum FROM pending_provenance WHERE FilePath = ?2",
+        &[track_id, &file_path],
+    )?;
+    conn.execute("DELETE FROM pending_provenance WHERE FilePath = ?1", &[&file_path])?;
+    Ok(claimed > 0)
+}
+
+/// Gets how the track with the given UUID was ripped, if it was ripped through the rip intake.
+pub fn get_provenance(track_id: &str, conn: &Connection) -> Result<Option<RipProvenance>> {
+    conn.query_row(
+        "SELECT Ripper, AccurateRip, Confidence, CopyCrc, LogChecksum FROM rip_provenance WHERE TrackId = ?1",
+        &[track_id],
+        provenance_from_row,
+    )
+    .optional()
+}
@@ -0,0 +1,175 @@
+//! The play queue of every profile, kept in the database so that it survives restarts.
+//!
+//! A queue is a list of tracks, the track currently playing, and how far into that track
+//! playback is. Frontends save the position as playback moves along, and restore the queue
+//! with `get_queue` when they start again. Removing a track from the library removes it
+//! from every queue.
+
+use crate::database::create_table_with_foreign_keys;
+use rusqlite::types::ToSql;
+use rusqlite::{Connection, OptionalExtension, Result, Transaction, TransactionBehavior};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PlayQueue {
+    pub tracks: Vec<PathBuf>,
+    /// The index of the track currently playing. This is the length of the queue
+    /// once every track has been played.
+    pub current: usize,
+    /// How far into the current track playback is, in milliseconds.
+    pub offset: i64,
+}
+
+pub fn create_queue_tables(conn: &Connection) {
+    create_table_with_foreign_keys(
+        "play_queues",
+        "ProfileId TEXT PRIMARY KEY REFERENCES profiles(ProfileId) ON DELETE CASCADE,
+        Current INTEGER NOT NULL DEFAULT 0,
+        Offset INTEGER NOT NULL DEFAULT 0,
+        Updated DATE",
+        conn,
+    )
+    .unwrap();
+    create_table_with_foreign_keys(
+        "queue_tracks",
+        "ProfileId TEXT NOT NULL REFERENCES profiles(ProfileId) ON DELETE CASCADE,
+        Position INTEGER NOT NULL,
+        FilePath TEXT NOT NULL REFERENCES tracks(FilePath) ON DELETE CASCADE ON UPDATE CASCADE,
+        PRIMARY KEY (ProfileId, Position)",
+        conn,
+    )
+    .unwrap();
+}
+
+fn path_string(file_path: &Path) -> String {
+    file_path.to_string_lossy().into_owned()
+}
+
+/// Gets the position of the current track of the profile's queue.
+///
+/// Positions are the indices of tracks in the queue, except that a track removed
+/// along with its file leaves a gap, so positions are only used within the database.
+fn get_current_position(profile_id: &str, conn: &Connection) -> Result<i64> {
+    let current = conn
+        .query_row(
+            "SELECT Current FROM play_queues WHERE ProfileId = ?1",
+            &[profile_id],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(current.unwrap_or(0))
+}
+
+/// Gets the play queue of the profile, which is empty if it was never set.
+pub fn get_queue(profile_id: &str, conn: &Connection) -> Result<PlayQueue> {
+    let (current, offset) = conn
+        .query_row(
+            "SELECT
+                (SELECT COUNT(*) FROM queue_tracks WHERE ProfileId = ?1 AND Position < Current),
+                Offset
+            FROM play_queues WHERE ProfileId = ?1",
+            &[profile_id],
+            |row| Ok((row.get::<_, i64>(0)?, row.get(1)?)),
+        )
+        .optional()?
+        .unwrap_or((0, 0));
+    let mut statement = conn.prepare(
+        "SELECT FilePath FROM queue_tracks WHERE ProfileId = ?1 ORDER BY Position",
+    )?;
+    let tracks = statement
+        .query_map(&[profile_id], |row| Ok(PathBuf::from(&row.get::<_, String>(0)?)))?
+        .collect::<Result<Vec<PathBuf>>>()?;
+    Ok(PlayQueue {
+        tracks,
+        current: current as usize,
+        offset,
+    })
+}
+
+/// Replaces the play queue of the profile, starting playback at the given index.
+pub fn set_queue(profile_id: &str, tracks: &[PathBuf], current: usize, conn: &Connection) -> Result<()> {
+    // The old queue is only replaced if the whole new queue could be written.
+    let transaction = Transaction::new_unchecked(conn, TransactionBehavior::Immediate)?;
+    transaction.execute("DELETE FROM queue_tracks WHERE ProfileId = ?1", &[profile_id])?;
+    {
+        let mut statement = transaction.prepare(
+            "INSERT INTO queue_tracks(ProfileId, Position, FilePath) VALUES (?1, ?2, ?3)",
+        )?;
+        for (position, track) in tracks.iter().enumerate() {
+            statement.execute(&[&profile_id as &dyn ToSql, &(position as i64), &path_string(track)])?;
+        }
+    }
+    set_queue_position(profile_id, current, 0, &transaction)?;
+    transaction.commit()
+}
+
+/// Saves how far playback of the profile's queue is, as the index of the current track
+/// and the offset into it in milliseconds.
+pub fn set_queue_position(profile_id: &str, current: usize, offset: i64, conn: &Connection) -> Result<()> {
+    // The index is converted to a position by skipping over any gaps before it.
+    conn.execute(
+        "INSERT INTO play_queues(ProfileId, Current, Offset, Updated)
+        VALUES (?1, IFNULL((SELECT Position FROM queue_tracks WHERE ProfileId = ?1
+                ORDER BY Position LIMIT 1 OFFSET ?2),
+            (SELECT IFNULL(MAX(Position) + 1, 0) FROM queue_tracks WHERE ProfileId = ?1)),
+            ?3, datetime('now'))
+        ON CONFLICT(ProfileId) DO UPDATE SET
+            Current = excluded.Current, Offset = excluded.Offset, Updated = excluded.Updated",
+        &[&profile_id as &dyn ToSql, &(current as i64), &offset],
+    )?;
+    Ok(())
+}
+
+/// Appends a track to the end of the profile's queue.
+pub fn enqueue(profile_id: &str, file_path: &Path, conn: &Connection) -> Result<()> {
+    conn.execute(
+        "INSERT INTO queue_tracks(ProfileId, Position, FilePath)
+            SELECT ?1, IFNULL(MAX(Position) + 1, 0), ?2 FROM queue_tracks WHERE ProfileId = ?1",
+        &[profile_id, &path_string(file_path)],
+    )?;
+    Ok(())
+}
+
+/// Inserts a track into the profile's queue, to be played right after the current track.
+pub fn play_next(profile_id: &str, file_path: &Path, conn: &Connection) -> Result<()> {
+    let transaction = Transaction::new_unchecked(conn, TransactionBehavior::Immediate)?;
+    let current = get_current_position(profile_id, &transaction)?;
+    // Positions are unique, so tracks are shifted through negative positions
+    // to make room without colliding with each other.
+    transaction.execute(
+        "UPDATE queue_tracks SET Position = -(Position + 1) WHERE ProfileId = ?1 AND Position > ?2",
+        &[&profile_id as &dyn ToSql, &current],
+    )?;
+    transaction.execute(
+        "UPDATE queue_tracks SET Position = -Position WHERE ProfileId = ?1 AND Position < 0",
+        &[profile_id],
+    )?;
+    let exists = transaction
+        .prepare("SELECT 1 FROM queue_tracks WHERE ProfileId = ?1 AND Position = ?2")?
+        .exists(&[&profile_id as &dyn ToSql, &current])?;
+    // If the current track was removed, the track takes its place, which is played next.
+    let position = if exists { current + 1 } else { current };
+    transaction.execute(
+        "INSERT INTO queue_tracks(ProfileId, Position, FilePath) VALUES (?1, ?2, ?3)",
+        &[&profile_id as &dyn ToSql, &position, &path_string(file_path)],
+    )?;
+    transaction.commit()
+}
+
+/// Removes the track at the given index from the profile's queue.
+/// Returns false if there is no track at the index.
+pub fn remove_from_queue(profile_id: &str, index: usize, conn: &Connection) -> Result<bool> {
+    let removed = conn.execute(
+        "DELETE FROM queue_tracks WHERE ProfileId = ?1 AND Position =
+            (SELECT Position FROM queue_tracks WHERE ProfileId = ?1 ORDER BY Position LIMIT 1 OFFSET ?2)",
+        &[&profile_id as &dyn ToSql, &(index as i64)],
+    )?;
+    Ok(removed > 0)
+}
+
+/// Empties the profile's queue.
+pub fn clear_queue(profile_id: &str, conn: &Connection) -> Result<()> {
+    conn.execute("DELETE FROM queue_tracks WHERE ProfileId = ?1", &[profile_id])?;
+    conn.execute("DELETE FROM play_queues WHERE ProfileId = ?1", &[profile_id])?;
+    Ok(())
+}
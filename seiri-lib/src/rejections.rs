@@ -0,0 +1,199 @@
+//! The ignore list of files that were rejected, so the same file dropped into the watch
+//! folder again, such as by a sync tool, is left alone instead of being quarantined and
+//! reported every time.
+//!
+//! Every file moved into the not added folder is remembered by the hash of its contents.
+//! Once the user deletes it from the not added folder it is added to the ignore list, as is
+//! any file the user rejects with `reject_file`. Files are only hashed when a file of the
+//! same size was rejected before, so imports do not read every file twice.
+
+use chrono::Local;
+use rusqlite::types::ToSql;
+use rusqlite::{Connection, OptionalExtension, Result, NO_PARAMS};
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+pub fn create_rejection_tables(conn: &Connection) {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS quarantined_files (
+        Hash TEXT PRIMARY KEY,
+        Size INTEGER NOT NULL,
+        FileName TEXT NOT NULL,
+        FilePath TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS ignored_files (
+        Hash TEXT PRIMARY KEY,
+        Size INTEGER NOT NULL,
+        FileName TEXT NOT NULL,
+        Ignored DATE NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS quarantined_files_size ON quarantined_files(Size);
+    CREATE INDEX IF NOT EXISTS ignored_files_size ON ignored_files(Size);",
+    )
+    .unwrap();
+}
+
+/// A file on the ignore list.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IgnoredFile {
+    /// The hash of the contents of the file.
+    pub hash: String,
+    pub size: i64,
+    /// The name of the file when it was rejected.
+    pub file_name: String,
+    /// The date the file was added to the ignore list.
+    pub ignored: String,
+}
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+
+fn fnv1a(mut hash: u64, bytes: &[u8]) -> u64 {
+    for byte in bytes {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+/// Hashes the contents of the file with 64-bit FNV-1a, which is stable across versions of seiri.
+pub fn hash_file(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut buffer = [0u8; 64 * 1024];
+    let mut hash = FNV_OFFSET_BASIS;
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hash = fnv1a(hash, &buffer[..read]);
+    }
+    Ok(format!("{:016x}", hash))
+}
+
+/// Hashes the bytes as `hash_file` hashes the contents of a file.
+pub(crate) fn hash_bytes(bytes: &[u8]) -> String {
+    format!("{:016x}", fnv1a(FNV_OFFSET_BASIS, bytes))
+}
+
+fn file_size(path: &Path) -> Result<i64> {
+    path.metadata()
+        .map(|metadata| metadata.len() as i64)
+        .map_err(|_| rusqlite::Error::InvalidPath(path.to_owned()))
+}
+
+fn hash(path: &Path) -> Result<String> {
+    hash_file(path).map_err(|_| rusqlite::Error::InvalidPath(path.to_owned()))
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+fn ignore(hash: &str, size: i64, file_name: &str, conn: &Connection) -> Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO ignored_files(Hash, Size, FileName, Ignored) VALUES (?1, ?2, ?3, ?4)",
+        &[
+            &hash as &dyn ToSql,
+            &size,
+            &file_name,
+            &Local::now().format("%Y-%m-%d").to_string(),
+        ],
+    )?;
+    conn.execute("DELETE FROM quarantined_files WHERE Hash = ?1", &[hash])?;
+    Ok(())
+}
+
+/// Remembers the file, which was just moved into the not added folder at the given path.
+pub fn record_quarantined(quarantined_path: &Path, conn: &Connection) -> Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO quarantined_files(Hash, Size, FileName, FilePath) VALUES (?1, ?2, ?3, ?4)",
+        &[
+            &hash(quarantined_path)? as &dyn ToSql,
+            &file_size(quarantined_path)?,
+            &file_name(quarantined_path),
+            &quarantined_path.to_string_lossy().into_owned(),
+        ],
+    )?;
+    Ok(())
+}
+
+/// Forgets every file quarantined in the given folder, which was purged from the not added folder,
+/// so the files are not added to the ignore list as though the user had deleted them.
+pub fn forget_quarantined_in(folder: &Path, conn: &Connection) -> Result<usize> {
+    conn.execute(
+        "DELETE FROM quarantined_files WHERE substr(FilePath, 1, length(?1)) = ?1",
+        &[&folder.to_string_lossy().into_owned()],
+    )
+}
+
+/// Adds the file to the ignore list, so the same file is never imported again.
+pub fn reject_file(path: &Path, conn: &Connection) -> Result<()> {
+    ignore(&hash(path)?, file_size(path)?, &file_name(path), conn)
+}
+
+/// Whether the file is on the ignore list. A file that was quarantined and has since been
+/// deleted from the not added folder is added to the ignore list first.
+pub fn is_ignored(path: &Path, conn: &Connection) -> Result<bool> {
+    let size = file_size(path)?;
+    let candidates: i64 = conn.query_row(
+        "SELECT (SELECT COUNT(*) FROM ignored_files WHERE Size = ?1)
+            + (SELECT COUNT(*) FROM quarantined_files WHERE Size = ?1)",
+        &[&size],
+        |row| row.get(0),
+    )?;
+    if candidates == 0 {
+        return Ok(false);
+    }
+    let hash = hash(path)?;
+    let ignored: bool = conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM ignored_files WHERE Hash = ?1)",
+        &[&hash],
+        |row| row.get(0),
+    )?;
+    if ignored {
+        return Ok(true);
+    }
+    let quarantined = conn
+        .query_row(
+            "SELECT FileName, FilePath FROM quarantined_files WHERE Hash = ?1",
+            &[&hash],
+            |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)),
+        )
+        .optional()?;
+    match quarantined {
+        Some((file_name, quarantined_path)) if !Path::new(&quarantined_path).exists() => {
+            ignore(&hash, size, &file_name, conn)?;
+            Ok(true)
+        }
+        _ => Ok(false),
+    }
+}
+
+/// Gets every file on the ignore list, most recently ignored first.
+pub fn get_ignored_files(conn: &Connection) -> Result<Vec<IgnoredFile>> {
+    let mut statement =
+        conn.prepare("SELECT Hash, Size, FileName, Ignored FROM ignored_files ORDER BY Ignored DESC, FileName")?;
+    let files = statement.query_map(NO_PARAMS, |row| {
+        Ok(IgnoredFile {
+            hash: row.get(0)?,
+            size: row.get(1)?,
+            file_name: row.get(2)?,
+            ignored: row.get(3)?,
+        })
+    })?;
+    files.collect()
+}
+
+/// Removes the file with the given hash from the ignore list, so it is imported again.
+pub fn unignore_file(hash: &str, conn: &Connection) -> Result<()> {
+    conn.execute("DELETE FROM ignored_files WHERE Hash = ?1", &[hash])?;
+    Ok(())
+}
+
+/// Removes every file from the ignore list, returning how many were removed.
+pub fn clear_ignored_files(conn: &Connection) -> Result<usize> {
+    conn.execute("DELETE FROM ignored_files", NO_PARAMS)
+}
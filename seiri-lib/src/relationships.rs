@@ -0,0 +1,104 @@
+//! Links between tracks that are alternate versions of one another, such as a remix and the
+//! track it is a remix of, so the versions of a track can be found and grouped together.
+//!
+//! A link is kept by the UUIDs of its tracks, so it follows them when they are moved, and is
+//! removed along with either track. Tracks linked as a version of another are matched by the
+//! `!rel` bang, such as `!rel{remixof:<UUID>}` for the remixes of a track.
+
+use crate::bangs::Relation;
+use crate::database::create_table_with_foreign_keys;
+use katatsuki::Track;
+use rusqlite::types::ToSql;
+use rusqlite::{Connection, Result, NO_PARAMS};
+use std::collections::HashSet;
+
+pub fn create_relation_table(conn: &Connection) {
+    create_table_with_foreign_keys(
+        "track_relations",
+        "TrackId TEXT NOT NULL REFERENCES tracks(TrackId) ON DELETE CASCADE,
+        Relation INTEGER NOT NULL,
+        RelatedId TEXT NOT NULL REFERENCES tracks(TrackId) ON DELETE CASCADE,
+        PRIMARY KEY (TrackId, Relation, RelatedId)",
+        conn,
+    )
+    .unwrap();
+}
+
+/// A link from a track to the track it is a version of.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Relationship {
+    /// The UUID of the alternate version.
+    pub track_id: String,
+    pub relation: Relation,
+    /// The UUID of the track it is a version of.
+    pub related_id: String,
+}
+
+/// Links the track with the given UUID as the given version of the related track.
+/// Returns false if either track is not in the library, or they are the same track.
+pub fn link(track_id: &str, relation: Relation, related_id: &str, conn: &Connection) -> Result<bool> {
+    if track_id == related_id {
+        return Ok(false);
+    }
+    let linked = conn.execute(
+        "INSERT OR IGNORE INTO track_relations(TrackId, Relation, RelatedId)
+        SELECT ?1, ?2, ?3 WHERE EXISTS (SELECT 1 FROM tracks WHERE TrackId = ?1)
+            AND EXISTS (SELECT 1 FROM tracks WHERE TrackId = ?3)",
+        &[&track_id as &dyn ToSql, &relation.to_i32(), &related_id],
+    )?;
+    if linked > 0 {
+        return Ok(true);
+    }
+    // The link may already exist.
+    conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM track_relations WHERE TrackId = ?1 AND Relation = ?2 AND RelatedId = ?3)",
+        &[&track_id as &dyn ToSql, &relation.to_i32(), &related_id],
+        |row| row.get(0),
+    )
+}
+
+/// Removes the link between the tracks. Returns whether they were linked.
+pub fn unlink(track_id: &str, relation: Relation, related_id: &str, conn: &Connection) -> Result<bool> {
+    let unlinked = conn.execute(
+        "DELETE FROM track_relations WHERE TrackId = ?1 AND Relation = ?2 AND RelatedId = ?3",
+        &[&track_id as &dyn ToSql, &relation.to_i32(), &related_id],
+    )?;
+    Ok(unlinked > 0)
+}
+
+/// Gets every link to or from the track with the given UUID.
+pub fn get_relationships(track_id: &str, conn: &Connection) -> Result<Vec<Relationship>> {
+    let mut statement = conn.prepare(
+        "SELECT TrackId, Relation, RelatedId FROM track_relations WHERE TrackId = ?1 OR RelatedId = ?1
+        ORDER BY Relation, TrackId, RelatedId",
+    )?;
+    let rows = statement.query_map(&[track_id], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, i32>(1)?, row.get::<_, String>(2)?))
+    })?;
+    let mut relationships = Vec::new();
+    for row in rows {
+        let (track_id, relation, related_id) = row?;
+        if let Some(relation) = Relation::from_i32(relation) {
+            relationships.push(Relationship { track_id, relation, related_id });
+        }
+    }
+    Ok(relationships)
+}
+
+/// Leaves out every track that is linked as a version of another of the tracks, so each group
+/// of versions is only listed once, by the track the others are versions of.
+pub fn collapse_versions(tracks: Vec<Track>, conn: &Connection) -> Result<Vec<Track>> {
+    let ids = tracks.iter().filter_map(|track| track.uuid.as_deref()).collect::<HashSet<&str>>();
+    let mut statement = conn.prepare("SELECT TrackId, RelatedId FROM track_relations")?;
+    let versions = statement
+        .query_map(NO_PARAMS, |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?
+        .collect::<Result<Vec<(String, String)>>>()?
+        .into_iter()
+        .filter(|(track_id, related_id)| ids.contains(track_id.as_str()) && ids.contains(related_id.as_str()))
+        .map(|(track_id, _)| track_id)
+        .collect::<HashSet<String>>();
+    Ok(tracks
+        .into_iter()
+        .filter(|track| track.uuid.as_ref().is_none_or(|uuid| !versions.contains(uuid)))
+        .collect())
+}
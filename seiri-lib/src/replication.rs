@@ -0,0 +1,612 @@
+//! Replication of ratings, favourites, play counts and playlists between instances of seiri,
+//! such as a desktop and a NAS serving the same music.
+//!
+//! An instance with `NetworkConfig::sync` set serves sync on its network port, and every
+//! instance syncs with its `sync_peers` in turn. A sync is a single request of one JSON line,
+//! carrying the changes made since the last sync, answered by a single JSON line carrying
+//! the changes the peer made since then, so both instances end up with the changes of the other.
+//!
+//! Tracks are matched between instances by `library::find_same_track`, since the files
+//! may be at different paths. Where the same statistics or playlist were changed on both
+//! instances, the change made last wins, except that the greater play count is always kept,
+//! so plays are never lost. Modification times are compared across instances, so their
+//! clocks should be kept in sync.
+//!
+//! Peers with `pull_files` set also have their audio files transferred over the same
+//! authenticated channel. Every track the peer has that this library does not is downloaded,
+//! and imported like any file dropped into the watch folder, so hooks and required tags apply.
+//!
+//! Sync is served on a listener of its own rather than the control socket, since it is served to
+//! other machines. It does not support TLS though, so it refuses to be served on any address but
+//! localhost, and is only reached from other machines through a tunnel, such as SSH port
+//! forwarding to `localhost:9236`.
+//!
+//! Changes applied from a peer are remembered along with the modification time they were made
+//! at there, so they are not sent back to the peer unless they are changed again here.
+
+use crate::auth::{authorize, validate_network_config, Scope};
+use crate::config::{Config, NetworkConfig, SyncPeer};
+use crate::database::{track_from_row, Connection, ConnectionPool, TRACK_COLUMNS};
+use crate::error::{Error, Result};
+use crate::events::Event;
+use crate::import;
+use crate::library::{find_same_track, TrackKey};
+use crate::paths::get_appdata_path;
+use crate::profiles::{add_to_playlist, create_playlist, MODIFIED_NOW};
+use rusqlite::types::ToSql;
+use rusqlite::{OptionalExtension, Transaction, TransactionBehavior, NO_PARAMS};
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// How long to wait for the other instance before giving up on a sync.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The statistics of a track for a profile, as last modified at `modified`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StatsChange {
+    pub profile_id: String,
+    pub profile_name: String,
+    pub track: TrackKey,
+    pub play_count: i32,
+    pub rating: Option<i32>,
+    pub favorite: bool,
+    pub last_played: Option<String>,
+    pub modified: String,
+}
+
+/// The whole of a playlist, as last modified at `modified`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PlaylistChange {
+    pub profile_id: String,
+    pub profile_name: String,
+    pub name: String,
+    pub tracks: Vec<TrackKey>,
+    pub modified: String,
+}
+
+/// A playlist removed at `modified`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PlaylistRemoval {
+    pub profile_id: String,
+    pub name: String,
+    pub modified: String,
+}
+
+/// The changes made to profiles since some time.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ChangeSet {
+    pub stats: Vec<StatsChange>,
+    pub playlists: Vec<PlaylistChange>,
+    pub removed_playlists: Vec<PlaylistRemoval>,
+}
+
+impl ChangeSet {
+    /// The number of changes in the set.
+    pub fn len(&self) -> usize {
+        self.stats.len() + self.playlists.len() + self.removed_playlists.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct SyncRequest {
+    token: Option<String>,
+    kind: RequestKind,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+enum RequestKind {
+    /// Applies the changes, and asks for the changes made after `since`, the time of the
+    /// instance being synced with that the last sync got to. `origin` identifies the instance
+    /// syncing, so the changes applied from it are not sent back to it.
+    Sync {
+        since: Option<String>,
+        changes: ChangeSet,
+        #[serde(default)]
+        origin: Option<String>,
+    },
+    /// Asks for the key of every track in the library.
+    ListTracks,
+    /// Asks for the file of the track.
+    Fetch(TrackKey),
+}
+
+impl RequestKind {
+    /// The scope a token must allow to make the request. Only syncing changes the library.
+    fn scope(&self) -> Scope {
+        match self {
+            RequestKind::Sync { .. } => Scope::Admin,
+            RequestKind::ListTracks | RequestKind::Fetch(_) => Scope::ReadOnly,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+enum SyncResponse {
+    /// The changes of the request were applied. `now` is the time the changes
+    /// of the response go up to, to be sent as `since` by the next sync.
+    Accepted { now: String, changes: ChangeSet },
+    Tracks(Vec<TrackKey>),
+    /// The file of the track follows this line, as `size` bytes.
+    File { extension: String, size: u64 },
+    NotFound,
+    Rejected(String),
+}
+
+/// The current time, in the format modification times are kept in.
+fn modified_now(conn: &Connection) -> rusqlite::Result<String> {
+    conn.query_row(&format!("SELECT {}", MODIFIED_NOW), NO_PARAMS, |row| row.get(0))
+}
+
+/// Gets the key of the track at the given path, remembering keys already read.
+fn get_track_key<'a>(
+    file_path: &str,
+    keys: &'a mut HashMap<String, Option<TrackKey>>,
+    conn: &Connection,
+) -> rusqlite::Result<Option<&'a TrackKey>> {
+    if !keys.contains_key(file_path) {
+        let key = conn
+            .query_row(
+                &format!("SELECT {} FROM tracks WHERE FilePath = ?1", TRACK_COLUMNS),
+                &[file_path],
+                track_from_row,
+            )
+            .optional()?
+            .map(|track| TrackKey::of(&track));
+        keys.insert(file_path.to_owned(), key);
+    }
+    Ok(keys[file_path].as_ref())
+}
+
+/// Gets the key of every track in the library.
+fn get_track_keys(conn: &Connection) -> rusqlite::Result<Vec<TrackKey>> {
+    let mut statement = conn.prepare(&format!("SELECT {} FROM tracks", TRACK_COLUMNS))?;
+    let tracks = statement.query_map(NO_PARAMS, track_from_row)?;
+    tracks.map(|track| track.map(|track| TrackKey::of(&track))).collect()
+}
+
+/// Gets the identifier of this instance, which other instances remember the changes applied
+/// from it by, generating it the first time.
+fn get_instance_id(conn: &Connection) -> rusqlite::Result<String> {
+    let id = thread_rng().sample_iter(&Alphanumeric).take(32).collect::<String>();
+    conn.execute("INSERT OR IGNORE INTO sync_instance(Id, InstanceId) VALUES (0, ?1)", &[&id])?;
+    conn.query_row("SELECT InstanceId FROM sync_instance WHERE Id = 0", NO_PARAMS, |row| row.get(0))
+}
+
+/// Whether a row of the table was last changed by applying a change from the peer, as the change
+/// was made there, where `item` is the column the change is remembered by.
+fn synced_from_peer(kind: &str, table: &str, item: &str) -> String {
+    format!(
+        "EXISTS (SELECT 1 FROM synced_changes WHERE Peer = ?2 AND Kind = '{kind}'
+            AND synced_changes.ProfileId = {table}.ProfileId AND Item = {table}.{item}
+            AND synced_changes.Modified = {table}.Modified)",
+        kind = kind,
+        table = table,
+        item = item
+    )
+}
+
+/// Remembers that the change was applied from the peer as it was made there.
+fn remember_synced(peer: Option<&str>, kind: &str, profile_id: &str, item: &str, modified: &str, conn: &Connection) -> rusqlite::Result<()> {
+    if let Some(peer) = peer {
+        conn.execute(
+            "INSERT OR REPLACE INTO synced_changes(Peer, Kind, ProfileId, Item, Modified) VALUES (?1, ?2, ?3, ?4, ?5)",
+            &[peer, kind, profile_id, item, modified],
+        )?;
+    }
+    Ok(())
+}
+
+/// Gets every change to profiles made after the given time, or every statistic
+/// and playlist if no time is given. Changes applied from the given peer, that were not changed
+/// here since, are left out, since the peer already has them.
+pub fn get_changes_since(since: Option<&str>, peer: Option<&str>, conn: &Connection) -> rusqlite::Result<ChangeSet> {
+    let since = since.unwrap_or("");
+    let mut keys = HashMap::<String, Option<TrackKey>>::new();
+    let mut changes = ChangeSet::default();
+
+    let mut statement = conn.prepare(&format!(
+        "SELECT ProfileId, Name, FilePath, PlayCount, Rating, Favorite, LastPlayed, Modified
+        FROM profile_tracks JOIN profiles USING (ProfileId) WHERE Modified > ?1 AND NOT {}",
+        synced_from_peer("stats", "profile_tracks", "FilePath")
+    ))?;
+    let mut rows = statement.query(&[&since as &dyn ToSql, &peer])?;
+    while let Some(row) = rows.next()? {
+        if let Some(track) = get_track_key(&row.get::<_, String>(2)?, &mut keys, conn)? {
+            changes.stats.push(StatsChange {
+                profile_id: row.get(0)?,
+                profile_name: row.get(1)?,
+                track: track.clone(),
+                play_count: row.get(3)?,
+                rating: row.get(4)?,
+                favorite: row.get(5)?,
+                last_played: row.get(6)?,
+                modified: row.get(7)?,
+            });
+        }
+    }
+
+    let mut statement = conn.prepare(&format!(
+        "SELECT PlaylistId, ProfileId, profiles.Name, playlists.Name, Modified
+        FROM playlists JOIN profiles USING (ProfileId) WHERE Modified > ?1 AND NOT {}",
+        synced_from_peer("playlist", "playlists", "Name")
+    ))?;
+    let mut tracks_statement = conn.prepare(
+        "SELECT FilePath FROM playlist_tracks WHERE PlaylistId = ?1 ORDER BY Position",
+    )?;
+    let mut rows = statement.query(&[&since as &dyn ToSql, &peer])?;
+    while let Some(row) = rows.next()? {
+        let mut tracks = Vec::new();
+        let mut track_rows = tracks_statement.query(&[&row.get::<_, i64>(0)?])?;
+        while let Some(track_row) = track_rows.next()? {
+            if let Some(track) = get_track_key(&track_row.get::<_, String>(0)?, &mut keys, conn)? {
+                tracks.push(track.clone());
+            }
+        }
+        changes.playlists.push(PlaylistChange {
+            profile_id: row.get(1)?,
+            profile_name: row.get(2)?,
+            name: row.get(3)?,
+            tracks,
+            modified: row.get(4)?,
+        });
+    }
+
+    let mut statement = conn.prepare(&format!(
+        "SELECT ProfileId, Name, Modified FROM removed_playlists WHERE Modified > ?1 AND NOT {}",
+        synced_from_peer("removal", "removed_playlists", "Name")
+    ))?;
+    changes.removed_playlists = statement
+        .query_map(&[&since as &dyn ToSql, &peer], |row| {
+            Ok(PlaylistRemoval {
+                profile_id: row.get(0)?,
+                name: row.get(1)?,
+                modified: row.get(2)?,
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<PlaylistRemoval>>>()?;
+    Ok(changes)
+}
+
+fn ensure_profile(profile_id: &str, name: &str, conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT OR IGNORE INTO profiles(ProfileId, Name) VALUES (?1, ?2)",
+        &[profile_id, name],
+    )?;
+    Ok(())
+}
+
+/// Applies the change if it is newer, or if it has a greater play count. Returns whether it was applied.
+fn apply_stats(change: &StatsChange, peer: Option<&str>, conn: &Connection) -> rusqlite::Result<bool> {
+    let file_path = match find_same_track(&change.track, conn)? {
+        Some(file_path) => file_path,
+        None => return Ok(false),
+    };
+    ensure_profile(&change.profile_id, &change.profile_name, conn)?;
+    let local = conn
+        .query_row(
+            "SELECT Modified, PlayCount FROM profile_tracks WHERE ProfileId = ?1 AND FilePath = ?2",
+            &[&change.profile_id, &file_path],
+            |row| Ok((row.get::<_, Option<String>>(0)?, row.get::<_, i32>(1)?)),
+        )
+        .optional()?;
+    let last_played = "LastPlayed = CASE WHEN LastPlayed IS NULL OR excluded.LastPlayed > LastPlayed
+        THEN excluded.LastPlayed ELSE LastPlayed END";
+    let newer = local
+        .as_ref()
+        .is_none_or(|(modified, _)| modified.as_ref().is_none_or(|modified| &change.modified > modified));
+    let query = if newer {
+        // A greater play count kept from here is a change the other instance does not have yet.
+        format!(
+            "ON CONFLICT(ProfileId, FilePath) DO UPDATE SET
+                Rating = excluded.Rating,
+                Favorite = excluded.Favorite,
+                {},
+                Modified = CASE WHEN PlayCount > excluded.PlayCount THEN {} ELSE excluded.Modified END,
+                PlayCount = MAX(PlayCount, excluded.PlayCount)",
+            last_played, MODIFIED_NOW
+        )
+    } else if local.is_some_and(|(_, play_count)| change.play_count > play_count) {
+        format!(
+            "ON CONFLICT(ProfileId, FilePath) DO UPDATE SET PlayCount = excluded.PlayCount, {}",
+            last_played
+        )
+    } else {
+        return Ok(false);
+    };
+    conn.execute(
+        &format!(
+            "INSERT INTO profile_tracks(ProfileId, FilePath, PlayCount, Rating, Favorite, LastPlayed, Modified)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7) {}",
+            query
+        ),
+        &[
+            &change.profile_id as &dyn ToSql,
+            &file_path,
+            &change.play_count,
+            &change.rating,
+            &change.favorite,
+            &change.last_played,
+            &change.modified,
+        ],
+    )?;
+    remember_synced(peer, "stats", &change.profile_id, &file_path, &change.modified, conn)?;
+    Ok(true)
+}
+
+/// Replaces the playlist with the change, if it is newer than the playlist
+/// and the last removal of a playlist of the same name. Returns whether it was applied.
+fn apply_playlist(change: &PlaylistChange, peer: Option<&str>, conn: &Connection) -> rusqlite::Result<bool> {
+    let removed = conn
+        .query_row(
+            "SELECT Modified FROM removed_playlists WHERE ProfileId = ?1 AND Name = ?2",
+            &[&change.profile_id, &change.name],
+            |row| row.get::<_, String>(0),
+        )
+        .optional()?;
+    let modified = conn
+        .query_row(
+            "SELECT Modified FROM playlists WHERE ProfileId = ?1 AND Name = ?2",
+            &[&change.profile_id, &change.name],
+            |row| row.get::<_, Option<String>>(0),
+        )
+        .optional()?
+        .flatten();
+    if removed.iter().chain(modified.iter()).any(|modified| modified >= &change.modified) {
+        return Ok(false);
+    }
+    ensure_profile(&change.profile_id, &change.profile_name, conn)?;
+    let playlist_id = create_playlist(&change.profile_id, &change.name, conn)?;
+    conn.execute("DELETE FROM playlist_tracks WHERE PlaylistId = ?1", &[&playlist_id])?;
+    for track in &change.tracks {
+        if let Some(file_path) = find_same_track(track, conn)? {
+            add_to_playlist(playlist_id, Path::new(&file_path), conn)?;
+        }
+    }
+    // Adding the tracks modified the playlist, so its modification time is set last.
+    conn.execute(
+        "UPDATE playlists SET Modified = ?2 WHERE PlaylistId = ?1",
+        &[&playlist_id as &dyn ToSql, &change.modified],
+    )?;
+    remember_synced(peer, "playlist", &change.profile_id, &change.name, &change.modified, conn)?;
+    Ok(true)
+}
+
+/// Removes the playlist if it was last modified before it was removed. Returns whether it was removed.
+fn apply_removal(removal: &PlaylistRemoval, peer: Option<&str>, conn: &Connection) -> rusqlite::Result<bool> {
+    let removed = conn.execute(
+        "DELETE FROM playlists WHERE ProfileId = ?1 AND Name = ?2 AND IFNULL(Modified, '') < ?3",
+        &[&removal.profile_id, &removal.name, &removal.modified],
+    )?;
+    if removed == 0 {
+        return Ok(false);
+    }
+    conn.execute(
+        "UPDATE removed_playlists SET Modified = ?3 WHERE ProfileId = ?1 AND Name = ?2",
+        &[&removal.profile_id, &removal.name, &removal.modified],
+    )?;
+    remember_synced(peer, "removal", &removal.profile_id, &removal.name, &removal.modified, conn)?;
+    Ok(true)
+}
+
+/// Applies the changes of another instance, keeping the changes made here that are newer.
+/// Returns the number of changes applied. The changes applied are remembered as applied from
+/// the peer, if it is given, so they are not sent back to it.
+///
+/// The changes are applied in a single transaction, so they are either applied in full or not at all.
+pub fn apply_changes(changes: &ChangeSet, peer: Option<&str>, conn: &Connection) -> rusqlite::Result<usize> {
+    let transaction = Transaction::new_unchecked(conn, TransactionBehavior::Immediate)?;
+    let mut applied = 0;
+    for change in &changes.stats {
+        applied += apply_stats(change, peer, &transaction)? as usize;
+    }
+    for change in &changes.playlists {
+        applied += apply_playlist(change, peer, &transaction)? as usize;
+    }
+    for removal in &changes.removed_playlists {
+        applied += apply_removal(removal, peer, &transaction)? as usize;
+    }
+    transaction.commit()?;
+    Ok(applied)
+}
+
+fn write_line<T: serde::Serialize>(stream: &mut TcpStream, message: &T) -> io::Result<()> {
+    let mut line = serde_json::to_string(message)?;
+    line.push('\n');
+    stream.write_all(line.as_bytes())
+}
+
+fn read_line<T: serde::de::DeserializeOwned>(reader: &mut BufReader<&TcpStream>) -> io::Result<T> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    Ok(serde_json::from_str(&line)?)
+}
+
+/// Sends the file of the track, if it is in the library and its file can be read.
+fn send_file(stream: &mut TcpStream, track: &TrackKey, conn: &Connection) -> Result<()> {
+    let failed = || Error::FileIOError(PathBuf::from(&track.title));
+    let file_path = find_same_track(track, conn).map_err(|_| failed())?.map(PathBuf::from);
+    let (file_path, mut file) = match file_path.and_then(|path| Some((File::open(&path).ok()?, path))) {
+        Some((file, file_path)) => (file_path, file),
+        None => return write_line(stream, &SyncResponse::NotFound).map_err(|_| failed()),
+    };
+    let size = file.metadata().map_err(|_| failed())?.len();
+    let extension = file_path
+        .extension()
+        .map(|extension| extension.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    write_line(stream, &SyncResponse::File { extension, size }).map_err(|_| failed())?;
+    io::copy(&mut file, stream).map_err(|_| failed())?;
+    Ok(())
+}
+
+fn handle_request(mut stream: TcpStream, config: &NetworkConfig, pool: &ConnectionPool) -> Result<()> {
+    let peer = stream.peer_addr().map(|address| address.to_string()).unwrap_or_default();
+    let failed = || Error::SyncFailed(peer.clone());
+    stream.set_read_timeout(Some(TIMEOUT)).map_err(|_| failed())?;
+    stream.set_write_timeout(Some(TIMEOUT)).map_err(|_| failed())?;
+    let request = read_line::<SyncRequest>(&mut BufReader::new(&stream)).map_err(|_| failed())?;
+    if let Err(err) = authorize(config, request.token.as_deref(), request.kind.scope()) {
+        write_line(&mut stream, &SyncResponse::Rejected(err.to_string())).ok();
+        return Err(err);
+    }
+    let conn = pool.get().map_err(|_| failed())?;
+    let response = match request.kind {
+        RequestKind::Sync { since, changes: received, origin } => {
+            // Changes are read before the changes of the request are applied, so they are not sent back,
+            // and the changes applied are remembered, so they are not sent back by later syncs either.
+            let now = modified_now(&conn).map_err(|_| failed())?;
+            let changes = get_changes_since(since.as_deref(), origin.as_deref(), &conn).map_err(|_| failed())?;
+            apply_changes(&received, origin.as_deref(), &conn).map_err(|_| failed())?;
+            SyncResponse::Accepted { now, changes }
+        }
+        RequestKind::ListTracks => SyncResponse::Tracks(get_track_keys(&conn).map_err(|_| failed())?),
+        RequestKind::Fetch(track) => return send_file(&mut stream, &track, &conn),
+    };
+    write_line(&mut stream, &response).map_err(|_| failed())
+}
+
+/// Serves sync to other instances on the configured address and port, reporting requests that
+/// failed to `report`. This blocks for as long as the listener works.
+///
+/// Fails if the address is not localhost, see `validate_network_config`.
+pub fn serve<R>(config: &NetworkConfig, pool: &ConnectionPool, report: R) -> Result<()>
+where
+    R: Fn(Event),
+{
+    validate_network_config(config)?;
+    let address = format!("{}:{}", config.bind_address, config.port);
+    let listener = TcpListener::bind(&address).map_err(|_| Error::SyncFailed(address.clone()))?;
+    for stream in listener.incoming().flatten() {
+        let peer = stream.peer_addr().map(|address| address.to_string()).unwrap_or_default();
+        if handle_request(stream, config, pool).is_err() {
+            report(Event::SyncError(peer));
+        }
+    }
+    Ok(())
+}
+
+/// Sends the request to the peer, returning the stream to read the response from.
+fn send_request(peer: &SyncPeer, kind: RequestKind) -> io::Result<TcpStream> {
+    let mut stream = TcpStream::connect(&peer.address)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let request = SyncRequest {
+        token: Some(peer.token.to_owned()),
+        kind,
+    };
+    write_line(&mut stream, &request)?;
+    Ok(stream)
+}
+
+/// Syncs with the peer, sending the changes made here since the last sync with it,
+/// and applying the changes it made since then. Returns the number of changes received and sent.
+pub fn sync_with(peer: &SyncPeer, conn: &Connection) -> Result<(usize, usize)> {
+    let failed = || Error::SyncFailed(peer.address.clone());
+    let (pulled, pushed) = conn
+        .query_row(
+            "SELECT Pulled, Pushed FROM sync_peers WHERE Peer = ?1",
+            &[&peer.address],
+            |row| Ok((row.get::<_, Option<String>>(0)?, row.get::<_, Option<String>>(1)?)),
+        )
+        .optional()
+        .map_err(|_| failed())?
+        .unwrap_or((None, None));
+    let pushing = modified_now(conn).map_err(|_| failed())?;
+    let changes = get_changes_since(pushed.as_deref(), Some(&peer.address), conn).map_err(|_| failed())?;
+    let sent = changes.len();
+    let origin = Some(get_instance_id(conn).map_err(|_| failed())?);
+
+    let stream = send_request(peer, RequestKind::Sync { since: pulled, changes, origin }).map_err(|_| failed())?;
+    let (now, received) = match read_line::<SyncResponse>(&mut BufReader::new(&stream)).map_err(|_| failed())? {
+        SyncResponse::Accepted { now, changes } => (now, changes),
+        SyncResponse::Rejected(_) => return Err(Error::Unauthorized),
+        _ => return Err(failed()),
+    };
+
+    apply_changes(&received, Some(&peer.address), conn).map_err(|_| failed())?;
+    conn.execute(
+        "INSERT OR REPLACE INTO sync_peers(Peer, Pulled, Pushed) VALUES (?1, ?2, ?3)",
+        &[&peer.address, &now, &pushing],
+    )
+    .map_err(|_| failed())?;
+    Ok((received.len(), sent))
+}
+
+/// Gets the keys of the tracks the peer has that are not in this library.
+pub fn get_missing_tracks(peer: &SyncPeer, conn: &Connection) -> Result<Vec<TrackKey>> {
+    let failed = || Error::SyncFailed(peer.address.clone());
+    let stream = send_request(peer, RequestKind::ListTracks).map_err(|_| failed())?;
+    let tracks = match read_line::<SyncResponse>(&mut BufReader::new(&stream)).map_err(|_| failed())? {
+        SyncResponse::Tracks(tracks) => tracks,
+        SyncResponse::Rejected(_) => return Err(Error::Unauthorized),
+        _ => return Err(failed()),
+    };
+    let mut missing = Vec::new();
+    for track in tracks {
+        if find_same_track(&track, conn).map_err(|_| failed())?.is_none() {
+            missing.push(track);
+        }
+    }
+    Ok(missing)
+}
+
+/// Downloads the file of the track from the peer into the given folder, returning its path,
+/// or `None` if the peer no longer has the track.
+///
+/// A file that could not be downloaded in full is removed.
+pub fn pull_track(peer: &SyncPeer, track: &TrackKey, folder: &Path) -> Result<Option<PathBuf>> {
+    let failed = || Error::SyncFailed(peer.address.clone());
+    let stream = send_request(peer, RequestKind::Fetch(track.clone())).map_err(|_| failed())?;
+    let mut reader = BufReader::new(&stream);
+    let (extension, size) = match read_line::<SyncResponse>(&mut reader).map_err(|_| failed())? {
+        SyncResponse::File { extension, size } => (extension, size),
+        SyncResponse::NotFound => return Ok(None),
+        SyncResponse::Rejected(_) => return Err(Error::Unauthorized),
+        _ => return Err(failed()),
+    };
+    fs::create_dir_all(folder).map_err(|_| Error::UnableToCreateDirectory(folder.display().to_string()))?;
+    let file_name = thread_rng().sample_iter(&Alphanumeric).take(16).collect::<String>();
+    let mut file_path = folder.join(file_name);
+    file_path.set_extension(extension);
+    let mut file = File::create(&file_path).map_err(|_| Error::FileIOError(file_path.clone()))?;
+    let written = io::copy(&mut reader.by_ref().take(size), &mut file);
+    if written.ok() != Some(size) {
+        drop(file);
+        fs::remove_file(&file_path).ok();
+        return Err(failed());
+    }
+    Ok(Some(file_path))
+}
+
+/// Downloads every track the peer has that is not in this library, and imports each of them
+/// like any other file found in the watch folder, reporting the result of every import.
+/// Returns the number of tracks downloaded.
+///
+/// Files are downloaded into the `transfers` folder of the application directory.
+pub fn pull_missing_tracks<R>(peer: &SyncPeer, config: &Config, conn: &Connection, report: R) -> Result<usize>
+where
+    R: Fn(Event),
+{
+    let folder = get_appdata_path().join("transfers");
+    let mut pulled = 0;
+    for track in get_missing_tracks(peer, conn)? {
+        if let Some(file_path) = pull_track(peer, &track, &folder)? {
+            report(import::import_track(&file_path, config, conn, false));
+            // A file that could not be imported is not kept, so it is downloaded again next time.
+            fs::remove_file(&file_path).ok();
+            pulled += 1;
+        }
+    }
+    Ok(pulled)
+}
@@ -0,0 +1,149 @@
+//! Reports of import batches, kept as an audit trail of what the watcher did outside the
+//! event stream, which is only seen by whoever is listening at the time.
+//!
+//! After every batch, if reports are enabled, a JSON report and a plain text summary of the
+//! batch are written into the reports folder, named for the time the batch started. A report
+//! lists every file of the batch, every event it produced, and where every track, sidecar
+//! and archive ended up.
+
+use crate::config::Config;
+use crate::database::{get_track_by_uuid, Connection};
+use crate::events::Event;
+use crate::paths::{get_appdata_path, get_iterative_filename};
+use chrono::Local;
+use serde_derive::Serialize;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Something that happened to a file of an import batch.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct ReportEntry {
+    pub code: &'static str,
+    pub args: Vec<String>,
+    pub error: bool,
+    /// Where the file ended up, if it was moved into the library or unpacked.
+    pub destination: Option<String>,
+}
+
+/// The report of an import batch.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct BatchReport {
+    /// The time the batch started, such as `2018-04-01 12:30:00`.
+    pub started: String,
+    pub finished: String,
+    /// Every file of the batch, as found in the watch folder.
+    pub files: Vec<String>,
+    /// The number of tracks added to the library.
+    pub imported: usize,
+    /// The number of entries that are errors.
+    pub errors: usize,
+    pub entries: Vec<ReportEntry>,
+}
+
+/// Gets the folder reports are written into, as configured.
+pub fn get_reports_path(config: &Config) -> PathBuf {
+    match &config.reports.folder {
+        Some(folder) => PathBuf::from(folder),
+        None => {
+            let mut reports_path = get_appdata_path();
+            reports_path.push("reports");
+            reports_path
+        }
+    }
+}
+
+/// The current time, as reports record it.
+pub fn timestamp() -> String {
+    Local::now().format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+/// Where the file the event is about ended up. Tracks are found by their UUID,
+/// so this must be called after the batch was committed.
+fn destination(event: &Event, conn: &Connection) -> Option<String> {
+    match event {
+        Event::TrackAdded { uuid, .. } => get_track_by_uuid(uuid, conn)
+            .ok()
+            .flatten()
+            .map(|track| track.file_path.to_string_lossy().into_owned()),
+        Event::SidecarAdded(path) => Some(path.to_owned()),
+        Event::ArchiveUnpacked(_, folder) | Event::RipImported(_, folder) => Some(folder.to_owned()),
+        _ => None,
+    }
+}
+
+/// Builds the report of the batch of files that started at `started`, from every event it produced.
+pub fn build_report(started: String, files: &[PathBuf], events: &[Event], conn: &Connection) -> BatchReport {
+    let entries = events
+        .iter()
+        .map(|event| ReportEntry {
+            code: event.code(),
+            args: event.args().into_iter().map(|arg| arg.into_owned()).collect(),
+            error: event.is_error(),
+            destination: destination(event, conn),
+        })
+        .collect::<Vec<ReportEntry>>();
+    BatchReport {
+        started,
+        finished: timestamp(),
+        files: files.iter().map(|file| file.to_string_lossy().into_owned()).collect(),
+        imported: events
+            .iter()
+            .filter(|event| matches!(event, Event::TrackAdded { .. }))
+            .count(),
+        errors: entries.iter().filter(|entry| entry.error).count(),
+        entries,
+    }
+}
+
+/// Writes the report as a summary a person can read.
+pub fn summary(report: &BatchReport) -> String {
+    let mut summary = format!(
+        "Import batch started {}, finished {}\n{} of {} files imported, {} errors\n\n",
+        report.started,
+        report.finished,
+        report.imported,
+        report.files.len(),
+        report.errors
+    );
+    for entry in &report.entries {
+        let marker = if entry.error { "!" } else { " " };
+        summary.push_str(&format!("{} {:<16} {}", marker, entry.code, entry.args.join(" | ")));
+        if let Some(destination) = &entry.destination {
+            summary.push_str(&format!("\n    -> {}", destination));
+        }
+        summary.push('\n');
+    }
+    summary
+}
+
+/// Writes the report into the folder as a JSON file and a plain text summary with the same name,
+/// returning the path of the JSON file.
+pub fn write_report(report: &BatchReport, folder: &Path) -> io::Result<PathBuf> {
+    fs::create_dir_all(folder)?;
+    let name = report.started.replace(':', "-");
+    let json_path = get_iterative_filename(&name, "json", folder);
+    let json = serde_json::to_string_pretty(report).map_err(io::Error::other)?;
+    fs::write(&json_path, json)?;
+    fs::write(json_path.with_extension("txt"), summary(report))?;
+    Ok(json_path)
+}
+
+/// Builds the report of the batch and writes it into the configured reports folder,
+/// reporting `Event::ReportError` if it could not be written.
+pub fn report_batch<R>(
+    started: String,
+    files: &[PathBuf],
+    events: &[Event],
+    config: &Config,
+    conn: &Connection,
+    report: R,
+) where
+    R: Fn(Event),
+{
+    let folder = get_reports_path(config);
+    let batch_report = build_report(started, files, events, conn);
+    if write_report(&batch_report, &folder).is_err() {
+        report(Event::ReportError(folder.to_string_lossy().into_owned()));
+    }
+}
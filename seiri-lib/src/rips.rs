@@ -0,0 +1,490 @@
+//! Intake of CDs ripped with Exact Audio Copy or whipper, from the folder the ripper writes to.
+//!
+//! Every folder of `RipConfig::folder` holding a rip log is a rip, taken in once nothing in it
+//! has changed for a while, since rippers write the log only once the whole CD is ripped. The log
+//! is read to verify the rip: every track must have been copied without errors, with the same CRC
+//! when it was tested as when it was copied, and every audio file of the folder must be a track
+//! of the log. Rips that fail are moved into the not added folder.
+//!
+//! Verified rips are moved into the watch folder as `CD_<folder>`, so they are imported as an
+//! album with `CD` as their source, along with their log as a sidecar. What AccurateRip found
+//! of each track is recorded as its provenance. WAV files, which can not be tagged, are first
+//! transcoded to FLAC by the `flac` transcoder, and rips of WAV files are rejected without one.
+//!
+//! Only logs in English are understood, and range rips of a whole CD are not.
+
+use crate::config::Config;
+use crate::database::Connection;
+use crate::downloads::{get_iterative_folder, move_folder_contents};
+use crate::events::Event;
+use crate::paths;
+use crate::provenance::{self, AccurateRip, RipProvenance};
+use crate::variants;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use walkdir::WalkDir;
+
+/// How long nothing in a rip must have changed before it is taken in.
+const SETTLE_TIME: Duration = Duration::from_secs(30);
+
+/// The extensions of the audio files rippers write.
+const AUDIO_EXTENSIONS: &[&str] = &["flac", "wav", "wv", "ape", "m4a", "mp3", "ogg", "opus", "aif", "aiff"];
+
+/// A track of a rip, as its log recorded it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RippedTrack {
+    /// The name of the file the track was ripped to, without its extension.
+    pub file_stem: String,
+    /// Whether the ripper copied the track without errors.
+    pub copy_ok: bool,
+    pub test_crc: Option<String>,
+    pub copy_crc: Option<String>,
+    pub accurate_rip: AccurateRip,
+}
+
+/// The log of a rip.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RipLog {
+    /// The program the CD was ripped with, along with its version.
+    pub ripper: String,
+    /// Whether the log ends with the checksum of its ripper.
+    pub log_checksum: bool,
+    pub tracks: Vec<RippedTrack>,
+}
+
+/// Why a rip failed to be verified.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RipProblem {
+    /// No log of the folder could be read as a rip log.
+    NoLog,
+    /// The log recorded no tracks.
+    NoTracks,
+    /// The track was not copied without errors.
+    CopyErrors(String),
+    /// The track was copied with a different CRC than it was tested with.
+    CrcMismatch(String),
+    /// AccurateRip found the track to be inaccurate, and only accurate rips are taken in.
+    Inaccurate(String),
+    /// The log recorded the track, but there is no file of it.
+    MissingFile(String),
+    /// The audio file is not a track of the log.
+    UnknownFile(String),
+    /// The WAV file could not be transcoded to FLAC.
+    Unconvertible(String),
+}
+
+impl fmt::Display for RipProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RipProblem::NoLog => write!(f, "No rip log could be read"),
+            RipProblem::NoTracks => write!(f, "The rip log records no tracks"),
+            RipProblem::CopyErrors(track) => write!(f, r#""{}" was copied with errors"#, track),
+            RipProblem::CrcMismatch(track) => write!(f, r#""{}" was copied with a different CRC than it was tested with"#, track),
+            RipProblem::Inaccurate(track) => write!(f, r#""{}" is not an accurate rip"#, track),
+            RipProblem::MissingFile(track) => write!(f, r#""{}" is in the rip log, but was not found"#, track),
+            RipProblem::UnknownFile(file) => write!(f, r#""{}" is not in the rip log"#, file),
+            RipProblem::Unconvertible(file) => write!(f, r#""{}" could not be transcoded to FLAC"#, file),
+        }
+    }
+}
+
+/// Reads a rip log, which Exact Audio Copy writes in UTF-16.
+fn decode_log(bytes: &[u8]) -> String {
+    match bytes {
+        [0xFF, 0xFE, rest @ ..] => {
+            let units = rest
+                .chunks_exact(2)
+                .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+                .collect::<Vec<u16>>();
+            String::from_utf16_lossy(&units)
+        }
+        [0xEF, 0xBB, 0xBF, rest @ ..] => String::from_utf8_lossy(rest).into_owned(),
+        _ => String::from_utf8_lossy(bytes).into_owned(),
+    }
+}
+
+/// Gets the file stem of a path the ripper recorded, which may be a path of another platform.
+fn recorded_file_stem(path: &str) -> String {
+    let file_name = path.trim().rsplit(['\\', '/']).next().unwrap_or_default();
+    Path::new(file_name)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// Gets the number following `(confidence ` in a line of an Exact Audio Copy log.
+fn parse_confidence(line: &str) -> u32 {
+    line.split("(confidence ")
+        .nth(1)
+        .and_then(|rest| rest.split(')').next())
+        .and_then(|confidence| confidence.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+/// Picks what AccurateRip found with the most certainty, such as when a track was verified by
+/// both versions of AccurateRip.
+fn most_certain(a: AccurateRip, b: AccurateRip) -> AccurateRip {
+    let rank = |result: AccurateRip| match result {
+        AccurateRip::Accurate { confidence } => (3, confidence),
+        AccurateRip::Inaccurate { confidence } => (2, confidence),
+        AccurateRip::NotPresent => (1, 0),
+        AccurateRip::Unknown => (0, 0),
+    };
+    if rank(b) > rank(a) {
+        b
+    } else {
+        a
+    }
+}
+
+fn new_track() -> RippedTrack {
+    RippedTrack {
+        file_stem: String::new(),
+        copy_ok: false,
+        test_crc: None,
+        copy_crc: None,
+        accurate_rip: AccurateRip::Unknown,
+    }
+}
+
+/// Reads a log written by Exact Audio Copy.
+fn parse_eac_log(log: &str) -> RipLog {
+    let mut lines = log.lines().map(str::trim);
+    let ripper = lines
+        .next()
+        .map(|line| line.split(" from ").next().unwrap_or(line).to_owned())
+        .unwrap_or_default();
+    let mut tracks: Vec<RippedTrack> = Vec::new();
+    let mut log_checksum = false;
+    for line in lines {
+        if line.starts_with("==== Log checksum") {
+            log_checksum = true;
+        }
+        // Other lines also start with `Track`, such as those of the summary of AccurateRip.
+        if let Some(number) = line.strip_prefix("Track ") {
+            if number.trim().parse::<u32>().is_ok() {
+                tracks.push(new_track());
+                continue;
+            }
+        }
+        let track = match tracks.last_mut() {
+            Some(track) => track,
+            None => continue,
+        };
+        if let Some(file_name) = line.strip_prefix("Filename ") {
+            track.file_stem = recorded_file_stem(file_name);
+        } else if let Some(crc) = line.strip_prefix("Test CRC ") {
+            track.test_crc = Some(crc.trim().to_uppercase());
+        } else if let Some(crc) = line.strip_prefix("Copy CRC ") {
+            track.copy_crc = Some(crc.trim().to_uppercase());
+        } else if line.starts_with("Accurately ripped") {
+            let confidence = parse_confidence(line);
+            track.accurate_rip = most_certain(track.accurate_rip, AccurateRip::Accurate { confidence });
+        } else if line.starts_with("Cannot be verified as accurate") {
+            let confidence = parse_confidence(line);
+            track.accurate_rip = most_certain(track.accurate_rip, AccurateRip::Inaccurate { confidence });
+        } else if line.starts_with("Track not present in AccurateRip database") {
+            track.accurate_rip = most_certain(track.accurate_rip, AccurateRip::NotPresent);
+        } else if line == "Copy OK" {
+            track.copy_ok = true;
+        }
+    }
+    RipLog {
+        ripper,
+        log_checksum,
+        tracks,
+    }
+}
+
+/// Reads a log written by whipper, which is laid out as YAML.
+fn parse_whipper_log(log: &str) -> RipLog {
+    let mut lines = log.lines().map(str::trim);
+    let ripper = lines
+        .next()
+        .and_then(|line| line.strip_prefix("Log created by:"))
+        .map(|ripper| ripper.split(" (").next().unwrap_or(ripper).trim().to_owned())
+        .unwrap_or_default();
+    let mut tracks: Vec<RippedTrack> = Vec::new();
+    let mut log_checksum = false;
+    let mut in_tracks = false;
+    // What the AccurateRip version being read found, until its confidence is read.
+    let mut result = AccurateRip::Unknown;
+    for line in lines {
+        if line.starts_with("SHA-256 hash:") {
+            log_checksum = true;
+        }
+        match line {
+            "Tracks:" => in_tracks = true,
+            "Conclusive status report:" => in_tracks = false,
+            _ => (),
+        }
+        if !in_tracks {
+            continue;
+        }
+        if let Some(number) = line.strip_suffix(':') {
+            if number.parse::<u32>().is_ok() {
+                tracks.push(new_track());
+                continue;
+            }
+        }
+        let track = match tracks.last_mut() {
+            Some(track) => track,
+            None => continue,
+        };
+        let (key, value) = match line.split_once(':') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => continue,
+        };
+        match key {
+            "Filename" => track.file_stem = recorded_file_stem(value),
+            "Test CRC" => track.test_crc = Some(value.to_uppercase()),
+            "Copy CRC" => track.copy_crc = Some(value.to_uppercase()),
+            "Status" => track.copy_ok = value == "Copy OK",
+            "Result" => {
+                let value = value.to_lowercase();
+                result = if value.contains("not present") {
+                    AccurateRip::NotPresent
+                } else if value.contains("no exact match") {
+                    AccurateRip::Inaccurate { confidence: 0 }
+                } else if value.contains("exact match") {
+                    AccurateRip::Accurate { confidence: 0 }
+                } else {
+                    AccurateRip::Unknown
+                };
+                track.accurate_rip = most_certain(track.accurate_rip, result);
+            }
+            "Confidence" => {
+                let confidence = value.parse().unwrap_or(0);
+                result = match result {
+                    AccurateRip::Accurate { .. } => AccurateRip::Accurate { confidence },
+                    AccurateRip::Inaccurate { .. } => AccurateRip::Inaccurate { confidence },
+                    result => result,
+                };
+                track.accurate_rip = most_certain(track.accurate_rip, result);
+            }
+            _ => (),
+        }
+    }
+    RipLog {
+        ripper,
+        log_checksum,
+        tracks,
+    }
+}
+
+/// Reads a rip log written by Exact Audio Copy or whipper, or `None` if it is neither.
+pub fn parse_rip_log(bytes: &[u8]) -> Option<RipLog> {
+    let log = decode_log(bytes);
+    let first_line = log.lines().map(str::trim).find(|line| !line.is_empty())?;
+    if first_line.starts_with("Exact Audio Copy") {
+        Some(parse_eac_log(&log))
+    } else if first_line.starts_with("Log created by: whipper") {
+        Some(parse_whipper_log(&log))
+    } else {
+        None
+    }
+}
+
+impl RipLog {
+    /// Verifies that every track was copied without errors, with the CRC it was tested with,
+    /// and was found accurate by AccurateRip if `require_accurate` is set.
+    pub fn verify(&self, require_accurate: bool) -> Result<(), RipProblem> {
+        if self.tracks.is_empty() {
+            return Err(RipProblem::NoTracks);
+        }
+        for track in &self.tracks {
+            if !track.copy_ok {
+                return Err(RipProblem::CopyErrors(track.file_stem.to_owned()));
+            }
+            if let (Some(test_crc), Some(copy_crc)) = (&track.test_crc, &track.copy_crc) {
+                if test_crc != copy_crc {
+                    return Err(RipProblem::CrcMismatch(track.file_stem.to_owned()));
+                }
+            }
+            if require_accurate {
+                if let AccurateRip::Inaccurate { .. } = track.accurate_rip {
+                    return Err(RipProblem::Inaccurate(track.file_stem.to_owned()));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Gets the provenance of the track.
+    pub fn provenance(&self, track: &RippedTrack) -> RipProvenance {
+        RipProvenance {
+            ripper: self.ripper.to_owned(),
+            accurate_rip: track.accurate_rip,
+            copy_crc: track.copy_crc.to_owned(),
+            log_checksum: self.log_checksum,
+        }
+    }
+}
+
+fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+        .is_some_and(|extension| extensions.contains(&extension.as_str()))
+}
+
+fn file_stem(path: &Path) -> String {
+    path.file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// Gets the files directly in the folder.
+fn list_files(folder: &Path) -> Vec<PathBuf> {
+    match fs::read_dir(folder) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file())
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Finds the folders within the folder, including itself, that hold a rip log.
+pub fn find_rips(folder: &Path) -> Vec<PathBuf> {
+    WalkDir::new(folder)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file() && has_extension(entry.path(), &["log"]))
+        .filter_map(|entry| entry.path().parent().map(Path::to_path_buf))
+        .collect::<BTreeSet<PathBuf>>()
+        .into_iter()
+        .collect()
+}
+
+/// Whether nothing in the folder has changed for `SETTLE_TIME`.
+fn is_settled(folder: &Path) -> bool {
+    list_files(folder).iter().all(|file| {
+        file.metadata()
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+            .is_some_and(|age| age >= SETTLE_TIME)
+    })
+}
+
+/// Reads the first log in the folder that is a rip log.
+fn read_rip_log(files: &[PathBuf]) -> Option<RipLog> {
+    files
+        .iter()
+        .filter(|file| has_extension(file, &["log"]))
+        .find_map(|file| parse_rip_log(&fs::read(file).ok()?))
+}
+
+/// Matches every audio file of the rip to its track in the log, transcoding WAV files to FLAC.
+fn match_tracks<'a>(log: &'a RipLog, files: &[PathBuf], config: &Config) -> Result<Vec<(PathBuf, &'a RippedTrack)>, RipProblem> {
+    let mut tracks = log
+        .tracks
+        .iter()
+        .map(|track| (track.file_stem.to_lowercase(), track))
+        .collect::<HashMap<String, &RippedTrack>>();
+    let mut matched = Vec::new();
+    for file in files.iter().filter(|file| has_extension(file, AUDIO_EXTENSIONS)) {
+        let track = tracks
+            .remove(&file_stem(file).to_lowercase())
+            .ok_or_else(|| RipProblem::UnknownFile(file.display().to_string()))?;
+        matched.push((file.to_owned(), track));
+    }
+    if let Some(track) = tracks.values().next() {
+        return Err(RipProblem::MissingFile(track.file_stem.to_owned()));
+    }
+    for (file, _) in matched.iter_mut() {
+        if !has_extension(file, &["wav"]) {
+            continue;
+        }
+        let unconvertible = || RipProblem::Unconvertible(file.display().to_string());
+        let transcoder = variants::find_transcoder(&config.transcoders, "flac").ok_or_else(unconvertible)?;
+        let flac = file.with_extension("flac");
+        if !transcoder.run(file, &flac) {
+            return Err(unconvertible());
+        }
+        fs::remove_file(&*file).ok();
+        *file = flac;
+    }
+    Ok(matched)
+}
+
+/// Gets a folder of the watch folder named for the rip that does not exist yet.
+fn import_folder(rip: &Path, auto_add_path: &Path) -> PathBuf {
+    let name = paths::sanitize_file_name(&rip.file_name().map(|name| name.to_string_lossy()).unwrap_or_default());
+    get_iterative_folder(&format!("CD_{}", name), auto_add_path)
+}
+
+/// Moves every file of the rip into the folder, removing the folder of the rip unless it is the
+/// folder of the ripper itself, which is kept to rip into again.
+fn move_rip(rip: &Path, folder: &Path, config: &Config) -> bool {
+    if move_folder_contents(rip, folder).is_err() {
+        return false;
+    }
+    if config.rips.folder.as_deref().map(Path::new) != Some(rip) {
+        // Only hidden files are left, so the rip is still moved if they can not be removed.
+        fs::remove_dir_all(rip).ok();
+    }
+    true
+}
+
+/// Verifies the rip in the folder, and moves it into the watch folder to be imported, or into
+/// the not added folder if it could not be verified.
+pub fn intake_rip(rip: &Path, config: &Config, conn: &Connection) -> Event {
+    let rip_name = rip.display().to_string();
+    let auto_add_path = match paths::ensure_music_folder(&config.music_folder) {
+        Ok((_, auto_add_path)) => auto_add_path,
+        Err(_) => return Event::LibraryNotFound(rip_name),
+    };
+    let files = list_files(rip);
+    let verified = read_rip_log(&files)
+        .ok_or(RipProblem::NoLog)
+        .and_then(|log| log.verify(config.rips.require_accurate).map(|_| log))
+        .and_then(|log| {
+            let matched = match_tracks(&log, &files, config)?;
+            Ok(matched
+                .into_iter()
+                .map(|(file, track)| (file, log.provenance(track)))
+                .collect::<Vec<(PathBuf, RipProvenance)>>())
+        });
+    match verified {
+        Ok(tracks) => {
+            let folder = import_folder(rip, &auto_add_path);
+            // Provenance is recorded before the files are moved, so it is there once they are imported.
+            for (file, provenance) in &tracks {
+                if let Some(file_name) = file.file_name() {
+                    provenance::record_pending(&folder.join(file_name), provenance, conn).ok();
+                }
+            }
+            if !move_rip(rip, &folder, config) {
+                return Event::TrackMoveError(rip_name);
+            }
+            Event::RipImported(rip_name, folder.display().to_string())
+        }
+        Err(problem) => {
+            let rejected = paths::ensure_not_added(&auto_add_path)
+                .map(|not_added| import_folder(rip, &not_added))
+                .is_ok_and(|folder| move_rip(rip, &folder, config));
+            if !rejected {
+                return Event::TrackMoveError(rip_name);
+            }
+            Event::RipRejected(rip_name, problem.to_string())
+        }
+    }
+}
+
+/// Takes in every rip in the folder of the ripper that has settled, returning the event for each.
+pub fn intake_rips(config: &Config, conn: &Connection) -> Vec<Event> {
+    let folder = match &config.rips.folder {
+        Some(folder) => Path::new(folder),
+        None => return Vec::new(),
+    };
+    find_rips(folder)
+        .into_iter()
+        .filter(|rip| is_settled(rip))
+        .map(|rip| intake_rip(&rip, config, conn))
+        .collect()
+}
@@ -0,0 +1,112 @@
+//! The cache of files scanned by `library::bootstrap`, so rescanning a collection only reads the
+//! tags of files that changed since they were last scanned.
+//!
+//! A file is known by its path, the time it was last modified, and its size. Files whose three
+//! are the same as when they were last scanned are taken to be the same as they were, whether
+//! they were added as tracks, are not tracks at all, or could not be imported. Tracks are only
+//! taken to be unchanged while they are still in the library.
+
+use rusqlite::types::ToSql;
+use rusqlite::{Connection, OptionalExtension, Result, NO_PARAMS};
+use std::convert::TryFrom;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+pub fn create_scan_table(conn: &Connection) {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS scanned_files (
+        FilePath TEXT PRIMARY KEY,
+        Modified INTEGER NOT NULL,
+        Size INTEGER NOT NULL,
+        Result INTEGER NOT NULL
+    );",
+    )
+    .unwrap();
+}
+
+/// What came of scanning a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScanResult {
+    /// The file was added to the library as a track.
+    Track = 0,
+    /// The file is not a track, such as art or a booklet.
+    Unsupported = 1,
+    /// The file could not be read, or is missing tags required to be imported.
+    Failed = 2,
+}
+
+impl ScanResult {
+    fn from_code(code: i64) -> Option<ScanResult> {
+        match code {
+            0 => Some(ScanResult::Track),
+            1 => Some(ScanResult::Unsupported),
+            2 => Some(ScanResult::Failed),
+            _ => None,
+        }
+    }
+}
+
+/// The time a file was last modified, in nanoseconds since the epoch, and its size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileIdentity {
+    pub modified: i64,
+    pub size: i64,
+}
+
+/// Gets the identity of the file, if its metadata can be read.
+pub fn file_identity(path: &Path) -> Option<FileIdentity> {
+    let metadata = path.metadata().ok()?;
+    let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some(FileIdentity {
+        modified: i64::try_from(modified.as_nanos()).ok()?,
+        size: metadata.len() as i64,
+    })
+}
+
+/// Gets what came of the last scan of the file, if it has not changed since.
+pub fn get_cached_scan(path: &Path, identity: &FileIdentity, conn: &Connection) -> Result<Option<ScanResult>> {
+    let result: Option<i64> = conn
+        .query_row(
+            "SELECT Result FROM scanned_files
+            WHERE FilePath = ?1 AND Modified = ?2 AND Size = ?3
+            AND (Result != 0 OR EXISTS (SELECT 1 FROM tracks WHERE tracks.FilePath = scanned_files.FilePath))",
+            &[&path.to_string_lossy().into_owned() as &dyn ToSql, &identity.modified, &identity.size],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(result.and_then(ScanResult::from_code))
+}
+
+/// Remembers what came of scanning the file.
+pub fn cache_scan(path: &Path, identity: &FileIdentity, result: ScanResult, conn: &Connection) -> Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO scanned_files(FilePath, Modified, Size, Result) VALUES (?1, ?2, ?3, ?4)",
+        &[
+            &path.to_string_lossy().into_owned() as &dyn ToSql,
+            &identity.modified,
+            &identity.size,
+            &(result as i64),
+        ],
+    )?;
+    Ok(())
+}
+
+/// Forgets every file in the folder and its subfolders that is gone. Returns how many were forgotten.
+pub fn forget_missing(folder: &Path, conn: &Connection) -> Result<usize> {
+    let mut statement = conn.prepare("SELECT FilePath FROM scanned_files WHERE substr(FilePath, 1, length(?1)) = ?1")?;
+    let missing = statement
+        .query_map(&[&folder.to_string_lossy().into_owned()], |row| row.get::<_, String>(0))?
+        .collect::<Result<Vec<String>>>()?
+        .into_iter()
+        .filter(|path| !Path::new(path).exists())
+        .collect::<Vec<String>>();
+    for path in &missing {
+        conn.execute("DELETE FROM scanned_files WHERE FilePath = ?1", &[path])?;
+    }
+    Ok(missing.len())
+}
+
+/// Forgets every scanned file, so the next scan reads every file again. Returns how many were forgotten.
+pub fn clear_scan_cache(conn: &Connection) -> Result<usize> {
+    conn.execute("DELETE FROM scanned_files", NO_PARAMS)
+}
@@ -0,0 +1,67 @@
+//! Schedules, which keep background jobs that are heavy on the disk to the hours they are
+//! configured for, such as running analysis only overnight on a desktop.
+//!
+//! A job can have any number of windows, each from a time of day until another. A window
+//! that ends before it starts lasts past midnight, so `22:00` until `06:00` is overnight.
+//! A job without windows is not scheduled, and runs whenever it is asked to, as does the
+//! watcher, which is never scheduled. Scheduled jobs stop once their window closes,
+//! and carry on from where they stopped in the next window.
+
+use chrono::{Local, NaiveTime};
+use serde_derive::{Deserialize, Serialize};
+
+/// The background jobs that can be scheduled.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Job {
+    /// The analysis jobs, which decode every track not analyzed since it was last updated.
+    /// Once scheduled, they run by themselves within their windows.
+    Analysis,
+    /// Syncing with the configured peers, and pulling missing tracks from them.
+    Sync,
+    /// Maintenance of the database, which prunes cached images and compacts the library.
+    /// Once scheduled, it runs by itself once a day, when its window first opens.
+    Maintenance,
+}
+
+/// A window of the day a job is allowed to run in.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Schedule {
+    pub job: Job,
+    /// The time of day the window opens, such as `02:00`.
+    pub from: String,
+    /// The time of day the window closes, such as `07:00`.
+    pub until: String,
+}
+
+fn parse_time(time: &str) -> Option<NaiveTime> {
+    NaiveTime::parse_from_str(time.trim(), "%H:%M").ok()
+}
+
+impl Schedule {
+    /// Whether the window is open at the given time. Windows with times that can not be read
+    /// are never open, so a job is not run when the configuration meant to keep it from running.
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        match (parse_time(&self.from), parse_time(&self.until)) {
+            (Some(from), Some(until)) if from <= until => from <= time && time < until,
+            (Some(from), Some(until)) => from <= time || time < until,
+            _ => false,
+        }
+    }
+}
+
+/// Whether the job has any windows, rather than running whenever it is asked to.
+pub fn is_scheduled(job: Job, schedules: &[Schedule]) -> bool {
+    schedules.iter().any(|schedule| schedule.job == job)
+}
+
+/// Whether the job is allowed to run at the given time, which it always is if it is not scheduled.
+pub fn is_allowed_at(job: Job, schedules: &[Schedule], time: NaiveTime) -> bool {
+    let mut windows = schedules.iter().filter(|schedule| schedule.job == job).peekable();
+    windows.peek().is_none() || windows.any(|schedule| schedule.contains(time))
+}
+
+/// Whether the job is allowed to run now.
+pub fn is_allowed_now(job: Job, schedules: &[Schedule]) -> bool {
+    is_allowed_at(job, schedules, Local::now().time())
+}
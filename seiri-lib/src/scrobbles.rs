@@ -0,0 +1,189 @@
+//! Exports of the listening history of a profile, so plays recorded while offline can be
+//! scrobbled later.
+//!
+//! Plays are only kept in the listening history if it is enabled in the configuration, see
+//! `ListenConfig`. They are exported either for ListenBrainz, as JSON lines of listens to submit
+//! with the `import` listen type, or for Last.fm, as the CSV that scrobble backfill tools read.
+
+use crate::database::{track_from_row, Connection, TRACK_COLUMNS};
+use chrono::{TimeZone, Utc};
+use katatsuki::Track;
+use rusqlite::types::ToSql;
+use rusqlite::Result;
+use serde_derive::Serialize;
+use serde_json::{Map, Value};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// The most listens ListenBrainz accepts in a single submission.
+pub const MAX_LISTENS_PER_SUBMISSION: usize = 1000;
+
+/// A play of a track in the listening history of a profile.
+#[derive(Debug, Clone)]
+pub struct Listen {
+    /// When the track was played, in seconds since the Unix epoch.
+    pub listened_at: i64,
+    pub track: Track,
+}
+
+/// The formats the listening history is exported in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScrobbleFormat {
+    /// JSON lines, each a payload of at most `MAX_LISTENS_PER_SUBMISSION` listens that can be
+    /// posted to the `submit-listens` endpoint of ListenBrainz as it is.
+    ListenBrainz,
+    /// CSV without a header, with the columns artist, track, album, timestamp in UTC, album
+    /// artist and duration in seconds.
+    LastFm,
+}
+
+impl ScrobbleFormat {
+    /// Gets the format by its name, either `listenbrainz` or `lastfm`.
+    pub fn from_name(name: &str) -> Option<ScrobbleFormat> {
+        match name.trim().to_lowercase().as_str() {
+            "listenbrainz" => Some(ScrobbleFormat::ListenBrainz),
+            "lastfm" => Some(ScrobbleFormat::LastFm),
+            _ => None,
+        }
+    }
+}
+
+/// Gets every play in the listening history of the profile, from the oldest, optionally only those
+/// at or after the given time in seconds since the Unix epoch.
+pub fn get_listens(profile_id: &str, since: Option<i64>, conn: &Connection) -> Result<Vec<Listen>> {
+    let mut statement = conn.prepare(&format!(
+        "SELECT {}, profile_listens.ListenedAt FROM profile_listens
+            JOIN tracks ON tracks.FilePath = profile_listens.FilePath
+            WHERE profile_listens.ProfileId = ?1 AND profile_listens.ListenedAt >= IFNULL(?2, 0)
+            ORDER BY profile_listens.ListenedAt",
+        TRACK_COLUMNS
+    ))?;
+    let rows = statement.query_map(&[&profile_id as &dyn ToSql, &since], |row| {
+        Ok(Listen {
+            track: track_from_row(row)?,
+            listened_at: row.get(23)?,
+        })
+    })?;
+    rows.collect()
+}
+
+#[derive(Serialize)]
+struct ListenBrainzSubmission<'a> {
+    listen_type: &'static str,
+    payload: Vec<ListenBrainzListen<'a>>,
+}
+
+#[derive(Serialize)]
+struct ListenBrainzListen<'a> {
+    listened_at: i64,
+    track_metadata: ListenBrainzMetadata<'a>,
+}
+
+#[derive(Serialize)]
+struct ListenBrainzMetadata<'a> {
+    artist_name: &'a str,
+    track_name: &'a str,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    release_name: &'a str,
+    additional_info: Map<String, Value>,
+}
+
+fn listenbrainz_listen(listen: &Listen) -> ListenBrainzListen<'_> {
+    let track = &listen.track;
+    let mut additional_info = Map::new();
+    additional_info.insert("media_player".to_owned(), Value::from("seiri"));
+    additional_info.insert("submission_client".to_owned(), Value::from("seiri"));
+    if track.duration > 0 {
+        additional_info.insert("duration_ms".to_owned(), Value::from(track.duration));
+    }
+    if track.track_number > 0 {
+        additional_info.insert("tracknumber".to_owned(), Value::from(track.track_number));
+    }
+    if let Some(recording_mbid) = track.musicbrainz_track_id.as_deref().filter(|id| !id.is_empty()) {
+        additional_info.insert("recording_mbid".to_owned(), Value::from(recording_mbid));
+    }
+    if let Some(isrc) = track.isrc.as_deref().filter(|isrc| !isrc.is_empty()) {
+        additional_info.insert("isrc".to_owned(), Value::from(isrc));
+    }
+    let album_artists = track.album_artists.join(", ");
+    if !album_artists.is_empty() {
+        additional_info.insert("release_artist_name".to_owned(), Value::from(album_artists));
+    }
+    ListenBrainzListen {
+        listened_at: listen.listened_at,
+        track_metadata: ListenBrainzMetadata {
+            artist_name: &track.artist,
+            track_name: &track.title,
+            release_name: &track.album,
+            additional_info,
+        },
+    }
+}
+
+/// Writes the listens for ListenBrainz, as one submission of at most `MAX_LISTENS_PER_SUBMISSION`
+/// listens per line.
+pub fn to_listenbrainz(listens: &[Listen]) -> String {
+    listens
+        .chunks(MAX_LISTENS_PER_SUBMISSION)
+        .map(|chunk| {
+            let submission = ListenBrainzSubmission {
+                listen_type: "import",
+                payload: chunk.iter().map(listenbrainz_listen).collect(),
+            };
+            serde_json::to_string(&submission).unwrap() + "\n"
+        })
+        .collect()
+}
+
+/// Quotes the field of a CSV row if it needs to be.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+/// Writes the listens for Last.fm, as rows of CSV.
+pub fn to_lastfm_csv(listens: &[Listen]) -> String {
+    listens
+        .iter()
+        .map(|listen| {
+            let track = &listen.track;
+            let timestamp = Utc
+                .timestamp_opt(listen.listened_at, 0)
+                .single()
+                .map(|time| time.format("%Y-%m-%d %H:%M:%S").to_string())
+                .unwrap_or_default();
+            let duration = if track.duration > 0 { (track.duration / 1000).to_string() } else { String::new() };
+            format!(
+                "{},{},{},{},{},{}\n",
+                csv_field(&track.artist),
+                csv_field(&track.title),
+                csv_field(&track.album),
+                timestamp,
+                csv_field(&track.album_artists.join(", ")),
+                duration
+            )
+        })
+        .collect()
+}
+
+/// Exports the listening history of the profile into the file at the path in the format,
+/// optionally only the plays at or after the given time. Returns the number of plays exported.
+pub fn export_listens(
+    profile_id: &str,
+    format: ScrobbleFormat,
+    since: Option<i64>,
+    path: &Path,
+    conn: &Connection,
+) -> io::Result<usize> {
+    let listens = get_listens(profile_id, since, conn).map_err(io::Error::other)?;
+    let exported = match format {
+        ScrobbleFormat::ListenBrainz => to_listenbrainz(&listens),
+        ScrobbleFormat::LastFm => to_lastfm_csv(&listens),
+    };
+    fs::write(path, exported)?;
+    Ok(listens.len())
+}
@@ -0,0 +1,291 @@
+//! Search optimized for queries typed one character at a time.
+//!
+//! When a query only narrows the previous one, such as `!q{ab}` becoming `!q{abc}`,
+//! every track it matches is already in the previous results, so they are filtered
+//! in memory instead of querying the database again. The in-memory filter mirrors
+//! the SQL generated by `database::query_tracks` exactly, and any bang it can not
+//! mirror falls back to the database.
+
+use crate::aliases;
+use crate::bangs::{ms_to_ticks, Bang};
+use crate::cache::QueryCache;
+use crate::database;
+use crate::database::Connection;
+use crate::genres;
+use katatsuki::{Track, TrackFileType};
+use regex::Regex;
+use rusqlite::Result;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+/// Removes any groupings around the bang, which do not change what it matches.
+fn ungroup(bang: &Bang) -> &Bang {
+    match bang {
+        Bang::Grouping(inner) => ungroup(inner),
+        bang => bang,
+    }
+}
+
+/// Whether every track matching `bang` is guaranteed to also match `previous`.
+///
+/// This only recognizes the ways a query grows while it is being typed, and
+/// returns false whenever it can not be sure.
+pub fn refines(bang: &Bang, previous: &Bang) -> bool {
+    let (bang, previous) = (ungroup(bang), ungroup(previous));
+    if bang == previous {
+        return true;
+    }
+    match (bang, previous) {
+        (_, Bang::All) => true,
+        (Bang::LogicalAnd(lhs, rhs), Bang::LogicalAnd(previous_lhs, previous_rhs))
+            if refines(lhs, previous_lhs) && refines(rhs, previous_rhs) =>
+        {
+            true
+        }
+        (Bang::LogicalAnd(lhs, rhs), previous) => refines(lhs, previous) || refines(rhs, previous),
+        (Bang::TitleSearch(search), Bang::TitleSearch(previous))
+        | (Bang::FullTextSearch(search), Bang::FullTextSearch(previous))
+        | (Bang::AlbumTitle(search), Bang::AlbumTitle(previous))
+        | (Bang::AlbumArtists(search), Bang::AlbumArtists(previous))
+        | (Bang::Artist(search), Bang::Artist(previous)) => search.contains(previous.as_str()),
+        (Bang::BitrateLessThan(value), Bang::BitrateLessThan(previous))
+        | (Bang::CoverArtWidthLessThan(value), Bang::CoverArtWidthLessThan(previous))
+        | (Bang::CoverArtHeightLessThan(value), Bang::CoverArtHeightLessThan(previous)) => {
+            value <= previous
+        }
+        (Bang::BitrateGreaterThan(value), Bang::BitrateGreaterThan(previous))
+        | (Bang::CoverArtWidthGreaterThan(value), Bang::CoverArtWidthGreaterThan(previous))
+        | (Bang::CoverArtHeightGreaterThan(value), Bang::CoverArtHeightGreaterThan(previous)) => {
+            value >= previous
+        }
+        (Bang::DurationLessThan(value), Bang::DurationLessThan(previous)) => value <= previous,
+        (Bang::DurationGreaterThan(value), Bang::DurationGreaterThan(previous)) => {
+            value >= previous
+        }
+        (Bang::UpdatedBefore(date), Bang::UpdatedBefore(previous)) => date <= previous,
+        (Bang::UpdatedAfter(date), Bang::UpdatedAfter(previous)) => date >= previous,
+        _ => false,
+    }
+}
+
+/// Matches tracks against a bang in memory, the same way SQLite would.
+struct Filter {
+    regexes: HashMap<String, Regex>,
+    /// Every name of every artist with aliases, starting with its canonical name.
+    artists: Vec<Vec<String>>,
+}
+
+impl Filter {
+    fn new(aliases: BTreeMap<String, Vec<String>>) -> Filter {
+        Filter {
+            regexes: HashMap::new(),
+            artists: aliases
+                .into_iter()
+                .map(|(artist, aliases)| std::iter::once(artist).chain(aliases).collect())
+                .collect(),
+        }
+    }
+
+    /// Mirrors `LIKE '%search%'`, which ignores the case of ASCII characters only.
+    /// Returns `None` if the search contains a `LIKE` wildcard.
+    fn like(haystack: &str, search: &str) -> Option<bool> {
+        if search.contains(['%', '_']) {
+            return None;
+        }
+        let haystack = haystack.chars().map(|c| c.to_ascii_lowercase()).collect::<String>();
+        let search = search.chars().map(|c| c.to_ascii_lowercase()).collect::<String>();
+        Some(haystack.contains(&search))
+    }
+
+    /// Mirrors `AlbumArtists REGEXP pattern`.
+    fn album_artists(&mut self, track: &Track, pattern: String) -> Option<bool> {
+        if !self.regexes.contains_key(&pattern) {
+            let regex = Regex::new(&pattern).ok()?;
+            self.regexes.insert(pattern.clone(), regex);
+        }
+        Some(self.regexes[&pattern].is_match(&track.album_artists.join(";")))
+    }
+
+    /// Mirrors the artist conditions, which match a track tagged with any name of an artist
+    /// with a name matching the search, using `matches` to compare two names.
+    fn artist<F>(&self, track: &Track, search: &str, matches: F) -> Option<bool>
+    where
+        F: Fn(&str, &str) -> Option<bool>,
+    {
+        if matches(&track.artist, search)? {
+            return Some(true);
+        }
+        for names in &self.artists {
+            let mut matched = false;
+            for name in names {
+                matched = matches(name, search)? || matched;
+            }
+            if matched {
+                for name in names {
+                    if matches(&track.artist, name)? {
+                        return Some(true);
+                    }
+                }
+            }
+        }
+        Some(false)
+    }
+
+    fn file_type(track: &Track, file_type: TrackFileType) -> bool {
+        let track_type = track.file_type as i32;
+        let between = |lesser: TrackFileType, greater: TrackFileType| {
+            (lesser as i32..=greater as i32).contains(&track_type)
+        };
+        match file_type {
+            TrackFileType::FLAC => between(TrackFileType::FLAC4, TrackFileType::FLAC),
+            TrackFileType::AIFF => between(TrackFileType::AIFF4, TrackFileType::AIFF),
+            TrackFileType::ALAC => between(TrackFileType::ALAC16, TrackFileType::ALAC),
+            TrackFileType::MonkeysAudio => {
+                between(TrackFileType::MonkeysAudio8, TrackFileType::MonkeysAudio)
+            }
+            TrackFileType::MP3 => {
+                track.file_type == TrackFileType::MP3CBR || track.file_type == TrackFileType::MP3VBR
+            }
+            file_type => track.file_type == file_type,
+        }
+    }
+
+    /// Whether the track matches the bang, or `None` if that can not be
+    /// decided without querying the database.
+    fn matches(&mut self, bang: &Bang, track: &Track) -> Option<bool> {
+        Some(match bang {
+            Bang::All => true,
+            Bang::FilePath(path) => track.file_path.to_string_lossy() == path.as_str(),
+            Bang::TitleSearch(search) => Filter::like(&track.title, search)?,
+            Bang::TitleSearchExact(search) => &track.title == search,
+            Bang::AlbumTitle(search) => Filter::like(&track.album, search)?,
+            Bang::AlbumTitleExact(search) => &track.album == search,
+            Bang::Artist(search) => self.artist(track, search, Filter::like)?,
+            Bang::ArtistExact(search) => {
+                self.artist(track, search, |name, search| Some(name == search))?
+            }
+            Bang::AlbumArtists(search) => {
+                self.album_artists(track, database::album_artists_pattern(search))?
+            }
+            Bang::AlbumArtistsExact(search) => {
+                self.album_artists(track, database::album_artists_exact_pattern(search))?
+            }
+            Bang::Source(source) => track.source.eq_ignore_ascii_case(source),
+            Bang::Genre(path) => match genres::parse_genre_path(path) {
+                // Whether a genre is under another depends on the genre tree.
+                (_, true) => return None,
+                (genre, false) => track.genres.iter().any(|g| g.eq_ignore_ascii_case(genre)),
+            },
+            Bang::Format(file_type) => Filter::file_type(track, *file_type),
+            Bang::Quality(quality) => track.quality() == Some(*quality),
+            Bang::BitrateLessThan(bitrate) => track.bitrate < *bitrate,
+            Bang::BitrateGreaterThan(bitrate) => track.bitrate > *bitrate,
+            Bang::CoverArtWidthLessThan(width) => track.front_cover_width < *width,
+            Bang::CoverArtWidthGreaterThan(width) => track.front_cover_width > *width,
+            Bang::CoverArtHeightLessThan(height) => track.front_cover_height < *height,
+            Bang::CoverArtHeightGreaterThan(height) => track.front_cover_height > *height,
+            Bang::DurationLessThan(duration) => ms_to_ticks(track.duration) < *duration,
+            Bang::DurationGreaterThan(duration) => ms_to_ticks(track.duration) > *duration,
+            Bang::UpdatedBefore(date) => track.updated.as_str() < date.as_str(),
+            Bang::UpdatedAfter(date) => track.updated.as_str() > date.as_str(),
+            Bang::HasCoverArt(has) => track.has_front_cover == *has,
+            Bang::HasMusicbrainzId(has) => track.musicbrainz_track_id.is_some() == *has,
+            // Whether a track has duplicates depends on the rest of the library.
+            Bang::HasDuplicates(_) | Bang::DuplicatesBy(_) => return None,
+            // Spectral analysis results are only kept in the database.
+            Bang::FakeLossless(_) => return None,
+            Bang::Isrc(isrc) => track
+                .isrc
+                .as_deref()
+                .is_some_and(|tagged| database::normalize_isrc(tagged) == database::normalize_isrc(isrc)),
+            Bang::Encoder(search) => match &track.encoder {
+                Some(encoder) => Filter::like(encoder, search)?,
+                None => false,
+            },
+            // Notes are only kept in the database.
+            Bang::Note(_) => return None,
+            // Relationships are only kept in the database.
+            Bang::Related(_) => return None,
+            // Custom fields are only kept in the database.
+            Bang::CustomField(_) => return None,
+            // Failures are only kept in the database.
+            Bang::Failure(_) => return None,
+            Bang::FullTextSearch(search) => {
+                Filter::like(&track.title, search)?
+                    || Filter::like(&track.album, search)?
+                    || Filter::like(&track.artist, search)?
+                    || self.album_artists(track, database::album_artists_pattern(search))?
+            }
+            Bang::FullTextSearchExact(search) => {
+                &track.title == search
+                    || &track.album == search
+                    || &track.artist == search
+                    || self.album_artists(track, database::album_artists_exact_pattern(search))?
+            }
+            Bang::LogicalAnd(lhs, rhs) => self.matches(lhs, track)? && self.matches(rhs, track)?,
+            Bang::LogicalOr(lhs, rhs) => self.matches(lhs, track)? || self.matches(rhs, track)?,
+            Bang::Grouping(inner) => self.matches(inner, track)?,
+        })
+    }
+
+    /// Filters the tracks in memory, keeping their order.
+    fn filter(&mut self, bang: &Bang, tracks: &[Track]) -> Option<Vec<Track>> {
+        let mut matched = Vec::new();
+        for track in tracks {
+            if self.matches(bang, track)? {
+                matched.push(track.clone());
+            }
+        }
+        Some(matched)
+    }
+}
+
+/// Runs queries as they are typed, filtering the previous results in memory
+/// whenever the new query refines the previous one.
+///
+/// Queries that can not be filtered in memory are run through a `QueryCache`.
+pub struct IncrementalSearch {
+    cache: QueryCache,
+    /// The last query run, its results, and the change version they were read at.
+    previous: Option<(Bang, Arc<Vec<Track>>, i64)>,
+}
+
+impl IncrementalSearch {
+    pub const fn new(cache: QueryCache) -> IncrementalSearch {
+        IncrementalSearch {
+            cache,
+            previous: None,
+        }
+    }
+
+    /// Forgets the previous results, and clears the cache.
+    pub fn clear(&mut self) {
+        self.cache.clear();
+        self.previous = None;
+    }
+
+    /// Runs the query, with every result in the same order as `database::query_tracks`.
+    pub fn search(&mut self, bang: Bang, conn: &Connection) -> Result<Arc<Vec<Track>>> {
+        let version = database::get_change_version(conn)?;
+        let filtered = match &self.previous {
+            Some((previous, tracks, previous_version))
+                if *previous_version == version && refines(&bang, previous) =>
+            {
+                Filter::new(aliases::get_all_aliases(conn)?).filter(&bang, tracks).map(Arc::new)
+            }
+            _ => None,
+        };
+        let tracks = match filtered {
+            Some(tracks) => tracks,
+            None => self.cache.query(bang.clone(), conn, None, None)?,
+        };
+        self.previous = Some((bang, Arc::clone(&tracks), version));
+        Ok(tracks)
+    }
+}
+
+impl Default for IncrementalSearch {
+    fn default() -> IncrementalSearch {
+        IncrementalSearch::new(QueryCache::default())
+    }
+}
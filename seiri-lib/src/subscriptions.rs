@@ -0,0 +1,131 @@
+//! Subscriptions to the results of queries, so views such as smart playlists can be kept current
+//! as the library changes, by the tracks that entered, left or changed in their results.
+//!
+//! The results of every subscription are run again only once the change log shows the library
+//! has been modified since they were read. Tracks are compared by their UUID, so a track moved
+//! or retagged is updated rather than removed and added again. Like the query cache, nothing
+//! is noticed of changes outside the tracks table, such as a note being set.
+
+use crate::bangs::Bang;
+use crate::database::{self, Connection};
+use katatsuki::Track;
+use rusqlite::Result;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::mem;
+use std::path::PathBuf;
+
+/// A change to the results of a subscription.
+#[derive(Debug, Clone)]
+pub enum SubscriptionChange {
+    /// The track entered the results.
+    Added(Track),
+    /// The track left the results. The track is as it was when it was last in the results.
+    Removed(Track),
+    /// The track is still in the results, but it has changed.
+    Updated(Track),
+}
+
+impl SubscriptionChange {
+    pub fn track(&self) -> &Track {
+        match self {
+            SubscriptionChange::Added(track)
+            | SubscriptionChange::Removed(track)
+            | SubscriptionChange::Updated(track) => track,
+        }
+    }
+}
+
+struct Subscription {
+    bang: Bang,
+    /// The change version of the library the results were read at.
+    version: i64,
+    /// The results of the query, by the UUID of each track.
+    tracks: HashMap<String, Track>,
+}
+
+/// Gets the key tracks are compared by, which is their UUID, or their path if they have none.
+fn track_key(track: &Track) -> String {
+    track
+        .uuid
+        .clone()
+        .unwrap_or_else(|| track.file_path.to_string_lossy().into_owned())
+}
+
+fn query_keyed(bang: &Bang, conn: &Connection) -> Result<HashMap<String, Track>> {
+    Ok(database::query_tracks(bang.clone(), conn, None, None)?
+        .into_iter()
+        .map(|track| (track_key(&track), track))
+        .collect())
+}
+
+/// The subscriptions of a client, by the identifier it chose for each.
+#[derive(Default)]
+pub struct Subscriptions {
+    subscriptions: BTreeMap<String, Subscription>,
+}
+
+impl Subscriptions {
+    pub const fn new() -> Subscriptions {
+        Subscriptions {
+            subscriptions: BTreeMap::new(),
+        }
+    }
+
+    /// Subscribes to the results of the query under the identifier, replacing any subscription
+    /// under it. Returns the results as they are now.
+    pub fn subscribe(&mut self, id: &str, bang: Bang, conn: &Connection) -> Result<Vec<Track>> {
+        let version = database::get_change_version(conn)?;
+        let tracks = query_keyed(&bang, conn)?;
+        let mut results = tracks.values().cloned().collect::<Vec<Track>>();
+        results.sort_by(|a, b| a.file_path.cmp(&b.file_path));
+        self.subscriptions.insert(id.to_owned(), Subscription { bang, version, tracks });
+        Ok(results)
+    }
+
+    /// Removes the subscription under the identifier. Returns whether there was one.
+    pub fn unsubscribe(&mut self, id: &str) -> bool {
+        self.subscriptions.remove(id).is_some()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.subscriptions.is_empty()
+    }
+
+    /// Gets the changes to the results of every subscription since they were last read, along with
+    /// the identifier of each subscription, or nothing if the library has not changed since.
+    pub fn refresh(&mut self, conn: &Connection) -> Result<Vec<(String, SubscriptionChange)>> {
+        let version = database::get_change_version(conn)?;
+        let mut changes = Vec::new();
+        for (id, subscription) in self.subscriptions.iter_mut() {
+            if subscription.version == version {
+                continue;
+            }
+            // The paths of every track written to since the results were read.
+            let written = database::get_changes_since(subscription.version, conn)?
+                .into_iter()
+                .map(|change| change.file_path)
+                .collect::<HashSet<PathBuf>>();
+            let mut tracks = query_keyed(&subscription.bang, conn)?;
+            let mut subscription_changes = Vec::new();
+            for (key, before) in mem::take(&mut subscription.tracks) {
+                match tracks.remove(&key) {
+                    Some(after) => {
+                        if before.file_path != after.file_path || written.contains(&after.file_path) {
+                            subscription_changes.push(SubscriptionChange::Updated(after.clone()));
+                        }
+                        subscription.tracks.insert(key, after);
+                    }
+                    None => subscription_changes.push(SubscriptionChange::Removed(before)),
+                }
+            }
+            for (key, after) in tracks {
+                subscription_changes.push(SubscriptionChange::Added(after.clone()));
+                subscription.tracks.insert(key, after);
+            }
+            subscription_changes.sort_by(|a, b| a.track().file_path.cmp(&b.track().file_path));
+            changes.extend(subscription_changes.into_iter().map(|change| (id.clone(), change)));
+            subscription.version = version;
+        }
+        Ok(changes)
+    }
+}
@@ -0,0 +1,150 @@
+//! Suggestions of artist, album and genre names for a prefix typed by the user, so frontends can
+//! complete names as they are typed without querying every track.
+//!
+//! Every name is kept in the suggestions table along with the number of tracks it names, by its
+//! case-folded form, so a prefix is found within the primary key instead of by scanning tracks.
+//! The table is kept up to date by triggers as tracks and their genres change, and names are
+//! removed once no track has them. Names are folded by SQLite, which only folds ASCII letters.
+//! Genres are suggested as they are tagged, and not as they are placed in the genre tree.
+
+use rusqlite::types::ToSql;
+use rusqlite::{Connection, Result, NO_PARAMS};
+
+/// The statement of a trigger counting another track with the name, where `value` is the name as
+/// the trigger refers to it, such as `NEW.Artist`.
+fn count_name(field: SuggestionField, value: &str) -> String {
+    format!(
+        "INSERT INTO suggestions(Field, Folded, Name, Tracks)
+            SELECT '{field}', lower({value}), {value}, 1 WHERE {value} != ''
+            ON CONFLICT(Field, Folded, Name) DO UPDATE SET Tracks = Tracks + 1;",
+        field = field.name(),
+        value = value
+    )
+}
+
+/// The statements of a trigger counting one track less with the name, removing it once no track
+/// has it.
+fn uncount_name(field: SuggestionField, value: &str) -> String {
+    format!(
+        "UPDATE suggestions SET Tracks = Tracks - 1 WHERE Field = '{field}' AND Folded = lower({value}) AND Name = {value};
+        DELETE FROM suggestions WHERE Field = '{field}' AND Folded = lower({value}) AND Name = {value} AND Tracks <= 0;",
+        field = field.name(),
+        value = value
+    )
+}
+
+pub fn create_suggestion_tables(conn: &Connection) {
+    let (artist, album, genre) = (SuggestionField::Artist, SuggestionField::Album, SuggestionField::Genre);
+    conn.execute_batch(&format!(
+        "CREATE TABLE IF NOT EXISTS suggestions (
+        Field TEXT NOT NULL,
+        Folded TEXT NOT NULL,
+        Name TEXT NOT NULL,
+        Tracks INTEGER NOT NULL,
+        PRIMARY KEY (Field, Folded, Name)
+    ) WITHOUT ROWID;
+    CREATE TRIGGER IF NOT EXISTS suggestions_track_insert AFTER INSERT ON tracks BEGIN
+        {} {}
+    END;
+    CREATE TRIGGER IF NOT EXISTS suggestions_track_update AFTER UPDATE OF Artist, Album ON tracks BEGIN
+        {} {} {} {}
+    END;
+    CREATE TRIGGER IF NOT EXISTS suggestions_track_delete AFTER DELETE ON tracks BEGIN
+        {} {}
+    END;
+    CREATE TRIGGER IF NOT EXISTS suggestions_genre_insert AFTER INSERT ON track_genres BEGIN
+        {}
+    END;
+    CREATE TRIGGER IF NOT EXISTS suggestions_genre_delete AFTER DELETE ON track_genres BEGIN
+        {}
+    END;",
+        count_name(artist, "NEW.Artist"),
+        count_name(album, "NEW.Album"),
+        uncount_name(artist, "OLD.Artist"),
+        uncount_name(album, "OLD.Album"),
+        count_name(artist, "NEW.Artist"),
+        count_name(album, "NEW.Album"),
+        uncount_name(artist, "OLD.Artist"),
+        uncount_name(album, "OLD.Album"),
+        count_name(genre, "NEW.Genre"),
+        uncount_name(genre, "OLD.Genre"),
+    ))
+    .unwrap();
+    // Libraries made before suggestions were kept have tracks, but no suggestions for them.
+    let empty = conn
+        .query_row("SELECT NOT EXISTS (SELECT 1 FROM suggestions)", NO_PARAMS, |row| row.get::<_, bool>(0))
+        .unwrap();
+    if empty {
+        rebuild_suggestions(conn).unwrap();
+    }
+}
+
+/// What a name suggested by `suggest` names.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SuggestionField {
+    Artist,
+    Album,
+    Genre,
+}
+
+impl SuggestionField {
+    /// Gets the field with the given name, such as `artist`.
+    pub fn from_name(name: &str) -> Option<SuggestionField> {
+        match name.to_lowercase().as_str() {
+            "artist" => Some(SuggestionField::Artist),
+            "album" => Some(SuggestionField::Album),
+            "genre" => Some(SuggestionField::Genre),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SuggestionField::Artist => "artist",
+            SuggestionField::Album => "album",
+            SuggestionField::Genre => "genre",
+        }
+    }
+}
+
+/// A name suggested for a prefix, with the number of tracks it names.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Suggestion {
+    pub name: String,
+    pub tracks: i64,
+}
+
+/// Suggests at most `limit` names of the field starting with the prefix, ignoring the case of
+/// ASCII letters, the names of the most tracks first.
+pub fn suggest(field: SuggestionField, prefix: &str, limit: u32, conn: &Connection) -> Result<Vec<Suggestion>> {
+    let from = prefix.to_ascii_lowercase();
+    // Every name starting with the prefix sorts before the prefix followed by the last character.
+    let to = format!("{}{}", from, char::MAX);
+    let mut statement = conn.prepare_cached(
+        "SELECT Name, Tracks FROM suggestions WHERE Field = ?1 AND Folded >= ?2 AND Folded < ?3
+        ORDER BY Tracks DESC, Folded LIMIT ?4",
+    )?;
+    let suggestions = statement.query_map(
+        &[&field.name() as &dyn ToSql, &from, &to, &limit],
+        |row| {
+            Ok(Suggestion {
+                name: row.get(0)?,
+                tracks: row.get(1)?,
+            })
+        },
+    )?;
+    suggestions.collect()
+}
+
+/// Rebuilds every suggestion from the tracks in the library.
+pub fn rebuild_suggestions(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "DELETE FROM suggestions;
+        INSERT INTO suggestions(Field, Folded, Name, Tracks)
+            SELECT 'artist', lower(Artist), Artist, COUNT(*) FROM tracks WHERE Artist != '' GROUP BY Artist;
+        INSERT INTO suggestions(Field, Folded, Name, Tracks)
+            SELECT 'album', lower(Album), Album, COUNT(*) FROM tracks WHERE Album != '' GROUP BY Album;
+        INSERT INTO suggestions(Field, Folded, Name, Tracks)
+            SELECT 'genre', lower(Genre), Genre, COUNT(*) FROM track_genres WHERE Genre != '' GROUP BY Genre COLLATE BINARY;",
+    )
+}
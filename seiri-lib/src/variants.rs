@@ -0,0 +1,163 @@
+//! Copies of tracks transcoded into other formats, such as an Opus copy of a FLAC track to export
+//! onto a phone, kept so that later exports reuse the transcode rather than transcoding again.
+//!
+//! Transcoders are configured as `[[transcoders]]` tables, each naming the format it writes,
+//! which is also the extension of the files it writes, and the program it runs along with its
+//! arguments. `{input}` and `{output}` in the arguments are replaced with the paths of the track
+//! and of the file to write:
+//!
+//! ```toml
+//! [[transcoders]]
+//! format = "opus"
+//! command = ["ffmpeg", "-i", "{input}", "-c:a", "libopus", "-b:a", "160k", "{output}"]
+//! ```
+//!
+//! Variants are written to the variants folder of the application directory, and kept by the
+//! UUID of their track and their format. A variant is transcoded again once the file of its track
+//! is newer than it, and is removed along with its track.
+
+use crate::database::create_table_with_foreign_keys;
+use crate::error::{Error, Result};
+use crate::paths::get_appdata_path;
+use katatsuki::Track;
+use rusqlite::{Connection, OptionalExtension};
+use serde_derive::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf, MAIN_SEPARATOR};
+use std::process::{Command, Stdio};
+
+/// A program that transcodes tracks into a format.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Transcoder {
+    /// The format the transcoder writes, such as `opus`, which is the extension of its files.
+    pub format: String,
+    /// The program followed by its arguments, with `{input}` and `{output}` replaced.
+    pub command: Vec<String>,
+}
+
+impl Transcoder {
+    /// Whether the track is already in the format of the transcoder, so it need not be transcoded.
+    pub fn is_format_of(&self, track: &Track) -> bool {
+        track
+            .file_path
+            .extension()
+            .is_some_and(|extension| extension.to_string_lossy().eq_ignore_ascii_case(&self.format))
+    }
+
+    /// Transcodes the file at the input path into the output path, replacing any file there.
+    /// Returns whether the transcoder exited successfully, having written the output. Nothing is
+    /// left at the output path otherwise.
+    pub fn run(&self, input: &Path, output: &Path) -> bool {
+        let (program, args) = match self.command.split_first() {
+            Some(command) => command,
+            None => return false,
+        };
+        // Transcoders such as ffmpeg refuse to replace a file without being asked to.
+        fs::remove_file(output).ok();
+        let (input, output_arg) = (input.to_string_lossy(), output.to_string_lossy());
+        let status = Command::new(program)
+            .args(args.iter().map(|arg| arg.replace("{input}", &input).replace("{output}", &output_arg)))
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status();
+        match status {
+            Ok(status) if status.success() && output.is_file() => true,
+            _ => {
+                fs::remove_file(output).ok();
+                false
+            }
+        }
+    }
+}
+
+/// Gets the transcoder of the format, ignoring case.
+pub fn find_transcoder<'a>(transcoders: &'a [Transcoder], format: &str) -> Option<&'a Transcoder> {
+    transcoders.iter().find(|transcoder| transcoder.format.eq_ignore_ascii_case(format.trim()))
+}
+
+pub fn create_variant_table(conn: &Connection) {
+    create_table_with_foreign_keys(
+        "track_variants",
+        "TrackId TEXT NOT NULL REFERENCES tracks(TrackId) ON DELETE CASCADE,
+        Format TEXT NOT NULL,
+        FilePath TEXT NOT NULL,
+        PRIMARY KEY (TrackId, Format)",
+        conn,
+    )
+    .unwrap();
+}
+
+fn get_variants_path() -> PathBuf {
+    let mut variants_path = get_appdata_path();
+    variants_path.push("variants");
+    variants_path
+}
+
+/// Gets the variant of the track with the given UUID in the format, if one was transcoded.
+pub fn get_variant(track_id: &str, format: &str, conn: &Connection) -> rusqlite::Result<Option<PathBuf>> {
+    conn.query_row(
+        "SELECT FilePath FROM track_variants WHERE TrackId = ?1 AND Format = ?2",
+        &[track_id, &format.to_lowercase()],
+        |row| row.get::<_, String>(0).map(PathBuf::from),
+    )
+    .optional()
+}
+
+/// Whether the variant was written after the file of its track was last changed.
+fn is_current(variant: &Path, track: &Track) -> bool {
+    let modified = |path: &Path| path.metadata().and_then(|metadata| metadata.modified()).ok();
+    match (modified(variant), modified(&track.file_path)) {
+        (Some(variant), Some(track)) => variant >= track,
+        _ => false,
+    }
+}
+
+/// Gets the variant of the track in the format of the transcoder, transcoding the track unless
+/// a current variant was already transcoded.
+///
+/// Fails if the track is not in the library, or the transcoder could not be run, exited with a
+/// failing status, or wrote nothing.
+pub fn transcode(track: &Track, transcoder: &Transcoder, conn: &Connection) -> Result<PathBuf> {
+    let format = transcoder.format.to_lowercase();
+    let failed = || Error::TranscodeFailed(track.file_path.to_string_lossy().into_owned(), format.clone());
+    let track_id = track.uuid.as_deref().ok_or_else(failed)?;
+    if let Some(variant) = get_variant(track_id, &format, conn).map_err(|_| failed())? {
+        if is_current(&variant, track) {
+            return Ok(variant);
+        }
+    }
+    let variants_path = get_variants_path();
+    fs::create_dir_all(&variants_path)
+        .map_err(|_| Error::UnableToCreateDirectory(variants_path.to_string_lossy().into_owned()))?;
+    let variant = variants_path.join(format!("{}.{}", track_id, format));
+    if !transcoder.run(&track.file_path, &variant) {
+        return Err(failed());
+    }
+    conn.execute(
+        "INSERT INTO track_variants(TrackId, Format, FilePath) VALUES (?1, ?2, ?3)
+        ON CONFLICT(TrackId, Format) DO UPDATE SET FilePath = excluded.FilePath",
+        &[track_id, &format, &*variant.to_string_lossy()],
+    )
+    .map_err(|_| failed())?;
+    Ok(variant)
+}
+
+/// Removes the variants of the track at the given path, or of every track in the folder at the
+/// given path and its subfolders, before the tracks are removed.
+pub fn remove_variants_under(path: &Path, conn: &Connection) -> rusqlite::Result<()> {
+    let file_path = path.to_string_lossy().into_owned();
+    let folder_path = format!("{}{}", file_path.trim_end_matches(MAIN_SEPARATOR), MAIN_SEPARATOR);
+    let mut statement = conn.prepare(
+        "SELECT track_variants.FilePath FROM track_variants
+            JOIN tracks ON tracks.TrackId = track_variants.TrackId
+            WHERE tracks.FilePath = ?1 OR substr(tracks.FilePath, 1, length(?2)) = ?2",
+    )?;
+    let variants = statement
+        .query_map(&[&file_path, &folder_path], |row| row.get::<_, String>(0))?
+        .collect::<rusqlite::Result<Vec<String>>>()?;
+    for variant in variants {
+        fs::remove_file(variant).ok();
+    }
+    Ok(())
+}
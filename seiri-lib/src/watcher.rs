@@ -0,0 +1,990 @@
+use notify;
+use notify::DebouncedEvent;
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use crate::config::{Backpressure, Config, WatchdogConfig};
+use crate::database::{self, Connection, ConnectionPool};
+use crate::events::Event;
+use crate::filesystem::{is_hidden_path, DiskFileSystem, FolderSnapshot, WatchFileSystem};
+use crate::lease::{holds_lease, renew_lease, with_lease};
+use crate::library::{self, BootstrapOptions};
+use crate::paths::{is_in_hidden_path, MoveJournal};
+use crate::reports;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use crossbeam::channel::{bounded, unbounded, Receiver, select};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use threadpool::ThreadPool;
+
+/// How long the folder must be quiet before the files that landed in it are processed.
+const BATCH_QUIET_PERIOD: Duration = Duration::from_secs(1);
+
+/// The most files processed in a single batch, while holding the write lease.
+/// Other writers wait on the batch, so this is kept small enough to finish quickly.
+pub const MAX_BATCH_SIZE: usize = 100;
+
+/// The name the watcher holds the write lease under.
+pub const LEASE_HOLDER: &str = "seiri-watcher";
+
+/// Held while the watcher writes. Every thread of the watcher takes the lease under the same name,
+/// so the lease alone would let one thread release it while another is still writing.
+static WRITING: Mutex<()> = Mutex::new(());
+
+fn lock_writes() -> std::sync::MutexGuard<'static, ()> {
+    // A batch that panicked leaves nothing behind that the next one could trip over.
+    WRITING.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Runs the writes of the watcher while holding the write lease like `with_lease`, waiting for
+/// the other threads of the watcher to finish writing first.
+///
+/// Every write of the watcher, including the writes of the commands it is sent, goes through this
+/// rather than `with_lease`, so that one thread never releases the lease another is writing under.
+pub fn with_write_lease<T, F, R>(conn: &Connection, report: R, write: F) -> rusqlite::Result<T>
+where
+    F: FnOnce() -> T,
+    R: Fn(Event),
+{
+    let _writing = lock_writes();
+    with_lease(LEASE_HOLDER, conn, report, write)
+}
+
+/// The files handed to the import threads that are not processed yet, by every watcher that ran,
+/// so a watcher started again after the last one died does not queue them a second time.
+static IN_FLIGHT: Mutex<BTreeSet<PathBuf>> = Mutex::new(BTreeSet::new());
+
+fn in_flight() -> std::sync::MutexGuard<'static, BTreeSet<PathBuf>> {
+    IN_FLIGHT.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// The files of a batch handed to the import threads, which are no longer in flight once it is
+/// dropped, even if processing them panicked.
+struct InFlightBatch(Vec<PathBuf>);
+
+impl InFlightBatch {
+    /// Takes the files of the batch that are not in flight already, leaving out groups with none left.
+    fn take(batch: Vec<FileGroup>) -> (Vec<FileGroup>, InFlightBatch) {
+        let mut in_flight = in_flight();
+        let batch = batch
+            .into_iter()
+            .map(|group| group.into_iter().filter(|path| in_flight.insert(path.clone())).collect::<FileGroup>())
+            .filter(|group| !group.is_empty())
+            .collect::<Vec<FileGroup>>();
+        let files = batch.iter().flatten().cloned().collect();
+        (batch, InFlightBatch(files))
+    }
+}
+
+impl Drop for InFlightBatch {
+    fn drop(&mut self) {
+        let mut in_flight = in_flight();
+        for path in &self.0 {
+            in_flight.remove(path);
+        }
+    }
+}
+
+/// The restarts left to the watchdog of the watcher, which waits twice as long before every
+/// restart in a row as it did before the last.
+pub struct RestartBudget<'a> {
+    config: &'a WatchdogConfig,
+    restarts: u32,
+    restarted_at: Option<Instant>,
+}
+
+impl<'a> RestartBudget<'a> {
+    pub fn new(config: &'a WatchdogConfig) -> RestartBudget<'a> {
+        RestartBudget {
+            config,
+            restarts: 0,
+            restarted_at: None,
+        }
+    }
+
+    /// Spends a restart of the watcher, returning how many restarts in a row it is and how long to
+    /// wait before it, or `None` if the budget is spent. Restarts are no longer in a row once the
+    /// watcher ran for `reset_after` seconds since the last.
+    pub fn restart(&mut self) -> Option<(u32, Duration)> {
+        let reset_after = Duration::from_secs(self.config.reset_after);
+        if self.restarted_at.is_some_and(|restarted_at| restarted_at.elapsed() >= reset_after) {
+            self.restarts = 0;
+        }
+        if self.restarts >= self.config.max_restarts {
+            return None;
+        }
+        let backoff = self
+            .config
+            .backoff
+            .saturating_mul(1u64 << self.restarts.min(63))
+            .min(self.config.max_backoff);
+        let delay = Duration::from_secs(backoff);
+        self.restarts += 1;
+        // The watcher only runs again once the delay is over.
+        self.restarted_at = Some(Instant::now() + delay);
+        Some((self.restarts, delay))
+    }
+}
+
+/// A temporary cap on how fast the watcher imports, such as while music is played from the disk
+/// being imported to, so playback does not stutter during a large import.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImportThrottle {
+    /// The most batches imported at once, which is never more than the configured import threads.
+    pub threads: usize,
+    /// How long to wait before importing each folder of files. While throttled, every folder is
+    /// imported in a transaction of its own, so writes are spread out rather than made at once.
+    pub pause: Duration,
+    /// When imports go back to full speed.
+    pub until: Instant,
+}
+
+static THROTTLE: Mutex<Option<ImportThrottle>> = Mutex::new(None);
+
+fn throttle() -> std::sync::MutexGuard<'static, Option<ImportThrottle>> {
+    THROTTLE.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Throttles imports for the given duration, replacing any throttle already in place.
+pub fn throttle_imports(threads: usize, pause: Duration, duration: Duration) -> ImportThrottle {
+    let throttled = ImportThrottle {
+        threads: threads.max(1),
+        pause,
+        until: Instant::now() + duration,
+    };
+    *throttle() = Some(throttled);
+    throttled
+}
+
+/// Lets imports go back to full speed before the throttle runs out.
+pub fn lift_import_throttle() {
+    *throttle() = None;
+}
+
+/// Gets the throttle imports are under, if they are throttled.
+pub fn import_throttle() -> Option<ImportThrottle> {
+    let mut throttle = throttle();
+    if throttle.is_some_and(|throttle| throttle.until <= Instant::now()) {
+        *throttle = None;
+    }
+    *throttle
+}
+
+/// Files found together in a single folder, which are processed as a unit.
+pub type FileGroup = Vec<PathBuf>;
+
+/// Groups the files by the folder they are in.
+/// Files directly inside the watched folder are never grouped with each other.
+fn group_by_folder(paths: Vec<PathBuf>, watch_dir: &Path) -> Vec<FileGroup> {
+    let mut loose = Vec::new();
+    let mut folders = BTreeMap::<PathBuf, FileGroup>::new();
+    for path in paths {
+        match path.parent() {
+            Some(folder) if folder != watch_dir => {
+                folders.entry(folder.to_owned()).or_default().push(path)
+            }
+            _ => loose.push(vec![path]),
+        }
+    }
+    loose.into_iter().chain(folders.into_values()).collect()
+}
+
+/// Splits the groups into batches of at most `MAX_BATCH_SIZE` files,
+/// without splitting any group.
+fn into_batches(groups: Vec<FileGroup>) -> Vec<Vec<FileGroup>> {
+    let mut batches = Vec::<Vec<FileGroup>>::new();
+    let mut size = 0;
+    for group in groups {
+        if batches.is_empty() || size + group.len() > MAX_BATCH_SIZE {
+            batches.push(Vec::new());
+            size = 0;
+        }
+        size += group.len();
+        batches.last_mut().unwrap().push(group);
+    }
+    batches
+}
+
+/// Sets aside the groups with placeholders of files kept in cloud storage, which are downloaded
+/// before the group is processed. Each placeholder is reported the first time it is found waiting.
+/// Returns the groups that are ready, and the files of the groups that were set aside.
+fn defer_placeholders<S, R>(
+    fs: &S,
+    groups: Vec<FileGroup>,
+    waiting: &mut BTreeSet<PathBuf>,
+    report: R,
+) -> (Vec<FileGroup>, Vec<PathBuf>)
+where
+    S: WatchFileSystem + ?Sized,
+    R: Fn(Event),
+{
+    let (ready, downloading): (Vec<FileGroup>, Vec<FileGroup>) = groups
+        .into_iter()
+        .partition(|group| !group.iter().any(|path| fs.is_placeholder(path)));
+    // Placeholders that were downloaded or removed are reported again if they are ever evicted.
+    waiting.retain(|path| fs.is_placeholder(path));
+    for path in downloading.iter().flatten() {
+        if fs.is_placeholder(path) && waiting.insert(path.clone()) {
+            fs.hydrate(path);
+            report(Event::WaitingForDownload(path.display().to_string()));
+        }
+    }
+    (ready, downloading.into_iter().flatten().collect())
+}
+
+/// Processes each group of files in a transaction of its own, reporting the result of each file
+/// followed by a single `Event::BatchImported` for the whole batch.
+///
+/// The batch is run while holding the write lease, which is renewed after every group is
+/// committed. Batches processed on other threads wait for the batch, so only the reports and
+/// clean up of batches run alongside each other. The results are reported once their groups are
+/// committed, and if a group can not be, or the lease is lost while processing it, the files moved
+/// while processing the group are moved back to where they were and the rest of the batch is left.
+pub fn process_batch<F, R>(groups: &[FileGroup], config: &Config, conn: &Connection, process: F, report: R)
+where
+    F: Fn(&[PathBuf], &Config, &Connection) -> Vec<Event>,
+    R: Fn(Event) + Copy,
+{
+    let process = |_: &DiskFileSystem, group: &[PathBuf], config: &Config, conn: &Connection| process(group, config, conn);
+    process_batch_in(&DiskFileSystem, groups, config, conn, process, report)
+}
+
+/// Processes each group of files like `process_batch`, handing `process` the file system to read
+/// and move the files through, and cleaning up the watch folder in it once they are processed.
+pub fn process_batch_in<S, F, R>(fs: &S, groups: &[FileGroup], config: &Config, conn: &Connection, process: F, report: R)
+where
+    S: WatchFileSystem + ?Sized,
+    F: Fn(&S, &[PathBuf], &Config, &Connection) -> Vec<Event>,
+    R: Fn(Event) + Copy,
+{
+    let started = reports::timestamp();
+    // Events are only kept for the report of the batch if reports are written.
+    let events = RefCell::new(Vec::new());
+    let report = |event: Event| {
+        if config.reports.enabled {
+            events.borrow_mut().push(event.clone());
+        }
+        report(event)
+    };
+    let result = with_write_lease(conn, report, || {
+        let mut events = Vec::new();
+        let mut committed = 0;
+        for group in groups {
+            let transaction = conn.unchecked_transaction()?;
+            let journal = MoveJournal::start();
+            let group_events = process(fs, group, config, conn);
+            // Other writers can not take the lease while the transaction holds its lock, so it is
+            // checked before the group is committed, and the group is abandoned once it is lost.
+            let result = match holds_lease(LEASE_HOLDER, &transaction) {
+                Ok(true) => transaction.commit().map(|()| true),
+                Ok(false) => Ok(false),
+                Err(err) => Err(err),
+            };
+            // The files are moved back into the watch folder if the group is abandoned,
+            // to be processed again along with the next batch.
+            if !matches!(result, Ok(true)) {
+                for path in fs.undo_moves(&journal.finish()) {
+                    report(Event::TrackMoveError(path.display().to_string()));
+                }
+            }
+            if !result? {
+                return Ok((events, committed));
+            }
+            events.extend(group_events);
+            committed += group.len();
+            // The lease is renewed once the group is committed, so other writers see it renewed.
+            if !renew_lease(LEASE_HOLDER, conn).unwrap_or(false) {
+                break;
+            }
+        }
+        Ok((events, committed))
+    });
+    // The tracks are only reported once they are committed to the library.
+    match result {
+        // The lost lease is reported by `with_lease`.
+        Ok(Ok((_, 0))) if !groups.is_empty() => (),
+        Ok(Ok((events, committed))) => {
+            let imported = events
+                .iter()
+                .filter(|event| matches!(event, Event::TrackAdded { .. }))
+                .count();
+            events.into_iter().for_each(report);
+            report(Event::BatchImported { imported, total: committed })
+        }
+        Ok(Err(err)) | Err(err) => report(Event::WatcherError(err.to_string())),
+    }
+    if config.reports.enabled {
+        let files = groups.iter().flatten().cloned().collect::<Vec<PathBuf>>();
+        reports::report_batch(started, &files, &events.borrow(), config, conn, report);
+    }
+    fs.clean_up(groups, config, conn);
+}
+
+/// Processes the files already in the watch folder. Returns the files waiting to be downloaded
+/// from cloud storage, which are handed to `watch` to be processed once they are.
+pub fn list<F, R>(watch_dir: &str, config: &Config, pool: &ConnectionPool, process: F, report: R) -> Vec<PathBuf>
+where
+    F: Fn(&[PathBuf], &Config, &Connection) -> Vec<Event> + Copy,
+    R: Fn(Event) + Copy,
+{
+    let process = move |_: &DiskFileSystem, group: &[PathBuf], config: &Config, conn: &Connection| process(group, config, conn);
+    list_in(&DiskFileSystem, Path::new(watch_dir), config, &pool.get().unwrap(), process, report)
+}
+
+/// Processes the files already in the watch folder of the file system like `list`, handing
+/// `process` the file system like `process_batch_in`.
+pub fn list_in<S, F, R>(fs: &S, watch_dir: &Path, config: &Config, conn: &Connection, process: F, report: R) -> Vec<PathBuf>
+where
+    S: WatchFileSystem + ?Sized,
+    F: Fn(&S, &[PathBuf], &Config, &Connection) -> Vec<Event> + Copy,
+    R: Fn(Event) + Copy,
+{
+    let paths = fs.files_under(watch_dir);
+    let (ready, downloading) = defer_placeholders(fs, group_by_folder(paths, watch_dir), &mut BTreeSet::new(), report);
+    for batch in into_batches(ready) {
+        process_batch_in(fs, &batch, config, conn, process, report);
+    }
+    downloading
+}
+
+/// Indexes the adopted music folder when the watcher starts, adding the tracks that are not in
+/// the library yet and removing the tracks whose files are gone since it was last watched.
+pub fn adopt<R>(library_dir: &str, pool: &ConnectionPool, report: R)
+where
+    R: Fn(Event) + Copy,
+{
+    let library_dir = Path::new(library_dir);
+    let conn = pool.get().unwrap();
+    let result = with_write_lease(&conn, report, || {
+        let indexed = library::bootstrap(library_dir, &BootstrapOptions::default(), &conn)?;
+        let removed = library::prune_missing(library_dir, &conn)?;
+        Ok((indexed, removed))
+    });
+    match result {
+        Ok(Ok((indexed, removed))) => {
+            for path in indexed.failed {
+                report(Event::TrackError(path.display().to_string()));
+            }
+            for path in removed {
+                report(Event::TracksRemoved { path: path.display().to_string(), count: 1 });
+            }
+            report(Event::BatchImported {
+                imported: indexed.imported,
+                total: indexed.imported + indexed.skipped + indexed.unsupported,
+            })
+        }
+        Ok(Err(err)) | Err(err) => report(Event::WatcherError(err.to_string())),
+    }
+}
+
+/// Removes the tracks of the file or folder that was removed from an adopted music folder.
+fn remove_tracks<R>(path: &Path, conn: &Connection, report: R)
+where
+    R: Fn(Event) + Copy,
+{
+    match with_write_lease(conn, report, || database::remove_tracks_under(path, conn)) {
+        Ok(Ok(0)) => (),
+        Ok(Ok(count)) => report(Event::TracksRemoved { path: path.display().to_string(), count }),
+        Ok(Err(err)) | Err(err) => report(Event::WatcherError(err.to_string())),
+    }
+}
+
+pub enum WatchStatus {
+    KeepAlive,
+    Exit,
+}
+
+/// A change to the watched folder.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FolderChange {
+    /// The file was created or written to.
+    Written(PathBuf),
+    /// The file or folder was removed.
+    Removed(PathBuf),
+    /// The file or folder was moved from the first path to the second.
+    Renamed(PathBuf, PathBuf),
+}
+
+impl FolderChange {
+    /// Gets the change to the folder of the event from the file system watcher, if it is one
+    /// the watcher handles.
+    pub fn from_event(event: DebouncedEvent) -> Option<FolderChange> {
+        match event {
+            DebouncedEvent::Write(path) | DebouncedEvent::Create(path) => Some(FolderChange::Written(path)),
+            DebouncedEvent::Remove(path) => Some(FolderChange::Removed(path)),
+            DebouncedEvent::Rename(from, to) => Some(FolderChange::Renamed(from, to)),
+            _ => None,
+        }
+    }
+}
+
+/// What a `WatchQueue` did with a change to the watched folder.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Handled {
+    /// The change did not concern the queue.
+    Ignored,
+    /// The file was queued to be processed once its folder settles.
+    Queued,
+    /// The file was queued, and a batch worth of files are queued, so the queue should be
+    /// flushed without waiting for the watched folder to be quiet.
+    Full,
+    /// The file or folder was removed from the adopted music folder, so its tracks should be
+    /// removed from the library. Any files moved in its place are queued.
+    Removed(PathBuf),
+}
+
+/// The files that landed in the watched folder and are waiting to be processed, which are
+/// processed once their folder has settled and any placeholders among them are downloaded.
+///
+/// The watcher hands every change to the folder to `handle`, and calls `flush` whenever the
+/// folder was quiet for a while. Driving the queue directly lets the pipeline be run step by step,
+/// without waiting on timers.
+pub struct WatchQueue<'a, S: WatchFileSystem + ?Sized> {
+    fs: &'a S,
+    watch_dir: PathBuf,
+    adopt_layout: bool,
+    /// Files that have landed since the folder was last quiet.
+    pending: Vec<PathBuf>,
+    snapshots: HashMap<PathBuf, FolderSnapshot>,
+    /// Placeholders that are being downloaded, which were already reported.
+    waiting: BTreeSet<PathBuf>,
+    /// Batches that were ready while the import queue was full, held until it has room.
+    held: Vec<Vec<FileGroup>>,
+}
+
+impl<'a, S: WatchFileSystem + ?Sized> WatchQueue<'a, S> {
+    /// Creates a queue for the watched folder in the file system, with the files that `list` left
+    /// waiting to be downloaded. Removals are only handled if the folder is an adopted music folder.
+    pub fn new(fs: &'a S, watch_dir: &Path, adopt_layout: bool, downloading: Vec<PathBuf>) -> WatchQueue<'a, S> {
+        WatchQueue {
+            fs,
+            watch_dir: watch_dir.to_owned(),
+            adopt_layout,
+            pending: downloading.clone(),
+            snapshots: HashMap::new(),
+            waiting: downloading.into_iter().collect(),
+            held: Vec::new(),
+        }
+    }
+
+    /// The files waiting to be processed.
+    pub fn pending(&self) -> &[PathBuf] {
+        &self.pending
+    }
+
+    /// Holds the batches until the import queue has room for them, before any batch held already.
+    pub fn hold(&mut self, mut batches: Vec<Vec<FileGroup>>) {
+        batches.append(&mut self.held);
+        self.held = batches;
+    }
+
+    /// Takes the batches that were held, in the order they were ready.
+    pub fn take_held(&mut self) -> Vec<Vec<FileGroup>> {
+        std::mem::take(&mut self.held)
+    }
+
+    fn is_held(&self, path: &Path) -> bool {
+        self.held.iter().flatten().flatten().any(|held| held == path)
+    }
+
+    /// Queues every file in the watched folder that is not queued or being processed already, such
+    /// as the files dropped while no watcher was running. Returns how many files were queued.
+    ///
+    /// The watcher reconciles once it is watching the folder, so a file dropped while it starts is
+    /// queued either here or by its change to the folder, and only once either way.
+    pub fn reconcile(&mut self) -> usize {
+        let watch_dir = self.watch_dir.as_path();
+        let in_flight = in_flight();
+        let leftovers = self
+            .fs
+            .files_under(watch_dir)
+            .into_iter()
+            .filter(|path| {
+                self.fs.is_idle(path)
+                    && !is_in_hidden_path(path, watch_dir)
+                    && !self.pending.contains(path)
+                    && !in_flight.contains(path)
+            })
+            .collect::<Vec<PathBuf>>();
+        drop(in_flight);
+        let queued = leftovers.len();
+        self.pending.extend(leftovers);
+        queued
+    }
+
+    /// Queues any file the change lands in the watched folder.
+    pub fn handle(&mut self, change: FolderChange) -> Handled {
+        let watch_dir = self.watch_dir.as_path();
+        match change {
+            // We only want to process events when the file is idle.
+            // However, if the write finishes before the delay, only the create event is fired.
+            // Otherwise, the write event will be delayed until the latest possible.
+            FolderChange::Written(path) => {
+                if self.fs.is_idle(&path)
+                    && self.fs.is_file(&path)
+                    && !is_in_hidden_path(&path, watch_dir)
+                    && !is_hidden_path(&path)
+                    && !self.pending.contains(&path)
+                    && !self.is_held(&path)
+                {
+                    self.pending.push(path);
+                    if self.pending.len() >= MAX_BATCH_SIZE {
+                        return Handled::Full;
+                    }
+                    return Handled::Queued;
+                }
+                Handled::Ignored
+            }
+            // Files are only moved out from under an adopted music folder by the user.
+            FolderChange::Removed(path) if self.adopt_layout && !is_in_hidden_path(&path, watch_dir) => {
+                Handled::Removed(path)
+            }
+            FolderChange::Renamed(path, to) if self.adopt_layout && !is_in_hidden_path(&path, watch_dir) => {
+                let moved = self
+                    .fs
+                    .files_under(&to)
+                    .into_iter()
+                    .filter(|path| !is_in_hidden_path(path, watch_dir) && !self.pending.contains(path) && !self.is_held(path))
+                    .collect::<Vec<PathBuf>>();
+                self.pending.extend(moved);
+                Handled::Removed(path)
+            }
+            _ => Handled::Ignored,
+        }
+    }
+
+    /// Takes the files whose folders have settled and are downloaded, in batches to be processed
+    /// with `process_batch_in`. Files in a folder are only processed once the folder has settled,
+    /// that is, when no files were added, removed, or written to between two flushes. The whole
+    /// folder is then processed as a unit, so an album is imported together, once every file of
+    /// the folder is downloaded if it is kept in cloud storage.
+    pub fn flush<R>(&mut self, report: R) -> Vec<Vec<FileGroup>>
+    where
+        R: Fn(Event),
+    {
+        let watch_dir = self.watch_dir.as_path();
+        let mut ready = Vec::new();
+        for group in group_by_folder(std::mem::take(&mut self.pending), watch_dir) {
+            let folder = match group[0].parent() {
+                Some(folder) if folder != watch_dir => folder.to_owned(),
+                _ => {
+                    ready.push(group);
+                    continue;
+                }
+            };
+            let snapshot = self.fs.snapshot_folder(&folder);
+            if snapshot.is_empty() {
+                self.snapshots.remove(&folder);
+            } else if self.snapshots.get(&folder) == Some(&snapshot)
+                && snapshot.iter().all(|(path, _)| self.fs.is_idle(path))
+            {
+                self.snapshots.remove(&folder);
+                ready.push(snapshot.into_iter().map(|(path, _)| path).collect());
+            } else {
+                self.snapshots.insert(folder, snapshot);
+                self.pending.extend(group);
+            }
+        }
+        let (ready, downloading) = defer_placeholders(self.fs, ready, &mut self.waiting, report);
+        self.pending.extend(downloading);
+        into_batches(ready)
+    }
+}
+
+/// Watches the folder, processing the files that land in it, along with the files already in it
+/// once the folder is watched, unless it is an adopted music folder. The files that `list` left
+/// waiting to be downloaded are processed along with them once they are.
+pub fn watch<F, R>(
+    watch_dir: &str,
+    config: &'static Config,
+    pool: Arc<ConnectionPool>,
+    process: F,
+    report: R,
+    quit_rx: &Receiver<WatchStatus>,
+    downloading: Vec<PathBuf>,
+) -> notify::Result<()>
+where
+    F: Fn(&[PathBuf], &Config, &Connection) -> Vec<Event> + Send + Sync + Copy + 'static,
+    R: Fn(Event) + Send + Sync + Copy + 'static,
+{
+    let (tx, rx) = unbounded::<notify::DebouncedEvent>();
+
+    let watch_dir = Path::new(watch_dir);
+
+    // Every batch is a write transaction, so batches are written one at a time however many threads there are.
+    let exec_pool = ThreadPool::new(config.import.threads());
+
+    // Every batch that finishes makes room in the import queue, which batches held while it was
+    // full wait for alongside the changes to the folder, so the watcher can still exit meanwhile.
+    let (room_tx, room_rx) = bounded::<()>(1);
+
+    let dispatch = |queue: &mut WatchQueue<DiskFileSystem>, batches: Vec<Vec<FileGroup>>| {
+        let throttle = import_throttle();
+        let threads = throttle.map_or(config.import.threads(), |throttle| {
+            throttle.threads.min(config.import.threads())
+        });
+        if exec_pool.max_count() != threads {
+            // Clones of the pool share its threads.
+            exec_pool.clone().set_num_threads(threads);
+        }
+        let batches = match throttle {
+            Some(_) => batches.into_iter().flatten().map(|group| vec![group]).collect(),
+            None => batches,
+        };
+        let mut batches = batches.into_iter();
+        while let Some(batch) = batches.next() {
+            if config.import.is_full(exec_pool.queued_count()) {
+                match config.import.backpressure {
+                    Backpressure::Block => {
+                        queue.hold(std::iter::once(batch).chain(batches).collect());
+                        return;
+                    }
+                    Backpressure::Shed => {
+                        report(Event::BatchShed {
+                            files: batch.iter().map(|group| group.len()).sum(),
+                        });
+                        continue;
+                    }
+                }
+            }
+            let (batch, in_flight) = InFlightBatch::take(batch);
+            if batch.is_empty() {
+                continue;
+            }
+            let db_pool = Arc::clone(&pool);
+            let room_tx = room_tx.clone();
+            exec_pool.execute(move || {
+                let _in_flight = in_flight;
+                if let Some(throttle) = import_throttle() {
+                    thread::sleep(throttle.pause);
+                }
+                let db_conn = db_pool.get().unwrap();
+                process_batch(&batch, config, &db_conn, process, report);
+                room_tx.try_send(()).ok();
+            });
+        }
+    };
+    // Held batches are dispatched before the batches ready after them.
+    let flush = |queue: &mut WatchQueue<DiskFileSystem>| {
+        let mut batches = queue.take_held();
+        batches.extend(queue.flush(report));
+        batches
+    };
+    // Automatically select the best implementation for your platform.
+    // You can also access each implementation directly e.g. INotifyWatcher.
+    let mut watcher: RecommendedWatcher = Watcher::new(tx, Duration::from_secs(30))?;
+
+    // Add a path to be watched. All files and directories at that path and
+    // below will be monitored for changes.
+    watcher.watch(watch_dir, RecursiveMode::Recursive)?;
+
+    let mut queue = WatchQueue::new(&DiskFileSystem, watch_dir, config.adopt_layout, downloading);
+    // An adopted music folder is indexed by `adopt` instead, since every file in it stays there.
+    if !config.adopt_layout {
+        let files = queue.reconcile();
+        if files > 0 {
+            report(Event::FilesReconciled { files });
+        }
+    }
+    loop {
+        select! {
+            recv(rx) -> event => match event {
+                Ok(event) => match FolderChange::from_event(event).map(|change| queue.handle(change)) {
+                    Some(Handled::Full) => {
+                        let batches = flush(&mut queue);
+                        dispatch(&mut queue, batches)
+                    }
+                    Some(Handled::Removed(path)) => {
+                        let db_pool = Arc::clone(&pool);
+                        exec_pool.execute(move || remove_tracks(&path, &db_pool.get().unwrap(), report));
+                    }
+                    _ => (),
+                },
+                // If a watch error occurred, break out of the thread
+                // to trigger a thread restart.
+                Err(_) => break,
+            },
+
+            recv(quit_rx) -> keepalive => match keepalive {
+                Ok(WatchStatus::KeepAlive) => (),
+                Ok(WatchStatus::Exit) => break,
+                Err(_) => break,
+            },
+
+            recv(room_rx) -> _ => {
+                let held = queue.take_held();
+                dispatch(&mut queue, held)
+            },
+
+            default(BATCH_QUIET_PERIOD) => {
+                let batches = flush(&mut queue);
+                dispatch(&mut queue, batches)
+            },
+        }
+    }
+    // Batches still held once the watcher exits are left in the folder for the next time it runs.
+    let batches = flush(&mut queue);
+    dispatch(&mut queue, batches);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{DatabaseConfig, DatabaseStorage};
+    use crate::filesystem::{MemoryFile, MemoryFileSystem};
+    use katatsuki::{Track, TrackFileType};
+
+    const WATCH_DIR: &str = "/watch";
+
+    fn library() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        database::enable_foreign_keys(&conn).unwrap();
+        database::add_regexp_function(&conn).unwrap();
+        database::create_database(&conn);
+        conn
+    }
+
+    fn track(title: &str) -> MemoryFile {
+        MemoryFile {
+            size: 100,
+            track: Some(
+                Track::builder("", TrackFileType::FLAC16)
+                    .title(title.to_owned())
+                    .artist("Alpha".to_owned())
+                    .album_artists(vec!["Alpha".to_owned()])
+                    .album("First".to_owned())
+                    .build(),
+            ),
+            ..MemoryFile::default()
+        }
+    }
+
+    /// Moves every track of the group into the library folder of the file system and adds it.
+    fn import(fs: &MemoryFileSystem, group: &[PathBuf], _config: &Config, conn: &Connection) -> Vec<Event> {
+        group
+            .iter()
+            .map(|path| {
+                let track = match fs.read_track(path) {
+                    Ok(track) => track,
+                    Err(_) => return Event::NonTrack(path.display().to_string()),
+                };
+                let destination = Path::new("/library").join(path.file_name().unwrap());
+                if fs.move_file(path, &destination).is_err() {
+                    return Event::TrackMoveError(path.display().to_string());
+                }
+                let track = Track {
+                    file_path: destination,
+                    ..track
+                };
+                Event::TrackAdded {
+                    artist: track.artist.clone(),
+                    title: track.title.clone(),
+                    uuid: database::add_track(&track, conn),
+                }
+            })
+            .collect()
+    }
+
+    fn tracks_in_library(conn: &Connection) -> Vec<String> {
+        let mut statement = conn.prepare("SELECT FilePath FROM tracks ORDER BY FilePath").unwrap();
+        let paths = statement.query_map(rusqlite::NO_PARAMS, |row| row.get(0)).unwrap();
+        paths.collect::<rusqlite::Result<Vec<String>>>().unwrap()
+    }
+
+    fn collect(events: &RefCell<Vec<Event>>) -> impl Fn(Event) + Copy + '_ {
+        move |event| events.borrow_mut().push(event)
+    }
+
+    #[test]
+    fn lists_the_watch_folder_deferring_placeholders() {
+        let fs = MemoryFileSystem::new();
+        fs.add_file("/watch/loose.flac", track("Loose"));
+        fs.add_file("/watch/album/1.flac", track("One"));
+        fs.add_file("/watch/album/cover.jpg", MemoryFile::default());
+        fs.add_file("/watch/.hidden/2.flac", track("Hidden"));
+        fs.add_file(
+            "/watch/cloud/3.flac",
+            MemoryFile {
+                placeholder: true,
+                ..track("Cloud")
+            },
+        );
+        let conn = library();
+        let events = RefCell::new(Vec::new());
+
+        let downloading = list_in(&fs, Path::new(WATCH_DIR), &Config::default(), &conn, import, collect(&events));
+
+        assert_eq!(downloading, [PathBuf::from("/watch/cloud/3.flac")]);
+        assert_eq!(fs.hydrated(), [PathBuf::from("/watch/cloud/3.flac")]);
+        assert_eq!(tracks_in_library(&conn), ["/library/1.flac", "/library/loose.flac"]);
+        assert!(fs.is_file(Path::new("/library/1.flac")));
+        assert!(!fs.is_file(Path::new("/watch/album/1.flac")));
+        assert!(fs.is_file(Path::new("/watch/.hidden/2.flac")));
+        let events = events.into_inner();
+        assert!(events.contains(&Event::WaitingForDownload("/watch/cloud/3.flac".to_owned())));
+        assert!(events.contains(&Event::NonTrack("/watch/album/cover.jpg".to_owned())));
+        assert_eq!(events.last(), Some(&Event::BatchImported { imported: 2, total: 3 }));
+    }
+
+    #[test]
+    fn flushes_a_folder_once_it_settles() {
+        let fs = MemoryFileSystem::new();
+        let mut queue = WatchQueue::new(&fs, Path::new(WATCH_DIR), false, Vec::new());
+        let first = PathBuf::from("/watch/album/1.flac");
+        let second = PathBuf::from("/watch/album/2.flac");
+        fs.add_file(&first, track("One"));
+        fs.add_file(
+            &second,
+            MemoryFile {
+                busy: true,
+                ..track("Two")
+            },
+        );
+
+        assert_eq!(queue.handle(FolderChange::Written(first.clone())), Handled::Queued);
+        assert_eq!(queue.handle(FolderChange::Written(second.clone())), Handled::Ignored);
+        assert_eq!(queue.handle(FolderChange::Written(first.clone())), Handled::Ignored);
+        assert_eq!(queue.handle(FolderChange::Written("/watch/.part".into())), Handled::Ignored);
+        // The folder is only ready once it looks the same on two flushes in a row,
+        // with every file in it done being written to.
+        assert!(queue.flush(|_| ()).is_empty());
+        assert!(queue.flush(|_| ()).is_empty());
+        fs.finish_writing(&second);
+        fs.resize(&second, 200);
+        assert!(queue.flush(|_| ()).is_empty());
+        assert_eq!(queue.flush(|_| ()), [vec![vec![first, second]]]);
+        assert!(queue.pending().is_empty());
+    }
+
+    #[test]
+    fn holds_batches_until_there_is_room_for_them() {
+        let fs = MemoryFileSystem::new();
+        let mut queue = WatchQueue::new(&fs, Path::new(WATCH_DIR), false, Vec::new());
+        let first = PathBuf::from("/watch/1.flac");
+        let second = PathBuf::from("/watch/2.flac");
+        let third = PathBuf::from("/watch/3.flac");
+        for path in [&first, &second, &third] {
+            fs.add_file(path, track("Track"));
+        }
+
+        queue.hold(vec![vec![vec![second.clone()]]]);
+        queue.hold(vec![vec![vec![first.clone()]]]);
+        // Held files are not queued again as they are written to while they wait.
+        assert_eq!(queue.handle(FolderChange::Written(first.clone())), Handled::Ignored);
+        assert_eq!(queue.handle(FolderChange::Written(third.clone())), Handled::Queued);
+        assert_eq!(queue.take_held(), [vec![vec![first.clone()]], vec![vec![second]]]);
+        assert!(queue.take_held().is_empty());
+        assert_eq!(queue.handle(FolderChange::Written(first)), Handled::Queued);
+    }
+
+    #[test]
+    fn flushes_a_placeholder_once_it_is_downloaded() {
+        let fs = MemoryFileSystem::new();
+        let placeholder = PathBuf::from("/watch/cloud.flac");
+        fs.add_file(
+            &placeholder,
+            MemoryFile {
+                placeholder: true,
+                ..track("Cloud")
+            },
+        );
+        let mut queue = WatchQueue::new(&fs, Path::new(WATCH_DIR), false, Vec::new());
+        let events = RefCell::new(Vec::new());
+
+        assert_eq!(queue.handle(FolderChange::Written(placeholder.clone())), Handled::Queued);
+        assert!(queue.flush(collect(&events)).is_empty());
+        assert!(queue.flush(collect(&events)).is_empty());
+        // The placeholder is only downloaded and reported once, however long it is waited on.
+        assert_eq!(fs.hydrated(), std::slice::from_ref(&placeholder));
+        assert_eq!(events.into_inner(), [Event::WaitingForDownload(placeholder.display().to_string())]);
+        fs.download(&placeholder);
+        assert_eq!(queue.flush(|_| ()), [vec![vec![placeholder]]]);
+    }
+
+    #[test]
+    fn processes_a_batch_reporting_once_committed() {
+        let fs = MemoryFileSystem::new();
+        fs.add_file("/watch/album/1.flac", track("One"));
+        fs.add_file("/watch/album/2.flac", track("Two"));
+        let conn = library();
+        let events = RefCell::new(Vec::new());
+        let groups = vec![vec![PathBuf::from("/watch/album/1.flac"), PathBuf::from("/watch/album/2.flac")]];
+
+        process_batch_in(&fs, &groups, &Config::default(), &conn, import, collect(&events));
+
+        let events = events.into_inner();
+        let added = events
+            .iter()
+            .filter_map(|event| match event {
+                Event::TrackAdded { title, .. } => Some(title.as_str()),
+                _ => None,
+            })
+            .collect::<Vec<&str>>();
+        assert_eq!(added, ["One", "Two"]);
+        assert_eq!(events.last(), Some(&Event::BatchImported { imported: 2, total: 2 }));
+        assert_eq!(tracks_in_library(&conn), ["/library/1.flac", "/library/2.flac"]);
+        assert!(fs.files_under(Path::new(WATCH_DIR)).is_empty());
+    }
+
+    #[test]
+    fn renews_the_lease_where_other_writers_see_it() {
+        let fs = MemoryFileSystem::new();
+        fs.add_file("/watch/one/1.flac", track("One"));
+        fs.add_file("/watch/two/2.flac", track("Two"));
+        let pool = database::get_configured_connection_pool(&DatabaseConfig {
+            storage: DatabaseStorage::Temporary,
+            ..DatabaseConfig::default()
+        });
+        let (conn, other) = (pool.get().unwrap(), pool.get().unwrap());
+        let groups = vec![vec![PathBuf::from("/watch/one/1.flac")], vec![PathBuf::from("/watch/two/2.flac")]];
+        // The lease is about to lapse while the first group is processed, and is renewed where
+        // other writers can see it by the time the second group is.
+        let check = |fs: &MemoryFileSystem, group: &[PathBuf], config: &Config, conn: &Connection| {
+            if group[0].ends_with("1.flac") {
+                other
+                    .execute_batch("UPDATE write_lease SET Expires = CAST(strftime('%s', 'now') AS INTEGER) + 1")
+                    .unwrap();
+            } else {
+                let left = other
+                    .query_row(
+                        "SELECT Expires - CAST(strftime('%s', 'now') AS INTEGER) FROM write_lease",
+                        rusqlite::NO_PARAMS,
+                        |row| row.get::<_, i64>(0),
+                    )
+                    .unwrap();
+                assert!(left > 10);
+            }
+            import(fs, group, config, conn)
+        };
+
+        process_batch_in(&fs, &groups, &Config::default(), &conn, check, |_| ());
+
+        assert_eq!(tracks_in_library(&conn), ["/library/1.flac", "/library/2.flac"]);
+    }
+
+    #[test]
+    fn moves_the_files_of_an_abandoned_batch_back() {
+        let fs = MemoryFileSystem::new();
+        fs.add_file("/watch/loose.flac", track("Loose"));
+        let conn = library();
+        let events = RefCell::new(Vec::new());
+        // Another writer takes the lease while the group is processed, so the batch is abandoned.
+        let steal = |fs: &MemoryFileSystem, group: &[PathBuf], config: &Config, conn: &Connection| {
+            let events = import(fs, group, config, conn);
+            conn.execute_batch("UPDATE write_lease SET Holder = 'someone-else'").unwrap();
+            events
+        };
+
+        process_batch_in(&fs, &[vec![PathBuf::from("/watch/loose.flac")]], &Config::default(), &conn, steal, collect(&events));
+
+        assert!(fs.is_file(Path::new("/watch/loose.flac")));
+        assert!(!fs.is_file(Path::new("/library/loose.flac")));
+        assert!(tracks_in_library(&conn).is_empty());
+        assert!(!events
+            .into_inner()
+            .iter()
+            .any(|event| matches!(event, Event::TrackAdded { .. } | Event::BatchImported { .. })));
+    }
+}
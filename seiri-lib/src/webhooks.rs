@@ -0,0 +1,88 @@
+//! Delivery of events to webhooks, so that seiri can notify other services of what it does.
+//!
+//! Every event is posted to each webhook that accepts its code as a JSON object,
+//! such as `{"code": "TRACKADDED", "args": ["Artist", "Title", "uuid"], "error": false}`.
+//! Events are delivered in order on a background thread, so the watcher never waits on
+//! a webhook. A webhook that can not be reached is retried with a doubling delay,
+//! after which the event is dropped and reported as `Event::WebhookError`.
+
+use crate::config::WebhookConfig;
+use crate::error::{Error, Result};
+use crate::events::Event;
+use serde_json::json;
+use std::sync::mpsc::{channel, Sender};
+use std::thread;
+use std::time::Duration;
+
+/// How long to wait before retrying delivery the first time. Every retry after waits twice as long.
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// How long to wait for a webhook to respond.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The JSON body posted to webhooks for the event.
+pub fn event_json(event: &Event) -> String {
+    json!({
+        "code": event.code(),
+        "args": event.args(),
+        "error": event.is_error(),
+    })
+    .to_string()
+}
+
+/// Posts the event to the webhook, retrying as many times as the webhook is configured to.
+///
+/// Returns an error if every attempt failed, or the webhook responded with an error status.
+pub fn deliver(webhook: &WebhookConfig, event: &Event) -> Result<()> {
+    let body = event_json(event);
+    let mut delay = RETRY_DELAY;
+    for attempt in 0..=webhook.retries {
+        if attempt > 0 {
+            thread::sleep(delay);
+            delay *= 2;
+        }
+        let response = ureq::post(&webhook.url)
+            .timeout(TIMEOUT)
+            .set("Content-Type", "application/json")
+            .send_string(&body);
+        match response {
+            Ok(_) => return Ok(()),
+            // The webhook was reached and rejected the event, so it would only reject it again.
+            Err(ureq::Error::Status(status, _)) if status < 500 => break,
+            Err(_) => continue,
+        }
+    }
+    Err(Error::WebhookFailed(webhook.url.to_owned()))
+}
+
+/// Sends events to the webhooks they are delivered to.
+pub struct Webhooks {
+    sender: Sender<Event>,
+}
+
+impl Webhooks {
+    /// Starts delivering events to the webhooks on a background thread, reporting events that
+    /// could not be delivered to `report`. Failed deliveries are never posted to webhooks.
+    pub fn start<R>(webhooks: Vec<WebhookConfig>, report: R) -> Webhooks
+    where
+        R: Fn(Event) + Send + 'static,
+    {
+        let (sender, receiver) = channel::<Event>();
+        thread::spawn(move || {
+            for event in receiver {
+                for webhook in webhooks.iter().filter(|webhook| webhook.accepts(event.code())) {
+                    if deliver(webhook, &event).is_err() {
+                        report(Event::WebhookError(webhook.url.to_owned()));
+                    }
+                }
+            }
+        });
+        Webhooks { sender }
+    }
+
+    /// Queues the event for delivery to every webhook that accepts it.
+    pub fn send(&self, event: &Event) {
+        // The delivery thread only stops once every sender is dropped.
+        self.sender.send(event.clone()).ok();
+    }
+}
@@ -2,5 +2,11 @@ const addon = require('../native');
 
 module.exports = {
     queryTracks: addon.queryTracks,
+    countTracks: addon.countTracks,
+    linkTracks: addon.linkTracks,
+    unlinkTracks: addon.unlinkTracks,
+    getTrackRelationships: addon.getTrackRelationships,
+    setReleaseGroup: addon.setReleaseGroup,
+    previewNormalization: addon.previewNormalization,
     refreshTracks: addon.refreshTracks
 };
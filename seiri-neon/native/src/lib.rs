@@ -1,11 +1,183 @@
 use neon::prelude::*;
 use num_traits::cast::ToPrimitive;
+use seiri::aliases;
+use seiri::analysis;
+use seiri::art;
+use seiri::cache::{QueryCache, DEFAULT_CAPACITY};
+use seiri::columns::{self, TrackColumn};
 use seiri::config::get_config;
 use seiri::database;
+use seiri::events::Event;
+use seiri::fields;
+use seiri::gains;
+use seiri::genres;
+use seiri::lease;
+use seiri::library;
+use seiri::locks;
+use seiri::notes;
 use seiri::paths;
+use seiri::provenance;
+use seiri::editions;
+use seiri::normalization;
+use seiri::queue;
+use seiri::relationships;
+use seiri::search::IncrementalSearch;
+use seiri::suggestions::{self, SuggestionField};
+use seiri::bangs::Relation;
 use seiri::Bang;
 use seiri::Track;
-use std::path::Path;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Results of recent queries, so searching as the user types rarely hits the database.
+static SEARCH: Mutex<IncrementalSearch> =
+    Mutex::new(IncrementalSearch::new(QueryCache::new(DEFAULT_CAPACITY)));
+
+/// The name the client holds the write lease under, when writing to the library itself.
+const LEASE_HOLDER: &str = "seiri-client";
+
+/// Reports the events of writes made by the client, such as losing the write lease, on stderr,
+/// as seiri-watcher does. The in-process watcher and its events are in seiri-node.
+fn report(event: Event) {
+    eprintln!("{}", event);
+}
+
+fn track_to_js<'a>(ctx: &mut FunctionContext<'a>, track: &Track) -> JsResult<'a, JsObject> {
+    track_columns_to_js(ctx, track, TrackColumn::ALL, None)
+}
+
+/// Converts only the given columns of the track, along with the gain to play it at, if it has one.
+#[allow(non_snake_case)]
+fn track_columns_to_js<'a>(
+    ctx: &mut FunctionContext<'a>,
+    track: &Track,
+    columns: &[TrackColumn],
+    gain: Option<f64>,
+) -> JsResult<'a, JsObject> {
+    let jsTrack = ctx.empty_object();
+    for column in columns {
+        match column {
+            TrackColumn::Path => {
+                let filePath = ctx.string(track.file_path.to_string_lossy());
+                jsTrack.set(ctx, "filePath", filePath)?;
+            }
+            TrackColumn::Title => {
+                let title = ctx.string(&track.title);
+                jsTrack.set(ctx, "title", title)?;
+            }
+            TrackColumn::Artist => {
+                let artist = ctx.string(&track.artist);
+                jsTrack.set(ctx, "artist", artist)?;
+            }
+            TrackColumn::AlbumArtists => {
+                let jsAlbumArtists = ctx.empty_array();
+                for (i, artist) in track.album_artists.iter().enumerate() {
+                    let jsArtistString = ctx.string(artist);
+                    jsAlbumArtists.set(ctx, i as u32, jsArtistString)?;
+                }
+                jsTrack.set(ctx, "albumArtists", jsAlbumArtists)?;
+            }
+            TrackColumn::Genres => {
+                let jsGenres = ctx.empty_array();
+                for (i, genre) in track.genres.iter().enumerate() {
+                    let jsGenreString = ctx.string(genre);
+                    jsGenres.set(ctx, i as u32, jsGenreString)?;
+                }
+                jsTrack.set(ctx, "genres", jsGenres)?;
+            }
+            TrackColumn::Album => {
+                let album = ctx.string(&track.album);
+                jsTrack.set(ctx, "album", album)?;
+            }
+            TrackColumn::Year => {
+                let year = ctx.number(track.year);
+                jsTrack.set(ctx, "year", year)?;
+            }
+            TrackColumn::TrackNumber => {
+                let trackNumber = ctx.number(track.track_number);
+                jsTrack.set(ctx, "trackNumber", trackNumber)?;
+            }
+            TrackColumn::MusicbrainzTrackId => {
+                optional_string_to_js(ctx, jsTrack, "musicbrainzTrackId", &track.musicbrainz_track_id)?
+            }
+            TrackColumn::Comment => optional_string_to_js(ctx, jsTrack, "comment", &track.comment)?,
+            TrackColumn::Isrc => optional_string_to_js(ctx, jsTrack, "isrc", &track.isrc)?,
+            TrackColumn::Encoder => optional_string_to_js(ctx, jsTrack, "encoder", &track.encoder)?,
+            TrackColumn::FrontCover => {
+                let hasFrontCover = ctx.boolean(track.has_front_cover);
+                jsTrack.set(ctx, "hasFrontCover", hasFrontCover)?;
+
+                let frontCoverHeight = ctx.number(track.front_cover_height);
+                jsTrack.set(ctx, "frontCoverHeight", frontCoverHeight)?;
+
+                let frontCoverWidth = ctx.number(track.front_cover_width);
+                jsTrack.set(ctx, "frontCoverWidth", frontCoverWidth)?;
+            }
+            TrackColumn::Bitrate => {
+                let bitrate = ctx.number(track.bitrate);
+                jsTrack.set(ctx, "bitrate", bitrate)?;
+            }
+            TrackColumn::SampleRate => {
+                let sampleRate = ctx.number(track.sample_rate);
+                jsTrack.set(ctx, "sampleRate", sampleRate)?;
+            }
+            TrackColumn::Source => {
+                let source = ctx.string(&track.source);
+                jsTrack.set(ctx, "source", source)?;
+            }
+            TrackColumn::DiscNumber => {
+                let discNumber = ctx.number(track.disc_number);
+                jsTrack.set(ctx, "discNumber", discNumber)?;
+            }
+            TrackColumn::Duration => {
+                let duration = ctx.number(track.duration);
+                jsTrack.set(ctx, "duration", duration)?;
+            }
+            TrackColumn::FileType => {
+                let fileType = ctx.number(track.file_type.to_i32().unwrap());
+                jsTrack.set(ctx, "fileType", fileType)?;
+            }
+            TrackColumn::Updated => {
+                let updated = ctx.string(&track.updated);
+                jsTrack.set(ctx, "updated", updated)?;
+            }
+            TrackColumn::Uuid => optional_string_to_js(ctx, jsTrack, "uuid", &track.uuid)?,
+            TrackColumn::Gain => {
+                let gain: Handle<JsValue> = match gain {
+                    Some(gain) => ctx.number(gain).upcast(),
+                    None => ctx.null().upcast(),
+                };
+                jsTrack.set(ctx, "gain", gain)?;
+            }
+            TrackColumn::Hash => {
+                let hash = ctx.string(columns::track_hash(track));
+                jsTrack.set(ctx, "hash", hash)?;
+            }
+        }
+    }
+    Ok(jsTrack)
+}
+
+/// Sets the key of the object to the string, or to null if there is none.
+fn optional_string_to_js<'a>(
+    ctx: &mut FunctionContext<'a>,
+    object: Handle<'a, JsObject>,
+    key: &str,
+    value: &Option<String>,
+) -> NeonResult<()> {
+    match value {
+        Some(value) => {
+            let value = ctx.string(value);
+            object.set(ctx, key, value)
+        }
+        None => {
+            let null = ctx.null();
+            object.set(ctx, key, null)
+        }
+    }?;
+    Ok(())
+}
 
 #[allow(non_snake_case)]
 fn refresh_tracks(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
@@ -25,31 +197,50 @@ fn refresh_tracks(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
         track_filenames.push(result);
     }
 
-    for file in track_filenames {
-        let tracks = database::query_tracks(Bang::FilePath(file.clone()), &conn, None, None);
-        if let Ok(tracks) = tracks {
-            if let Some(track) = tracks.into_iter().next() {
-                match paths::reconsider_track(&track, &library_path) {
-                    Ok(Some(new_track)) => {
-                        println!("RECONSIDERED OK {:?}", new_track);
-                        database::remove_track(&track, &conn);
-                        database::add_track(&new_track, &conn);
+    let result = lease::with_lease(LEASE_HOLDER, &conn, report, || {
+        for file in track_filenames {
+            let tracks = database::query_tracks(Bang::FilePath(file.clone()), &conn, None, None);
+            if let Ok(tracks) = tracks {
+                if let Some(track) = tracks.into_iter().next() {
+                    if locks::is_locked(&track, &conn).unwrap_or(false) {
+                        println!("RECONSIDER SKIPPED LOCKED {}", file);
+                        continue;
                     }
-                    Ok(None) => {
-                        println!("RECONSIDERED NOT FOUND {:?}", track);
-                        database::remove_track(&track, &conn);
+                    match paths::reconsider_track(&track, library_path, config.folder_casing, &config.layouts, &config.required_tags) {
+                        Ok(Some(new_track)) => {
+                            println!("RECONSIDERED OK {:?}", new_track);
+                            if let Err(err) = database::replace_track(&track, &new_track, &conn) {
+                                println!("RECONSIDER ERROR FOR {}. {}", file, err)
+                            }
+                        }
+                        Ok(None) => {
+                            println!("RECONSIDERED NOT FOUND {:?}", track);
+                            database::remove_track(&track, &conn);
+                        }
+                        Err(_) => {
+                            println!(
+                                "RECONSIDER ERROR FOR {}. Is tools set up correctly?",
+                                file
+                            )
+                        },
                     }
-                    Err(_) => {
-                        println!(
-                            "RECONSIDER ERROR FOR {}. Is tools set up correctly?",
-                            file
-                        )
-                    },
                 }
             }
         }
+    });
+    match result {
+        Ok(()) => Ok(ctx.undefined()),
+        Err(e) => ctx.throw_error(e.to_string()),
     }
-    Ok(ctx.undefined())
+}
+
+/// Whether the option of the options object is true. Options that are not booleans are false.
+fn option_flag(ctx: &mut FunctionContext, options: Handle<JsObject>, name: &str) -> NeonResult<bool> {
+    let option = options.get(ctx, name)?;
+    Ok(option
+        .downcast::<JsBoolean, _>(ctx)
+        .map(|option| option.value(ctx))
+        .unwrap_or(false))
 }
 
 #[allow(non_snake_case)]
@@ -57,81 +248,74 @@ fn query_tracks(mut ctx: FunctionContext) -> JsResult<JsObject> {
     let ret = ctx.empty_object();
 
     let query = ctx.argument::<JsString>(0)?.value(&mut ctx);
+    // Only the given columns of every track are returned, if any are given.
+    let mut column_names: Vec<String> = Vec::new();
+    if let Some(columns) = ctx
+        .argument_opt(1)
+        .and_then(|columns| columns.downcast::<JsArray, _>(&mut ctx).ok())
+    {
+        for i in 0..columns.len(&mut ctx) {
+            let column = columns
+                .get(&mut ctx, i)?
+                .downcast::<JsString, _>(&mut ctx)
+                .or_throw(&mut ctx)?
+                .value(&mut ctx);
+            column_names.push(column);
+        }
+    }
+    let columns = match columns::parse_column_names(column_names.iter().map(String::as_str)) {
+        Ok(columns) => columns,
+        Err(e) => return ctx.throw_error(e.to_string()),
+    };
+    // The third argument is an object of options for the results.
+    let options = ctx
+        .argument_opt(2)
+        .and_then(|options| options.downcast::<JsObject, _>(&mut ctx).ok());
+    let (collapse, editions) = match options {
+        Some(options) => (
+            option_flag(&mut ctx, options, "collapse")?,
+            option_flag(&mut ctx, options, "editions")?,
+        ),
+        None => (false, false),
+    };
 
-    let bang = Bang::new(&query).unwrap();
+    let bang = match Bang::new(&query) {
+        Ok(bang) => bang,
+        Err(e) => return ctx.throw_error(e.to_string()),
+    };
     let conn = database::get_database_connection();
-    let results = database::query_tracks(bang, &conn, None, None);
+    let results = match SEARCH.lock() {
+        Ok(mut search) => search.search(bang, &conn),
+        Err(_) => database::query_tracks(bang, &conn, None, None).map(Arc::new),
+    };
+    let results = match results {
+        Ok(results) if collapse => relationships::collapse_versions(results.to_vec(), &conn).map(Arc::new),
+        results => results,
+    };
+    let results = match results {
+        Ok(results) if editions => {
+            let preference = get_config().map(|config| config.editions.prefer).unwrap_or_default();
+            editions::preferred_editions(results.to_vec(), preference, &conn).map(Arc::new)
+        }
+        results => results,
+    };
+
+    // Gains are only looked up if they are asked for, since they are not kept with the tracks.
+    let gains = match results {
+        Ok(ref results) if columns.contains(&TrackColumn::Gain) => {
+            let playback = get_config().map(|config| config.playback).unwrap_or_default();
+            gains::get_playback_gains(results, &playback, &conn).unwrap_or_default()
+        }
+        _ => HashMap::new(),
+    };
 
     let result: JsResult<JsObject> = match results {
         Ok(results) => {
             let jsTracks = ctx.empty_array();
 
-            for (i, track) in results.into_iter().enumerate() {
-                let jsTrack = ctx.empty_object();
-                let filePath = ctx.string(&track.file_path.into_os_string().into_string().unwrap());
-                jsTrack.set(&mut ctx, "filePath", filePath)?;
-        
-                let title = ctx.string(&track.title);
-                jsTrack.set(&mut ctx, "title", title)?;
-        
-                let artist = ctx.string(&track.artist);
-                jsTrack.set(&mut ctx, "artist", artist)?;
-        
-                let jsAlbumArtists = ctx.empty_array();
-        
-                for (i, artist) in track.album_artists.into_iter().enumerate() {
-                    let jsArtistString = ctx.string(&artist);
-                    jsAlbumArtists.set(&mut ctx, i as u32, jsArtistString)?;
-                }
-        
-                jsTrack.set(&mut ctx, "albumArtists", jsAlbumArtists)?;
-                let album = ctx.string(&track.album);
-                jsTrack.set(&mut ctx, "album", album)?;
-        
-                let trackNumber = ctx.number(track.track_number);
-                jsTrack.set(&mut ctx, "trackNumber", trackNumber)?;
-        
-                match &track.musicbrainz_track_id {
-                    Some(track_id) => {
-                        let trackId = ctx.string(track_id);
-                        jsTrack.set(&mut ctx, "musicbrainzTrackId", trackId)
-                    }
-                    None => {
-                        let null = ctx.null();
-                        jsTrack.set(&mut ctx, "musicbrainzTrackId", null)
-                    }
-                }?;
-        
-                let hasFrontCover = ctx.boolean(track.has_front_cover);
-                jsTrack.set(&mut ctx, "hasFrontCover", hasFrontCover)?;
-        
-                let frontCoverHeight = ctx.number(track.front_cover_height);
-                jsTrack.set(&mut ctx, "frontCoverHeight", frontCoverHeight)?;
-        
-                let frontCoverWidth = ctx.number(track.front_cover_width);
-                jsTrack.set(&mut ctx, "frontCoverWidth", frontCoverWidth)?;
-        
-                let bitrate = ctx.number(track.bitrate);
-                jsTrack.set(&mut ctx, "bitrate", bitrate)?;
-        
-                let sampleRate = ctx.number(track.sample_rate);
-                jsTrack.set(&mut ctx, "sampleRate", sampleRate)?;
-        
-                let source = ctx.string(&track.source);
-                jsTrack.set(&mut ctx, "source", source)?;
-        
-                let discNumber = ctx.number(track.disc_number);
-                jsTrack.set(&mut ctx, "discNumber", discNumber)?;
-        
-                let duration = ctx.number(track.duration);
-                jsTrack.set(&mut ctx, "duration", duration)?;
-        
-                let fileType = ctx.number(track.file_type.to_i32().unwrap());
-                jsTrack.set(&mut ctx, "fileType", fileType)?;
-        
-                let updated = ctx.string(&track.updated);
-                jsTrack.set(&mut ctx, "updated", updated)?;
-        
+            for (i, track) in results.iter().enumerate() {
+                let gain = track.uuid.as_ref().and_then(|uuid| gains.get(uuid)).copied();
+                let jsTrack = track_columns_to_js(&mut ctx, track, &columns, gain)?;
                 jsTracks.set(&mut ctx, i as u32, jsTrack)?;
             }
             ret.set(&mut ctx, "tracks", jsTracks)?;
@@ -144,8 +328,599 @@ fn query_tracks(mut ctx: FunctionContext) -> JsResult<JsObject> {
     result
 }
 
+/// Gets every change the configured normalization rules would make to the tracks matching the
+/// bang query, or to every track if there is no query, without changing them.
+fn preview_normalization(mut ctx: FunctionContext) -> JsResult<JsArray> {
+    let query = match ctx.argument_opt(0) {
+        Some(query) => query.downcast::<JsString, _>(&mut ctx).or_throw(&mut ctx)?.value(&mut ctx),
+        None => String::new(),
+    };
+    let config = match get_config() {
+        Ok(config) => config,
+        Err(e) => return ctx.throw_error(e.to_string()),
+    };
+    let bang = match Bang::new(&query) {
+        Ok(bang) => bang,
+        Err(e) => return ctx.throw_error(e.to_string()),
+    };
+    let conn = database::get_database_connection();
+    let tracks = match database::query_tracks(bang, &conn, None, None) {
+        Ok(tracks) => tracks,
+        Err(e) => return ctx.throw_error(e.to_string()),
+    };
+    let js_changes = ctx.empty_array();
+    for (i, change) in normalization::preview(&tracks, &config.normalization).iter().enumerate() {
+        let js_change = ctx.empty_object();
+        let rule = ctx.number(change.rule as f64);
+        js_change.set(&mut ctx, "rule", rule)?;
+        let rule_name = ctx.string(config.normalization[change.rule].name());
+        js_change.set(&mut ctx, "ruleName", rule_name)?;
+        let path = ctx.string(change.file_path.to_string_lossy());
+        js_change.set(&mut ctx, "path", path)?;
+        let tag = ctx.string(&change.tag);
+        js_change.set(&mut ctx, "tag", tag)?;
+        let before = ctx.string(&change.before);
+        js_change.set(&mut ctx, "before", before)?;
+        let after = ctx.string(&change.after);
+        js_change.set(&mut ctx, "after", after)?;
+        js_changes.set(&mut ctx, i as u32, js_change)?;
+    }
+    Ok(js_changes)
+}
+
+/// Counts the tracks matching the bang query, without reading any of them.
+fn count_tracks(mut ctx: FunctionContext) -> JsResult<JsNumber> {
+    let query = ctx.argument::<JsString>(0)?.value(&mut ctx);
+    let bang = match Bang::new(&query) {
+        Ok(bang) => bang,
+        Err(e) => return ctx.throw_error(e.to_string()),
+    };
+    let conn = database::get_database_connection();
+    match database::count(bang, &conn) {
+        Ok(count) => Ok(ctx.number(count as f64)),
+        Err(e) => ctx.throw_error(e.to_string()),
+    }
+}
+
+/// Builds a queue of up to the given number of tracks related to the track with the given UUID.
+fn similar_tracks(mut ctx: FunctionContext) -> JsResult<JsArray> {
+    let track_id = ctx.argument::<JsString>(0)?.value(&mut ctx);
+    let n = ctx.argument::<JsNumber>(1)?.value(&mut ctx);
+    let conn = database::get_database_connection();
+    let tracks = match library::similar(&track_id, n.max(0.0) as usize, &conn) {
+        Ok(tracks) => tracks,
+        Err(e) => return ctx.throw_error(e.to_string()),
+    };
+    let js_tracks = ctx.empty_array();
+    for (i, track) in tracks.iter().enumerate() {
+        let js_track = track_to_js(&mut ctx, track)?;
+        js_tracks.set(&mut ctx, i as u32, js_track)?;
+    }
+    Ok(js_tracks)
+}
+
+/// Suggests artist, album or genre names starting with a prefix as it is typed, as
+/// `{ name, tracks }`, the names of the most tracks first.
+fn suggest(mut ctx: FunctionContext) -> JsResult<JsArray> {
+    let field = ctx.argument::<JsString>(0)?.value(&mut ctx);
+    let prefix = ctx.argument::<JsString>(1)?.value(&mut ctx);
+    let limit = ctx.argument::<JsNumber>(2)?.value(&mut ctx);
+    let field = match SuggestionField::from_name(&field) {
+        Some(field) => field,
+        None => return ctx.throw_error(format!("Unknown field {}", field)),
+    };
+    let conn = database::get_database_connection();
+    let suggestions = match suggestions::suggest(field, &prefix, limit.max(0.0) as u32, &conn) {
+        Ok(suggestions) => suggestions,
+        Err(e) => return ctx.throw_error(e.to_string()),
+    };
+    let js_suggestions = ctx.empty_array();
+    for (i, suggestion) in suggestions.iter().enumerate() {
+        let js_suggestion = ctx.empty_object();
+        let js_name = ctx.string(&suggestion.name);
+        js_suggestion.set(&mut ctx, "name", js_name)?;
+        let js_tracks = ctx.number(suggestion.tracks as f64);
+        js_suggestion.set(&mut ctx, "tracks", js_tracks)?;
+        js_suggestions.set(&mut ctx, i as u32, js_suggestion)?;
+    }
+    Ok(js_suggestions)
+}
+
+/// Compacts the database and refreshes its statistics, returning its size
+/// in pages before and after, and the number of cached images removed.
+fn maintain_database(mut ctx: FunctionContext) -> JsResult<JsObject> {
+    let conn = database::get_database_connection();
+    let report = match lease::with_lease(LEASE_HOLDER, &conn, report, || database::maintain(&conn)) {
+        Ok(Ok(report)) => report,
+        Ok(Err(e)) | Err(e) => return ctx.throw_error(e.to_string()),
+    };
+    let js_report = ctx.empty_object();
+    let pages_before = ctx.number(report.pages_before as f64);
+    js_report.set(&mut ctx, "pagesBefore", pages_before)?;
+    let pages_after = ctx.number(report.pages_after as f64);
+    js_report.set(&mut ctx, "pagesAfter", pages_after)?;
+    let art_pruned = ctx.number(report.art_pruned as f64);
+    js_report.set(&mut ctx, "artPruned", art_pruned)?;
+    Ok(js_report)
+}
+
+/// Adds an alias for an artist, so that searching for either name finds tracks under both.
+fn add_artist_alias(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+    let alias = ctx.argument::<JsString>(0)?.value(&mut ctx);
+    let artist = ctx.argument::<JsString>(1)?.value(&mut ctx);
+    let conn = database::get_database_connection();
+    if let Err(e) = aliases::add_alias(&alias, &artist, &conn) {
+        return ctx.throw_error(e.to_string());
+    }
+    Ok(ctx.undefined())
+}
+
+/// Removes an alias, returning false if there was no such alias.
+fn remove_artist_alias(mut ctx: FunctionContext) -> JsResult<JsBoolean> {
+    let alias = ctx.argument::<JsString>(0)?.value(&mut ctx);
+    let conn = database::get_database_connection();
+    match aliases::remove_alias(&alias, &conn) {
+        Ok(removed) => Ok(ctx.boolean(removed)),
+        Err(e) => ctx.throw_error(e.to_string()),
+    }
+}
+
+/// Locks or unlocks the track with the given UUID, so automation leaves it alone.
+/// Returns false if there is no such track.
+fn set_track_locked(mut ctx: FunctionContext) -> JsResult<JsBoolean> {
+    let uuid = ctx.argument::<JsString>(0)?.value(&mut ctx);
+    let locked = ctx.argument::<JsBoolean>(1)?.value(&mut ctx);
+    let conn = database::get_database_connection();
+    let result = if locked {
+        locks::lock_track(&uuid, &conn)
+    } else {
+        locks::unlock_track(&uuid, &conn).map(|_| true)
+    };
+    match result {
+        Ok(found) => Ok(ctx.boolean(found)),
+        Err(e) => ctx.throw_error(e.to_string()),
+    }
+}
+
+/// Gets the private note of the track with the given UUID, or null if it has none.
+fn get_track_note(mut ctx: FunctionContext) -> JsResult<JsValue> {
+    let uuid = ctx.argument::<JsString>(0)?.value(&mut ctx);
+    let conn = database::get_database_connection();
+    match notes::get_note(&uuid, &conn) {
+        Ok(Some(note)) => Ok(ctx.string(note).upcast()),
+        Ok(None) => Ok(ctx.null().upcast()),
+        Err(e) => ctx.throw_error(e.to_string()),
+    }
+}
+
+/// Sets the private note of the track with the given UUID, removing it if the note is empty.
+/// Returns false if there is no such track.
+fn set_track_note(mut ctx: FunctionContext) -> JsResult<JsBoolean> {
+    let uuid = ctx.argument::<JsString>(0)?.value(&mut ctx);
+    let note = ctx.argument::<JsString>(1)?.value(&mut ctx);
+    let conn = database::get_database_connection();
+    match notes::set_note(&uuid, &note, &conn) {
+        Ok(found) => Ok(ctx.boolean(found)),
+        Err(e) => ctx.throw_error(e.to_string()),
+    }
+}
+
+/// Gets how the track with the given UUID was ripped from a CD, as an object of its ripper,
+/// what AccurateRip found and with what confidence, the CRC of the copy, and whether the rip log
+/// was signed. Returns null if the track was not taken in from a rip.
+fn get_track_provenance(mut ctx: FunctionContext) -> JsResult<JsValue> {
+    let uuid = ctx.argument::<JsString>(0)?.value(&mut ctx);
+    let conn = database::get_database_connection();
+    let provenance = match provenance::get_provenance(&uuid, &conn) {
+        Ok(Some(provenance)) => provenance,
+        Ok(None) => return Ok(ctx.null().upcast()),
+        Err(e) => return ctx.throw_error(e.to_string()),
+    };
+    let js_provenance = ctx.empty_object();
+    let ripper = ctx.string(&provenance.ripper);
+    js_provenance.set(&mut ctx, "ripper", ripper)?;
+    let accurate_rip = ctx.string(provenance.accurate_rip.as_str());
+    js_provenance.set(&mut ctx, "accurateRip", accurate_rip)?;
+    let confidence: Handle<JsValue> = match provenance.accurate_rip.confidence() {
+        Some(confidence) => ctx.number(confidence).upcast(),
+        None => ctx.null().upcast(),
+    };
+    js_provenance.set(&mut ctx, "confidence", confidence)?;
+    let copy_crc: Handle<JsValue> = match &provenance.copy_crc {
+        Some(copy_crc) => ctx.string(copy_crc).upcast(),
+        None => ctx.null().upcast(),
+    };
+    js_provenance.set(&mut ctx, "copyCrc", copy_crc)?;
+    let log_checksum = ctx.boolean(provenance.log_checksum);
+    js_provenance.set(&mut ctx, "logChecksum", log_checksum)?;
+    Ok(js_provenance.upcast())
+}
+
+/// Gets the values of the custom fields of the track with the given UUID, by the name of each field.
+fn get_track_fields(mut ctx: FunctionContext) -> JsResult<JsObject> {
+    let uuid = ctx.argument::<JsString>(0)?.value(&mut ctx);
+    let conn = database::get_database_connection();
+    let values = match fields::get_fields(&uuid, &conn) {
+        Ok(values) => values,
+        Err(e) => return ctx.throw_error(e.to_string()),
+    };
+    let js_fields = ctx.empty_object();
+    for (name, value) in values {
+        let value = ctx.string(value);
+        js_fields.set(&mut ctx, name.as_str(), value)?;
+    }
+    Ok(js_fields)
+}
+
+/// Sets the custom field of the track with the given UUID, removing its value if the value is
+/// empty. Throws if the field is not configured or does not allow the value.
+/// Returns false if there is no such track.
+fn set_track_field(mut ctx: FunctionContext) -> JsResult<JsBoolean> {
+    let uuid = ctx.argument::<JsString>(0)?.value(&mut ctx);
+    let name = ctx.argument::<JsString>(1)?.value(&mut ctx);
+    let value = ctx.argument::<JsString>(2)?.value(&mut ctx);
+    let config = match get_config() {
+        Ok(config) => config,
+        Err(e) => return ctx.throw_error(e.to_string()),
+    };
+    let field = match fields::find_field(&config.custom_fields, &name) {
+        Some(field) => field,
+        None => return ctx.throw_error(format!("Unknown field {}", name)),
+    };
+    let conn = database::get_database_connection();
+    let result = if value.trim().is_empty() {
+        fields::remove_field(&uuid, &field.name, &conn).map(|_| true)
+    } else {
+        match field.parse_value(&value) {
+            Some(value) => fields::set_field(&uuid, field, &value, &conn),
+            None => return ctx.throw_error(format!("Invalid value {} of the field {}", value, field.name)),
+        }
+    };
+    match result {
+        Ok(found) => Ok(ctx.boolean(found)),
+        Err(e) => ctx.throw_error(e.to_string()),
+    }
+}
+
+/// Links the track with the first UUID as the given version of the track with the second,
+/// such as `remixof`. Returns false if either track is not in the library.
+fn link_tracks(mut ctx: FunctionContext) -> JsResult<JsBoolean> {
+    let track_id = ctx.argument::<JsString>(0)?.value(&mut ctx);
+    let relation = ctx.argument::<JsString>(1)?.value(&mut ctx);
+    let related_id = ctx.argument::<JsString>(2)?.value(&mut ctx);
+    let relation = match relation.parse::<Relation>() {
+        Ok(relation) => relation,
+        Err(_) => return ctx.throw_error(format!("Unknown relation {}", relation)),
+    };
+    let conn = database::get_database_connection();
+    match relationships::link(&track_id, relation, &related_id, &conn) {
+        Ok(linked) => Ok(ctx.boolean(linked)),
+        Err(e) => ctx.throw_error(e.to_string()),
+    }
+}
+
+/// Sets the MusicBrainz release group of the tracks with the given UUIDs, which editions of
+/// albums are grouped by. An empty release group removes it from the tracks.
+/// Returns the number of tracks set.
+fn set_release_group(mut ctx: FunctionContext) -> JsResult<JsNumber> {
+    let uuids = ctx.argument::<JsArray>(0)?;
+    let release_group_id = ctx.argument::<JsString>(1)?.value(&mut ctx);
+    let conn = database::get_database_connection();
+    let mut set = 0;
+    for i in 0..uuids.len(&mut ctx) {
+        let uuid = uuids
+            .get(&mut ctx, i)?
+            .downcast::<JsString, _>(&mut ctx)
+            .or_throw(&mut ctx)?
+            .value(&mut ctx);
+        match editions::set_release_group(&uuid, &release_group_id, &conn) {
+            Ok(true) => set += 1,
+            Ok(false) => (),
+            Err(e) => return ctx.throw_error(e.to_string()),
+        }
+    }
+    Ok(ctx.number(set))
+}
+
+/// Removes the link between the tracks made by `linkTracks`. Returns whether they were linked.
+fn unlink_tracks(mut ctx: FunctionContext) -> JsResult<JsBoolean> {
+    let track_id = ctx.argument::<JsString>(0)?.value(&mut ctx);
+    let relation = ctx.argument::<JsString>(1)?.value(&mut ctx);
+    let related_id = ctx.argument::<JsString>(2)?.value(&mut ctx);
+    let relation = match relation.parse::<Relation>() {
+        Ok(relation) => relation,
+        Err(_) => return ctx.throw_error(format!("Unknown relation {}", relation)),
+    };
+    let conn = database::get_database_connection();
+    match relationships::unlink(&track_id, relation, &related_id, &conn) {
+        Ok(unlinked) => Ok(ctx.boolean(unlinked)),
+        Err(e) => ctx.throw_error(e.to_string()),
+    }
+}
+
+/// Gets every link to or from the track with the given UUID, as `{ trackId, relation, relatedId }`.
+fn get_track_relationships(mut ctx: FunctionContext) -> JsResult<JsArray> {
+    let track_id = ctx.argument::<JsString>(0)?.value(&mut ctx);
+    let conn = database::get_database_connection();
+    let relationships = match relationships::get_relationships(&track_id, &conn) {
+        Ok(relationships) => relationships,
+        Err(e) => return ctx.throw_error(e.to_string()),
+    };
+    let js_relationships = ctx.empty_array();
+    for (i, relationship) in relationships.iter().enumerate() {
+        let js_relationship = ctx.empty_object();
+        let track_id = ctx.string(&relationship.track_id);
+        js_relationship.set(&mut ctx, "trackId", track_id)?;
+        let relation = ctx.string(relationship.relation.name());
+        js_relationship.set(&mut ctx, "relation", relation)?;
+        let related_id = ctx.string(&relationship.related_id);
+        js_relationship.set(&mut ctx, "relatedId", related_id)?;
+        js_relationships.set(&mut ctx, i as u32, js_relationship)?;
+    }
+    Ok(js_relationships)
+}
+
+/// Gets the canonical name and every alias of the artist with the given name.
+fn get_artist_aliases(mut ctx: FunctionContext) -> JsResult<JsObject> {
+    let name = ctx.argument::<JsString>(0)?.value(&mut ctx);
+    let conn = database::get_database_connection();
+    let (artist, names) = match aliases::get_canonical_artist(&name, &conn)
+        .and_then(|artist| aliases::get_aliases(&artist, &conn).map(|names| (artist, names)))
+    {
+        Ok(aliases) => aliases,
+        Err(e) => return ctx.throw_error(e.to_string()),
+    };
+    let js_aliases = ctx.empty_object();
+    let js_artist = ctx.string(artist);
+    js_aliases.set(&mut ctx, "artist", js_artist)?;
+    let js_names = ctx.empty_array();
+    for (i, name) in names.iter().enumerate() {
+        let js_name = ctx.string(name);
+        js_names.set(&mut ctx, i as u32, js_name)?;
+    }
+    js_aliases.set(&mut ctx, "aliases", js_names)?;
+    Ok(js_aliases)
+}
+
+/// Rewrites the artist of every track tagged with an alias to its canonical name,
+/// returning the number of tracks rewritten.
+fn canonicalize_artists(mut ctx: FunctionContext) -> JsResult<JsNumber> {
+    let conn = database::get_database_connection();
+    match lease::with_lease(LEASE_HOLDER, &conn, report, || aliases::canonicalize_library(&conn)) {
+        Ok(Ok(count)) => Ok(ctx.number(count as f64)),
+        Ok(Err(e)) | Err(e) => ctx.throw_error(e.to_string()),
+    }
+}
+
+/// Places a genre under another in the genre tree, or at the top of the tree if the parent
+/// is null. Returns false if the parent is already under the genre.
+fn set_genre_parent(mut ctx: FunctionContext) -> JsResult<JsBoolean> {
+    let genre = ctx.argument::<JsString>(0)?.value(&mut ctx);
+    let parent = ctx
+        .argument_opt(1)
+        .and_then(|parent| parent.downcast::<JsString, _>(&mut ctx).ok())
+        .map(|parent| parent.value(&mut ctx));
+    let conn = database::get_database_connection();
+    let result = match parent {
+        Some(parent) => genres::set_genre_parent(&genre, &parent, &conn),
+        None => genres::remove_genre_parent(&genre, &conn).map(|_| true),
+    };
+    match result {
+        Ok(set) => Ok(ctx.boolean(set)),
+        Err(e) => ctx.throw_error(e.to_string()),
+    }
+}
+
+/// Gets the genre tree, as an object from every genre with genres under it to those genres.
+fn get_genre_tree(mut ctx: FunctionContext) -> JsResult<JsObject> {
+    let conn = database::get_database_connection();
+    let tree = match genres::get_genre_tree(&conn) {
+        Ok(tree) => tree,
+        Err(e) => return ctx.throw_error(e.to_string()),
+    };
+    let js_tree = ctx.empty_object();
+    for (parent, subgenres) in tree {
+        let js_subgenres = ctx.empty_array();
+        for (i, genre) in subgenres.iter().enumerate() {
+            let js_genre = ctx.string(genre);
+            js_subgenres.set(&mut ctx, i as u32, js_genre)?;
+        }
+        js_tree.set(&mut ctx, parent.as_str(), js_subgenres)?;
+    }
+    Ok(js_tree)
+}
+
+/// Gets the play queue of a profile, as the file paths of its tracks, the index of
+/// the current track, and the offset into it in milliseconds.
+fn get_queue(mut ctx: FunctionContext) -> JsResult<JsObject> {
+    let profile_id = ctx.argument::<JsString>(0)?.value(&mut ctx);
+    let conn = database::get_database_connection();
+    let play_queue = match queue::get_queue(&profile_id, &conn) {
+        Ok(play_queue) => play_queue,
+        Err(e) => return ctx.throw_error(e.to_string()),
+    };
+    let js_queue = ctx.empty_object();
+    let js_tracks = ctx.empty_array();
+    for (i, track) in play_queue.tracks.iter().enumerate() {
+        let js_track = ctx.string(track.to_string_lossy());
+        js_tracks.set(&mut ctx, i as u32, js_track)?;
+    }
+    js_queue.set(&mut ctx, "tracks", js_tracks)?;
+    let current = ctx.number(play_queue.current as f64);
+    js_queue.set(&mut ctx, "current", current)?;
+    let offset = ctx.number(play_queue.offset as f64);
+    js_queue.set(&mut ctx, "offset", offset)?;
+    Ok(js_queue)
+}
+
+/// Replaces the play queue of a profile with the given file paths, starting at the given index.
+fn set_queue(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+    let profile_id = ctx.argument::<JsString>(0)?.value(&mut ctx);
+    let args = ctx.argument::<JsArray>(1)?;
+    let current = ctx.argument::<JsNumber>(2)?.value(&mut ctx);
+    let mut tracks: Vec<PathBuf> = Vec::new();
+    for i in 0..args.len(&mut ctx) {
+        let track = args
+            .get(&mut ctx, i)?
+            .downcast::<JsString, _>(&mut ctx)
+            .or_throw(&mut ctx)?
+            .value(&mut ctx);
+        tracks.push(PathBuf::from(track));
+    }
+    let conn = database::get_database_connection();
+    if let Err(e) = queue::set_queue(&profile_id, &tracks, current.max(0.0) as usize, &conn) {
+        return ctx.throw_error(e.to_string());
+    }
+    Ok(ctx.undefined())
+}
+
+/// Saves how far playback of a profile's queue is, as the index of the current track
+/// and the offset into it in milliseconds.
+fn set_queue_position(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+    let profile_id = ctx.argument::<JsString>(0)?.value(&mut ctx);
+    let current = ctx.argument::<JsNumber>(1)?.value(&mut ctx);
+    let offset = ctx.argument::<JsNumber>(2)?.value(&mut ctx);
+    let conn = database::get_database_connection();
+    if let Err(e) =
+        queue::set_queue_position(&profile_id, current.max(0.0) as usize, offset as i64, &conn)
+    {
+        return ctx.throw_error(e.to_string());
+    }
+    Ok(ctx.undefined())
+}
+
+/// Adds a track to a profile's queue, at the end, or right after the current track
+/// if the third argument is true.
+fn enqueue_track(mut ctx: FunctionContext) -> JsResult<JsUndefined> {
+    let profile_id = ctx.argument::<JsString>(0)?.value(&mut ctx);
+    let file_path = ctx.argument::<JsString>(1)?.value(&mut ctx);
+    let next = ctx
+        .argument_opt(2)
+        .and_then(|next| next.downcast::<JsBoolean, _>(&mut ctx).ok())
+        .is_some_and(|next| next.value(&mut ctx));
+    let conn = database::get_database_connection();
+    let result = if next {
+        queue::play_next(&profile_id, Path::new(&file_path), &conn)
+    } else {
+        queue::enqueue(&profile_id, Path::new(&file_path), &conn)
+    };
+    if let Err(e) = result {
+        return ctx.throw_error(e.to_string());
+    }
+    Ok(ctx.undefined())
+}
+
+/// Removes the track at the given index from a profile's queue,
+/// returning false if there was no track at the index.
+fn remove_from_queue(mut ctx: FunctionContext) -> JsResult<JsBoolean> {
+    let profile_id = ctx.argument::<JsString>(0)?.value(&mut ctx);
+    let index = ctx.argument::<JsNumber>(1)?.value(&mut ctx);
+    let conn = database::get_database_connection();
+    match queue::remove_from_queue(&profile_id, index.max(0.0) as usize, &conn) {
+        Ok(removed) => Ok(ctx.boolean(removed)),
+        Err(e) => ctx.throw_error(e.to_string()),
+    }
+}
+
+/// Gets the waveform of the track with the given UUID, as an array of peaks from 0 to 255,
+/// or null if it was not analyzed yet.
+fn get_waveform(mut ctx: FunctionContext) -> JsResult<JsValue> {
+    let track_id = ctx.argument::<JsString>(0)?.value(&mut ctx);
+    let conn = database::get_database_connection();
+    let waveform = match analysis::get_waveform(&track_id, &conn) {
+        Ok(Some(waveform)) => waveform,
+        Ok(None) => return Ok(ctx.null().upcast()),
+        Err(e) => return ctx.throw_error(e.to_string()),
+    };
+    let js_waveform = ctx.empty_array();
+    for (i, peak) in waveform.iter().enumerate() {
+        let js_peak = ctx.number(*peak);
+        js_waveform.set(&mut ctx, i as u32, js_peak)?;
+    }
+    Ok(js_waveform.upcast())
+}
+
+/// Gets the spectral analysis of the track with the given UUID, as an object with the `cutoff`
+/// frequency of its spectrum, which is null if there is none, and whether it is a `suspect`
+/// lossy transcode. Returns null if the track was not analyzed.
+fn get_spectrum(mut ctx: FunctionContext) -> JsResult<JsValue> {
+    let track_id = ctx.argument::<JsString>(0)?.value(&mut ctx);
+    let conn = database::get_database_connection();
+    let spectrum = match analysis::get_spectrum(&track_id, &conn) {
+        Ok(Some(spectrum)) => spectrum,
+        Ok(None) => return Ok(ctx.null().upcast()),
+        Err(e) => return ctx.throw_error(e.to_string()),
+    };
+    let js_spectrum = ctx.empty_object();
+    let cutoff: Handle<JsValue> = match spectrum.cutoff {
+        Some(cutoff) => ctx.number(cutoff).upcast(),
+        None => ctx.null().upcast(),
+    };
+    js_spectrum.set(&mut ctx, "cutoff", cutoff)?;
+    let suspect = ctx.boolean(spectrum.suspect);
+    js_spectrum.set(&mut ctx, "suspect", suspect)?;
+    Ok(js_spectrum.upcast())
+}
+
+/// Gets the path of the preview clip of the track with the given UUID,
+/// or null if none was written.
+fn get_preview_path(mut ctx: FunctionContext) -> JsResult<JsValue> {
+    let track_id = ctx.argument::<JsString>(0)?.value(&mut ctx);
+    match analysis::get_preview_path(&track_id) {
+        Some(preview_path) => Ok(ctx.string(preview_path.to_string_lossy()).upcast()),
+        None => Ok(ctx.null().upcast()),
+    }
+}
+
+/// Gets the path of the cached front cover of the track with the given UUID, extracting it
+/// into the cache if need be, or null if the track has no front cover.
+fn get_art_path(mut ctx: FunctionContext) -> JsResult<JsValue> {
+    let track_id = ctx.argument::<JsString>(0)?.value(&mut ctx);
+    let conn = database::get_database_connection();
+    let art_path = match database::get_track_by_uuid(&track_id, &conn) {
+        Ok(Some(track)) => art::get_cached_art(&track, &conn),
+        Ok(None) => Ok(None),
+        Err(e) => Err(e),
+    };
+    match art_path {
+        Ok(Some(art_path)) => Ok(ctx.string(art_path.to_string_lossy()).upcast()),
+        Ok(None) => Ok(ctx.null().upcast()),
+        Err(e) => ctx.throw_error(e.to_string()),
+    }
+}
+
 register_module!(mut m, {
     m.export_function("queryTracks", query_tracks)?;
+    m.export_function("countTracks", count_tracks)?;
     m.export_function("refreshTracks", refresh_tracks)?;
+    m.export_function("similarTracks", similar_tracks)?;
+    m.export_function("suggest", suggest)?;
+    m.export_function("maintainDatabase", maintain_database)?;
+    m.export_function("addArtistAlias", add_artist_alias)?;
+    m.export_function("removeArtistAlias", remove_artist_alias)?;
+    m.export_function("getArtistAliases", get_artist_aliases)?;
+    m.export_function("canonicalizeArtists", canonicalize_artists)?;
+    m.export_function("setTrackLocked", set_track_locked)?;
+    m.export_function("getTrackNote", get_track_note)?;
+    m.export_function("setTrackNote", set_track_note)?;
+    m.export_function("getTrackProvenance", get_track_provenance)?;
+    m.export_function("getTrackFields", get_track_fields)?;
+    m.export_function("setTrackField", set_track_field)?;
+    m.export_function("linkTracks", link_tracks)?;
+    m.export_function("unlinkTracks", unlink_tracks)?;
+    m.export_function("getTrackRelationships", get_track_relationships)?;
+    m.export_function("setReleaseGroup", set_release_group)?;
+    m.export_function("previewNormalization", preview_normalization)?;
+    m.export_function("setGenreParent", set_genre_parent)?;
+    m.export_function("getGenreTree", get_genre_tree)?;
+    m.export_function("getQueue", get_queue)?;
+    m.export_function("setQueue", set_queue)?;
+    m.export_function("setQueuePosition", set_queue_position)?;
+    m.export_function("enqueueTrack", enqueue_track)?;
+    m.export_function("removeFromQueue", remove_from_queue)?;
+    m.export_function("getWaveform", get_waveform)?;
+    m.export_function("getPreviewPath", get_preview_path)?;
+    m.export_function("getArtPath", get_art_path)?;
+    m.export_function("getSpectrum", get_spectrum)?;
     Ok(())
 });
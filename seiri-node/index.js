@@ -0,0 +1,27 @@
+const addon = require('./seiri-node.node');
+
+/**
+ * Calls the callback with every event emitted by the in-process watcher,
+ * checking for new events every `interval` milliseconds. If events were
+ * dropped since the last check, `onDropped` is called with how many were.
+ * Returns a function that stops the subscription.
+ */
+const subscribe = (callback, interval = 500, onDropped = () => {}) => {
+    const timer = setInterval(() => {
+        const { events, dropped } = addon.pollEvents();
+        if (dropped > 0) {
+            onDropped(dropped);
+        }
+        events.forEach(callback);
+    }, interval);
+    return () => clearInterval(timer);
+};
+
+module.exports = {
+    queryTracks: addon.queryTracks,
+    importTracks: addon.importTracks,
+    startWatcher: addon.startWatcher,
+    stopWatcher: addon.stopWatcher,
+    pollEvents: addon.pollEvents,
+    subscribe
+};
@@ -0,0 +1,232 @@
+use crossbeam::channel::{bounded, unbounded, Receiver, Sender};
+use napi::{Error, Result};
+use napi_derive::napi;
+use seiri::config::{get_config, Config};
+use seiri::database;
+use seiri::events::Event;
+use seiri::import;
+use seiri::lease;
+use seiri::messages::{self, Locale};
+use seiri::paths;
+use seiri::watcher;
+use seiri::watcher::WatchStatus;
+use seiri::Bang;
+use seiri::Track;
+use std::net::TcpListener;
+use std::path::Path;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread::{self, JoinHandle};
+
+/// How many events of the watcher are kept for JavaScript to poll, after which newer events are
+/// dropped until it polls again, so a watcher nobody polls does not grow without bound.
+const EVENT_CAPACITY: usize = 1024;
+
+/// The name the bindings hold the write lease under, when writing to the library themselves.
+const LEASE_HOLDER: &str = "seiri-node";
+
+/// Events emitted by the in-process watcher, waiting to be polled by JavaScript.
+static EVENTS: OnceLock<(Sender<Event>, Receiver<Event>)> = OnceLock::new();
+
+/// The number of events dropped since JavaScript last polled, since the queue was full.
+static DROPPED: AtomicU32 = AtomicU32::new(0);
+
+/// The language the messages of events are given in, read from the configuration once.
+static LOCALE: OnceLock<Locale> = OnceLock::new();
+
+/// The running in-process watcher.
+struct RunningWatcher {
+    quit: Sender<WatchStatus>,
+    thread: JoinHandle<()>,
+    /// The instance lock, held until the watcher has stopped.
+    lock: TcpListener,
+}
+
+static WATCHER: Mutex<Option<RunningWatcher>> = Mutex::new(None);
+
+fn events() -> &'static (Sender<Event>, Receiver<Event>) {
+    EVENTS.get_or_init(|| bounded(EVENT_CAPACITY))
+}
+
+fn push_event(event: Event) {
+    if events().0.try_send(event).is_err() {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+fn event_locale() -> Locale {
+    *LOCALE.get_or_init(|| {
+        get_config()
+            .ok()
+            .and_then(|config| Locale::from_tag(&config.locale))
+            .unwrap_or_default()
+    })
+}
+
+fn to_js_error<E: ToString>(err: E) -> Error {
+    Error::from_reason(err.to_string())
+}
+
+#[napi(object, js_name = "Event")]
+pub struct JsEvent {
+    pub code: String,
+    pub args: Vec<String>,
+    pub message: String,
+}
+
+impl From<&Event> for JsEvent {
+    fn from(event: &Event) -> JsEvent {
+        JsEvent {
+            code: event.code().to_owned(),
+            args: event.args().into_iter().map(|arg| arg.into_owned()).collect(),
+            message: messages::describe(event, event_locale()),
+        }
+    }
+}
+
+/// The events taken by a poll, along with how many were dropped before it.
+#[napi(object, js_name = "EventBatch")]
+pub struct JsEventBatch {
+    pub events: Vec<JsEvent>,
+    pub dropped: u32,
+}
+
+#[napi(object, js_name = "Track")]
+pub struct JsTrack {
+    pub file_path: String,
+    pub title: String,
+    pub artist: String,
+    pub album_artists: Vec<String>,
+    pub album: String,
+    pub year: i32,
+    pub track_number: i32,
+    pub musicbrainz_track_id: Option<String>,
+    pub genres: Vec<String>,
+    pub has_front_cover: bool,
+    pub front_cover_width: i32,
+    pub front_cover_height: i32,
+    pub bitrate: i32,
+    pub sample_rate: i32,
+    pub source: String,
+    pub disc_number: i32,
+    pub duration: i32,
+    pub updated: String,
+    pub uuid: Option<String>,
+}
+
+impl From<Track> for JsTrack {
+    fn from(track: Track) -> JsTrack {
+        JsTrack {
+            file_path: track.file_path.to_string_lossy().into_owned(),
+            title: track.title,
+            artist: track.artist,
+            album_artists: track.album_artists,
+            album: track.album,
+            year: track.year,
+            track_number: track.track_number,
+            musicbrainz_track_id: track.musicbrainz_track_id,
+            genres: track.genres,
+            has_front_cover: track.has_front_cover,
+            front_cover_width: track.front_cover_width,
+            front_cover_height: track.front_cover_height,
+            bitrate: track.bitrate,
+            sample_rate: track.sample_rate,
+            source: track.source,
+            disc_number: track.disc_number,
+            duration: track.duration,
+            updated: track.updated,
+            uuid: track.uuid,
+        }
+    }
+}
+
+/// Runs a bang query against the library.
+#[napi]
+pub fn query_tracks(query: String) -> Result<Vec<JsTrack>> {
+    let bang = Bang::new(&query).map_err(to_js_error)?;
+    let conn = database::get_database_connection();
+    let tracks = database::query_tracks(bang, &conn, None, None).map_err(to_js_error)?;
+    Ok(tracks.into_iter().map(JsTrack::from).collect())
+}
+
+/// Imports the given files into the library, returning the event
+/// describing the result of each import, in order.
+#[napi]
+pub fn import_tracks(file_paths: Vec<String>) -> Result<Vec<JsEvent>> {
+    let config = get_config().map_err(to_js_error)?;
+    let conn = database::get_database_connection();
+    let events = lease::with_lease(LEASE_HOLDER, &conn, push_event, || {
+        file_paths
+            .iter()
+            .map(|file| import::import_track(Path::new(file), &config, &conn, true))
+            .collect::<Vec<Event>>()
+    })
+    .map_err(to_js_error)?;
+    Ok(events.iter().map(JsEvent::from).collect())
+}
+
+/// Starts watching the Automatically Add to Library folder in-process.
+/// Returns false if a watcher was already started by this process.
+#[napi]
+pub fn start_watcher() -> Result<bool> {
+    let mut running = WATCHER.lock().unwrap();
+    if running.is_some() {
+        return Ok(false);
+    }
+
+    // Share the instance lock with seiri-watcher, so the two never process the same files.
+    let lock = TcpListener::bind(("localhost", 9235)).map_err(|_| {
+        Error::from_reason("ENOLOCK::Unable to acquire lock. Only have one instance of seiri running.")
+    })?;
+    let config: &'static Config = Box::leak(Box::new(get_config().map_err(to_js_error)?));
+
+    let (quit, quit_rx) = unbounded::<WatchStatus>();
+    let pool = Arc::new(database::get_configured_connection_pool(&config.database));
+    let thread = thread::spawn(move || match paths::ensure_music_folder(&config.music_folder) {
+        // The music folder is indexed where it is, and the watch folder is left alone.
+        Ok((library_path, _)) if config.adopt_layout => {
+            let watch_path = library_path.to_string_lossy().into_owned();
+            watcher::adopt(&watch_path, pool.as_ref(), push_event);
+            if let Err(e) =
+                watcher::watch(&watch_path, config, pool, import::index_album, push_event, &quit_rx, Vec::new())
+            {
+                push_event(Event::WatcherError(e.to_string()));
+            }
+        }
+        Ok((_, auto_add_path)) => {
+            let watch_path = auto_add_path.to_string_lossy().into_owned();
+            if let Err(e) =
+                watcher::watch(&watch_path, config, pool, import::import_album, push_event, &quit_rx, Vec::new())
+            {
+                push_event(Event::WatcherError(e.to_string()));
+            }
+        }
+        Err(_) => push_event(Event::WatcherNoAccess(config.music_folder.to_owned())),
+    });
+
+    *running = Some(RunningWatcher { quit, thread, lock });
+    Ok(true)
+}
+
+/// Stops the in-process watcher, if one is running, returning once it has stopped.
+#[napi]
+pub fn stop_watcher() {
+    // The watcher is kept locked while it stops, so another is not started alongside it.
+    let mut running = WATCHER.lock().unwrap();
+    if let Some(RunningWatcher { quit, thread, lock }) = running.take() {
+        quit.send(WatchStatus::Exit).unwrap_or(());
+        // The instance lock is only released once the watcher is done with its files,
+        // so seiri-watcher can not start processing them in the meantime.
+        thread.join().unwrap_or(());
+        drop(lock);
+    }
+}
+
+/// Takes every event emitted by the in-process watcher since the last poll.
+#[napi]
+pub fn poll_events() -> JsEventBatch {
+    JsEventBatch {
+        events: events().1.try_iter().map(|event| JsEvent::from(&event)).collect(),
+        dropped: DROPPED.swap(0, Ordering::Relaxed),
+    }
+}
@@ -0,0 +1,232 @@
+//! A single server binary running the library watcher and every service alongside it, for
+//! headless machines such as a NAS, along with one-off commands over the library.
+//!
+//! ```text
+//! seiri-server serve
+//! seiri-server scan [--rescan] [folder]
+//! seiri-server query <query>
+//! seiri-server export [--format <format>] <folder> <query>
+//! ```
+
+use seiri::config::{self, Config};
+use seiri::database::{self, Connection};
+use seiri::events::Event;
+use seiri::lease;
+use seiri::library::{self, BootstrapOptions, FileOp};
+use seiri::variants;
+use seiri::{Bang, ConfigErrorType, Error};
+use seiri_watcher::Commands;
+use std::env;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+
+const USAGE: &str = "Usage: seiri-server serve
+       seiri-server scan [--rescan] [folder]
+       seiri-server query <query>
+       seiri-server export [--format <format>] <folder> <query>";
+
+/// The name the one-off commands hold the write lease under, apart from the watcher of `serve`.
+const LEASE_HOLDER: &str = "seiri-server-cli";
+
+/// Gets the configuration, reporting why it could not be read if it can not be.
+fn get_config() -> Option<Config> {
+    match config::get_config() {
+        Ok(config) => Some(config),
+        Err(Error::ConfigError(ConfigErrorType::Invalid)) => {
+            eprintln!("{}", Event::ConfigInvalid("The configuration file is invalid".to_owned()));
+            None
+        }
+        Err(Error::ConfigError(ConfigErrorType::IOError(path))) => {
+            eprintln!("{}", Event::ConfigIOError(path));
+            None
+        }
+        Err(_) => None,
+    }
+}
+
+/// Adds every track in the folder to the library where it is, or every track in the music folder.
+fn scan(config: &Config, rescan: bool, folder: Option<&str>, conn: &Connection) -> bool {
+    let folder = Path::new(folder.unwrap_or(&config.music_folder));
+    let options = BootstrapOptions {
+        skip_existing: !rescan,
+        required_tags: config.required_tags,
+        ..BootstrapOptions::default()
+    };
+    match lease::with_lease(LEASE_HOLDER, conn, seiri_watcher::report, || {
+        library::bootstrap(folder, &options, conn)
+    }) {
+        Ok(Ok(report)) => {
+            for file in &report.failed {
+                println!("BOOTSTRAPFAILED::{}", file.to_string_lossy());
+            }
+            println!(
+                "BOOTSTRAPPED::{}||{}||{}||{}",
+                report.imported,
+                report.skipped,
+                report.unsupported,
+                report.failed.len()
+            );
+            true
+        }
+        Ok(Err(err)) | Err(err) => {
+            eprintln!("{:?}", err);
+            false
+        }
+    }
+}
+
+/// Prints the path of every track matching the query, one to a line.
+fn query(query: &str, conn: &Connection) -> bool {
+    let tracks = Bang::new(query)
+        .map_err(|err| format!("{:?}", err))
+        .and_then(|bang| database::query_tracks(bang, conn, None, None).map_err(|err| format!("{:?}", err)));
+    match tracks {
+        Ok(tracks) => {
+            for track in tracks {
+                println!("{}", track.file_path.to_string_lossy());
+            }
+            true
+        }
+        Err(err) => {
+            eprintln!("{}", err);
+            false
+        }
+    }
+}
+
+/// Copies the files of every track matching the query into the folder, laid out as they are in
+/// the library, transcoded into the format of a configured transcoder if one is given.
+fn export(config: &Config, format: Option<&str>, folder: &str, query: &str, conn: &Connection) -> bool {
+    let folder = PathBuf::from(folder);
+    let op = match format {
+        Some(format) => match variants::find_transcoder(&config.transcoders, format) {
+            Some(transcoder) => FileOp::ExportAs(folder, transcoder.clone()),
+            None => {
+                eprintln!("No transcoder is configured for {}", format);
+                return false;
+            }
+        },
+        None => FileOp::CopyTo(folder),
+    };
+    let bang = match Bang::new(query) {
+        Ok(bang) => bang,
+        Err(err) => {
+            eprintln!("{:?}", err);
+            return false;
+        }
+    };
+    let library_path = Path::new(&config.music_folder);
+    match lease::with_lease(LEASE_HOLDER, conn, seiri_watcher::report, || {
+        library::apply(bang, &op, library_path, false, conn)
+    }) {
+        Ok(Ok(report)) => {
+            for (from, to) in &report.files {
+                println!("FILEOP::{}||{}", from.to_string_lossy(), to.to_string_lossy());
+            }
+            for file in &report.skipped {
+                println!("FILEOPSKIPPED::{}", file.to_string_lossy());
+            }
+            for file in &report.failed {
+                println!("FILEOPFAILED::{}", file.to_string_lossy());
+            }
+            report.failed.is_empty()
+        }
+        Ok(Err(err)) | Err(err) => {
+            eprintln!("{:?}", err);
+            false
+        }
+    }
+}
+
+/// Takes the value of the option out of the arguments, such as the format of `--format opus`.
+fn take_option(args: &mut Vec<String>, name: &str) -> Option<String> {
+    let index = args.iter().position(|arg| arg == name)?;
+    args.remove(index);
+    if index < args.len() {
+        Some(args.remove(index))
+    } else {
+        None
+    }
+}
+
+/// Takes the flag out of the arguments, returning whether it was given.
+fn take_flag(args: &mut Vec<String>, name: &str) -> bool {
+    let given = args.iter().any(|arg| arg == name);
+    args.retain(|arg| arg != name);
+    given
+}
+
+fn main() {
+    let mut args = env::args().skip(1).collect::<Vec<String>>();
+    let command = if args.is_empty() { String::new() } else { args.remove(0) };
+    if command == "serve" && args.is_empty() {
+        seiri_watcher::run(Commands::<io::Empty>::ControlSocket);
+        return;
+    }
+
+    let config = match get_config() {
+        Some(config) => config,
+        None => process::exit(1),
+    };
+    let pool = database::get_configured_connection_pool(&config.database);
+    let conn = pool.get().expect("Unable to open a connection to the library.");
+    let succeeded = match command.as_str() {
+        "scan" => {
+            let rescan = take_flag(&mut args, "--rescan");
+            match args.as_slice() {
+                [] => scan(&config, rescan, None, &conn),
+                [folder] => scan(&config, rescan, Some(folder), &conn),
+                _ => usage(),
+            }
+        }
+        "query" if !args.is_empty() => query(&args.join(" "), &conn),
+        "export" => {
+            let format = take_option(&mut args, "--format");
+            match args.split_first() {
+                Some((folder, query)) if !query.is_empty() => {
+                    export(&config, format.as_deref(), folder, &query.join(" "), &conn)
+                }
+                _ => usage(),
+            }
+        }
+        _ => usage(),
+    };
+    if !succeeded {
+        process::exit(1);
+    }
+}
+
+fn usage() -> bool {
+    eprintln!("{}", USAGE);
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| (*arg).to_owned()).collect()
+    }
+
+    #[test]
+    fn takes_options_out_of_the_arguments() {
+        let mut given = args(&["/exports", "--format", "opus", "!al{First}"]);
+        assert_eq!(take_option(&mut given, "--format").as_deref(), Some("opus"));
+        assert_eq!(given, args(&["/exports", "!al{First}"]));
+        assert_eq!(take_option(&mut given, "--format"), None);
+
+        let mut trailing = args(&["/exports", "--format"]);
+        assert_eq!(take_option(&mut trailing, "--format"), None);
+        assert_eq!(trailing, args(&["/exports"]));
+    }
+
+    #[test]
+    fn takes_flags_out_of_the_arguments() {
+        let mut given = args(&["--rescan", "/music"]);
+        assert!(take_flag(&mut given, "--rescan"));
+        assert_eq!(given, args(&["/music"]));
+        assert!(!take_flag(&mut given, "--rescan"));
+    }
+}
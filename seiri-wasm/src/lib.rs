@@ -0,0 +1,65 @@
+//! The bang query grammar compiled to WebAssembly.
+//!
+//! This uses the same lexer and parser as the backend, so queries that are
+//! accepted here are accepted by `seiri-neon` and `seiri-watcher` as well.
+
+use seiri::bangs::{tokenize_with_spans, TokenCategory};
+use seiri::Bang;
+use wasm_bindgen::prelude::*;
+
+/// Parses the query, and returns the error message if the query is invalid.
+/// Returns `undefined` for valid queries.
+#[wasm_bindgen(js_name = parseError)]
+pub fn parse_error(query: &str) -> Option<String> {
+    Bang::new(query).err().map(|err| err.to_string())
+}
+
+/// Whether the query is a valid bang query.
+#[wasm_bindgen(js_name = isValid)]
+pub fn is_valid(query: &str) -> bool {
+    Bang::new(query).is_ok()
+}
+
+/// A highlighted span of a query.
+///
+/// Unlike `seiri::bangs::TokenSpan`, offsets are in UTF-16 code units,
+/// so they can be used directly with JavaScript strings.
+#[wasm_bindgen]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    category: TokenCategory,
+}
+
+#[wasm_bindgen]
+impl Span {
+    /// One of `bang`, `delimiter`, `argument`, `operator` or `error`.
+    #[wasm_bindgen(getter)]
+    pub fn category(&self) -> String {
+        match self.category {
+            TokenCategory::BangName => "bang",
+            TokenCategory::Delimiter => "delimiter",
+            TokenCategory::Argument => "argument",
+            TokenCategory::Operator => "operator",
+            TokenCategory::Error => "error",
+        }
+        .to_owned()
+    }
+}
+
+fn utf16_offset(query: &str, offset: usize) -> usize {
+    query[..offset].encode_utf16().count()
+}
+
+/// Splits the query into spans for syntax highlighting.
+#[wasm_bindgen]
+pub fn tokenize(query: &str) -> Vec<Span> {
+    tokenize_with_spans(query)
+        .into_iter()
+        .map(|span| Span {
+            start: utf16_offset(query, span.start),
+            end: utf16_offset(query, span.end),
+            category: span.category,
+        })
+        .collect()
+}
@@ -0,0 +1,353 @@
+//! The library watcher, along with every service running alongside it, such as sync, the HTTP API
+//! and the scheduled jobs, which are run by *seiri-watcher* and *seiri-server*.
+
+use chrono::Local;
+use crossbeam::channel::{select, unbounded, Receiver, Sender};
+
+use std::io::{self, BufRead};
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::sync::{Arc, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+mod utils;
+
+use seiri::analysis;
+use seiri::config;
+use seiri::config::Config;
+use seiri::conflicts;
+use seiri::database;
+use seiri::database::ConnectionPool;
+use seiri::events::Event;
+use seiri::http;
+use seiri::import;
+use seiri::paths;
+use seiri::replication;
+use seiri::rips;
+use seiri::schedule::{self, Job};
+use seiri::watcher;
+use seiri::watcher::{RestartBudget, WatchStatus};
+use seiri::webhooks::Webhooks;
+use seiri::ConfigErrorType;
+use seiri::Error;
+
+/// Delivers events to the configured webhooks, once started.
+static WEBHOOKS: OnceLock<Webhooks> = OnceLock::new();
+
+/// Prints the event to stderr, and delivers it to the configured webhooks.
+pub fn report(event: Event) {
+    eprintln!("{}", event);
+    if let Some(webhooks) = WEBHOOKS.get() {
+        webhooks.send(&event);
+    }
+}
+
+fn wait_for_watch_root_available(folder: &str) -> (PathBuf, PathBuf) {
+    println!("Waiting for folder {}...", folder);
+    let wait_time = Duration::from_secs(5);
+    while paths::ensure_music_folder(folder).is_err() {
+        thread::park_timeout(wait_time);
+    }
+    println!("Successfully ensured folder {}", folder);
+    paths::ensure_music_folder(folder).unwrap()
+}
+
+fn begin_watch(config: &'static Config, pool: Arc<ConnectionPool>, rx: &Receiver<WatchStatus>) {
+    let auto_paths = wait_for_watch_root_available(&config.music_folder);
+    if config.adopt_layout {
+        // The music folder is indexed where it is, and the watch folder is left alone.
+        let library_path = auto_paths.0.to_str().unwrap();
+        println!("Watching {}", library_path);
+        watcher::adopt(library_path, pool.as_ref(), report);
+        if let Err(e) = watcher::watch(library_path, config, pool, import::index_album, report, rx, Vec::new()) {
+            eprintln!("{}", Event::WatcherError(e.to_string()));
+        }
+        return;
+    }
+    let watch_path = auto_paths.1.to_str().unwrap();
+    println!("Watching {}", watch_path);
+    // The files already in the watch folder are processed once it is watched, so none dropped in between are missed.
+    if let Err(e) = watcher::watch(watch_path, config, pool, import::import_album, report, rx, Vec::new()) {
+        eprintln!("{}", Event::WatcherError(e.to_string()));
+    }
+}
+
+fn get_watcher_thread(
+    rx: Receiver<WatchStatus>,
+    config: &'static Config,
+    pool: Arc<ConnectionPool>,
+) -> io::Result<thread::JoinHandle<()>> {
+    thread::Builder::new()
+        .name("WatchThread".to_string())
+        .spawn(move || begin_watch(config, pool, &rx))
+}
+
+/// Restarts the watcher whenever it dies, backing off between restarts in a row, and leaves it
+/// stopped once the restart budget of the configuration is spent.
+fn start_watcher_watchdog(wait_time: Duration, config: &'static Config, pool: Arc<ConnectionPool>) -> Sender<()> {
+    let (qtx, qrx) = unbounded::<()>();
+
+    thread::spawn(move || {
+        let (tx, rx) = unbounded();
+        let mut tx = tx;
+        let mut budget = RestartBudget::new(&config.watchdog);
+        // When the watcher died and waits to be restarted.
+        let mut restart_at: Option<Instant> = None;
+        let mut degraded = false;
+
+        wait_for_watch_root_available(&config.music_folder);
+        let mut _watch_thread = get_watcher_thread(rx, config, Arc::clone(&pool)).unwrap();
+        loop {
+            let timeout = restart_at.map_or(wait_time, |restart_at| {
+                restart_at.saturating_duration_since(Instant::now()).min(wait_time)
+            });
+            select! {
+                recv(qrx) -> _ => {
+                    // do quit stuff
+                    if tx.send(WatchStatus::Exit).is_ok() {
+                        _watch_thread.join().ok();
+                    }
+                    drop(pool);
+                    break;
+                },
+                default(timeout) => {
+                    if degraded {
+                        continue;
+                    }
+                    if let Some(at) = restart_at {
+                        if Instant::now() >= at {
+                            restart_at = None;
+                            let (new_tx, rx) = unbounded();
+                            tx = new_tx.clone();
+                            _watch_thread = get_watcher_thread(rx, config, Arc::clone(&pool)).unwrap();
+                        }
+                        continue;
+                    }
+                    if tx.send(WatchStatus::KeepAlive).is_err() {
+                        report(Event::WatcherDied("Keep-alive failed. Watcher thread probably panicked.".to_owned()));
+                        match budget.restart() {
+                            Some((restarts, delay)) => {
+                                report(Event::WatcherRestarting { restarts, delay: delay.as_secs() });
+                                restart_at = Some(Instant::now() + delay);
+                            }
+                            None => {
+                                report(Event::WatcherDegraded { restarts: config.watchdog.max_restarts });
+                                degraded = true;
+                            }
+                        }
+                        continue;
+                    }
+
+                    let music_folder = paths::ensure_music_folder(&config.music_folder);
+                    if music_folder.is_err() {
+                        report(Event::WatcherNoAccess(config.music_folder.to_owned()));
+                        wait_for_watch_root_available(&config.music_folder);
+                        let (new_tx, rx) = unbounded();
+                        tx.send(WatchStatus::Exit).unwrap();
+                        report(Event::WatcherRestart("Requested watcher thread exit. Restarting Watcher Thread...".to_owned()));
+                        tx = new_tx.clone();
+                        _watch_thread = get_watcher_thread(rx, config, Arc::clone(&pool)).unwrap();
+                    }
+                }
+            }
+        }
+    });
+
+    qtx
+}
+
+/// Serves sync to other instances, and syncs with the configured peers every `sync_interval` seconds.
+fn start_sync(config: &'static Config, pool: Arc<ConnectionPool>) {
+    let network = &config.network;
+    if network.sync {
+        let pool = Arc::clone(&pool);
+        thread::spawn(move || {
+            if replication::serve(network, pool.as_ref(), report).is_err() {
+                report(Event::SyncError(format!("{}:{}", network.bind_address, network.port)));
+            }
+        });
+    }
+    if !network.sync_peers.is_empty() {
+        thread::spawn(move || loop {
+            thread::sleep(Duration::from_secs(network.sync_interval));
+            if !schedule::is_allowed_now(Job::Sync, &config.schedules) {
+                continue;
+            }
+            let conn = match pool.get() {
+                Ok(conn) => conn,
+                Err(_) => continue,
+            };
+            for peer in &network.sync_peers {
+                let mut synced = replication::sync_with(peer, &conn).map(|_| ());
+                if peer.pull_files && synced.is_ok() {
+                    synced = replication::pull_missing_tracks(peer, config, &conn, report).map(|_| ());
+                }
+                if synced.is_err() {
+                    report(Event::SyncError(peer.address.to_owned()));
+                }
+            }
+        });
+    }
+}
+
+/// Serves the HTTP API, which searches the library and streams its tracks, if it is enabled.
+fn start_http(config: &'static Config, pool: Arc<ConnectionPool>) {
+    let network = &config.network;
+    if !network.http {
+        return;
+    }
+    thread::spawn(move || {
+        if http::serve(network, pool, report).is_err() {
+            report(Event::HttpError(format!("{}:{}", network.bind_address, network.http_port)));
+        }
+    });
+}
+
+/// Runs the analysis jobs by themselves whenever their window is open, if they are scheduled.
+/// The job stops once the window closes, and carries on in the next window.
+fn start_scheduled_analysis(config: &'static Config, pool: Arc<ConnectionPool>) {
+    if !schedule::is_scheduled(Job::Analysis, &config.schedules) {
+        return;
+    }
+    let check_interval = Duration::from_secs(10 * 60);
+    thread::spawn(move || loop {
+        if schedule::is_allowed_now(Job::Analysis, &config.schedules) {
+            if let Ok(conn) = pool.get() {
+                // Analysis only writes waveforms, so it runs without the lease.
+                // Tracks that fail are reported as they are analyzed, so there is nothing left to report.
+                analysis::analyze_library_while(&config.analysis, &conn, report, || {
+                    schedule::is_allowed_now(Job::Analysis, &config.schedules)
+                })
+                .ok();
+            }
+        }
+        thread::sleep(check_interval);
+    });
+}
+
+/// Maintains the database by itself once a day within its window, if maintenance is scheduled.
+fn start_scheduled_maintenance(config: &'static Config, pool: Arc<ConnectionPool>) {
+    if !schedule::is_scheduled(Job::Maintenance, &config.schedules) {
+        return;
+    }
+    let check_interval = Duration::from_secs(10 * 60);
+    thread::spawn(move || {
+        let mut maintained_on = None;
+        loop {
+            let today = Local::now().date_naive();
+            if maintained_on != Some(today) && schedule::is_allowed_now(Job::Maintenance, &config.schedules) {
+                if let Ok(conn) = pool.get() {
+                    // Maintenance that fails, such as while another writer holds the lease,
+                    // is tried again at the next check.
+                    if let Ok(Ok(_)) = watcher::with_write_lease(&conn, report, || database::maintain(&conn)) {
+                        maintained_on = Some(today);
+                    }
+                }
+            }
+            thread::sleep(check_interval);
+        }
+    });
+}
+
+/// Takes in the CDs ripped into the folder of the ripper as they are finished, if one is configured.
+fn start_rip_intake(config: &'static Config, pool: Arc<ConnectionPool>) {
+    if config.rips.folder.is_none() {
+        return;
+    }
+    let check_interval = Duration::from_secs(10);
+    thread::spawn(move || loop {
+        thread::sleep(check_interval);
+        if let Ok(conn) = pool.get() {
+            for event in rips::intake_rips(config, &conn) {
+                report(event);
+            }
+        }
+    });
+}
+
+/// Prints the changes to the results of the subscriptions of the frontend as the library changes.
+fn start_subscriptions(pool: Arc<ConnectionPool>) {
+    let check_interval = Duration::from_millis(500);
+    thread::spawn(move || loop {
+        thread::sleep(check_interval);
+        if let Ok(conn) = pool.get() {
+            utils::refresh_subscriptions(&conn);
+        }
+    });
+}
+
+fn ensure_port(port: u16) -> Result<TcpListener, io::Error> {
+    TcpListener::bind(("localhost", port))
+}
+
+/// Where the commands of the watcher are read from.
+pub enum Commands<I: BufRead> {
+    /// Commands are read from the reader, such as stdin, and the watcher exits once `exit` is read.
+    Reader(I),
+    /// Commands are read from every connection to the port the watcher holds to be the only instance
+    /// running, which only listens on localhost, once it authenticates with an admin token.
+    /// The watcher runs until it is stopped.
+    ControlSocket,
+}
+
+/// Runs the watcher along with every service configured, reading commands until it exits.
+pub fn run<I: BufRead>(commands: Commands<I>) {
+    let lock = ensure_port(9235).expect("ENOLOCK::Unable to acquire lock. Only have one instance of seiri running.");
+
+    let wait_time = Duration::from_secs(5);
+    match config::get_config() {
+        Ok(config) => {
+            // Config will stay for lifetime of the program.
+            let config: &'static Config = Box::leak(Box::new(config));
+            if !config.webhooks.is_empty() {
+                // Failed deliveries are only printed, so they are never delivered in turn.
+                let webhooks = Webhooks::start(config.webhooks.clone(), |event| eprintln!("{}", event));
+                WEBHOOKS.set(webhooks).ok();
+            }
+            // so will db_pool but we want to be able to drop it later.
+            let pool = database::get_configured_connection_pool(&config.database);
+            let db_pool = Arc::new(pool);
+            let conflicts = conflicts::find_conflicts(&database::get_database_path());
+            if !conflicts.is_empty() {
+                // The library is forked while there are conflicting copies of it,
+                // so nothing is watched or synced until every copy is merged.
+                for conflict in &conflicts {
+                    report(Event::DatabaseConflict(conflict.to_string_lossy().into_owned()));
+                }
+                let conn = db_pool.get().expect("Unable to open a connection to the library.");
+                utils::wait_for_conflicts(&conn, conflicts);
+                return;
+            }
+            //let config = Arc::new(config);
+            let quit_handle = start_watcher_watchdog(wait_time, config, Arc::clone(&db_pool));
+            start_sync(config, Arc::clone(&db_pool));
+            start_http(config, Arc::clone(&db_pool));
+            start_scheduled_analysis(config, Arc::clone(&db_pool));
+            start_scheduled_maintenance(config, Arc::clone(&db_pool));
+            start_subscriptions(Arc::clone(&db_pool));
+            start_rip_intake(config, Arc::clone(&db_pool));
+            // Commands are read on the main thread, which holds its connection until exit.
+            let conn = db_pool.get().expect("Unable to open a connection to the library.");
+            match commands {
+                Commands::Reader(commands) => utils::wait_for_exit(&conn, &db_pool, config, commands),
+                Commands::ControlSocket => utils::serve_control(lock, Arc::clone(&db_pool), config),
+            }
+            quit_handle.send(()).unwrap();
+            drop(conn);
+            drop(db_pool);
+        }
+        Err(err) => {
+            if let Error::ConfigError(err) = err {
+                match err {
+                    ConfigErrorType::Invalid => {
+                        eprintln!("{}", Event::ConfigInvalid("The configuration file is invalid".to_owned()));
+                    }
+                    ConfigErrorType::IOError(path) => {
+                        eprintln!("{}", Event::ConfigIOError(path));
+                    }
+                }
+            }
+        }
+    }
+}
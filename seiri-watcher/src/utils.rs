@@ -1,41 +1,1266 @@
-use std::io;
-use std::path::Path;
-use seiri::Bang;
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::collections::BTreeMap;
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use seiri::{Bang, Track};
+use seiri::bangs::Relation;
+use seiri::aliases;
+use seiri::analysis;
+use seiri::art;
+use seiri::auth;
+use seiri::browse;
+use seiri::casting::{self, CastControl, CastTarget, Caster};
+use seiri::catalog;
+use seiri::columns;
+use seiri::compilations;
+use seiri::conflicts;
+use seiri::database;
+use seiri::downloads;
+use seiri::editions;
+use seiri::events::Event;
+use seiri::failures;
+use seiri::fields;
+use seiri::genres;
+use seiri::import;
 use seiri::database::query_tracks;
-use seiri::database::Connection;
+use seiri::database::{Connection, ConnectionPool};
 use seiri::paths::reconsider_track;
+use seiri::library;
+use seiri::locks;
+use seiri::needledrops::{self, Segment, SplitAlbum, SplitTrack};
+use seiri::notes;
+use seiri::profiles;
+use seiri::provenance;
+use seiri::queue;
+use seiri::rejections;
+use seiri::relationships;
+use seiri::replication;
+use seiri::scrobbles;
+use seiri::subscriptions::{SubscriptionChange, Subscriptions};
+use seiri::variants;
+use seiri::watcher;
 use seiri::config::Config;
 
-pub fn wait_for_exit(conn: &Connection, config: &Config) {
+thread_local! {
+    /// The connection to the control socket the commands of this thread were read from, if they
+    /// were not read from stdin.
+    static REPLIES: RefCell<Option<TcpStream>> = const { RefCell::new(None) };
+    /// The id of the connection to the control socket the commands of this thread were read
+    /// from, or 0 if they were read from stdin.
+    static CONNECTION: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Answers the command being run on the control socket it was read from, or prints the answer to
+/// stdout. This shadows `println!` in this module, so every command answers where it was asked.
+macro_rules! println {
+    ($($arg:tt)*) => {
+        reply(format_args!($($arg)*))
+    };
+}
+
+fn reply(line: fmt::Arguments) {
+    REPLIES.with(|replies| match replies.borrow_mut().as_mut() {
+        Some(stream) => {
+            writeln!(stream, "{}", line).ok();
+        }
+        None => std::println!("{}", line),
+    })
+}
+
+/// How long cast targets are discovered for before they are listed.
+const CAST_DISCOVERY_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Gets the target of the name, discovering targets again if it was not found when they were last discovered.
+fn find_cast_target(name: &str, targets: &mut Vec<CastTarget>) -> Option<CastTarget> {
+    if casting::find_target(targets, name).is_none() {
+        *targets = casting::discover(CAST_DISCOVERY_TIMEOUT).unwrap_or_default();
+    }
+    casting::find_target(targets, name).cloned()
+}
+
+/// Casts the tracks to the target of the name, finding it like `find_cast_target`.
+fn cast_tracks(caster: &mut Caster, name: &str, targets: &mut Vec<CastTarget>, tracks: &[Track]) {
+    let target = match find_cast_target(name, targets) {
+        Some(target) => target,
+        None => return println!("{}", Event::CastTargetNotFound(name.to_owned())),
+    };
+    match caster.cast(&target, tracks) {
+        Ok(()) => println!("CASTING::{}||{}", target.name, tracks.len()),
+        Err(err) => println!("{:?}", err),
+    }
+}
+
+/// The subscriptions made on a connection to the control socket, or on stdin.
+struct Subscriber {
+    subscriptions: Subscriptions,
+    /// The connection changes to the subscriptions are answered on, or nothing to print them to stdout.
+    replies: Option<TcpStream>,
+}
+
+/// The subscriptions of every connection to the control socket, by the id of the connection, so
+/// connections choosing the same id for a subscription do not replace each other's subscriptions.
+/// Subscriptions made on stdin are of connection 0.
+static SUBSCRIBERS: Mutex<BTreeMap<u64, Subscriber>> = Mutex::new(BTreeMap::new());
+
+/// The id of the next connection to the control socket.
+static NEXT_CONNECTION: AtomicU64 = AtomicU64::new(1);
+
+fn subscription_change_line(id: &str, change: &SubscriptionChange) -> String {
+    let code = match change {
+        SubscriptionChange::Added(_) => "SUBADDED",
+        SubscriptionChange::Removed(_) => "SUBREMOVED",
+        SubscriptionChange::Updated(_) => "SUBUPDATED",
+    };
+    let track = change.track();
+    format!(
+        "{}::{}||{}||{}||{}",
+        code,
+        id,
+        track.uuid.as_deref().unwrap_or(""),
+        track.file_path.to_string_lossy(),
+        columns::track_hash(track)
+    )
+}
+
+fn print_subscription_change(id: &str, change: &SubscriptionChange) {
+    println!("{}", subscription_change_line(id, change));
+}
+
+/// Subscribes to the results of the query under the id on the connection, answering changes to
+/// them on `replies`. Returns the results as they are now.
+fn subscribe(
+    connection: u64,
+    id: &str,
+    bang: Bang,
+    replies: Option<TcpStream>,
+    conn: &Connection,
+) -> database::QueryResult<Vec<Track>> {
+    let mut subscribers = SUBSCRIBERS.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let subscriber = subscribers.entry(connection).or_insert_with(|| Subscriber {
+        subscriptions: Subscriptions::new(),
+        replies: None,
+    });
+    let tracks = subscriber.subscriptions.subscribe(id, bang, conn)?;
+    subscriber.replies = replies;
+    Ok(tracks)
+}
+
+/// Removes the subscription under the id made on the connection. Returns whether there was one.
+fn unsubscribe(connection: u64, id: &str) -> bool {
+    let mut subscribers = SUBSCRIBERS.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let unsubscribed = subscribers
+        .get_mut(&connection)
+        .is_some_and(|subscriber| subscriber.subscriptions.unsubscribe(id));
+    if subscribers.get(&connection).is_some_and(|subscriber| subscriber.subscriptions.is_empty()) {
+        subscribers.remove(&connection);
+    }
+    unsubscribed
+}
+
+/// Gets the changes to the results of every subscription since they were last read, as the lines
+/// answering them, along with the connection the subscription was made on.
+fn subscription_changes(
+    subscribers: &mut BTreeMap<u64, Subscriber>,
+    conn: &Connection,
+) -> database::QueryResult<Vec<(u64, String)>> {
+    let mut lines = Vec::new();
+    for (connection, subscriber) in subscribers.iter_mut() {
+        for (id, change) in subscriber.subscriptions.refresh(conn)? {
+            lines.push((*connection, subscription_change_line(&id, &change)));
+        }
+    }
+    Ok(lines)
+}
+
+/// Prints the changes to the results of every subscription since they were last printed, or
+/// answers them on the connection the subscription was made on. Subscriptions whose connection
+/// was closed are dropped.
+pub fn refresh_subscriptions(conn: &Connection) {
+    let mut subscribers = SUBSCRIBERS.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    if subscribers.is_empty() {
+        return;
+    }
+    match subscription_changes(&mut subscribers, conn) {
+        Ok(lines) => {
+            for (connection, line) in lines {
+                let closed = match subscribers.get_mut(&connection).and_then(|subscriber| subscriber.replies.as_mut()) {
+                    Some(stream) => writeln!(stream, "{}", line).is_err(),
+                    None => {
+                        std::println!("{}", line);
+                        false
+                    }
+                };
+                if closed {
+                    subscribers.remove(&connection);
+                }
+            }
+        }
+        Err(err) => std::println!("{:?}", err),
+    }
+}
+
+/// Drops every subscription made on the connection to the control socket, once it is closed.
+fn forget_subscriptions(connection: u64) {
+    SUBSCRIBERS.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).remove(&connection);
+}
+
+fn print_file_op_report(report: &library::FileOpReport) {
+    for (from, to) in &report.files {
+        println!("FILEOP::{}||{}", from.to_string_lossy(), to.to_string_lossy());
+    }
+    for file in &report.skipped {
+        println!("FILEOPSKIPPED::{}", file.to_string_lossy());
+    }
+    for file in &report.failed {
+        println!("FILEOPFAILED::{}", file.to_string_lossy());
+    }
+    let operation_id = report.operation_id.map(|id| id.to_string()).unwrap_or_default();
+    println!("FILEOPDONE::{}||{}", operation_id, report.files.len());
+}
+
+fn print_replace_report(report: &library::ReplaceReport) {
+    for change in &report.changes {
+        println!("REPLACED::{}||{}||{}", change.file_path.to_string_lossy(), change.before, change.after);
+    }
+    for file in &report.skipped {
+        println!("REPLACESKIPPED::{}", file.to_string_lossy());
+    }
+    for file in &report.failed {
+        println!("REPLACEFAILED::{}", file.to_string_lossy());
+    }
+    let replacement_id = report.replacement_id.map(|id| id.to_string()).unwrap_or_default();
+    println!("REPLACEDONE::{}||{}", replacement_id, report.changes.len());
+}
+
+/// Reads commands while there are conflicting copies of the library, which can only be merged
+/// with `resolveconflict <path>`, or left with `exit`. Returns once every copy is merged.
+pub fn wait_for_conflicts(conn: &Connection, mut conflicts: Vec<PathBuf>) {
     let stdin = io::stdin();
+    println!("Type 'resolveconflict <path>' to merge a conflicting copy of the library, or 'exit' to exit");
+    let mut input = String::new();
+    while stdin.read_line(&mut input).is_ok() {
+        // Commands are matched by their first word, so `play` does not also match `playnext`.
+        let command = input.split_whitespace().next().unwrap_or("");
+        if input.trim().eq_ignore_ascii_case("exit") {
+            return;
+        }
+        if command == "resolveconflict" {
+            let conflict_path = PathBuf::from(input.trim().split_once(' ').map_or("", |(_, path)| path));
+            if !conflicts.contains(&conflict_path) {
+                println!("NOCONFLICT::{}", conflict_path.to_string_lossy());
+            } else {
+                match watcher::with_write_lease(conn, crate::report, || {
+                    conflicts::merge_conflict(&conflict_path, conn)
+                }) {
+                    Ok(Ok(report)) => match conflicts::set_aside(&conflict_path) {
+                        Ok(merged_path) => {
+                            println!(
+                                "CONFLICTRESOLVED::{}||{}||{}||{}||{}",
+                                merged_path.to_string_lossy(),
+                                report.matched,
+                                report.added,
+                                report.skipped,
+                                report.playlists
+                            );
+                            conflicts.retain(|conflict| conflict != &conflict_path);
+                        }
+                        Err(err) => println!("{:?}", err),
+                    },
+                    Ok(Err(err)) | Err(err) => println!("{:?}", err),
+                }
+            }
+            if conflicts.is_empty() {
+                return;
+            }
+        }
+        input.clear();
+    }
+}
+
+/// How long a connection to the control socket has to send its `auth` line before it is closed.
+const AUTH_TIMEOUT: Duration = Duration::from_secs(10);
+/// The longest `auth` line read, so a connection can not send an endless one.
+const MAX_AUTH_LINE: u64 = 1024;
+/// How many connections to the control socket may be authenticating at once. Connections made
+/// past it are closed right away, so connections that never authenticate can not take up threads.
+const MAX_AUTHENTICATING: usize = 16;
+
+/// Runs the commands of every connection to the control socket, each on a thread of its own
+/// with its own connection to the library, answering them on the connection they were read from.
+/// Changes to subscriptions are answered on the connection they were made on, and the
+/// subscriptions of a connection are dropped once it is closed.
+///
+/// The first line of every connection must be `auth <token>`, with one of the admin tokens of
+/// the network configuration, sent within `AUTH_TIMEOUT`. Connections that do not authenticate
+/// are answered with `EUNAUTHORIZED` and closed, without ever taking a connection to the library.
+pub fn serve_control(listener: TcpListener, pool: Arc<ConnectionPool>, config: &'static Config) {
+    let authenticating = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming().flatten() {
+        if authenticating.fetch_add(1, Ordering::SeqCst) >= MAX_AUTHENTICATING {
+            authenticating.fetch_sub(1, Ordering::SeqCst);
+            continue;
+        }
+        let pool = Arc::clone(&pool);
+        let authenticating = Arc::clone(&authenticating);
+        thread::spawn(move || {
+            let authenticated = authenticate(stream, config);
+            authenticating.fetch_sub(1, Ordering::SeqCst);
+            let (commands, mut replies) = match authenticated {
+                Some(streams) => streams,
+                None => return,
+            };
+            let conn = match pool.get() {
+                Ok(conn) => conn,
+                Err(err) => {
+                    writeln!(replies, "{:?}", err).ok();
+                    return;
+                }
+            };
+            let connection = NEXT_CONNECTION.fetch_add(1, Ordering::SeqCst);
+            CONNECTION.with(|id| id.set(connection));
+            REPLIES.with(|stream| *stream.borrow_mut() = Some(replies));
+            wait_for_exit(&conn, &pool, config, commands);
+            forget_subscriptions(connection);
+        });
+    }
+}
+
+/// Reads the `auth` line of a connection to the control socket, giving the commands and replies
+/// of the connection if it authenticated, and answering `EUNAUTHORIZED` otherwise.
+fn authenticate(stream: TcpStream, config: &Config) -> Option<(BufReader<TcpStream>, TcpStream)> {
+    let mut replies = stream.try_clone().ok()?;
+    stream.set_read_timeout(Some(AUTH_TIMEOUT)).ok()?;
+    let mut commands = BufReader::new(stream);
+    let mut auth = String::new();
+    // A connection that times out or sends no line reads as an empty one, which is refused.
+    (&mut commands).take(MAX_AUTH_LINE).read_line(&mut auth).ok();
+    let token = auth.trim().strip_prefix("auth ").map(str::trim);
+    if auth::authenticate_control(&config.network, token).is_err() {
+        writeln!(replies, "EUNAUTHORIZED").ok();
+        return None;
+    }
+    // Commands are waited for for as long as the connection is open.
+    replies.set_read_timeout(None).ok()?;
+    Some((commands, replies))
+}
+
+/// Runs the commands read from `commands` until `exit` is read, or there are no more to read.
+pub fn wait_for_exit<I: BufRead>(conn: &Connection, pool: &ConnectionPool, config: &Config, mut commands: I) {
     println!("Type 'exit' to exit");
     let folder = &config.music_folder;
     let library_path = Path::new(&folder);
+    let mut caster = Caster::new(&config.casting);
+    let mut cast_targets = Vec::new();
     let mut input = String::new();
-    while let Ok(_) = stdin.read_line(&mut input) {
-        if input.trim().eq_ignore_ascii_case("exit") {
+    while let Ok(read) = commands.read_line(&mut input) {
+        if read == 0 || input.trim().eq_ignore_ascii_case("exit") {
             return;
         }
-        if input.trim().starts_with("refresh") {
-            let file_name: &str = match input.trim().splitn(2, ' ').nth(1) {
-                Some(query_str) => query_str,
-                None => "",
+        // Commands are matched by their first word, so `play` does not also match `playnext`.
+        let command = input.split_whitespace().next().unwrap_or("");
+        if command == "refresh" {
+            let file_name = input.trim().split_once(' ').map_or("", |(_, file_name)| file_name);
+            let track = match query_tracks(Bang::FilePath(file_name.to_owned()), conn, None, None) {
+                Ok(track) => track,
+                Err(_) => {
+                    println!("{}", Event::TrackError(file_name.to_owned()));
+                    input.clear();
+                    continue;
+                }
             };
-            let track = query_tracks(Bang::FilePath(file_name.to_owned()), conn, None, None).unwrap();
             match track.into_iter().next() {
+                Some(track) if locks::is_locked(&track, conn).unwrap_or(false) => {
+                    println!("LOCKED::{}", track.file_path.to_string_lossy())
+                }
+                Some(track) if config.adopt_layout => {
+                    if let Some(event) = import::index_track(&track.file_path, config, conn) {
+                        crate::report(event);
+                    }
+                }
                 Some(track) => {
-                    reconsider_track(&track, &library_path).unwrap();
+                    if reconsider_track(&track, library_path, config.folder_casing, &config.layouts, &config.required_tags).is_err() {
+                        println!("{}", Event::TrackError(file_name.to_owned()));
+                    }
+                }
+                None => println!("{}", Event::TrackNotFound(file_name.to_owned())),
+            };
+        }
+        if command == "subscribe" {
+            // subscribe <id> <query>, printing every track in the results, and then the changes to them.
+            let args = input.trim().split_once(' ').map_or("", |(_, args)| args);
+            let (id, query) = args.split_once(' ').unwrap_or((args, ""));
+            match Bang::new(query) {
+                Ok(bang) => {
+                    let replies = REPLIES.with(|replies| replies.borrow().as_ref().and_then(|stream| stream.try_clone().ok()));
+                    match subscribe(CONNECTION.with(Cell::get), id, bang, replies, conn) {
+                        Ok(tracks) => {
+                            for track in tracks {
+                                print_subscription_change(id, &SubscriptionChange::Added(track));
+                            }
+                            println!("SUBSCRIBED::{}", id);
+                        }
+                        Err(err) => println!("{:?}", err),
+                    }
+                }
+                Err(err) => println!("{:?}", err),
+            }
+        }
+        if command == "unsubscribe" {
+            let id = input.trim().split_once(' ').map_or("", |(_, id)| id.trim());
+            if unsubscribe(CONNECTION.with(Cell::get), id) {
+                println!("UNSUBSCRIBED::{}", id);
+            } else {
+                println!("{}", Event::NotSubscribed(id.to_owned()));
+            }
+        }
+        if command == "snapshot" {
+            let snapshot_path = input.trim().split_once(' ').map_or("", |(_, path)| path);
+            match database::export_snapshot(Path::new(snapshot_path), conn) {
+                Ok(version) => println!("SNAPSHOT::{}||{}", version, snapshot_path),
+                Err(err) => println!("{:?}", err),
+            }
+        }
+        if command == "maintain" {
+            match watcher::with_write_lease(conn, crate::report, || database::maintain(conn)) {
+                Ok(Ok(report)) => {
+                    println!("MAINTAINED::{}||{}||{}", report.pages_before, report.pages_after, report.art_pruned)
+                }
+                Ok(Err(err)) | Err(err) => println!("{:?}", err),
+            }
+        }
+        if command == "delta" {
+            let mut args = input.trim().splitn(3, ' ').skip(1);
+            let since = args.next().and_then(|v| v.parse::<i64>().ok()).unwrap_or(0);
+            let delta_path: &str = args.next().unwrap_or("");
+            match database::export_delta(since, Path::new(delta_path), conn) {
+                Ok(version) => println!("DELTA::{}||{}", version, delta_path),
+                Err(err) => println!("{:?}", err),
+            }
+        }
+        if command == "replica" {
+            // replica <version> <name>, once the replica named applied the snapshot or delta of the version.
+            let mut args = input.trim().splitn(3, ' ').skip(1);
+            let version = args.next().and_then(|v| v.parse::<i64>().ok()).unwrap_or(0);
+            let name: &str = args.next().unwrap_or("");
+            match database::acknowledge_replica(name, version, conn) {
+                Ok(pruned) => println!("REPLICA::{}||{}||{}", name, version, pruned),
+                Err(err) => println!("{:?}", err),
+            }
+        }
+        if command == "unreplica" {
+            let name = input.trim().split_once(' ').map_or("", |(_, name)| name);
+            match database::forget_replica(name, conn) {
+                Ok(true) => println!("UNREPLICA::{}", name),
+                Ok(false) => println!("{}", Event::ReplicaNotFound(name.to_owned())),
+                Err(err) => println!("{:?}", err),
+            }
+        }
+        if command == "diff" {
+            // diff <snapshot>, comparing the snapshot to the library as it is now.
+            let snapshot_path = input.trim().split_once(' ').map_or("", |(_, path)| path);
+            match library::diff_with_library(Path::new(snapshot_path), conn) {
+                Ok(diff) => {
+                    for track in &diff.added {
+                        println!("DIFFADDED::{}", track.file_path.to_string_lossy());
+                    }
+                    for track in &diff.removed {
+                        println!("DIFFREMOVED::{}", track.file_path.to_string_lossy());
+                    }
+                    for change in &diff.changed {
+                        println!("DIFFCHANGED::{}||{}", change.after.file_path.to_string_lossy(), change.fields.join(","));
+                    }
+                    println!("DIFF::{}||{}||{}", diff.added.len(), diff.removed.len(), diff.changed.len());
+                }
+                Err(err) => println!("{:?}", err),
+            }
+        }
+        if command == "catalog" {
+            // catalog <path>, writing a single JSON file if the path ends in .json, or a site otherwise.
+            let catalog_path = Path::new(input.trim().split_once(' ').map_or("", |(_, path)| path));
+            match catalog::build_catalog(conn) {
+                Ok(mut built) => {
+                    let albums = built.artists.iter().map(|artist| artist.albums.len()).sum::<usize>();
+                    let written = if catalog_path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("json")) {
+                        catalog::export_json(&built, catalog_path)
+                    } else {
+                        catalog::export_site(&mut built, catalog_path)
+                    };
+                    match written {
+                        Ok(()) => println!("CATALOG::{}||{}", albums, catalog_path.to_string_lossy()),
+                        Err(err) => println!("{:?}", err),
+                    }
+                }
+                Err(err) => println!("{:?}", err),
+            }
+        }
+        if command == "import_url" {
+            // import_url <url>, downloading the URL into the watch folder to be imported.
+            let url = input.trim().split_once(' ').map_or("", |(_, url)| url.trim());
+            match downloads::import_url(url, config) {
+                Ok(import_path) => println!("IMPORTURL::{}||{}", url, import_path.to_string_lossy()),
+                Err(err) => println!("{:?}", err),
+            }
+        }
+        if command == "needledrop" {
+            // needledrop <path>, suggesting the segments to split the recording into.
+            let path = input.trim().split_once(' ').map_or("", |(_, path)| path.trim());
+            match needledrops::suggest_segments(Path::new(path), &config.needledrops) {
+                Ok(segments) => {
+                    for segment in &segments {
+                        println!("SEGMENT::{}||{}||{}", path, segment.start, segment.end);
+                    }
+                }
+                Err(err) => println!("{:?}", err),
+            }
+        }
+        if command == "splitneedledrop" {
+            // splitneedledrop <path>||<artist>||<album>||<year>||<first track number>, followed by
+            // ||<start>||<end>||<title> for every track, with the times in milliseconds.
+            let args = input.trim().split_once(' ').map_or("", |(_, args)| args);
+            let args = args.split("||").collect::<Vec<&str>>();
+            let split = match args.as_slice() {
+                [path, artist, album, year, first_track, tracks @ ..] if !tracks.is_empty() && tracks.len() % 3 == 0 => {
+                    let tracks = tracks
+                        .chunks(3)
+                        .map(|track| {
+                            Some(SplitTrack {
+                                segment: Segment {
+                                    start: track[0].parse().ok()?,
+                                    end: track[1].parse().ok()?,
+                                },
+                                title: track[2].to_owned(),
+                            })
+                        })
+                        .collect::<Option<Vec<SplitTrack>>>();
+                    let album = first_track.parse().ok().map(|first_track| SplitAlbum {
+                        artist: artist.to_string(),
+                        album: album.to_string(),
+                        year: year.parse().ok(),
+                        first_track,
+                    });
+                    tracks.zip(album).map(|(tracks, album)| (Path::new(path), tracks, album))
+                }
+                _ => None,
+            };
+            match split {
+                Some((path, tracks, album)) => match needledrops::split_recording(path, &tracks, &album, config) {
+                    Ok(import_path) => println!("NEEDLEDROPSPLIT::{}||{}", path.display(), import_path.display()),
+                    Err(err) => println!("{:?}", err),
+                },
+                None => println!("{}", Event::InvalidCommand(input.trim().to_owned())),
+            }
+        }
+        if command == "play" {
+            // play <profile> <file>
+            let mut args = input.trim().splitn(3, ' ').skip(1);
+            let profile_id = args.next().unwrap_or("");
+            let file_name = args.next().unwrap_or("");
+            if let Err(err) = profiles::record_play(profile_id, Path::new(file_name), conn) {
+                println!("{:?}", err)
+            }
+            if config.listens.history {
+                if let Err(err) = profiles::record_listen(profile_id, Path::new(file_name), None, conn) {
+                    println!("{:?}", err)
+                }
+            }
+        }
+        if command == "exportlistens" {
+            // exportlistens <profile> <listenbrainz|lastfm> <path>
+            let mut args = input.trim().splitn(4, ' ').skip(1);
+            let profile_id = args.next().unwrap_or("");
+            let format = args.next().and_then(scrobbles::ScrobbleFormat::from_name);
+            let export_path = args.next().unwrap_or("");
+            match format {
+                Some(format) => match scrobbles::export_listens(profile_id, format, None, Path::new(export_path), conn) {
+                    Ok(exported) => println!("EXPORTLISTENS::{}||{}", exported, export_path),
+                    Err(err) => println!("{:?}", err),
+                },
+                None => println!("Unknown format, expected listenbrainz or lastfm"),
+            }
+        }
+        if command == "rate" {
+            // rate <profile> <rating> <file>, where a rating of 0 clears the rating.
+            let mut args = input.trim().splitn(4, ' ').skip(1);
+            let profile_id = args.next().unwrap_or("");
+            let rating = args
+                .next()
+                .and_then(|r| r.parse::<i32>().ok())
+                .filter(|&r| r > 0);
+            let file_name = args.next().unwrap_or("");
+            if let Err(err) = profiles::set_rating(profile_id, Path::new(file_name), rating, conn) {
+                println!("{:?}", err)
+            }
+        }
+        if command == "favorite" {
+            // favorite <profile> <true|false> <file>
+            let mut args = input.trim().splitn(4, ' ').skip(1);
+            let profile_id = args.next().unwrap_or("");
+            let favorite = args.next().and_then(|f| f.parse::<bool>().ok()).unwrap_or(true);
+            let file_name = args.next().unwrap_or("");
+            if let Err(err) = profiles::set_favorite(profile_id, Path::new(file_name), favorite, conn) {
+                println!("{:?}", err)
+            }
+        }
+        if command == "alias" {
+            // alias <alias>||<artist>
+            let args = input.trim().split_once(' ').map_or("", |(_, args)| args);
+            let (alias, artist) = args.split_once("||").unwrap_or((args, ""));
+            if let Err(err) = aliases::add_alias(alias, artist, conn) {
+                println!("{:?}", err)
+            }
+        }
+        if command == "unalias" {
+            let alias = input.trim().split_once(' ').map_or("", |(_, alias)| alias);
+            if let Err(err) = aliases::remove_alias(alias, conn) {
+                println!("{:?}", err)
+            }
+        }
+        if command == "lockalbum" || command == "unlockalbum" {
+            // lockalbum <album>||<album artists, separated by ;>
+            let (command, args) = input.trim().split_once(' ').unwrap_or((input.trim(), ""));
+            let (album, album_artists) = args.split_once("||").unwrap_or((args, ""));
+            let album_artists = album_artists.split(';').map(|artist| artist.to_owned()).collect::<Vec<String>>();
+            let result = if command == "lockalbum" {
+                locks::lock_album(album, &album_artists, conn)
+            } else {
+                locks::unlock_album(album, &album_artists, conn)
+            };
+            if let Err(err) = result {
+                println!("{:?}", err)
+            }
+        }
+        if command == "lock" || command == "unlock" {
+            // lock <uuid>
+            let (command, uuid) = input.trim().split_once(' ').unwrap_or((input.trim(), ""));
+            let result = if command == "lock" {
+                locks::lock_track(uuid, conn)
+            } else {
+                locks::unlock_track(uuid, conn).map(|_| true)
+            };
+            match result {
+                Ok(true) => (),
+                Ok(false) => println!("{}", Event::TrackNotFound(uuid.to_owned())),
+                Err(err) => println!("{:?}", err),
+            }
+        }
+        if command == "note" {
+            // note <uuid> <note>, removing the note if it is empty.
+            let args = input.trim().split_once(' ').map_or("", |(_, args)| args);
+            let (uuid, note) = args.split_once(' ').unwrap_or((args, ""));
+            match notes::set_note(uuid, note, conn) {
+                Ok(true) => (),
+                Ok(false) => println!("{}", Event::TrackNotFound(uuid.to_owned())),
+                Err(err) => println!("{:?}", err),
+            }
+        }
+        if command == "field" {
+            // field <uuid> <name> <value>, removing the value of the field if it is empty.
+            let args = input.trim().splitn(4, ' ').collect::<Vec<&str>>();
+            match (args.get(1), args.get(2).and_then(|name| fields::find_field(&config.custom_fields, name))) {
+                (Some(uuid), Some(field)) => {
+                    let value = args.get(3).copied().unwrap_or("");
+                    let result = if value.trim().is_empty() {
+                        Some(fields::remove_field(uuid, &field.name, conn).map(|_| true))
+                    } else {
+                        field.parse_value(value).map(|parsed| fields::set_field(uuid, field, &parsed, conn))
+                    };
+                    match result {
+                        Some(Ok(true)) => (),
+                        Some(Ok(false)) => println!("{}", Event::TrackNotFound(uuid.to_string())),
+                        Some(Err(err)) => println!("{:?}", err),
+                        None => println!("{}", Event::InvalidFieldValue(field.name.clone(), value.to_owned())),
+                    }
+                }
+                _ => println!("{}", Event::InvalidCommand(input.trim().to_owned())),
+            }
+        }
+        if command == "fields" {
+            // fields <uuid>
+            let uuid = input.trim().split_once(' ').map_or("", |(_, uuid)| uuid.trim());
+            match fields::get_fields(uuid, conn) {
+                Ok(values) => {
+                    for (name, value) in values {
+                        println!("FIELD::{}||{}||{}", uuid, name, value);
+                    }
+                }
+                Err(err) => println!("{:?}", err),
+            }
+        }
+        if command == "releasegroup" {
+            // releasegroup <uuid> <release_group_id>, removing the release group if it is empty.
+            let args = input.trim().split_once(' ').map_or("", |(_, args)| args);
+            let (uuid, release_group_id) = args.split_once(' ').unwrap_or((args, ""));
+            match editions::set_release_group(uuid, release_group_id, conn) {
+                Ok(true) => (),
+                Ok(false) => println!("{}", Event::TrackNotFound(uuid.to_owned())),
+                Err(err) => println!("{:?}", err),
+            }
+        }
+        if command == "link" || command == "unlink" {
+            // link <uuid> <relation> <related_uuid>, or unlink with the same arguments.
+            let args = input.split_whitespace().collect::<Vec<&str>>();
+            match (args.as_slice(), args.get(2).and_then(|relation| relation.parse::<Relation>().ok())) {
+                ([command, uuid, _, related_uuid], Some(relation)) => {
+                    let result = if *command == "link" {
+                        relationships::link(uuid, relation, related_uuid, conn)
+                    } else {
+                        relationships::unlink(uuid, relation, related_uuid, conn)
+                    };
+                    match result {
+                        Ok(true) => (),
+                        Ok(false) if *command == "link" => {
+                            println!("{}", Event::LinkError(uuid.to_string(), related_uuid.to_string()))
+                        }
+                        Ok(false) => println!("{}", Event::NotLinked(uuid.to_string(), related_uuid.to_string())),
+                        Err(err) => println!("{:?}", err),
+                    }
+                }
+                _ => println!("{}", Event::InvalidCommand(input.trim().to_owned())),
+            }
+        }
+        if input.trim() == "locked" {
+            match locks::get_locked_tracks(conn) {
+                Ok(tracks) => {
+                    for uuid in tracks {
+                        println!("LOCKEDTRACK::{}", uuid);
+                    }
+                }
+                Err(err) => println!("{:?}", err),
+            }
+        }
+        if command == "canonicalize" {
+            match watcher::with_write_lease(conn, crate::report, || aliases::canonicalize_library(conn)) {
+                Ok(Ok(count)) => println!("CANONICALIZED::{}", count),
+                Ok(Err(err)) | Err(err) => println!("{:?}", err),
+            }
+        }
+        if command == "bootstrap" || command == "rescan" {
+            // bootstrap <folder>, adding an organized collection to the library where it is.
+            // rescan <folder> also reads the tracks already in the library again, if their files changed.
+            let (command, folder) = input.trim().split_once(' ').unwrap_or((input.trim(), ""));
+            let folder = Path::new(folder);
+            let options = library::BootstrapOptions {
+                skip_existing: command != "rescan",
+                required_tags: config.required_tags,
+                ..library::BootstrapOptions::default()
+            };
+            match watcher::with_write_lease(conn, crate::report, || library::bootstrap(folder, &options, conn)) {
+                Ok(Ok(report)) => {
+                    for file in &report.failed {
+                        println!("BOOTSTRAPFAILED::{}", file.to_string_lossy());
+                    }
+                    println!(
+                        "BOOTSTRAPPED::{}||{}||{}||{}",
+                        report.imported, report.skipped, report.unsupported, report.failed.len()
+                    )
+                }
+                Ok(Err(err)) | Err(err) => println!("{:?}", err),
+            }
+        }
+        if command == "importcompilation" {
+            // importcompilation <profile id>||<playlist name>||<folder>, listing the tracks of the
+            // folder the library already has in the playlist, and importing only the rest.
+            let args = input.trim().split_once(' ').map_or("", |(_, args)| args);
+            let mut args = args.splitn(3, "||");
+            let profile_id = args.next().unwrap_or("");
+            let name = args.next().unwrap_or("");
+            let folder = Path::new(args.next().unwrap_or(""));
+            match watcher::with_write_lease(conn, crate::report, || {
+                compilations::import_compilation(folder, profile_id, name, config, conn)
+            }) {
+                Ok(Ok(compilation)) => {
+                    for (file, existing) in &compilation.matched {
+                        println!("COMPILATIONMATCH::{}||{}", file.to_string_lossy(), existing.to_string_lossy());
+                    }
+                    let imported = compilation.imported.len();
+                    for event in compilation.imported {
+                        crate::report(event);
+                    }
+                    println!(
+                        "COMPILATION::{}||{}||{}",
+                        compilation.playlist_id,
+                        compilation.matched.len(),
+                        imported
+                    )
+                }
+                Ok(Err(err)) | Err(err) => println!("{:?}", err),
+            }
+        }
+        if command == "merge" {
+            // merge <database>, adding the tracks of the other library and counting plays in both.
+            let other_path = Path::new(input.trim().split_once(' ').map_or("", |(_, path)| path));
+            let strategy = library::MergeStrategy {
+                add_missing_tracks: true,
+                play_counts: library::PlayCountStrategy::Sum,
+                rebase: None,
+            };
+            match watcher::with_write_lease(conn, crate::report, || library::merge(other_path, &strategy, conn)) {
+                Ok(Ok(report)) => println!(
+                    "MERGED::{}||{}||{}||{}",
+                    report.matched, report.added, report.skipped, report.playlists
+                ),
+                Ok(Err(err)) | Err(err) => println!("{:?}", err),
+            }
+        }
+        if input.trim() == "sync" {
+            // Syncs with every peer now, without waiting for the next sync.
+            for peer in &config.network.sync_peers {
+                match replication::sync_with(peer, conn) {
+                    Ok((pulled, pushed)) => println!("SYNCED::{}||{}||{}", peer.address, pulled, pushed),
+                    Err(err) => println!("{:?}", err),
+                }
+                if peer.pull_files {
+                    match replication::pull_missing_tracks(peer, config, conn, crate::report) {
+                        Ok(count) => println!("PULLED::{}||{}", peer.address, count),
+                        Err(err) => println!("{:?}", err),
+                    }
+                }
+            }
+        }
+        if command == "reject" {
+            // reject <file>, adding the file to the ignore list so it is never imported.
+            let file_name = input.trim().split_once(' ').map_or("", |(_, path)| path);
+            if let Err(err) = rejections::reject_file(Path::new(file_name), conn) {
+                println!("{:?}", err)
+            }
+        }
+        if input.trim() == "ignored" {
+            match rejections::get_ignored_files(conn) {
+                Ok(files) => {
+                    for file in files {
+                        println!("IGNORED::{}||{}||{}", file.hash, file.file_name, file.ignored);
+                    }
+                }
+                Err(err) => println!("{:?}", err),
+            }
+        }
+        if command == "unignore" {
+            let hash = input.trim().split_once(' ').map_or("", |(_, hash)| hash);
+            if let Err(err) = rejections::unignore_file(hash, conn) {
+                println!("{:?}", err)
+            }
+        }
+        if input.trim() == "clearignored" {
+            match rejections::clear_ignored_files(conn) {
+                Ok(count) => println!("CLEAREDIGNORED::{}", count),
+                Err(err) => println!("{:?}", err),
+            }
+        }
+        if command == "errors" {
+            // errors [<min failures>], listing the files that failed at least that often, once by default.
+            let min_failures = input.trim().split_once(' ').and_then(|(_, min)| min.trim().parse().ok()).unwrap_or(1);
+            match failures::get_problem_files(min_failures, conn) {
+                Ok(files) => {
+                    for file in files {
+                        println!(
+                            "PROBLEMFILE::{}||{}||{}||{}",
+                            file.file_path.display(),
+                            file.failures,
+                            file.last_occurred,
+                            file.last_code
+                        );
+                    }
+                }
+                Err(err) => println!("{:?}", err),
+            }
+        }
+        if command == "errorhistory" {
+            let path = input.trim().split_once(' ').map_or("", |(_, path)| path);
+            match failures::get_errors(Path::new(path), conn) {
+                Ok(errors) => {
+                    for error in errors {
+                        println!(
+                            "FILEERROR::{}||{}||{}||{}||{}",
+                            error.error_id,
+                            error.occurred,
+                            error.stage.as_str(),
+                            error.code,
+                            error.message
+                        );
+                    }
+                }
+                Err(err) => println!("{:?}", err),
+            }
+        }
+        if command == "resolveerrors" {
+            let path = input.trim().split_once(' ').map_or("", |(_, path)| path);
+            match failures::resolve_errors(Path::new(path), conn) {
+                Ok(count) => println!("RESOLVEDERRORS::{}", count),
+                Err(err) => println!("{:?}", err),
+            }
+        }
+        if command == "resolvematchingerrors" {
+            // resolvematchingerrors <query>, resolving the failures of every track matching the query.
+            let query = input.trim().split_once(' ').map_or("", |(_, query)| query);
+            match Bang::new(query).map(|bang| failures::resolve_matching(bang, conn)) {
+                Ok(Ok(count)) => println!("RESOLVEDERRORS::{}", count),
+                Ok(Err(err)) => println!("{:?}", err),
+                Err(err) => println!("{:?}", err),
+            }
+        }
+        if command == "genre" {
+            // genre <genre>||<parent>, where an empty parent moves the genre to the top of the tree.
+            let args = input.trim().split_once(' ').map_or("", |(_, args)| args);
+            let (genre, parent) = args.split_once("||").unwrap_or((args, ""));
+            let result = if parent.is_empty() {
+                genres::remove_genre_parent(genre, conn).map(|_| true)
+            } else {
+                genres::set_genre_parent(genre, parent, conn)
+            };
+            match result {
+                Ok(true) => (),
+                Ok(false) => println!("{} is already under {}", parent, genre),
+                Err(err) => println!("{:?}", err),
+            }
+        }
+        if command == "enqueue" || command == "playnext" {
+            // enqueue <profile> <file>, or playnext <profile> <file>
+            let mut args = input.trim().splitn(3, ' ');
+            let command = args.next().unwrap_or("");
+            let profile_id = args.next().unwrap_or("");
+            let file_name = args.next().unwrap_or("");
+            let result = if command == "playnext" {
+                queue::play_next(profile_id, Path::new(file_name), conn)
+            } else {
+                queue::enqueue(profile_id, Path::new(file_name), conn)
+            };
+            if let Err(err) = result {
+                println!("{:?}", err)
+            }
+        }
+        if command == "dequeue" {
+            // dequeue <profile> <index>
+            let mut args = input.trim().splitn(3, ' ').skip(1);
+            let profile_id = args.next().unwrap_or("");
+            let index = args.next().and_then(|i| i.parse::<usize>().ok()).unwrap_or(0);
+            if let Err(err) = queue::remove_from_queue(profile_id, index, conn) {
+                println!("{:?}", err)
+            }
+        }
+        if command == "seek" {
+            // seek <profile> <index> <offset in ms>
+            let mut args = input.trim().splitn(4, ' ').skip(1);
+            let profile_id = args.next().unwrap_or("");
+            let index = args.next().and_then(|i| i.parse::<usize>().ok()).unwrap_or(0);
+            let offset = args.next().and_then(|o| o.parse::<i64>().ok()).unwrap_or(0);
+            if let Err(err) = queue::set_queue_position(profile_id, index, offset, conn) {
+                println!("{:?}", err)
+            }
+        }
+        if command == "showqueue" {
+            let profile_id = input.trim().split_once(' ').map_or("", |(_, profile_id)| profile_id);
+            match queue::get_queue(profile_id, conn) {
+                Ok(queue) => {
+                    println!("QUEUE::{}||{}||{}", profile_id, queue.current, queue.offset);
+                    for track in queue.tracks {
+                        println!("{}", track.display());
+                    }
                 }
-                None => {
-                    println!("Some Error")
+                Err(err) => println!("{:?}", err),
+            }
+        }
+        if command == "provenance" {
+            let uuid = input.trim().split_once(' ').map_or("", |(_, uuid)| uuid.trim());
+            match provenance::get_provenance(uuid, conn) {
+                Ok(Some(provenance)) => println!(
+                    "PROVENANCE::{}||{}||{}||{}||{}||{}",
+                    uuid,
+                    provenance.ripper,
+                    provenance.accurate_rip.as_str(),
+                    provenance.accurate_rip.confidence().map(|confidence| confidence.to_string()).unwrap_or_default(),
+                    provenance.copy_crc.unwrap_or_default(),
+                    provenance.log_checksum
+                ),
+                Ok(None) => println!("{}", Event::NoProvenance(uuid.to_owned())),
+                Err(err) => println!("{:?}", err),
+            }
+        }
+        if command == "casttargets" {
+            match casting::discover(CAST_DISCOVERY_TIMEOUT) {
+                Ok(targets) => {
+                    for target in &targets {
+                        println!("CASTTARGET::{}||{}||{}:{}", target.name, target.kind.as_str(), target.address, target.port);
+                    }
+                    cast_targets = targets;
+                }
+                Err(err) => println!("{:?}", err),
+            }
+        }
+        if command == "cast" {
+            // cast <target>||<query>, casting every track in the results in order.
+            let args = input.trim().split_once(' ').map_or("", |(_, args)| args);
+            let (name, query) = args.split_once("||").unwrap_or((args, ""));
+            match Bang::new(query).map(|bang| query_tracks(bang, conn, None, None)) {
+                Ok(Ok(tracks)) => cast_tracks(&mut caster, name, &mut cast_targets, &tracks),
+                Ok(Err(err)) => println!("{:?}", err),
+                Err(err) => println!("{:?}", err),
+            }
+        }
+        if command == "castqueue" {
+            // castqueue <target>||<profile id>, casting the play queue of the profile from its current track.
+            let args = input.trim().split_once(' ').map_or("", |(_, args)| args);
+            let (name, profile_id) = args.split_once("||").unwrap_or((args, ""));
+            match queue::get_queue(profile_id, conn) {
+                Ok(queue) => {
+                    let tracks = queue
+                        .tracks
+                        .iter()
+                        .skip(queue.current)
+                        .filter_map(|path| {
+                            let bang = Bang::FilePath(path.to_string_lossy().into_owned());
+                            query_tracks(bang, conn, None, None).ok()?.into_iter().next()
+                        })
+                        .collect::<Vec<Track>>();
+                    cast_tracks(&mut caster, name, &mut cast_targets, &tracks);
+                }
+                Err(err) => println!("{:?}", err),
+            }
+        }
+        if let Some(control) = match input.trim() {
+            "castplay" => Some(CastControl::Play),
+            "castpause" => Some(CastControl::Pause),
+            "caststop" => Some(CastControl::Stop),
+            _ => None,
+        } {
+            let target = caster.target().map(|target| target.name.to_owned()).unwrap_or_default();
+            match caster.control(control) {
+                Ok(()) => println!("CASTCONTROL::{}||{:?}", target, control),
+                Err(err) => println!("{:?}", err),
+            }
+        }
+        if command == "analyze" {
+            // Analysis only writes waveforms, so it runs without the lease.
+            match analysis::analyze_library(&config.analysis, conn, crate::report) {
+                Ok(count) => println!("ANALYZED::{}", count),
+                Err(err) => println!("{:?}", err),
+            }
+        }
+        if input.trim() == "lowart" {
+            match art::low_resolution_albums(config.art.min_resolution, conn) {
+                Ok(albums) => {
+                    for album in albums {
+                        println!(
+                            "LOWART::{}||{}||{}||{}",
+                            album.album,
+                            album.album_artists.join(";"),
+                            album.width,
+                            album.height
+                        );
+                    }
+                }
+                Err(err) => println!("{:?}", err),
+            }
+        }
+        if input.trim() == "upgradeart" {
+            match watcher::with_write_lease(conn, crate::report, || art::upgrade_art(config, conn)) {
+                Ok(Ok(report)) => {
+                    for file in &report.failed {
+                        println!("ARTFAILED::{}", file.to_string_lossy());
+                    }
+                    println!("ARTUPGRADED::{}||{}", report.albums, report.tracks)
                 }
+                Ok(Err(err)) | Err(err) => println!("{:?}", err),
+            }
+        }
+        if command == "normalizeart" {
+            // normalizeart <max dimension> <jpeg|png> <query>
+            let mut args = input.trim().splitn(4, ' ').skip(1);
+            let max_dimension = args.next().and_then(|max_dimension| max_dimension.parse::<u32>().ok());
+            let format = args.next().and_then(library::ArtFormat::from_name);
+            let bang = Bang::new(args.next().unwrap_or(""));
+            match (max_dimension, format, bang) {
+                (Some(max_dimension), Some(format), Ok(bang)) => {
+                    let normalized = watcher::with_write_lease(conn, crate::report, || {
+                        library::normalize_art(bang, max_dimension, format, conn)
+                    });
+                    match normalized {
+                        Ok(Ok(report)) => {
+                            for file in &report.failed {
+                                println!("ARTFAILED::{}", file.to_string_lossy());
+                            }
+                            println!("ARTNORMALIZED::{}||{}", report.tracks, report.saved)
+                        }
+                        Ok(Err(err)) | Err(err) => println!("{:?}", err),
+                    }
+                }
+                (_, _, Err(err)) => println!("{:?}", err),
+                _ => println!("Usage: normalizeart <max dimension> <jpeg|png> <query>"),
+            }
+        }
+        if command == "fileop" {
+            // fileop <copy|move|delete|export:<format>> <run|dry> <folder>||<query>, where the folder is left
+            // empty to delete.
+            let mut args = input.trim().splitn(4, ' ').skip(1);
+            let operation = args.next().unwrap_or("");
+            let dry_run = args.next() == Some("dry");
+            let (folder, query) = args.next().unwrap_or("").split_once("||").unwrap_or(("", ""));
+            let op = match operation {
+                "copy" if !folder.is_empty() => Some(library::FileOp::CopyTo(PathBuf::from(folder))),
+                "move" if !folder.is_empty() => Some(library::FileOp::MoveTo(PathBuf::from(folder))),
+                "delete" => Some(library::FileOp::Delete),
+                _ => match operation.split_once(':') {
+                    Some(("export", format)) if !folder.is_empty() => variants::find_transcoder(&config.transcoders, format)
+                        .map(|transcoder| library::FileOp::ExportAs(PathBuf::from(folder), transcoder.clone())),
+                    _ => None,
+                },
             };
+            match (op, Bang::new(query)) {
+                (Some(op), Ok(bang)) => {
+                    let library_path = Path::new(&config.music_folder);
+                    let applied = watcher::with_write_lease(conn, crate::report, || {
+                        library::apply(bang, &op, library_path, dry_run, conn)
+                    });
+                    match applied {
+                        Ok(Ok(report)) => print_file_op_report(&report),
+                        Ok(Err(err)) | Err(err) => println!("{:?}", err),
+                    }
+                }
+                (_, Err(err)) => println!("{:?}", err),
+                _ => println!("Usage: fileop <copy|move|delete|export:<format>> <run|dry> <folder>||<query>"),
+            }
+        }
+        if command == "undofileop" {
+            let operation_id = input.trim().split_once(' ').and_then(|(_, id)| id.trim().parse::<i64>().ok());
+            match operation_id {
+                Some(operation_id) => {
+                    let undone = watcher::with_write_lease(conn, crate::report, || {
+                        library::undo(operation_id, conn)
+                    });
+                    match undone {
+                        Ok(Ok(report)) => print_file_op_report(&report),
+                        Ok(Err(err)) | Err(err) => println!("{:?}", err),
+                    }
+                }
+                None => println!("Usage: undofileop <operation>"),
+            }
         }
-        if input.trim().starts_with("query") {
-            let query_str: &str = match input.trim().splitn(2, " ").nth(1) {
-                Some(query_str) => query_str,
-                None => "",
+        if command == "forgetfileop" {
+            let operation_id = input.trim().split_once(' ').and_then(|(_, id)| id.trim().parse::<i64>().ok());
+            match operation_id.map(|operation_id| library::forget_operation(operation_id, conn)) {
+                Some(Ok(())) => {}
+                Some(Err(err)) => println!("{:?}", err),
+                None => println!("Usage: forgetfileop <operation>"),
+            }
+        }
+        if command == "replace" {
+            // replace <title|artist|album|album_artists> <literal|regex> <run|dry> <pattern>||<replacement>||<query>
+            let mut args = input.trim().splitn(5, ' ').skip(1);
+            let field = args.next().and_then(library::ReplaceField::from_name);
+            let kind = args.next().unwrap_or("");
+            let dry_run = args.next() == Some("dry");
+            let mut parts = args.next().unwrap_or("").splitn(3, "||");
+            let (pattern, replacement, query) = (parts.next(), parts.next(), parts.next());
+            let pattern = match (kind, pattern) {
+                ("literal", Some(pattern)) => Some(Ok(library::ReplacePattern::Literal(pattern.to_owned()))),
+                ("regex", Some(pattern)) => Some(library::ReplacePattern::regex(pattern)),
+                _ => None,
             };
+            match (field, pattern, replacement, query.map(Bang::new)) {
+                (Some(field), Some(Ok(pattern)), Some(replacement), Some(Ok(bang))) => {
+                    let replaced = watcher::with_write_lease(conn, crate::report, || {
+                        library::replace(bang, field, &pattern, replacement, dry_run, conn)
+                    });
+                    match replaced {
+                        Ok(Ok(report)) => print_replace_report(&report),
+                        Ok(Err(err)) | Err(err) => println!("{:?}", err),
+                    }
+                }
+                (_, Some(Err(err)), _, _) => println!("{:?}", err),
+                (_, _, _, Some(Err(err))) => println!("{:?}", err),
+                _ => println!(
+                    "Usage: replace <title|artist|album|album_artists> <literal|regex> <run|dry> <pattern>||<replacement>||<query>"
+                ),
+            }
+        }
+        if command == "undoreplace" {
+            let replacement_id = input.trim().split_once(' ').and_then(|(_, id)| id.trim().parse::<i64>().ok());
+            match replacement_id {
+                Some(replacement_id) => {
+                    let undone = watcher::with_write_lease(conn, crate::report, || {
+                        library::undo_replace(replacement_id, conn)
+                    });
+                    match undone {
+                        Ok(Ok(report)) => print_replace_report(&report),
+                        Ok(Err(err)) | Err(err) => println!("{:?}", err),
+                    }
+                }
+                None => println!("Usage: undoreplace <replacement>"),
+            }
+        }
+        if command == "throttle" {
+            // throttle <threads> <pause in ms> <seconds>, capping imports until the seconds are up.
+            let mut args = input.split_whitespace().skip(1).map(|arg| arg.parse::<u64>().ok());
+            match (args.next().flatten(), args.next().flatten(), args.next().flatten()) {
+                (Some(threads), Some(pause), Some(seconds)) => {
+                    let throttle = watcher::throttle_imports(
+                        threads as usize,
+                        Duration::from_millis(pause),
+                        Duration::from_secs(seconds),
+                    );
+                    println!("THROTTLED::{}||{}||{}", throttle.threads, pause, seconds)
+                }
+                _ => println!("Usage: throttle <threads> <pause in ms> <seconds>"),
+            }
+        }
+        if input.trim() == "unthrottle" {
+            watcher::lift_import_throttle();
+            println!("UNTHROTTLED")
+        }
+        if input.trim() == "artists" {
+            match browse::get_album_artists(conn) {
+                Ok(artists) => {
+                    for album_artists in artists {
+                        println!("ARTIST::{}", album_artists.join(";"));
+                    }
+                }
+                Err(err) => println!("{:?}", err),
+            }
+        }
+        if command == "albums" {
+            // albums [album artist], listing the albums of every album artist or of one.
+            let album_artist = input.trim().split_once(' ').map(|(_, artist)| artist.trim());
+            match browse::get_albums(album_artist, conn) {
+                Ok(albums) => {
+                    for album in albums {
+                        println!(
+                            "ALBUM::{}||{}||{}||{}",
+                            album.title,
+                            album.album_artists.join(";"),
+                            album.year,
+                            album.tracks
+                        );
+                    }
+                }
+                Err(err) => println!("{:?}", err),
+            }
+        }
+        if input.trim() == "artstats" {
+            match art::stats(conn) {
+                Ok(stats) => println!("ARTSTATS::{}||{}||{}||{}", stats.files, stats.tracks, stats.size, stats.saved),
+                Err(err) => println!("{:?}", err),
+            }
+        }
+        if input.trim() == "poolstats" {
+            let stats = pool.stats();
+            println!(
+                "POOLSTATS::{}||{}||{}||{}||{}||{}||{}",
+                stats.connections,
+                stats.idle_connections,
+                stats.max_connections,
+                stats.checkouts,
+                stats.average_wait,
+                stats.timeouts,
+                stats.errors
+            );
+        }
+        if command == "query" {
+            let query_str = input.trim().split_once(' ').map_or("", |(_, query_str)| query_str);
 
             match Bang::new(query_str) {
                 Ok(bang) => {
@@ -51,3 +1276,49 @@ pub fn wait_for_exit(conn: &Connection, config: &Config) {
         continue;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use seiri::TrackFileType;
+
+    fn add_titled_track(file_name: &str, title: &str, conn: &Connection) {
+        let track = Track::builder(format!("/music/{}", file_name), TrackFileType::FLAC16)
+            .title(title.to_owned())
+            .build();
+        database::add_track(&track, conn);
+    }
+
+    #[test]
+    fn keeps_the_subscriptions_of_each_connection_apart() {
+        let conn = Connection::open_in_memory().unwrap();
+        database::create_database(&conn);
+        add_titled_track("a.flac", "Hello", &conn);
+
+        // Both connections choose the same id for subscriptions to different queries.
+        let hello = subscribe(1, "songs", Bang::new("!T{Hello}").unwrap(), None, &conn).unwrap();
+        let world = subscribe(2, "songs", Bang::new("!T{World}").unwrap(), None, &conn).unwrap();
+        assert_eq!(hello.len(), 1);
+        assert!(world.is_empty());
+
+        add_titled_track("b.flac", "World", &conn);
+        let changes = subscription_changes(&mut SUBSCRIBERS.lock().unwrap(), &conn).unwrap();
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].0, 2);
+        assert!(changes[0].1.starts_with("SUBADDED::songs||"));
+        assert!(changes[0].1.contains("/music/b.flac"));
+
+        // Unsubscribing on one connection leaves the subscription of the other.
+        assert!(unsubscribe(1, "songs"));
+        assert!(!unsubscribe(1, "songs"));
+        add_titled_track("c.flac", "World", &conn);
+        add_titled_track("d.flac", "Hello", &conn);
+        let changes = subscription_changes(&mut SUBSCRIBERS.lock().unwrap(), &conn).unwrap();
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].0, 2);
+        assert!(changes[0].1.contains("/music/c.flac"));
+
+        forget_subscriptions(2);
+        assert!(SUBSCRIBERS.lock().unwrap().is_empty());
+    }
+}